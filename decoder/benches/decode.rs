@@ -0,0 +1,102 @@
+//! Decoding throughput, in frames per second, on a multi-MB capture of spans, events and
+//! metrics.
+
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+use std::io;
+
+use common::FrameBytes;
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use defmt_decoder::Table;
+use tracing_defmt_decoder::export::JsonLinesWriter;
+use tracing_defmt_decoder::TraceDecoder;
+
+/// Size of the capture, about as much as a busy device logs in a minute.
+const CAPTURE_LEN: usize = 4 << 20;
+
+/// Log statements of the firmware besides the ones in the capture, so looking up their
+/// locations costs what it does in a real one.
+const OTHER_STATEMENTS: usize = 2000;
+
+fn table() -> Table {
+    let mut entries = vec![
+        ("Info", "span_enter: poll(queue={=u32})".to_string()),
+        ("Debug", "sent {=u32} bytes, port=3".to_string()),
+        ("Info", "counter: rx_packets={=u32}, iface=eth0".to_string()),
+        ("Info", "span_exit: poll".to_string()),
+    ];
+    entries.extend((0..OTHER_STATEMENTS).map(|i| ("Info", format!("statement {}", i))));
+    let entries: Vec<(&str, &str)> = entries
+        .iter()
+        .map(|(level, format)| (*level, format.as_str()))
+        .collect();
+    common::table(&entries, Some("{=u64:us}"))
+}
+
+/// The capture and the number of frames in it.
+fn capture() -> (Vec<u8>, u64) {
+    let mut data = Vec::with_capacity(CAPTURE_LEN);
+    let mut frames = 0;
+    let mut timestamp = 0;
+    while data.len() < CAPTURE_LEN {
+        for (entry, value) in [(0, Some(7)), (1, Some(1500)), (2, Some(3)), (3, None)] {
+            timestamp += 25;
+            let mut frame = FrameBytes::new(entry).u64(timestamp);
+            if let Some(value) = value {
+                frame = frame.u32(value);
+            }
+            data.extend(frame.bytes());
+            frames += 1;
+        }
+    }
+    (data, frames)
+}
+
+fn decode(c: &mut Criterion) {
+    let locations = common::locations(4 + OTHER_STATEMENTS);
+    let decoder = TraceDecoder::builder()
+        .build_from_table(table(), locations.clone())
+        .unwrap();
+    let (data, frames) = capture();
+
+    let mut group = c.benchmark_group("decode");
+    group.throughput(Throughput::Elements(frames));
+    group.sample_size(10);
+    group.bench_function("process_into", |b| {
+        b.iter(|| {
+            let mut stream = decoder.new_stream();
+            let mut records = 0;
+            stream.process_into(&data, |_| records += 1).unwrap();
+            records
+        })
+    });
+
+    // The same with every record written out, as with `--export jsonl`.
+    group.bench_function("json_lines", |b| {
+        b.iter(|| {
+            let mut stream = decoder.new_stream();
+            stream.add_exporter(JsonLinesWriter::new(io::sink()));
+            stream.process(&data).unwrap();
+        })
+    });
+
+    // Frames decoded by a runner and handed over, see `TraceStream::process_frame`.
+    let table = table();
+    let text_decoder = TraceDecoder::builder().build_for_text().unwrap();
+    group.bench_function("process_frame", |b| {
+        b.iter(|| {
+            let mut stream = text_decoder.new_stream();
+            let mut rest = &data[..];
+            while let Ok((frame, consumed)) = table.decode(rest) {
+                rest = &rest[consumed..];
+                let location = locations.get(&frame.index());
+                stream.process_frame(0, &frame, location).unwrap();
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, decode);
+criterion_main!(benches);
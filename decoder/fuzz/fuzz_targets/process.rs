@@ -0,0 +1,13 @@
+//! Feeds arbitrary bytes to a stream, with the options and chunking taken from the input.
+//!
+//! `cargo +nightly fuzz run process` from `decoder/`. The stream must not panic, hang or
+//! buffer without bound; anomalies are only reported through its issues and statistics.
+
+#![no_main]
+
+#[path = "../../tests/common/mod.rs"]
+mod common;
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| common::fuzz::decode_arbitrary(data));
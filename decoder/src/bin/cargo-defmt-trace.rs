@@ -0,0 +1,253 @@
+//! `cargo defmt-trace`: builds the firmware, runs it on the device and reconstructs the
+//! traces, in one command like `cargo run`.
+//!
+//! Usage: `cargo defmt-trace [options] [-- <probe-rs run options>]`
+//!
+//! The firmware is built with `cargo build`, and the ELF is taken from the artifacts
+//! cargo reports. `probe-rs run` flashes it (or attaches, with `--no-flash` after `--`)
+//! and decodes the defmt frames from RTT against the same ELF; its JSON output is
+//! turned back into spans by [`LineReconstructor`]. The `probe-rs` CLI has to be
+//! installed. Stop with Ctrl-C: the open spans are closed and the exporters flushed.
+//!
+//! ```text
+//! cargo defmt-trace --chip RP2040 --release --export chrome=trace.json
+//! ```
+
+use std::error::Error;
+use std::ffi::OsString;
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+use std::process::{Command, ExitCode, Stdio};
+
+use serde_json::Value;
+use tracing_defmt_decoder::export::JsonLinesWriter;
+use tracing_defmt_decoder::source::LineReconstructor;
+
+#[path = "shared/exports.rs"]
+mod exports;
+
+fn usage() -> String {
+    format!(
+        "\
+usage: cargo defmt-trace [options] [-- <probe-rs run options>]
+
+options:
+    --chip <name>         target chip, passed to probe-rs (or set PROBE_RS_CHIP)
+    --bin <name>          binary to build and run
+    --example <name>      example to build and run
+    --release             build with the release profile
+    --features <list>     features of the firmware to enable
+{}
+                          Without one, JSON lines go to stdout.
+{}
+{}",
+        exports::USAGE,
+        exports::KIND_USAGE,
+        exports::BUDGET_USAGE
+    )
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct Args {
+    chip: Option<String>,
+    /// Options passed on to `cargo build`.
+    cargo: Vec<String>,
+    exports: Vec<String>,
+    kinds: Vec<String>,
+    budget: Option<String>,
+    /// Options passed on to `probe-rs run`.
+    probe_rs: Vec<OsString>,
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run() -> Result<(), Box<dyn Error>> {
+    let Some(args) = parse_args(std::env::args_os().skip(1))? else {
+        println!("{}", usage());
+        return Ok(());
+    };
+    let elf = build(&args.cargo)?;
+    eprintln!("     Tracing {}", elf.display());
+
+    let decoder = exports::decoder(None, &args.exports, &args.kinds)?;
+    let mut reconstructor = LineReconstructor::new(decoder.new_stream());
+    if args.exports.is_empty() {
+        reconstructor
+            .stream_mut()
+            .add_exporter(JsonLinesWriter::stdout());
+    }
+    exports::add_exporters(reconstructor.stream_mut(), &args.exports)?;
+    let check = exports::add_budget_check(reconstructor.stream_mut(), args.budget.as_deref())?;
+
+    // Ctrl-C reaches probe-rs as well, which then exits and ends its output, so the
+    // traces can be finished below.
+    ctrlc::set_handler(|| {})?;
+    let mut probe_rs = Command::new("probe-rs");
+    probe_rs.args(["run", "--log-format", "json"]);
+    if let Some(chip) = &args.chip {
+        probe_rs.args(["--chip", chip]);
+    }
+    let mut child = probe_rs
+        .args(&args.probe_rs)
+        .arg(&elf)
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("could not start probe-rs: {}; is it installed?", e))?;
+
+    let output = BufReader::new(child.stdout.take().unwrap());
+    for line in output.lines() {
+        reconstructor.push_line(&line?)?;
+    }
+    let stats = reconstructor.finish()?;
+    child.wait()?;
+    eprintln!(
+        "    Finished, closing {} spans that were still open",
+        stats.spans_truncated
+    );
+    exports::finish_budget_check(check)
+}
+
+/// Parses the arguments after the program name, `None` when asked for help.
+fn parse_args(args: impl IntoIterator<Item = OsString>) -> Result<Option<Args>, Box<dyn Error>> {
+    let mut args = args.into_iter().peekable();
+    // Cargo passes the subcommand name on.
+    if args.peek().is_some_and(|arg| arg == "defmt-trace") {
+        args.next();
+    }
+
+    let mut parsed = Args::default();
+    while let Some(arg) = args.next() {
+        let arg = arg.into_string().map_err(|_| "arguments must be UTF-8")?;
+        let mut value = || {
+            args.next()
+                .and_then(|value| value.into_string().ok())
+                .ok_or_else(|| format!("{} needs a value\n\n{}", arg, usage()))
+        };
+        match arg.as_str() {
+            "--chip" => parsed.chip = Some(value()?),
+            "--bin" | "--example" | "--features" => {
+                let value = value()?;
+                parsed.cargo.extend([arg, value]);
+            }
+            "--release" => parsed.cargo.push(arg),
+            "--export" => parsed.exports.push(value()?),
+            "--kind" => parsed.kinds.push(value()?),
+            "--budget" => parsed.budget = Some(value()?),
+            "--" => parsed.probe_rs.extend(args.by_ref()),
+            "-h" | "--help" => return Ok(None),
+            _ => return Err(format!("unknown option `{}`\n\n{}", arg, usage()).into()),
+        }
+    }
+    Ok(Some(parsed))
+}
+
+/// Builds the firmware and returns the path of its ELF.
+fn build(options: &[String]) -> Result<PathBuf, Box<dyn Error>> {
+    let cargo = std::env::var_os("CARGO").unwrap_or_else(|| "cargo".into());
+    let mut child = Command::new(cargo)
+        .args(["build", "--message-format=json-render-diagnostics"])
+        .args(options)
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    let mut executables = Vec::new();
+    for line in BufReader::new(child.stdout.take().unwrap()).lines() {
+        let message: Value = serde_json::from_str(&line?).unwrap_or_default();
+        if message["reason"] != "compiler-artifact" {
+            continue;
+        }
+        if let Some(path) = message["executable"].as_str() {
+            let name = message["target"]["name"].as_str().unwrap_or_default();
+            executables.push((name.to_string(), PathBuf::from(path)));
+        }
+    }
+    if !child.wait()?.success() {
+        return Err("the firmware failed to build".into());
+    }
+    match executables.len() {
+        0 => Err("cargo built no executable".into()),
+        1 => Ok(executables.pop().unwrap().1),
+        _ => {
+            let names: Vec<_> = executables.into_iter().map(|(name, _)| name).collect();
+            Err(format!(
+                "cargo built several executables ({}); pick one with --bin or --example",
+                names.join(", ")
+            )
+            .into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Option<Args>, String> {
+        parse_args(args.iter().map(OsString::from)).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn test_options_are_sorted_by_tool() {
+        let args = parse(&[
+            "defmt-trace",
+            "--chip",
+            "RP2040",
+            "--release",
+            "--bin",
+            "blinky",
+            "--export",
+            "chrome=trace.json",
+            "--kind",
+            "radio_tx=client",
+            "--budget",
+            "poll=5ms",
+            "--",
+            "--no-flash",
+            "--probe",
+            "1234:5678",
+        ])
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            args,
+            Args {
+                chip: Some("RP2040".to_string()),
+                cargo: ["--release", "--bin", "blinky"].map(String::from).to_vec(),
+                exports: vec!["chrome=trace.json".to_string()],
+                kinds: vec!["radio_tx=client".to_string()],
+                budget: Some("poll=5ms".to_string()),
+                probe_rs: ["--no-flash", "--probe", "1234:5678"]
+                    .map(OsString::from)
+                    .to_vec(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_subcommand_name_is_optional() {
+        assert_eq!(parse(&[]).unwrap(), Some(Args::default()));
+        assert_eq!(parse(&["defmt-trace"]).unwrap(), Some(Args::default()));
+    }
+
+    #[test]
+    fn test_help_stops_parsing() {
+        assert_eq!(parse(&["defmt-trace", "--help", "--bogus"]).unwrap(), None);
+        assert_eq!(parse(&["-h"]).unwrap(), None);
+    }
+
+    #[test]
+    fn test_bad_arguments_are_errors() {
+        let e = parse(&["--bogus"]).unwrap_err();
+        assert!(e.starts_with("unknown option `--bogus`"), "{}", e);
+        let e = parse(&["--release", "--chip"]).unwrap_err();
+        assert!(e.starts_with("--chip needs a value"), "{}", e);
+    }
+}
@@ -0,0 +1,154 @@
+//! Adds span reconstruction to an existing `probe-rs run` or `defmt-print` setup.
+//!
+//! Usage: `probe-rs run --log-format json <firmware> | defmt-trace-pipe --export <exporter>`
+//!
+//! Reads the decoded logs on stdin, in any format [`TextLines`] recognizes (JSON from
+//! `--log-format json` / `defmt-print --json` keeps levels, locations and timestamps),
+//! and passes every line through to stdout unchanged, so the runner's output is still
+//! there to read. The spans rebuilt from the lines go to the exporters. Stop with
+//! Ctrl-C: the open spans are closed and the exporters flushed.
+//!
+//! ```text
+//! defmt-print --json -e firmware.elf tcp | defmt-trace-pipe --export otlp=http://localhost:4317
+//! ```
+//!
+//! With `--input hex` or `--input base64`, the lines are the raw defmt bytes instead, as
+//! captured by a terminal program in hex mode, and decoded against the firmware given with
+//! `--elf`.
+//!
+//! [`TextLines`]: tracing_defmt_decoder::source::TextLines
+
+use std::error::Error;
+use std::io::{self, BufRead, Write};
+use std::process::ExitCode;
+
+use tracing_defmt_decoder::source::{LineEncoding, LineReconstructor};
+
+#[path = "shared/exports.rs"]
+mod exports;
+
+fn usage() -> String {
+    format!(
+        "\
+usage: <runner> | defmt-trace-pipe [options]
+
+options:
+    --quiet               do not pass the lines through to stdout
+    --input <format>      what the lines are: text (decoded logs, the default), or the
+                          raw defmt bytes in hex or base64
+    --elf <path>          the firmware, to decode hex or base64 input
+{}
+{}
+{}",
+        exports::USAGE,
+        exports::KIND_USAGE,
+        exports::BUDGET_USAGE
+    )
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run() -> Result<(), Box<dyn Error>> {
+    let mut quiet = false;
+    let mut encoding = None;
+    let mut elf = None;
+    let mut exports = Vec::new();
+    let mut kinds = Vec::new();
+    let mut budget = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--quiet" => quiet = true,
+            "--input" => {
+                encoding = match args.next().as_deref() {
+                    Some("text") => None,
+                    Some("hex") => Some(LineEncoding::Hex),
+                    Some("base64") => Some(LineEncoding::Base64),
+                    _ => {
+                        return Err(
+                            format!("--input needs text, hex or base64\n\n{}", usage()).into()
+                        )
+                    }
+                }
+            }
+            "--elf" => {
+                elf = Some(
+                    args.next()
+                        .ok_or_else(|| format!("--elf needs a value\n\n{}", usage()))?,
+                )
+            }
+            "--export" => exports.push(
+                args.next()
+                    .ok_or_else(|| format!("--export needs a value\n\n{}", usage()))?,
+            ),
+            "--kind" => kinds.push(
+                args.next()
+                    .ok_or_else(|| format!("--kind needs a value\n\n{}", usage()))?,
+            ),
+            "--budget" => {
+                budget = Some(
+                    args.next()
+                        .ok_or_else(|| format!("--budget needs a value\n\n{}", usage()))?,
+                )
+            }
+            "-h" | "--help" => return Err(usage().into()),
+            _ => return Err(format!("unknown option `{}`\n\n{}", arg, usage()).into()),
+        }
+    }
+    if exports.is_empty() {
+        return Err(format!("nothing to export to\n\n{}", usage()).into());
+    }
+
+    let elf = match (encoding, elf) {
+        (Some(_), Some(path)) => {
+            Some(std::fs::read(&path).map_err(|e| format!("{}: {}", path, e))?)
+        }
+        (Some(_), None) => return Err(format!("--input needs --elf\n\n{}", usage()).into()),
+        (None, _) => None,
+    };
+    let decoder = exports::decoder(elf.as_deref(), &exports, &kinds)?;
+
+    // Ctrl-C reaches the runner as well, which then exits and closes the pipe, so the
+    // traces can be finished below.
+    ctrlc::set_handler(|| {})?;
+    let mut out = io::stdout().lock();
+    if let Some(encoding) = encoding {
+        let mut stream = decoder.new_stream();
+        exports::add_exporters(&mut stream, &exports)?;
+        let check = exports::add_budget_check(&mut stream, budget.as_deref())?;
+        for line in io::stdin().lock().lines() {
+            let line = line?;
+            if !quiet {
+                writeln!(out, "{}", line)?;
+            }
+            match encoding.decode(&line) {
+                Some(bytes) => stream.process(&bytes)?,
+                // Bytes may be missing where the line does not decode.
+                None => stream.reset(),
+            }
+        }
+        stream.finish()?;
+        return exports::finish_budget_check(check);
+    }
+
+    let mut reconstructor = LineReconstructor::new(decoder.new_stream());
+    exports::add_exporters(reconstructor.stream_mut(), &exports)?;
+    let check = exports::add_budget_check(reconstructor.stream_mut(), budget.as_deref())?;
+    for line in io::stdin().lock().lines() {
+        let line = line?;
+        if !quiet {
+            writeln!(out, "{}", line)?;
+        }
+        reconstructor.push_line(&line)?;
+    }
+    reconstructor.finish()?;
+    exports::finish_budget_check(check)
+}
@@ -0,0 +1,131 @@
+//! The `--export` and `--kind` options of the command-line tools.
+
+use std::error::Error;
+use std::time::Duration;
+
+use tracing_defmt_decoder::budget::{BudgetCheck, LatencyBudgets};
+use tracing_defmt_decoder::export::{ChromeTraceWriter, JsonLinesWriter};
+use tracing_defmt_decoder::{SpanKind, TraceDecoder, TraceStream};
+
+pub const USAGE: &str = "\
+    --export <exporter>   where the traces go, repeatable:
+                            jsonl            JSON lines on stdout
+                            jsonl=<path>     JSON lines file
+                            chrome=<path>    Chrome trace (chrome://tracing, Perfetto)
+                            summary          span statistics on stderr every 10 s
+                            otlp=<endpoint>  OTLP collector (with the `otlp` feature)
+                            journald         systemd journal (with the `syslog` feature)
+                            syslog=<address> RFC 5424 syslog over UDP (with the `syslog` feature)
+                            ws=<address>     live browser viewer, e.g. ws=127.0.0.1:8080
+                                             (with the `websocket` feature)";
+
+pub const KIND_USAGE: &str = "\
+    --kind <span>=<kind>  OpenTelemetry kind of the spans named <span>, repeatable:
+                          client, server, producer, consumer or internal";
+
+pub const BUDGET_USAGE: &str = "\
+    --budget <path>       latency budgets to check at the end, one per line, e.g.
+                          `spi_write <= 200us p99`; fails if one is exceeded";
+
+/// How often the `summary` exporter prints.
+const SUMMARY_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Builds a decoder for the raw defmt bytes of the firmware `elf`, or without it for logs
+/// decoded by `probe-rs` or `defmt-print`, with the span kinds of `kinds` and the
+/// exporters among `exports` that are configured on the decoder.
+pub fn decoder(
+    elf: Option<&[u8]>,
+    exports: &[String],
+    kinds: &[String],
+) -> Result<TraceDecoder, Box<dyn Error>> {
+    let mut builder = TraceDecoder::builder();
+    for kind in kinds {
+        let (span, kind) = parse_kind(kind)?;
+        builder = builder.with_span_kind(span, kind);
+    }
+    for export in exports {
+        match export.split_once('=') {
+            None if export == "summary" => builder = builder.with_summary(SUMMARY_INTERVAL),
+            #[cfg(feature = "otlp-pipeline")]
+            Some(("otlp", endpoint)) => builder = builder.with_otlp_endpoint(endpoint),
+            _ => {}
+        }
+    }
+    match elf {
+        Some(elf) => Ok(builder.build(elf)?),
+        None => Ok(builder.build_for_text()?),
+    }
+}
+
+/// Adds the exporters among `exports` that write files or stdout to `stream`.
+pub fn add_exporters(stream: &mut TraceStream, exports: &[String]) -> Result<(), Box<dyn Error>> {
+    for export in exports {
+        match export.split_once('=') {
+            None if export == "jsonl" => stream.add_exporter(JsonLinesWriter::stdout()),
+            Some(("jsonl", path)) => stream.add_exporter(JsonLinesWriter::create(path)?),
+            Some(("chrome", path)) => stream.add_exporter(ChromeTraceWriter::create(path)?),
+            #[cfg(all(feature = "syslog", unix))]
+            None if export == "journald" => {
+                stream.add_exporter(tracing_defmt_decoder::export::JournaldWriter::new()?)
+            }
+            #[cfg(feature = "syslog")]
+            Some(("syslog", address)) => {
+                stream.add_exporter(tracing_defmt_decoder::export::SyslogWriter::udp(address)?)
+            }
+            #[cfg(feature = "websocket")]
+            Some(("ws", address)) => {
+                let server = tracing_defmt_decoder::export::WebSocketServer::serve(address)?;
+                eprintln!("Serving the traces on http://{}/", server.local_addr());
+                stream.add_exporter(server);
+            }
+            None if export == "summary" => {}
+            #[cfg(feature = "otlp-pipeline")]
+            Some(("otlp", _)) => {}
+            _ => return Err(format!("unknown exporter `{}`", export).into()),
+        }
+    }
+    Ok(())
+}
+
+/// Reads the budgets of `--budget` and adds their check to `stream`.
+pub fn add_budget_check(
+    stream: &mut TraceStream,
+    path: Option<&str>,
+) -> Result<Option<BudgetCheck>, Box<dyn Error>> {
+    let Some(path) = path else {
+        return Ok(None);
+    };
+    let budgets = LatencyBudgets::load(path).map_err(|e| format!("{}: {}", path, e))?;
+    let check = BudgetCheck::new(budgets);
+    stream.add_exporter(check.clone());
+    Ok(Some(check))
+}
+
+/// Prints the report of the `--budget` check on stderr, failing if a budget was exceeded.
+pub fn finish_budget_check(check: Option<BudgetCheck>) -> Result<(), Box<dyn Error>> {
+    let Some(check) = check else {
+        return Ok(());
+    };
+    let report = check.report();
+    eprint!("{}", report);
+    match report.violations().count() {
+        0 => Ok(()),
+        n => Err(format!("{} latency budget(s) exceeded", n).into()),
+    }
+}
+
+/// Parses `<span>=<kind>`.
+fn parse_kind(rule: &str) -> Result<(&str, SpanKind), Box<dyn Error>> {
+    let (span, kind) = rule
+        .split_once('=')
+        .ok_or_else(|| format!("`{}` is not <span>=<kind>", rule))?;
+    let kind = match kind {
+        "client" => SpanKind::Client,
+        "server" => SpanKind::Server,
+        "producer" => SpanKind::Producer,
+        "consumer" => SpanKind::Consumer,
+        "internal" => SpanKind::Internal,
+        _ => return Err(format!("unknown span kind `{}`", kind).into()),
+    };
+    Ok((span, kind))
+}
@@ -0,0 +1,180 @@
+//! Live terminal viewer for bench debugging.
+//!
+//! Usage: `tracing-defmt-tui <firmware.elf> <capture file | host:port>`
+//!
+//! Decodes the raw defmt stream read from a file (e.g. a named pipe) or a TCP
+//! connection, such as an RTT channel forwarded by a debug probe, and shows the
+//! decoded events with the span stack of every context. Over TCP, control commands
+//! typed after `:` (e.g. `:set-filter app::net=trace`) are sent back to the device.
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::process::{Command, ExitCode, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tracing_defmt_decoder::tui::{KeyAction, LiveView};
+use tracing_defmt_decoder::{ControlCommand, TraceDecoder};
+
+const USAGE: &str = "usage: tracing-defmt-tui <firmware.elf> <capture file | host:port>";
+const FRAME_TIME: Duration = Duration::from_millis(50);
+
+enum Input {
+    Data(Vec<u8>),
+    Keys(Vec<u8>),
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run() -> Result<(), Box<dyn Error>> {
+    let mut args = std::env::args().skip(1);
+    let (Some(elf), Some(source)) = (args.next(), args.next()) else {
+        return Err(USAGE.into());
+    };
+    let decoder = TraceDecoder::new(&std::fs::read(elf)?)?;
+    let (reader, mut commands) = open_source(&source)?;
+
+    let (tx, rx) = mpsc::channel();
+    spawn_reader(reader, tx.clone(), Input::Data);
+    // Restores the terminal on every return path.
+    let _terminal = RawTerminal::enter()?;
+    spawn_reader(Box::new(io::stdin()), tx, Input::Keys);
+
+    let mut view = LiveView::new();
+    let mut stream = decoder.new_stream();
+    let mut out = io::stdout().lock();
+    let mut dirty = true;
+    let mut last_draw: Option<Instant> = None;
+    loop {
+        match rx.recv_timeout(FRAME_TIME) {
+            Ok(Input::Data(data)) => {
+                stream.process_into(&data, |record| view.push(&record))?;
+                dirty = true;
+            }
+            Ok(Input::Keys(keys)) => {
+                for key in keys {
+                    match view.handle_key(key) {
+                        KeyAction::Quit => return Ok(()),
+                        KeyAction::Redraw => dirty = true,
+                        KeyAction::Command => {
+                            if let Some(command) = view.take_command() {
+                                let notice = send_command(commands.as_mut(), &command);
+                                view.set_notice(notice);
+                            }
+                            dirty = true;
+                        }
+                        KeyAction::Ignored => {}
+                    }
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        if dirty && last_draw.is_none_or(|at| at.elapsed() >= FRAME_TIME) {
+            if let Some((rows, columns)) = terminal_size() {
+                view.set_size(rows, columns);
+            }
+            view.render(&mut out)?;
+            dirty = false;
+            last_draw = Some(Instant::now());
+        }
+    }
+    Ok(())
+}
+
+/// Opens `source` as a file if it exists, otherwise connects to it over TCP. Over TCP,
+/// also returns the connection to send control commands on.
+fn open_source(source: &str) -> io::Result<(Box<dyn Read + Send>, Option<TcpStream>)> {
+    if Path::new(source).exists() {
+        Ok((Box::new(File::open(source)?), None))
+    } else {
+        let connection = TcpStream::connect(source)?;
+        let commands = connection.try_clone()?;
+        Ok((Box::new(connection), Some(commands)))
+    }
+}
+
+/// Sends `command` to the device and describes the outcome.
+fn send_command(connection: Option<&mut TcpStream>, command: &ControlCommand) -> String {
+    let Some(connection) = connection else {
+        return "commands need a TCP connection to the device".to_string();
+    };
+    match connection.write_all(&command.to_bytes()) {
+        Ok(()) => format!("sent: {}", command),
+        Err(e) => format!("sending failed: {}", e),
+    }
+}
+
+/// Forwards everything read from `reader` until it ends.
+fn spawn_reader(mut reader: Box<dyn Read + Send>, tx: Sender<Input>, wrap: fn(Vec<u8>) -> Input) {
+    thread::spawn(move || {
+        let mut buf = [0u8; 4096];
+        loop {
+            let n = match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            };
+            if tx.send(wrap(buf[..n].to_vec())).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+/// Puts the terminal into unbuffered, non-echoing mode on an alternate screen, and
+/// restores it when dropped.
+struct RawTerminal {
+    saved: String,
+}
+
+impl RawTerminal {
+    fn enter() -> io::Result<Self> {
+        let saved = stty(&["-g"])?;
+        stty(&["-icanon", "-echo", "-isig", "min", "1"])?;
+        print!("\x1b[?1049h\x1b[?25l");
+        io::stdout().flush()?;
+        Ok(Self {
+            saved: saved.trim().to_string(),
+        })
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        print!("\x1b[?25h\x1b[?1049l");
+        let _ = io::stdout().flush();
+        let _ = stty(&[&self.saved]);
+    }
+}
+
+fn terminal_size() -> Option<(u16, u16)> {
+    let size = stty(&["size"]).ok()?;
+    let (rows, columns) = size.trim().split_once(' ')?;
+    Some((rows.parse().ok()?, columns.parse().ok()?))
+}
+
+fn stty(args: &[&str]) -> io::Result<String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other("stty failed; is stdin a terminal?"));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
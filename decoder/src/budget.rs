@@ -0,0 +1,422 @@
+//! Latency budgets: per-span duration limits checked over a session, e.g. as a
+//! performance gate on the bench.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use crate::diff::Statistic;
+use crate::export::Exporter;
+use crate::{Error, RecordKind, TraceRecord};
+
+/// Offenders listed per violated budget in the text report.
+const SHOWN_OFFENDERS: usize = 5;
+
+/// A duration limit for the spans of one name, e.g. `spi_write <= 200us p99`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Budget {
+    pub span: String,
+    pub limit: Duration,
+    /// What must stay within the limit: the slowest span, by default, or the mean or a
+    /// percentile of the span durations.
+    pub statistic: Statistic,
+}
+
+impl fmt::Display for Budget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} <= {:?}", self.span, self.limit)?;
+        match self.statistic {
+            Statistic::Max => Ok(()),
+            statistic => write!(f, " {}", statistic),
+        }
+    }
+}
+
+/// A set of [`Budget`]s, declared in code or parsed from text.
+///
+/// The text form has one budget per line (or comma-separated), `<span> <= <limit>
+/// [<statistic>]`, where the limit has a unit of `ns`, `us`, `ms` or `s`, and the
+/// statistic is `max` (the default), `mean` or a percentile such as `p99` or `p99.9`.
+/// Lines starting with `#` are comments:
+///
+/// ```text
+/// # SPI transfers of one page
+/// spi_write <= 200us p99
+/// flash_erase <= 40ms
+/// ```
+///
+/// # Example
+/// ```rust,ignore
+/// let budgets = LatencyBudgets::load("budgets.txt")?
+///     .with_budget("handle_rx", Duration::from_micros(80), Statistic::Mean);
+/// let report = budgets.evaluate(&decoder.decode_records(&capture)?);
+/// assert!(report.passed(), "{}", report);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LatencyBudgets {
+    budgets: Vec<Budget>,
+}
+
+impl LatencyBudgets {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads budgets in the text form from the file at `path`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        std::fs::read_to_string(path)?.parse()
+    }
+
+    /// Limits the `statistic` of the durations of the spans named `span` to `limit`.
+    pub fn with_budget(
+        mut self,
+        span: impl Into<String>,
+        limit: Duration,
+        statistic: Statistic,
+    ) -> Self {
+        self.budgets.push(Budget {
+            span: span.into(),
+            limit,
+            statistic,
+        });
+        self
+    }
+
+    pub fn budgets(&self) -> &[Budget] {
+        &self.budgets
+    }
+
+    /// Checks the spans in `records` against the budgets.
+    pub fn evaluate(&self, records: &[TraceRecord]) -> BudgetReport {
+        let mut check = BudgetCheck::new(self.clone());
+        for record in records {
+            // Checking only fails for exporters that write somewhere.
+            let _ = check.export(record);
+        }
+        check.report()
+    }
+}
+
+impl FromStr for LatencyBudgets {
+    type Err = Error;
+
+    fn from_str(spec: &str) -> Result<Self, Error> {
+        let mut budgets = Self::new();
+        for budget in spec
+            .lines()
+            .flat_map(|line| line.split(','))
+            .map(str::trim)
+            .filter(|budget| !budget.is_empty() && !budget.starts_with('#'))
+        {
+            let invalid = |why: &str| Error::Budget(format!("`{}` {}", budget, why));
+            let (span, rest) = budget
+                .split_once("<=")
+                .ok_or_else(|| invalid("is not <span> <= <limit> [<statistic>]"))?;
+            let mut rest = rest.split_whitespace();
+            let limit = rest
+                .next()
+                .and_then(parse_duration)
+                .ok_or_else(|| invalid("has no limit such as 200us"))?;
+            let statistic = match rest.next() {
+                None => Statistic::Max,
+                Some(statistic) => parse_statistic(statistic)
+                    .ok_or_else(|| invalid("has no statistic such as max, mean or p99"))?,
+            };
+            if span.trim().is_empty() || rest.next().is_some() {
+                return Err(invalid("is not <span> <= <limit> [<statistic>]"));
+            }
+            budgets = budgets.with_budget(span.trim(), limit, statistic);
+        }
+        Ok(budgets)
+    }
+}
+
+/// Parses a duration such as `200us` or `1.5ms`.
+fn parse_duration(text: &str) -> Option<Duration> {
+    let split = text.find(|c: char| !c.is_ascii_digit() && c != '.')?;
+    let (value, unit) = text.split_at(split);
+    let value: f64 = value.parse().ok()?;
+    let seconds = match unit {
+        "ns" => value / 1e9,
+        "us" | "µs" => value / 1e6,
+        "ms" => value / 1e3,
+        "s" => value,
+        _ => return None,
+    };
+    Duration::try_from_secs_f64(seconds).ok()
+}
+
+fn parse_statistic(text: &str) -> Option<Statistic> {
+    match text {
+        "max" => Some(Statistic::Max),
+        "mean" => Some(Statistic::Mean),
+        _ => {
+            let percentile: f64 = text.strip_prefix('p')?.parse().ok()?;
+            (0.0..=100.0)
+                .contains(&percentile)
+                .then_some(Statistic::Percentile(percentile / 100.0))
+        }
+    }
+}
+
+/// A span over its budget's limit.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Offender {
+    pub duration: Duration,
+    /// OpenTelemetry trace id of the span, when it was exported through a
+    /// `tracing-opentelemetry` subscriber, to look it up in the tracing backend.
+    pub trace_id: Option<String>,
+    /// The decoder's id of the span, see [`TraceRecord::span_id`].
+    pub span_id: u64,
+    /// Device timestamp of the span's enter, in microseconds.
+    pub timestamp: Option<u64>,
+}
+
+impl fmt::Display for Offender {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.duration)?;
+        match (&self.trace_id, self.timestamp) {
+            (Some(trace_id), _) => write!(f, " trace {}", trace_id),
+            (None, Some(timestamp)) => write!(f, " span {} at {}us", self.span_id, timestamp),
+            (None, None) => write!(f, " span {}", self.span_id),
+        }
+    }
+}
+
+/// How the spans of a session fared against one [`Budget`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct BudgetResult {
+    pub budget: Budget,
+    /// Completed spans of the name.
+    pub count: usize,
+    /// The budget's statistic over them, `None` without spans.
+    pub observed: Option<Duration>,
+    /// The spans over the limit, slowest first.
+    pub offenders: Vec<Offender>,
+}
+
+impl BudgetResult {
+    pub fn violated(&self) -> bool {
+        self.observed
+            .is_some_and(|observed| observed > self.budget.limit)
+    }
+}
+
+/// The outcome of checking a session against [`LatencyBudgets`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct BudgetReport {
+    results: Vec<BudgetResult>,
+}
+
+impl BudgetReport {
+    /// One result per budget, in the order they were declared.
+    pub fn results(&self) -> &[BudgetResult] {
+        &self.results
+    }
+
+    /// Whether every budget held. Budgets of spans that never completed hold.
+    pub fn passed(&self) -> bool {
+        self.violations().next().is_none()
+    }
+
+    pub fn violations(&self) -> impl Iterator<Item = &BudgetResult> {
+        self.results.iter().filter(|result| result.violated())
+    }
+
+    /// The report as JSON: `passed`, and per budget its span, limit, statistic, observed
+    /// value and offenders, durations in microseconds.
+    pub fn to_json(&self) -> Value {
+        let budgets: Vec<Value> = self
+            .results
+            .iter()
+            .map(|result| {
+                let offenders: Vec<Value> = result
+                    .offenders
+                    .iter()
+                    .map(|offender| {
+                        json!({
+                            "duration_us": offender.duration.as_micros() as u64,
+                            "trace_id": offender.trace_id,
+                            "span_id": offender.span_id,
+                            "timestamp_us": offender.timestamp,
+                        })
+                    })
+                    .collect();
+                json!({
+                    "span": result.budget.span,
+                    "limit_us": result.budget.limit.as_micros() as u64,
+                    "statistic": result.budget.statistic.to_string(),
+                    "count": result.count,
+                    "observed_us": result.observed.map(|observed| observed.as_micros() as u64),
+                    "violated": result.violated(),
+                    "offenders": offenders,
+                })
+            })
+            .collect();
+        json!({ "passed": self.passed(), "budgets": budgets })
+    }
+}
+
+impl fmt::Display for BudgetReport {
+    /// One line per budget, followed by the slowest offenders of violated ones:
+    ///
+    /// ```text
+    /// FAIL spi_write <= 200µs p99: 312µs over 1200 spans
+    ///        812µs trace 4bf92f3577b34da6a3ce929d0e0e4736
+    /// ok   flash_erase <= 40ms: 31.2ms over 4 spans
+    /// ```
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for result in &self.results {
+            let status = if result.violated() { "FAIL" } else { "ok  " };
+            write!(f, "{} {}: ", status, result.budget)?;
+            match result.observed {
+                Some(observed) => writeln!(f, "{:?} over {} spans", observed, result.count)?,
+                None => writeln!(f, "no spans")?,
+            }
+            if result.violated() {
+                for offender in result.offenders.iter().take(SHOWN_OFFENDERS) {
+                    writeln!(f, "       {}", offender)?;
+                }
+                if result.offenders.len() > SHOWN_OFFENDERS {
+                    writeln!(
+                        f,
+                        "       and {} more",
+                        result.offenders.len() - SHOWN_OFFENDERS
+                    )?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Checks [`LatencyBudgets`] as the records are decoded, for sessions too long to keep.
+/// Only the durations of budgeted spans are kept.
+///
+/// Clones share their state, so keep one to read the [`report`](Self::report) after
+/// adding another to a stream.
+///
+/// # Example
+/// ```rust,ignore
+/// let check = BudgetCheck::new(LatencyBudgets::load("budgets.txt")?);
+/// stream.add_exporter(check.clone());
+/// source.feed(&mut stream)?;
+/// let report = check.report();
+/// ```
+#[derive(Clone, Debug)]
+pub struct BudgetCheck(Arc<Mutex<CheckState>>);
+
+#[derive(Debug)]
+struct CheckState {
+    budgets: LatencyBudgets,
+    /// The enter records of the budgeted spans that are open, by span id.
+    open: HashMap<u64, TraceRecord>,
+    /// The completed budgeted spans, by name.
+    spans: HashMap<String, Vec<Offender>>,
+}
+
+impl BudgetCheck {
+    pub fn new(budgets: LatencyBudgets) -> Self {
+        Self(Arc::new(Mutex::new(CheckState {
+            budgets,
+            open: HashMap::new(),
+            spans: HashMap::new(),
+        })))
+    }
+
+    /// The outcome for the spans completed so far.
+    pub fn report(&self) -> BudgetReport {
+        let state = self.0.lock().unwrap();
+        let results = state
+            .budgets
+            .budgets
+            .iter()
+            .map(|budget| {
+                let spans = state.spans.get(&budget.span).map_or(&[][..], Vec::as_slice);
+                let mut durations: Vec<Duration> = spans.iter().map(|span| span.duration).collect();
+                durations.sort();
+                let mut offenders: Vec<Offender> = spans
+                    .iter()
+                    .filter(|span| span.duration > budget.limit)
+                    .cloned()
+                    .collect();
+                offenders.sort_by_key(|offender| std::cmp::Reverse(offender.duration));
+                BudgetResult {
+                    budget: budget.clone(),
+                    count: spans.len(),
+                    observed: observe(&durations, budget.statistic),
+                    offenders,
+                }
+            })
+            .collect();
+        BudgetReport { results }
+    }
+}
+
+/// The `statistic` of the sorted `durations`, `None` if there are none.
+fn observe(durations: &[Duration], statistic: Statistic) -> Option<Duration> {
+    let last = durations.last()?;
+    Some(match statistic {
+        Statistic::Max => *last,
+        Statistic::Mean => durations.iter().sum::<Duration>() / durations.len() as u32,
+        Statistic::Percentile(quantile) => {
+            // The nearest-rank percentile.
+            let rank = (durations.len() as f64 * quantile).ceil() as usize;
+            durations[rank.clamp(1, durations.len()) - 1]
+        }
+    })
+}
+
+impl Exporter for BudgetCheck {
+    fn export(&mut self, record: &TraceRecord) -> io::Result<()> {
+        let Some(span_id) = record.span_id else {
+            return Ok(());
+        };
+        let mut state = self.0.lock().unwrap();
+        match record.kind {
+            RecordKind::SpanEnter => {
+                let budgeted = state
+                    .budgets
+                    .budgets
+                    .iter()
+                    .any(|budget| budget.span == record.message);
+                if budgeted {
+                    state.open.insert(span_id, record.clone());
+                }
+            }
+            RecordKind::SpanExit => {
+                let Some(enter) = state.open.remove(&span_id) else {
+                    return Ok(());
+                };
+                let duration = match (enter.timestamp, record.timestamp) {
+                    (Some(start), Some(end)) => {
+                        Some(Duration::from_micros(end.saturating_sub(start)))
+                    }
+                    _ => record
+                        .wall_time
+                        .zip(enter.wall_time)
+                        .and_then(|(end, start)| end.duration_since(start).ok()),
+                };
+                // Spans without any timing cannot be checked.
+                let Some(duration) = duration else {
+                    return Ok(());
+                };
+                let span = Offender {
+                    duration,
+                    trace_id: enter.trace_id.or_else(|| record.trace_id.clone()),
+                    span_id,
+                    timestamp: enter.timestamp,
+                };
+                state.spans.entry(enter.message).or_default().push(span);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
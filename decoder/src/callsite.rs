@@ -0,0 +1,248 @@
+//! Runtime-created tracing callsites.
+//!
+//! `tracing` expects span names, targets and field names to be `'static`, which the
+//! macros guarantee by putting them in statics. The decoder only learns them at runtime
+//! (from the builder and from the device's frames), so it creates callsites on demand and
+//! leaks them. Each distinct combination is created once per process.
+//!
+//! The targets and the field names of events come from the device, or from any text fed
+//! to a text decoder, so there is no telling how many there will be. Past
+//! [`MAX_CALLSITES`], new targets are replaced by the configured one and events with a
+//! new set of fields get them in the single [`OVERFLOW_FIELD`], which keeps the leak
+//! bounded on untrusted input.
+
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+
+use tracing::callsite::{self, Callsite};
+use tracing::field::{FieldSet, Value};
+use tracing::metadata::Kind;
+use tracing::subscriber::Interest;
+use tracing::{Level, Metadata, Span};
+
+/// Fields every device span carries, in this order.
+pub(crate) const SPAN_FIELDS: &[&str] = &[
+    "otel.name",
+    "code.function",
+    "code.filepath",
+    "code.lineno",
+    "code.namespace",
+    // Left empty for internal spans.
+    "otel.kind",
+];
+
+/// Fields every device event carries, in this order.
+pub(crate) const EVENT_FIELDS: &[&str] =
+    &["message", "code.filepath", "code.lineno", "code.namespace"];
+
+/// Callsites created before new targets and field sets are no longer given their own.
+pub(crate) const MAX_CALLSITES: usize = 1024;
+
+/// The field of the events past [`MAX_CALLSITES`] that holds their other fields, as
+/// `key=value` pairs.
+pub(crate) const OVERFLOW_FIELD: &str = "fields";
+
+struct DynCallsite {
+    metadata: OnceLock<Metadata<'static>>,
+}
+
+impl Callsite for DynCallsite {
+    fn set_interest(&self, _interest: Interest) {}
+
+    fn metadata(&self) -> &Metadata<'_> {
+        self.metadata
+            .get()
+            .expect("callsite used before initialization")
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct Key {
+    is_span: bool,
+    level: Level,
+    target: String,
+    name: String,
+    extra_fields: Vec<String>,
+}
+
+fn registry() -> &'static Mutex<HashMap<Key, &'static Metadata<'static>>> {
+    static REGISTRY: OnceLock<Mutex<HashMap<Key, &'static Metadata<'static>>>> = OnceLock::new();
+    REGISTRY.get_or_init(Default::default)
+}
+
+/// Returns a leaked copy of `value`, reusing an earlier one for equal strings.
+pub(crate) fn intern(value: &str) -> &'static str {
+    static STRINGS: OnceLock<Mutex<HashMap<String, &'static str>>> = OnceLock::new();
+    let mut strings = STRINGS.get_or_init(Default::default).lock().unwrap();
+    if let Some(interned) = strings.get(value) {
+        return interned;
+    }
+    let interned: &'static str = Box::leak(value.to_string().into_boxed_str());
+    strings.insert(value.to_string(), interned);
+    interned
+}
+
+/// The metadata of `key`. Once [`MAX_CALLSITES`] are created, a new `key` is replaced by
+/// the one `fallback` returns, if any, which is created regardless.
+fn metadata(
+    key: Key,
+    fallback: impl FnOnce(Key) -> Option<Key>,
+) -> Option<&'static Metadata<'static>> {
+    let mut registry = registry().lock().unwrap();
+    if let Some(metadata) = registry.get(&key) {
+        return Some(metadata);
+    }
+    let key = match registry.len() < MAX_CALLSITES {
+        true => key,
+        false => fallback(key)?,
+    };
+    if let Some(metadata) = registry.get(&key) {
+        return Some(metadata);
+    }
+
+    let base = if key.is_span {
+        SPAN_FIELDS
+    } else {
+        EVENT_FIELDS
+    };
+    let names: Vec<&'static str> = base
+        .iter()
+        .copied()
+        .chain(key.extra_fields.iter().map(|name| intern(name)))
+        .collect();
+    let names: &'static [&'static str] = Box::leak(names.into_boxed_slice());
+
+    let callsite: &'static DynCallsite = Box::leak(Box::new(DynCallsite {
+        metadata: OnceLock::new(),
+    }));
+    let kind = if key.is_span { Kind::SPAN } else { Kind::EVENT };
+    let _ = callsite.metadata.set(Metadata::new(
+        intern(&key.name),
+        intern(&key.target),
+        key.level,
+        None,
+        None,
+        None,
+        FieldSet::new(names, callsite::Identifier(callsite)),
+        kind,
+    ));
+    callsite::register(callsite);
+
+    let metadata = callsite.metadata();
+    registry.insert(key, metadata);
+    Some(metadata)
+}
+
+/// Replaces the target of `key` by `default_target`.
+fn with_target(default_target: &str) -> impl FnOnce(Key) -> Option<Key> + '_ {
+    move |key| {
+        Some(Key {
+            target: default_target.to_string(),
+            ..key
+        })
+    }
+}
+
+/// Span metadata with [`SPAN_FIELDS`]. Past [`MAX_CALLSITES`], spans of a new `target`
+/// get `default_target`.
+pub(crate) fn span_metadata(
+    target: &str,
+    default_target: &str,
+    name: &str,
+    level: Level,
+) -> &'static Metadata<'static> {
+    let key = Key {
+        is_span: true,
+        level,
+        target: target.to_string(),
+        name: name.to_string(),
+        extra_fields: Vec::new(),
+    };
+    metadata(key, with_target(default_target)).unwrap()
+}
+
+/// Event metadata with [`EVENT_FIELDS`] followed by `extra_fields`, which the decoder
+/// names. Past [`MAX_CALLSITES`], events of a new `target` get `default_target`.
+pub(crate) fn event_metadata(
+    target: &str,
+    default_target: &str,
+    level: Level,
+    extra_fields: &[&str],
+) -> &'static Metadata<'static> {
+    metadata(
+        event_key(target, level, extra_fields),
+        with_target(default_target),
+    )
+    .unwrap()
+}
+
+/// Event metadata with [`EVENT_FIELDS`] followed by `extra_fields`, which the device
+/// names. `None` past [`MAX_CALLSITES`] for a new combination, for the caller to fall
+/// back to [`overflow_event_metadata`].
+pub(crate) fn device_event_metadata(
+    target: &str,
+    level: Level,
+    extra_fields: &[&str],
+) -> Option<&'static Metadata<'static>> {
+    metadata(event_key(target, level, extra_fields), |_| None)
+}
+
+/// Event metadata with [`EVENT_FIELDS`] followed by [`OVERFLOW_FIELD`] and
+/// `exception.message`, for the events [`device_event_metadata`] has no callsite for.
+pub(crate) fn overflow_event_metadata(
+    default_target: &str,
+    level: Level,
+) -> &'static Metadata<'static> {
+    let key = event_key(
+        default_target,
+        level,
+        &[OVERFLOW_FIELD, "exception.message"],
+    );
+    metadata(key, Some).unwrap()
+}
+
+fn event_key(target: &str, level: Level, extra_fields: &[&str]) -> Key {
+    Key {
+        is_span: false,
+        level,
+        target: target.to_string(),
+        name: "device_event".to_string(),
+        extra_fields: extra_fields.iter().map(|s| s.to_string()).collect(),
+    }
+}
+
+fn enabled(metadata: &'static Metadata<'static>) -> bool {
+    tracing::dispatcher::get_default(|dispatch| dispatch.enabled(metadata))
+}
+
+/// Creates a span in the current dispatcher. `values` must match the metadata's fields.
+pub(crate) fn new_span(
+    metadata: &'static Metadata<'static>,
+    parent: Option<&Span>,
+    values: &[Option<&dyn Value>],
+) -> Span {
+    if !enabled(metadata) {
+        return Span::none();
+    }
+    let values = metadata.fields().value_set_all(values);
+    match parent {
+        Some(parent) => Span::child_of(parent, metadata, &values),
+        None => Span::new(metadata, &values),
+    }
+}
+
+/// Dispatches an event to the current dispatcher. `values` must match the metadata's fields.
+pub(crate) fn dispatch_event(
+    metadata: &'static Metadata<'static>,
+    parent: Option<&Span>,
+    values: &[Option<&dyn Value>],
+) {
+    if !enabled(metadata) {
+        return;
+    }
+    let values = metadata.fields().value_set_all(values);
+    match parent.and_then(|span| span.id()) {
+        Some(id) => tracing::Event::child_of(id, metadata, &values),
+        None => tracing::Event::dispatch(metadata, &values),
+    }
+}
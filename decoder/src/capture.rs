@@ -0,0 +1,133 @@
+//! Raw capture files, written with [`TraceStream::set_capture`] and read back with
+//! [`Replay`](crate::source::Replay).
+//!
+//! [`TraceStream::set_capture`]: crate::TraceStream::set_capture
+
+use std::fs::{File, OpenOptions};
+#[cfg(feature = "compression")]
+use std::io::Write;
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Creates a new capture file named after the current UTC time in `dir`, e.g.
+/// `capture-20240131T142502Z.bin`, and returns its path and a buffered writer.
+///
+/// Never overwrites an existing file; a counter is appended if the name is taken.
+pub fn create_capture_file<P: AsRef<Path>>(dir: P) -> io::Result<(PathBuf, BufWriter<File>)> {
+    let (path, file) = create_file(dir.as_ref(), "bin")?;
+    Ok((path, BufWriter::new(file)))
+}
+
+/// How a capture file is compressed, see [`create_compressed_capture_file`].
+#[cfg(feature = "compression")]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Compression {
+    /// `.bin.gz`, readable with any gzip tool.
+    Gzip,
+    /// `.bin.zst`, smaller and faster to write than gzip.
+    Zstd,
+}
+
+/// Like [`create_capture_file`], compressing the capture, e.g. into
+/// `capture-20240131T142502Z.bin.zst`.
+///
+/// The compressed stream is completed when the writer is dropped. Flushing it, as
+/// [`TraceStream::flush`](crate::TraceStream::flush) does, makes everything written so
+/// far readable, at some cost in compression. [`Replay::open`](crate::source::Replay::open)
+/// reads both formats.
+#[cfg(feature = "compression")]
+pub fn create_compressed_capture_file<P: AsRef<Path>>(
+    dir: P,
+    compression: Compression,
+) -> io::Result<(PathBuf, Box<dyn Write + Send + Sync>)> {
+    let (path, writer): (_, Box<dyn Write + Send + Sync>) = match compression {
+        Compression::Gzip => {
+            let (path, file) = create_file(dir.as_ref(), "bin.gz")?;
+            let encoder = flate2::write::GzEncoder::new(file, flate2::Compression::default());
+            (path, Box::new(encoder))
+        }
+        Compression::Zstd => {
+            let (path, file) = create_file(dir.as_ref(), "bin.zst")?;
+            let encoder = zstd::Encoder::new(file, zstd::DEFAULT_COMPRESSION_LEVEL)?;
+            (path, Box::new(ZstdWriter(Some(encoder))))
+        }
+    };
+    Ok((path, writer))
+}
+
+/// Completes the zstd stream when dropped, like flate2's encoders do.
+#[cfg(feature = "compression")]
+struct ZstdWriter(Option<zstd::Encoder<'static, File>>);
+
+#[cfg(feature = "compression")]
+impl Write for ZstdWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.as_mut().expect("written after finishing").write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.as_mut().expect("flushed after finishing").flush()
+    }
+}
+
+#[cfg(feature = "compression")]
+impl Drop for ZstdWriter {
+    fn drop(&mut self) {
+        if let Some(encoder) = self.0.take() {
+            if let Err(e) = encoder.finish() {
+                log::warn!("could not complete the compressed capture: {}", e);
+            }
+        }
+    }
+}
+
+/// Creates a new file named after the current UTC time in `dir`, with `extension`.
+fn create_file(dir: &Path, extension: &str) -> io::Result<(PathBuf, File)> {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+    let stem = format!("capture-{}", utc_timestamp(secs));
+
+    for attempt in 0u32.. {
+        let name = match attempt {
+            0 => format!("{}.{}", stem, extension),
+            n => format!("{}-{}.{}", stem, n, extension),
+        };
+        let path = dir.join(name);
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    unreachable!()
+}
+
+/// Formats seconds since the Unix epoch as `YYYYMMDDTHHMMSSZ`.
+fn utc_timestamp(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    let time = secs % 86_400;
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm).
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year,
+        month,
+        day,
+        time / 3_600,
+        time / 60 % 60,
+        time % 60
+    )
+}
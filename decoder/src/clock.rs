@@ -0,0 +1,233 @@
+//! Anchoring of device timestamps to host wall-clock time.
+
+use std::collections::VecDeque;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tracing::Span;
+use tracing_opentelemetry::OtelData;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Registry;
+
+/// How fast the estimated offset may grow, in parts per million of device time, to follow
+/// a device clock that runs slower than the host's.
+const MAX_DRIFT_PPM: u64 = 200;
+
+/// Most time sync requests waiting for their reply; older ones are forgotten.
+const MAX_PENDING_SYNCS: usize = 16;
+/// Most time sync round trips the offset and drift are fitted to.
+const MAX_SYNC_SAMPLES: usize = 64;
+/// Round trips longer than twice the shortest one, plus this many microseconds of
+/// scheduling noise, are left out of the fit.
+const ROUND_TRIP_SLACK: u64 = 1_000;
+/// How many times the shortest round trip the sync samples need to span for the drift
+/// to be estimated, which keeps its error below 0.1%.
+const MIN_DRIFT_SPAN_FACTOR: u64 = 1_000;
+
+/// Estimates the wall-clock time of device timestamps from when frames arrive.
+///
+/// Frames arrive some (variable) time after the device stamped them, so every frame
+/// gives an upper bound for the offset between the two clocks. The smallest one seen,
+/// i.e. the frame with the least transport delay, is the best estimate. The estimate
+/// is allowed to creep up slowly so clock drift does not accumulate, and starts over
+/// when the device clock jumps backwards (a reboot).
+///
+/// Replies to time sync requests replace this estimate. Each round trip places the
+/// device timestamp of the reply halfway between sending the request and receiving the
+/// reply, which is off by at most half the round trip. Offset and drift are fitted to
+/// the round trips with the least delay, so the anchoring stays accurate over captures
+/// of many hours.
+#[derive(Debug, Default)]
+pub(crate) struct WallClock {
+    /// Host minus device time, in microseconds.
+    offset: Option<i128>,
+    last_device: u64,
+    /// Nonce and host time in microseconds of the requests waiting for their reply.
+    pending: VecDeque<(u32, i128)>,
+    samples: VecDeque<SyncSample>,
+    fit: Option<Fit>,
+}
+
+#[derive(Debug)]
+struct SyncSample {
+    device: u64,
+    /// Host time halfway through the round trip, in microseconds.
+    host: i128,
+    round_trip: u64,
+}
+
+/// Host time `host` at device time `device`, and host microseconds per device
+/// microsecond, unless the samples span too little time to tell.
+#[derive(Debug)]
+struct Fit {
+    device: u64,
+    host: i128,
+    rate: Option<f64>,
+}
+
+impl WallClock {
+    pub(crate) fn observe(&mut self, device_micros: u64, received: SystemTime) {
+        let Some(host_micros) = host_micros(received) else {
+            return;
+        };
+        let observed = host_micros - i128::from(device_micros);
+
+        self.offset = match self.offset {
+            Some(offset) if device_micros >= self.last_device => {
+                let creep =
+                    (device_micros - self.last_device).saturating_mul(MAX_DRIFT_PPM) / 1_000_000;
+                Some(observed.min(offset + i128::from(creep)))
+            }
+            _ => {
+                // The device clock started over, so the round trips no longer apply.
+                self.samples.clear();
+                self.fit = None;
+                Some(observed)
+            }
+        };
+        self.last_device = device_micros;
+    }
+
+    /// Records that the time sync request `nonce` was sent at `sent`.
+    pub(crate) fn sync_requested(&mut self, nonce: u32, sent: SystemTime) {
+        let Some(sent) = host_micros(sent) else {
+            return;
+        };
+        if self.pending.len() == MAX_PENDING_SYNCS {
+            self.pending.pop_front();
+        }
+        self.pending.push_back((nonce, sent));
+    }
+
+    /// Adds the round trip of the request `nonce`, answered at `device_micros` and
+    /// received at `received`. Replies to unknown requests are ignored.
+    pub(crate) fn sync_replied(&mut self, nonce: u32, device_micros: u64, received: SystemTime) {
+        let Some(index) = self.pending.iter().position(|(n, _)| *n == nonce) else {
+            return;
+        };
+        let (_, sent) = self.pending.remove(index).unwrap();
+        let Some(received) = host_micros(received) else {
+            return;
+        };
+        let round_trip = (received - sent).max(0);
+        if self.samples.len() == MAX_SYNC_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(SyncSample {
+            device: device_micros,
+            host: sent + round_trip / 2,
+            round_trip: round_trip as u64,
+        });
+        self.fit = self.fit_samples();
+    }
+
+    /// Fits a line through the samples with the shortest round trips.
+    fn fit_samples(&self) -> Option<Fit> {
+        let shortest = self.samples.iter().map(|sample| sample.round_trip).min()?;
+        let samples: Vec<_> = self
+            .samples
+            .iter()
+            .filter(|sample| {
+                sample.round_trip <= shortest.saturating_mul(2).saturating_add(ROUND_TRIP_SLACK)
+            })
+            .collect();
+        let n = samples.len() as f64;
+        let base = samples[0];
+        let points: Vec<(f64, f64)> = samples
+            .iter()
+            .map(|sample| {
+                (
+                    sample.device as f64 - base.device as f64,
+                    (sample.host - base.host) as f64,
+                )
+            })
+            .collect();
+        let mean_device = points.iter().map(|(device, _)| device).sum::<f64>() / n;
+        let mean_host = points.iter().map(|(_, host)| host).sum::<f64>() / n;
+
+        let (min, max) = samples.iter().fold((u64::MAX, 0), |(min, max), sample| {
+            (min.min(sample.device), max.max(sample.device))
+        });
+        let rate = if max - min >= shortest.max(1).saturating_mul(MIN_DRIFT_SPAN_FACTOR) {
+            let (covariance, variance) =
+                points
+                    .iter()
+                    .fold((0.0, 0.0), |(covariance, variance), (device, host)| {
+                        let device = device - mean_device;
+                        (
+                            covariance + device * (host - mean_host),
+                            variance + device * device,
+                        )
+                    });
+            Some(covariance / variance)
+        } else {
+            None
+        };
+        Some(Fit {
+            device: base.device,
+            host: base.host + (mean_host - rate.unwrap_or(1.0) * mean_device).round() as i128,
+            rate,
+        })
+    }
+
+    /// The estimated drift of the device clock, in parts per million: positive when it
+    /// runs faster than the host's. `None` until time sync round trips span enough time.
+    pub(crate) fn drift_ppm(&self) -> Option<f64> {
+        let rate = self.fit.as_ref()?.rate?;
+        Some((1.0 / rate - 1.0) * 1_000_000.0)
+    }
+
+    pub(crate) fn wall_time(&self, device_micros: u64) -> Option<SystemTime> {
+        let micros = match &self.fit {
+            Some(fit) => {
+                let elapsed = device_micros as f64 - fit.device as f64;
+                fit.host
+                    .saturating_add((elapsed * fit.rate.unwrap_or(1.0)).round() as i128)
+            }
+            None => self.offset? + i128::from(device_micros),
+        };
+        let micros = u64::try_from(micros).ok()?;
+        UNIX_EPOCH.checked_add(Duration::from_micros(micros))
+    }
+}
+
+fn host_micros(time: SystemTime) -> Option<i128> {
+    let since_epoch = time.duration_since(UNIX_EPOCH).ok()?;
+    Some(since_epoch.as_micros() as i128)
+}
+
+/// Runs `f` on the OpenTelemetry data of `span`, if it is recorded by an OpenTelemetry
+/// layer on top of a `tracing_subscriber::Registry`.
+fn with_otel_data(span: &Span, f: impl FnOnce(&mut OtelData)) {
+    span.with_subscriber(|(id, dispatch)| {
+        let Some(registry) = dispatch.downcast_ref::<Registry>() else {
+            return;
+        };
+        if let Some(span) = registry.span(id) {
+            if let Some(data) = span.extensions_mut().get_mut::<OtelData>() {
+                f(data);
+            }
+        }
+    });
+}
+
+pub(crate) fn set_start_time(span: &Span, time: SystemTime) {
+    with_otel_data(span, |data| data.builder.start_time = Some(time));
+}
+
+pub(crate) fn set_end_time(span: &Span, time: SystemTime) {
+    with_otel_data(span, |data| data.builder.end_time = Some(time));
+}
+
+/// Sets the time of the event most recently recorded in `span`.
+pub(crate) fn set_last_event_time(span: &Span, time: SystemTime) {
+    with_otel_data(span, |data| {
+        if let Some(event) = data
+            .builder
+            .events
+            .as_mut()
+            .and_then(|events| events.last_mut())
+        {
+            event.timestamp = time;
+        }
+    });
+}
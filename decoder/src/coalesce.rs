@@ -0,0 +1,59 @@
+//! Merging of repeated events.
+
+use crate::{RecordKind, TraceRecord};
+
+/// Field counting the events merged into a record.
+pub(crate) const COUNT_FIELD: &str = "count";
+
+/// Merges runs of identical consecutive events into the first of them, which gets a
+/// `count` field.
+///
+/// Events are identical when they come from the same log statement with the same level,
+/// message and fields. Consecutive events of a context are in the same span, since any
+/// span enter or exit ends the run.
+pub(crate) struct EventCoalescer {
+    /// The first event of the current run, and the length of the run.
+    run: Option<(TraceRecord, u64)>,
+}
+
+impl EventCoalescer {
+    pub(crate) fn new() -> Self {
+        Self { run: None }
+    }
+
+    /// Adds `record`, appending the records that are done to `ready`.
+    pub(crate) fn push(&mut self, record: TraceRecord, ready: &mut Vec<TraceRecord>) {
+        if let Some((first, count)) = &mut self.run {
+            if is_repeat(first, &record) {
+                *count += 1;
+                return;
+            }
+        }
+        self.drain(ready);
+        if record.kind == RecordKind::Event {
+            self.run = Some((record, 1));
+        } else {
+            ready.push(record);
+        }
+    }
+
+    /// Passes on the current run.
+    pub(crate) fn drain(&mut self, ready: &mut Vec<TraceRecord>) {
+        if let Some((mut first, count)) = self.run.take() {
+            if count > 1 {
+                first
+                    .fields
+                    .push((COUNT_FIELD.to_string(), count.to_string()));
+            }
+            ready.push(first);
+        }
+    }
+}
+
+fn is_repeat(first: &TraceRecord, record: &TraceRecord) -> bool {
+    record.kind == RecordKind::Event
+        && record.level == first.level
+        && record.location == first.location
+        && record.message == first.message
+        && record.fields == first.fields
+}
@@ -0,0 +1,117 @@
+//! Commands for the runtime log filter of the device.
+
+use std::fmt;
+use std::str::FromStr;
+
+use tracing::level_filters::LevelFilter;
+
+use crate::Error;
+
+/// Longest command line the device accepts, without its line ending.
+const MAX_LINE: usize = 64;
+
+/// A command for the device's runtime log filter, applied by `tracing_defmt::control`
+/// on the device (with the facade's `control` feature).
+///
+/// Commands are sent as text lines over an RTT down channel or the serial port the
+/// device reads them from, e.g. `set-filter app::net=trace` to turn on trace logging
+/// for one module of a deployed device. The filter can only narrow what the firmware
+/// was built with (`DEFMT_LOG`). The device answers every command with a
+/// `control: ...` log frame.
+///
+/// # Example
+/// ```
+/// # use tracing::level_filters::LevelFilter;
+/// # use tracing_defmt_decoder::ControlCommand;
+/// let command: ControlCommand = "set-filter app::net=trace".parse().unwrap();
+/// assert_eq!(
+///     command,
+///     ControlCommand::SetFilter {
+///         target: Some("app::net".into()),
+///         level: LevelFilter::TRACE,
+///     }
+/// );
+/// assert_eq!(command.to_bytes(), b"set-filter app::net=trace\n");
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ControlCommand {
+    /// Sets the level of the module path `target` and the modules below it, or without a
+    /// target, of everything without a filter of its own. The most specific target wins.
+    SetFilter {
+        target: Option<String>,
+        level: LevelFilter,
+    },
+    /// Removes the target filters and restores the level the firmware started with.
+    ResetFilter,
+    /// Asks the device for its current time, answered with a `time_sync: <nonce>` frame.
+    /// Created by [`TraceStream::time_sync_request`](crate::TraceStream::time_sync_request).
+    Sync { nonce: u32 },
+}
+
+impl ControlCommand {
+    /// The command line as sent to the device, including the line ending.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        format!("{}\n", self).into_bytes()
+    }
+}
+
+impl fmt::Display for ControlCommand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ControlCommand::SetFilter {
+                target: Some(target),
+                level,
+            } => write!(f, "set-filter {}={}", target, level_name(*level)),
+            ControlCommand::SetFilter {
+                target: None,
+                level,
+            } => write!(f, "set-filter {}", level_name(*level)),
+            ControlCommand::ResetFilter => f.write_str("reset-filter"),
+            ControlCommand::Sync { nonce } => write!(f, "sync {}", nonce),
+        }
+    }
+}
+
+/// The level as the device spells it.
+fn level_name(level: LevelFilter) -> String {
+    level.to_string().to_lowercase()
+}
+
+impl FromStr for ControlCommand {
+    type Err = Error;
+
+    /// Parses `set-filter [<target>=]<level>`, `reset-filter` or `sync <nonce>`.
+    fn from_str(line: &str) -> Result<Self, Error> {
+        let invalid = |reason: &str| Error::Command(format!("{}: {:?}", reason, line));
+        let line = line.trim();
+        if line.len() > MAX_LINE {
+            return Err(invalid("longer than the device accepts"));
+        }
+        if line == "reset-filter" {
+            return Ok(ControlCommand::ResetFilter);
+        }
+        if let Some(nonce) = line.strip_prefix("sync ") {
+            let nonce = nonce.trim().parse().map_err(|_| invalid("invalid nonce"))?;
+            return Ok(ControlCommand::Sync { nonce });
+        }
+        let filter = line
+            .strip_prefix("set-filter ")
+            .ok_or_else(|| invalid("expected set-filter, reset-filter or sync"))?
+            .trim();
+        let (target, level) = match filter.split_once('=') {
+            Some((target, level)) => (Some(target.trim()), level.trim()),
+            None => (None, filter),
+        };
+        if target.is_some_and(|target| target.is_empty() || target.contains(char::is_whitespace)) {
+            return Err(invalid("invalid target"));
+        }
+        let level = match level {
+            "off" | "error" | "warn" | "info" | "debug" | "trace" => level.parse().unwrap(),
+            _ => return Err(invalid("invalid level")),
+        };
+        Ok(ControlCommand::SetFilter {
+            target: target.map(str::to_string),
+            level,
+        })
+    }
+}
@@ -0,0 +1,233 @@
+//! Crash frames logged by the facade's fault handler helpers (see [`wire::CRASH`]), turned
+//! into a `crash` span whose fields say where and why the firmware faulted.
+
+use std::fmt::Write;
+
+use tracing::Level;
+use tracing_defmt_wire as wire;
+
+use crate::record::{self, RecordKind, RecordLocation, TraceRecord};
+use crate::symbols::Symbolizer;
+
+/// The causes of a fault flagged in the Configurable Fault Status Register, by bit.
+const CFSR_CAUSES: [(u32, &str); 18] = [
+    (0, "instruction access violation"),
+    (1, "data access violation"),
+    (3, "MemManage fault on exception return"),
+    (
+        4,
+        "MemManage fault on exception entry, likely a stack overflow",
+    ),
+    (
+        5,
+        "MemManage fault during floating-point lazy state preservation",
+    ),
+    (8, "instruction bus error"),
+    (9, "precise data bus error"),
+    (10, "imprecise data bus error"),
+    (11, "bus fault on exception return"),
+    (12, "bus fault on exception entry, likely a stack overflow"),
+    (
+        13,
+        "bus fault during floating-point lazy state preservation",
+    ),
+    (16, "undefined instruction"),
+    (
+        17,
+        "invalid state, e.g. a jump to an address without the Thumb bit",
+    ),
+    (18, "invalid exception return"),
+    (19, "coprocessor access, e.g. the FPU while it is disabled"),
+    (20, "stack overflow past the stack limit"),
+    (24, "unaligned access"),
+    (25, "division by zero"),
+];
+/// CFSR bits that say the MMFAR and BFAR registers hold the faulting address.
+const MMARVALID: u32 = 1 << 7;
+const BFARVALID: u32 = 1 << 15;
+/// HFSR bits: a vector table read failed, or a configurable fault was escalated.
+const VECTTBL: u32 = 1 << 1;
+const FORCED: u32 = 1 << 30;
+
+/// A decoded `crash` frame.
+#[derive(Clone, Debug)]
+pub(crate) struct Crash {
+    /// The exception, e.g. `HardFault`.
+    fault: String,
+    /// The registers in the order logged, values as logged.
+    registers: Vec<(String, String)>,
+    backtrace: Vec<u64>,
+}
+
+impl Crash {
+    pub(crate) fn parse(message: &str) -> Option<Self> {
+        let payload = message.strip_prefix(wire::CRASH)?;
+        let (fault, registers) = match payload.split_once(wire::FIELD_SEPARATOR) {
+            Some((fault, registers)) => (fault, record::parse_fields(registers)),
+            None => (payload, Vec::new()),
+        };
+        let mut crash = Crash {
+            fault: fault.trim().to_string(),
+            registers,
+            backtrace: Vec::new(),
+        };
+        if let Some(index) = crash
+            .registers
+            .iter()
+            .position(|(key, _)| key == wire::BACKTRACE_FIELD)
+        {
+            let (_, addresses) = crash.registers.remove(index);
+            crash.backtrace = addresses
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .split(',')
+                .filter_map(|address| parse_number(address.trim()))
+                .collect();
+        }
+        Some(crash)
+    }
+
+    fn register(&self, name: &str) -> Option<u64> {
+        self.registers
+            .iter()
+            .find(|(key, _)| key == name)
+            .and_then(|(_, value)| parse_number(value))
+    }
+
+    /// What the fault status registers say caused the fault.
+    fn reason(&self) -> Option<String> {
+        let cfsr = self.register("cfsr").unwrap_or(0) as u32;
+        let hfsr = self.register("hfsr").unwrap_or(0) as u32;
+        let mut causes: Vec<String> = CFSR_CAUSES
+            .iter()
+            .filter(|(bit, _)| cfsr & (1 << bit) != 0)
+            .map(|(_, cause)| cause.to_string())
+            .collect();
+        for (valid, register) in [(MMARVALID, "mmfar"), (BFARVALID, "bfar")] {
+            if let Some(address) = self.register(register).filter(|_| cfsr & valid != 0) {
+                causes.push(format!("at address {:#010x}", address));
+            }
+        }
+        if hfsr & VECTTBL != 0 {
+            causes.push("vector table read fault".to_string());
+        }
+        if causes.is_empty() && hfsr & FORCED != 0 {
+            causes.push("escalated fault".to_string());
+        }
+        (!causes.is_empty()).then(|| causes.join(", "))
+    }
+
+    /// The records of the `crash` span that stands for `frame`, the record of the crash
+    /// frame: its entry, an error event naming the faulting function, and its exit. They
+    /// take their timestamp and context from `frame`, and the source location of the
+    /// faulting instruction if `symbols` know it.
+    pub(crate) fn into_records(self, frame: TraceRecord, symbols: &Symbolizer) -> [TraceRecord; 3] {
+        let pc = self.register("pc");
+        let fault_location = pc.and_then(|pc| symbols.locate(pc));
+        let location = fault_location
+            .as_ref()
+            .and_then(|code| {
+                let file = code.file?;
+                let module = code
+                    .function
+                    .and_then(|function| function.rsplit_once("::"))
+                    .map_or("", |(module, _)| module);
+                Some(RecordLocation {
+                    file: file.clone(),
+                    line: code.line,
+                    module: module.into(),
+                })
+            })
+            .or_else(|| frame.location.clone());
+
+        let mut message = self.fault.clone();
+        match (&fault_location, pc) {
+            (Some(code), _) => write!(message, " in {}", code).unwrap(),
+            (None, Some(pc)) => write!(message, " at {:#010x}", pc).unwrap(),
+            (None, None) => {}
+        }
+        let reason = self.reason();
+        if let Some(reason) = &reason {
+            write!(message, ": {}", reason).unwrap();
+        }
+
+        let mut fields = vec![("fault".to_string(), self.fault.clone())];
+        if let Some(reason) = reason {
+            fields.push((wire::REASON_FIELD.to_string(), reason));
+        }
+        if let Some(function) = fault_location.as_ref().and_then(|code| code.function) {
+            fields.push(("function".to_string(), function.to_string()));
+        }
+        fields.extend(self.registers.iter().cloned());
+        fields.push((wire::BACKTRACE_FIELD.to_string(), self.backtrace(symbols)));
+
+        let record = |kind, level, message: String, fields| TraceRecord {
+            kind,
+            level,
+            message,
+            fields,
+            location: location.clone(),
+            span_id: None,
+            parent_id: None,
+            trace_id: None,
+            otel_span_id: None,
+            interned_fields: Vec::new(),
+            ..frame.clone()
+        };
+        let level = frame.level.or(Some(Level::ERROR));
+        [
+            record(
+                RecordKind::SpanEnter,
+                level,
+                wire::CRASH_SPAN.to_string(),
+                fields,
+            ),
+            record(RecordKind::Event, Some(Level::ERROR), message, Vec::new()),
+            record(
+                RecordKind::SpanExit,
+                level,
+                wire::CRASH_SPAN.to_string(),
+                Vec::new(),
+            ),
+        ]
+    }
+
+    /// One line per frame, innermost first: the program counter, the link register and
+    /// the return addresses found on the stack, e.g.
+    /// `#1 0x08000f11 app::main at src/main.rs:30`. Return addresses point after the
+    /// call, so they are looked up one byte earlier.
+    fn backtrace(&self, symbols: &Symbolizer) -> String {
+        let pc = self.register("pc").map(|pc| (pc, pc));
+        // A fault in an exception handler leaves an EXC_RETURN value in the link
+        // register rather than a return address.
+        let lr = self
+            .register("lr")
+            .filter(|lr| lr & 0xff00_0000 != 0xff00_0000);
+        let returns = lr
+            .into_iter()
+            .chain(self.backtrace.iter().copied())
+            .map(|address| (address, (address & !1).saturating_sub(1)));
+
+        let mut frames: Vec<(u64, u64)> = pc.into_iter().chain(returns).collect();
+        frames.dedup_by_key(|(address, _)| *address);
+        let mut backtrace = String::new();
+        for (i, (address, lookup)) in frames.into_iter().enumerate() {
+            if i > 0 {
+                backtrace.push('\n');
+            }
+            write!(backtrace, "#{} {:#010x}", i, address).unwrap();
+            if let Some(code) = symbols.locate(lookup) {
+                write!(backtrace, " {}", code).unwrap();
+            }
+        }
+        backtrace
+    }
+}
+
+/// Parses a register value as logged: hex with `0x`, or decimal.
+fn parse_number(value: &str) -> Option<u64> {
+    match value.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
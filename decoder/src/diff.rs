@@ -0,0 +1,321 @@
+//! Comparison of two decoded sessions, e.g. of the firmware before and after a change.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use crate::export::{Exporter, SpanStats, SpanSummary};
+use crate::TraceRecord;
+
+/// Relative change of the compared duration that counts as a regression, in percent.
+const DEFAULT_THRESHOLD: f64 = 10.0;
+
+/// Which duration of a span name a [`TraceDiff`] compares.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum Statistic {
+    #[default]
+    Mean,
+    /// The duration this quantile (e.g. 0.99) of the spans are shorter than or equal to.
+    Percentile(f64),
+    Max,
+}
+
+impl Statistic {
+    fn of(self, stats: &SpanStats) -> u64 {
+        match self {
+            Statistic::Mean => stats.mean_us(),
+            Statistic::Percentile(quantile) => stats.percentile_us(quantile),
+            Statistic::Max => stats.max_us,
+        }
+    }
+}
+
+impl fmt::Display for Statistic {
+    /// `mean`, `max`, or the percentile, e.g. `p99`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Statistic::Mean => write!(f, "mean"),
+            // Rounded, so that 0.999 comes out as p99.9.
+            Statistic::Percentile(quantile) => {
+                write!(f, "p{}", (quantile * 1e8).round() / 1e6)
+            }
+            Statistic::Max => write!(f, "max"),
+        }
+    }
+}
+
+/// How a span name changed between the sessions.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Change {
+    /// Only in the second session.
+    Added,
+    /// Only in the first session.
+    Removed,
+    /// Slower beyond the threshold.
+    Regressed,
+    /// Faster beyond the threshold.
+    Improved,
+    Unchanged,
+}
+
+impl Change {
+    fn as_str(self) -> &'static str {
+        match self {
+            Change::Added => "added",
+            Change::Removed => "removed",
+            Change::Regressed => "regressed",
+            Change::Improved => "improved",
+            Change::Unchanged => "unchanged",
+        }
+    }
+}
+
+/// The comparison of the spans of one name.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpanDiff {
+    pub name: String,
+    /// The completed spans of the first session, `None` if it has none.
+    pub before: Option<SpanStats>,
+    /// The completed spans of the second session, `None` if it has none.
+    pub after: Option<SpanStats>,
+    pub change: Change,
+    /// Change of the compared duration in percent, when both sessions have the span.
+    pub duration_change: Option<f64>,
+}
+
+impl SpanDiff {
+    /// How many more spans of the name the second session completed.
+    pub fn count_delta(&self) -> i64 {
+        let count = |stats: &Option<SpanStats>| stats.as_ref().map_or(0, |stats| stats.count);
+        count(&self.after) as i64 - count(&self.before) as i64
+    }
+}
+
+/// Compares the spans of two decoded sessions by name: which are new or gone, how often
+/// each ran, and which got slower or faster beyond a threshold.
+///
+/// Only completed spans count. Durations use device timestamps when the firmware
+/// provides them, like [`SpanSummary`]. The comparison is printed as a table (its
+/// [`Display`](fmt::Display)) or rendered as JSON.
+///
+/// # Example
+/// ```rust,ignore
+/// let before = old_decoder.decode_records(&std::fs::read("v1.bin")?)?;
+/// let after = new_decoder.decode_records(&std::fs::read("v2.bin")?)?;
+/// let diff = TraceDiff::new(&before, &after).with_threshold(5.0);
+/// print!("{}", diff);
+/// assert!(diff.regressions().next().is_none());
+/// ```
+///
+/// ```text
+/// span       before   after   delta  before_us   after_us   change  status
+/// handle_rx    1200    1180     -20         52         61   +17.3%  regressed
+/// ```
+#[derive(Clone, Debug)]
+pub struct TraceDiff {
+    before: BTreeMap<String, SpanStats>,
+    after: BTreeMap<String, SpanStats>,
+    threshold: f64,
+    min_change: Duration,
+    statistic: Statistic,
+    spans: Vec<SpanDiff>,
+}
+
+impl TraceDiff {
+    /// Compares the records of the session `before` with those of `after`.
+    pub fn new(before: &[TraceRecord], after: &[TraceRecord]) -> Self {
+        let mut diff = Self {
+            before: span_stats(before),
+            after: span_stats(after),
+            threshold: DEFAULT_THRESHOLD,
+            min_change: Duration::ZERO,
+            statistic: Statistic::default(),
+            spans: Vec::new(),
+        };
+        diff.compare();
+        diff
+    }
+
+    /// Sets the change of the compared duration, in percent, beyond which a span counts
+    /// as regressed or improved. Defaults to 10%.
+    pub fn with_threshold(mut self, percent: f64) -> Self {
+        self.threshold = percent;
+        self.compare();
+        self
+    }
+
+    /// Ignores changes smaller than `change`, for short spans whose durations vary by a
+    /// large fraction of themselves. Defaults to none.
+    pub fn with_min_change(mut self, change: Duration) -> Self {
+        self.min_change = change;
+        self.compare();
+        self
+    }
+
+    /// Sets the duration compared. Defaults to [`Statistic::Mean`].
+    pub fn with_statistic(mut self, statistic: Statistic) -> Self {
+        self.statistic = statistic;
+        self.compare();
+        self
+    }
+
+    /// Every span name of either session, in alphabetical order.
+    pub fn spans(&self) -> &[SpanDiff] {
+        &self.spans
+    }
+
+    /// The span names that got slower beyond the threshold.
+    pub fn regressions(&self) -> impl Iterator<Item = &SpanDiff> {
+        self.spans
+            .iter()
+            .filter(|span| span.change == Change::Regressed)
+    }
+
+    /// The comparison as JSON: the settings and one object per span name with its
+    /// counts, compared durations and `change`.
+    pub fn to_json(&self) -> Value {
+        let spans: Vec<Value> = self
+            .spans
+            .iter()
+            .map(|span| {
+                let count = |stats: &Option<SpanStats>| stats.as_ref().map(|stats| stats.count);
+                let errors = |stats: &Option<SpanStats>| stats.as_ref().map(|stats| stats.errors);
+                json!({
+                    "name": span.name,
+                    "change": span.change.as_str(),
+                    "count_before": count(&span.before),
+                    "count_after": count(&span.after),
+                    "count_delta": span.count_delta(),
+                    "errors_before": errors(&span.before),
+                    "errors_after": errors(&span.after),
+                    "before_us": self.duration(&span.before),
+                    "after_us": self.duration(&span.after),
+                    "duration_change_percent": span.duration_change,
+                })
+            })
+            .collect();
+        json!({
+            "statistic": self.statistic.to_string(),
+            "threshold_percent": self.threshold,
+            "min_change_us": self.min_change.as_micros() as u64,
+            "regressions": self.regressions().count(),
+            "spans": spans,
+        })
+    }
+
+    fn duration(&self, stats: &Option<SpanStats>) -> Option<u64> {
+        stats.as_ref().map(|stats| self.statistic.of(stats))
+    }
+
+    fn compare(&mut self) {
+        let mut names: Vec<&String> = self.before.keys().chain(self.after.keys()).collect();
+        names.sort();
+        names.dedup();
+        self.spans = names
+            .into_iter()
+            .map(|name| {
+                let before = self.before.get(name).cloned();
+                let after = self.after.get(name).cloned();
+                let (change, duration_change) = match (&before, &after) {
+                    (None, _) => (Change::Added, None),
+                    (_, None) => (Change::Removed, None),
+                    (Some(before), Some(after)) => self.change(before, after),
+                };
+                SpanDiff {
+                    name: name.clone(),
+                    before,
+                    after,
+                    change,
+                    duration_change,
+                }
+            })
+            .collect();
+    }
+
+    fn change(&self, before: &SpanStats, after: &SpanStats) -> (Change, Option<f64>) {
+        let before = self.statistic.of(before);
+        let after = self.statistic.of(after);
+        let percent = match before {
+            0 if after == 0 => 0.0,
+            0 => f64::INFINITY,
+            _ => (after as f64 - before as f64) / before as f64 * 100.0,
+        };
+        let significant = after.abs_diff(before) >= self.min_change.as_micros() as u64;
+        let change = match percent {
+            percent if significant && percent > self.threshold => Change::Regressed,
+            percent if significant && percent < -self.threshold => Change::Improved,
+            _ => Change::Unchanged,
+        };
+        (change, Some(percent).filter(|percent| percent.is_finite()))
+    }
+}
+
+impl fmt::Display for TraceDiff {
+    /// The comparison as an aligned text table, one row per span name.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let width = self
+            .spans
+            .iter()
+            .map(|span| span.name.len())
+            .max()
+            .unwrap_or(0)
+            .max(4);
+        let row = |f: &mut fmt::Formatter, cells: [&str; 8]| {
+            let line = format!(
+                "{:width$}  {:>6}  {:>6}  {:>6}  {:>9}  {:>9}  {:>7}  {}",
+                cells[0], cells[1], cells[2], cells[3], cells[4], cells[5], cells[6], cells[7],
+            );
+            // Unchanged spans have no status.
+            writeln!(f, "{}", line.trim_end())
+        };
+        row(
+            f,
+            [
+                "span",
+                "before",
+                "after",
+                "delta",
+                "before_us",
+                "after_us",
+                "change",
+                "status",
+            ],
+        )?;
+        let optional = |value: Option<u64>| value.map_or("-".to_string(), |v| v.to_string());
+        for span in &self.spans {
+            let count = |stats: &Option<SpanStats>| stats.as_ref().map(|stats| stats.count);
+            row(
+                f,
+                [
+                    &span.name,
+                    &optional(count(&span.before)),
+                    &optional(count(&span.after)),
+                    &format!("{:+}", span.count_delta()),
+                    &optional(self.duration(&span.before)),
+                    &optional(self.duration(&span.after)),
+                    &span
+                        .duration_change
+                        .map_or("-".to_string(), |percent| format!("{:+.1}%", percent)),
+                    match span.change {
+                        Change::Unchanged => "",
+                        change => change.as_str(),
+                    },
+                ],
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// The statistics of the completed spans in `records`, by span name.
+fn span_stats(records: &[TraceRecord]) -> BTreeMap<String, SpanStats> {
+    let mut summary = SpanSummary::new(io::sink());
+    for record in records {
+        // Without an interval the summary only writes when finished, to the sink.
+        let _ = summary.export(record);
+    }
+    summary.spans().clone()
+}
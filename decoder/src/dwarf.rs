@@ -0,0 +1,245 @@
+//! Source locations of log statements and code addresses, read from the DWARF debug info.
+//!
+//! `defmt-decoder` computes the same information for log statements, but gives up on the
+//! whole ELF at the first attribute form or unit it does not expect (e.g. DWARF 5 string
+//! offsets). This walks the `DEFMT_LOG_STATEMENT` variables unit by unit and skips what
+//! it cannot read, so it can fill in the statements the defmt location table is missing.
+
+use std::borrow::Cow;
+use std::collections::{BTreeSet, HashMap};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use defmt_decoder::{Location, Locations, Table};
+use gimli::{AttributeValue, DebuggingInformationEntry, EndianSlice, Operation, RunTimeEndian};
+use object::{Object, ObjectSection};
+
+type Reader<'a> = EndianSlice<'a, RunTimeEndian>;
+type Dwarf<'a> = gimli::Dwarf<Reader<'a>>;
+type Unit<'a> = gimli::Unit<Reader<'a>>;
+
+/// Returns the locations of the log statements whose index is in `wanted`.
+pub(crate) fn locations(elf_data: &[u8], table: &Table, wanted: &BTreeSet<u64>) -> Locations {
+    let mut found = Locations::new();
+    let symbols: BTreeSet<&str> = table.raw_symbols().collect();
+    for_each_unit(elf_data, |dwarf, unit| {
+        read_unit(dwarf, unit, &symbols, wanted, &mut found)
+    });
+    found
+}
+
+/// A row of the line tables: the code from `address` up to the next row comes from
+/// `location`, a file and line, or from nowhere known past the end of a sequence.
+#[derive(Clone, Debug)]
+pub(crate) struct LineRow {
+    pub address: u64,
+    pub location: Option<(Arc<str>, u64)>,
+}
+
+/// Returns the rows of the line tables of all units, ordered by address.
+pub(crate) fn line_rows(elf_data: &[u8]) -> Vec<LineRow> {
+    let mut rows = Vec::new();
+    for_each_unit(elf_data, |dwarf, unit| read_lines(dwarf, unit, &mut rows));
+    // Where one sequence ends and the next starts, the start wins.
+    rows.sort_by_key(|row| (row.address, row.location.is_some()));
+    rows
+}
+
+/// Calls `f` with every unit of the DWARF debug info, skipping those it fails on.
+fn for_each_unit(elf_data: &[u8], mut f: impl FnMut(&Dwarf, &Unit) -> gimli::Result<()>) {
+    let Ok(object) = object::File::parse(elf_data) else {
+        return;
+    };
+    let endian = match object.is_little_endian() {
+        true => RunTimeEndian::Little,
+        false => RunTimeEndian::Big,
+    };
+    let load_section = |id: gimli::SectionId| -> gimli::Result<Cow<[u8]>> {
+        Ok(object
+            .section_by_name(id.name())
+            .and_then(|section| section.uncompressed_data().ok())
+            .unwrap_or(Cow::Borrowed(&[])))
+    };
+    let Ok(sections) = gimli::DwarfSections::load(load_section) else {
+        return;
+    };
+    let dwarf = sections.borrow(|section| EndianSlice::new(section, endian));
+
+    let mut units = dwarf.units();
+    loop {
+        let header = match units.next() {
+            Ok(Some(header)) => header,
+            Ok(None) => break,
+            Err(e) => {
+                log::debug!("stopped reading DWARF units: {}", e);
+                break;
+            }
+        };
+        let result = dwarf.unit(header).and_then(|unit| f(&dwarf, &unit));
+        if let Err(e) = result {
+            log::debug!("skipped DWARF unit at {:?}: {}", header.offset(), e);
+        }
+    }
+}
+
+fn read_lines(dwarf: &Dwarf, unit: &Unit, rows: &mut Vec<LineRow>) -> gimli::Result<()> {
+    let Some(program) = unit.line_program.clone() else {
+        return Ok(());
+    };
+    // Rows of a unit share a handful of files.
+    let mut files: HashMap<u64, Option<Arc<str>>> = HashMap::new();
+    let mut program_rows = program.rows();
+    while let Some((_, row)) = program_rows.next_row()? {
+        if row.end_sequence() {
+            rows.push(LineRow {
+                address: row.address(),
+                location: None,
+            });
+            continue;
+        }
+        let file = match files.get(&row.file_index()) {
+            Some(file) => file.clone(),
+            None => {
+                let file = file_path(dwarf, unit, row.file_index())?
+                    .map(|path| Arc::from(path.to_string_lossy().as_ref()));
+                files.insert(row.file_index(), file.clone());
+                file
+            }
+        };
+        let line = row.line().map_or(0, |line| line.get());
+        rows.push(LineRow {
+            address: row.address(),
+            location: file.map(|file| (file, line)),
+        });
+    }
+    Ok(())
+}
+
+fn read_unit(
+    dwarf: &Dwarf,
+    unit: &Unit,
+    symbols: &BTreeSet<&str>,
+    wanted: &BTreeSet<u64>,
+    found: &mut Locations,
+) -> gimli::Result<()> {
+    // Enclosing namespaces with their depth, which make up the module path.
+    let mut namespaces: Vec<(isize, String)> = Vec::new();
+    let mut depth = 0;
+    let mut entries = unit.entries();
+    while let Some((delta, entry)) = entries.next_dfs()? {
+        depth += delta;
+        namespaces.retain(|(namespace_depth, _)| *namespace_depth < depth);
+        match entry.tag() {
+            gimli::DW_TAG_namespace => {
+                if let Some(name) = string(dwarf, unit, entry, gimli::DW_AT_name)? {
+                    namespaces.push((depth, name));
+                }
+            }
+            gimli::DW_TAG_variable => {
+                let Some((index, file, line)) = log_statement(dwarf, unit, entry, symbols)? else {
+                    continue;
+                };
+                if wanted.contains(&index) {
+                    let module: Vec<&str> =
+                        namespaces.iter().map(|(_, name)| name.as_str()).collect();
+                    let location = Location {
+                        file,
+                        line,
+                        module: module.join("::"),
+                    };
+                    found.insert(index, location);
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Returns the index, file and line of a `DEFMT_LOG_STATEMENT` variable that is part of
+/// the table; variables of statements the linker removed keep a bogus address.
+fn log_statement(
+    dwarf: &Dwarf,
+    unit: &Unit,
+    entry: &DebuggingInformationEntry<Reader>,
+    symbols: &BTreeSet<&str>,
+) -> gimli::Result<Option<(u64, PathBuf, u64)>> {
+    if string(dwarf, unit, entry, gimli::DW_AT_name)?.as_deref() != Some("DEFMT_LOG_STATEMENT") {
+        return Ok(None);
+    }
+    let in_table = string(dwarf, unit, entry, gimli::DW_AT_linkage_name)?
+        .is_some_and(|symbol| symbols.contains(symbol.as_str()));
+    if !in_table {
+        return Ok(None);
+    }
+
+    let Some(AttributeValue::Exprloc(expression)) = entry.attr_value(gimli::DW_AT_location)? else {
+        return Ok(None);
+    };
+    let mut operations = expression.operations(unit.encoding());
+    let mut index = None;
+    while let Some(operation) = operations.next()? {
+        if let Operation::Address { address } = operation {
+            index = Some(address);
+            break;
+        }
+    }
+
+    let file = match entry.attr_value(gimli::DW_AT_decl_file)? {
+        Some(AttributeValue::FileIndex(file)) => Some(file),
+        Some(value) => value.udata_value(),
+        None => None,
+    };
+    let line = entry
+        .attr_value(gimli::DW_AT_decl_line)?
+        .and_then(|value| value.udata_value());
+    let (Some(index), Some(file), Some(line)) = (index, file, line) else {
+        return Ok(None);
+    };
+    Ok(file_path(dwarf, unit, file)?.map(|path| (index, path, line)))
+}
+
+fn file_path(dwarf: &Dwarf, unit: &Unit, file: u64) -> gimli::Result<Option<PathBuf>> {
+    let Some(program) = &unit.line_program else {
+        return Ok(None);
+    };
+    let header = program.header();
+    let Some(entry) = header.file(file) else {
+        return Ok(None);
+    };
+
+    let mut path = PathBuf::new();
+    if let Some(directory) = entry.directory(header) {
+        let directory = dwarf.attr_string(unit, directory)?.to_string_lossy();
+        if !std::path::Path::new(directory.as_ref()).is_absolute() {
+            if let Some(comp_dir) = &unit.comp_dir {
+                path.push(comp_dir.to_string_lossy().as_ref());
+            }
+        }
+        path.push(directory.as_ref());
+    }
+    path.push(
+        dwarf
+            .attr_string(unit, entry.path_name())?
+            .to_string_lossy()
+            .as_ref(),
+    );
+    Ok(Some(path))
+}
+
+fn string(
+    dwarf: &Dwarf,
+    unit: &Unit,
+    entry: &DebuggingInformationEntry<Reader>,
+    name: gimli::DwAt,
+) -> gimli::Result<Option<String>> {
+    match entry.attr_value(name)? {
+        Some(value) => Ok(Some(
+            dwarf
+                .attr_string(unit, value)?
+                .to_string_lossy()
+                .into_owned(),
+        )),
+        None => Ok(None),
+    }
+}
@@ -0,0 +1,87 @@
+//! Async adapter yielding decoded records from a tokio `AsyncRead`.
+
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_core::Stream;
+use tokio::io::{AsyncRead, ReadBuf};
+
+use crate::{Error, TraceRecord, TraceStream};
+
+const READ_BUFFER_SIZE: usize = 4096;
+
+/// A [`Stream`] of the records decoded from a reader, created by
+/// [`TraceStream::into_event_stream`].
+///
+/// The stream ends when the reader reaches EOF, after yielding any records still held
+/// back by the reorder window. Read errors are yielded once and end the stream as well.
+pub struct EventStream<'a, R> {
+    stream: TraceStream<'a>,
+    reader: R,
+    pending: VecDeque<TraceRecord>,
+    buffer: Box<[u8]>,
+    done: bool,
+}
+
+impl<'a> TraceStream<'a> {
+    /// Reads defmt data from `reader` and yields the decoded records as they complete.
+    ///
+    /// Like [`process_into`](Self::process_into), records are only yielded; nothing is
+    /// emitted to tracing or the registered exporters.
+    pub fn into_event_stream<R: AsyncRead + Unpin>(self, reader: R) -> EventStream<'a, R> {
+        EventStream {
+            stream: self,
+            reader,
+            pending: VecDeque::new(),
+            buffer: vec![0; READ_BUFFER_SIZE].into_boxed_slice(),
+            done: false,
+        }
+    }
+}
+
+impl<'a, R> EventStream<'a, R> {
+    /// Stops reading and returns the trace stream, e.g. to continue with another reader.
+    pub fn into_inner(self) -> TraceStream<'a> {
+        self.stream
+    }
+}
+
+impl<R: AsyncRead + Unpin> Stream for EventStream<'_, R> {
+    type Item = Result<TraceRecord, Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(record) = this.pending.pop_front() {
+                return Poll::Ready(Some(Ok(record)));
+            }
+            if this.done {
+                return Poll::Ready(None);
+            }
+
+            let mut buf = ReadBuf::new(&mut this.buffer);
+            match Pin::new(&mut this.reader).poll_read(cx, &mut buf) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Err(e)) => {
+                    this.done = true;
+                    return Poll::Ready(Some(Err(e.into())));
+                }
+                Poll::Ready(Ok(())) if buf.filled().is_empty() => {
+                    let pending = &mut this.pending;
+                    this.stream.flush_into(|record| pending.push_back(record));
+                    this.done = true;
+                }
+                Poll::Ready(Ok(())) => {
+                    let pending = &mut this.pending;
+                    if let Err(e) = this
+                        .stream
+                        .process_into(buf.filled(), |record| pending.push_back(record))
+                    {
+                        return Poll::Ready(Some(Err(e)));
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,415 @@
+//! Assertions on a decoded trace, e.g. the records of a [`hil`](crate::hil) run or of a
+//! recorded capture, see [`TraceDecoder::decode_records`](crate::TraceDecoder::decode_records).
+//!
+//! Expectations are built fluently and checked at the end, with `check` returning what
+//! did not match, or `assert` panicking with it:
+//!
+//! ```rust,ignore
+//! let records = decoder.decode_records(&std::fs::read("boot.bin")?)?;
+//! expect_span(&records, "handle_packet")
+//!     .with_field("len", 12)
+//!     .containing_event(Level::WARN, "checksum mismatch")
+//!     .assert();
+//! expect_event(&records, "link up").in_span("init").times(1).assert();
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+use tracing::Level;
+
+use crate::{RecordKind, TraceRecord};
+
+/// A failed expectation, saying what was expected and what the trace had instead.
+#[derive(thiserror::Error, Clone, Debug, PartialEq, Eq)]
+#[error("{0}")]
+pub struct ExpectationFailed(String);
+
+/// Expects a span named `name` in `records`.
+///
+/// The expectation holds if any one span of that name meets all its conditions.
+pub fn expect_span<'r>(records: &'r [TraceRecord], name: &str) -> SpanExpectation<'r> {
+    SpanExpectation {
+        records,
+        name: name.to_string(),
+        fields: Vec::new(),
+        completed: false,
+        within: None,
+        without_errors: false,
+        events: Vec::new(),
+    }
+}
+
+/// Expects an event whose message contains `message` in `records`, at least once unless
+/// [`times`](EventExpectation::times) says otherwise.
+pub fn expect_event<'r>(records: &'r [TraceRecord], message: &str) -> EventExpectation<'r> {
+    EventExpectation {
+        records,
+        message: message.to_string(),
+        level: None,
+        fields: Vec::new(),
+        span: None,
+        times: None,
+    }
+}
+
+/// Conditions on a span, see [`expect_span`].
+#[derive(Clone, Debug)]
+pub struct SpanExpectation<'r> {
+    records: &'r [TraceRecord],
+    name: String,
+    fields: Vec<(String, String)>,
+    completed: bool,
+    within: Option<Duration>,
+    without_errors: bool,
+    /// Level and message text of the events the span must contain.
+    events: Vec<(Level, String)>,
+}
+
+/// Conditions on an event, see [`expect_event`].
+#[derive(Clone, Debug)]
+pub struct EventExpectation<'r> {
+    records: &'r [TraceRecord],
+    message: String,
+    level: Option<Level>,
+    fields: Vec<(String, String)>,
+    span: Option<String>,
+    times: Option<usize>,
+}
+
+/// One span in the records: its enter and exit records and everything in between.
+pub(crate) struct SpanRun<'r> {
+    pub(crate) enter: &'r TraceRecord,
+    pub(crate) exit: Option<&'r TraceRecord>,
+    /// Events of the span and of the spans nested in it.
+    pub(crate) events: Vec<&'r TraceRecord>,
+}
+
+impl<'r> SpanRun<'r> {
+    /// The spans `select` picks from `records`, in the order they were entered.
+    pub(crate) fn collect(
+        records: &'r [TraceRecord],
+        mut select: impl FnMut(&TraceRecord) -> bool,
+    ) -> Vec<Self> {
+        let spans = Spans::new(records);
+        let mut runs = Vec::new();
+        let mut index = HashMap::new();
+        for record in records {
+            let Some(id) = record.span_id else {
+                continue;
+            };
+            match record.kind {
+                RecordKind::SpanEnter if select(record) => {
+                    index.insert(id, runs.len());
+                    runs.push(SpanRun {
+                        enter: record,
+                        exit: None,
+                        events: Vec::new(),
+                    });
+                }
+                RecordKind::SpanExit => {
+                    if let Some(&i) = index.get(&id) {
+                        runs[i].exit.get_or_insert(record);
+                    }
+                }
+                RecordKind::Event => {
+                    for id in spans.ancestry(Some(id)) {
+                        if let Some(&i) = index.get(&id) {
+                            runs[i].events.push(record);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        runs
+    }
+
+    /// From enter to exit, by device timestamps, or by wall-clock time without them.
+    pub(crate) fn duration(&self) -> Option<Duration> {
+        let exit = self.exit?;
+        if let (Some(start), Some(end)) = (self.enter.timestamp, exit.timestamp) {
+            return Some(Duration::from_micros(end.saturating_sub(start)));
+        }
+        exit.wall_time?.duration_since(self.enter.wall_time?).ok()
+    }
+}
+
+/// The name and parent of every span entered in the records, by span id.
+struct Spans<'r>(HashMap<u64, (&'r str, Option<u64>)>);
+
+impl<'r> Spans<'r> {
+    fn new(records: &'r [TraceRecord]) -> Self {
+        Self(
+            records
+                .iter()
+                .filter(|record| record.kind == RecordKind::SpanEnter)
+                .filter_map(|record| {
+                    let id = record.span_id?;
+                    Some((id, (record.message.as_str(), record.parent_id)))
+                })
+                .collect(),
+        )
+    }
+
+    /// The ids of span `id` and its ancestors, innermost first.
+    fn ancestry(&self, id: Option<u64>) -> impl Iterator<Item = u64> + '_ {
+        std::iter::successors(id, |id| self.0.get(id).and_then(|(_, parent)| *parent))
+    }
+
+    fn name(&self, id: u64) -> Option<&'r str> {
+        self.0.get(&id).map(|(name, _)| *name)
+    }
+}
+
+/// What `record` lacks of `fields`, `None` if it has them all.
+fn missing_field(record: &TraceRecord, fields: &[(String, String)]) -> Option<String> {
+    fields.iter().find_map(
+        |(key, value)| match record.fields.iter().find(|(k, _)| k == key) {
+            Some((_, v)) if v == value => None,
+            Some((_, v)) => Some(format!("has {}={}", key, v)),
+            None => Some(format!("has no field {}", key)),
+        },
+    )
+}
+
+impl<'r> SpanExpectation<'r> {
+    /// The span must have the field `key` with `value`, e.g. an argument recorded by
+    /// `#[instrument]`. Values are compared as text.
+    pub fn with_field(mut self, key: &str, value: impl fmt::Display) -> Self {
+        self.fields.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// The span must have exited.
+    pub fn completed(mut self) -> Self {
+        self.completed = true;
+        self
+    }
+
+    /// The span must have exited within `limit` of entering it, by device timestamps, or
+    /// by wall-clock time without them.
+    pub fn completed_within(mut self, limit: Duration) -> Self {
+        self.completed = true;
+        self.within = Some(limit);
+        self
+    }
+
+    /// No `ERROR` event may occur in the span or the spans nested in it.
+    pub fn without_errors(mut self) -> Self {
+        self.without_errors = true;
+        self
+    }
+
+    /// An event at `level` whose message contains `message` must occur in the span or
+    /// the spans nested in it.
+    pub fn containing_event(mut self, level: Level, message: &str) -> Self {
+        self.events.push((level, message.to_string()));
+        self
+    }
+
+    /// Whether some span meets all conditions.
+    pub fn check(&self) -> Result<(), ExpectationFailed> {
+        let runs = self.runs();
+        let mut mismatches = Vec::new();
+        for run in &runs {
+            match self.mismatch(run) {
+                None => return Ok(()),
+                Some(mismatch) => mismatches.push(mismatch),
+            }
+        }
+        let found = match runs.len() {
+            0 => "no such span".to_string(),
+            1 => format!("its one run {}", mismatches[0]),
+            n => format!("{} runs, which {}", n, mismatches.join("; ")),
+        };
+        Err(ExpectationFailed(format!(
+            "expected span `{}`{}, found {}",
+            self.name, self, found
+        )))
+    }
+
+    /// Panics unless some span meets all conditions.
+    #[track_caller]
+    pub fn assert(&self) {
+        if let Err(failed) = self.check() {
+            panic!("{}", failed);
+        }
+    }
+
+    /// What `run` does not meet, `None` if it meets all conditions.
+    fn mismatch(&self, run: &SpanRun) -> Option<String> {
+        if let Some(missing) = missing_field(run.enter, &self.fields) {
+            return Some(missing);
+        }
+        if self.completed && run.exit.is_none() {
+            return Some("did not complete".to_string());
+        }
+        if let Some(limit) = self.within {
+            match run.duration() {
+                Some(duration) if duration <= limit => {}
+                Some(duration) => return Some(format!("took {:?}", duration)),
+                None => return Some("has no timestamps".to_string()),
+            }
+        }
+        if self.without_errors {
+            let error = run
+                .events
+                .iter()
+                .find(|record| record.level == Some(Level::ERROR));
+            if let Some(error) = error {
+                return Some(format!("logged error `{}`", error.message));
+            }
+        }
+        for (level, message) in &self.events {
+            let found = run.events.iter().any(|record| {
+                record.level == Some(*level) && record.message.contains(message.as_str())
+            });
+            if !found {
+                return Some(format!("has no {} event `{}`", level, message));
+            }
+        }
+        None
+    }
+
+    /// The spans named `name`, in the order they were entered.
+    fn runs(&self) -> Vec<SpanRun<'r>> {
+        SpanRun::collect(self.records, |record| record.message == self.name)
+    }
+}
+
+impl fmt::Display for SpanExpectation<'_> {
+    /// The conditions, e.g. ` with len=12 completed within 2s without errors`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (key, value) in &self.fields {
+            write!(f, " with {}={}", key, value)?;
+        }
+        match self.within {
+            Some(limit) => write!(f, " completed within {:?}", limit)?,
+            None if self.completed => write!(f, " completed")?,
+            None => {}
+        }
+        if self.without_errors {
+            write!(f, " without errors")?;
+        }
+        for (level, message) in &self.events {
+            write!(f, " containing {} event `{}`", level, message)?;
+        }
+        Ok(())
+    }
+}
+
+impl EventExpectation<'_> {
+    /// The event must be logged at `level`.
+    pub fn at_level(mut self, level: Level) -> Self {
+        self.level = Some(level);
+        self
+    }
+
+    /// The event must have the field `key` with `value`, see
+    /// [`with_field_extraction`](crate::TraceDecoderBuilder::with_field_extraction).
+    /// Values are compared as text.
+    pub fn with_field(mut self, key: &str, value: impl fmt::Display) -> Self {
+        self.fields.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// The event must occur in a span named `name`, or in a span nested in one.
+    pub fn in_span(mut self, name: &str) -> Self {
+        self.span = Some(name.to_string());
+        self
+    }
+
+    /// The event must occur exactly `count` times, 0 for never.
+    pub fn times(mut self, count: usize) -> Self {
+        self.times = Some(count);
+        self
+    }
+
+    /// Whether the event occurs as often as expected.
+    pub fn check(&self) -> Result<(), ExpectationFailed> {
+        let spans = Spans::new(self.records);
+        let mut count = 0;
+        // The closest miss, to say why it did not match.
+        let mut miss = None;
+        for record in self.records {
+            if record.kind != RecordKind::Event || !record.message.contains(self.message.as_str()) {
+                continue;
+            }
+            match self.mismatch(record, &spans) {
+                None => count += 1,
+                Some(mismatch) => {
+                    miss.get_or_insert(mismatch);
+                }
+            }
+        }
+        let matched = match self.times {
+            Some(times) => count == times,
+            None => count > 0,
+        };
+        if matched {
+            return Ok(());
+        }
+        let found = match miss {
+            Some(miss) if count == 0 => format!("none, the first similar one {}", miss),
+            _ if count == 0 => "none".to_string(),
+            _ => format!("{}", count),
+        };
+        Err(ExpectationFailed(format!(
+            "expected event `{}`{}, found {}",
+            self.message, self, found
+        )))
+    }
+
+    /// Panics unless the event occurs as often as expected.
+    #[track_caller]
+    pub fn assert(&self) {
+        if let Err(failed) = self.check() {
+            panic!("{}", failed);
+        }
+    }
+
+    /// What `record` does not meet, `None` if it meets all conditions.
+    fn mismatch(&self, record: &TraceRecord, spans: &Spans) -> Option<String> {
+        if let Some(level) = self.level {
+            if record.level != Some(level) {
+                let actual = record
+                    .level
+                    .map_or("no level".to_string(), |l| l.to_string());
+                return Some(format!("is at {}", actual));
+            }
+        }
+        if let Some(missing) = missing_field(record, &self.fields) {
+            return Some(missing);
+        }
+        if let Some(name) = &self.span {
+            let inside = spans
+                .ancestry(record.span_id)
+                .any(|id| spans.name(id) == Some(name.as_str()));
+            if !inside {
+                return Some(format!("is not in span `{}`", name));
+            }
+        }
+        None
+    }
+}
+
+impl fmt::Display for EventExpectation<'_> {
+    /// The conditions, e.g. ` at WARN in span `init` 2 times`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(level) = self.level {
+            write!(f, " at {}", level)?;
+        }
+        for (key, value) in &self.fields {
+            write!(f, " with {}={}", key, value)?;
+        }
+        if let Some(span) = &self.span {
+            write!(f, " in span `{}`", span)?;
+        }
+        if let Some(times) = self.times {
+            write!(f, " {} times", times)?;
+        }
+        Ok(())
+    }
+}
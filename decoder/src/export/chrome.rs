@@ -0,0 +1,182 @@
+//! [Chrome Trace Event Format] export, viewable in `chrome://tracing` and ui.perfetto.dev.
+//!
+//! [Chrome Trace Event Format]: https://docs.google.com/document/d/1CvAClvFfyA5R-PhYUmn5OOQtYMH4h6I0nSsKchNAySU
+
+use std::collections::{BTreeSet, HashMap};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::Instant;
+
+use serde_json::{json, Map, Value};
+
+use super::Exporter;
+use crate::{RecordKind, TraceRecord};
+
+const PID: u32 = 1;
+
+/// Writes device spans as `B`/`E` duration events, log events as instant events and
+/// metrics as counter events (counters show their running total).
+///
+/// Each execution context becomes its own "thread" in the viewer. Device timestamps are
+/// used when the firmware provides them; otherwise the host receive time stands in.
+///
+/// The output uses the JSON array format, so a capture that was cut short (e.g. by a
+/// crash of the host tool) still loads; [`finish`](Self::finish) writes the closing bracket.
+pub struct ChromeTraceWriter<W: Write> {
+    writer: Option<W>,
+    first: bool,
+    contexts: BTreeSet<u32>,
+    counter_totals: HashMap<String, f64>,
+    started: Instant,
+}
+
+impl ChromeTraceWriter<BufWriter<File>> {
+    /// Creates (or truncates) a `trace.json` file at `path`.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write> ChromeTraceWriter<W> {
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        writer.write_all(b"[\n")?;
+        Ok(Self {
+            writer: Some(writer),
+            first: true,
+            contexts: BTreeSet::new(),
+            counter_totals: HashMap::new(),
+            started: Instant::now(),
+        })
+    }
+
+    /// Terminates the JSON array and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        let mut writer = self.writer.take().unwrap();
+        writer.write_all(b"\n]\n")?;
+        writer.flush()?;
+        Ok(writer)
+    }
+
+    fn write_event(&mut self, event: &Value) -> io::Result<()> {
+        let writer = self.writer.as_mut().unwrap();
+        if !self.first {
+            writer.write_all(b",\n")?;
+        }
+        self.first = false;
+        serde_json::to_writer(&mut *writer, event)?;
+        Ok(())
+    }
+
+    fn timestamp(&self, record: &TraceRecord) -> u64 {
+        record
+            .timestamp
+            .unwrap_or_else(|| self.started.elapsed().as_micros() as u64)
+    }
+}
+
+impl<W: Write> Exporter for ChromeTraceWriter<W> {
+    fn export(&mut self, record: &TraceRecord) -> io::Result<()> {
+        if self.contexts.insert(record.context) {
+            self.write_event(&json!({
+                "name": "thread_name",
+                "ph": "M",
+                "pid": PID,
+                "tid": record.context,
+                "args": { "name": format!("context {}", record.context) },
+            }))?;
+        }
+
+        let mut args: Map<String, Value> = record
+            .fields
+            .iter()
+            .map(|(key, value)| (key.clone(), Value::from(value.as_str())))
+            .collect();
+        if let Some(location) = &record.location {
+            args.insert("file".into(), Value::from(&*location.file));
+            args.insert("line".into(), location.line.into());
+        }
+
+        let ts = self.timestamp(record);
+        let event = match record.kind {
+            RecordKind::SpanEnter => json!({
+                "name": record.message,
+                "cat": category(record),
+                "ph": "B",
+                "ts": ts,
+                "pid": PID,
+                "tid": record.context,
+                "args": args,
+            }),
+            RecordKind::SpanExit => json!({
+                "ph": "E",
+                "ts": ts,
+                "pid": PID,
+                "tid": record.context,
+            }),
+            RecordKind::Event => {
+                if let Some(level) = record.level {
+                    args.insert("level".into(), level.as_str().into());
+                }
+                json!({
+                    "name": record.message,
+                    "cat": category(record),
+                    "ph": "i",
+                    "s": "t",
+                    "ts": ts,
+                    "pid": PID,
+                    "tid": record.context,
+                    "args": args,
+                })
+            }
+            RecordKind::Counter | RecordKind::Gauge => {
+                let Some(mut value) = metric_value(record) else {
+                    return Ok(());
+                };
+                if record.kind == RecordKind::Counter {
+                    let total = self
+                        .counter_totals
+                        .entry(record.message.clone())
+                        .or_default();
+                    *total += value;
+                    value = *total;
+                }
+                json!({
+                    "name": record.message,
+                    "ph": "C",
+                    "ts": ts,
+                    "pid": PID,
+                    "tid": record.context,
+                    "args": { "value": value },
+                })
+            }
+        };
+        self.write_event(&event)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.as_mut().unwrap().flush()
+    }
+}
+
+impl<W: Write> Drop for ChromeTraceWriter<W> {
+    fn drop(&mut self) {
+        if let Some(writer) = self.writer.as_mut() {
+            let _ = writer.write_all(b"\n]\n");
+            let _ = writer.flush();
+        }
+    }
+}
+
+fn metric_value(record: &TraceRecord) -> Option<f64> {
+    let (_, value) = record.fields.iter().find(|(key, _)| key == "value")?;
+    value.parse().ok()
+}
+
+fn category(record: &TraceRecord) -> &str {
+    record
+        .location
+        .as_ref()
+        .map(|location| &*location.module)
+        .unwrap_or("device")
+}
@@ -0,0 +1,83 @@
+//! CSV export of span timing, one row per completed span.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use super::span_rows::{SpanRow, SpanRows, COLUMNS};
+use super::Exporter;
+use crate::TraceRecord;
+
+/// Writes a row per completed span, for latency studies in pandas or DuckDB, e.g.
+/// `SELECT name, quantile_cont(duration_us, 0.99) FROM 'spans.csv' GROUP BY name`.
+///
+/// The columns are `name`, `start_us`, `end_us` and `duration_us` (device time, empty
+/// without timestamps), `context`, `span_id`, `parent_id`, `parent` (the parent's name)
+/// and `attributes`, the span's fields as a JSON object. Rows are written as spans exit,
+/// so a long soak run can be analyzed while it is going on.
+pub struct SpanCsvWriter<W: Write> {
+    writer: W,
+    rows: SpanRows,
+}
+
+impl SpanCsvWriter<BufWriter<File>> {
+    /// Creates (or truncates) a CSV file at `path`.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write> SpanCsvWriter<W> {
+    /// Writes the header row to `writer`.
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        writeln!(writer, "{}", COLUMNS.join(","))?;
+        Ok(Self {
+            writer,
+            rows: SpanRows::default(),
+        })
+    }
+
+    /// Flushes and returns the underlying writer. Spans that have not exited are left out.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn write_row(&mut self, row: &SpanRow) -> io::Result<()> {
+        let number = |value: Option<u64>| value.map(|value| value.to_string()).unwrap_or_default();
+        let fields = [
+            quote(&row.name),
+            number(row.start),
+            number(row.end),
+            number(row.duration()),
+            row.context.to_string(),
+            row.span_id.to_string(),
+            number(row.parent_id),
+            row.parent.as_deref().map(quote).unwrap_or_default(),
+            quote(&row.attributes()),
+        ];
+        writeln!(self.writer, "{}", fields.join(","))
+    }
+}
+
+impl<W: Write> Exporter for SpanCsvWriter<W> {
+    fn export(&mut self, record: &TraceRecord) -> io::Result<()> {
+        match self.rows.push(record) {
+            Some(row) => self.write_row(&row),
+            None => Ok(()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Quotes a field if it contains a separator, quote or line break (RFC 4180).
+fn quote(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
@@ -0,0 +1,89 @@
+//! JSON Lines output: one JSON object per decoded record.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Stdout, Write};
+use std::path::Path;
+
+use serde_json::{json, Map, Value};
+
+use super::Exporter;
+use crate::{RecordKind, TraceRecord};
+
+/// Writes each record as a single-line JSON object, for piping into `jq`, Vector or scripts.
+///
+/// ```text
+/// {"kind":"span_enter","timestamp":1000,"level":"INFO","message":"my_function","fields":{"x":"10"},"span_id":1,"parent_id":null,"context":0,"file":"src/main.rs","line":12,"module":"app"}
+/// ```
+pub struct JsonLinesWriter<W: Write> {
+    writer: W,
+}
+
+impl JsonLinesWriter<Stdout> {
+    /// Writes to stdout.
+    pub fn stdout() -> Self {
+        Self::new(io::stdout())
+    }
+}
+
+impl JsonLinesWriter<BufWriter<File>> {
+    /// Creates (or truncates) the file at `path`.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write> JsonLinesWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    /// Flushes and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+impl<W: Write> Exporter for JsonLinesWriter<W> {
+    fn export(&mut self, record: &TraceRecord) -> io::Result<()> {
+        serde_json::to_writer(&mut self.writer, &to_json(record))?;
+        self.writer.write_all(b"\n")
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// The JSON object of a record, as documented on [`JsonLinesWriter`].
+pub(crate) fn to_json(record: &TraceRecord) -> Value {
+    let kind = match record.kind {
+        RecordKind::SpanEnter => "span_enter",
+        RecordKind::SpanExit => "span_exit",
+        RecordKind::Event => "event",
+        RecordKind::Counter => "counter",
+        RecordKind::Gauge => "gauge",
+    };
+    let fields: Map<String, Value> = record
+        .fields
+        .iter()
+        .map(|(key, value)| (key.clone(), Value::from(value.as_str())))
+        .collect();
+
+    let mut line = json!({
+        "kind": kind,
+        "timestamp": record.timestamp,
+        "level": record.level.map(|level| level.as_str()),
+        "message": record.message,
+        "fields": fields,
+        "span_id": record.span_id,
+        "parent_id": record.parent_id,
+        "context": record.context,
+    });
+    if let Some(location) = &record.location {
+        line["file"] = Value::from(&*location.file);
+        line["line"] = location.line.into();
+        line["module"] = Value::from(&*location.module);
+    }
+    line
+}
@@ -0,0 +1,87 @@
+//! Device log events, emitted through the OpenTelemetry Logs API.
+
+use std::io;
+use std::time::SystemTime;
+
+use opentelemetry::logs::{AnyValue, LogRecord as _, Logger, Severity};
+use opentelemetry::trace::{SpanId, TraceFlags, TraceId};
+use tracing::Level;
+
+use super::Exporter;
+use crate::record;
+use crate::{RecordKind, TraceRecord};
+
+/// Emits every device log event as an OpenTelemetry log record, for backends that keep
+/// logs apart from traces, e.g. Loki next to Tempo or an OTLP logs pipeline.
+///
+/// The events still reach the spans as span events; this sends them as a signal of
+/// their own. Records carry the trace and span id of the enclosing span, if it is
+/// exported through OpenTelemetry, so the backend links each line to its trace. The
+/// device level becomes the severity, fields become attributes next to `code.filepath`,
+/// `code.lineno`, `code.namespace` and the context as `thread.id`, and the wall-clock
+/// time (see [`with_wall_clock_anchoring`](crate::TraceDecoderBuilder::with_wall_clock_anchoring))
+/// the timestamp. Span records and metrics are left out.
+///
+/// # Example
+/// ```rust,ignore
+/// let mut stream = decoder.new_stream();
+/// stream.add_exporter(DeviceLogs::new(logger_provider.logger("device")));
+/// ```
+pub struct DeviceLogs<L: Logger> {
+    logger: L,
+}
+
+impl<L: Logger> DeviceLogs<L> {
+    pub fn new(logger: L) -> Self {
+        Self { logger }
+    }
+}
+
+impl<L: Logger> Exporter for DeviceLogs<L> {
+    fn export(&mut self, record: &TraceRecord) -> io::Result<()> {
+        if record.kind != RecordKind::Event {
+            return Ok(());
+        }
+        let mut log = self.logger.create_log_record();
+        log.set_observed_timestamp(SystemTime::now());
+        if let Some(time) = record.wall_time {
+            log.set_timestamp(time);
+        }
+        if let Some(level) = record.level {
+            log.set_severity_number(severity(level));
+            log.set_severity_text(level.as_str());
+        }
+        log.set_body(record.message.clone().into());
+        if let Some(location) = &record.location {
+            log.add_attribute("code.filepath", location.file.to_string());
+            log.add_attribute("code.lineno", location.line as i64);
+            log.add_attribute("code.namespace", location.module.to_string());
+        }
+        log.add_attribute("thread.id", i64::from(record.context));
+        for (key, value) in &record.fields {
+            log.add_attribute(key.clone(), AnyValue::from(record.field_value(key, value)));
+        }
+        if let Some(error) = record::error_message(record) {
+            log.add_attribute("exception.message", error);
+        }
+        if let (Some(trace_id), Some(span_id)) = (&record.trace_id, &record.otel_span_id) {
+            if let (Ok(trace_id), Ok(span_id)) =
+                (TraceId::from_hex(trace_id), SpanId::from_hex(span_id))
+            {
+                log.set_trace_context(trace_id, span_id, Some(TraceFlags::SAMPLED));
+            }
+        }
+        self.logger.emit(log);
+        Ok(())
+    }
+}
+
+fn severity(level: Level) -> Severity {
+    match level {
+        Level::TRACE => Severity::Trace,
+        Level::DEBUG => Severity::Debug,
+        Level::INFO => Severity::Info,
+        Level::WARN => Severity::Warn,
+        Level::ERROR => Severity::Error,
+    }
+}
@@ -0,0 +1,257 @@
+//! Push of device log events to Grafana Loki.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_json::{json, Map, Value};
+
+use super::Exporter;
+use crate::{RecordKind, TraceRecord};
+
+/// Lines sent in one push request at most.
+const BATCH_LEN: usize = 500;
+/// Longest time a line waits for its batch to fill up.
+const BATCH_DELAY: Duration = Duration::from_secs(1);
+/// Lines waiting to be pushed; more are dropped.
+const QUEUE_LEN: usize = 10_000;
+
+/// Pushes device log events to Loki's push API, next to the trace export.
+///
+/// Every event becomes a log line with the labels `level` and `module`, plus the ones
+/// added with [`with_label`](Self::with_label), e.g. `device_id`. Its fields are appended
+/// to the line as `key=value`. The trace id of the enclosing span, if it is exported
+/// through OpenTelemetry, goes into the structured metadata as `trace_id`, which Grafana
+/// uses to link the line to its trace.
+///
+/// Lines are batched and pushed from a background thread, so a slow Loki does not hold up
+/// decoding. When too many are waiting they are dropped with a warning; failed pushes are
+/// logged and reported by the next [`flush`](Exporter::flush).
+///
+/// # Example
+/// ```rust,ignore
+/// let mut stream = decoder.new_stream();
+/// stream.add_exporter(LokiExporter::new("http://loki:3100").with_label("device_id", "board-7"));
+/// ```
+pub struct LokiExporter {
+    url: String,
+    labels: Vec<(String, String)>,
+    /// Started with the first line, once the labels are final.
+    worker: Option<Worker>,
+    dropped: u64,
+    /// Lines handed to the worker and not pushed yet.
+    queued: Arc<AtomicUsize>,
+}
+
+struct Worker {
+    sender: SyncSender<Message>,
+    thread: JoinHandle<()>,
+}
+
+enum Message {
+    Line(Line),
+    /// Push what is queued and report the outcome.
+    Flush(SyncSender<io::Result<()>>),
+}
+
+struct Line {
+    labels: Vec<(String, String)>,
+    time: SystemTime,
+    text: String,
+    trace_id: Option<String>,
+}
+
+impl LokiExporter {
+    /// Pushes to the Loki at `url`, e.g. `http://loki:3100`; the push API path is appended
+    /// unless `url` already ends with it.
+    pub fn new(url: impl Into<String>) -> Self {
+        let mut url = url.into();
+        if !url.ends_with("/loki/api/v1/push") {
+            url = format!("{}/loki/api/v1/push", url.trim_end_matches('/'));
+        }
+        Self {
+            url,
+            labels: Vec::new(),
+            worker: None,
+            dropped: 0,
+            queued: Arc::default(),
+        }
+    }
+
+    /// Adds a label to every line. Keep the number of distinct values low, Loki indexes
+    /// streams by their labels.
+    pub fn with_label(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.labels.push((key.into(), value.into()));
+        self
+    }
+
+    /// Number of lines dropped because too many were waiting to be pushed.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    fn worker(&mut self) -> &Worker {
+        self.worker.get_or_insert_with(|| {
+            let (sender, receiver) = mpsc::sync_channel(QUEUE_LEN);
+            let url = self.url.clone();
+            let queued = self.queued.clone();
+            let thread = thread::spawn(move || run(&url, receiver, &queued));
+            Worker { sender, thread }
+        })
+    }
+}
+
+impl Exporter for LokiExporter {
+    fn export(&mut self, record: &TraceRecord) -> io::Result<()> {
+        if record.kind != RecordKind::Event {
+            return Ok(());
+        }
+        let mut labels = self.labels.clone();
+        let level = record.level.map_or("unknown", |level| level.as_str());
+        labels.push(("level".to_string(), level.to_ascii_lowercase()));
+        if let Some(location) = &record.location {
+            labels.push(("module".to_string(), location.module.to_string()));
+        }
+        let mut text = record.message.clone();
+        for (key, value) in &record.fields {
+            text.push_str(&format!(" {}={}", key, value));
+        }
+        let line = Line {
+            labels,
+            time: record.wall_time.unwrap_or_else(SystemTime::now),
+            text,
+            trace_id: record.trace_id.clone(),
+        };
+
+        match self.worker().sender.try_send(Message::Line(line)) {
+            Ok(()) => {
+                self.queued.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            }
+            Err(TrySendError::Full(_)) => {
+                if self.dropped == 0 {
+                    log::warn!("Loki push is falling behind, dropping log lines");
+                }
+                self.dropped += 1;
+                Ok(())
+            }
+            Err(TrySendError::Disconnected(_)) => Err(io::Error::other("Loki push thread stopped")),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let Some(worker) = &self.worker else {
+            return Ok(());
+        };
+        let (ack, result) = mpsc::sync_channel(1);
+        worker
+            .sender
+            .send(Message::Flush(ack))
+            .map_err(|_| io::Error::other("Loki push thread stopped"))?;
+        result
+            .recv()
+            .map_err(|_| io::Error::other("Loki push thread stopped"))?
+    }
+
+    fn queued(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for LokiExporter {
+    /// Pushes the remaining lines.
+    fn drop(&mut self) {
+        if let Some(Worker { sender, thread }) = self.worker.take() {
+            drop(sender);
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Collects lines into batches and pushes them until the exporter is dropped.
+fn run(url: &str, receiver: Receiver<Message>, queued: &AtomicUsize) {
+    let client = reqwest::blocking::Client::new();
+    let mut batch = Vec::new();
+    // The first failure since the last flush.
+    let mut failure = None;
+    loop {
+        let (flush, ack) = match receiver.recv_timeout(BATCH_DELAY) {
+            Ok(Message::Line(line)) => {
+                batch.push(line);
+                (batch.len() >= BATCH_LEN, None)
+            }
+            Ok(Message::Flush(ack)) => (true, Some(ack)),
+            Err(RecvTimeoutError::Timeout) => (true, None),
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        if flush {
+            if let Err(e) = push(&client, url, &batch) {
+                failure.get_or_insert(e);
+            }
+            queued.fetch_sub(batch.len(), Ordering::Relaxed);
+            batch.clear();
+        }
+        if let Some(ack) = ack {
+            let _ = ack.send(failure.take().map_or(Ok(()), Err));
+        }
+    }
+    let _ = push(&client, url, &batch);
+}
+
+/// Sends `lines` in one request, logging failures.
+fn push(client: &reqwest::blocking::Client, url: &str, lines: &[Line]) -> io::Result<()> {
+    if lines.is_empty() {
+        return Ok(());
+    }
+    let result = send(client, url, lines);
+    if let Err(e) = &result {
+        log::warn!("Loki push of {} lines failed: {}", lines.len(), e);
+    }
+    result
+}
+
+fn send(client: &reqwest::blocking::Client, url: &str, lines: &[Line]) -> io::Result<()> {
+    let response = client
+        .post(url)
+        .header("Content-Type", "application/json")
+        .body(body(lines).to_string())
+        .send()
+        .map_err(io::Error::other)?;
+    if !response.status().is_success() {
+        let status = response.status();
+        let text = response.text().unwrap_or_default();
+        return Err(io::Error::other(format!("{}: {}", status, text.trim())));
+    }
+    Ok(())
+}
+
+/// The push request for `lines`, with one stream per label set.
+fn body(lines: &[Line]) -> Value {
+    let mut streams: BTreeMap<&[(String, String)], Vec<Value>> = BTreeMap::new();
+    for line in lines {
+        let nanos = line
+            .time
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_nanos());
+        let mut value = vec![nanos.to_string().into(), line.text.as_str().into()];
+        if let Some(trace_id) = &line.trace_id {
+            value.push(json!({ "trace_id": trace_id }));
+        }
+        streams.entry(&line.labels).or_default().push(value.into());
+    }
+    let streams: Vec<Value> = streams
+        .into_iter()
+        .map(|(labels, values)| {
+            let labels: Map<String, Value> = labels
+                .iter()
+                .map(|(key, value)| (key.clone(), value.as_str().into()))
+                .collect();
+            json!({ "stream": labels, "values": values })
+        })
+        .collect();
+    json!({ "streams": streams })
+}
@@ -0,0 +1,179 @@
+//! Span duration and device metrics, recorded through the OpenTelemetry metrics API.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::time::Instant;
+
+use opentelemetry::metrics::{Counter, Gauge, Histogram, Meter};
+use opentelemetry::KeyValue;
+
+use super::Exporter;
+use crate::{memory, RecordKind, TraceRecord};
+
+/// Histogram bucket boundaries in seconds, from 10 µs to 10 s. The SDK defaults are
+/// meant for milliseconds and would put most device spans into the first bucket.
+const DURATION_BOUNDARIES: [f64; 19] = [
+    0.000_01, 0.000_025, 0.000_05, 0.000_1, 0.000_25, 0.000_5, 0.001, 0.002_5, 0.005, 0.01, 0.025,
+    0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Records the duration of every device span into the `device.span.duration` histogram
+/// and counts calls in `device.span.calls`, both keyed by the `span.name` attribute.
+///
+/// Aggregation happens in the OpenTelemetry SDK, so long soak tests can be monitored
+/// for latency regressions without storing every span: export the meter's data with
+/// OTLP, or scrape it with the `opentelemetry-prometheus` exporter. Calls are counted
+/// on entry, so a function that hangs shows up as calls without durations.
+///
+/// Durations use device timestamps when the firmware provides them; otherwise the host
+/// receive time stands in.
+///
+/// # Example
+/// ```rust,ignore
+/// let mut stream = decoder.new_stream();
+/// stream.add_exporter(SpanMetrics::new(&opentelemetry::global::meter("device")));
+/// ```
+pub struct SpanMetrics {
+    duration: Histogram<f64>,
+    calls: Counter<u64>,
+    /// Name and start time of the open spans, per context.
+    open: BTreeMap<u32, Vec<(String, u64)>>,
+    started: Instant,
+}
+
+impl SpanMetrics {
+    /// Creates the instruments on `meter`.
+    pub fn new(meter: &Meter) -> Self {
+        Self {
+            duration: meter
+                .f64_histogram("device.span.duration")
+                .with_unit("s")
+                .with_description("Duration of device spans")
+                .with_boundaries(DURATION_BOUNDARIES.to_vec())
+                .build(),
+            calls: meter
+                .u64_counter("device.span.calls")
+                .with_unit("{call}")
+                .with_description("Number of device spans entered")
+                .build(),
+            open: BTreeMap::new(),
+            started: Instant::now(),
+        }
+    }
+
+    fn timestamp(&self, record: &TraceRecord) -> u64 {
+        record
+            .timestamp
+            .unwrap_or_else(|| self.started.elapsed().as_micros() as u64)
+    }
+}
+
+impl Exporter for SpanMetrics {
+    fn export(&mut self, record: &TraceRecord) -> io::Result<()> {
+        let timestamp = self.timestamp(record);
+        let open = self.open.entry(record.context).or_default();
+        match record.kind {
+            RecordKind::SpanEnter => {
+                self.calls
+                    .add(1, &[KeyValue::new("span.name", record.message.clone())]);
+                open.push((record.message.clone(), timestamp));
+            }
+            RecordKind::SpanExit => {
+                if let Some((name, start)) = open.pop() {
+                    let seconds = timestamp.saturating_sub(start) as f64 / 1e6;
+                    self.duration
+                        .record(seconds, &[KeyValue::new("span.name", name)]);
+                }
+            }
+            RecordKind::Event | RecordKind::Counter | RecordKind::Gauge => {}
+        }
+        Ok(())
+    }
+}
+
+/// Publishes the device's `counter:` and `gauge:` frames as OpenTelemetry counters and
+/// gauges of the same name.
+///
+/// The metric name is part of the interned format string, so it is resolved from the
+/// ELF like any other log message, e.g. `counter: rx_packets={=u32}, iface={=str}`.
+/// Fields after the value become attributes. How values are aggregated and how often
+/// they are exported is configured on the `MeterProvider` the meter comes from, e.g.
+/// with the interval of its `PeriodicReader`. Frames whose value is not a number, and
+/// negative counter increments, are dropped.
+///
+/// The memory gauges of the facade's `memory` module (`memory.heap.free`,
+/// `memory.heap.used` and `memory.stack.high_water`) also get the name of the innermost
+/// span open when they were measured as `span.name`, so memory pressure can be broken
+/// down by operation.
+///
+/// # Example
+/// ```rust,ignore
+/// let mut stream = decoder.new_stream();
+/// stream.add_exporter(DeviceMetrics::new(&opentelemetry::global::meter("device")));
+/// ```
+pub struct DeviceMetrics {
+    meter: Meter,
+    counters: HashMap<String, Counter<f64>>,
+    gauges: HashMap<String, Gauge<f64>>,
+    /// Names of the open spans, per context.
+    open: BTreeMap<u32, Vec<String>>,
+}
+
+impl DeviceMetrics {
+    pub fn new(meter: &Meter) -> Self {
+        Self {
+            meter: meter.clone(),
+            counters: HashMap::new(),
+            gauges: HashMap::new(),
+            open: BTreeMap::new(),
+        }
+    }
+}
+
+impl Exporter for DeviceMetrics {
+    fn export(&mut self, record: &TraceRecord) -> io::Result<()> {
+        match record.kind {
+            RecordKind::SpanEnter => {
+                let open = self.open.entry(record.context).or_default();
+                open.push(record.message.clone());
+                return Ok(());
+            }
+            RecordKind::SpanExit => {
+                self.open.entry(record.context).or_default().pop();
+                return Ok(());
+            }
+            _ => {}
+        }
+        let Some(((_, value), attributes)) = record.fields.split_first() else {
+            return Ok(());
+        };
+        let Ok(value) = value.parse::<f64>() else {
+            log::debug!("non-numeric value `{}` of metric {}", value, record.message);
+            return Ok(());
+        };
+        let mut attributes: Vec<KeyValue> = attributes
+            .iter()
+            .map(|(key, value)| KeyValue::new(key.clone(), value.clone()))
+            .collect();
+        let span = self.open.get(&record.context).and_then(|open| open.last());
+        if let Some(span) = span.filter(|_| memory::is_memory_gauge(&record.message)) {
+            attributes.push(KeyValue::new("span.name", span.clone()));
+        }
+
+        let meter = &self.meter;
+        match record.kind {
+            RecordKind::Counter if value >= 0.0 => self
+                .counters
+                .entry(record.message.clone())
+                .or_insert_with(|| meter.f64_counter(record.message.clone()).build())
+                .add(value, &attributes),
+            RecordKind::Gauge => self
+                .gauges
+                .entry(record.message.clone())
+                .or_insert_with(|| meter.f64_gauge(record.message.clone()).build())
+                .record(value, &attributes),
+            _ => {}
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,67 @@
+//! Exporters that consume [`TraceRecord`]s next to (or instead of) the tracing pipeline.
+//!
+//! Register an exporter with [`TraceStream::add_exporter`](crate::TraceStream::add_exporter).
+
+use std::io;
+
+use crate::TraceRecord;
+
+pub mod chrome;
+pub mod csv;
+pub mod json_lines;
+#[cfg(feature = "logs")]
+pub mod logs;
+#[cfg(feature = "loki")]
+pub mod loki;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+#[cfg(feature = "parquet")]
+pub mod parquet;
+#[cfg(feature = "perfetto")]
+pub mod perfetto;
+mod span_rows;
+pub mod speedscope;
+pub mod summary;
+#[cfg(feature = "syslog")]
+pub mod syslog;
+#[cfg(feature = "websocket")]
+pub mod websocket;
+
+pub use chrome::ChromeTraceWriter;
+pub use csv::SpanCsvWriter;
+pub use json_lines::JsonLinesWriter;
+#[cfg(feature = "logs")]
+pub use logs::DeviceLogs;
+#[cfg(feature = "loki")]
+pub use loki::LokiExporter;
+#[cfg(feature = "metrics")]
+pub use metrics::{DeviceMetrics, SpanMetrics};
+#[cfg(feature = "parquet")]
+pub use parquet::SpanParquetWriter;
+#[cfg(feature = "perfetto")]
+pub use perfetto::PerfettoTraceWriter;
+pub use speedscope::SpeedscopeWriter;
+pub use summary::{SpanStats, SpanSummary};
+#[cfg(all(feature = "syslog", unix))]
+pub use syslog::JournaldWriter;
+#[cfg(feature = "syslog")]
+pub use syslog::SyslogWriter;
+#[cfg(feature = "websocket")]
+pub use websocket::WebSocketServer;
+
+/// Receives every decoded record of a [`TraceStream`](crate::TraceStream).
+pub trait Exporter {
+    /// Called once per decoded frame, in stream order.
+    fn export(&mut self, record: &TraceRecord) -> io::Result<()>;
+
+    /// Writes out anything still buffered.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Number of records accepted but not sent yet, for exporters that send in the
+    /// background.
+    fn queued(&self) -> usize {
+        0
+    }
+}
@@ -0,0 +1,164 @@
+//! Parquet export of span timing, one row per completed span.
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use parquet::data_type::{ByteArray, ByteArrayType, Int64Type};
+use parquet::errors::ParquetError;
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::{SerializedColumnWriter, SerializedFileWriter};
+use parquet::schema::parser::parse_message_type;
+
+use super::span_rows::{SpanRow, SpanRows};
+use super::Exporter;
+use crate::TraceRecord;
+
+/// Rows buffered before they are written as a row group.
+const ROW_GROUP_LEN: usize = 8192;
+
+const SCHEMA: &str = "
+message span {
+    required binary name (UTF8);
+    optional int64 start_us;
+    optional int64 end_us;
+    optional int64 duration_us;
+    required int64 context;
+    required int64 span_id;
+    optional int64 parent_id;
+    optional binary parent (UTF8);
+    required binary attributes (UTF8);
+}";
+
+/// Writes a row per completed span to a Parquet file, with the columns of
+/// [`SpanCsvWriter`](super::SpanCsvWriter), for soak runs too long for CSV.
+///
+/// Rows are written in row groups of 8192; [`finish`](Self::finish) (or drop) writes the
+/// last one and the footer, without which the file cannot be read.
+pub struct SpanParquetWriter<W: Write + Send> {
+    writer: Option<SerializedFileWriter<W>>,
+    rows: SpanRows,
+    pending: Vec<SpanRow>,
+}
+
+impl SpanParquetWriter<File> {
+    /// Creates (or truncates) a Parquet file at `path`.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::new(File::create(path)?)
+    }
+}
+
+impl<W: Write + Send> SpanParquetWriter<W> {
+    pub fn new(writer: W) -> io::Result<Self> {
+        let schema = parse_message_type(SCHEMA).map_err(io::Error::other)?;
+        let properties = WriterProperties::builder().build();
+        let writer = SerializedFileWriter::new(writer, Arc::new(schema), Arc::new(properties))
+            .map_err(io::Error::other)?;
+        Ok(Self {
+            writer: Some(writer),
+            rows: SpanRows::default(),
+            pending: Vec::new(),
+        })
+    }
+
+    /// Writes the remaining rows and the footer, and returns the underlying writer. Spans
+    /// that have not exited are left out.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_row_group()?;
+        let writer = self.writer.take().unwrap();
+        writer.into_inner().map_err(io::Error::other)
+    }
+
+    fn write_row_group(&mut self) -> io::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let rows = std::mem::take(&mut self.pending);
+        write_rows(self.writer.as_mut().unwrap(), &rows).map_err(io::Error::other)
+    }
+}
+
+fn write_rows<W: Write + Send>(
+    writer: &mut SerializedFileWriter<W>,
+    rows: &[SpanRow],
+) -> Result<(), ParquetError> {
+    let mut row_group = writer.next_row_group()?;
+    let mut column = 0;
+    while let Some(mut writer) = row_group.next_column()? {
+        match column {
+            0 => write_strings(&mut writer, rows.iter().map(|row| Some(row.name.clone())))?,
+            1 => write_numbers(&mut writer, rows.iter().map(|row| row.start))?,
+            2 => write_numbers(&mut writer, rows.iter().map(|row| row.end))?,
+            3 => write_numbers(&mut writer, rows.iter().map(SpanRow::duration))?,
+            4 => write_numbers(&mut writer, rows.iter().map(|row| Some(row.context.into())))?,
+            5 => write_numbers(&mut writer, rows.iter().map(|row| Some(row.span_id)))?,
+            6 => write_numbers(&mut writer, rows.iter().map(|row| row.parent_id))?,
+            7 => write_strings(&mut writer, rows.iter().map(|row| row.parent.clone()))?,
+            _ => write_strings(&mut writer, rows.iter().map(|row| Some(row.attributes())))?,
+        }
+        writer.close()?;
+        column += 1;
+    }
+    row_group.close()?;
+    Ok(())
+}
+
+/// Writes a column; the definition levels mark which values are present, and are
+/// ignored for required columns.
+fn write_numbers(
+    writer: &mut SerializedColumnWriter,
+    values: impl Iterator<Item = Option<u64>>,
+) -> Result<(), ParquetError> {
+    let (present, levels): (Vec<_>, Vec<_>) = values
+        .map(|value| (value.map(|value| value as i64), i16::from(value.is_some())))
+        .unzip();
+    let present: Vec<i64> = present.into_iter().flatten().collect();
+    writer
+        .typed::<Int64Type>()
+        .write_batch(&present, Some(&levels), None)?;
+    Ok(())
+}
+
+fn write_strings(
+    writer: &mut SerializedColumnWriter,
+    values: impl Iterator<Item = Option<String>>,
+) -> Result<(), ParquetError> {
+    let (present, levels): (Vec<_>, Vec<_>) = values
+        .map(|value| {
+            let level = i16::from(value.is_some());
+            (
+                value.map(|value| ByteArray::from(value.into_bytes())),
+                level,
+            )
+        })
+        .unzip();
+    let present: Vec<ByteArray> = present.into_iter().flatten().collect();
+    writer
+        .typed::<ByteArrayType>()
+        .write_batch(&present, Some(&levels), None)?;
+    Ok(())
+}
+
+impl<W: Write + Send> Exporter for SpanParquetWriter<W> {
+    fn export(&mut self, record: &TraceRecord) -> io::Result<()> {
+        if let Some(row) = self.rows.push(record) {
+            self.pending.push(row);
+            if self.pending.len() >= ROW_GROUP_LEN {
+                self.write_row_group()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write + Send> Drop for SpanParquetWriter<W> {
+    fn drop(&mut self) {
+        if self.writer.is_some() {
+            let _ = self.write_row_group();
+            if let Some(mut writer) = self.writer.take() {
+                let _ = writer.finish();
+            }
+        }
+    }
+}
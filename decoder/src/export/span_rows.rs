@@ -0,0 +1,88 @@
+//! Completed spans as table rows, for the CSV and Parquet exports.
+
+use std::collections::HashMap;
+
+use serde_json::{Map, Value};
+
+use crate::{RecordKind, TraceRecord};
+
+/// Column names, in the order of the fields of [`SpanRow`].
+pub(crate) const COLUMNS: [&str; 9] = [
+    "name",
+    "start_us",
+    "end_us",
+    "duration_us",
+    "context",
+    "span_id",
+    "parent_id",
+    "parent",
+    "attributes",
+];
+
+/// One span, from its enter to its exit record.
+pub(crate) struct SpanRow {
+    pub(crate) name: String,
+    /// Device timestamps; missing when the firmware has none.
+    pub(crate) start: Option<u64>,
+    pub(crate) end: Option<u64>,
+    pub(crate) context: u32,
+    pub(crate) span_id: u64,
+    pub(crate) parent_id: Option<u64>,
+    pub(crate) parent: Option<String>,
+    pub(crate) fields: Vec<(String, String)>,
+}
+
+impl SpanRow {
+    pub(crate) fn duration(&self) -> Option<u64> {
+        self.end?.checked_sub(self.start?)
+    }
+
+    /// The span's fields as a JSON object, e.g. for `json.loads` or DuckDB's `->>`.
+    pub(crate) fn attributes(&self) -> String {
+        let fields: Map<String, Value> = self
+            .fields
+            .iter()
+            .map(|(key, value)| (key.clone(), Value::from(value.as_str())))
+            .collect();
+        Value::Object(fields).to_string()
+    }
+}
+
+/// Pairs enter and exit records into rows.
+#[derive(Default)]
+pub(crate) struct SpanRows {
+    open: HashMap<u64, SpanRow>,
+}
+
+impl SpanRows {
+    /// Returns the row of the span `record` closes.
+    pub(crate) fn push(&mut self, record: &TraceRecord) -> Option<SpanRow> {
+        let span_id = record.span_id?;
+        match record.kind {
+            RecordKind::SpanEnter => {
+                let parent = record
+                    .parent_id
+                    .and_then(|parent| self.open.get(&parent))
+                    .map(|parent| parent.name.clone());
+                let row = SpanRow {
+                    name: record.message.clone(),
+                    start: record.timestamp,
+                    end: None,
+                    context: record.context,
+                    span_id,
+                    parent_id: record.parent_id,
+                    parent,
+                    fields: record.fields.clone(),
+                };
+                self.open.insert(span_id, row);
+                None
+            }
+            RecordKind::SpanExit => {
+                let mut row = self.open.remove(&span_id)?;
+                row.end = record.timestamp;
+                Some(row)
+            }
+            RecordKind::Event | RecordKind::Counter | RecordKind::Gauge => None,
+        }
+    }
+}
@@ -0,0 +1,167 @@
+//! [speedscope] export, an interactive flamechart that runs in the browser.
+//!
+//! [speedscope]: https://www.speedscope.app
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::Instant;
+
+use serde_json::{json, Value};
+
+use super::Exporter;
+use crate::{RecordKind, TraceRecord};
+
+/// Writes device spans as an evented speedscope profile, one per execution context, so
+/// each core gets a lane of its own. Open the file at speedscope.app or with the
+/// `speedscope` CLI.
+///
+/// Device timestamps are used when the firmware provides them; otherwise the host
+/// receive time stands in. Log events and metrics have no place in a flamechart and are
+/// left out.
+///
+/// The format is a single JSON document, so everything is kept until
+/// [`finish`](Self::finish) (or drop) writes it. Spans still open then are closed at the
+/// last timestamp of their context.
+pub struct SpeedscopeWriter<W: Write> {
+    writer: Option<W>,
+    /// `shared.frames`, one per distinct span name and location.
+    frames: Vec<Value>,
+    frame_ids: HashMap<(String, Option<(String, u64)>), usize>,
+    profiles: BTreeMap<u32, Profile>,
+    started: Instant,
+}
+
+#[derive(Default)]
+struct Profile {
+    events: Vec<Value>,
+    /// Frames of the spans that are open.
+    stack: Vec<usize>,
+    start: Option<u64>,
+    end: u64,
+}
+
+impl Profile {
+    /// Adds an open (`O`) or close (`C`) event. Speedscope wants the times in order, which
+    /// a reboot or a reordered frame would break, so time never goes backwards.
+    fn push(&mut self, kind: &str, frame: usize, at: u64) {
+        let at = at.max(self.end);
+        self.start.get_or_insert(at);
+        self.end = at;
+        self.events
+            .push(json!({ "type": kind, "frame": frame, "at": at }));
+    }
+}
+
+impl SpeedscopeWriter<BufWriter<File>> {
+    /// Creates (or truncates) a `.speedscope.json` file at `path`.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write> SpeedscopeWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: Some(writer),
+            frames: Vec::new(),
+            frame_ids: HashMap::new(),
+            profiles: BTreeMap::new(),
+            started: Instant::now(),
+        }
+    }
+
+    /// Writes the profile and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        let mut writer = self.writer.take().unwrap();
+        self.write_to(&mut writer)?;
+        Ok(writer)
+    }
+
+    fn write_to(&mut self, writer: &mut W) -> io::Result<()> {
+        let profiles: Vec<Value> = self
+            .profiles
+            .iter_mut()
+            .map(|(context, profile)| {
+                while let Some(frame) = profile.stack.pop() {
+                    let end = profile.end;
+                    profile.push("C", frame, end);
+                }
+                json!({
+                    "type": "evented",
+                    "name": format!("context {}", context),
+                    "unit": "microseconds",
+                    "startValue": profile.start.unwrap_or_default(),
+                    "endValue": profile.end,
+                    "events": profile.events,
+                })
+            })
+            .collect();
+        let document = json!({
+            "$schema": "https://www.speedscope.app/file-format-schema.json",
+            "shared": { "frames": self.frames },
+            "profiles": profiles,
+            "name": "device trace",
+            "exporter": concat!("tracing-defmt-decoder ", env!("CARGO_PKG_VERSION")),
+        });
+        serde_json::to_writer(&mut *writer, &document)?;
+        writer.flush()
+    }
+
+    fn frame_id(&mut self, record: &TraceRecord) -> usize {
+        let location = record
+            .location
+            .as_ref()
+            .map(|location| (location.file.to_string(), location.line));
+        let key = (record.message.clone(), location);
+        if let Some(id) = self.frame_ids.get(&key) {
+            return *id;
+        }
+        let mut frame = json!({ "name": record.message });
+        if let Some((file, line)) = &key.1 {
+            frame["file"] = file.as_str().into();
+            frame["line"] = (*line).into();
+        }
+        self.frames.push(frame);
+        self.frame_ids.insert(key, self.frames.len() - 1);
+        self.frames.len() - 1
+    }
+
+    fn timestamp(&self, record: &TraceRecord) -> u64 {
+        record
+            .timestamp
+            .unwrap_or_else(|| self.started.elapsed().as_micros() as u64)
+    }
+}
+
+impl<W: Write> Exporter for SpeedscopeWriter<W> {
+    fn export(&mut self, record: &TraceRecord) -> io::Result<()> {
+        let at = self.timestamp(record);
+        match record.kind {
+            RecordKind::SpanEnter => {
+                let frame = self.frame_id(record);
+                let profile = self.profiles.entry(record.context).or_default();
+                profile.stack.push(frame);
+                profile.push("O", frame, at);
+            }
+            RecordKind::SpanExit => {
+                let profile = self.profiles.entry(record.context).or_default();
+                // Close the innermost span, like the decoder's span stack does.
+                if let Some(frame) = profile.stack.pop() {
+                    profile.push("C", frame, at);
+                }
+            }
+            RecordKind::Event | RecordKind::Counter | RecordKind::Gauge => {}
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Drop for SpeedscopeWriter<W> {
+    fn drop(&mut self) {
+        if let Some(mut writer) = self.writer.take() {
+            let _ = self.write_to(&mut writer);
+        }
+    }
+}
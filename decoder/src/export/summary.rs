@@ -0,0 +1,220 @@
+//! Per-span-name duration statistics, printed as a table.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use super::Exporter;
+use crate::{record, RecordKind, TraceRecord};
+
+/// Values below this are counted exactly, larger ones in buckets of under 1% width.
+const EXACT: u64 = 128;
+
+/// Duration statistics of all spans of one name.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SpanStats {
+    /// Number of completed spans.
+    pub count: u64,
+    /// Spans with an event inside that carries an error.
+    pub errors: u64,
+    pub min_us: u64,
+    pub max_us: u64,
+    pub total_us: u64,
+    /// Count per duration bucket, see [`bucket`].
+    histogram: BTreeMap<u32, u64>,
+}
+
+impl SpanStats {
+    pub fn mean_us(&self) -> u64 {
+        self.total_us.checked_div(self.count).unwrap_or_default()
+    }
+
+    /// The duration `quantile` (e.g. 0.99) of the spans are shorter than or equal to, to
+    /// within 1%.
+    pub fn percentile_us(&self, quantile: f64) -> u64 {
+        let rank = ((self.count as f64 * quantile).ceil() as u64).max(1);
+        if rank >= self.count {
+            return self.max_us;
+        }
+        let mut seen = 0;
+        for (bucket, count) in &self.histogram {
+            seen += count;
+            if seen >= rank {
+                return bucket_start(*bucket).clamp(self.min_us, self.max_us);
+            }
+        }
+        self.max_us
+    }
+
+    fn add(&mut self, duration: u64, error: bool) {
+        if self.count == 0 || duration < self.min_us {
+            self.min_us = duration;
+        }
+        self.max_us = self.max_us.max(duration);
+        self.count += 1;
+        self.errors += u64::from(error);
+        self.total_us = self.total_us.saturating_add(duration);
+        *self.histogram.entry(bucket(duration)).or_default() += 1;
+    }
+}
+
+/// Histogram bucket of `value`: exact below [`EXACT`], then 128 buckets per power of two.
+fn bucket(value: u64) -> u32 {
+    if value < EXACT {
+        return value as u32;
+    }
+    let shift = 63 - value.leading_zeros() - 7;
+    let sub = (value >> shift) as u32 & 0x7f;
+    EXACT as u32 + shift * 128 + sub
+}
+
+fn bucket_start(bucket: u32) -> u64 {
+    if u64::from(bucket) < EXACT {
+        return bucket.into();
+    }
+    let shift = (bucket - EXACT as u32) / 128;
+    let sub = u64::from((bucket - EXACT as u32) % 128);
+    (128 + sub) << shift
+}
+
+/// Aggregates span durations per span name (count, errors, min, mean, p99 and max)
+/// instead of keeping every span, for a quick performance characterization on the bench.
+///
+/// The table is written to `writer` every [`with_interval`](Self::with_interval) and
+/// when the summary is finished or dropped. A span counts as failed when an event inside
+/// it carries an error, like the spans the decoder marks with an error status. Durations
+/// use device timestamps when the firmware provides them; otherwise the host receive
+/// time stands in.
+///
+/// See [`TraceDecoderBuilder::with_summary`](crate::TraceDecoderBuilder::with_summary) to
+/// skip the export of individual spans altogether.
+///
+/// # Example
+/// ```rust,ignore
+/// let mut stream = decoder.new_stream();
+/// stream.add_exporter(SpanSummary::new(std::io::stdout()).with_interval(Duration::from_secs(10)));
+/// ```
+///
+/// ```text
+/// span          count  errors    min_us   mean_us    p99_us    max_us
+/// handle_rx      1200       3        41        52       118       240
+/// ```
+pub struct SpanSummary<W: Write> {
+    writer: Option<W>,
+    interval: Option<Duration>,
+    last_report: Instant,
+    spans: BTreeMap<String, SpanStats>,
+    /// Name, start time and whether an error occurred, of the open spans per context.
+    open: BTreeMap<u32, Vec<(String, u64, bool)>>,
+    started: Instant,
+}
+
+impl<W: Write> SpanSummary<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: Some(writer),
+            interval: None,
+            last_report: Instant::now(),
+            spans: BTreeMap::new(),
+            open: BTreeMap::new(),
+            started: Instant::now(),
+        }
+    }
+
+    /// Also writes the table every `interval` of host time, with the totals so far.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = Some(interval);
+        self
+    }
+
+    /// The statistics so far, by span name.
+    pub fn spans(&self) -> &BTreeMap<String, SpanStats> {
+        &self.spans
+    }
+
+    /// Writes the final table and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.report()?;
+        Ok(self.writer.take().unwrap())
+    }
+
+    fn report(&mut self) -> io::Result<()> {
+        self.last_report = Instant::now();
+        if self.spans.is_empty() {
+            return Ok(());
+        }
+        let table = Table(&self.spans).to_string();
+        let writer = self.writer.as_mut().unwrap();
+        writer.write_all(table.as_bytes())?;
+        writer.flush()
+    }
+
+    fn timestamp(&self, record: &TraceRecord) -> u64 {
+        record
+            .timestamp
+            .unwrap_or_else(|| self.started.elapsed().as_micros() as u64)
+    }
+}
+
+impl<W: Write> Exporter for SpanSummary<W> {
+    fn export(&mut self, record: &TraceRecord) -> io::Result<()> {
+        let timestamp = self.timestamp(record);
+        let open = self.open.entry(record.context).or_default();
+        match record.kind {
+            RecordKind::SpanEnter => open.push((record.message.clone(), timestamp, false)),
+            RecordKind::SpanExit => {
+                if let Some((name, start, error)) = open.pop() {
+                    let duration = timestamp.saturating_sub(start);
+                    self.spans.entry(name).or_default().add(duration, error);
+                }
+            }
+            RecordKind::Event => {
+                if let Some(span) = open.last_mut() {
+                    span.2 |= record::error_message(record).is_some();
+                }
+            }
+            RecordKind::Counter | RecordKind::Gauge => {}
+        }
+        match self.interval {
+            Some(interval) if self.last_report.elapsed() >= interval => self.report(),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl<W: Write> Drop for SpanSummary<W> {
+    fn drop(&mut self) {
+        if self.writer.is_some() {
+            let _ = self.report();
+        }
+    }
+}
+
+/// The statistics as an aligned text table, one row per span name.
+struct Table<'s>(&'s BTreeMap<String, SpanStats>);
+
+impl fmt::Display for Table<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let width = self.0.keys().map(String::len).max().unwrap_or(0).max(4);
+        writeln!(
+            f,
+            "{:width$}  {:>8}  {:>6}  {:>8}  {:>8}  {:>8}  {:>8}",
+            "span", "count", "errors", "min_us", "mean_us", "p99_us", "max_us"
+        )?;
+        for (name, stats) in self.0 {
+            writeln!(
+                f,
+                "{:width$}  {:>8}  {:>6}  {:>8}  {:>8}  {:>8}  {:>8}",
+                name,
+                stats.count,
+                stats.errors,
+                stats.min_us,
+                stats.mean_us(),
+                stats.percentile_us(0.99),
+                stats.max_us
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,319 @@
+//! Device log events into the host's native logging: systemd-journald or syslog.
+
+use std::io;
+use std::net::{ToSocketAddrs, UdpSocket};
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+#[cfg(unix)]
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tracing::Level;
+
+use super::Exporter;
+use crate::{RecordKind, TraceRecord};
+
+/// Where journald listens for the native protocol.
+#[cfg(unix)]
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// Enterprise number of the structured data element, the one reserved for
+/// documentation (RFC 5612), as the fields are not registered anywhere.
+const SD_ID: &str = "defmt@32473";
+
+/// Application name of both exporters unless set otherwise.
+const DEFAULT_APP_NAME: &str = "tracing-defmt";
+
+/// Syslog severity of a device log level; `println!` frames count as informational.
+fn severity(level: Option<Level>) -> u8 {
+    match level {
+        Some(Level::ERROR) => 3,
+        Some(Level::WARN) => 4,
+        Some(Level::INFO) | None => 6,
+        Some(_) => 7,
+    }
+}
+
+/// Writes device log events to systemd-journald over its native protocol, for
+/// embedded-Linux gateways that collect device logs with the host's own.
+///
+/// Every event becomes a journal entry with `MESSAGE`, `PRIORITY` from the defmt level,
+/// `SYSLOG_IDENTIFIER`, `CODE_FILE`/`CODE_LINE` and `DEFMT_MODULE` from the location,
+/// `DEFMT_CONTEXT`, `DEFMT_TIMESTAMP` (device time) and `TRACE_ID` when known. The fields
+/// of the event follow as `DEFMT_<KEY>`, upper-cased, so they can be matched with
+/// `journalctl DEFMT_<KEY>=value`. Spans and metrics are not written.
+///
+/// # Example
+/// ```rust,ignore
+/// let mut stream = decoder.new_stream();
+/// stream.add_exporter(JournaldWriter::new()?.with_identifier("sensor-gateway"));
+/// ```
+#[cfg(unix)]
+pub struct JournaldWriter {
+    socket: UnixDatagram,
+    identifier: String,
+    entry: Vec<u8>,
+}
+
+#[cfg(unix)]
+impl JournaldWriter {
+    /// Writes to the journal of the host.
+    pub fn new() -> io::Result<Self> {
+        Self::connect(JOURNALD_SOCKET)
+    }
+
+    /// Writes to the journald socket at `path`, e.g. of a container's journal.
+    pub fn connect(path: impl AsRef<Path>) -> io::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(path)?;
+        Ok(Self {
+            socket,
+            identifier: DEFAULT_APP_NAME.to_string(),
+            entry: Vec::new(),
+        })
+    }
+
+    /// Sets `SYSLOG_IDENTIFIER`, by which `journalctl -t` selects entries; defaults to
+    /// `tracing-defmt`.
+    pub fn with_identifier(mut self, identifier: impl Into<String>) -> Self {
+        self.identifier = identifier.into();
+        self
+    }
+}
+
+#[cfg(unix)]
+impl Exporter for JournaldWriter {
+    fn export(&mut self, record: &TraceRecord) -> io::Result<()> {
+        if record.kind != RecordKind::Event {
+            return Ok(());
+        }
+        let entry = &mut self.entry;
+        entry.clear();
+        journal_field(entry, "MESSAGE", &record.message);
+        journal_field(entry, "PRIORITY", &severity(record.level).to_string());
+        journal_field(entry, "SYSLOG_IDENTIFIER", &self.identifier);
+        if let Some(location) = &record.location {
+            journal_field(entry, "CODE_FILE", &location.file);
+            journal_field(entry, "CODE_LINE", &location.line.to_string());
+            journal_field(entry, "DEFMT_MODULE", &location.module);
+        }
+        journal_field(entry, "DEFMT_CONTEXT", &record.context.to_string());
+        if let Some(timestamp) = record.timestamp {
+            journal_field(entry, "DEFMT_TIMESTAMP", &timestamp.to_string());
+        }
+        if let Some(trace_id) = &record.trace_id {
+            journal_field(entry, "TRACE_ID", trace_id);
+        }
+        for (key, value) in &record.fields {
+            let name: String = key
+                .chars()
+                .map(|c| match c {
+                    'a'..='z' | 'A'..='Z' | '0'..='9' => c.to_ascii_uppercase(),
+                    _ => '_',
+                })
+                .collect();
+            journal_field(entry, &format!("DEFMT_{}", name), value);
+        }
+        self.socket.send(entry)?;
+        Ok(())
+    }
+}
+
+/// Appends a field of the journal's native protocol to `entry`.
+#[cfg(unix)]
+fn journal_field(entry: &mut Vec<u8>, name: &str, value: &str) {
+    entry.extend_from_slice(name.as_bytes());
+    if value.contains('\n') {
+        // Values with newlines are sent with their length instead.
+        entry.push(b'\n');
+        entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        entry.push(b'=');
+    }
+    entry.extend_from_slice(value.as_bytes());
+    entry.push(b'\n');
+}
+
+/// Sends device log events as RFC 5424 syslog messages, for gateways that forward their
+/// logs with rsyslog or syslog-ng.
+///
+/// The severity comes from the defmt level, the time from the record's wall-clock time
+/// (see [`with_wall_clock_anchoring`](crate::TraceDecoderBuilder::with_wall_clock_anchoring))
+/// or else the time of decoding. The location, context, device timestamp, trace id and
+/// fields of the event go into a `[defmt@32473 ...]` structured data element. Spans and
+/// metrics are not sent.
+///
+/// # Example
+/// ```rust,ignore
+/// let mut stream = decoder.new_stream();
+/// stream.add_exporter(SyslogWriter::udp("127.0.0.1:514")?.with_hostname("board-7"));
+/// ```
+pub struct SyslogWriter {
+    transport: Transport,
+    facility: u8,
+    hostname: String,
+    app_name: String,
+}
+
+enum Transport {
+    Udp(UdpSocket),
+    #[cfg(unix)]
+    Unix(UnixDatagram),
+}
+
+impl SyslogWriter {
+    /// Sends to the syslog server at `address` over UDP, e.g. `127.0.0.1:514`.
+    pub fn udp(address: impl ToSocketAddrs) -> io::Result<Self> {
+        let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+        socket.connect(address)?;
+        Ok(Self::new(Transport::Udp(socket)))
+    }
+
+    /// Sends to the local syslog daemon listening on the datagram socket at `path`,
+    /// usually `/dev/log`.
+    #[cfg(unix)]
+    pub fn unix(path: impl AsRef<Path>) -> io::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(path)?;
+        Ok(Self::new(Transport::Unix(socket)))
+    }
+
+    fn new(transport: Transport) -> Self {
+        Self {
+            transport,
+            facility: 1,
+            hostname: "-".to_string(),
+            app_name: DEFAULT_APP_NAME.to_string(),
+        }
+    }
+
+    /// Sets the facility, 0 to 23; defaults to 1 (user-level messages).
+    pub fn with_facility(mut self, facility: u8) -> Self {
+        self.facility = facility.min(23);
+        self
+    }
+
+    /// Sets the hostname of the messages, e.g. the device's name; left out by default.
+    pub fn with_hostname(mut self, hostname: impl Into<String>) -> Self {
+        self.hostname = header_field(&hostname.into(), 255);
+        self
+    }
+
+    /// Sets the application name of the messages; defaults to `tracing-defmt`.
+    pub fn with_app_name(mut self, app_name: impl Into<String>) -> Self {
+        self.app_name = header_field(&app_name.into(), 48);
+        self
+    }
+
+    fn message(&self, record: &TraceRecord) -> String {
+        let time = record.wall_time.unwrap_or_else(SystemTime::now);
+        let mut message = format!(
+            "<{}>1 {} {} {} - - [{}",
+            self.facility * 8 + severity(record.level),
+            rfc3339(time),
+            self.hostname,
+            self.app_name,
+            SD_ID
+        );
+        let mut param = |name: &str, value: &str| {
+            message.push(' ');
+            message.push_str(&param_name(name));
+            message.push_str("=\"");
+            for c in value.chars() {
+                if matches!(c, '"' | '\\' | ']') {
+                    message.push('\\');
+                }
+                message.push(c);
+            }
+            message.push('"');
+        };
+        if let Some(location) = &record.location {
+            param("file", &location.file);
+            param("line", &location.line.to_string());
+            param("module", &location.module);
+        }
+        param("context", &record.context.to_string());
+        if let Some(timestamp) = record.timestamp {
+            param("timestamp", &timestamp.to_string());
+        }
+        if let Some(trace_id) = &record.trace_id {
+            param("trace_id", trace_id);
+        }
+        for (key, value) in &record.fields {
+            param(key, value);
+        }
+        message.push_str("] ");
+        message.push_str(&record.message);
+        message
+    }
+}
+
+impl Exporter for SyslogWriter {
+    fn export(&mut self, record: &TraceRecord) -> io::Result<()> {
+        if record.kind != RecordKind::Event {
+            return Ok(());
+        }
+        let message = self.message(record);
+        match &self.transport {
+            Transport::Udp(socket) => socket.send(message.as_bytes())?,
+            #[cfg(unix)]
+            Transport::Unix(socket) => socket.send(message.as_bytes())?,
+        };
+        Ok(())
+    }
+}
+
+/// A header field of at most `max` printable ASCII characters, `-` if empty.
+fn header_field(value: &str, max: usize) -> String {
+    let field: String = value
+        .chars()
+        .filter(|c| c.is_ascii_graphic())
+        .take(max)
+        .collect();
+    if field.is_empty() {
+        "-".to_string()
+    } else {
+        field
+    }
+}
+
+/// A structured data parameter name: at most 32 printable ASCII characters other than
+/// `=`, space, `]` and `"`.
+fn param_name(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            '=' | ']' | '"' => '_',
+            c if c.is_ascii_graphic() => c,
+            _ => '_',
+        })
+        .take(32)
+        .collect()
+}
+
+/// `time` as an RFC 3339 UTC timestamp with microseconds.
+fn rfc3339(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, secs_of_day) = ((secs / 86_400) as i64, secs % 86_400);
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm).
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+        since_epoch.subsec_micros()
+    )
+}
@@ -0,0 +1,230 @@
+//! Live records over WebSocket, for browser viewers.
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use super::json_lines;
+use super::Exporter;
+use crate::TraceRecord;
+
+/// Records a client may fall behind by before it is disconnected, so a stalled browser
+/// tab cannot hold up decoding.
+const CLIENT_QUEUE: usize = 4096;
+
+/// Appended to the client's key to accept a WebSocket handshake (RFC 6455).
+const HANDSHAKE_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// A minimal live timeline, served at `/`.
+const PAGE: &str = include_str!("websocket.html");
+
+/// Serves every record as a JSON text message to the WebSocket clients connected to
+/// `/ws`, and a minimal live timeline at `/`, to watch device traces in a browser
+/// during demos and debugging sessions without an OpenTelemetry backend.
+///
+/// The messages are the objects written by [`JsonLinesWriter`](super::JsonLinesWriter).
+/// Clients only see the records decoded after they connected. A client that falls
+/// behind by more than a few thousand records is disconnected. Clones share the
+/// server, so several streams can feed one viewer.
+///
+/// # Example
+/// ```rust,ignore
+/// let server = WebSocketServer::serve("0.0.0.0:8080")?;
+/// let mut stream = decoder.new_stream();
+/// stream.add_exporter(server.clone());
+/// // Open http://localhost:8080/ in a browser.
+/// ```
+#[derive(Clone, Debug)]
+pub struct WebSocketServer {
+    clients: Arc<Mutex<Vec<SyncSender<Arc<str>>>>>,
+    address: SocketAddr,
+}
+
+impl WebSocketServer {
+    /// Listens on `address`, e.g. `0.0.0.0:8080`, from a background thread.
+    pub fn serve(address: impl ToSocketAddrs) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        let server = Self {
+            clients: Default::default(),
+            address: listener.local_addr()?,
+        };
+        let served = server.clone();
+        thread::spawn(move || {
+            for connection in listener.incoming() {
+                let served = served.clone();
+                thread::spawn(move || {
+                    let result = connection.and_then(|connection| served.respond(connection));
+                    if let Err(e) = result {
+                        log::debug!("websocket server: {}", e);
+                    }
+                });
+            }
+        });
+        Ok(server)
+    }
+
+    /// The address the server listens on, e.g. to learn the port picked for `:0`.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Number of connected clients.
+    pub fn clients(&self) -> usize {
+        self.clients.lock().unwrap().len()
+    }
+
+    fn respond(&self, mut connection: TcpStream) -> io::Result<()> {
+        let mut reader = BufReader::new(&connection);
+        let mut request = String::new();
+        reader.read_line(&mut request)?;
+        let mut key = None;
+        let mut header = String::new();
+        while reader.read_line(&mut header)? > 2 {
+            if let Some((name, value)) = header.split_once(':') {
+                if name.eq_ignore_ascii_case("sec-websocket-key") {
+                    key = Some(value.trim().to_string());
+                }
+            }
+            header.clear();
+        }
+
+        let path = request.split_whitespace().nth(1).unwrap_or("");
+        match (path, key) {
+            ("/ws", Some(key)) => {
+                let (sender, receiver) = mpsc::sync_channel(CLIENT_QUEUE);
+                // Registered before the handshake completes, so the client sees every
+                // record exported after it.
+                self.clients.lock().unwrap().push(sender);
+                let accept = base64(&sha1(format!("{}{}", key, HANDSHAKE_GUID).as_bytes()));
+                write!(
+                    connection,
+                    "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\
+                     Connection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+                    accept
+                )?;
+                send_messages(connection, receiver)
+            }
+            ("/", _) => write!(
+                connection,
+                "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\n\
+                 Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                PAGE.len(),
+                PAGE
+            ),
+            _ => connection.write_all(
+                b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            ),
+        }
+    }
+}
+
+impl Exporter for WebSocketServer {
+    fn export(&mut self, record: &TraceRecord) -> io::Result<()> {
+        let mut clients = self.clients.lock().unwrap();
+        if clients.is_empty() {
+            return Ok(());
+        }
+        let message: Arc<str> = json_lines::to_json(record).to_string().into();
+        clients.retain(|client| match client.try_send(Arc::clone(&message)) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                log::warn!("websocket server: disconnecting a client that fell behind");
+                false
+            }
+            Err(TrySendError::Disconnected(_)) => false,
+        });
+        Ok(())
+    }
+}
+
+/// Writes the messages of one client as unmasked text frames until it disconnects.
+fn send_messages(mut connection: TcpStream, messages: Receiver<Arc<str>>) -> io::Result<()> {
+    let mut frame = Vec::new();
+    for message in messages {
+        frame.clear();
+        frame.push(0x81); // FIN, text
+        match message.len() {
+            len @ 0..=125 => frame.push(len as u8),
+            len @ 126..=0xffff => {
+                frame.push(126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                frame.push(127);
+                frame.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(message.as_bytes());
+        connection.write_all(&frame)?;
+    }
+    Ok(())
+}
+
+/// SHA-1, as needed for the handshake; not for anything that has to be secure.
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for block in message.chunks(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes(word.try_into().unwrap());
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a827999),
+                20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            (e, d, c, b, a) = (d, c, b.rotate_left(30), a, temp);
+        }
+        for (h, value) in h.iter_mut().zip([a, b, c, d, e]) {
+            *h = h.wrapping_add(value);
+        }
+    }
+
+    let mut digest = [0u8; 20];
+    for (bytes, word) in digest.chunks_mut(4).zip(h) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let bits = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(bits >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
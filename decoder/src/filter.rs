@@ -0,0 +1,82 @@
+//! `EnvFilter`-style filtering of decoded records by device module and level.
+
+use std::str::FromStr;
+
+use tracing::level_filters::LevelFilter;
+
+use crate::{Error, TraceRecord};
+
+/// Selects which device records are decoded further, e.g. `info,app::radio=trace,app::usb=off`.
+///
+/// The syntax follows `tracing_subscriber::EnvFilter` restricted to module paths: a bare
+/// level sets the default, `module=level` applies to that module and its submodules,
+/// and a bare module path enables everything in it. The most specific module wins.
+/// Module paths are those of the defmt location table. Without a default directive,
+/// records of other modules all pass.
+///
+/// Filtered spans are dropped together with their exit, so events inside them are
+/// attributed to the enclosing span.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Filter {
+    default: LevelFilter,
+    /// Sorted by descending module path length, so the first match is the most specific.
+    directives: Vec<(String, LevelFilter)>,
+}
+
+impl Filter {
+    /// Whether `record` passes the filter. `println!` frames have no level and are only
+    /// dropped by `off`.
+    pub fn enabled(&self, record: &TraceRecord) -> bool {
+        let module = record.location.as_ref().map(|location| &*location.module);
+        let max = module
+            .and_then(|module| {
+                self.directives
+                    .iter()
+                    .find(|(prefix, _)| is_within(module, prefix))
+            })
+            .map(|(_, level)| *level)
+            .unwrap_or(self.default);
+
+        match record.level {
+            Some(level) => level <= max,
+            None => max != LevelFilter::OFF,
+        }
+    }
+}
+
+fn is_within(module: &str, prefix: &str) -> bool {
+    match module.strip_prefix(prefix) {
+        Some(rest) => rest.is_empty() || rest.starts_with("::"),
+        None => false,
+    }
+}
+
+impl FromStr for Filter {
+    type Err = Error;
+
+    fn from_str(spec: &str) -> Result<Self, Error> {
+        let mut default = LevelFilter::TRACE;
+        let mut directives = Vec::new();
+        for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            let parse_level = |level: &str| {
+                level
+                    .parse::<LevelFilter>()
+                    .map_err(|_| Error::Filter(format!("invalid level in `{}`", directive)))
+            };
+            match directive.split_once('=') {
+                Some((module, level)) => {
+                    directives.push((module.trim().to_string(), parse_level(level.trim())?))
+                }
+                None => match directive.parse::<LevelFilter>() {
+                    Ok(level) => default = level,
+                    Err(_) => directives.push((directive.to_string(), LevelFilter::TRACE)),
+                },
+            }
+        }
+        directives.sort_by_key(|(module, _)| std::cmp::Reverse(module.len()));
+        Ok(Self {
+            default,
+            directives,
+        })
+    }
+}
@@ -0,0 +1,136 @@
+//! Decoding many devices in one process.
+
+use std::sync::Arc;
+use std::thread;
+
+use crate::source::Source;
+use crate::{Error, TraceDecoder, TraceDecoderBuilder, TraceStream};
+
+type StreamSetup = dyn Fn(&str, &mut TraceStream<'static>) + Send + Sync;
+
+/// Decodes many devices at once, e.g. a hardware-in-the-loop rack, each with its own
+/// firmware, stream and [`Source`], exporting into the same backend.
+///
+/// Every device runs in a thread of its own. Devices added with
+/// [`add_device`](Self::add_device) get their id as the `device.id` resource attribute,
+/// which the OTLP and Loki export carry, so their traces and logs can be told apart.
+///
+/// # Example
+/// ```rust,ignore
+/// let mut fleet = Fleet::new();
+/// for (id, elf, port) in boards {
+///     let builder = TraceDecoder::builder().with_otlp_endpoint("http://collector:4317");
+///     fleet.add_device(id, builder, &elf, Tcp::connect(port)?)?;
+/// }
+/// fleet.on_stream(move |id, stream| stream.report_health(&health, id));
+/// fleet.run()?;
+/// ```
+#[derive(Default)]
+pub struct Fleet {
+    devices: Vec<Device>,
+    setup: Option<Arc<StreamSetup>>,
+}
+
+struct Device {
+    id: String,
+    decoder: TraceDecoder,
+    source: Box<dyn Source + Send>,
+}
+
+impl Fleet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the device `id`, running the firmware `elf_data`, read from `source`.
+    pub fn add_device(
+        &mut self,
+        id: impl Into<String>,
+        builder: TraceDecoderBuilder,
+        elf_data: &[u8],
+        source: impl Source + Send + 'static,
+    ) -> Result<(), Error> {
+        let id = id.into();
+        let decoder = builder.with_device_id(id.as_str()).build(elf_data)?;
+        self.add_decoder(id, decoder, source);
+        Ok(())
+    }
+
+    /// Adds the device `id` with a decoder built elsewhere, e.g. for several images. Give
+    /// it the id with [`with_device_id`](TraceDecoderBuilder::with_device_id) to have it
+    /// exported.
+    pub fn add_decoder(
+        &mut self,
+        id: impl Into<String>,
+        decoder: TraceDecoder,
+        source: impl Source + Send + 'static,
+    ) {
+        self.devices.push(Device {
+            id: id.into(),
+            decoder,
+            source: Box::new(source),
+        });
+    }
+
+    /// Calls `setup` with the id and stream of every device before it starts, e.g. to add
+    /// exporters or report its health.
+    pub fn on_stream(
+        &mut self,
+        setup: impl Fn(&str, &mut TraceStream<'static>) + Send + Sync + 'static,
+    ) {
+        self.setup = Some(Arc::new(setup));
+    }
+
+    /// The ids of the devices, in the order they were added.
+    pub fn device_ids(&self) -> impl Iterator<Item = &str> {
+        self.devices.iter().map(|device| device.id.as_str())
+    }
+
+    /// Runs every device until its source ends, then closes its open spans.
+    ///
+    /// A device whose source fails does not stop the others; the first failure is returned
+    /// once all devices are done, as [`Error::Device`]; every failure is logged.
+    pub fn run(self) -> Result<(), Error> {
+        let threads = self
+            .devices
+            .into_iter()
+            .map(|device| {
+                let id = device.id.clone();
+                let setup = self.setup.clone();
+                let thread = thread::Builder::new()
+                    .name(format!("device {}", id))
+                    .spawn(move || device.run(setup.as_deref()))?;
+                Ok((id, thread))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let mut result = Ok(());
+        for (id, thread) in threads {
+            let error = match thread.join() {
+                Ok(Ok(())) => continue,
+                Ok(Err(e)) => e,
+                Err(panic) => std::panic::resume_unwind(panic),
+            };
+            log::warn!("Device {} failed: {}", id, error);
+            if result.is_ok() {
+                result = Err(Error::Device {
+                    device: id,
+                    source: Box::new(error),
+                });
+            }
+        }
+        result
+    }
+}
+
+impl Device {
+    fn run(mut self, setup: Option<&StreamSetup>) -> Result<(), Error> {
+        let mut stream = self.decoder.into_stream();
+        if let Some(setup) = setup {
+            setup(&self.id, &mut stream);
+        }
+        let result = self.source.run(&mut stream);
+        let finished = stream.finish();
+        result.and(finished.map(drop))
+    }
+}
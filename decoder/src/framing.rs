@@ -0,0 +1,159 @@
+//! Splitting the received bytes into defmt frames.
+//!
+//! Does the same as the stream decoders of `defmt-decoder`, which borrow the `Table` for
+//! as long as they buffer data. Here the buffer is kept apart and the table is only
+//! borrowed while decoding, so a stream can own its state, see
+//! [`TraceDecoder::into_stream`](crate::TraceDecoder::into_stream).
+
+use defmt_decoder::{DecodeError, Encoding, Frame, Table};
+
+/// Number of bytes kept per context until its first frame decodes, see [`detect`].
+pub(crate) const PROBE_LEN: usize = 1024;
+
+/// Longest incomplete frame kept waiting for more data. Frames are a few bytes to a few
+/// hundred; more means a corrupt length (e.g. of a `{=str}`) or a missing rzCOBS
+/// separator, which would otherwise buffer the rest of the stream.
+pub(crate) const MAX_FRAME_LEN: usize = 64 * 1024;
+
+/// Returns the encoding of `data`, the first bytes of a stream, if it is not `expected`:
+/// when at least two frames decode with another encoding and nothing is malformed
+/// except, for rzCOBS, a first frame that was cut off.
+pub(crate) fn detect(table: &Table, expected: Encoding, data: &[u8]) -> Option<Encoding> {
+    [Encoding::Raw, Encoding::Rzcobs]
+        .into_iter()
+        .filter(|encoding| *encoding != expected)
+        .find(|encoding| {
+            let mut framer = Framer::new(*encoding);
+            framer.received(data);
+            let (mut frames, mut malformed) = (0, 0);
+            loop {
+                match framer.decode(table) {
+                    Ok(_) => frames += 1,
+                    Err(DecodeError::UnexpectedEof) => break,
+                    Err(DecodeError::Malformed) => {
+                        malformed += 1;
+                        // Raw data cannot resynchronize after a malformed frame.
+                        if !encoding.can_recover() {
+                            break;
+                        }
+                    }
+                }
+            }
+            frames >= 2 && malformed <= usize::from(encoding.can_recover())
+        })
+}
+
+/// Bytes of one context that were received but not decoded yet.
+#[derive(Debug)]
+pub(crate) struct Framer {
+    encoding: Encoding,
+    buffer: Vec<u8>,
+    /// Offset of the first byte not decoded yet. The decoded bytes are only dropped when
+    /// more data arrives, so a large chunk is not shifted after every frame.
+    start: usize,
+    /// The last rzCOBS frame, decoded.
+    frame: Vec<u8>,
+}
+
+impl Framer {
+    pub(crate) fn new(encoding: Encoding) -> Self {
+        Self {
+            encoding,
+            buffer: Vec::new(),
+            start: 0,
+            frame: Vec::new(),
+        }
+    }
+
+    pub(crate) fn received(&mut self, mut data: &[u8]) {
+        self.buffer.drain(..self.start);
+        self.start = 0;
+        if matches!(self.encoding, Encoding::Rzcobs) && self.buffer.is_empty() {
+            // Skip frame separators, the next frame starts at the first non-zero byte.
+            while let [0, rest @ ..] = data {
+                data = rest;
+            }
+        }
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// Decodes the next complete frame; `UnexpectedEof` when more data is needed, and
+    /// `Malformed` once more than [`MAX_FRAME_LEN`] bytes wait for their frame to end.
+    pub(crate) fn decode<'t>(&mut self, table: &'t Table) -> Result<Frame<'t>, DecodeError> {
+        match self.decode_pending(table) {
+            Err(DecodeError::UnexpectedEof) if self.buffer.len() - self.start > MAX_FRAME_LEN => {
+                self.buffer.clear();
+                self.start = 0;
+                Err(DecodeError::Malformed)
+            }
+            result => result,
+        }
+    }
+
+    fn decode_pending<'t>(&mut self, table: &'t Table) -> Result<Frame<'t>, DecodeError> {
+        let pending = &self.buffer[self.start..];
+        match self.encoding {
+            Encoding::Raw => {
+                let (frame, consumed) = table.decode(pending)?;
+                self.start += consumed;
+                Ok(frame)
+            }
+            Encoding::Rzcobs => {
+                let zero = pending
+                    .iter()
+                    .position(|&byte| byte == 0)
+                    .ok_or(DecodeError::UnexpectedEof)?;
+                let decoded = rzcobs_decode(&pending[..zero], &mut self.frame);
+                // Drop the frame even if it is malformed, and the separators after it.
+                self.start += pending[zero..]
+                    .iter()
+                    .position(|&byte| byte != 0)
+                    .map_or(pending.len(), |nonzero| zero + nonzero);
+                decoded?;
+                match table.decode(&self.frame) {
+                    Ok((frame, _)) => Ok(frame),
+                    Err(_) => Err(DecodeError::Malformed),
+                }
+            }
+            // Encodings added to defmt later; nothing can be decoded.
+            _ => {
+                self.buffer.clear();
+                self.start = 0;
+                Err(DecodeError::Malformed)
+            }
+        }
+    }
+}
+
+/// Decodes one rzCOBS encoded frame, without its `0x00` separator, into `decoded`.
+fn rzcobs_decode(data: &[u8], decoded: &mut Vec<u8>) -> Result<(), DecodeError> {
+    decoded.clear();
+    let mut data = data.iter().rev().copied();
+    while let Some(byte) = data.next() {
+        match byte {
+            0 => return Err(DecodeError::Malformed),
+            0x01..=0x7f => {
+                for bit in 0..7 {
+                    if byte & (1 << (6 - bit)) == 0 {
+                        decoded.push(data.next().ok_or(DecodeError::Malformed)?);
+                    } else {
+                        decoded.push(0);
+                    }
+                }
+            }
+            0x80..=0xfe => {
+                decoded.push(0);
+                for _ in 0..(byte & 0x7f) + 7 {
+                    decoded.push(data.next().ok_or(DecodeError::Malformed)?);
+                }
+            }
+            0xff => {
+                for _ in 0..134 {
+                    decoded.push(data.next().ok_or(DecodeError::Malformed)?);
+                }
+            }
+        }
+    }
+    decoded.reverse();
+    Ok(())
+}
@@ -0,0 +1,72 @@
+//! Decoder health reporting.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Something that went wrong while decoding, reported to the callback registered with
+/// [`TraceStream::on_issue`](crate::TraceStream::on_issue).
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum StreamIssue {
+    /// Bytes that do not form a valid frame were received. The decoder was reset and
+    /// resumes with the bytes following `offset`.
+    Malformed {
+        context: u32,
+        /// Bytes received on `context` up to the point where decoding resumes.
+        offset: u64,
+        /// Bytes dropped since the last good frame. This counts whole reads, so when a
+        /// good frame and the start of the malformed data arrived together it is an upper bound.
+        bytes_discarded: u64,
+    },
+    /// The transport reported frames that never arrived, see
+    /// [`TraceStream::report_loss`](crate::TraceStream::report_loss).
+    FramesLost { context: u32, count: u64 },
+    /// The device rebooted. Open spans of `context` were closed as truncated and the
+    /// following spans belong to a new boot session.
+    Reboot { context: u32, cause: RebootCause },
+    /// The device timestamp of `context` jumped forward by more than a quarter of the
+    /// counter range, see [`Timebase::with_counter_width`](crate::Timebase::with_counter_width).
+    /// `from` and `to` are the unwrapped timestamps before and after, in microseconds.
+    TimestampJump { context: u32, from: u64, to: u64 },
+    /// The transport was reconnected after `attempts` attempts and `downtime` without it,
+    /// see [`TraceStream::report_reconnect`](crate::TraceStream::report_reconnect).
+    Reconnected { attempts: u32, downtime: Duration },
+}
+
+/// How a [reboot](StreamIssue::Reboot) was detected.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum RebootCause {
+    /// The device timestamp went backwards by more than the reorder window.
+    TimestampReset,
+    /// A `boot` (or `boot: ...`) frame arrived after earlier frames.
+    BootFrame,
+    /// The transport reported it, see [`TraceStream::report_reboot`](crate::TraceStream::report_reboot).
+    Reported,
+}
+
+/// Running totals over all contexts of a stream, see [`TraceStream::stats`](crate::TraceStream::stats).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StreamStats {
+    pub bytes_received: u64,
+    pub frames_decoded: u64,
+    /// Number of times malformed data forced a decoder reset.
+    pub malformed: u64,
+    pub bytes_discarded: u64,
+    pub frames_lost: u64,
+    /// Spans that were still open when the stream was [finished](crate::TraceStream::finish)
+    /// or the device rebooted.
+    pub spans_truncated: u64,
+    pub reboots: u64,
+    /// Boot frames by the `reset.reason` they carry, e.g. `watchdog`.
+    pub resets: BTreeMap<String, u64>,
+    /// Times the transport was reconnected, see
+    /// [`TraceStream::report_reconnect`](crate::TraceStream::report_reconnect).
+    pub reconnects: u64,
+    /// Events left out of their span past the limit of their level, see
+    /// [`TraceDecoderBuilder::with_max_span_events`](crate::TraceDecoderBuilder::with_max_span_events).
+    pub events_dropped: u64,
+    /// Records kept for a trigger that did not come in time, see
+    /// [`TraceDecoderBuilder::with_retention`](crate::TraceDecoderBuilder::with_retention).
+    pub records_discarded: u64,
+}
@@ -0,0 +1,306 @@
+//! Hardware-in-the-loop tests: flash the firmware, decode its trace for a while, then
+//! assert on the spans and events it produced.
+
+use std::io;
+use std::path::Path;
+use std::process::Command;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use tracing::Level;
+
+use crate::expect::{expect_event, expect_span, EventExpectation, SpanExpectation};
+use crate::export::Exporter;
+use crate::report::TestReport;
+use crate::source::{Chunk, FrameSource};
+use crate::{Error, RecordKind, TraceDecoder, TraceRecord};
+
+const DEFAULT_DURATION: Duration = Duration::from_secs(10);
+
+/// Why a [`HilRun`] stopped decoding.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    /// The marker event of [`HilRun::until_event`] was decoded.
+    Marker,
+    /// The run lasted its [`duration`](HilRun::with_duration).
+    Duration,
+    /// The source ended, e.g. an emulator exited.
+    SourceEnded,
+}
+
+/// Runs the firmware on a device and decodes its trace, for an acceptance test.
+///
+/// The firmware is flashed by the commands given, e.g. with
+/// [`with_probe_rs`](Self::with_probe_rs), after which the trace is read from any
+/// [`FrameSource`], such as a [`Tcp`](crate::source::Tcp) connection to the probe's RTT
+/// server or a [`Semihosting`](crate::source::Semihosting) emulator. Decoding stops
+/// after a time, or at a marker event the firmware logs when the scenario is done.
+///
+/// The source is read on a thread of its own. When the run stops, that thread ends
+/// with the next read of the source, closing its transport.
+///
+/// # Example
+/// ```rust,ignore
+/// let decoder = TraceDecoder::new(&std::fs::read(elf)?)?;
+/// let trace = HilRun::new()
+///     .with_probe_rs("nRF52840_xxAA", elf)
+///     .with_duration(Duration::from_secs(30))
+///     .until_event("dfu done")
+///     .run(&decoder, Tcp::connect("127.0.0.1:19021")?)?;
+/// trace
+///     .expect_span("dfu_update")
+///     .completed_within(Duration::from_secs(2))
+///     .without_errors()
+///     .assert();
+/// ```
+#[derive(Debug)]
+pub struct HilRun {
+    flash: Vec<Command>,
+    duration: Duration,
+    marker: Option<String>,
+}
+
+impl Default for HilRun {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HilRun {
+    /// Decodes for 10 seconds, without flashing.
+    pub fn new() -> Self {
+        Self {
+            flash: Vec::new(),
+            duration: DEFAULT_DURATION,
+            marker: None,
+        }
+    }
+
+    /// Runs `command` before reading the trace, after the commands added before it. The
+    /// run fails unless it exits successfully.
+    pub fn with_flash_command(mut self, command: Command) -> Self {
+        self.flash.push(command);
+        self
+    }
+
+    /// Flashes `elf` to `chip` and resets it with the `probe-rs` command line tool.
+    pub fn with_probe_rs(self, chip: &str, elf: impl AsRef<Path>) -> Self {
+        let mut download = Command::new("probe-rs");
+        download
+            .args(["download", "--chip", chip])
+            .arg(elf.as_ref());
+        let mut reset = Command::new("probe-rs");
+        reset.args(["reset", "--chip", chip]);
+        self.with_flash_command(download).with_flash_command(reset)
+    }
+
+    /// Sets how long to decode at most. Defaults to 10 seconds.
+    pub fn with_duration(mut self, duration: Duration) -> Self {
+        self.duration = duration;
+        self
+    }
+
+    /// Stops decoding at the first event whose message contains `marker`.
+    pub fn until_event(mut self, marker: impl Into<String>) -> Self {
+        self.marker = Some(marker.into());
+        self
+    }
+
+    /// Flashes the firmware, then decodes what `source` reads with a new stream of
+    /// `decoder` until the run stops.
+    ///
+    /// The records are emitted as usual as well, e.g. to the decoder's OTLP export.
+    pub fn run(
+        &mut self,
+        decoder: &TraceDecoder,
+        source: impl FrameSource + Send + 'static,
+    ) -> Result<HilTrace, Error> {
+        for command in &mut self.flash {
+            let status = command.status()?;
+            if !status.success() {
+                return Err(io::Error::other(format!("{:?} failed: {}", command, status)).into());
+            }
+        }
+
+        let started = Instant::now();
+        let receiver = spawn_source(source);
+        let records = Arc::new(Mutex::new(Vec::new()));
+        let mut stream = decoder.new_stream();
+        stream.add_exporter(Collector(records.clone()));
+
+        let mut checked = 0;
+        let stop = loop {
+            let Some(left) = self.duration.checked_sub(started.elapsed()) else {
+                break StopReason::Duration;
+            };
+            match receiver.recv_timeout(left) {
+                Ok(Message::Chunk(chunk)) => stream.process_chunk(chunk.as_chunk())?,
+                Ok(Message::End(result)) => {
+                    result?;
+                    break StopReason::SourceEnded;
+                }
+                Err(RecvTimeoutError::Timeout) => break StopReason::Duration,
+                Err(RecvTimeoutError::Disconnected) => break StopReason::SourceEnded,
+            }
+            if let Some(marker) = &self.marker {
+                let records = records.lock().unwrap();
+                let found = records[checked..].iter().any(|record| {
+                    record.kind == RecordKind::Event && record.message.contains(marker.as_str())
+                });
+                checked = records.len();
+                if found {
+                    break StopReason::Marker;
+                }
+            }
+        };
+        stream.flush()?;
+        drop(stream);
+
+        let records = std::mem::take(&mut *records.lock().unwrap());
+        Ok(HilTrace {
+            records,
+            stop,
+            elapsed: started.elapsed(),
+        })
+    }
+}
+
+/// The trace decoded by a [`HilRun`].
+#[derive(Clone, Debug)]
+pub struct HilTrace {
+    records: Vec<TraceRecord>,
+    stop: StopReason,
+    elapsed: Duration,
+}
+
+impl HilTrace {
+    /// The records, in the order they were decoded.
+    pub fn records(&self) -> &[TraceRecord] {
+        &self.records
+    }
+
+    pub fn stop_reason(&self) -> StopReason {
+        self.stop
+    }
+
+    /// How long the run decoded, not counting flashing.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    /// The `ERROR` events.
+    pub fn errors(&self) -> impl Iterator<Item = &TraceRecord> {
+        self.records
+            .iter()
+            .filter(|record| record.kind == RecordKind::Event && record.level == Some(Level::ERROR))
+    }
+
+    /// A test report with a case per top-level span, see [`TestReport`].
+    pub fn report(&self) -> TestReport {
+        TestReport::from_records(self.records.clone())
+    }
+
+    /// Expects a span named `name`, see [`expect_span`].
+    pub fn expect_span(&self, name: &str) -> SpanExpectation<'_> {
+        expect_span(&self.records, name)
+    }
+
+    /// Expects an event whose message contains `message`, see [`expect_event`].
+    pub fn expect_event(&self, message: &str) -> EventExpectation<'_> {
+        expect_event(&self.records, message)
+    }
+}
+
+/// What the thread reading the source sends.
+enum Message {
+    Chunk(OwnedChunk),
+    /// The source ended, or failed.
+    End(Result<(), Error>),
+}
+
+/// A [`Chunk`] that owns its bytes.
+enum OwnedChunk {
+    Data {
+        context: u32,
+        bytes: Vec<u8>,
+        received_at: Option<SystemTime>,
+    },
+    Lost {
+        frames: Option<u64>,
+    },
+    Reconnected {
+        attempts: u32,
+        downtime: Duration,
+    },
+}
+
+impl OwnedChunk {
+    fn new(chunk: Chunk) -> Self {
+        match chunk {
+            Chunk::Data {
+                context,
+                bytes,
+                received_at,
+            } => OwnedChunk::Data {
+                context,
+                bytes: bytes.to_vec(),
+                received_at,
+            },
+            Chunk::Lost { frames } => OwnedChunk::Lost { frames },
+            Chunk::Reconnected { attempts, downtime } => {
+                OwnedChunk::Reconnected { attempts, downtime }
+            }
+        }
+    }
+
+    fn as_chunk(&self) -> Chunk<'_> {
+        match *self {
+            OwnedChunk::Data {
+                context,
+                ref bytes,
+                received_at,
+            } => Chunk::Data {
+                context,
+                bytes,
+                received_at,
+            },
+            OwnedChunk::Lost { frames } => Chunk::Lost { frames },
+            OwnedChunk::Reconnected { attempts, downtime } => {
+                Chunk::Reconnected { attempts, downtime }
+            }
+        }
+    }
+}
+
+/// Reads `source` on a thread of its own, until it ends or the receiver is dropped.
+fn spawn_source(mut source: impl FrameSource + Send + 'static) -> mpsc::Receiver<Message> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut sink = |chunk: Chunk| {
+            sender
+                .send(Message::Chunk(OwnedChunk::new(chunk)))
+                .map_err(|_| Error::Io(io::ErrorKind::BrokenPipe.into()))
+        };
+        let result = loop {
+            match source.read_chunks(&mut sink) {
+                Ok(true) => {}
+                Ok(false) => break Ok(()),
+                Err(e) => break Err(e),
+            }
+        };
+        let _ = sender.send(Message::End(result));
+    });
+    receiver
+}
+
+/// Collects the records of a run.
+struct Collector(Arc<Mutex<Vec<TraceRecord>>>);
+
+impl Exporter for Collector {
+    fn export(&mut self, record: &TraceRecord) -> io::Result<()> {
+        self.0.lock().unwrap().push(record.clone());
+        Ok(())
+    }
+}
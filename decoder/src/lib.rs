@@ -1,173 +1,2584 @@
-use defmt_decoder::{DecodeError, Frame, Location, StreamDecoder, Table};
-use std::collections::BTreeMap;
-use tracing::{info, span, Level, Span};
+use defmt_decoder::{DecodeError, Frame, Location, Locations, Table};
+use opentelemetry::trace::TraceContextExt;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::Write;
+use std::ops::Deref;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tracing::field::Value;
+use tracing::{Dispatch, Level, Span};
+use tracing_defmt_wire as wire;
 use tracing_opentelemetry::OpenTelemetrySpanExt;
 
+pub mod budget;
+mod callsite;
+pub mod capture;
+mod clock;
+mod coalesce;
+mod control;
+mod crash;
+pub mod diff;
+mod dwarf;
+#[cfg(feature = "async")]
+mod event_stream;
+pub mod expect;
+pub mod export;
+mod filter;
+mod fleet;
+mod framing;
+mod health;
+pub mod hil;
+mod location;
+mod memory;
+#[cfg(feature = "otlp-pipeline")]
+mod otlp;
+#[cfg(feature = "prometheus")]
+mod prometheus;
+mod propagation;
+mod record;
+mod reorder;
+pub mod report;
+mod retention;
+mod sampling;
+mod short_spans;
+pub mod snapshot;
+pub mod source;
+mod symbols;
+mod targets;
+mod timestamp;
+#[cfg(feature = "tui")]
+pub mod tui;
+mod watch;
+
+pub use control::ControlCommand;
+pub use defmt_decoder::Encoding;
+#[cfg(feature = "async")]
+pub use event_stream::EventStream;
+use export::Exporter;
+pub use filter::Filter;
+pub use fleet::Fleet;
+pub use health::{RebootCause, StreamIssue, StreamStats};
+pub use opentelemetry::trace::SpanKind;
+#[cfg(all(feature = "otlp-pipeline", feature = "metrics"))]
+pub use opentelemetry_sdk::metrics::Temporality;
+#[cfg(feature = "otlp-pipeline")]
+pub use otlp::OtlpProtocol;
+#[cfg(feature = "prometheus")]
+pub use prometheus::HealthEndpoint;
+pub use record::{RawFrame, RecordKind, RecordLocation, TraceRecord};
+use reorder::ReorderBuffer;
+pub use retention::Retention;
+pub use sampling::Sampler;
+pub use targets::TargetMap;
+pub use timestamp::Timebase;
+pub use watch::ElfWatcher;
+
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     #[error("Defmt decode error: {0}")]
     Defmt(#[from] DecodeError),
     #[error("Elf parsing error: {0}")]
     Elf(String),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Exporter setup error: {0}")]
+    Exporter(String),
+    #[error("Invalid filter: {0}")]
+    Filter(String),
+    #[error("Invalid target rule: {0}")]
+    TargetRule(String),
+    #[error("Invalid latency budget: {0}")]
+    Budget(String),
+    #[error("Invalid control command: {0}")]
+    Command(String),
+    #[error(
+        "Firmware uses wire protocol version {found}, newer than version {supported} of \
+         this decoder; upgrade tracing-defmt-decoder"
+    )]
+    UnsupportedProtocol { found: u32, supported: u32 },
+    #[error(
+        "Context {context} looks {detected:?} encoded, but is decoded as {expected:?}; \
+         check the firmware's defmt encoding or override it with `with_encoding`"
+    )]
+    EncodingMismatch {
+        context: u32,
+        expected: Encoding,
+        detected: Encoding,
+    },
+    #[error("Device {device}: {source}")]
+    Device {
+        device: String,
+        #[source]
+        source: Box<Error>,
+    },
 }
 
-pub struct TraceDecoder {
+/// Configures a [`TraceDecoder`] before parsing the ELF.
+///
+/// # Example
+/// ```rust,ignore
+/// let decoder = TraceDecoder::builder()
+///     .with_otlp_endpoint("http://collector:4317")
+///     .build(&elf)?;
+/// ```
+#[derive(Debug, Default)]
+pub struct TraceDecoderBuilder {
+    config: DecoderConfig,
+    #[cfg(feature = "otlp-pipeline")]
+    otlp: Option<otlp::OtlpConfig>,
+    dispatch: Option<Dispatch>,
+}
+
+/// Which error event describes the status of a span that had several.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ErrorDescription {
+    /// The first error, usually the cause of the others.
+    First,
+    /// The latest error, the one the span ended with.
+    #[default]
+    Last,
+}
+
+/// Naming of the spans and events the decoder emits.
+#[derive(Clone, Debug)]
+struct DecoderConfig {
+    target: String,
+    span_name: String,
+    default_namespace: String,
+    service_name: String,
+    /// Extra resource attributes, in addition to `service.name`.
+    resource: Vec<(String, String)>,
+    reorder_window: Option<Duration>,
+    min_span_duration: Option<Duration>,
+    coalesce_events: bool,
+    retention: Option<Retention>,
+    /// Aggregate statistics mode, see `with_summary`.
+    summary_interval: Option<Duration>,
+    filter: Option<Filter>,
+    sampler: Option<Sampler>,
+    target_map: Option<TargetMap>,
+    path_remap: location::PathRemap,
+    /// Span kinds by span name, for spans whose firmware does not set one.
+    span_kinds: HashMap<String, SpanKind>,
+    error_description: ErrorDescription,
+    timebase: Option<Timebase>,
+    anchor_wall_clock: bool,
+    dwarf_locations: bool,
+    extract_fields: bool,
+    max_fields: Option<usize>,
+    max_field_len: Option<usize>,
+    /// Events kept per span, by level, see `with_max_span_events`.
+    max_span_events: [Option<usize>; 5],
+    encoding: Option<Encoding>,
+    #[cfg(feature = "loki")]
+    loki_endpoint: Option<String>,
+}
+
+impl Default for DecoderConfig {
+    fn default() -> Self {
+        Self {
+            target: "device_log".to_string(),
+            span_name: "device_span".to_string(),
+            default_namespace: "device".to_string(),
+            service_name: "tracing-defmt-decoder".to_string(),
+            resource: Vec::new(),
+            reorder_window: None,
+            min_span_duration: None,
+            coalesce_events: false,
+            retention: None,
+            summary_interval: None,
+            filter: None,
+            sampler: None,
+            target_map: None,
+            path_remap: Default::default(),
+            span_kinds: HashMap::new(),
+            error_description: ErrorDescription::default(),
+            timebase: None,
+            anchor_wall_clock: false,
+            dwarf_locations: false,
+            extract_fields: true,
+            max_fields: None,
+            max_field_len: None,
+            max_span_events: [None; 5],
+            encoding: None,
+            #[cfg(feature = "loki")]
+            loki_endpoint: None,
+        }
+    }
+}
+
+impl DecoderConfig {
+    /// The tracing target of `record`: its `target:`, else the one its module maps to,
+    /// else the default.
+    fn target<'r>(&'r self, record: &'r TraceRecord) -> &'r str {
+        record::field(record, wire::TARGET_FIELD)
+            .or_else(|| {
+                let module = &record.location.as_ref()?.module;
+                self.target_map.as_ref()?.target(module)
+            })
+            .unwrap_or(&self.target)
+    }
+
+    fn resource_attributes(&self) -> impl Iterator<Item = (&str, &str)> {
+        std::iter::once(("service.name", self.service_name.as_str())).chain(
+            self.resource
+                .iter()
+                .map(|(key, value)| (key.as_str(), value.as_str())),
+        )
+    }
+}
+
+impl TraceDecoderBuilder {
+    /// Sets the tracing target of all decoded spans and events. Defaults to `device_log`.
+    pub fn with_target(mut self, target: impl Into<String>) -> Self {
+        self.config.target = target.into();
+        self
+    }
+
+    /// Gives the records of device modules the targets of `map` instead of the one set
+    /// with [`with_target`](Self::with_target), see [`TargetMap`].
+    pub fn with_target_map(mut self, map: TargetMap) -> Self {
+        self.config.target_map = Some(map);
+        self
+    }
+
+    /// Sets the static tracing name of decoded spans. Defaults to `device_span`.
+    ///
+    /// The device function name is always reported through the `otel.name` field,
+    /// which OpenTelemetry backends show as the span name.
+    pub fn with_span_name(mut self, name: impl Into<String>) -> Self {
+        self.config.span_name = name.into();
+        self
+    }
+
+    /// Gives spans named `span_name` the OpenTelemetry span kind `kind` instead of
+    /// [`SpanKind::Internal`], so backends can draw service maps and split latencies.
+    ///
+    /// Firmware sets the kind of a span itself with
+    /// `#[instrument(fields(otel.kind = "client"))]`, which takes precedence. Instances
+    /// of a span, `uart_task[2]`, are matched by their base name `uart_task`.
+    pub fn with_span_kind(mut self, span_name: impl Into<String>, kind: SpanKind) -> Self {
+        self.config.span_kinds.insert(span_name.into(), kind);
+        self
+    }
+
+    /// Sets the `code.namespace` used for frames without a known location. Defaults to `device`.
+    pub fn with_default_namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.config.default_namespace = namespace.into();
+        self
+    }
+
+    /// Sets the `service.name` resource attribute of exported traces.
+    pub fn with_service_name(mut self, name: impl Into<String>) -> Self {
+        self.config.service_name = name.into();
+        self
+    }
+
+    /// Adds a resource attribute to exported traces, e.g. `deployment.environment`.
+    ///
+    /// Resource attributes describe the device as a whole and let traces from a fleet be
+    /// told apart in the backend. Setting a key again replaces its value.
+    pub fn with_resource_attribute(
+        mut self,
+        key: impl Into<String>,
+        value: impl Into<String>,
+    ) -> Self {
+        let key = key.into();
+        self.config
+            .resource
+            .retain(|(existing, _)| *existing != key);
+        self.config.resource.push((key, value.into()));
+        self
+    }
+
+    /// Sets the `device.id` resource attribute, e.g. to a serial number.
+    pub fn with_device_id(self, id: impl Into<String>) -> Self {
+        self.with_resource_attribute("device.id", id)
+    }
+
+    /// Sets the `service.version` resource attribute to the firmware version.
+    pub fn with_firmware_version(self, version: impl Into<String>) -> Self {
+        self.with_resource_attribute("service.version", version)
+    }
+
+    /// Converts raw tick timestamps to real time. Without it, plain integer timestamps
+    /// are taken as microseconds.
+    pub fn with_timebase(mut self, timebase: Timebase) -> Self {
+        self.config.timebase = Some(timebase);
+        self
+    }
+
+    /// Maps device timestamps to host wall-clock time, estimated from when frames arrive
+    /// and refined as more frames come in.
+    ///
+    /// Decoded records carry the estimate in [`TraceRecord::wall_time`], and spans and
+    /// events recorded by `tracing-opentelemetry` get it as their start/end time, so
+    /// exported traces line up with backend logs. Only meaningful for live sources. For
+    /// accuracy beyond the transport delay, and drift correction over long captures, see
+    /// [`TraceStream::time_sync_request`].
+    pub fn with_wall_clock_anchoring(mut self, enabled: bool) -> Self {
+        self.config.anchor_wall_clock = enabled;
+        self
+    }
+
+    /// Looks up log statements missing from the defmt location table in the DWARF debug
+    /// info of the ELF, which is read more leniently.
+    ///
+    /// Without it, and for statements still not found, records have no
+    /// [`location`](TraceRecord::location); see [`TraceDecoder::missing_locations`].
+    pub fn with_dwarf_locations(mut self, enabled: bool) -> Self {
+        self.config.dwarf_locations = enabled;
+        self
+    }
+
+    /// Whether the trailing `key=value` section of event messages is split off into
+    /// [`TraceRecord::fields`], which become typed tracing fields (and OpenTelemetry
+    /// attributes). On by default.
+    ///
+    /// `info!(port = 3, up = true, "link changed")` arrives as `link changed, port=3,
+    /// up=true` and becomes the message `link changed` with an integer `port` and a
+    /// boolean `up` field. Values that are not numbers or booleans stay strings.
+    pub fn with_field_extraction(mut self, enabled: bool) -> Self {
+        self.config.extract_fields = enabled;
+        self
+    }
+
+    /// Keeps at most `count` fields per record, dropping the rest, so a runaway loop of
+    /// fields cannot produce spans that backends reject. Unlimited by default.
+    ///
+    /// Records that lost fields get a `truncated=true` field, which is not counted.
+    pub fn with_max_fields(mut self, count: usize) -> Self {
+        self.config.max_fields = Some(count);
+        self
+    }
+
+    /// Cuts field values to at most `len` bytes, e.g. an accidental hexdump of a 4 KiB
+    /// buffer. Unlimited by default.
+    ///
+    /// Records with a cut value get a `truncated=true` field.
+    pub fn with_max_field_len(mut self, len: usize) -> Self {
+        self.config.max_field_len = Some(len);
+        self
+    }
+
+    /// Keeps at most `count` events of each level per span, so a chatty loop cannot attach
+    /// tens of thousands of events to one span. Unlimited by default.
+    ///
+    /// Each level has its own budget, so a flood of debug events does not crowd out the
+    /// errors. Further events are left out of tracing, but still reach the exporters; the
+    /// span ends with a warning `dropped N further events` with a `dropped_events` field,
+    /// and they are counted in [`StreamStats::events_dropped`].
+    pub fn with_max_span_events(mut self, count: usize) -> Self {
+        self.config.max_span_events = [Some(count); 5];
+        self
+    }
+
+    /// Like [`with_max_span_events`](Self::with_max_span_events), for events of `level`
+    /// only, e.g. to keep every error but few debug events.
+    pub fn with_max_span_events_at(mut self, level: Level, count: usize) -> Self {
+        self.config.max_span_events[level_index(level)] = Some(count);
+        self
+    }
+
+    /// Replaces the prefix `from` of the source paths in records and `code.filepath`
+    /// attributes with `to`, like rustc's `--remap-path-prefix`, so they are portable
+    /// and open in IDE-integrated trace viewers. The first matching prefix wins.
+    ///
+    /// The defmt location table holds the absolute paths of the build machine, cargo
+    /// registry paths of dependencies included, e.g.
+    /// `.with_path_prefix("/home/ci/.cargo/registry/src/index.crates.io-1949cf8c6b5b557f", "crates")`.
+    pub fn with_path_prefix(mut self, from: impl Into<String>, to: impl Into<String>) -> Self {
+        self.config.path_remap.push(from.into(), to.into());
+        self
+    }
+
+    /// Makes the source paths below `root`, e.g. the firmware's workspace, relative to it.
+    pub fn with_workspace_root(self, root: impl Into<String>) -> Self {
+        self.with_path_prefix(root, "")
+    }
+
+    /// Decodes frames as `encoding` instead of the encoding the ELF declares, e.g. for a
+    /// transport that re-encodes the stream.
+    ///
+    /// A stream whose first bytes decode cleanly with the other encoding only fails with
+    /// [`Error::EncodingMismatch`], whether the encoding is overridden or not.
+    pub fn with_encoding(mut self, encoding: Encoding) -> Self {
+        self.config.encoding = Some(encoding);
+        self
+    }
+
+    /// Drops records that do not pass `filter` right after decoding, so noisy device
+    /// modules can be silenced without reflashing.
+    pub fn with_filter(mut self, filter: Filter) -> Self {
+        self.config.filter = Some(filter);
+        self
+    }
+
+    /// Thins out high-frequency spans by name before they reach the tracing pipeline
+    /// and exporters, see [`Sampler`].
+    pub fn with_sampler(mut self, sampler: Sampler) -> Self {
+        self.config.sampler = Some(sampler);
+        self
+    }
+
+    /// Whether the first or the last error event of a span describes its error status.
+    /// Defaults to [`ErrorDescription::Last`].
+    pub fn with_error_description(mut self, description: ErrorDescription) -> Self {
+        self.config.error_description = description;
+        self
+    }
+
+    /// Holds decoded records back for `window` and releases them in device timestamp order.
+    ///
+    /// Frames logged from interrupts can reach the host before frames that were produced
+    /// earlier, placing them in the wrong span. With a window slightly longer than the
+    /// longest such delay, records are sorted by their `defmt::timestamp!` value before
+    /// their place in the span tree is decided. Records are delayed until a frame `window`
+    /// newer arrives, or until [`TraceStream::flush`]. Has no effect without device timestamps.
+    pub fn with_reorder_window(mut self, window: Duration) -> Self {
+        self.config.reorder_window = Some(window);
+        self
+    }
+
+    /// Drops spans that last less than `duration`, e.g. helpers called millions of times.
+    /// Their events and child spans are kept, in the closest surviving span.
+    ///
+    /// The records of a span are held back until it has lasted `duration` or exited, or
+    /// until [`TraceStream::flush`], which keeps the spans still open. Spans without device
+    /// timestamps are always kept.
+    pub fn with_min_span_duration(mut self, duration: Duration) -> Self {
+        self.config.min_span_duration = Some(duration);
+        self
+    }
+
+    /// Merges runs of identical consecutive events, i.e. of the same log statement, level,
+    /// message and fields, into the first event of the run, which gets a `count` field.
+    /// A retry loop then logs one warning instead of hundreds.
+    ///
+    /// Runs never cross a span boundary. The last event of a context is held back until a
+    /// different record arrives, or until [`TraceStream::flush`].
+    pub fn with_event_coalescing(mut self, enabled: bool) -> Self {
+        self.config.coalesce_events = enabled;
+        self
+    }
+
+    /// Post-hoc capture: keeps the latest records in memory instead of exporting them, and
+    /// only exports them when an error is logged or on
+    /// [`TraceStream::export_retained`], see [`Retention`]. A device can then run for
+    /// weeks with only the traces leading up to its failures exported.
+    ///
+    /// Spans are rebuilt when the records are exported, so use
+    /// [`with_wall_clock_anchoring`](Self::with_wall_clock_anchoring) for them to keep
+    /// the times they ran at. Records kept when the stream is finished are discarded.
+    pub fn with_retention(mut self, retention: Retention) -> Self {
+        self.config.retention = Some(retention);
+        self
+    }
+
+    /// Aggregate statistics mode: spans and events are not emitted to tracing, so nothing
+    /// is exported per span. Instead every stream keeps an [`export::SpanSummary`] and
+    /// prints it to stderr every `interval` and at the end.
+    ///
+    /// Exporters added to the stream still see every record.
+    pub fn with_summary(mut self, interval: Duration) -> Self {
+        self.config.summary_interval = Some(interval);
+        self
+    }
+
+    /// Exports reconstructed traces to an OTLP collector (e.g. `http://collector:4317`).
+    ///
+    /// The decoder sets up a batch exporter and its own tracing subscriber, and flushes
+    /// all pending spans when it is dropped. The global subscriber is left untouched.
+    #[cfg(feature = "otlp-pipeline")]
+    pub fn with_otlp_endpoint(mut self, endpoint: impl Into<String>) -> Self {
+        self.otlp.get_or_insert_with(Default::default).endpoint = endpoint.into();
+        self
+    }
+
+    /// Selects gRPC or HTTP (protobuf/JSON) transport. Defaults to gRPC when available.
+    #[cfg(feature = "otlp-pipeline")]
+    pub fn with_otlp_protocol(mut self, protocol: OtlpProtocol) -> Self {
+        self.otlp.get_or_insert_with(Default::default).protocol = protocol;
+        self
+    }
+
+    /// Adds a header (or gRPC metadata entry) sent with every export, e.g. for authentication.
+    #[cfg(feature = "otlp-pipeline")]
+    pub fn with_otlp_header(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.otlp
+            .get_or_insert_with(Default::default)
+            .headers
+            .insert(key.into(), value.into());
+        self
+    }
+
+    /// Sets how many spans wait for export at most; more are dropped. Defaults to 2048.
+    ///
+    /// A busy device can end thousands of spans within one export interval, especially
+    /// when a capture is replayed or RTT catches up after a halt, so raise this (or
+    /// shorten the [interval](Self::with_otlp_export_interval)) if spans go missing.
+    #[cfg(feature = "otlp-pipeline")]
+    pub fn with_otlp_max_queue_size(mut self, size: usize) -> Self {
+        self.otlp
+            .get_or_insert_with(Default::default)
+            .max_queue_size = Some(size);
+        self
+    }
+
+    /// Sets how many spans are sent in one export request at most. Defaults to 512, and
+    /// to the queue size if that is smaller.
+    #[cfg(feature = "otlp-pipeline")]
+    pub fn with_otlp_max_export_batch_size(mut self, size: usize) -> Self {
+        self.otlp
+            .get_or_insert_with(Default::default)
+            .max_export_batch_size = Some(size);
+        self
+    }
+
+    /// Sets how long spans wait to be batched before they are exported. Defaults to 5 s.
+    #[cfg(feature = "otlp-pipeline")]
+    pub fn with_otlp_export_interval(mut self, interval: Duration) -> Self {
+        self.otlp
+            .get_or_insert_with(Default::default)
+            .export_interval = Some(interval);
+        self
+    }
+
+    /// Sets how long an export request may take before it is abandoned. Defaults to 30 s.
+    #[cfg(feature = "otlp-pipeline")]
+    pub fn with_otlp_export_timeout(mut self, timeout: Duration) -> Self {
+        self.otlp
+            .get_or_insert_with(Default::default)
+            .export_timeout = Some(timeout);
+        self
+    }
+
+    /// Trusts an additional PEM encoded root certificate for OTLP/HTTP over TLS.
+    #[cfg(feature = "otlp-http")]
+    pub fn with_otlp_ca_certificate(mut self, pem: impl Into<Vec<u8>>) -> Self {
+        self.otlp
+            .get_or_insert_with(Default::default)
+            .ca_certificates
+            .push(pem.into());
+        self
+    }
+
+    /// Disables certificate verification for OTLP/HTTP. Only meant for test collectors.
+    #[cfg(feature = "otlp-http")]
+    pub fn with_otlp_accept_invalid_certs(mut self, accept: bool) -> Self {
+        self.otlp
+            .get_or_insert_with(Default::default)
+            .accept_invalid_certs = accept;
+        self
+    }
+
+    /// Also exports the device's metric frames to the OTLP collector, every `interval`.
+    ///
+    /// Every stream of the decoder then publishes its `counter:` and `gauge:` frames as
+    /// [`DeviceMetrics`](export::DeviceMetrics) do.
+    #[cfg(all(feature = "otlp-pipeline", feature = "metrics"))]
+    pub fn with_otlp_metrics(mut self, interval: Duration) -> Self {
+        self.otlp
+            .get_or_insert_with(Default::default)
+            .metrics_interval = Some(interval);
+        self
+    }
+
+    /// Selects whether exported metric sums are cumulative (the default) or deltas since
+    /// the previous export.
+    #[cfg(all(feature = "otlp-pipeline", feature = "metrics"))]
+    pub fn with_otlp_metrics_temporality(mut self, temporality: Temporality) -> Self {
+        self.otlp
+            .get_or_insert_with(Default::default)
+            .metrics_temporality = temporality;
+        self
+    }
+
+    /// Also exports the device log events to the OTLP collector as log records, next to
+    /// the span events of the traces.
+    ///
+    /// Every stream of the decoder then emits its events as
+    /// [`DeviceLogs`](export::DeviceLogs) do, correlated with the exported spans by trace
+    /// and span id.
+    #[cfg(all(feature = "otlp-pipeline", feature = "logs"))]
+    pub fn with_otlp_logs(mut self, enabled: bool) -> Self {
+        self.otlp.get_or_insert_with(Default::default).logs = enabled;
+        self
+    }
+
+    /// Pushes the device log events of every stream to the Grafana Loki at `url`, e.g.
+    /// `http://loki:3100`, in addition to the trace export; see [`export::LokiExporter`].
+    ///
+    /// Lines are labeled with `service_name` and, if set, `device_id`, taken from the
+    /// resource attributes.
+    #[cfg(feature = "loki")]
+    pub fn with_loki_endpoint(mut self, url: impl Into<String>) -> Self {
+        self.config.loki_endpoint = Some(url.into());
+        self
+    }
+
+    /// Emits decoded spans and events into `dispatch` instead of the default subscriber.
+    pub fn with_dispatch(mut self, dispatch: Dispatch) -> Self {
+        self.dispatch = Some(dispatch);
+        self
+    }
+
+    pub fn build(self, elf_data: &[u8]) -> Result<TraceDecoder, Error> {
+        self.build_multi(&[elf_data])
+    }
+
+    /// Builds a decoder for a device running several firmware images, e.g. a bootloader
+    /// and an application, which each have their own defmt table.
+    ///
+    /// Decoding starts with the first image. The image in use switches when a frame
+    /// `image_switch: N` is decoded, which the outgoing image logs right before handing
+    /// over to image `N` (an index into `elf_images`), e.g. with
+    /// `defmt::info!("image_switch: {=usize}", 1)` just before the bootloader jumps to
+    /// the application. Transports that know better can use [`TraceStream::select_image`].
+    ///
+    /// The GNU build id of the first image, if it has one (link with `-C link-arg=--build-id`),
+    /// is added as the `firmware.build_id` resource attribute unless set explicitly.
+    pub fn build_multi(mut self, elf_images: &[&[u8]]) -> Result<TraceDecoder, Error> {
+        let images = elf_images
+            .iter()
+            .map(|elf_data| parse_elf(elf_data, self.config.dwarf_locations))
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let has_build_id = self
+            .config
+            .resource
+            .iter()
+            .any(|(key, _)| key == "firmware.build_id");
+        if let (false, Some(build_id)) = (
+            has_build_id,
+            elf_images.first().and_then(|elf| build_id(elf)),
+        ) {
+            self = self.with_resource_attribute("firmware.build_id", build_id);
+        }
+
+        self.build_images(images)
+    }
+
+    /// Builds the decoder from an already parsed defmt table, e.g. one cached in a symbol store.
+    pub fn build_from_table(
+        self,
+        table: Table,
+        locations: Locations,
+    ) -> Result<TraceDecoder, Error> {
+        self.build_from_tables(vec![(table, locations)])
+    }
+
+    /// Multi-image variant of [`build_from_table`](Self::build_from_table), see [`build_multi`](Self::build_multi).
+    pub fn build_from_tables(self, tables: Vec<(Table, Locations)>) -> Result<TraceDecoder, Error> {
+        let images = tables
+            .into_iter()
+            .map(|(table, locations)| Image::new(table, locations))
+            .collect();
+        self.build_images(images)
+    }
+
+    /// Builds a decoder without a defmt table, for logs that were already decoded by
+    /// `defmt-print` or `probe-rs`, see [`source::TextLines`].
+    ///
+    /// Span reconstruction and the exporters work as usual; raw defmt bytes cannot be
+    /// decoded and make [`TraceStream::process`] fail.
+    pub fn build_for_text(self) -> Result<TraceDecoder, Error> {
+        self.finish(Vec::new())
+    }
+
+    fn build_images(self, images: Vec<Image>) -> Result<TraceDecoder, Error> {
+        if images.is_empty() {
+            return Err(Error::Elf("No firmware image given".to_string()));
+        }
+        self.finish(images)
+    }
+
+    fn finish(self, images: Vec<Image>) -> Result<TraceDecoder, Error> {
+        #[cfg(feature = "otlp-pipeline")]
+        let otlp = match &self.otlp {
+            Some(config) => Some(otlp::OtlpPipeline::new(
+                config,
+                self.config
+                    .resource_attributes()
+                    .map(|(key, value)| {
+                        opentelemetry::KeyValue::new(key.to_string(), value.to_string())
+                    })
+                    .collect(),
+            )?),
+            None => None,
+        };
+
+        #[cfg(feature = "otlp-pipeline")]
+        let dispatch = self
+            .dispatch
+            .or_else(|| otlp.as_ref().map(|p| p.dispatch().clone()));
+        #[cfg(not(feature = "otlp-pipeline"))]
+        let dispatch = self.dispatch;
+
+        Ok(TraceDecoder {
+            images,
+            config: self.config,
+            dispatch,
+            #[cfg(feature = "otlp-pipeline")]
+            _otlp: otlp,
+        })
+    }
+}
+
+/// The current host time in microseconds since the Unix epoch.
+fn unix_micros() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_micros() as u64)
+}
+
+/// The defmt table and source locations of one firmware image.
+struct Image {
     table: Table,
     locations: BTreeMap<u64, Location>,
+    /// `locations` as they go into records.
+    resolved: location::LocationIndex,
+    /// Number of log statements without a location.
+    missing_locations: usize,
+    /// Wire protocol version declared by the ELF.
+    protocol_version: Option<u32>,
+    /// Span names by their hash, for firmware built with hashed span names.
+    span_names: HashMap<u32, String>,
+    /// Functions and line tables, to symbolicate the addresses of crash frames.
+    symbols: symbols::Symbolizer,
+}
+
+impl Image {
+    fn new(table: Table, locations: Locations) -> Self {
+        let missing_locations = unlocated(&table, &locations).len();
+        Self {
+            span_names: span_names(&table),
+            table,
+            resolved: location::LocationIndex::new(&locations),
+            locations,
+            missing_locations,
+            protocol_version: None,
+            symbols: symbols::Symbolizer::default(),
+        }
+    }
+}
+
+/// The kind as `otel.kind` spells it.
+fn span_kind_name(kind: &SpanKind) -> &'static str {
+    match kind {
+        SpanKind::Client => "client",
+        SpanKind::Server => "server",
+        SpanKind::Producer => "producer",
+        SpanKind::Consumer => "consumer",
+        SpanKind::Internal => "internal",
+    }
+}
+
+/// The names of the spans entered by the log statements of `table`, by their
+/// [`wire::name_hash`]. They are taken from the format strings in the symbols of the ELF,
+/// so tables built without an ELF have none.
+fn span_names(table: &Table) -> HashMap<u32, String> {
+    table
+        .raw_symbols()
+        .filter_map(|symbol| {
+            let symbol: serde_json::Value = serde_json::from_str(symbol).ok()?;
+            let payload = symbol
+                .get("data")?
+                .as_str()?
+                .strip_prefix(wire::SPAN_ENTER)?;
+            let (name, _) = record::parse_span_enter(payload);
+            Some((wire::name_hash(name), name.to_string()))
+        })
+        .collect()
+}
+
+pub struct TraceDecoder {
+    images: Vec<Image>,
+    config: DecoderConfig,
+    dispatch: Option<Dispatch>,
+    // Declared last: flushes the exporter once everything else is dropped.
+    #[cfg(feature = "otlp-pipeline")]
+    _otlp: Option<otlp::OtlpPipeline>,
 }
 
 impl TraceDecoder {
     pub fn new(elf_data: &[u8]) -> Result<Self, Error> {
-        let table = Table::parse(elf_data)
-            .map_err(|e| Error::Elf(format!("{:?}", e)))?
-            .ok_or_else(|| Error::Elf("No defmt table found".to_string()))?;
+        Self::builder().build(elf_data)
+    }
+
+    /// The resource attributes describing the device, starting with `service.name`.
+    ///
+    /// Used by the built-in OTLP export; useful to configure other pipelines the same way.
+    pub fn resource_attributes(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.config.resource_attributes()
+    }
+
+    /// Decoder for several firmware images, see [`TraceDecoderBuilder::build_multi`].
+    pub fn new_multi(elf_images: &[&[u8]]) -> Result<Self, Error> {
+        Self::builder().build_multi(elf_images)
+    }
+
+    pub fn builder() -> TraceDecoderBuilder {
+        TraceDecoderBuilder::default()
+    }
+
+    /// The encoding frames are decoded with: the one set with
+    /// [`with_encoding`](TraceDecoderBuilder::with_encoding), or else the one the ELF
+    /// declares. `None` for a decoder built for text. With several images, that of the first.
+    pub fn encoding(&self) -> Option<Encoding> {
+        (!self.images.is_empty()).then(|| self.image_encoding(0))
+    }
+
+    /// The encoding the ELF declares, set by the `defmt` features of the firmware.
+    pub fn declared_encoding(&self) -> Option<Encoding> {
+        self.images.first().map(|image| image.table.encoding())
+    }
+
+    /// The wire protocol version the ELF declares, see `tracing_defmt_wire::VERSION`.
+    /// `None` for firmware built before the facade recorded it, or for a decoder built
+    /// from tables or for text. With several images, that of the first.
+    pub fn protocol_version(&self) -> Option<u32> {
+        self.images.first()?.protocol_version
+    }
+
+    fn image_encoding(&self, image: usize) -> Encoding {
+        self.config
+            .encoding
+            .unwrap_or_else(|| self.images[image].table.encoding())
+    }
+
+    fn framer(&self, image: usize) -> framing::Framer {
+        framing::Framer::new(self.image_encoding(image))
+    }
+
+    /// Number of log statements in the firmware whose source location is unknown, e.g.
+    /// because the ELF was stripped of debug info. Their records have no
+    /// [`location`](TraceRecord::location).
+    pub fn missing_locations(&self) -> usize {
+        self.images
+            .iter()
+            .map(|image| image.missing_locations)
+            .sum()
+    }
+
+    pub fn new_stream(&self) -> TraceStream<'_> {
+        self.stream(DecoderRef::Borrowed(self))
+    }
+
+    /// A stream that owns the decoder, e.g. to move it into a thread or task of its own.
+    pub fn into_stream(self) -> TraceStream<'static> {
+        Arc::new(self).new_shared_stream()
+    }
+
+    /// A stream that shares the decoder with other streams and may outlive `self`, e.g.
+    /// one per device in a host service, each running in its own task.
+    ///
+    /// # Example
+    /// ```rust,ignore
+    /// let decoder = Arc::new(TraceDecoder::new(&elf)?);
+    /// for port in ports {
+    ///     let mut stream = decoder.new_shared_stream();
+    ///     tokio::spawn(async move { /* feed `port` into `stream` */ });
+    /// }
+    /// ```
+    pub fn new_shared_stream(self: &Arc<Self>) -> TraceStream<'static> {
+        self.stream(DecoderRef::Shared(Arc::clone(self)))
+    }
+
+    fn stream<'a>(&self, parent: DecoderRef<'a>) -> TraceStream<'a> {
+        #[allow(unused_mut)]
+        let mut stream = TraceStream {
+            parent,
+            contexts: BTreeMap::new(),
+            next_span_id: 1,
+            exporters: Vec::new(),
+            stats: StreamStats::default(),
+            retained: Default::default(),
+            on_issue: None,
+            on_frame: None,
+            wall_clock: self.config.anchor_wall_clock.then(Default::default),
+            received_at: None,
+            next_sync_nonce: 0,
+            rendered_timestamp: String::new(),
+            external_locations: HashMap::new(),
+            location_strings: Default::default(),
+            remapped_paths: HashMap::new(),
+            interned_fields: HashMap::new(),
+            recent_spans: HashMap::new(),
+            sampling: sampling::SamplingState::new(),
+            #[cfg(feature = "prometheus")]
+            health: None,
+        };
+        #[cfg(all(feature = "otlp-pipeline", feature = "metrics"))]
+        if let Some(meter) = self._otlp.as_ref().and_then(|otlp| otlp.meter()) {
+            stream.add_exporter(export::DeviceMetrics::new(&meter));
+        }
+        #[cfg(all(feature = "otlp-pipeline", feature = "logs"))]
+        if let Some(logger) = self._otlp.as_ref().and_then(|otlp| otlp.logger()) {
+            stream.add_exporter(export::DeviceLogs::new(logger));
+        }
+        if let Some(interval) = self.config.summary_interval {
+            stream
+                .add_exporter(export::SpanSummary::new(std::io::stderr()).with_interval(interval));
+        }
+        #[cfg(feature = "loki")]
+        if let Some(url) = &self.config.loki_endpoint {
+            let mut loki = export::LokiExporter::new(url.as_str());
+            for (key, value) in self.resource_attributes() {
+                let label = match key {
+                    "service.name" => "service_name",
+                    "device.id" => "device_id",
+                    _ => continue,
+                };
+                loki = loki.with_label(label, value);
+            }
+            stream.add_exporter(loki);
+        }
+        stream
+    }
+
+    /// Flushes and stops the built-in OTLP export, reporting failures that dropping the
+    /// decoder would only log. Does nothing without it.
+    pub fn shutdown(self) -> Result<(), Error> {
+        #[cfg(feature = "otlp-pipeline")]
+        if let Some(mut otlp) = self._otlp {
+            otlp.shutdown()?;
+        }
+        Ok(())
+    }
+
+    /// Decodes the raw capture file at `path` at full speed, see [`source::Replay`].
+    pub fn replay<P: AsRef<std::path::Path>>(&self, path: P) -> Result<(), Error> {
+        let mut stream = self.new_stream();
+        source::Replay::open(path)?.run(&mut stream)?;
+        Ok(())
+    }
 
-        let locations = table
-            .get_locations(elf_data)
-            .map_err(|e| Error::Elf(format!("Locs: {:?}", e)))?;
+    /// Decodes `data` as context 0 with a fresh stream and returns the records, e.g. of a
+    /// recorded capture to check with [`expect`].
+    pub fn decode_records(&self, data: &[u8]) -> Result<Vec<TraceRecord>, Error> {
+        let mut records = Vec::new();
+        let mut stream = self.new_stream();
+        stream.process_into(data, |record| records.push(record))?;
+        stream.flush_into(|record| records.push(record));
+        Ok(records)
+    }
+
+    /// Replaces the defmt table and locations with those of a rebuilt firmware image.
+    ///
+    /// Configuration and the export pipeline are kept. Streams borrow or share the
+    /// decoder, so they have to be dropped first and recreated afterwards; this also resets
+    /// the stream decoders, which would otherwise misinterpret frames of the new build. On error the
+    /// previous table stays in use, e.g. when the ELF is read while the linker still writes it.
+    pub fn reload(&mut self, elf_data: &[u8]) -> Result<(), Error> {
+        self.reload_image(0, elf_data)
+    }
 
-        Ok(Self { table, locations })
+    /// Like [`reload`](Self::reload), for image `image` of a [multi-image](TraceDecoderBuilder::build_multi) decoder.
+    pub fn reload_image(&mut self, image: usize, elf_data: &[u8]) -> Result<(), Error> {
+        let slot = self
+            .images
+            .get_mut(image)
+            .ok_or_else(|| Error::Elf(format!("No image {}", image)))?;
+        *slot = parse_elf(elf_data, self.config.dwarf_locations)?;
+        Ok(())
     }
+}
+
+/// Returns the GNU build id note of an ELF file as a hex string.
+fn build_id(elf_data: &[u8]) -> Option<String> {
+    use object::Object;
+
+    let file = object::File::parse(elf_data).ok()?;
+    let id = file.build_id().ok()??;
+    Some(id.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// Reads the wire protocol version the facade records in the ELF. `None` for firmware
+/// built before it did, or linked without the symbol.
+fn protocol_version(elf_data: &[u8]) -> Option<u32> {
+    use object::{Object, ObjectSection, ObjectSymbol};
+
+    let file = object::File::parse(elf_data).ok()?;
+    let symbol = file
+        .symbols()
+        .find(|symbol| symbol.name() == Ok(wire::VERSION_SYMBOL))?;
+    let section = file.section_by_index(symbol.section_index()?).ok()?;
+    let offset = usize::try_from(symbol.address().checked_sub(section.address())?).ok()?;
+    let bytes = section.data().ok()?.get(offset..offset.checked_add(4)?)?;
+    let bytes = bytes.try_into().ok()?;
+    Some(if file.is_little_endian() {
+        u32::from_le_bytes(bytes)
+    } else {
+        u32::from_be_bytes(bytes)
+    })
+}
+
+/// Parses the defmt table and locations of an ELF. Missing locations are not an error,
+/// only the table is required to decode.
+///
+/// Firmware of a newer wire protocol version than this decoder's is refused, rather
+/// than decoded into subtly wrong traces.
+fn parse_elf(elf_data: &[u8], dwarf_locations: bool) -> Result<Image, Error> {
+    let protocol_version = protocol_version(elf_data);
+    match protocol_version {
+        Some(found) if found > wire::VERSION => {
+            return Err(Error::UnsupportedProtocol {
+                found,
+                supported: wire::VERSION,
+            })
+        }
+        Some(found) => log::debug!("Firmware uses wire protocol version {}", found),
+        None => log::debug!("No wire protocol version in the ELF, assuming version 1"),
+    }
+
+    let table = Table::parse(elf_data)
+        .map_err(|e| Error::Elf(format!("{:?}", e)))?
+        .ok_or_else(|| Error::Elf("No defmt table found".to_string()))?;
+
+    let mut locations = table.get_locations(elf_data).unwrap_or_else(|e| {
+        log::warn!("No defmt location table: {:?}", e);
+        Locations::new()
+    });
+    let missing = unlocated(&table, &locations);
+    if dwarf_locations && !missing.is_empty() {
+        let found = dwarf::locations(elf_data, &table, &missing);
+        log::debug!("Found {} locations in the DWARF debug info", found.len());
+        locations.extend(found);
+    }
+
+    let mut image = Image::new(table, locations);
+    image.protocol_version = protocol_version;
+    image.symbols = symbols::Symbolizer::new(elf_data);
+    if image.symbols.is_empty() {
+        log::debug!("No symbols in the ELF, crash frames will not be symbolicated");
+    }
+    if image.missing_locations > 0 {
+        log::warn!(
+            "{} of {} log statements have no source location",
+            image.missing_locations,
+            image.table.indices().count()
+        );
+    }
+    Ok(image)
+}
+
+/// Indices of the log statements of `table` without an entry in `locations`.
+fn unlocated(table: &Table, locations: &Locations) -> BTreeSet<u64> {
+    table
+        .indices()
+        .map(|index| index as u64)
+        .filter(|index| !locations.contains_key(index))
+        .collect()
+}
+
+/// A span that was entered on the device and has not exited yet.
+struct OpenSpan {
+    id: u64,
+    name: String,
+    span: Span,
+    /// Error events recorded in the span so far.
+    errors: u32,
+    /// Events recorded in the span so far, by level.
+    events: [usize; 5],
+    /// Events left out of the span past the limit of their level, and the metadata of
+    /// the event that reports them.
+    dropped: Option<(usize, &'static tracing::Metadata<'static>)>,
+    /// Extremes of the memory gauges measured while the span was open.
+    memory: memory::MemoryPeaks,
+}
 
-    pub fn new_stream(&self) -> TraceStream {
-        let stream_decoder = self.table.new_stream_decoder();
-        TraceStream {
-            parent: self,
-            stream_decoder: Some(stream_decoder),
-            span_stack: Vec::new(),
+impl Drop for OpenSpan {
+    /// Counts the errors, records the memory peaks and reports the dropped events once
+    /// the span closes, however it closes.
+    fn drop(&mut self) {
+        if self.errors > 0 {
+            self.span
+                .set_attribute("error.count", i64::from(self.errors));
         }
+        self.memory.record(&self.span);
+        if let Some((dropped, metadata)) = self.dropped {
+            let message = format!("dropped {} further events", dropped);
+            let dropped = dropped as i64;
+            callsite::dispatch_event(
+                metadata,
+                Some(&self.span),
+                &[Some(&message), None, None, None, Some(&dropped)],
+            );
+        }
+    }
+}
+
+/// The field of the event that reports the events dropped from a span.
+const DROPPED_EVENTS_FIELD: &str = "dropped_events";
+
+/// Position of `level` in per-level arrays, from trace to error.
+fn level_index(level: Level) -> usize {
+    match level {
+        Level::TRACE => 0,
+        Level::DEBUG => 1,
+        Level::INFO => 2,
+        Level::WARN => 3,
+        Level::ERROR => 4,
     }
 }
 
+/// Decoding state of one execution context (core, channel).
+struct ContextState<'a> {
+    /// Index of the firmware image currently logging.
+    image: usize,
+    framer: Option<framing::Framer>,
+    span_stack: Vec<OpenSpan>,
+    reorder: Option<ReorderBuffer>,
+    short_spans: Option<short_spans::ShortSpanFilter>,
+    coalescer: Option<coalesce::EventCoalescer>,
+    /// Receives a copy of every byte fed into this context.
+    capture: Option<Box<dyn Write + Send + Sync + 'a>>,
+    bytes_received: u64,
+    /// Bytes received since (and including) the read that completed the last frame.
+    bytes_since_frame: u64,
+    /// Parent of root spans, set by `traceparent:` frames.
+    remote_parent: Option<opentelemetry::Context>,
+    /// Nesting depth inside a span that was not sampled; its records are dropped.
+    unsampled_depth: usize,
+    /// Reboots seen on this context, exported as `boot.id`.
+    boot: u64,
+    /// Host time the current boot was first seen, in microseconds since the Unix epoch.
+    /// Together with the context and `boot` it makes up the `session.id`.
+    boot_seen: u64,
+    /// Cause of the reset that started the current boot, from its `boot` frame.
+    reset_reason: Option<String>,
+    /// Converts the device timestamps, across counter rollovers.
+    timestamps: timestamp::Unwrapper,
+    /// Device timestamp of the latest record, to detect a timestamp reset.
+    last_timestamp: Option<u64>,
+    /// Whether a record was received since the stream started.
+    has_records: bool,
+    /// Bytes of the frame being received, kept for `on_frame`.
+    frame_bytes: Vec<u8>,
+    /// The first bytes received, kept until a frame decodes to check the encoding.
+    probe: Option<Vec<u8>>,
+}
+
+/// Decodes the byte stream(s) of one device.
+///
+/// Devices with several cores or channels deliver one defmt stream per execution
+/// context. Feed each through [`process_context`](Self::process_context) with its own
+/// id: every context gets its own decoder and span stack, so spans of different cores
+/// nest independently, and its records are tagged with the context id.
+/// [`process`](Self::process) is shorthand for context 0.
+///
+/// A frame `traceparent: <W3C traceparent>`, logged e.g. when the device receives a
+/// request from a backend service, makes the following root spans of its context
+/// children of that remote span, so they show up in the service's distributed trace.
+/// It applies until the next `traceparent:` frame; one without a valid value (e.g.
+/// `traceparent: none`) returns to starting new traces.
+///
+/// A frame `follows_from: name` adds a span link from the innermost open span of its
+/// context to the most recent span called `name` on any context, e.g. from a task to
+/// the interrupt handler that woke it, which parent/child nesting cannot express.
+///
+/// A frame `span_record: name, field=value`, logged by the facade's `Span::record_u32`
+/// and its siblings, sets the field as an attribute of the innermost open span called
+/// `name` of its context, instead of becoming an event.
+///
+/// An error-level event, or any event with an `error=` field, sets the status of the
+/// enclosing span to error, described by the field's value or else the message, and
+/// carries it as `exception.message`. With several errors the last one describes the
+/// status, see [`TraceDecoderBuilder::with_error_description`], and the span gets an
+/// `error.count` attribute.
+///
+/// A reboot of the device, detected from a `boot` frame (or `boot: ...`, logged first
+/// thing after reset) or from its timestamp going backwards, closes the open spans of
+/// the context as truncated; see [`report_reboot`](Self::report_reboot). The
+/// `reset.reason` field of a `boot: reset.reason=<reason>` frame, logged by the
+/// facade's `reset::log_boot`, becomes an attribute of the spans of that boot session and
+/// is counted in [`StreamStats::resets`].
 pub struct TraceStream<'a> {
-    parent: &'a TraceDecoder,
-    stream_decoder: Option<Box<dyn StreamDecoder + 'a>>,
-    span_stack: Vec<Span>,
+    parent: DecoderRef<'a>,
+    contexts: BTreeMap<u32, ContextState<'a>>,
+    next_span_id: u64,
+    exporters: Vec<Box<dyn Exporter + Send + Sync + 'a>>,
+    stats: StreamStats,
+    /// Records held for a trigger, see [`TraceDecoderBuilder::with_retention`].
+    retained: retention::RetainedRecords,
+    on_issue: Option<IssueCallback<'a>>,
+    on_frame: Option<FrameCallback<'a>>,
+    wall_clock: Option<clock::WallClock>,
+    /// When the bytes being decoded arrived, if their source knows, see [`source::Chunk`].
+    received_at: Option<SystemTime>,
+    next_sync_nonce: u32,
+    /// Scratch buffer the device timestamp of each frame is rendered into.
+    rendered_timestamp: String,
+    /// Locations of the frames handed to `process_frame`, by frame index.
+    external_locations: HashMap<u64, RecordLocation>,
+    location_strings: location::Strings,
+    /// Source paths after [`TraceDecoderBuilder::with_path_prefix`], by original path.
+    remapped_paths: HashMap<Arc<str>, Arc<str>>,
+    /// The keys of [`record::interned_fields`] by image and frame index, which saves
+    /// parsing the format string of each frame again.
+    interned_fields: HashMap<(usize, u64), Vec<String>>,
+    /// OpenTelemetry context of the most recently entered span of each name, the
+    /// targets of `follows_from:` links.
+    recent_spans: HashMap<String, opentelemetry::trace::SpanContext>,
+    sampling: sampling::SamplingState,
+    /// Where to report the stream's health, and under which name.
+    #[cfg(feature = "prometheus")]
+    health: Option<(HealthEndpoint, String)>,
+}
+
+type IssueCallback<'a> = Box<dyn FnMut(&StreamIssue) + Send + Sync + 'a>;
+type FrameCallback<'a> = Box<dyn FnMut(&RawFrame) + Send + Sync + 'a>;
+
+/// The decoder a stream belongs to, borrowed or shared.
+#[derive(Clone)]
+enum DecoderRef<'a> {
+    Borrowed(&'a TraceDecoder),
+    Shared(Arc<TraceDecoder>),
+}
+
+impl Deref for DecoderRef<'_> {
+    type Target = TraceDecoder;
+
+    fn deref(&self) -> &TraceDecoder {
+        match self {
+            Self::Borrowed(decoder) => decoder,
+            Self::Shared(decoder) => decoder,
+        }
+    }
 }
 
 impl<'a> TraceStream<'a> {
+    /// Feeds every decoded record into `exporter`, in addition to the tracing pipeline.
+    pub fn add_exporter(&mut self, exporter: impl Exporter + Send + Sync + 'a) {
+        self.exporters.push(Box::new(exporter));
+    }
+
+    /// Calls `callback` for every decoding problem, e.g. to expose decoder health in a
+    /// host service. Problems are also logged through the `log` crate.
+    pub fn on_issue(&mut self, callback: impl FnMut(&StreamIssue) + Send + Sync + 'a) {
+        self.on_issue = Some(Box::new(callback));
+    }
+
+    /// Calls `callback` with every decoded frame, its bytes as received and its source
+    /// location, before the frame is turned into a record. Meant for tooling that needs
+    /// more than the records, e.g. protocol checks or binary diffing of captures.
+    ///
+    /// Frames that are filtered or sampled out are included. To know where each frame
+    /// ends, data is fed to the stream decoder byte by byte, which costs some throughput.
+    pub fn on_frame(&mut self, callback: impl FnMut(&RawFrame) + Send + Sync + 'a) {
+        self.on_frame = Some(Box::new(callback));
+    }
+
+    /// Totals of received, decoded and dropped data so far.
+    pub fn stats(&self) -> &StreamStats {
+        &self.stats
+    }
+
+    /// Reports the health of the stream to `endpoint` as `name`, after every call that
+    /// processes data and on [`flush`](Self::flush).
+    #[cfg(feature = "prometheus")]
+    pub fn report_health(&mut self, endpoint: &HealthEndpoint, name: impl Into<String>) {
+        self.health = Some((endpoint.clone(), name.into()));
+        self.publish_health();
+    }
+
+    fn publish_health(&self) {
+        #[cfg(feature = "prometheus")]
+        if let Some((endpoint, name)) = &self.health {
+            let snapshot = prometheus::Snapshot {
+                stats: self.stats.clone(),
+                open_spans: self
+                    .contexts
+                    .iter()
+                    .map(|(context, state)| (*context, state.span_stack.len()))
+                    .collect(),
+                queued: self
+                    .exporters
+                    .iter()
+                    .map(|exporter| exporter.queued())
+                    .sum(),
+            };
+            endpoint.update(name, snapshot);
+        }
+    }
+
+    fn report_issue(&mut self, issue: StreamIssue) {
+        match &issue {
+            StreamIssue::Malformed {
+                context,
+                offset,
+                bytes_discarded,
+            } => {
+                self.stats.malformed += 1;
+                self.stats.bytes_discarded += bytes_discarded;
+                log::warn!(
+                    "context {}: malformed defmt data, dropped up to {} bytes, resuming at byte {}",
+                    context,
+                    bytes_discarded,
+                    offset
+                );
+            }
+            StreamIssue::FramesLost { context, count } => {
+                self.stats.frames_lost += count;
+                log::warn!("context {}: {} frames lost", context, count);
+            }
+            StreamIssue::Reboot { context, cause } => {
+                self.stats.reboots += 1;
+                log::info!("context {}: device rebooted ({:?})", context, cause);
+            }
+            StreamIssue::TimestampJump { context, from, to } => {
+                log::warn!(
+                    "context {}: device timestamp jumped from {} us to {} us, \
+                     a counter rollover or reboot may have been missed",
+                    context,
+                    from,
+                    to
+                );
+            }
+            StreamIssue::Reconnected { attempts, downtime } => {
+                self.stats.reconnects += 1;
+                log::info!(
+                    "reconnected after {} attempts, {:?} without transport",
+                    attempts,
+                    downtime
+                );
+            }
+        }
+        if let Some(callback) = &mut self.on_issue {
+            callback(&issue);
+        }
+    }
+
+    /// Writes the exact bytes received for `context` to `writer` before decoding them.
+    ///
+    /// The capture can be decoded again later with [`source::Replay`], e.g. against the
+    /// matching ELF if it was missing, or after a decoder bug has been fixed. See
+    /// [`capture::create_capture_file`] for a timestamped file.
+    pub fn set_capture(&mut self, context: u32, writer: impl Write + Send + Sync + 'a) {
+        self.context_mut(context).capture = Some(Box::new(writer));
+    }
+
+    /// Decodes `data` and emits the result as tracing spans and events, and to all exporters.
     pub fn process(&mut self, data: &[u8]) -> Result<(), Error> {
-        let mut decoder = self.stream_decoder.take().unwrap();
-        decoder.received(data);
+        self.process_context(0, data)
+    }
+
+    /// Like [`process`](Self::process), for the stream of execution context `context`.
+    pub fn process_context(&mut self, context: u32, data: &[u8]) -> Result<(), Error> {
+        self.decode_with(context, data, Self::emit)
+    }
+
+    /// Handles a chunk read by a [`FrameSource`](source::FrameSource): decodes its
+    /// bytes like [`process_context`](Self::process_context), anchoring them to the wall
+    /// clock by their receive time, or resets the decoder after lost bytes.
+    pub fn process_chunk(&mut self, chunk: source::Chunk) -> Result<(), Error> {
+        match chunk {
+            source::Chunk::Data {
+                context,
+                bytes,
+                received_at,
+            } => {
+                self.received_at = received_at;
+                let result = self.process_context(context, bytes);
+                self.received_at = None;
+                result
+            }
+            source::Chunk::Lost {
+                frames: Some(frames),
+            } => {
+                self.report_loss(frames);
+                Ok(())
+            }
+            source::Chunk::Lost { frames: None } => {
+                self.reset();
+                Ok(())
+            }
+            source::Chunk::Reconnected { attempts, downtime } => {
+                self.report_reconnect(attempts, downtime);
+                Ok(())
+            }
+        }
+    }
+
+    /// Decodes `data` and passes each record to `sink`.
+    ///
+    /// Nothing is emitted to tracing or the registered exporters, which leaves it to the
+    /// caller to build its own pipeline. Span and parent ids are assigned as in [`process`](Self::process).
+    pub fn process_into(
+        &mut self,
+        data: &[u8],
+        sink: impl FnMut(TraceRecord),
+    ) -> Result<(), Error> {
+        self.process_context_into(0, data, sink)
+    }
+
+    /// Like [`process_into`](Self::process_into), for the stream of execution context `context`.
+    pub fn process_context_into(
+        &mut self,
+        context: u32,
+        data: &[u8],
+        mut sink: impl FnMut(TraceRecord),
+    ) -> Result<(), Error> {
+        self.decode_with(context, data, |this, record| {
+            this.update_span_stack(&record, Span::none());
+            sink(record);
+            Ok(())
+        })
+    }
+
+    /// Like [`process`](Self::process), calling `before` right before each record is emitted.
+    pub(crate) fn process_with(
+        &mut self,
+        data: &[u8],
+        mut before: impl FnMut(&TraceRecord),
+    ) -> Result<(), Error> {
+        self.decode_with(0, data, |this, record| {
+            before(&record);
+            this.emit(record)
+        })
+    }
+
+    /// Emits a placed record to tracing and the exporters.
+    fn emit(&mut self, mut record: TraceRecord) -> Result<(), Error> {
+        self.in_dispatch(|this| this.handle_record(&mut record))
+    }
 
-        loop {
-            match decoder.decode() {
-                Ok(frame) => self.handle_frame(frame),
-                Err(DecodeError::UnexpectedEof) => break,
+    fn decode_with(
+        &mut self,
+        context: u32,
+        data: &[u8],
+        mut handle: impl FnMut(&mut Self, TraceRecord) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        if self.parent.images.is_empty() {
+            return Err(Error::Elf(
+                "No defmt table to decode raw data, the decoder was built for text".to_string(),
+            ));
+        }
+        if let Some(capture) = &mut self.context_mut(context).capture {
+            capture.write_all(data)?;
+        }
+
+        // After an image switch the remaining bytes belong to another table. The framer
+        // decodes whatever it buffered with one table, so with several images the data
+        // is fed byte by byte and the switch happens exactly at the frame boundary. The
+        // same gives `on_frame` the bytes of each frame.
+        let chunk_len = match (self.parent.images.len(), &self.on_frame) {
+            (1, None) => data.len().max(1),
+            _ => 1,
+        };
+        let result = data
+            .chunks(chunk_len)
+            .try_for_each(|chunk| self.decode_chunk(context, chunk, &mut handle));
+        self.publish_health();
+        result
+    }
+
+    fn decode_chunk(
+        &mut self,
+        context: u32,
+        data: &[u8],
+        handle: &mut impl FnMut(&mut Self, TraceRecord) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let parent = self.parent.clone();
+        let keep_bytes = self.on_frame.is_some();
+        self.stats.bytes_received += data.len() as u64;
+        let state = self.context_mut(context);
+        state.bytes_received += data.len() as u64;
+        state.bytes_since_frame += data.len() as u64;
+        let mut image = state.image;
+        let mut framer = state.framer.take().unwrap_or_else(|| parent.framer(image));
+        framer.received(data);
+        if let Some(probe) = &mut state.probe {
+            let room = framing::PROBE_LEN.saturating_sub(probe.len());
+            probe.extend_from_slice(&data[..data.len().min(room)]);
+        }
+        if keep_bytes {
+            state.frame_bytes.extend_from_slice(data);
+        }
+
+        let result = loop {
+            match framer.decode(&parent.images[image].table) {
+                Ok(frame) => {
+                    self.stats.frames_decoded += 1;
+                    let state = self.context_mut(context);
+                    state.bytes_since_frame = data.len() as u64;
+                    state.probe = None;
+                    let bytes = std::mem::take(&mut state.frame_bytes);
+                    if let Some(callback) = &mut self.on_frame {
+                        callback(&RawFrame {
+                            context,
+                            bytes: &bytes,
+                            frame: &frame,
+                            location: parent.images[image].locations.get(&frame.index()),
+                        });
+                    }
+                    let record = self.build_record(context, image, &frame);
+                    let switch = record::parse_image_switch(&record.message)
+                        .filter(|next| *next < parent.images.len());
+                    if let Err(e) = self.accept(record, handle) {
+                        break Err(e);
+                    }
+                    if let Some(next) = switch {
+                        image = next;
+                        self.context_mut(context).image = next;
+                        framer = parent.framer(next);
+                    }
+                }
+                Err(DecodeError::UnexpectedEof) => {
+                    break self.check_encoding(context, image, false)
+                }
                 Err(DecodeError::Malformed) => {
-                    eprintln!("⚠️  Defmt stream malformed. Resetting decoder...");
-                    decoder = self.parent.table.new_stream_decoder();
-                    break;
+                    // The rest of `data` was already handed to the discarded framer.
+                    let state = self.context_mut(context);
+                    state.frame_bytes.clear();
+                    let issue = StreamIssue::Malformed {
+                        context,
+                        offset: state.bytes_received,
+                        bytes_discarded: std::mem::take(&mut state.bytes_since_frame),
+                    };
+                    self.report_issue(issue);
+                    framer = parent.framer(image);
+                    break self.check_encoding(context, image, true);
                 }
             }
+        };
+
+        self.context_mut(context).framer = Some(framer);
+        result
+    }
+
+    /// Fails if the bytes `context` received before its first frame decode cleanly with
+    /// the other encoding. Checked at every malformed frame, and once when the probe is
+    /// full without a frame.
+    fn check_encoding(&mut self, context: u32, image: usize, malformed: bool) -> Result<(), Error> {
+        let parent = self.parent.clone();
+        let state = self.context_mut(context);
+        let Some(probe) = &state.probe else {
+            return Ok(());
+        };
+        let full = probe.len() >= framing::PROBE_LEN;
+        if !malformed && !full {
+            return Ok(());
+        }
+        let expected = parent.image_encoding(image);
+        let detected = framing::detect(&parent.images[image].table, expected, probe);
+        if full {
+            state.probe = None;
+        }
+        match detected {
+            Some(detected) => Err(Error::EncodingMismatch {
+                context,
+                expected,
+                detected,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Emits a frame that was decoded elsewhere with `defmt_decoder`, like one decoded
+    /// from [`process_context`](Self::process_context).
+    ///
+    /// The hook for runners that decode the defmt stream themselves, e.g. a tool built on
+    /// `probe-rs` that prints the logs as before and hands every frame on to get spans
+    /// too. The stream's decoder does not need the ELF, see
+    /// [`build_for_text`](TraceDecoderBuilder::build_for_text). For the output of
+    /// `probe-rs run` or `defmt-print`, see [`source::LineReconstructor`] and the
+    /// `defmt-trace-pipe` binary.
+    ///
+    /// # Example
+    /// ```rust,ignore
+    /// let decoder = TraceDecoder::builder().build_for_text()?;
+    /// let mut stream = decoder.new_stream();
+    /// while let Ok(frame) = frames.decode() {
+    ///     println!("{}", frame.display(true));
+    ///     stream.process_frame(0, &frame, locations.get(&frame.index()))?;
+    /// }
+    /// ```
+    pub fn process_frame(
+        &mut self,
+        context: u32,
+        frame: &Frame,
+        location: Option<&Location>,
+    ) -> Result<(), Error> {
+        let timestamp = self.frame_timestamp(context, frame);
+        let location = location.map(|location| self.external_location(frame.index(), location));
+        let record = TraceRecord::from_message(
+            context,
+            frame.level().map(record::level_from_defmt),
+            frame.display_message().to_string(),
+            location,
+            timestamp,
+        );
+        self.stats.frames_decoded += 1;
+        self.process_record(record)
+    }
+
+    /// The location of a frame handed to [`process_frame`](Self::process_frame), resolved
+    /// once per log statement.
+    fn external_location(&mut self, index: u64, location: &Location) -> RecordLocation {
+        if let Some(cached) = self.external_locations.get(&index) {
+            // The same index in another firmware is another statement.
+            if cached.line == location.line
+                && *cached.module == location.module
+                && location.file.to_str() == Some(&*cached.file)
+            {
+                return cached.clone();
+            }
+        }
+        let resolved = self.location_strings.resolve(location);
+        self.external_locations.insert(index, resolved.clone());
+        resolved
+    }
+
+    /// Emits a record that was decoded elsewhere, e.g. by [`source::TextLines`], like a
+    /// decoded frame.
+    pub(crate) fn process_record(&mut self, record: TraceRecord) -> Result<(), Error> {
+        self.accept(record, &mut Self::emit)
+    }
+
+    /// Anchors a new record to the wall clock, expands crash frames into a `crash` span,
+    /// then [`admit`](Self::admit)s the records.
+    fn accept(
+        &mut self,
+        mut record: TraceRecord,
+        handle: &mut impl FnMut(&mut Self, TraceRecord) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        if let Some(cause) = self.detect_reboot(&record) {
+            self.reboot(record.context, cause, handle)?;
+        }
+        if let (Some(clock), Some(timestamp)) = (&mut self.wall_clock, record.timestamp) {
+            let received = self.received_at.unwrap_or_else(SystemTime::now);
+            clock.observe(timestamp, received);
+            if let Some(nonce) = record::parse_time_sync(&record.message) {
+                clock.sync_replied(nonce, timestamp, received);
+            }
+            record.wall_time = clock.wall_time(timestamp);
+        }
+        if record.kind == RecordKind::Event && record::is_boot_frame(&record.message) {
+            self.start_boot(&mut record);
+        }
+        if let Some(crash) =
+            crash::Crash::parse(&record.message).filter(|_| record.kind == RecordKind::Event)
+        {
+            let image = self.context_mut(record.context).image;
+            let parent = self.parent.clone();
+            for record in crash.into_records(record, &parent.images[image].symbols) {
+                self.admit(record, handle)?;
+            }
+            return Ok(());
         }
+        self.admit(record, handle)
+    }
 
-        self.stream_decoder = Some(decoder);
+    /// Takes the fields of a `boot` frame, whatever the field extraction setting, and
+    /// keeps its reset reason for the spans of the boot session.
+    fn start_boot(&mut self, record: &mut TraceRecord) {
+        record::extract_boot_fields(record);
+        let Some(reason) = record::field(record, wire::RESET_REASON_FIELD) else {
+            return;
+        };
+        let reason = reason.to_string();
+        *self.stats.resets.entry(reason.clone()).or_default() += 1;
+        self.context_mut(record.context).reset_reason = Some(reason);
+    }
+
+    /// Extracts event fields, applies the filter, the field limits and the source path
+    /// remapping before releasing or retaining a record.
+    fn admit(
+        &mut self,
+        mut record: TraceRecord,
+        handle: &mut impl FnMut(&mut Self, TraceRecord) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        if self.parent.config.extract_fields && record.kind == RecordKind::Event {
+            record::extract_fields(&mut record);
+        }
+        if let Some(filter) = &self.parent.config.filter {
+            if !filter.enabled(&record) {
+                return Ok(());
+            }
+        }
+        let config = &self.parent.config;
+        if config.max_fields.is_some() || config.max_field_len.is_some() {
+            record::limit_fields(&mut record, config.max_fields, config.max_field_len);
+        }
+        if let Some(location) = record
+            .location
+            .as_mut()
+            .filter(|_| !config.path_remap.is_empty())
+        {
+            // Once per source file, as locations share their paths.
+            location.file = self
+                .remapped_paths
+                .entry(location.file.clone())
+                .or_insert_with_key(|file| {
+                    config
+                        .path_remap
+                        .apply(file)
+                        .map_or_else(|| file.clone(), Into::into)
+                })
+                .clone();
+        }
+        let Some(retention) = &config.retention else {
+            return self.release(record.context, Some(record), handle);
+        };
+        let triggered = retention.triggers(&record);
+        self.stats.records_discarded += self.retained.push(retention, record) as u64;
+        if triggered {
+            self.release_retained(handle)?;
+        }
         Ok(())
     }
 
-    fn handle_frame(&mut self, frame: Frame) {
-        let message = frame.display(false).to_string();
+    /// Exports the records kept by [`TraceDecoderBuilder::with_retention`], e.g. from a
+    /// signal handler or when a test fails. Records are then kept again.
+    pub fn export_retained(&mut self) -> Result<(), Error> {
+        self.release_retained(&mut Self::emit)
+    }
 
-        if let Some(idx) = message.find("span_enter: ") {
-            let rest = &message[idx + "span_enter: ".len()..];
-            self.handle_span_enter(rest, &frame);
-        } else if let Some(idx) = message.find("span_exit: ") {
-            let rest = &message[idx + "span_exit: ".len()..];
-            self.handle_span_exit(rest);
-        } else {
-            self.handle_log(&message, &frame);
+    fn release_retained(
+        &mut self,
+        handle: &mut impl FnMut(&mut Self, TraceRecord) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        for record in self.retained.take() {
+            self.release(record.context, Some(record), handle)?;
         }
+        Ok(())
     }
-    fn handle_span_enter(&mut self, name: &str, frame: &Frame) {
-        let clean_name = if let Some(idx) = name.find("; file=") {
-            &name[..idx]
-        } else {
-            name
+
+    fn detect_reboot(&mut self, record: &TraceRecord) -> Option<RebootCause> {
+        let window = self
+            .parent
+            .config
+            .reorder_window
+            .map_or(0, |window| window.as_micros() as u64);
+        let state = self.context_mut(record.context);
+        let has_records = std::mem::replace(&mut state.has_records, true);
+        let last_timestamp = match record.timestamp {
+            Some(timestamp) => state.last_timestamp.replace(timestamp),
+            None => None,
         };
+        if has_records && record.kind == RecordKind::Event && record::is_boot_frame(&record.message)
+        {
+            return Some(RebootCause::BootFrame);
+        }
+        match (record.timestamp, last_timestamp) {
+            (Some(timestamp), Some(last)) if timestamp.saturating_add(window) < last => {
+                Some(RebootCause::TimestampReset)
+            }
+            _ => None,
+        }
+    }
+
+    /// Records that the device of `context` rebooted, e.g. when the transport reset it
+    /// through the debug probe; reboots are also detected from the frames themselves.
+    ///
+    /// Held back records are emitted and open spans are closed as truncated, like
+    /// [`finish`](Self::finish) does. Spans after the reboot start new traces and carry
+    /// an incremented `boot.id` and a new `session.id` attribute, so a crash loop shows
+    /// up as one trace per boot.
+    pub fn report_reboot(&mut self, context: u32) -> Result<(), Error> {
+        self.reboot(context, RebootCause::Reported, &mut Self::emit)
+    }
+
+    fn reboot(
+        &mut self,
+        context: u32,
+        cause: RebootCause,
+        handle: &mut impl FnMut(&mut Self, TraceRecord) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        self.release(context, None, handle)?;
+        self.truncate_spans(context, handle)?;
+        let state = self.context_mut(context);
+        state.remote_parent = None;
+        state.unsampled_depth = 0;
+        state.boot += 1;
+        state.boot_seen = unix_micros();
+        state.reset_reason = None;
+        self.report_issue(StreamIssue::Reboot { context, cause });
+        Ok(())
+    }
+
+    /// Closes the open spans of `context`, innermost first, with synthetic exit records.
+    fn truncate_spans(
+        &mut self,
+        context: u32,
+        handle: &mut impl FnMut(&mut Self, TraceRecord) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        while let Some(open) = self.span_stack(context).last() {
+            open.span.set_attribute("truncated", true);
+            let mut record = TraceRecord {
+                kind: RecordKind::SpanExit,
+                level: None,
+                message: open.name.clone(),
+                fields: vec![("truncated".to_string(), "true".to_string())],
+                location: None,
+                timestamp: None,
+                wall_time: None,
+                span_id: None,
+                parent_id: None,
+                context,
+                trace_id: None,
+                otel_span_id: None,
+                interned_fields: Vec::new(),
+            };
+            self.place_record(&mut record);
+            self.stats.spans_truncated += 1;
+            handle(self, record)?;
+        }
+        Ok(())
+    }
 
-        let mut file = String::new();
-        let mut line = 0i64;
-        let mut module = String::from("rp_pico");
+    /// Decodes the following data of `context` with firmware image `image`, e.g. when the
+    /// transport learns that the device rebooted into its bootloader.
+    ///
+    /// Panics if the decoder has no such image.
+    pub fn select_image(&mut self, context: u32, image: usize) {
+        assert!(image < self.parent.images.len(), "no image {}", image);
+        let state = self.context_mut(context);
+        state.image = image;
+        state.framer = None;
+    }
 
-        if let Some(loc) = self.parent.locations.get(&frame.index()) {
-            file = loc.file.display().to_string();
-            line = loc.line as i64;
-            module = loc.module.clone();
+    /// Creates a time sync request, to be sent to the device right away through its
+    /// control channel (the facade's `control` feature).
+    ///
+    /// The device answers with a `time_sync: <nonce>` frame. With
+    /// [`with_wall_clock_anchoring`](TraceDecoderBuilder::with_wall_clock_anchoring), the
+    /// round trips replace the estimate from when frames arrive: the offset and drift of
+    /// the device clock are fitted to them, which keeps span times accurate over long
+    /// captures. Send a request every few seconds; the drift is estimated once the round
+    /// trips span a thousand times the shortest one.
+    pub fn time_sync_request(&mut self) -> ControlCommand {
+        let nonce = self.next_sync_nonce;
+        self.next_sync_nonce = nonce.wrapping_add(1);
+        if let Some(clock) = &mut self.wall_clock {
+            clock.sync_requested(nonce, SystemTime::now());
         }
+        ControlCommand::Sync { nonce }
+    }
 
-        let parent_span = self.span_stack.last();
+    /// The drift of the device clock estimated from time sync round trips, in parts per
+    /// million: positive when it runs faster than the host's. See
+    /// [`time_sync_request`](Self::time_sync_request).
+    pub fn clock_drift_ppm(&self) -> Option<f64> {
+        self.wall_clock.as_ref()?.drift_ppm()
+    }
 
-        // We use a static name "device_span" because tracing requires static names.
-        // We set OTel semantic conventions via attributes.
-        // tracing-opentelemetry might map "otel_name" field to span name, so we provide it.
-        let span = if let Some(parent) = parent_span {
-            span!(
-                target: "device_log",
-                parent: parent,
-                Level::INFO,
-                "device_span",
-                otel_name = clean_name
-            )
-        } else {
-            span!(
-                target: "device_log",
-                Level::INFO,
-                "device_span",
-                otel_name = clean_name
-            )
+    /// Passes `record` through the reorder buffer, the sampler, the short span filter and
+    /// the event coalescing of its context, then places and handles the records that are ready. `None` releases
+    /// everything still held back.
+    fn release(
+        &mut self,
+        context: u32,
+        record: Option<TraceRecord>,
+        handle: &mut impl FnMut(&mut Self, TraceRecord) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let state = self.context_mut(context);
+        if state.reorder.is_none() && state.short_spans.is_none() && state.coalescer.is_none() {
+            // Nothing is held back, the record can skip the buffers below.
+            if let Some(mut record) = record {
+                if self.sample(&mut record) {
+                    self.place_record(&mut record);
+                    handle(self, record)?;
+                }
+            }
+            return Ok(());
+        }
+
+        let drain = record.is_none();
+        let ready = match (self.context_mut(context).reorder.as_mut(), record) {
+            (Some(buffer), Some(record)) => buffer.push(record),
+            (Some(buffer), None) => buffer.drain(),
+            (None, record) => record.into_iter().collect(),
         };
+        let mut kept = Vec::with_capacity(ready.len());
+        for mut record in ready {
+            if !self.sample(&mut record) {
+                continue;
+            }
+            match &mut self.context_mut(context).short_spans {
+                Some(filter) => filter.push(record, &mut kept),
+                None => kept.push(record),
+            }
+        }
+        if let (true, Some(filter)) = (drain, &mut self.context_mut(context).short_spans) {
+            filter.drain(&mut kept);
+        }
+        if let Some(coalescer) = &mut self.context_mut(context).coalescer {
+            let mut merged = Vec::with_capacity(kept.len());
+            for record in kept {
+                coalescer.push(record, &mut merged);
+            }
+            if drain {
+                coalescer.drain(&mut merged);
+            }
+            kept = merged;
+        }
+        for mut record in kept {
+            self.place_record(&mut record);
+            handle(self, record)?;
+        }
+        Ok(())
+    }
 
-        // Set semantic conventions attributes
-        span.set_attribute("otel.name", clean_name.to_string()); // Override span name
-        span.set_attribute("code.function", clean_name.to_string());
-        span.set_attribute("code.filepath", file);
-        span.set_attribute("code.lineno", line);
-        span.set_attribute("code.namespace", module);
+    /// Applies the sampler, if any. Returns whether `record` is kept, adding the
+    /// `sampling.rate` field to sampled spans.
+    fn sample(&mut self, record: &mut TraceRecord) -> bool {
+        let parent = self.parent.clone();
+        let Some(sampler) = &parent.config.sampler else {
+            return true;
+        };
+        let state = self.context_mut(record.context);
+        if state.unsampled_depth > 0 {
+            match record.kind {
+                RecordKind::SpanEnter => state.unsampled_depth += 1,
+                RecordKind::SpanExit => state.unsampled_depth -= 1,
+                _ => {}
+            }
+            return false;
+        }
+        if record.kind != RecordKind::SpanEnter {
+            return true;
+        }
 
-        self.span_stack.push(span);
+        let name = record::base_span_name(&record.message);
+        match self.sampling.sample(sampler, name, record.timestamp) {
+            sampling::Decision::Unsampled => true,
+            sampling::Decision::Keep(rate) => {
+                record
+                    .fields
+                    .push(("sampling.rate".to_string(), rate.to_string()));
+                true
+            }
+            sampling::Decision::Drop => {
+                self.context_mut(record.context).unsampled_depth = 1;
+                false
+            }
+        }
     }
 
-    fn handle_span_exit(&mut self, _name: &str) {
-        self.span_stack.pop();
+    fn context_mut(&mut self, context: u32) -> &mut ContextState<'a> {
+        let window = self.parent.config.reorder_window;
+        let min_span_duration = self.parent.config.min_span_duration;
+        let timebase = self.parent.config.timebase;
+        let coalesce_events = self.parent.config.coalesce_events;
+        self.contexts
+            .entry(context)
+            .or_insert_with(|| ContextState {
+                image: 0,
+                framer: None,
+                span_stack: Vec::new(),
+                reorder: window.map(|window| ReorderBuffer::new(window.as_micros() as u64)),
+                short_spans: min_span_duration
+                    .map(|duration| short_spans::ShortSpanFilter::new(duration.as_micros() as u64)),
+                coalescer: coalesce_events.then(coalesce::EventCoalescer::new),
+                capture: None,
+                bytes_received: 0,
+                bytes_since_frame: 0,
+                remote_parent: None,
+                unsampled_depth: 0,
+                boot: 0,
+                boot_seen: unix_micros(),
+                reset_reason: None,
+                timestamps: timestamp::Unwrapper::new(timebase.as_ref()),
+                last_timestamp: None,
+                has_records: false,
+                frame_bytes: Vec::new(),
+                probe: Some(Vec::new()),
+            })
     }
 
-    fn handle_log(&mut self, message: &str, frame: &Frame) {
-        let mut file = String::new();
-        let mut line = 0i64;
-        let mut module = String::from("rp_pico");
+    fn span_stack(&self, context: u32) -> &[OpenSpan] {
+        self.contexts
+            .get(&context)
+            .map(|state| state.span_stack.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// The tracing span of the open span `id` of `context`.
+    fn open_span(&self, context: u32, id: Option<u64>) -> Option<&Span> {
+        let id = id?;
+        self.span_stack(context)
+            .iter()
+            .rev()
+            .find(|open| open.id == id)
+            .map(|open| &open.span)
+    }
 
-        if let Some(loc) = self.parent.locations.get(&frame.index()) {
-            file = loc.file.display().to_string();
-            line = loc.line as i64;
-            module = loc.module.clone();
+    /// Emits the records held back by the reorder window and the short span filter, then
+    /// flushes all registered exporters.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        let contexts: Vec<u32> = self.contexts.keys().copied().collect();
+        for context in contexts {
+            self.release(context, None, &mut Self::emit)?;
+        }
+        for exporter in &mut self.exporters {
+            exporter.flush()?;
         }
+        for state in self.contexts.values_mut() {
+            if let Some(capture) = &mut state.capture {
+                capture.flush()?;
+            }
+        }
+        self.publish_health();
+        Ok(())
+    }
 
-        let parent_span = self.span_stack.last();
+    /// Ends decoding, e.g. at the end of the input or on Ctrl-C, and returns the final
+    /// statistics.
+    ///
+    /// Like [`flush`](Self::flush), and in addition closes the spans that are still open,
+    /// innermost first, so the tail of the trace is exported instead of lost. Each gets a
+    /// synthetic exit record without timestamp and a `truncated` field, which is also set
+    /// as attribute on the tracing span. Call [`TraceDecoder::shutdown`] afterwards to
+    /// wait for the built-in OTLP export.
+    pub fn finish(mut self) -> Result<StreamStats, Error> {
+        self.stats.records_discarded += self.retained.take().len() as u64;
+        let contexts: Vec<u32> = self.contexts.keys().copied().collect();
+        for &context in &contexts {
+            self.release(context, None, &mut Self::emit)?;
+        }
+        for context in contexts {
+            self.truncate_spans(context, &mut Self::emit)?;
+        }
+        self.flush()?;
+        Ok(std::mem::take(&mut self.stats))
+    }
 
-        // Use underscores for tracing fields, but OTel layer might NOT map these to dots automatically.
-        // However, we cannot use dots in info! macro.
-        if let Some(span) = parent_span {
-            info!(
-                target: "device_log",
-                parent: span,
-                code_filepath = file.as_str(),
-                code_lineno = line,
-                code_namespace = module.as_str(),
-                "{}",
-                message
+    /// Passes the records held back by the reorder window to `sink`.
+    ///
+    /// The counterpart of [`flush`](Self::flush) when decoding with [`process_into`](Self::process_into).
+    pub fn flush_into(&mut self, mut sink: impl FnMut(TraceRecord)) {
+        let contexts: Vec<u32> = self.contexts.keys().copied().collect();
+        for context in contexts {
+            let _ = self.release(context, None, &mut |this, record| {
+                this.update_span_stack(&record, Span::none());
+                sink(record);
+                Ok(())
+            });
+        }
+    }
+
+    /// Discards any partially received frame and starts decoding from a clean state.
+    ///
+    /// Call this whenever bytes may have been lost (e.g. after a transport reconnect),
+    /// so the next frame boundary is picked up instead of decoding garbage.
+    /// Open spans are kept, as the device keeps running while the link is down.
+    /// Applies to all execution contexts.
+    pub fn reset(&mut self) {
+        for state in self.contexts.values_mut() {
+            state.framer = None;
+        }
+    }
+
+    /// Records that `lost` frames, or at least that many, were dropped by the transport.
+    ///
+    /// Resets the stream decoder and annotates the trace with a warning event inside
+    /// the current span of context 0, so the gap is visible in the reconstructed trace.
+    pub fn report_loss(&mut self, lost: u64) {
+        self.reset();
+        self.report_issue(StreamIssue::FramesLost {
+            context: 0,
+            count: lost,
+        });
+
+        let config = &self.parent.config;
+        let metadata = callsite::event_metadata(
+            &config.target,
+            &config.target,
+            Level::WARN,
+            &["frames_lost"],
+        );
+        let message = format!("{} frames lost", lost);
+        self.in_dispatch(|this| {
+            let parent = this.span_stack(0).last().map(|open| &open.span);
+            callsite::dispatch_event(
+                metadata,
+                parent,
+                &[Some(&message.as_str()), None, None, None, Some(&lost)],
             );
-        } else {
-            info!(
-                target: "device_log",
-                code_filepath = file.as_str(),
-                code_lineno = line,
-                code_namespace = module.as_str(),
-                "{}",
-                message
+        });
+    }
+
+    /// Records that the transport was reconnected after `attempts` attempts and
+    /// `downtime` without it, as the sources do following their
+    /// [`ReconnectPolicy`](source::ReconnectPolicy).
+    ///
+    /// Resets the stream decoder and annotates the trace with a warning event inside
+    /// the current span of context 0, so the outage is visible in the reconstructed trace.
+    pub fn report_reconnect(&mut self, attempts: u32, downtime: Duration) {
+        self.reset();
+        self.report_issue(StreamIssue::Reconnected { attempts, downtime });
+
+        let config = &self.parent.config;
+        let metadata = callsite::event_metadata(
+            &config.target,
+            &config.target,
+            Level::WARN,
+            &["reconnect_attempts", "downtime_ms"],
+        );
+        let message = format!("reconnected after {:?} without transport", downtime);
+        let downtime_ms = downtime.as_millis() as u64;
+        self.in_dispatch(|this| {
+            let parent = this.span_stack(0).last().map(|open| &open.span);
+            callsite::dispatch_event(
+                metadata,
+                parent,
+                &[
+                    Some(&message.as_str()),
+                    None,
+                    None,
+                    None,
+                    Some(&attempts),
+                    Some(&downtime_ms),
+                ],
             );
+        });
+    }
+
+    /// Runs `f` with the decoder's own dispatch (if any) as the default subscriber.
+    fn in_dispatch<R>(&mut self, f: impl FnOnce(&mut Self) -> R) -> R {
+        match self.parent.dispatch.clone() {
+            Some(dispatch) => tracing::dispatcher::with_default(&dispatch, || f(self)),
+            None => f(self),
+        }
+    }
+
+    /// Classifies a frame, see [`TraceRecord::from_message`].
+    fn build_record(&mut self, context: u32, image: usize, frame: &Frame) -> TraceRecord {
+        let message = frame.display_message().to_string();
+        let location = self.parent.images[image]
+            .resolved
+            .get(frame.index())
+            .cloned();
+        let timestamp = self.frame_timestamp(context, frame);
+        let mut record = TraceRecord::from_message(
+            context,
+            frame.level().map(record::level_from_defmt),
+            message,
+            location,
+            timestamp,
+        );
+        if let Some(hash) = record::name_hash(&record) {
+            if let Some(name) = self.parent.images[image].span_names.get(&hash) {
+                record.message.clone_from(name);
+            }
+        }
+        let interned = self
+            .interned_fields
+            .entry((image, frame.index()))
+            .or_insert_with(|| record::interned_fields(frame));
+        record.interned_fields.clone_from(interned);
+        record
+    }
+
+    /// The device timestamp of `frame` in microseconds, see [`device_timestamp`](Self::device_timestamp).
+    fn frame_timestamp(&mut self, context: u32, frame: &Frame) -> Option<u64> {
+        use std::fmt::Write;
+
+        let mut rendered = std::mem::take(&mut self.rendered_timestamp);
+        rendered.clear();
+        write!(rendered, "{}", frame.display_timestamp()?).ok()?;
+        let timestamp = self.device_timestamp(context, &rendered);
+        self.rendered_timestamp = rendered;
+        timestamp
+    }
+
+    /// Converts a rendered device timestamp of `context` to microseconds, reporting
+    /// jumps the counter width cannot account for.
+    pub(crate) fn device_timestamp(&mut self, context: u32, rendered: &str) -> Option<u64> {
+        let timebase = self.parent.config.timebase;
+        let timestamp = self
+            .context_mut(context)
+            .timestamps
+            .convert(rendered, timebase.as_ref())?;
+        if let Some(from) = timestamp.jumped_from {
+            self.report_issue(StreamIssue::TimestampJump {
+                context,
+                from,
+                to: timestamp.micros,
+            });
         }
+        Some(timestamp.micros)
+    }
+
+    /// Assigns the record its span and parent ids from the span stack of its context.
+    fn place_record(&mut self, record: &mut TraceRecord) {
+        let span_stack = self.span_stack(record.context);
+        let current = span_stack.last().map(|open| open.id);
+        let enclosing = span_stack.iter().rev().nth(1).map(|open| open.id);
+        // An explicit `parent:` names the innermost open span of that name, or none.
+        let parent = match record::field(record, wire::PARENT_FIELD) {
+            Some(wire::NO_PARENT) => None,
+            Some(name) if !name.is_empty() => span_stack
+                .iter()
+                .rev()
+                .find(|open| record::base_span_name(&open.name) == name || open.name == name)
+                .map_or(current, |open| Some(open.id)),
+            _ => current,
+        };
 
-        eprintln!("{}", message);
+        match record.kind {
+            RecordKind::SpanEnter => {
+                record.span_id = Some(self.next_span_id);
+                record.parent_id = parent;
+                self.next_span_id += 1;
+            }
+            RecordKind::SpanExit => {
+                record.span_id = current;
+                record.parent_id = enclosing;
+                // Exits carry the static name only, or its hash; the instance suffix is
+                // the enter's.
+                if let Some(open) = span_stack.last() {
+                    let name = record::base_span_name(&open.name);
+                    if open.name != record.message
+                        && (name == record.message
+                            || record::name_hash(record) == Some(wire::name_hash(name)))
+                    {
+                        record.message.clone_from(&open.name);
+                    }
+                }
+            }
+            RecordKind::Event | RecordKind::Counter | RecordKind::Gauge => record.span_id = parent,
+        }
+    }
+
+    fn handle_record(&mut self, record: &mut TraceRecord) -> Result<(), Error> {
+        // The span an exit closes is gone afterwards, the one an enter opens only exists
+        // afterwards.
+        let exported = !self.exporters.is_empty();
+        let otel_ids = exported.then(|| self.otel_ids(record.context)).flatten();
+        let summary_only = self.parent.config.summary_interval.is_some();
+        match record.kind {
+            // The span stack still places the records.
+            RecordKind::SpanEnter if summary_only => self.update_span_stack(record, Span::none()),
+            RecordKind::SpanEnter => self.handle_span_enter(record),
+            RecordKind::Event if summary_only => {}
+            RecordKind::SpanExit => self.update_span_stack(record, Span::none()),
+            RecordKind::Event => {
+                if let Some(remote_parent) = propagation::parse_traceparent_frame(&record.message) {
+                    self.context_mut(record.context).remote_parent = remote_parent;
+                }
+                if let Some(cause) = record::parse_follows_from(&record.message) {
+                    self.link_follows_from(record.context, cause);
+                }
+                if !self.record_span_fields(record) {
+                    self.handle_log(record)
+                }
+            }
+            RecordKind::Gauge => self.observe_memory(record),
+            // Metrics have no tracing equivalent and only reach the exporters.
+            RecordKind::Counter => {}
+        }
+        if exported {
+            (record.trace_id, record.otel_span_id) =
+                otel_ids.or_else(|| self.otel_ids(record.context)).unzip();
+        }
+
+        for exporter in &mut self.exporters {
+            exporter.export(record)?;
+        }
+        Ok(())
+    }
+
+    /// Takes a memory gauge into the peaks of the open spans of its context, which become
+    /// their attributes, e.g. the lowest free heap while each ran.
+    fn observe_memory(&mut self, record: &TraceRecord) {
+        if !memory::is_memory_gauge(&record.message) {
+            return;
+        }
+        let Some(value) = record::field(record, "value").and_then(|value| value.parse().ok())
+        else {
+            return;
+        };
+        if let Some(state) = self.contexts.get_mut(&record.context) {
+            for open in &mut state.span_stack {
+                open.memory.observe(&record.message, value);
+            }
+        }
+    }
+
+    /// Trace and span id of the innermost open span of `context`, if it is exported to
+    /// OpenTelemetry.
+    fn otel_ids(&self, context: u32) -> Option<(String, String)> {
+        let open = self.span_stack(context).last()?;
+        let span_context = open.span.context().span().span_context().clone();
+        span_context.is_valid().then(|| {
+            (
+                span_context.trace_id().to_string(),
+                span_context.span_id().to_string(),
+            )
+        })
+    }
+
+    fn handle_span_enter(&mut self, record: &TraceRecord) {
+        let state = self.context_mut(record.context);
+        let boot = state.boot;
+        let session_id = format!("{:x}-{}-{}", state.boot_seen, record.context, boot);
+        let reset_reason = state.reset_reason.clone();
+        let config = &self.parent.config;
+        let name = record.message.as_str();
+        let (file, line, module) = self.location_attributes(record);
+        let level = record.level.unwrap_or(Level::INFO);
+
+        // Spans share a static tracing name; the device function name goes into `otel.name`,
+        // which tracing-opentelemetry uses as the exported span name.
+        let target = config.target(record);
+        // tracing-opentelemetry reads the span kind from the `otel.kind` field.
+        let kind = record::field(record, wire::KIND_FIELD).or_else(|| {
+            config
+                .span_kinds
+                .get(record::base_span_name(name))
+                .map(span_kind_name)
+        });
+        let metadata = callsite::span_metadata(target, &config.target, &config.span_name, level);
+        let parent_span = self.open_span(record.context, record.parent_id);
+        let span = callsite::new_span(
+            metadata,
+            parent_span,
+            &[
+                Some(&name),
+                Some(&name),
+                file.as_ref().map(|file| file as &dyn Value),
+                line.as_ref().map(|line| line as &dyn Value),
+                Some(&module),
+                kind.as_ref().map(|kind| kind as &dyn Value),
+            ],
+        );
+
+        span.set_attribute("thread.id", record.context as i64);
+        span.set_attribute("thread.name", format!("context {}", record.context));
+        span.set_attribute("boot.id", boot as i64);
+        span.set_attribute("session.id", session_id);
+        if let Some(reason) = reset_reason {
+            span.set_attribute(wire::RESET_REASON_FIELD, reason);
+        }
+        if target != config.target {
+            span.set_attribute("target", target.to_string());
+        }
+        for (key, value) in &record.fields {
+            if !record::is_placement_field(key) && key != wire::KIND_FIELD {
+                span.set_attribute(key.clone(), record.field_value(key, value));
+            }
+        }
+        if let Some(time) = record.wall_time {
+            clock::set_start_time(&span, time);
+        }
+        if parent_span.is_none() {
+            if let Some(remote_parent) = &self.context_mut(record.context).remote_parent {
+                span.set_parent(remote_parent.clone());
+            }
+        }
+
+        let span_context = span.context().span().span_context().clone();
+        if span_context.is_valid() {
+            self.recent_spans.insert(name.to_string(), span_context);
+        }
+
+        self.update_span_stack(record, span);
+    }
+
+    /// Links the innermost open span of `context` to the latest span called `cause`.
+    fn link_follows_from(&mut self, context: u32, cause: &str) {
+        let (Some(open), Some(cause)) = (
+            self.span_stack(context).last(),
+            self.recent_spans.get(cause),
+        ) else {
+            return;
+        };
+        if open.span.context().span().span_context() != cause {
+            open.span.add_link(cause.clone());
+        }
+    }
+
+    /// Sets the fields of a `span_record` frame on the innermost open span of its name in
+    /// its context. Returns whether `record` is such a frame, which is no event.
+    fn record_span_fields(&self, record: &TraceRecord) -> bool {
+        let Some((name, fields)) = record::parse_span_record(record) else {
+            return false;
+        };
+        let open = self
+            .span_stack(record.context)
+            .iter()
+            .rev()
+            .find(|open| record::base_span_name(&open.name) == name || open.name == name);
+        if let Some(open) = open {
+            for (key, value) in &fields {
+                if !record::is_placement_field(key) {
+                    open.span
+                        .set_attribute(key.clone(), record.field_value(key, value));
+                }
+            }
+        }
+        true
+    }
+
+    /// Keeps the stack of open spans in step with `record`.
+    ///
+    /// `span` is the tracing span opened for a `SpanEnter` record, and ignored otherwise.
+    fn update_span_stack(&mut self, record: &TraceRecord, span: Span) {
+        let span_stack = &mut self.context_mut(record.context).span_stack;
+        match record.kind {
+            RecordKind::SpanEnter => span_stack.push(OpenSpan {
+                id: record.span_id.unwrap_or_default(),
+                name: record.message.clone(),
+                span,
+                errors: 0,
+                events: [0; 5],
+                dropped: None,
+                memory: memory::MemoryPeaks::default(),
+            }),
+            RecordKind::SpanExit => {
+                if let Some(open) = span_stack.pop() {
+                    if let Some(time) = record.wall_time {
+                        clock::set_end_time(&open.span, time);
+                    }
+                    if record::field(record, record::CANCELLED_FIELD).is_some() {
+                        open.span.set_attribute(record::CANCELLED_FIELD, true);
+                        // An error logged in the span describes it better.
+                        if open.errors == 0 {
+                            let description = match record::field(record, wire::REASON_FIELD) {
+                                Some(reason) => format!("cancelled: {}", reason),
+                                None => "cancelled".to_string(),
+                            };
+                            open.span
+                                .set_status(opentelemetry::trace::Status::error(description));
+                        }
+                    }
+                    if open.name != record.message {
+                        log::debug!(
+                            "span_exit for {} while {} is the innermost span",
+                            record.message,
+                            open.name
+                        );
+                    }
+                }
+            }
+            RecordKind::Event | RecordKind::Counter | RecordKind::Gauge => {}
+        }
+    }
+
+    fn handle_log(&mut self, record: &TraceRecord) {
+        if self.drop_span_event(record) {
+            return;
+        }
+        let config = &self.parent.config;
+        let (file, line, module) = self.location_attributes(record);
+        let level = record.level.unwrap_or(Level::INFO);
+        let error = record::error_message(record);
+
+        // Fields named like a built-in one, or repeated, are left to the exporters.
+        let mut extra_fields: Vec<&str> = Vec::new();
+        let mut field_values = Vec::new();
+        for (key, value) in &record.fields {
+            if !callsite::EVENT_FIELDS.contains(&key.as_str())
+                && key != "exception.message"
+                && !record::is_placement_field(key)
+                && !extra_fields.contains(&key.as_str())
+            {
+                extra_fields.push(key);
+                field_values.push(record.field_value(key, value));
+            }
+        }
+        if error.is_some() {
+            extra_fields.push("exception.message");
+        }
+
+        let target = config.target(record);
+        let metadata = callsite::device_event_metadata(target, level, &extra_fields);
+        let parent_span = self.open_span(record.context, record.span_id);
+        let mut values: Vec<Option<&dyn Value>> = vec![
+            Some(&record.message),
+            file.as_ref().map(|file| file as &dyn Value),
+            line.as_ref().map(|line| line as &dyn Value),
+            Some(&module),
+        ];
+        // Past the callsite limit, the target and the fields go into a single field.
+        let overflow;
+        let metadata = match metadata {
+            Some(metadata) => {
+                values.extend(field_values.iter().map(|value| Some(value.as_value())));
+                if let Some(error) = &error {
+                    values.push(Some(error));
+                }
+                metadata
+            }
+            None => {
+                let mut pairs: Vec<String> = extra_fields
+                    .iter()
+                    .filter_map(|&key| Some(format!("{}={}", key, record::field(record, key)?)))
+                    .collect();
+                if target != config.target {
+                    pairs.insert(0, format!("{}={}", wire::TARGET_FIELD, target));
+                }
+                overflow = pairs.join(wire::FIELD_SEPARATOR);
+                values.push(Some(&overflow));
+                values.push(error.as_ref().map(|error| error as &dyn Value));
+                callsite::overflow_event_metadata(&config.target, level)
+            }
+        };
+        callsite::dispatch_event(metadata, parent_span, &values);
+        if let (Some(span), Some(time)) = (parent_span, record.wall_time) {
+            clock::set_last_event_time(span, time);
+        }
+        let description = config.error_description;
+        if let (Some(error), Some(id)) = (error, record.span_id) {
+            let span_stack = &mut self.context_mut(record.context).span_stack;
+            if let Some(open) = span_stack.iter_mut().rev().find(|open| open.id == id) {
+                open.errors += 1;
+                if open.errors == 1 || description == ErrorDescription::Last {
+                    open.span
+                        .set_status(opentelemetry::trace::Status::error(error));
+                }
+            }
+        }
+    }
+
+    /// Counts an event in its span, and returns whether it is past the limit of its
+    /// level, see [`TraceDecoderBuilder::with_max_span_events`].
+    fn drop_span_event(&mut self, record: &TraceRecord) -> bool {
+        let level = level_index(record.level.unwrap_or(Level::INFO));
+        let config = &self.parent.config;
+        let (Some(limit), Some(id)) = (config.max_span_events[level], record.span_id) else {
+            return false;
+        };
+        let Some(open) = self
+            .span_stack(record.context)
+            .iter()
+            .find(|open| open.id == id)
+        else {
+            return false;
+        };
+        // The metadata of the report is made with the first dropped event, while the
+        // config can still be borrowed.
+        let report = (open.events[level] >= limit && open.dropped.is_none()).then(|| {
+            let target = config.target(record);
+            callsite::event_metadata(target, &config.target, Level::WARN, &[DROPPED_EVENTS_FIELD])
+        });
+        let span_stack = &mut self.context_mut(record.context).span_stack;
+        let open = span_stack.iter_mut().find(|open| open.id == id).unwrap();
+        open.events[level] += 1;
+        if open.events[level] <= limit {
+            return false;
+        }
+        match &mut open.dropped {
+            Some((dropped, _)) => *dropped += 1,
+            None => open.dropped = report.map(|report| (1, report)),
+        }
+        self.stats.events_dropped += 1;
+        true
+    }
+
+    /// Returns `code.filepath`, `code.lineno` and `code.namespace` for a record. The
+    /// file and line are left out when the location is unknown.
+    fn location_attributes<'r>(
+        &'r self,
+        record: &'r TraceRecord,
+    ) -> (Option<&'r str>, Option<i64>, &'r str) {
+        match &record.location {
+            Some(loc) => (Some(&loc.file), Some(loc.line as i64), &loc.module),
+            None => (None, None, &self.parent.config.default_namespace),
+        }
     }
 }
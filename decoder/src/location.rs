@@ -0,0 +1,109 @@
+//! Looking up the location of a frame's log statement by its index.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use defmt_decoder::{Location, Locations};
+
+use crate::RecordLocation;
+
+/// Indices beyond this many entries per located statement are looked up in a map
+/// instead, so a table with a stray huge index does not allocate a huge vector.
+const MAX_DENSITY: u64 = 4;
+
+/// The locations of a firmware image as they go into records, resolved once rather than
+/// for every frame.
+///
+/// Frame indices are offsets into the `.defmt` section, so they are small and close
+/// together: a vector indexed by them is faster to look up than the map of
+/// `defmt-decoder`, which matters at high RTT rates.
+#[derive(Debug, Default)]
+pub(crate) enum LocationIndex {
+    #[default]
+    Empty,
+    Dense(Vec<Option<RecordLocation>>),
+    Sparse(HashMap<u64, RecordLocation>),
+}
+
+impl LocationIndex {
+    pub(crate) fn new(locations: &Locations) -> Self {
+        let Some(max) = locations.keys().next_back() else {
+            return Self::Empty;
+        };
+        let mut strings = Strings::default();
+        let resolved = locations
+            .iter()
+            .map(|(index, location)| (*index, strings.resolve(location)));
+        if *max <= locations.len() as u64 * MAX_DENSITY + 1024 {
+            let mut dense = vec![None; *max as usize + 1];
+            for (index, location) in resolved {
+                dense[index as usize] = Some(location);
+            }
+            Self::Dense(dense)
+        } else {
+            Self::Sparse(resolved.collect())
+        }
+    }
+
+    pub(crate) fn get(&self, index: u64) -> Option<&RecordLocation> {
+        match self {
+            Self::Empty => None,
+            Self::Dense(locations) => locations.get(index as usize)?.as_ref(),
+            Self::Sparse(locations) => locations.get(&index),
+        }
+    }
+}
+
+/// Rewrites the source paths of the location table, which are absolute paths on the
+/// build machine, see `TraceDecoderBuilder::with_path_prefix`.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct PathRemap(Vec<(String, String)>);
+
+impl PathRemap {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub(crate) fn push(&mut self, from: String, to: String) {
+        self.0.push((from, to));
+    }
+
+    /// `path` with the first matching prefix replaced, or `None` if none matches. A
+    /// prefix only matches whole path components, and an empty replacement makes the
+    /// path relative.
+    pub(crate) fn apply(&self, path: &str) -> Option<String> {
+        let is_separator = |c: char| c == '/' || c == '\\';
+        self.0.iter().find_map(|(from, to)| {
+            let rest = path.strip_prefix(from.trim_end_matches(is_separator))?;
+            if !rest.is_empty() && !rest.starts_with(is_separator) {
+                return None;
+            }
+            Some(match to.trim_end_matches(is_separator) {
+                "" => rest.trim_start_matches(is_separator).to_string(),
+                to => format!("{}{}", to, rest),
+            })
+        })
+    }
+}
+
+/// Shares the file and module strings between the locations of a firmware image, since
+/// many log statements have the same ones.
+#[derive(Debug, Default)]
+pub(crate) struct Strings(HashMap<String, Arc<str>>);
+
+impl Strings {
+    pub(crate) fn resolve(&mut self, location: &Location) -> RecordLocation {
+        RecordLocation {
+            file: self.intern(location.file.display().to_string()),
+            line: location.line,
+            module: self.intern(location.module.clone()),
+        }
+    }
+
+    fn intern(&mut self, value: String) -> Arc<str> {
+        self.0
+            .entry(value)
+            .or_insert_with_key(|value| value.as_str().into())
+            .clone()
+    }
+}
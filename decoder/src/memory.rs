@@ -0,0 +1,60 @@
+//! The memory gauges logged by the facade's `memory` module, tied to the spans they were
+//! measured in.
+
+use tracing::Span;
+use tracing_defmt_wire as wire;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// The span attribute each memory gauge sets on the spans open when it is measured, and
+/// whether the attribute keeps the lowest value seen while the span ran, or the highest.
+const SPAN_ATTRIBUTES: [(&str, &str, Extreme); 3] = [
+    (wire::HEAP_FREE_GAUGE, "memory.heap.free.min", Extreme::Min),
+    (wire::HEAP_USED_GAUGE, "memory.heap.used.max", Extreme::Max),
+    (
+        wire::STACK_HIGH_WATER_GAUGE,
+        "memory.stack.high_water",
+        Extreme::Max,
+    ),
+];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Extreme {
+    Min,
+    Max,
+}
+
+/// Whether `name` is one of the memory gauges.
+pub(crate) fn is_memory_gauge(name: &str) -> bool {
+    SPAN_ATTRIBUTES.iter().any(|(gauge, _, _)| *gauge == name)
+}
+
+/// The extremes of the memory gauges measured while a span was open.
+#[derive(Debug, Default)]
+pub(crate) struct MemoryPeaks(Vec<(&'static str, Extreme, i64)>);
+
+impl MemoryPeaks {
+    /// Takes in the `value` of gauge `name`, if it is a memory gauge.
+    pub(crate) fn observe(&mut self, name: &str, value: i64) {
+        let Some(&(_, attribute, extreme)) =
+            SPAN_ATTRIBUTES.iter().find(|(gauge, _, _)| *gauge == name)
+        else {
+            return;
+        };
+        match self.0.iter_mut().find(|(key, _, _)| *key == attribute) {
+            Some((_, _, peak)) => {
+                *peak = match extreme {
+                    Extreme::Min => (*peak).min(value),
+                    Extreme::Max => (*peak).max(value),
+                }
+            }
+            None => self.0.push((attribute, extreme, value)),
+        }
+    }
+
+    /// Sets the extremes as attributes of `span`.
+    pub(crate) fn record(&self, span: &Span) {
+        for &(attribute, _, value) in &self.0 {
+            span.set_attribute(attribute, value);
+        }
+    }
+}
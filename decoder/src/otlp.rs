@@ -0,0 +1,342 @@
+//! Built-in OTLP export pipeline.
+//!
+//! Owns a small tokio runtime for the exporters, the batch span and log processors and
+//! the periodic metric reader, so synchronous host tools don't need to set up an async
+//! runtime themselves.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+#[cfg(feature = "logs")]
+use opentelemetry::logs::LoggerProvider as _;
+#[cfg(feature = "metrics")]
+use opentelemetry::metrics::{Meter, MeterProvider as _};
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::KeyValue;
+#[cfg(feature = "logs")]
+use opentelemetry_otlp::LogExporter;
+#[cfg(feature = "metrics")]
+use opentelemetry_otlp::MetricExporter;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+#[cfg(feature = "logs")]
+use opentelemetry_sdk::logs::{Logger, LoggerProvider};
+#[cfg(feature = "metrics")]
+use opentelemetry_sdk::metrics::{PeriodicReader, SdkMeterProvider, Temporality};
+use opentelemetry_sdk::trace::{BatchConfigBuilder, BatchSpanProcessor, TracerProvider};
+use opentelemetry_sdk::{runtime, Resource};
+use tracing::Dispatch;
+use tracing_subscriber::layer::SubscriberExt;
+
+use crate::Error;
+
+const INSTRUMENTATION_NAME: &str = "tracing-defmt-decoder";
+
+/// Wire protocol used to talk to the OTLP collector.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OtlpProtocol {
+    /// OTLP/gRPC, usually on port 4317.
+    #[cfg(feature = "otlp")]
+    Grpc,
+    /// OTLP/HTTP with protobuf bodies, usually on port 4318.
+    #[cfg(feature = "otlp-http")]
+    HttpProtobuf,
+    /// OTLP/HTTP with JSON bodies, usually on port 4318.
+    #[cfg(feature = "otlp-http")]
+    HttpJson,
+}
+
+impl Default for OtlpProtocol {
+    fn default() -> Self {
+        #[cfg(feature = "otlp")]
+        return OtlpProtocol::Grpc;
+        #[cfg(not(feature = "otlp"))]
+        return OtlpProtocol::HttpProtobuf;
+    }
+}
+
+/// Exporter settings collected by the [`TraceDecoderBuilder`](crate::TraceDecoderBuilder).
+///
+/// An empty endpoint falls back to the `OTEL_EXPORTER_OTLP_*` environment variables
+/// and then to the collector's default local address.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct OtlpConfig {
+    pub(crate) endpoint: String,
+    pub(crate) protocol: OtlpProtocol,
+    pub(crate) headers: HashMap<String, String>,
+    /// Batch span processor settings; unset ones come from the `OTEL_BSP_*` environment
+    /// variables or the SDK defaults.
+    pub(crate) max_queue_size: Option<usize>,
+    pub(crate) max_export_batch_size: Option<usize>,
+    pub(crate) export_interval: Option<Duration>,
+    pub(crate) export_timeout: Option<Duration>,
+    // PEM encoded root certificates trusted in addition to the system roots.
+    #[cfg(feature = "otlp-http")]
+    pub(crate) ca_certificates: Vec<Vec<u8>>,
+    #[cfg(feature = "otlp-http")]
+    pub(crate) accept_invalid_certs: bool,
+    /// Export interval of device metrics; `None` disables metrics export.
+    #[cfg(feature = "metrics")]
+    pub(crate) metrics_interval: Option<Duration>,
+    #[cfg(feature = "metrics")]
+    pub(crate) metrics_temporality: Temporality,
+    /// Whether device log events are exported as log records.
+    #[cfg(feature = "logs")]
+    pub(crate) logs: bool,
+}
+
+pub(crate) struct OtlpPipeline {
+    provider: TracerProvider,
+    #[cfg(feature = "metrics")]
+    meter_provider: Option<SdkMeterProvider>,
+    #[cfg(feature = "logs")]
+    logger_provider: Option<LoggerProvider>,
+    dispatch: Dispatch,
+    shut_down: bool,
+    // Kept last so it outlives the provider during shutdown.
+    runtime: tokio::runtime::Runtime,
+}
+
+impl OtlpPipeline {
+    pub(crate) fn new(config: &OtlpConfig, resource: Vec<KeyValue>) -> Result<Self, Error> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("tracing-defmt-otlp")
+            .enable_all()
+            .build()?;
+
+        // The exporter clients and the batch processor both spawn onto the current runtime.
+        let _guard = runtime.enter();
+
+        let exporter = build_exporter(config).map_err(|e| Error::Exporter(e.to_string()))?;
+
+        #[cfg(feature = "metrics")]
+        let meter_provider = match config.metrics_interval {
+            Some(interval) => {
+                let exporter =
+                    build_metric_exporter(config).map_err(|e| Error::Exporter(e.to_string()))?;
+                let reader = PeriodicReader::builder(exporter, runtime::Tokio)
+                    .with_interval(interval)
+                    .build();
+                Some(
+                    SdkMeterProvider::builder()
+                        .with_reader(reader)
+                        .with_resource(Resource::new(resource.clone()))
+                        .build(),
+                )
+            }
+            None => None,
+        };
+
+        #[cfg(feature = "logs")]
+        let logger_provider = if config.logs {
+            let exporter =
+                build_log_exporter(config).map_err(|e| Error::Exporter(e.to_string()))?;
+            Some(
+                LoggerProvider::builder()
+                    .with_batch_exporter(exporter, runtime::Tokio)
+                    .with_resource(Resource::new(resource.clone()))
+                    .build(),
+            )
+        } else {
+            None
+        };
+
+        let processor = BatchSpanProcessor::builder(exporter, runtime::Tokio)
+            .with_batch_config(batch_config(config))
+            .build();
+        let provider = TracerProvider::builder()
+            .with_span_processor(processor)
+            .with_resource(Resource::new(resource))
+            .build();
+
+        let tracer = provider.tracer(INSTRUMENTATION_NAME);
+        let subscriber =
+            tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(tracer));
+
+        Ok(Self {
+            provider,
+            #[cfg(feature = "metrics")]
+            meter_provider,
+            #[cfg(feature = "logs")]
+            logger_provider,
+            dispatch: Dispatch::new(subscriber),
+            shut_down: false,
+            runtime,
+        })
+    }
+
+    pub(crate) fn dispatch(&self) -> &Dispatch {
+        &self.dispatch
+    }
+
+    /// Flushes all batched spans, metrics and logs and stops the exporters. Later calls do nothing.
+    pub(crate) fn shutdown(&mut self) -> Result<(), Error> {
+        if std::mem::replace(&mut self.shut_down, true) {
+            return Ok(());
+        }
+        let _guard = self.runtime.enter();
+        let traces = self.provider.shutdown();
+        #[cfg(feature = "metrics")]
+        if let Some(Err(e)) = self.meter_provider.as_ref().map(|p| p.shutdown()) {
+            return Err(Error::Exporter(format!(
+                "OTLP metrics exporter shutdown failed: {}",
+                e
+            )));
+        }
+        #[cfg(feature = "logs")]
+        if let Some(Err(e)) = self.logger_provider.as_ref().map(|p| p.shutdown()) {
+            return Err(Error::Exporter(format!(
+                "OTLP logs exporter shutdown failed: {}",
+                e
+            )));
+        }
+        traces.map_err(|e| Error::Exporter(format!("OTLP exporter shutdown failed: {}", e)))
+    }
+
+    /// The meter exporting to the collector, if metrics export is enabled.
+    #[cfg(feature = "metrics")]
+    pub(crate) fn meter(&self) -> Option<Meter> {
+        self.meter_provider
+            .as_ref()
+            .map(|provider| provider.meter(INSTRUMENTATION_NAME))
+    }
+
+    /// The logger exporting to the collector, if logs export is enabled.
+    #[cfg(feature = "logs")]
+    pub(crate) fn logger(&self) -> Option<Logger> {
+        self.logger_provider
+            .as_ref()
+            .map(|provider| provider.logger(INSTRUMENTATION_NAME))
+    }
+}
+
+impl Drop for OtlpPipeline {
+    fn drop(&mut self) {
+        if let Err(e) = self.shutdown() {
+            log::warn!("{}", e);
+        }
+    }
+}
+
+type BuildError = Box<dyn std::error::Error + Send + Sync>;
+
+fn batch_config(config: &OtlpConfig) -> opentelemetry_sdk::trace::BatchConfig {
+    let mut builder = BatchConfigBuilder::default();
+    if let Some(size) = config.max_queue_size {
+        builder = builder.with_max_queue_size(size);
+    }
+    if let Some(size) = config.max_export_batch_size {
+        builder = builder.with_max_export_batch_size(size);
+    }
+    if let Some(interval) = config.export_interval {
+        builder = builder.with_scheduled_delay(interval);
+    }
+    if let Some(timeout) = config.export_timeout {
+        builder = builder.with_max_export_timeout(timeout);
+    }
+    builder.build()
+}
+
+fn build_exporter(config: &OtlpConfig) -> Result<SpanExporter, BuildError> {
+    match config.protocol {
+        #[cfg(feature = "otlp")]
+        OtlpProtocol::Grpc => {
+            Ok(with_tonic_config(SpanExporter::builder().with_tonic(), config)?.build()?)
+        }
+        #[cfg(feature = "otlp-http")]
+        OtlpProtocol::HttpProtobuf | OtlpProtocol::HttpJson => {
+            Ok(with_http_config(SpanExporter::builder().with_http(), config)?.build()?)
+        }
+    }
+}
+
+#[cfg(feature = "metrics")]
+fn build_metric_exporter(config: &OtlpConfig) -> Result<MetricExporter, BuildError> {
+    let builder = MetricExporter::builder();
+    match config.protocol {
+        #[cfg(feature = "otlp")]
+        OtlpProtocol::Grpc => {
+            let builder = builder
+                .with_tonic()
+                .with_temporality(config.metrics_temporality);
+            Ok(with_tonic_config(builder, config)?.build()?)
+        }
+        #[cfg(feature = "otlp-http")]
+        OtlpProtocol::HttpProtobuf | OtlpProtocol::HttpJson => {
+            let builder = builder
+                .with_http()
+                .with_temporality(config.metrics_temporality);
+            Ok(with_http_config(builder, config)?.build()?)
+        }
+    }
+}
+
+#[cfg(feature = "logs")]
+fn build_log_exporter(config: &OtlpConfig) -> Result<LogExporter, BuildError> {
+    match config.protocol {
+        #[cfg(feature = "otlp")]
+        OtlpProtocol::Grpc => {
+            Ok(with_tonic_config(LogExporter::builder().with_tonic(), config)?.build()?)
+        }
+        #[cfg(feature = "otlp-http")]
+        OtlpProtocol::HttpProtobuf | OtlpProtocol::HttpJson => {
+            Ok(with_http_config(LogExporter::builder().with_http(), config)?.build()?)
+        }
+    }
+}
+
+/// Applies endpoint and headers to a span, metric or log exporter builder.
+#[cfg(feature = "otlp")]
+fn with_tonic_config<B>(mut builder: B, config: &OtlpConfig) -> Result<B, BuildError>
+where
+    B: opentelemetry_otlp::WithTonicConfig + WithExportConfig,
+{
+    use tonic::metadata::{MetadataKey, MetadataMap, MetadataValue};
+
+    let mut metadata = MetadataMap::new();
+    for (key, value) in &config.headers {
+        metadata.insert(
+            MetadataKey::from_bytes(key.as_bytes())?,
+            MetadataValue::try_from(value.as_str())?,
+        );
+    }
+
+    builder = builder.with_metadata(metadata);
+    if !config.endpoint.is_empty() {
+        builder = builder.with_endpoint(&config.endpoint);
+    }
+    Ok(builder)
+}
+
+/// Applies protocol, TLS settings, endpoint and headers to a span, metric or log exporter
+/// builder.
+#[cfg(feature = "otlp-http")]
+fn with_http_config<B>(builder: B, config: &OtlpConfig) -> Result<B, BuildError>
+where
+    B: opentelemetry_otlp::WithHttpConfig + WithExportConfig,
+{
+    use opentelemetry_otlp::Protocol;
+
+    let protocol = match config.protocol {
+        OtlpProtocol::HttpJson => Protocol::HttpJson,
+        _ => Protocol::HttpBinary,
+    };
+
+    let mut client = reqwest::Client::builder();
+    for pem in &config.ca_certificates {
+        client = client.add_root_certificate(reqwest::Certificate::from_pem(pem)?);
+    }
+    let client = client
+        .danger_accept_invalid_certs(config.accept_invalid_certs)
+        .build()?;
+
+    let mut builder = builder.with_protocol(protocol).with_http_client(client);
+    if !config.endpoint.is_empty() {
+        builder = builder.with_endpoint(&config.endpoint);
+    }
+    // `with_headers` only keeps the first entry of each map it is given.
+    for (key, value) in &config.headers {
+        builder = builder.with_headers(HashMap::from([(key.clone(), value.clone())]));
+    }
+    Ok(builder)
+}
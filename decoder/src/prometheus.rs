@@ -0,0 +1,215 @@
+//! Prometheus endpoint for the health of the decoder itself.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::StreamStats;
+
+/// Serves the health of decoding streams in the Prometheus text format, for monitoring
+/// long-running decoder gateways.
+///
+/// Streams report to it once registered with
+/// [`TraceStream::report_health`](crate::TraceStream::report_health), after every call
+/// that processes data. The totals of [`StreamStats`] are counters (use `rate()` for
+/// frames or bytes per second); the depth of the span stack of each context and the
+/// records queued in exporters are gauges. Every metric has a `stream` label.
+///
+/// # Example
+/// ```rust,ignore
+/// let health = HealthEndpoint::serve("0.0.0.0:9464")?;
+/// let mut stream = decoder.new_stream();
+/// stream.report_health(&health, "board-7");
+/// ```
+#[derive(Clone, Debug)]
+pub struct HealthEndpoint {
+    streams: Arc<Mutex<BTreeMap<String, Snapshot>>>,
+    address: Option<SocketAddr>,
+}
+
+/// The health of one stream, as of its last report.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Snapshot {
+    pub(crate) stats: StreamStats,
+    /// Number of open spans per context.
+    pub(crate) open_spans: BTreeMap<u32, usize>,
+    /// Records waiting in exporters.
+    pub(crate) queued: usize,
+}
+
+/// Name, help text and value of a counter.
+type Counter = (&'static str, &'static str, fn(&StreamStats) -> u64);
+
+const COUNTERS: &[Counter] = &[
+    ("bytes_received", "Bytes received", |s| s.bytes_received),
+    ("frames_decoded", "Frames decoded", |s| s.frames_decoded),
+    ("malformed", "Decoder resets due to malformed data", |s| {
+        s.malformed
+    }),
+    ("bytes_discarded", "Bytes dropped as malformed", |s| {
+        s.bytes_discarded
+    }),
+    (
+        "frames_lost",
+        "Frames reported lost by the transport",
+        |s| s.frames_lost,
+    ),
+    ("spans_truncated", "Spans closed without exit frame", |s| {
+        s.spans_truncated
+    }),
+    ("reboots", "Device reboots", |s| s.reboots),
+    ("reconnects", "Transport reconnects", |s| s.reconnects),
+    (
+        "events_dropped",
+        "Events left out of spans past their limit",
+        |s| s.events_dropped,
+    ),
+    (
+        "records_discarded",
+        "Records discarded without an export trigger",
+        |s| s.records_discarded,
+    ),
+];
+
+impl HealthEndpoint {
+    /// Serves `GET /metrics` on `address`, e.g. `0.0.0.0:9464`, from a background thread.
+    pub fn serve(address: impl ToSocketAddrs) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        let endpoint = Self {
+            streams: Default::default(),
+            address: Some(listener.local_addr()?),
+        };
+        let served = endpoint.clone();
+        thread::spawn(move || {
+            for connection in listener.incoming() {
+                let result = connection.and_then(|connection| served.respond(connection));
+                if let Err(e) = result {
+                    log::debug!("health endpoint: {}", e);
+                }
+            }
+        });
+        Ok(endpoint)
+    }
+
+    /// Collects reports without serving them, for [`render`](Self::render)ing into an
+    /// existing HTTP server.
+    pub fn new() -> Self {
+        Self {
+            streams: Default::default(),
+            address: None,
+        }
+    }
+
+    /// The address [`serve`](Self::serve) listens on, e.g. to learn the port picked for `:0`.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.address
+    }
+
+    /// The current metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let streams = self.streams.lock().unwrap();
+        let mut out = String::new();
+        for (name, help, value) in COUNTERS {
+            let _ = writeln!(out, "# HELP tracing_defmt_{}_total {}", name, help);
+            let _ = writeln!(out, "# TYPE tracing_defmt_{}_total counter", name);
+            for (stream, snapshot) in streams.iter() {
+                let _ = writeln!(
+                    out,
+                    "tracing_defmt_{}_total{{stream=\"{}\"}} {}",
+                    name,
+                    escape(stream),
+                    value(&snapshot.stats)
+                );
+            }
+        }
+
+        out.push_str("# HELP tracing_defmt_resets_total Device boots by reset reason\n");
+        out.push_str("# TYPE tracing_defmt_resets_total counter\n");
+        for (stream, snapshot) in streams.iter() {
+            for (reason, count) in &snapshot.stats.resets {
+                let _ = writeln!(
+                    out,
+                    "tracing_defmt_resets_total{{stream=\"{}\",reason=\"{}\"}} {}",
+                    escape(stream),
+                    escape(reason),
+                    count
+                );
+            }
+        }
+
+        out.push_str("# HELP tracing_defmt_open_spans Depth of the span stack\n");
+        out.push_str("# TYPE tracing_defmt_open_spans gauge\n");
+        for (stream, snapshot) in streams.iter() {
+            for (context, depth) in &snapshot.open_spans {
+                let _ = writeln!(
+                    out,
+                    "tracing_defmt_open_spans{{stream=\"{}\",context=\"{}\"}} {}",
+                    escape(stream),
+                    context,
+                    depth
+                );
+            }
+        }
+
+        out.push_str("# HELP tracing_defmt_exporter_queue Records waiting in exporters\n");
+        out.push_str("# TYPE tracing_defmt_exporter_queue gauge\n");
+        for (stream, snapshot) in streams.iter() {
+            let _ = writeln!(
+                out,
+                "tracing_defmt_exporter_queue{{stream=\"{}\"}} {}",
+                escape(stream),
+                snapshot.queued
+            );
+        }
+        out
+    }
+
+    pub(crate) fn update(&self, stream: &str, snapshot: Snapshot) {
+        self.streams
+            .lock()
+            .unwrap()
+            .insert(stream.to_string(), snapshot);
+    }
+
+    fn respond(&self, mut connection: TcpStream) -> io::Result<()> {
+        let mut reader = BufReader::new(&connection);
+        let mut request = String::new();
+        reader.read_line(&mut request)?;
+        // Skip the headers.
+        let mut header = String::new();
+        while reader.read_line(&mut header)? > 2 {
+            header.clear();
+        }
+
+        let path = request.split_whitespace().nth(1).unwrap_or("");
+        let response = if path == "/metrics" || path.starts_with("/metrics?") {
+            let body = self.render();
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\n\
+                 Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+        } else {
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+        };
+        connection.write_all(response.as_bytes())
+    }
+}
+
+impl Default for HealthEndpoint {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Escapes a label value.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
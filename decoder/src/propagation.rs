@@ -0,0 +1,47 @@
+//! Remote trace context announced by the device.
+
+use opentelemetry::trace::{SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState};
+use opentelemetry::Context;
+
+/// Parses the payload of a `traceparent: ` frame.
+///
+/// Returns `Some(None)` for a frame that clears the remote context (any value that is
+/// not a valid W3C `traceparent` header, e.g. `none`) and `None` for other frames.
+pub(crate) fn parse_traceparent_frame(message: &str) -> Option<Option<Context>> {
+    let value = message.strip_prefix(tracing_defmt_wire::TRACEPARENT)?;
+    Some(
+        parse_traceparent(value.trim())
+            .map(|span_context| Context::new().with_remote_span_context(span_context)),
+    )
+}
+
+/// Parses a W3C `traceparent` header value, e.g.
+/// `00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01`.
+fn parse_traceparent(value: &str) -> Option<SpanContext> {
+    let mut parts = value.split('-');
+    let version = parts.next()?;
+    let trace_id = parts.next()?;
+    let span_id = parts.next()?;
+    let flags = parts.next()?;
+    // Later versions may append fields, version 00 must not.
+    if version.len() != 2 || version == "ff" || (version == "00" && parts.next().is_some()) {
+        return None;
+    }
+    u8::from_str_radix(version, 16).ok()?;
+
+    let is_lower_hex = |s: &str, len: usize| {
+        s.len() == len && s.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+    };
+    if !is_lower_hex(trace_id, 32) || !is_lower_hex(span_id, 16) || !is_lower_hex(flags, 2) {
+        return None;
+    }
+
+    let span_context = SpanContext::new(
+        TraceId::from_hex(trace_id).ok()?,
+        SpanId::from_hex(span_id).ok()?,
+        TraceFlags::new(u8::from_str_radix(flags, 16).ok()? & TraceFlags::SAMPLED.to_u8()),
+        true,
+        TraceState::default(),
+    );
+    span_context.is_valid().then_some(span_context)
+}
@@ -0,0 +1,521 @@
+//! Decoded, transport-independent view of a device frame.
+
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use defmt_decoder::{Frame, Location};
+use defmt_parser::{Fragment, Type};
+use tracing::Level;
+use tracing_defmt_wire as wire;
+
+/// What a decoded frame means for the reconstructed trace.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RecordKind {
+    /// A `span_enter` frame emitted on entry of an instrumented function.
+    SpanEnter,
+    /// A `span_exit` frame emitted when the instrumented function returns.
+    SpanExit,
+    /// A regular log event.
+    Event,
+    /// A `counter: name={value}` frame: `value` is added to the counter `name`.
+    Counter,
+    /// A `gauge: name={value}` frame: the gauge `name` is set to `value`.
+    Gauge,
+}
+
+/// Source location of the log statement that produced a frame.
+///
+/// The strings are shared between the records of a log statement, so cloning is cheap.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecordLocation {
+    pub file: Arc<str>,
+    pub line: u64,
+    pub module: Arc<str>,
+}
+
+/// One decoded frame, classified and annotated with its place in the span tree.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TraceRecord {
+    pub kind: RecordKind,
+    /// Device log level. `None` for `println!` frames.
+    pub level: Option<Level>,
+    /// The span name for enter/exit records, the metric name for counters and gauges,
+    /// the formatted message for events.
+    pub message: String,
+    /// Key/value pairs, e.g. the arguments recorded by `#[instrument]` or the fields of
+    /// an event (see [`with_field_extraction`](crate::TraceDecoderBuilder::with_field_extraction)).
+    /// Metric records start with a `value` field, followed by the metric's attributes.
+    pub fields: Vec<(String, String)>,
+    pub location: Option<RecordLocation>,
+    /// Device timestamp in microseconds, if the firmware defines `defmt::timestamp!`.
+    pub timestamp: Option<u64>,
+    /// Host wall-clock time of `timestamp`, when wall-clock anchoring is enabled.
+    pub wall_time: Option<SystemTime>,
+    /// The span opened or closed by this record, or the enclosing span of an event.
+    pub span_id: Option<u64>,
+    /// The parent of `span_id`.
+    pub parent_id: Option<u64>,
+    /// The execution context (core, thread) that emitted the frame.
+    pub context: u32,
+    /// OpenTelemetry trace id (hex) of the span, or of the enclosing span of an event.
+    /// Only known to exporters of records emitted through a `tracing-opentelemetry`
+    /// subscriber, e.g. to correlate logs with traces.
+    pub trace_id: Option<String>,
+    /// OpenTelemetry span id (hex) that goes with `trace_id`.
+    pub otel_span_id: Option<String>,
+    /// Keys of the `fields` sent as interned strings (`{=istr}`), e.g. the states of a
+    /// state machine. Their values are exported as strings, whatever their text looks like.
+    pub interned_fields: Vec<String>,
+}
+
+/// A frame as it came off the wire, see [`TraceStream::on_frame`](crate::TraceStream::on_frame).
+#[derive(Debug)]
+pub struct RawFrame<'f> {
+    pub context: u32,
+    /// The encoded bytes of the frame, including the delimiter of the rzCOBS encoding.
+    pub bytes: &'f [u8],
+    pub frame: &'f Frame<'f>,
+    /// Where the log statement is, if the ELF has debug info for it.
+    pub location: Option<&'f Location>,
+}
+
+type Fields = Vec<(String, String)>;
+
+impl TraceRecord {
+    /// Classifies a decoded log message. Its place in the span tree is decided later.
+    pub(crate) fn from_message(
+        context: u32,
+        level: Option<Level>,
+        mut message: String,
+        location: Option<RecordLocation>,
+        timestamp: Option<u64>,
+    ) -> Self {
+        let mut record = TraceRecord {
+            kind: RecordKind::Event,
+            level,
+            message: String::new(),
+            fields: Vec::new(),
+            location,
+            timestamp,
+            wall_time: None,
+            span_id: None,
+            parent_id: None,
+            context,
+            trace_id: None,
+            otel_span_id: None,
+            interned_fields: Vec::new(),
+        };
+
+        // Span names are cut out of `message` in place, which saves an allocation per
+        // span record.
+        if let Some(payload) = message.strip_prefix(wire::SPAN_ENTER) {
+            let (name, fields) = parse_span_enter(payload);
+            record.kind = RecordKind::SpanEnter;
+            record.fields = fields;
+            message.truncate(wire::SPAN_ENTER.len() + name.len());
+            message.replace_range(..wire::SPAN_ENTER.len(), "");
+            if let Some((_, instance)) = record
+                .fields
+                .iter()
+                .find(|(key, _)| key == wire::INSTANCE_FIELD || key == wire::NAME_SUFFIX_FIELD)
+            {
+                message.push('[');
+                message.push_str(instance);
+                message.push(']');
+            }
+            record.message = message;
+        } else if let Some(payload) = message.strip_prefix(wire::SPAN_CANCEL) {
+            // An exit that marks the span cancelled.
+            let (name, fields) = match payload.split_once(wire::FIELD_SEPARATOR) {
+                Some((name, fields)) => (name, parse_fields(fields)),
+                None => (payload, Vec::new()),
+            };
+            record.kind = RecordKind::SpanExit;
+            record
+                .fields
+                .push((CANCELLED_FIELD.to_string(), "true".to_string()));
+            record.fields.extend(fields);
+            record.message = name.to_string();
+        } else if message.starts_with(wire::SPAN_EXIT) {
+            record.kind = RecordKind::SpanExit;
+            message.replace_range(..wire::SPAN_EXIT.len(), "");
+            record.message = message;
+        } else if let Some((kind, name, fields)) = parse_metric(&message) {
+            record.kind = kind;
+            record.message = name;
+            record.fields = fields;
+        } else {
+            record.message = message;
+        }
+
+        record
+    }
+
+    /// The typed value of field `key`: a string for interned fields, else inferred from
+    /// the text.
+    pub(crate) fn field_value<'v>(&self, key: &str, value: &'v str) -> FieldValue<'v> {
+        if self.interned_fields.iter().any(|interned| interned == key) {
+            FieldValue::Str(value)
+        } else {
+            FieldValue::infer(value)
+        }
+    }
+}
+
+/// Keys of the `key={=istr}` parameters of `frame`, which the facade's macros emit for
+/// fields given as `defmt::intern!(..)`.
+pub(crate) fn interned_fields(frame: &Frame) -> Vec<String> {
+    frame
+        .fragments()
+        .windows(2)
+        .filter_map(|pair| match pair {
+            [Fragment::Literal(literal), Fragment::Parameter(parameter)]
+                if parameter.ty == Type::IStr =>
+            {
+                // The key follows the field separator, or the `(` of a span's fields.
+                let key = literal.strip_suffix(wire::KEY_VALUE_SEPARATOR)?;
+                let key = key.rsplit([' ', ',', '(']).next()?;
+                (!key.is_empty()).then(|| key.to_string())
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+pub(crate) fn level_from_defmt(level: defmt_parser::Level) -> Level {
+    match level {
+        defmt_parser::Level::Trace => Level::TRACE,
+        defmt_parser::Level::Debug => Level::DEBUG,
+        defmt_parser::Level::Info => Level::INFO,
+        defmt_parser::Level::Warn => Level::WARN,
+        defmt_parser::Level::Error => Level::ERROR,
+    }
+}
+
+/// Splits a `span_enter` payload such as `my_fn(x=1, y=2)` into the span name and its fields.
+pub(crate) fn parse_span_enter(payload: &str) -> (&str, Vec<(String, String)>) {
+    // Older firmware appended "; file=..." to the payload.
+    let payload = match payload.find("; file=") {
+        Some(idx) => &payload[..idx],
+        None => payload,
+    };
+
+    match payload.find(wire::FIELDS_START) {
+        Some(idx) if payload.ends_with(wire::FIELDS_END) => {
+            let args = &payload[idx + 1..payload.len() - 1];
+            (&payload[..idx], parse_fields(args))
+        }
+        _ => (payload, Vec::new()),
+    }
+}
+
+/// The name of a span without the instance suffix the decoder appended to it, see
+/// [`wire::INSTANCE_FIELD`]: `uart_task` for `uart_task[2]`.
+pub(crate) fn base_span_name(name: &str) -> &str {
+    match name.find('[') {
+        Some(idx) if name.ends_with(']') => &name[..idx],
+        _ => name,
+    }
+}
+
+/// The value of the field `key` of `record`.
+pub(crate) fn field<'r>(record: &'r TraceRecord, key: &str) -> Option<&'r str> {
+    record
+        .fields
+        .iter()
+        .find(|(k, _)| k == key)
+        .map(|(_, value)| value.as_str())
+}
+
+/// Whether `key` is one of the fields that say where a record goes rather than
+/// describing it: `target` and `parent`.
+pub(crate) fn is_placement_field(key: &str) -> bool {
+    key == wire::TARGET_FIELD || key == wire::PARENT_FIELD
+}
+
+/// The hash a `span_exit` record carries instead of its name, see [`wire::NAME_HASH`].
+pub(crate) fn name_hash(record: &TraceRecord) -> Option<u32> {
+    if record.kind != RecordKind::SpanExit {
+        return None;
+    }
+    let hash = record.message.strip_prefix(wire::NAME_HASH)?;
+    u32::from_str_radix(hash, 16).ok()
+}
+
+/// Returns the error described by an event: the value of its `error` field, or its
+/// message for error-level events.
+pub(crate) fn error_message(record: &TraceRecord) -> Option<String> {
+    let field = record
+        .fields
+        .iter()
+        .find(|(key, _)| key == wire::ERROR_FIELD)
+        .map(|(_, value)| value.clone())
+        .or_else(|| error_in_message(&record.message));
+    match (field, record.level) {
+        (Some(error), _) => Some(error),
+        (None, Some(Level::ERROR)) => Some(record.message.clone()),
+        (None, _) => None,
+    }
+}
+
+/// Finds an `error=value` pair in a log message, e.g. `write failed, error=Timeout`.
+fn error_in_message(message: &str) -> Option<String> {
+    let mut search = 0;
+    while let Some(found) = message[search..].find("error=") {
+        let start = search + found;
+        let at_word_start = message[..start]
+            .chars()
+            .next_back()
+            .is_none_or(|c| !c.is_alphanumeric() && c != '_');
+        let value_start = start + "error=".len();
+        if at_word_start {
+            let value = &message[value_start..];
+            let end = value
+                .find(|c: char| c == ',' || c == ')' || c.is_whitespace())
+                .unwrap_or(value.len());
+            return Some(value[..end].to_string()).filter(|value| !value.is_empty());
+        }
+        search = value_start;
+    }
+    None
+}
+
+/// Parses a metric frame such as `counter: rx_packets=3, iface=eth0` into its kind, the
+/// metric name and its fields, the value first.
+pub(crate) fn parse_metric(message: &str) -> Option<(RecordKind, String, Fields)> {
+    let (kind, payload) = match message.strip_prefix(wire::COUNTER) {
+        Some(payload) => (RecordKind::Counter, payload),
+        None => (RecordKind::Gauge, message.strip_prefix(wire::GAUGE)?),
+    };
+    let mut fields = parse_fields(payload);
+    if fields.is_empty() {
+        return None;
+    }
+    let (name, value) = fields.remove(0);
+    fields.insert(0, ("value".to_string(), value));
+    Some((kind, name, fields))
+}
+
+/// Returns the image index announced by an `image_switch: N` frame.
+pub(crate) fn parse_image_switch(message: &str) -> Option<usize> {
+    message
+        .strip_prefix(wire::IMAGE_SWITCH)?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Returns the nonce of a `time_sync: N` frame, the device's reply to a time sync request.
+pub(crate) fn parse_time_sync(message: &str) -> Option<u32> {
+    message.strip_prefix(wire::TIME_SYNC)?.trim().parse().ok()
+}
+
+/// Whether `message` is a `boot` marker frame, logged first thing after reset.
+pub(crate) fn is_boot_frame(message: &str) -> bool {
+    message
+        .strip_prefix(wire::BOOT)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with(BOOT_DETAILS))
+}
+
+/// Separates `boot` from the details of a boot frame.
+const BOOT_DETAILS: &str = ": ";
+
+/// Moves the fields that end the details of a `boot: ...` frame, e.g. its
+/// `reset.reason`, into the record's fields. `boot` and the details before the fields
+/// stay the message.
+pub(crate) fn extract_boot_fields(record: &mut TraceRecord) {
+    let Some(details) = record
+        .message
+        .strip_prefix(wire::BOOT)
+        .and_then(|rest| rest.strip_prefix(BOOT_DETAILS))
+    else {
+        return;
+    };
+    let Some(start) = field_section(details) else {
+        return;
+    };
+    record.fields.extend(parse_fields(&details[start..]));
+    let end = match start {
+        0 => wire::BOOT.len(),
+        _ => wire::BOOT.len() + BOOT_DETAILS.len() + start - wire::FIELD_SEPARATOR.len(),
+    };
+    record.message.truncate(end);
+}
+
+/// Returns the span name announced by a `follows_from: name` frame.
+pub(crate) fn parse_follows_from(message: &str) -> Option<&str> {
+    let name = message.strip_prefix(wire::FOLLOWS_FROM)?.trim();
+    (!name.is_empty()).then_some(name)
+}
+
+/// Returns the span name and the fields of a `span_record: name, field=value` frame. The
+/// fields are still in the message when field extraction is off.
+pub(crate) fn parse_span_record(record: &TraceRecord) -> Option<(&str, Fields)> {
+    let payload = record.message.strip_prefix(wire::SPAN_RECORD)?;
+    let (name, mut fields) = match payload.split_once(wire::FIELD_SEPARATOR) {
+        Some((name, fields)) => (name, parse_fields(fields)),
+        None => (payload, Vec::new()),
+    };
+    fields.extend(record.fields.iter().cloned());
+    (!name.is_empty()).then_some((name, fields))
+}
+
+/// Moves the trailing `key=value` section of an event message, which the facade's macros
+/// generate for `info!(key = x, "msg")`, into the record's fields.
+///
+/// A message that consists of fields only is kept as it is.
+pub(crate) fn extract_fields(record: &mut TraceRecord) {
+    let Some(start) = field_section(&record.message) else {
+        return;
+    };
+    let fields = parse_fields(&record.message[start..]);
+    if start > 0 {
+        record.message.truncate(start - wire::FIELD_SEPARATOR.len());
+    }
+    record.fields.extend(fields);
+}
+
+/// The field of the exit record made of a [`wire::SPAN_CANCEL`] frame.
+pub(crate) const CANCELLED_FIELD: &str = "cancelled";
+
+/// The field added to a record whose fields were cut by [`limit_fields`].
+pub(crate) const TRUNCATED_FIELD: &str = "truncated";
+
+/// Drops the fields past the first `max_count` and cuts values longer than `max_len`
+/// bytes, then marks the record with `truncated=true` if anything was lost. Fields that
+/// say where the record goes, or its span kind, are kept and not counted.
+pub(crate) fn limit_fields(
+    record: &mut TraceRecord,
+    max_count: Option<usize>,
+    max_len: Option<usize>,
+) {
+    let mut count = 0;
+    let mut truncated = false;
+    record.fields.retain_mut(|(key, value)| {
+        if is_placement_field(key) || key == wire::KIND_FIELD {
+            return true;
+        }
+        count += 1;
+        if max_count.is_some_and(|max| count > max) {
+            truncated = true;
+            return false;
+        }
+        if let Some(max) = max_len.filter(|max| value.len() > *max) {
+            let mut end = max;
+            while !value.is_char_boundary(end) {
+                end -= 1;
+            }
+            value.truncate(end);
+            truncated = true;
+        }
+        true
+    });
+    if truncated {
+        record
+            .fields
+            .push((TRUNCATED_FIELD.to_string(), "true".to_string()));
+    }
+}
+
+/// Returns the offset of the first `, `-separated segment that starts with `key=`.
+fn field_section(message: &str) -> Option<usize> {
+    let mut offset = 0;
+    for segment in message.split(wire::FIELD_SEPARATOR) {
+        if let Some((key, _)) = segment.split_once(wire::KEY_VALUE_SEPARATOR) {
+            if is_field_key(key) {
+                return Some(offset);
+            }
+        }
+        offset += segment.len() + wire::FIELD_SEPARATOR.len();
+    }
+    None
+}
+
+/// A field value as the type it looks like, so backends can filter and aggregate it.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) enum FieldValue<'a> {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(&'a str),
+}
+
+impl<'a> FieldValue<'a> {
+    pub(crate) fn infer(value: &'a str) -> Self {
+        if let Ok(value) = value.parse() {
+            return FieldValue::Bool(value);
+        }
+        if let Ok(value) = value.parse() {
+            return FieldValue::Int(value);
+        }
+        // Rules out `inf`, `NaN` and the like.
+        let numeric = value
+            .chars()
+            .all(|c| c.is_ascii_digit() || matches!(c, '.' | '-' | '+' | 'e' | 'E'));
+        match value.parse() {
+            Ok(value) if numeric => FieldValue::Float(value),
+            _ => FieldValue::Str(value),
+        }
+    }
+
+    pub(crate) fn as_value(&self) -> &dyn tracing::field::Value {
+        match self {
+            FieldValue::Bool(value) => value,
+            FieldValue::Int(value) => value,
+            FieldValue::Float(value) => value,
+            FieldValue::Str(value) => value,
+        }
+    }
+}
+
+impl From<FieldValue<'_>> for opentelemetry::Value {
+    fn from(value: FieldValue<'_>) -> Self {
+        match value {
+            FieldValue::Bool(value) => value.into(),
+            FieldValue::Int(value) => value.into(),
+            FieldValue::Float(value) => value.into(),
+            FieldValue::Str(value) => value.to_string().into(),
+        }
+    }
+}
+
+#[cfg(feature = "logs")]
+impl From<FieldValue<'_>> for opentelemetry::logs::AnyValue {
+    fn from(value: FieldValue<'_>) -> Self {
+        match value {
+            FieldValue::Bool(value) => value.into(),
+            FieldValue::Int(value) => value.into(),
+            FieldValue::Float(value) => value.into(),
+            FieldValue::Str(value) => value.to_string().into(),
+        }
+    }
+}
+
+/// Parses `key=value, key2=value2` as generated by the facade's macros.
+///
+/// Segments without a `=` are appended to the previous value, so values containing
+/// `", "` (e.g. formatted structs) survive the round trip.
+pub(crate) fn parse_fields(args: &str) -> Vec<(String, String)> {
+    let mut fields: Vec<(String, String)> = Vec::new();
+    for segment in args.split(wire::FIELD_SEPARATOR) {
+        match segment.split_once(wire::KEY_VALUE_SEPARATOR) {
+            Some((key, value)) if is_field_key(key) => {
+                fields.push((key.to_string(), value.to_string()));
+            }
+            _ => {
+                if let Some((_, value)) = fields.last_mut() {
+                    value.push_str(wire::FIELD_SEPARATOR);
+                    value.push_str(segment);
+                }
+            }
+        }
+    }
+    fields
+}
+
+fn is_field_key(key: &str) -> bool {
+    !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
@@ -0,0 +1,54 @@
+//! Reordering of records by device timestamp.
+
+use std::mem;
+
+use crate::TraceRecord;
+
+/// Holds records back for a short window and releases them in device timestamp order.
+///
+/// Interrupt handlers can log while a thread-mode frame is still being written, so the
+/// transport order of frames is not always the order in which they were produced.
+pub(crate) struct ReorderBuffer {
+    window: u64,
+    /// Sorted by timestamp; every entry has one.
+    pending: Vec<TraceRecord>,
+}
+
+impl ReorderBuffer {
+    /// `window` is in microseconds, like the record timestamps.
+    pub(crate) fn new(window: u64) -> Self {
+        Self {
+            window,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Adds `record` and returns the records that have left the window, oldest first.
+    ///
+    /// A record is released once a record at least `window` newer has arrived. Records
+    /// without a timestamp cannot be placed and release everything before them.
+    pub(crate) fn push(&mut self, record: TraceRecord) -> Vec<TraceRecord> {
+        let Some(timestamp) = record.timestamp else {
+            let mut ready = mem::take(&mut self.pending);
+            ready.push(record);
+            return ready;
+        };
+
+        // Records with equal timestamps keep their arrival order.
+        let pos = self
+            .pending
+            .partition_point(|pending| pending.timestamp <= Some(timestamp));
+        self.pending.insert(pos, record);
+
+        let newest = self.pending.last().and_then(|r| r.timestamp).unwrap_or(0);
+        let ready = self.pending.partition_point(|pending| {
+            pending.timestamp.unwrap_or(0).saturating_add(self.window) <= newest
+        });
+        self.pending.drain(..ready).collect()
+    }
+
+    /// Returns all held records, oldest first.
+    pub(crate) fn drain(&mut self) -> Vec<TraceRecord> {
+        mem::take(&mut self.pending)
+    }
+}
@@ -0,0 +1,311 @@
+//! Test reports from a decoded trace, for firmware whose on-device tests are spans: each
+//! span becomes a test case that failed if it logged an error.
+
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::time::Duration;
+
+use serde_json::{json, Map, Value};
+use tracing::Level;
+
+use crate::expect::SpanRun;
+use crate::{Error, TraceDecoder, TraceRecord};
+
+const DEFAULT_SUITE: &str = "device";
+
+/// How a [`TestCase`] ended.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Passed,
+    /// The span, or a span nested in it, logged these `ERROR` events.
+    Failed {
+        errors: Vec<String>,
+    },
+    /// The span had not exited by the end of the trace, e.g. because the device crashed
+    /// or the run was stopped.
+    Incomplete,
+}
+
+/// One test case of a [`TestReport`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TestCase {
+    /// The span name.
+    pub name: String,
+    /// The span's fields, e.g. the arguments recorded by `#[instrument]`.
+    pub fields: Vec<(String, String)>,
+    /// The execution context the span ran in.
+    pub context: u32,
+    /// From enter to exit, by device timestamps, or by wall-clock time without them.
+    pub duration: Option<Duration>,
+    pub outcome: Outcome,
+    /// The events logged in the span, as `LEVEL message` lines.
+    pub output: Vec<String>,
+}
+
+impl TestCase {
+    fn new(run: &SpanRun) -> Self {
+        let errors: Vec<String> = run
+            .events
+            .iter()
+            .filter(|record| record.level == Some(Level::ERROR))
+            .map(|record| record.message.clone())
+            .collect();
+        let outcome = if !errors.is_empty() {
+            Outcome::Failed { errors }
+        } else if run.exit.is_none() {
+            Outcome::Incomplete
+        } else {
+            Outcome::Passed
+        };
+        let output = run
+            .events
+            .iter()
+            .map(|record| match record.level {
+                Some(level) => format!("{} {}", level, record.message),
+                None => record.message.clone(),
+            })
+            .collect();
+        Self {
+            name: run.enter.message.clone(),
+            fields: run.enter.fields.clone(),
+            context: run.enter.context,
+            duration: run.duration(),
+            outcome,
+            output,
+        }
+    }
+
+    fn outcome_name(&self) -> &'static str {
+        match self.outcome {
+            Outcome::Passed => "passed",
+            Outcome::Failed { .. } => "failed",
+            Outcome::Incomplete => "incomplete",
+        }
+    }
+}
+
+/// A test report of a decoded session, as JUnit XML for CI dashboards or as JSON.
+///
+/// Every top-level span is a test case by default, or with
+/// [`with_case_prefix`](Self::with_case_prefix) every span whose name starts with a
+/// prefix, nested or not. A case fails if an `ERROR` event is logged in its span or the
+/// spans nested in it, and is reported as an error if the span never exited. Each run of
+/// a span is a case of its own.
+///
+/// # Example
+/// ```rust,ignore
+/// let report = TestReport::decode(&decoder, &std::fs::read("selftest.bin")?)?
+///     .with_suite_name("selftest")
+///     .with_case_prefix("test_");
+/// report.write_junit(File::create("target/selftest.xml")?)?;
+/// assert!(report.passed());
+/// ```
+#[derive(Clone, Debug)]
+pub struct TestReport {
+    suite: String,
+    cases: Vec<TestCase>,
+    records: Vec<TraceRecord>,
+    prefix: Option<String>,
+}
+
+impl TestReport {
+    /// Decodes `data` as context 0 with a fresh stream of `decoder`.
+    pub fn decode(decoder: &TraceDecoder, data: &[u8]) -> Result<Self, Error> {
+        Ok(Self::from_records(decoder.decode_records(data)?))
+    }
+
+    /// Reports on records collected elsewhere, e.g. by a [`HilRun`](crate::hil::HilRun).
+    pub fn from_records(records: Vec<TraceRecord>) -> Self {
+        let mut report = Self {
+            suite: DEFAULT_SUITE.to_string(),
+            cases: Vec::new(),
+            records,
+            prefix: None,
+        };
+        report.collect_cases();
+        report
+    }
+
+    /// Sets the name of the test suite, and the class name of its cases. Defaults to
+    /// `device`.
+    pub fn with_suite_name(mut self, name: impl Into<String>) -> Self {
+        self.suite = name.into();
+        self
+    }
+
+    /// Makes every span whose name starts with `prefix` a test case, instead of every
+    /// top-level span.
+    pub fn with_case_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = Some(prefix.into());
+        self.collect_cases();
+        self
+    }
+
+    /// The test cases, in the order their spans were entered.
+    pub fn cases(&self) -> &[TestCase] {
+        &self.cases
+    }
+
+    /// Whether every case passed. A report without cases passes.
+    pub fn passed(&self) -> bool {
+        self.cases
+            .iter()
+            .all(|case| case.outcome == Outcome::Passed)
+    }
+
+    /// The report as a JUnit XML document.
+    pub fn to_junit_xml(&self) -> String {
+        let suite = escape(&self.suite);
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        let _ = writeln!(
+            xml,
+            "<testsuites>\n  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"{}\" \
+             skipped=\"0\" time=\"{}\">",
+            suite,
+            self.cases.len(),
+            self.count("failed"),
+            self.count("incomplete"),
+            seconds(self.cases.iter().filter_map(|case| case.duration).sum()),
+        );
+        for case in &self.cases {
+            let _ = write!(
+                xml,
+                "    <testcase name=\"{}\" classname=\"{}\" time=\"{}\"",
+                escape(&case_name(case)),
+                suite,
+                seconds(case.duration.unwrap_or_default()),
+            );
+            if case.outcome == Outcome::Passed && case.output.is_empty() {
+                xml.push_str("/>\n");
+                continue;
+            }
+            xml.push_str(">\n");
+            match &case.outcome {
+                Outcome::Passed => {}
+                Outcome::Failed { errors } => {
+                    let _ = writeln!(
+                        xml,
+                        "      <failure message=\"{}\" type=\"ERROR\">{}</failure>",
+                        escape(&errors[0]),
+                        escape(&errors.join("\n")),
+                    );
+                }
+                Outcome::Incomplete => {
+                    xml.push_str(
+                        "      <error message=\"span did not exit\" type=\"incomplete\"/>\n",
+                    );
+                }
+            }
+            if !case.output.is_empty() {
+                let _ = writeln!(
+                    xml,
+                    "      <system-out>{}</system-out>",
+                    escape(&case.output.join("\n")),
+                );
+            }
+            xml.push_str("    </testcase>\n");
+        }
+        xml.push_str("  </testsuite>\n</testsuites>\n");
+        xml
+    }
+
+    /// Writes the report as a JUnit XML document.
+    pub fn write_junit<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(self.to_junit_xml().as_bytes())?;
+        writer.flush()
+    }
+
+    /// The report as JSON: the suite's name and counts, and its `cases` with their
+    /// `outcome` (`passed`, `failed` or `incomplete`), duration and errors.
+    pub fn to_json(&self) -> Value {
+        let cases: Vec<Value> = self
+            .cases
+            .iter()
+            .map(|case| {
+                let fields: Map<String, Value> = case
+                    .fields
+                    .iter()
+                    .map(|(key, value)| (key.clone(), Value::from(value.as_str())))
+                    .collect();
+                let errors = match &case.outcome {
+                    Outcome::Failed { errors } => errors.clone(),
+                    _ => Vec::new(),
+                };
+                json!({
+                    "name": case.name,
+                    "fields": fields,
+                    "context": case.context,
+                    "duration_us": case.duration.map(|d| d.as_micros() as u64),
+                    "outcome": case.outcome_name(),
+                    "errors": errors,
+                    "output": case.output,
+                })
+            })
+            .collect();
+        json!({
+            "name": self.suite,
+            "tests": self.cases.len(),
+            "passed": self.count("passed"),
+            "failed": self.count("failed"),
+            "incomplete": self.count("incomplete"),
+            "cases": cases,
+        })
+    }
+
+    /// The number of cases with the outcome named `outcome`.
+    fn count(&self, outcome: &str) -> usize {
+        self.cases
+            .iter()
+            .filter(|case| case.outcome_name() == outcome)
+            .count()
+    }
+
+    fn collect_cases(&mut self) {
+        let prefix = self.prefix.as_deref();
+        self.cases = SpanRun::collect(&self.records, |record| match prefix {
+            Some(prefix) => record.message.starts_with(prefix),
+            None => record.parent_id.is_none(),
+        })
+        .iter()
+        .map(TestCase::new)
+        .collect();
+    }
+}
+
+/// The case's name with its fields, so the runs of a parameterized test tell apart, e.g.
+/// `test_crc(len=12)`.
+fn case_name(case: &TestCase) -> String {
+    if case.fields.is_empty() {
+        return case.name.clone();
+    }
+    let fields: Vec<String> = case
+        .fields
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect();
+    format!("{}({})", case.name, fields.join(", "))
+}
+
+/// Seconds with microsecond precision, as JUnit has them.
+fn seconds(duration: Duration) -> String {
+    format!("{:.6}", duration.as_secs_f64())
+}
+
+/// Escapes `text` for XML attributes and text. Control characters XML cannot carry
+/// become U+FFFD.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\n' | '\t' | '\r' => escaped.push(c),
+            c if c.is_control() => escaped.push('\u{FFFD}'),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
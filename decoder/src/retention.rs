@@ -0,0 +1,108 @@
+//! Post-hoc capture: the latest records are kept in memory and only exported when
+//! something went wrong.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use tracing::Level;
+
+use crate::TraceRecord;
+
+/// How much of the recent trace a stream keeps in memory instead of exporting it, see
+/// [`TraceDecoderBuilder::with_retention`](crate::TraceDecoderBuilder::with_retention).
+///
+/// Records older than `max_age` (by the time they were decoded) are discarded, and so are
+/// the oldest records once the kept ones take more than `max_bytes`. An event at the
+/// trigger level or above exports everything kept, as does
+/// [`TraceStream::export_retained`](crate::TraceStream::export_retained), e.g. from a
+/// signal handler or when a test fails.
+///
+/// # Example
+/// ```rust,ignore
+/// let retention = Retention::new(Duration::from_secs(30), 16 << 20);
+/// let decoder = TraceDecoder::builder().with_retention(retention).build(&elf)?;
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Retention {
+    max_age: Duration,
+    max_bytes: usize,
+    trigger: Option<Level>,
+}
+
+impl Retention {
+    /// Keeps the records of the last `max_age`, up to about `max_bytes` of them, and
+    /// exports them on error events.
+    pub fn new(max_age: Duration, max_bytes: usize) -> Self {
+        Self {
+            max_age,
+            max_bytes,
+            trigger: Some(Level::ERROR),
+        }
+    }
+
+    /// Exports the kept records on events at `level` or above. `None` leaves it to
+    /// [`TraceStream::export_retained`](crate::TraceStream::export_retained).
+    pub fn with_trigger_level(mut self, level: Option<Level>) -> Self {
+        self.trigger = level;
+        self
+    }
+
+    /// Whether `record` exports the kept records.
+    pub(crate) fn triggers(&self, record: &TraceRecord) -> bool {
+        match (self.trigger, record.level) {
+            // `ERROR` is the lowest level.
+            (Some(trigger), Some(level)) => level <= trigger,
+            _ => false,
+        }
+    }
+}
+
+/// The records kept for a [`Retention`], oldest first.
+#[derive(Default)]
+pub(crate) struct RetainedRecords {
+    /// Each with the time it was kept and its approximate size.
+    records: VecDeque<(Instant, usize, TraceRecord)>,
+    bytes: usize,
+}
+
+impl RetainedRecords {
+    /// Keeps `record`, returning the number of records discarded to make room for it.
+    pub(crate) fn push(&mut self, retention: &Retention, record: TraceRecord) -> usize {
+        let now = Instant::now();
+        let size = record_size(&record);
+        self.records.push_back((now, size, record));
+        self.bytes += size;
+
+        let mut discarded = 0;
+        while let Some((kept, size, _)) = self.records.front() {
+            let expired = now.duration_since(*kept) > retention.max_age;
+            // The newest record stays, however large.
+            if !expired && (self.bytes <= retention.max_bytes || self.records.len() == 1) {
+                break;
+            }
+            self.bytes -= size;
+            self.records.pop_front();
+            discarded += 1;
+        }
+        discarded
+    }
+
+    /// Takes all kept records, oldest first.
+    pub(crate) fn take(&mut self) -> Vec<TraceRecord> {
+        self.bytes = 0;
+        self.records
+            .drain(..)
+            .map(|(_, _, record)| record)
+            .collect()
+    }
+}
+
+/// The memory taken by `record`, roughly.
+fn record_size(record: &TraceRecord) -> usize {
+    let fields: usize = record
+        .fields
+        .iter()
+        .map(|(key, value)| key.len() + value.len())
+        .sum();
+    std::mem::size_of::<TraceRecord>() + record.message.len() + fields
+}
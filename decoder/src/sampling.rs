@@ -0,0 +1,142 @@
+//! Head-based sampling of device spans by name.
+
+use std::collections::HashMap;
+use std::time::Instant;
+
+/// Tolerance for the rounding errors of adding up ratios, e.g. ten times 0.1.
+const EPSILON: f64 = 1e-9;
+
+/// Thins out high-frequency device spans before they are exported.
+///
+/// The decision is made when a span is entered; a span that is not sampled is dropped
+/// together with everything inside it (child spans, events and its exit). Spans that
+/// are kept carry a `sampling.rate` attribute: the number of spans of that name it
+/// stands for, including itself, so dashboards can scale counts back up.
+///
+/// Sampling is deterministic: a ratio of 0.25 keeps exactly every fourth span.
+///
+/// # Example
+/// ```rust,ignore
+/// let sampler = Sampler::new()
+///     .with_ratio("adc_isr", 0.01)
+///     .with_rate_limit("radio_rx", 10.0);
+/// let decoder = TraceDecoder::builder().with_sampler(sampler).build(&elf)?;
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Sampler {
+    default: Option<Rule>,
+    rules: HashMap<String, Rule>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Rule {
+    /// Fraction of spans kept.
+    Ratio(f64),
+    /// Spans kept per second.
+    RateLimit(f64),
+}
+
+impl Sampler {
+    /// A sampler that keeps every span until rules are added.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keeps `ratio` (0.0 to 1.0) of the spans called `span_name`.
+    pub fn with_ratio(mut self, span_name: impl Into<String>, ratio: f64) -> Self {
+        self.rules
+            .insert(span_name.into(), Rule::Ratio(ratio.clamp(0.0, 1.0)));
+        self
+    }
+
+    /// Keeps at most `per_second` spans called `span_name` per second of device time
+    /// (host time for firmware without timestamps), allowing bursts of one second's worth.
+    pub fn with_rate_limit(mut self, span_name: impl Into<String>, per_second: f64) -> Self {
+        self.rules
+            .insert(span_name.into(), Rule::RateLimit(per_second.max(0.0)));
+        self
+    }
+
+    /// Keeps `ratio` of the spans that have no rule of their own. Defaults to all of them,
+    /// without a `sampling.rate` attribute.
+    pub fn with_default_ratio(mut self, ratio: f64) -> Self {
+        self.default = Some(Rule::Ratio(ratio.clamp(0.0, 1.0)));
+        self
+    }
+}
+
+/// Outcome of [`SamplingState::sample`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Decision {
+    /// No rule applies to the span.
+    Unsampled,
+    /// Keep the span, which stands for this many spans.
+    Keep(u64),
+    Drop,
+}
+
+/// Per-name sampling progress of one stream.
+#[derive(Debug)]
+pub(crate) struct SamplingState {
+    names: HashMap<String, NameState>,
+    started: Instant,
+}
+
+#[derive(Debug, Default)]
+struct NameState {
+    /// Ratio credit, or rate limit tokens.
+    budget: f64,
+    /// Time of the last rate limit refill, in microseconds.
+    last_refill: Option<u64>,
+    /// Spans dropped since the last one kept.
+    dropped: u64,
+}
+
+impl SamplingState {
+    pub(crate) fn new() -> Self {
+        Self {
+            names: HashMap::new(),
+            started: Instant::now(),
+        }
+    }
+
+    /// Decides whether the span `name` entered at `timestamp` (device microseconds) is kept.
+    pub(crate) fn sample(
+        &mut self,
+        sampler: &Sampler,
+        name: &str,
+        timestamp: Option<u64>,
+    ) -> Decision {
+        let Some(rule) = sampler.rules.get(name).or(sampler.default.as_ref()) else {
+            return Decision::Unsampled;
+        };
+        let now = timestamp.unwrap_or_else(|| self.started.elapsed().as_micros() as u64);
+        let state = self.names.entry(name.to_string()).or_default();
+
+        let keep = match *rule {
+            Rule::Ratio(ratio) => {
+                state.budget += ratio;
+                state.budget + EPSILON >= 1.0
+            }
+            Rule::RateLimit(per_second) => {
+                let elapsed = match state.last_refill {
+                    Some(last) => now.saturating_sub(last),
+                    // Start with a full bucket.
+                    None => u64::MAX,
+                };
+                let capacity = per_second.max(1.0);
+                state.budget = (state.budget + per_second * elapsed as f64 / 1e6).min(capacity);
+                state.last_refill = Some(now);
+                state.budget + EPSILON >= 1.0
+            }
+        };
+
+        if keep {
+            state.budget -= 1.0;
+            Decision::Keep(std::mem::take(&mut state.dropped) + 1)
+        } else {
+            state.dropped += 1;
+            Decision::Drop
+        }
+    }
+}
@@ -0,0 +1,107 @@
+//! Dropping of spans shorter than a minimum duration.
+
+use crate::{RecordKind, TraceRecord};
+
+/// Holds the records of a span back until it is known to last at least `min_duration`.
+///
+/// A span is kept as soon as a record at least `min_duration` after its enter arrives, or
+/// when it exits late enough. A span that exits earlier is dropped; the records inside it
+/// are passed on and end up in the closest surviving span. Spans without device
+/// timestamps are always kept.
+pub(crate) struct ShortSpanFilter {
+    /// In microseconds, like the record timestamps.
+    min_duration: u64,
+    /// Spans that have not exited, outermost first. Those already kept come first.
+    held: Vec<HeldSpan>,
+}
+
+struct HeldSpan {
+    /// `None` once the span is kept and its enter was passed on.
+    enter: Option<TraceRecord>,
+    start: Option<u64>,
+    /// Records inside the span, waiting for the decision.
+    records: Vec<TraceRecord>,
+}
+
+impl ShortSpanFilter {
+    /// `min_duration` is in microseconds.
+    pub(crate) fn new(min_duration: u64) -> Self {
+        Self {
+            min_duration,
+            held: Vec::new(),
+        }
+    }
+
+    /// Adds `record`, appending the records that are decided on to `ready`.
+    pub(crate) fn push(&mut self, record: TraceRecord, ready: &mut Vec<TraceRecord>) {
+        let now = record.timestamp;
+        match record.kind {
+            RecordKind::SpanEnter => self.held.push(HeldSpan {
+                start: record.timestamp,
+                enter: Some(record),
+                records: Vec::new(),
+            }),
+            RecordKind::SpanExit => match self.held.pop() {
+                Some(HeldSpan {
+                    enter: Some(enter),
+                    start,
+                    records,
+                }) => {
+                    let short = match (start, record.timestamp) {
+                        (Some(start), Some(end)) => end.saturating_sub(start) < self.min_duration,
+                        _ => false,
+                    };
+                    let target = self.target(ready);
+                    if short {
+                        target.extend(records);
+                    } else {
+                        target.push(enter);
+                        target.extend(records);
+                        target.push(record);
+                    }
+                }
+                // Kept already, or entered before the filter saw it.
+                _ => ready.push(record),
+            },
+            RecordKind::Event | RecordKind::Counter | RecordKind::Gauge => {
+                self.target(ready).push(record)
+            }
+        }
+        self.keep_long(now, ready);
+    }
+
+    /// Passes on everything held back; the spans that are still open are kept.
+    pub(crate) fn drain(&mut self, ready: &mut Vec<TraceRecord>) {
+        for mut span in self.held.drain(..) {
+            ready.extend(span.enter.take());
+            ready.append(&mut span.records);
+        }
+    }
+
+    /// Where a record inside the innermost open span goes.
+    fn target<'r>(&'r mut self, ready: &'r mut Vec<TraceRecord>) -> &'r mut Vec<TraceRecord> {
+        match self.held.last_mut() {
+            Some(span) if span.enter.is_some() => &mut span.records,
+            _ => ready,
+        }
+    }
+
+    /// Keeps the spans that have lasted `min_duration` by `now`, outermost first.
+    fn keep_long(&mut self, now: Option<u64>, ready: &mut Vec<TraceRecord>) {
+        for span in &mut self.held {
+            if span.enter.is_none() {
+                continue;
+            }
+            let long = match (span.start, now) {
+                (Some(start), Some(now)) => now.saturating_sub(start) >= self.min_duration,
+                (Some(_), None) => false,
+                (None, _) => true,
+            };
+            if !long {
+                break;
+            }
+            ready.extend(span.enter.take());
+            ready.append(&mut span.records);
+        }
+    }
+}
@@ -0,0 +1,387 @@
+//! Deterministic renderings of a decoded trace, for golden-file (snapshot) tests of
+//! firmware instrumentation.
+//!
+//! [`Normalizer`] is the canonical form both renderings build on: run it over the records
+//! of two captures to compare them with a diff of your own.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::Arc;
+
+use serde_json::{json, Map, Value};
+
+use crate::{Error, RecordKind, TraceDecoder, TraceRecord};
+
+/// How timestamps appear in a [`Snapshot`].
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Timestamps {
+    /// Microseconds since the first timestamped record, and span durations.
+    #[default]
+    Relative,
+    /// Relative, rounded to the nearest multiple of this many microseconds, so that
+    /// jitter below it does not change the snapshot.
+    Rounded(u64),
+    /// Left out, for firmware whose timing varies from run to run.
+    Omitted,
+}
+
+/// Field values replaced by [`Normalizer::with_scrubbed_field`].
+const SCRUBBED: &str = "<scrubbed>";
+
+/// Brings the records of a capture into a canonical form, so two runs of the same
+/// firmware come out the same:
+///
+/// - timestamps are relative to the first timestamped record, see [`Timestamps`];
+/// - records are ordered by execution context, keeping their order within each, so
+///   contexts interleaving differently does not matter;
+/// - span ids are renumbered from 1 in that order, and wall-clock times and
+///   OpenTelemetry ids are cleared;
+/// - absolute source paths are stripped to the crate directory, e.g.
+///   `/home/ci/work/firmware/src/main.rs` to `firmware/src/main.rs`, or by the prefixes
+///   given;
+/// - scrubbed fields, e.g. nonces or addresses, have their values replaced.
+///
+/// # Example
+/// ```rust,ignore
+/// let normalizer = Normalizer::new()
+///     .with_timestamps(Timestamps::Rounded(100))
+///     .with_scrubbed_field("nonce");
+/// assert_eq!(normalizer.normalize(&before), normalizer.normalize(&after));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Normalizer {
+    timestamps: Timestamps,
+    path_prefixes: Vec<String>,
+    scrubbed_fields: Vec<String>,
+}
+
+impl Normalizer {
+    /// Relative timestamps, stripped paths and no scrubbed fields.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_timestamps(mut self, timestamps: Timestamps) -> Self {
+        self.timestamps = timestamps;
+        self
+    }
+
+    /// Strips `prefix` from the source paths that start with it, e.g. the workspace
+    /// directory, instead of guessing the crate directory.
+    pub fn with_path_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.path_prefixes.push(prefix.into().replace('\\', "/"));
+        self
+    }
+
+    /// Replaces the value of the field `key` of every record with `<scrubbed>`.
+    pub fn with_scrubbed_field(mut self, key: impl Into<String>) -> Self {
+        self.scrubbed_fields.push(key.into());
+        self
+    }
+
+    /// The canonical form of `records`.
+    pub fn normalize(&self, records: &[TraceRecord]) -> Vec<TraceRecord> {
+        let start = records.iter().filter_map(|record| record.timestamp).min();
+        let mut records = records.to_vec();
+        records.sort_by_key(|record| record.context);
+
+        let mut ids = HashMap::new();
+        let mut renumber = |id: u64| {
+            let next = ids.len() as u64 + 1;
+            *ids.entry(id).or_insert(next)
+        };
+        for record in &mut records {
+            record.span_id = record.span_id.map(&mut renumber);
+            record.parent_id = record.parent_id.map(&mut renumber);
+            record.timestamp = record
+                .timestamp
+                .zip(start)
+                .and_then(|(timestamp, start)| self.timestamp(timestamp.saturating_sub(start)));
+            record.wall_time = None;
+            record.trace_id = None;
+            record.otel_span_id = None;
+            if let Some(location) = &mut record.location {
+                location.file = self.strip_path(&location.file);
+            }
+            for (key, value) in &mut record.fields {
+                if self.scrubbed_fields.contains(key) {
+                    *value = SCRUBBED.to_string();
+                }
+            }
+        }
+        records
+    }
+
+    fn timestamp(&self, relative: u64) -> Option<u64> {
+        match self.timestamps {
+            Timestamps::Relative | Timestamps::Rounded(0) => Some(relative),
+            Timestamps::Rounded(granularity) => {
+                Some((relative + granularity / 2) / granularity * granularity)
+            }
+            Timestamps::Omitted => None,
+        }
+    }
+
+    fn strip_path(&self, file: &Arc<str>) -> Arc<str> {
+        let path = file.replace('\\', "/");
+        for prefix in &self.path_prefixes {
+            if let Some(rest) = path.strip_prefix(prefix.as_str()) {
+                return rest.trim_start_matches('/').into();
+            }
+        }
+        let absolute = path.starts_with('/') || path.get(1..3) == Some(":/");
+        if !absolute {
+            return file.clone();
+        }
+        // The crate directory is the one that holds `src`.
+        match path.rfind("/src/") {
+            Some(src) => {
+                let crate_dir = path[..src].rfind('/').map_or(0, |slash| slash + 1);
+                path[crate_dir..].into()
+            }
+            None => path.rsplit('/').next().unwrap_or_default().into(),
+        }
+    }
+}
+
+/// The span tree of a capture, rendered as indented text (its [`Display`](fmt::Display))
+/// or JSON, ready for `insta` or a checked-in expected file.
+///
+/// Everything that changes between runs of the same firmware is left out: span ids,
+/// absolute timestamps, wall-clock times, and by default source locations, which move
+/// with unrelated edits. Records are grouped by execution context. The records are
+/// normalized first, see [`with_normalizer`](Self::with_normalizer) to scrub fields or
+/// round timestamps.
+///
+/// # Example
+/// ```rust,ignore
+/// let decoder = TraceDecoder::new(&elf)?;
+/// let snapshot = Snapshot::decode(&decoder, &std::fs::read("boot.bin")?)?;
+/// insta::assert_snapshot!(snapshot.to_string());
+/// ```
+///
+/// ```text
+/// handle_packet len=12 +0us 250us
+///   WARN checksum mismatch +150us
+/// ```
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    records: Vec<TraceRecord>,
+    normalizer: Normalizer,
+    locations: bool,
+}
+
+impl Snapshot {
+    /// Decodes `data` as context 0 with a fresh stream of `decoder`.
+    pub fn decode(decoder: &TraceDecoder, data: &[u8]) -> Result<Self, Error> {
+        Ok(Self::from_records(decoder.decode_records(data)?))
+    }
+
+    /// Renders records collected elsewhere, e.g. with [`TraceStream::process_into`](crate::TraceStream::process_into).
+    pub fn from_records(records: Vec<TraceRecord>) -> Self {
+        Self {
+            records,
+            normalizer: Normalizer::default(),
+            locations: false,
+        }
+    }
+
+    pub fn with_timestamps(mut self, timestamps: Timestamps) -> Self {
+        self.normalizer.timestamps = timestamps;
+        self
+    }
+
+    /// Normalizes the records with `normalizer`, including its timestamps setting.
+    pub fn with_normalizer(mut self, normalizer: Normalizer) -> Self {
+        self.normalizer = normalizer;
+        self
+    }
+
+    /// Includes the `file:line` of each record. Off by default.
+    pub fn with_locations(mut self, locations: bool) -> Self {
+        self.locations = locations;
+        self
+    }
+
+    /// The span tree as JSON: an array of `{"context": id, "trace": [...]}` with one node
+    /// per span, event or metric, and the children of spans nested in `children`.
+    pub fn to_json(&self) -> Value {
+        let records = self.normalizer.normalize(&self.records);
+        let contexts: Vec<Value> = trees(&records)
+            .iter()
+            .map(|(context, nodes)| {
+                let trace: Vec<Value> = nodes.iter().map(|node| self.node_json(node)).collect();
+                json!({ "context": context, "trace": trace })
+            })
+            .collect();
+        Value::Array(contexts)
+    }
+
+    fn duration(&self, node: &Node) -> Option<u64> {
+        match (node.record.timestamp, node.end) {
+            (Some(start), Some(Some(end))) => Some(end.saturating_sub(start)),
+            _ => None,
+        }
+    }
+
+    fn write_node(&self, f: &mut fmt::Formatter, node: &Node, depth: usize) -> fmt::Result {
+        let record = node.record;
+        write!(f, "{:indent$}", "", indent = depth * 2)?;
+        match record.kind {
+            RecordKind::Counter => write!(f, "counter ")?,
+            RecordKind::Gauge => write!(f, "gauge ")?,
+            RecordKind::Event => {
+                if let Some(level) = record.level {
+                    write!(f, "{} ", level)?;
+                }
+            }
+            RecordKind::SpanEnter | RecordKind::SpanExit => {}
+        }
+        write!(f, "{}", record.message)?;
+        for (key, value) in &record.fields {
+            write!(f, " {}={}", key, value)?;
+        }
+        if let (true, Some(location)) = (self.locations, &record.location) {
+            write!(f, " @ {}:{}", location.file, location.line)?;
+        }
+        if let Some(time) = record.timestamp {
+            write!(f, " +{}us", time)?;
+        }
+        if let Some(duration) = self.duration(node) {
+            write!(f, " {}us", duration)?;
+        }
+        if node.is_span && node.end.is_none() {
+            write!(f, " (not exited)")?;
+        }
+        writeln!(f)?;
+        for child in &node.children {
+            self.write_node(f, child, depth + 1)?;
+        }
+        Ok(())
+    }
+
+    fn node_json(&self, node: &Node) -> Value {
+        let record = node.record;
+        let mut object = Map::new();
+        let kind = match record.kind {
+            RecordKind::SpanEnter | RecordKind::SpanExit => "span",
+            RecordKind::Event => "event",
+            RecordKind::Counter => "counter",
+            RecordKind::Gauge => "gauge",
+        };
+        object.insert(kind.into(), record.message.as_str().into());
+        if let (RecordKind::Event, Some(level)) = (record.kind, record.level) {
+            object.insert("level".into(), level.as_str().into());
+        }
+        if !record.fields.is_empty() {
+            let fields: Map<String, Value> = record
+                .fields
+                .iter()
+                .map(|(key, value)| (key.clone(), value.as_str().into()))
+                .collect();
+            object.insert("fields".into(), fields.into());
+        }
+        if let (true, Some(location)) = (self.locations, &record.location) {
+            object.insert("file".into(), Value::from(&*location.file));
+            object.insert("line".into(), location.line.into());
+        }
+        if let Some(time) = record.timestamp {
+            object.insert("time_us".into(), time.into());
+        }
+        if let Some(duration) = self.duration(node) {
+            object.insert("duration_us".into(), duration.into());
+        }
+        if node.is_span {
+            if node.end.is_none() {
+                object.insert("exited".into(), false.into());
+            }
+            let children: Vec<Value> = node
+                .children
+                .iter()
+                .map(|child| self.node_json(child))
+                .collect();
+            object.insert("children".into(), children.into());
+        }
+        Value::Object(object)
+    }
+}
+
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let records = self.normalizer.normalize(&self.records);
+        let trees = trees(&records);
+        for (context, nodes) in &trees {
+            if trees.len() > 1 {
+                writeln!(f, "context {}:", context)?;
+            }
+            for node in nodes {
+                self.write_node(f, node, 0)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Builds the span tree of every context.
+fn trees(records: &[TraceRecord]) -> BTreeMap<u32, Vec<Node<'_>>> {
+    let mut trees: BTreeMap<u32, (Vec<Node>, Vec<Node>)> = BTreeMap::new();
+    for record in records {
+        let (roots, open) = trees.entry(record.context).or_default();
+        match record.kind {
+            RecordKind::SpanEnter => open.push(Node::span(record)),
+            RecordKind::SpanExit => {
+                if let Some(mut node) = open.pop() {
+                    node.end = Some(record.timestamp);
+                    attach(roots, open, node);
+                }
+            }
+            RecordKind::Event | RecordKind::Counter | RecordKind::Gauge => {
+                attach(roots, open, Node::leaf(record))
+            }
+        }
+    }
+    trees
+        .into_iter()
+        .map(|(context, (mut roots, mut open))| {
+            while let Some(node) = open.pop() {
+                attach(&mut roots, &mut open, node);
+            }
+            (context, roots)
+        })
+        .collect()
+}
+
+/// A span with its children, or a single event or metric.
+struct Node<'a> {
+    record: &'a TraceRecord,
+    is_span: bool,
+    /// Timestamp of the span's exit, `None` while it has not exited.
+    end: Option<Option<u64>>,
+    children: Vec<Node<'a>>,
+}
+
+impl<'a> Node<'a> {
+    fn span(record: &'a TraceRecord) -> Self {
+        Self {
+            record,
+            is_span: true,
+            end: None,
+            children: Vec::new(),
+        }
+    }
+
+    fn leaf(record: &'a TraceRecord) -> Self {
+        Self {
+            is_span: false,
+            ..Self::span(record)
+        }
+    }
+}
+
+/// Adds `node` to the innermost open span, or to the roots.
+fn attach<'a>(roots: &mut Vec<Node<'a>>, open: &mut [Node<'a>], node: Node<'a>) {
+    match open.last_mut() {
+        Some(parent) => parent.children.push(node),
+        None => roots.push(node),
+    }
+}
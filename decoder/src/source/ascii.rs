@@ -0,0 +1,71 @@
+//! Binary data carried as text.
+
+/// Decodes standard base64, with or without padding; whitespace is skipped.
+pub(crate) fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(text.len() / 4 * 3);
+    let mut bits = 0u32;
+    let mut count = 0;
+    let mut padding = false;
+    for c in text.bytes().filter(|c| !c.is_ascii_whitespace()) {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            b'=' => {
+                padding = true;
+                continue;
+            }
+            _ => return None,
+        };
+        if padding {
+            return None;
+        }
+        bits = bits << 6 | u32::from(value);
+        count += 1;
+        if count == 4 {
+            out.extend(&bits.to_be_bytes()[1..]);
+            bits = 0;
+            count = 0;
+        }
+    }
+    match count {
+        0 => {}
+        2 => out.push((bits >> 4) as u8),
+        3 => out.extend(&((bits >> 2) as u16).to_be_bytes()),
+        _ => return None,
+    }
+    Some(out)
+}
+
+/// Decodes hex digit pairs, separated by whitespace, commas or colons or not at all, each
+/// group optionally prefixed with `0x`.
+pub(crate) fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(text.len() / 2);
+    for group in text
+        .split(|c: char| c.is_ascii_whitespace() || c == ',' || c == ':')
+        .filter(|group| !group.is_empty())
+    {
+        let (digits, prefixed) = match group.strip_prefix("0x").or(group.strip_prefix("0X")) {
+            Some(digits) => (digits, true),
+            None => (group, false),
+        };
+        let digits = digits.as_bytes();
+        match digits.len() {
+            // `0x5`, as C arrays have it.
+            1 if prefixed => out.push(hex_digit(digits[0])?),
+            n if n % 2 == 0 && n > 0 => {
+                for pair in digits.chunks(2) {
+                    out.push(hex_digit(pair[0])? << 4 | hex_digit(pair[1])?);
+                }
+            }
+            _ => return None,
+        }
+    }
+    Some(out)
+}
+
+fn hex_digit(c: u8) -> Option<u8> {
+    (c as char).to_digit(16).map(|digit| digit as u8)
+}
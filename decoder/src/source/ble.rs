@@ -0,0 +1,309 @@
+use std::io;
+use std::time::Duration;
+
+use btleplug::api::{Central as _, Manager as _, Peripheral as _, ScanFilter};
+use btleplug::platform::{Adapter, Manager, Peripheral};
+use futures_util::StreamExt;
+use uuid::Uuid;
+
+use super::reconnect::{ReconnectPolicy, Reconnector};
+use super::{AsyncFrameSource, Chunk, ChunkSink, FrameSource};
+use crate::{Error, TraceStream};
+
+/// The Nordic UART Service.
+const NUS_SERVICE: Uuid = Uuid::from_u128(0x6e400001_b5a3_f393_e0a9_e50e24dcca9e);
+/// The NUS characteristic the device sends its data on, as notifications.
+const NUS_TX: Uuid = Uuid::from_u128(0x6e400003_b5a3_f393_e0a9_e50e24dcca9e);
+
+const DEFAULT_SCAN_TIME: Duration = Duration::from_secs(2);
+/// How long without notifications before checking that the device is still connected.
+const CONNECTION_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Reads defmt bytes over Bluetooth LE from a device running the Nordic UART Service.
+///
+/// The source scans for a device advertising NUS, optionally with a given name or
+/// address, connects to it and subscribes to its TX characteristic; the payload of every
+/// notification goes into the stream. When the connection drops, e.g. because the device
+/// went out of range, it scans again and resets the stream decoder once reconnected.
+///
+/// Besides [`run`](Self::run), which brings its own tokio runtime, the source can be
+/// driven from an async runtime as an [`AsyncFrameSource`].
+///
+/// The first Bluetooth adapter of the host is used. On Linux, BlueZ is accessed over
+/// D-Bus, which needs libdbus unless built with the `ble-vendored-dbus` feature.
+///
+/// # Example
+/// ```rust,ignore
+/// let decoder = TraceDecoder::new(&elf)?;
+/// let mut stream = decoder.new_stream();
+/// Ble::new().with_name("sensor-12").run(&mut stream)?;
+/// ```
+#[derive(Debug)]
+pub struct Ble {
+    name: Option<String>,
+    address: Option<String>,
+    scan_time: Duration,
+    reconnect: Reconnector,
+    adapter: Option<Adapter>,
+    /// The runtime of the blocking [`FrameSource`] implementation.
+    runtime: Option<tokio::runtime::Runtime>,
+}
+
+impl Default for Ble {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Ble {
+    /// Connects to the first device found advertising NUS.
+    pub fn new() -> Self {
+        Self {
+            name: None,
+            address: None,
+            scan_time: DEFAULT_SCAN_TIME,
+            reconnect: Reconnector::new(ReconnectPolicy::default()),
+            adapter: None,
+            runtime: None,
+        }
+    }
+
+    /// Only connects to a device advertising the local name `name`.
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Only connects to the device with Bluetooth address `address`, e.g.
+    /// `"E4:5F:01:2A:3B:4C"`. Not available on macOS, which hides addresses.
+    pub fn with_address(mut self, address: impl Into<String>) -> Self {
+        self.address = Some(address.into());
+        self
+    }
+
+    /// Sets how long each scan for the device lasts. Defaults to 2 seconds.
+    pub fn with_scan_time(mut self, time: Duration) -> Self {
+        self.scan_time = time;
+        self
+    }
+
+    /// Sets how long to wait before scanning again, after a scan that did not find the
+    /// device or a dropped connection. Defaults to 1 second.
+    pub fn with_reconnect_delay(self, delay: Duration) -> Self {
+        self.with_reconnect_policy(ReconnectPolicy::fixed(delay))
+    }
+
+    /// Sets how to scan again, see [`ReconnectPolicy`]. Each scan that does not find
+    /// the device counts as an attempt.
+    pub fn with_reconnect_policy(mut self, policy: ReconnectPolicy) -> Self {
+        self.reconnect = Reconnector::new(policy);
+        self
+    }
+
+    /// Reads forever, feeding every notification into `stream`.
+    ///
+    /// Only returns on decoding errors, when the host has no Bluetooth adapter or when
+    /// the reconnect policy gives up; a device that disconnects is scanned for again.
+    pub fn run(&mut self, stream: &mut TraceStream) -> Result<(), Error> {
+        FrameSource::feed(self, stream)
+    }
+
+    /// Scans for the device once and, if found, reads it until it disconnects.
+    async fn connect_and_read(&mut self, sink: &mut ChunkSink<'_>) -> Result<(), Error> {
+        let adapter = match &self.adapter {
+            Some(adapter) => adapter.clone(),
+            None => {
+                let manager = Manager::new().await.map_err(ble_error)?;
+                let adapter = manager
+                    .adapters()
+                    .await
+                    .map_err(ble_error)?
+                    .into_iter()
+                    .next()
+                    .ok_or_else(|| {
+                        io::Error::new(io::ErrorKind::NotFound, "no Bluetooth adapter")
+                    })?;
+                self.adapter.insert(adapter).clone()
+            }
+        };
+
+        match self.connect(&adapter).await {
+            Ok(Some(peripheral)) => {
+                // Bytes were lost while the device was disconnected.
+                if let Some(chunk) = self.reconnect.connected() {
+                    sink(chunk)?;
+                }
+                self.read(&peripheral, sink).await?;
+                let _ = peripheral.disconnect().await;
+                self.reconnect.disconnected();
+                return Ok(());
+            }
+            Ok(None) => {}
+            Err(e) => log::warn!("BLE connection failed: {}, retrying", e),
+        }
+        tokio::time::sleep(self.reconnect.failed()?).await;
+        Ok(())
+    }
+
+    /// Scans for the device and connects to it, `None` if it was not found.
+    async fn connect(&self, adapter: &Adapter) -> btleplug::Result<Option<Peripheral>> {
+        adapter
+            .start_scan(ScanFilter {
+                services: vec![NUS_SERVICE],
+            })
+            .await?;
+        tokio::time::sleep(self.scan_time).await;
+        adapter.stop_scan().await?;
+
+        for peripheral in adapter.peripherals().await? {
+            let Some(properties) = peripheral.properties().await? else {
+                continue;
+            };
+            if !self.matches(
+                &properties.services,
+                properties.local_name.as_deref(),
+                &peripheral.address().to_string(),
+            ) {
+                continue;
+            }
+            if !peripheral.is_connected().await? {
+                peripheral.connect().await?;
+            }
+            log::info!(
+                "Connected to BLE device {} ({})",
+                properties.local_name.as_deref().unwrap_or("unnamed"),
+                peripheral.address()
+            );
+            return Ok(Some(peripheral));
+        }
+        Ok(None)
+    }
+
+    /// Whether a device advertising `services`, with the local name `local_name` and the
+    /// address `address`, is the one to read.
+    fn matches(&self, services: &[Uuid], local_name: Option<&str>, address: &str) -> bool {
+        services.contains(&NUS_SERVICE)
+            && self
+                .name
+                .as_deref()
+                .is_none_or(|name| local_name == Some(name))
+            && self
+                .address
+                .as_deref()
+                .is_none_or(|wanted| address.eq_ignore_ascii_case(wanted))
+    }
+
+    /// Passes the TX notifications of `peripheral` to `sink` until it disconnects.
+    async fn read(&self, peripheral: &Peripheral, sink: &mut ChunkSink<'_>) -> Result<(), Error> {
+        let subscribed = async {
+            peripheral.discover_services().await?;
+            let tx = peripheral
+                .characteristics()
+                .into_iter()
+                .find(|characteristic| characteristic.uuid == NUS_TX)
+                .ok_or(btleplug::Error::NoSuchCharacteristic)?;
+            peripheral.subscribe(&tx).await?;
+            peripheral.notifications().await
+        };
+        let mut notifications = match subscribed.await {
+            Ok(notifications) => notifications,
+            Err(e) => {
+                log::warn!("BLE subscription failed: {}", e);
+                return Ok(());
+            }
+        };
+
+        loop {
+            match tokio::time::timeout(CONNECTION_CHECK_INTERVAL, notifications.next()).await {
+                Ok(Some(notification)) if notification.uuid == NUS_TX => {
+                    sink(Chunk::data(&notification.value))?;
+                }
+                Ok(Some(_)) => {}
+                Ok(None) => break,
+                Err(_) if peripheral.is_connected().await.unwrap_or(false) => {}
+                Err(_) => break,
+            }
+        }
+        log::warn!("BLE device disconnected");
+        Ok(())
+    }
+}
+
+impl FrameSource for Ble {
+    /// Scans for the device once and, if found, reads it until it disconnects, on a
+    /// runtime of its own. Never ends, like [`Ble::run`].
+    fn read_chunks(&mut self, sink: &mut ChunkSink<'_>) -> Result<bool, Error> {
+        let runtime = match self.runtime.take() {
+            Some(runtime) => runtime,
+            None => tokio::runtime::Builder::new_current_thread()
+                .enable_time()
+                .build()?,
+        };
+        let result = runtime.block_on(self.connect_and_read(sink));
+        self.runtime = Some(runtime);
+        result.map(|()| true)
+    }
+}
+
+impl AsyncFrameSource for Ble {
+    /// Scans for the device once and, if found, reads it until it disconnects. Never
+    /// ends. Needs a tokio runtime with the time driver enabled.
+    async fn read_chunks(&mut self, sink: &mut ChunkSink<'_>) -> Result<bool, Error> {
+        self.connect_and_read(sink).await.map(|()| true)
+    }
+}
+
+fn ble_error(e: btleplug::Error) -> io::Error {
+    io::Error::other(e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The Battery Service, advertised next to NUS by many devices.
+    const BATTERY_SERVICE: Uuid = Uuid::from_u128(0x0000180f_0000_1000_8000_00805f9b34fb);
+    const ADDRESS: &str = "E4:5F:01:2A:3B:4C";
+
+    #[test]
+    fn test_device_must_advertise_nus() {
+        let ble = Ble::new();
+        assert!(ble.matches(&[BATTERY_SERVICE, NUS_SERVICE], None, ADDRESS));
+        assert!(!ble.matches(&[BATTERY_SERVICE], Some("sensor-12"), ADDRESS));
+        assert!(!ble.matches(&[], None, ADDRESS));
+    }
+
+    #[test]
+    fn test_name_must_match_exactly() {
+        let ble = Ble::new().with_name("sensor-12");
+        assert!(ble.matches(&[NUS_SERVICE], Some("sensor-12"), ADDRESS));
+        assert!(!ble.matches(&[NUS_SERVICE], Some("sensor-1"), ADDRESS));
+        assert!(!ble.matches(&[NUS_SERVICE], Some("SENSOR-12"), ADDRESS));
+        assert!(!ble.matches(&[NUS_SERVICE], None, ADDRESS));
+    }
+
+    #[test]
+    fn test_address_matches_in_any_case() {
+        let ble = Ble::new().with_address("e4:5f:01:2a:3b:4c");
+        assert!(ble.matches(&[NUS_SERVICE], None, ADDRESS));
+        assert!(!ble.matches(&[NUS_SERVICE], None, "E4:5F:01:2A:3B:4D"));
+
+        let ble = ble.with_name("sensor-12");
+        assert!(ble.matches(&[NUS_SERVICE], Some("sensor-12"), ADDRESS));
+        assert!(!ble.matches(&[NUS_SERVICE], Some("sensor-13"), ADDRESS));
+    }
+
+    #[test]
+    fn test_scans_back_off_as_the_policy_says() {
+        let mut ble = Ble::new().with_reconnect_delay(Duration::from_millis(250));
+        assert_eq!(ble.reconnect.failed().unwrap(), Duration::from_millis(250));
+        assert_eq!(ble.reconnect.failed().unwrap(), Duration::from_millis(250));
+
+        let policy = ReconnectPolicy::exponential(Duration::from_secs(1), Duration::from_secs(3))
+            .with_max_attempts(4);
+        let mut ble = Ble::new().with_reconnect_policy(policy);
+        let delays: Vec<Duration> = (0..3).map(|_| ble.reconnect.failed().unwrap()).collect();
+        assert_eq!(delays, [1, 2, 3].map(Duration::from_secs));
+        assert!(ble.reconnect.failed().is_err());
+    }
+}
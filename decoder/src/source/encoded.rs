@@ -0,0 +1,102 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+use super::ascii::{decode_base64, decode_hex};
+use super::{Chunk, ChunkSink, FrameSource};
+use crate::{Error, TraceStream};
+
+/// How the raw defmt bytes are written in the lines read by [`EncodedLines`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LineEncoding {
+    /// Hex digit pairs such as `01 a4 3f`, `01a43f` or `0x01, 0xa4, 0x3f`.
+    Hex,
+    /// Standard base64, with or without padding.
+    Base64,
+}
+
+impl LineEncoding {
+    /// Decodes one line, `None` if it is not in this encoding.
+    pub fn decode(self, line: &str) -> Option<Vec<u8>> {
+        match self {
+            LineEncoding::Hex => decode_hex(line),
+            LineEncoding::Base64 => decode_base64(line),
+        }
+    }
+}
+
+/// Feeds raw defmt bytes written as hex or base64 text, one chunk per line, e.g. UART
+/// output captured with a generic terminal program in hex mode.
+///
+/// Blank lines are skipped. Any other line that does not decode, such as a header the
+/// terminal wrote, resets the stream decoder, since it may stand for bytes that are lost.
+///
+/// # Example
+/// ```rust,ignore
+/// let decoder = TraceDecoder::new(&elf)?;
+/// let mut stream = decoder.new_stream();
+/// EncodedLines::new(io::stdin().lock(), LineEncoding::Hex).run(&mut stream)?;
+/// ```
+pub struct EncodedLines {
+    reader: Box<dyn BufRead>,
+    encoding: LineEncoding,
+    invalid_lines: u64,
+}
+
+impl EncodedLines {
+    /// Opens the file at `path`.
+    pub fn open<P: AsRef<Path>>(path: P, encoding: LineEncoding) -> io::Result<Self> {
+        Ok(Self::new(BufReader::new(File::open(path)?), encoding))
+    }
+
+    /// Reads the lines from `reader`.
+    pub fn new(reader: impl BufRead + 'static, encoding: LineEncoding) -> Self {
+        Self {
+            reader: Box::new(reader),
+            encoding,
+            invalid_lines: 0,
+        }
+    }
+
+    /// The number of lines that did not decode so far.
+    pub fn invalid_lines(&self) -> u64 {
+        self.invalid_lines
+    }
+
+    /// Processes all lines, then flushes the stream. Returns the number of lines read.
+    pub fn run(&mut self, stream: &mut TraceStream) -> Result<u64, Error> {
+        let mut lines = 0;
+        while self.read_chunks(&mut |chunk| stream.process_chunk(chunk))? {
+            lines += 1;
+        }
+        stream.flush()?;
+        Ok(lines)
+    }
+}
+
+impl FrameSource for EncodedLines {
+    /// Reads one line.
+    fn read_chunks(&mut self, sink: &mut ChunkSink<'_>) -> Result<bool, Error> {
+        let mut line = String::new();
+        loop {
+            match self.reader.read_line(&mut line) {
+                Ok(0) => return Ok(false),
+                Ok(_) => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        if line.trim().is_empty() {
+            return Ok(true);
+        }
+        match self.encoding.decode(&line) {
+            Some(bytes) => sink(Chunk::data(&bytes))?,
+            None => {
+                log::debug!("Not {:?}, skipped: {}", self.encoding, line.trim_end());
+                self.invalid_lines += 1;
+                sink(Chunk::Lost { frames: None })?;
+            }
+        }
+        Ok(true)
+    }
+}
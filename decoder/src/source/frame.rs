@@ -0,0 +1,106 @@
+use std::future::Future;
+use std::time::{Duration, SystemTime};
+
+use crate::{Error, TraceStream};
+
+/// What a [`FrameSource`] read from its transport.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Chunk<'a> {
+    /// Raw defmt bytes of execution context `context`.
+    Data {
+        context: u32,
+        bytes: &'a [u8],
+        /// When the bytes arrived at the host, if the transport knows better than the
+        /// time they are decoded at, e.g. the packet timestamps of a capture. Used to
+        /// anchor device timestamps to the wall clock.
+        received_at: Option<SystemTime>,
+    },
+    /// Bytes were lost, at least `frames` frames if the transport can tell: transports
+    /// that lose whole packets, like [`Udp`](super::Udp), count the packets. The stream
+    /// decoder is reset, see [`TraceStream::report_loss`].
+    Lost { frames: Option<u64> },
+    /// The transport was re-established after it was lost, see
+    /// [`TraceStream::report_reconnect`].
+    Reconnected { attempts: u32, downtime: Duration },
+}
+
+impl<'a> Chunk<'a> {
+    /// Bytes of context 0, received just now.
+    pub fn data(bytes: &'a [u8]) -> Self {
+        Chunk::Data {
+            context: 0,
+            bytes,
+            received_at: None,
+        }
+    }
+}
+
+/// Where a [`FrameSource`] passes its chunks, typically to [`TraceStream::process_chunk`].
+pub type ChunkSink<'s> = dyn FnMut(Chunk<'_>) -> Result<(), Error> + 's;
+
+/// A transport that delivers raw defmt bytes in chunks, as all built-in sources but
+/// [`TextLines`](super::TextLines) do.
+///
+/// Implement it to plug in a transport the crate does not cover, e.g. a CAN bus or a
+/// proprietary radio bridge. A `FrameSource`, boxed or not, is a
+/// [`Source`](super::Source), so it can be run in a [`Fleet`](crate::Fleet) as well.
+///
+/// # Example
+/// ```rust,ignore
+/// impl FrameSource for CanBus {
+///     fn read_chunks(&mut self, sink: &mut ChunkSink<'_>) -> Result<bool, Error> {
+///         let frame = self.socket.read_frame()?;
+///         sink(Chunk::Data {
+///             context: 0,
+///             bytes: frame.data(),
+///             received_at: Some(frame.timestamp()),
+///         })?;
+///         Ok(true)
+///     }
+/// }
+///
+/// CanBus::open("can0")?.feed(&mut stream)?;
+/// ```
+pub trait FrameSource {
+    /// Blocks until the transport delivers something and passes it to `sink`, as any
+    /// number of chunks. Returns `false` once the transport has ended.
+    fn read_chunks(&mut self, sink: &mut ChunkSink<'_>) -> Result<bool, Error>;
+
+    /// Feeds all chunks into `stream` until the transport ends, then flushes it.
+    fn feed(&mut self, stream: &mut TraceStream) -> Result<(), Error> {
+        while self.read_chunks(&mut |chunk| stream.process_chunk(chunk))? {}
+        stream.flush()
+    }
+}
+
+impl<S: FrameSource + ?Sized> FrameSource for Box<S> {
+    fn read_chunks(&mut self, sink: &mut ChunkSink<'_>) -> Result<bool, Error> {
+        (**self).read_chunks(sink)
+    }
+
+    fn feed(&mut self, stream: &mut TraceStream) -> Result<(), Error> {
+        (**self).feed(stream)
+    }
+}
+
+/// The async flavor of [`FrameSource`], for transports that are driven by an async
+/// runtime, such as [`Ble`](super::Ble).
+pub trait AsyncFrameSource {
+    /// Waits until the transport delivers something and passes it to `sink`, as any
+    /// number of chunks. Resolves to `false` once the transport has ended.
+    fn read_chunks(
+        &mut self,
+        sink: &mut ChunkSink<'_>,
+    ) -> impl Future<Output = Result<bool, Error>>;
+
+    /// Feeds all chunks into `stream` until the transport ends, then flushes it.
+    fn feed(&mut self, stream: &mut TraceStream<'_>) -> impl Future<Output = Result<(), Error>> {
+        async move {
+            while self
+                .read_chunks(&mut |chunk| stream.process_chunk(chunk))
+                .await?
+            {}
+            stream.flush()
+        }
+    }
+}
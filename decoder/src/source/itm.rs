@@ -0,0 +1,207 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+
+use super::{Chunk, ChunkSink, FrameSource};
+use crate::{Error, TraceStream};
+
+const READ_BUFFER_SIZE: usize = 4096;
+
+/// The last byte of a synchronization packet, after at least five zero bytes.
+const SYNC_END: u8 = 0x80;
+/// An overflow packet: the ITM dropped packets because its FIFO was full.
+const OVERFLOW: u8 = 0x70;
+
+/// Counters of an [`Itm`] source.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ItmStats {
+    /// Stimulus port bytes fed into the decoder.
+    pub bytes: u64,
+    /// Packets of stimulus ports that were not selected, and DWT hardware packets.
+    pub ignored: u64,
+    /// Overflow packets, after each of which the decoder is reset.
+    pub overflows: u64,
+}
+
+/// State of the ITM packet parser between reads.
+#[derive(Copy, Clone)]
+enum State {
+    /// Expecting a packet header.
+    Header,
+    /// In a synchronization packet.
+    Sync,
+    /// In the payload of a stimulus or hardware source packet.
+    Source { port: u8, left: u8, keep: bool },
+    /// In a timestamp or extension packet, up to a byte without continuation bit.
+    Continuation,
+}
+
+/// Reads defmt bytes written to ITM stimulus ports from SWO output, e.g. a file or TCP
+/// port the probe's SWO data is forwarded to.
+///
+/// Only the payload of the selected stimulus ports is fed into the stream, port `n` to
+/// context `n`; timestamps, DWT packets and the other ports are dropped. The data must
+/// be the plain ITM packet stream, with the TPIU formatter bypassed as usual for SWO.
+///
+/// # Example
+/// ```rust,ignore
+/// let mut stream = TraceDecoder::new(&elf)?.new_stream();
+/// let swo = TcpStream::connect("127.0.0.1:3344")?;
+/// Itm::new(swo).with_stimulus_ports([0]).run(&mut stream)?;
+/// ```
+pub struct Itm {
+    reader: Box<dyn Read + Send>,
+    /// Bit `n` is set if stimulus port `n` is read.
+    ports: u32,
+    state: State,
+    /// Bytes of one port, fed into the stream together.
+    pending: Option<(u8, Vec<u8>)>,
+    stats: ItmStats,
+}
+
+impl Itm {
+    /// Opens the SWO capture at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self::new(BufReader::new(File::open(path)?)))
+    }
+
+    /// Reads SWO data from `reader`, taking stimulus port 0.
+    pub fn new(reader: impl Read + Send + 'static) -> Self {
+        Self {
+            reader: Box::new(reader),
+            ports: 1,
+            state: State::Header,
+            pending: None,
+            stats: ItmStats::default(),
+        }
+    }
+
+    /// Reads the stimulus ports in `ports` (0 to 31) instead of port 0.
+    pub fn with_stimulus_ports(mut self, ports: impl IntoIterator<Item = u8>) -> Self {
+        self.ports = ports
+            .into_iter()
+            .filter(|&port| port < 32)
+            .fold(0, |ports, port| ports | 1 << port);
+        self
+    }
+
+    /// Returns the counters collected so far.
+    pub fn stats(&self) -> ItmStats {
+        self.stats
+    }
+
+    /// Decodes everything read until the end of the data, then flushes the stream.
+    /// Returns the number of bytes read.
+    pub fn run(&mut self, stream: &mut TraceStream) -> Result<u64, Error> {
+        let mut total = 0;
+        loop {
+            match self.read(&mut |chunk| stream.process_chunk(chunk))? {
+                0 => break,
+                n => total += n as u64,
+            }
+        }
+        stream.flush()?;
+        Ok(total)
+    }
+
+    /// Reads once, returning the number of bytes read, 0 at the end of the data.
+    fn read(&mut self, sink: &mut ChunkSink<'_>) -> Result<usize, Error> {
+        let mut buf = [0u8; READ_BUFFER_SIZE];
+        loop {
+            match self.reader.read(&mut buf) {
+                Ok(n) => {
+                    self.process(&buf[..n], sink)?;
+                    return Ok(n);
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// Passes the stimulus port payload of `data` to `sink`.
+    fn process(&mut self, data: &[u8], sink: &mut ChunkSink<'_>) -> Result<(), Error> {
+        for &byte in data {
+            self.state = match self.state {
+                State::Sync if byte == 0 => State::Sync,
+                State::Sync | State::Header if byte == SYNC_END => State::Header,
+                State::Sync | State::Header => self.header(byte, sink)?,
+                State::Continuation if byte & 0x80 != 0 => State::Continuation,
+                State::Continuation => State::Header,
+                State::Source { port, left, keep } => {
+                    if keep {
+                        self.push(port, byte, sink)?;
+                    }
+                    match left - 1 {
+                        0 => State::Header,
+                        left => State::Source { port, left, keep },
+                    }
+                }
+            };
+        }
+        self.feed_pending(sink)
+    }
+
+    /// Starts the packet with header `byte`.
+    fn header(&mut self, byte: u8, sink: &mut ChunkSink<'_>) -> Result<State, Error> {
+        Ok(match byte {
+            0 => State::Sync,
+            OVERFLOW => {
+                self.feed_pending(sink)?;
+                self.stats.overflows += 1;
+                sink(Chunk::Lost { frames: None })?;
+                State::Header
+            }
+            // Protocol packets: local and global timestamps and extensions.
+            _ if byte & 0x03 == 0 && byte & 0x80 != 0 => State::Continuation,
+            _ if byte & 0x03 == 0 => State::Header,
+            _ => {
+                let port = byte >> 3;
+                let hardware = byte & 0x04 != 0;
+                let keep = !hardware && self.ports & 1 << port != 0;
+                if !keep {
+                    self.stats.ignored += 1;
+                }
+                let left = match byte & 0x03 {
+                    1 => 1,
+                    2 => 2,
+                    _ => 4,
+                };
+                State::Source { port, left, keep }
+            }
+        })
+    }
+
+    fn push(&mut self, port: u8, byte: u8, sink: &mut ChunkSink<'_>) -> Result<(), Error> {
+        if self
+            .pending
+            .as_ref()
+            .is_some_and(|(pending, _)| *pending != port)
+        {
+            self.feed_pending(sink)?;
+        }
+        self.pending
+            .get_or_insert_with(|| (port, Vec::new()))
+            .1
+            .push(byte);
+        Ok(())
+    }
+
+    fn feed_pending(&mut self, sink: &mut ChunkSink<'_>) -> Result<(), Error> {
+        if let Some((port, bytes)) = self.pending.take() {
+            self.stats.bytes += bytes.len() as u64;
+            sink(Chunk::Data {
+                context: u32::from(port),
+                bytes: &bytes,
+                received_at: None,
+            })?;
+        }
+        Ok(())
+    }
+}
+
+impl FrameSource for Itm {
+    fn read_chunks(&mut self, sink: &mut ChunkSink<'_>) -> Result<bool, Error> {
+        self.read(sink).map(|n| n > 0)
+    }
+}
@@ -0,0 +1,55 @@
+//! Input sources that read raw defmt bytes from a transport and feed them into a [`TraceStream`],
+//! and [`TextLines`] for logs that were already decoded.
+
+mod ascii;
+#[cfg(feature = "ble")]
+pub mod ble;
+pub mod encoded;
+pub mod frame;
+pub mod itm;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+pub mod pcap;
+pub mod reconnect;
+pub mod replay;
+pub mod semihosting;
+pub mod tcp;
+pub mod text;
+pub mod udp;
+#[cfg(feature = "usb")]
+pub mod usb;
+
+#[cfg(feature = "ble")]
+pub use ble::Ble;
+pub use encoded::{EncodedLines, LineEncoding};
+pub use frame::{AsyncFrameSource, Chunk, ChunkSink, FrameSource};
+pub use itm::{Itm, ItmStats};
+#[cfg(feature = "mqtt")]
+pub use mqtt::{MqttFleet, MqttPayload};
+pub use pcap::{Pcap, PcapStats};
+pub use reconnect::ReconnectPolicy;
+pub use replay::Replay;
+pub use semihosting::Semihosting;
+pub use tcp::Tcp;
+pub use text::{LineReconstructor, TextLines};
+pub use udp::{SequenceHeader, Udp, UdpStats};
+#[cfg(feature = "usb")]
+pub use usb::Usb;
+
+use crate::{Error, TraceStream};
+
+/// A transport that delivers the defmt bytes of one device, e.g. to run it in a
+/// [`Fleet`](crate::Fleet).
+///
+/// Every [`FrameSource`] is a `Source`, run by [`FrameSource::feed`], so transports
+/// the crate does not cover are easiest added as one.
+pub trait Source {
+    /// Feeds everything received into `stream` until the transport ends or fails.
+    fn run(&mut self, stream: &mut TraceStream) -> Result<(), Error>;
+}
+
+impl<S: FrameSource + ?Sized> Source for S {
+    fn run(&mut self, stream: &mut TraceStream) -> Result<(), Error> {
+        self.feed(stream)
+    }
+}
@@ -0,0 +1,270 @@
+use std::collections::HashMap;
+use std::thread;
+use std::time::Duration;
+
+use rumqttc::{Client, Connection, Event, MqttOptions, Packet, QoS};
+
+use super::ascii::decode_base64;
+use super::reconnect::{ReconnectPolicy, Reconnector};
+use crate::{Error, TraceDecoder, TraceStream};
+
+/// Requests queued to the MQTT event loop, only subscriptions here.
+const REQUEST_CAPACITY: usize = 16;
+
+type DecoderLookup = dyn FnMut(&str) -> Result<Option<TraceDecoder>, Error> + Send;
+type StreamSetup = dyn Fn(&str, &mut TraceStream<'static>) + Send + Sync;
+
+/// How the defmt bytes are carried in the messages of an [`MqttFleet`].
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum MqttPayload {
+    /// The payload is the bytes themselves.
+    #[default]
+    Raw,
+    /// The payload is the bytes in base64, for gateways that only pass on text.
+    Base64,
+}
+
+/// Decodes a fleet of devices that publish their defmt bytes to an MQTT broker, one
+/// topic per device, with a stream of their own each.
+///
+/// The source subscribes to a topic filter such as `devices/+/defmt`. The level of the
+/// topic matched by the first `+`, or the whole topic if there is none, is the device
+/// id. The first message of a device asks `decoder_for` for its decoder, e.g. built from
+/// the firmware that device runs; devices it returns `None` for are ignored.
+///
+/// Chunks are fed into the device's stream as they arrive. A device whose chunks were
+/// lost, because the connection to the broker dropped, gets its stream decoder reset.
+///
+/// # Example
+/// ```rust,ignore
+/// let mut fleet = MqttFleet::new("broker.local", 1883, "devices/+/defmt", |id| {
+///     let elf = std::fs::read(firmware_of(id))?;
+///     let builder = TraceDecoder::builder().with_device_id(id).with_otlp_endpoint(url);
+///     Ok(Some(builder.build(&elf)?))
+/// });
+/// fleet.run()?;
+/// ```
+pub struct MqttFleet {
+    host: String,
+    port: u16,
+    client_id: String,
+    credentials: Option<(String, String)>,
+    topic: String,
+    payload: MqttPayload,
+    reconnect: Reconnector,
+    decoder_for: Box<DecoderLookup>,
+    setup: Option<Box<StreamSetup>>,
+    /// The streams of the devices seen so far, `None` for ignored devices.
+    streams: HashMap<String, Option<TraceStream<'static>>>,
+    connection: Option<(Client, Connection)>,
+}
+
+impl MqttFleet {
+    /// Subscribes to `topic` at the broker at `host` and `port`, once polled.
+    pub fn new(
+        host: impl Into<String>,
+        port: u16,
+        topic: impl Into<String>,
+        decoder_for: impl FnMut(&str) -> Result<Option<TraceDecoder>, Error> + Send + 'static,
+    ) -> Self {
+        Self {
+            host: host.into(),
+            port,
+            client_id: format!("tracing-defmt-{}", std::process::id()),
+            credentials: None,
+            topic: topic.into(),
+            payload: MqttPayload::default(),
+            reconnect: Reconnector::new(ReconnectPolicy::default()),
+            decoder_for: Box::new(decoder_for),
+            setup: None,
+            streams: HashMap::new(),
+            connection: None,
+        }
+    }
+
+    /// Sets the MQTT client id. Defaults to `tracing-defmt-<process id>`.
+    pub fn with_client_id(mut self, client_id: impl Into<String>) -> Self {
+        self.client_id = client_id.into();
+        self
+    }
+
+    /// Logs in to the broker with a user name and password.
+    pub fn with_credentials(
+        mut self,
+        username: impl Into<String>,
+        password: impl Into<String>,
+    ) -> Self {
+        self.credentials = Some((username.into(), password.into()));
+        self
+    }
+
+    /// Sets how the bytes are carried in the messages. Defaults to [`MqttPayload::Raw`].
+    pub fn with_payload(mut self, payload: MqttPayload) -> Self {
+        self.payload = payload;
+        self
+    }
+
+    /// Sets how long to wait between failed connection attempts. Defaults to 1 second.
+    pub fn with_reconnect_delay(self, delay: Duration) -> Self {
+        self.with_reconnect_policy(ReconnectPolicy::fixed(delay))
+    }
+
+    /// Sets how to reconnect to the broker, see [`ReconnectPolicy`]. A reconnect resets
+    /// the stream decoder of every device.
+    pub fn with_reconnect_policy(mut self, policy: ReconnectPolicy) -> Self {
+        self.reconnect = Reconnector::new(policy);
+        self
+    }
+
+    /// Calls `setup` with the id and stream of every device when its first message
+    /// arrives, e.g. to add exporters or report its health.
+    pub fn on_stream(
+        mut self,
+        setup: impl Fn(&str, &mut TraceStream<'static>) + Send + Sync + 'static,
+    ) -> Self {
+        self.setup = Some(Box::new(setup));
+        self
+    }
+
+    /// The ids of the devices decoded so far.
+    pub fn device_ids(&self) -> impl Iterator<Item = &str> {
+        self.streams
+            .iter()
+            .filter(|(_, stream)| stream.is_some())
+            .map(|(id, _)| id.as_str())
+    }
+
+    /// Receives and decodes messages forever, reconnecting to the broker as needed.
+    ///
+    /// Failing devices are logged and do not stop the others, so this only returns
+    /// when the subscription cannot be made or the reconnect policy gives up.
+    pub fn run(&mut self) -> Result<(), Error> {
+        loop {
+            self.poll()?;
+        }
+    }
+
+    /// Blocks until the next event from the broker and handles it.
+    ///
+    /// Returns the number of bytes fed into a device's stream, 0 for anything but a
+    /// message from a device.
+    pub fn poll(&mut self) -> Result<usize, Error> {
+        if self.connection.is_none() {
+            let mut options = MqttOptions::new(&self.client_id, &self.host, self.port);
+            if let Some((username, password)) = &self.credentials {
+                options.set_credentials(username, password);
+            }
+            self.connection = Some(Client::new(options, REQUEST_CAPACITY));
+        }
+        let (client, connection) = self.connection.as_mut().unwrap();
+        let event = match connection.recv() {
+            Ok(event) => event,
+            // The event loop is gone, which only happens once the client is dropped.
+            Err(_) => {
+                self.connection = None;
+                return Ok(0);
+            }
+        };
+        match event {
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                log::info!("Connected to MQTT broker, subscribing to {}", self.topic);
+                // Messages published in the meantime are lost.
+                if let Some(chunk) = self.reconnect.connected() {
+                    for stream in self.streams.values_mut().flatten() {
+                        stream.process_chunk(chunk.clone())?;
+                    }
+                }
+                // Subscriptions do not outlast a clean session.
+                client
+                    .try_subscribe(self.topic.as_str(), QoS::AtMostOnce)
+                    .map_err(|e| Error::Io(std::io::Error::other(e)))?;
+                Ok(0)
+            }
+            Ok(Event::Incoming(Packet::Publish(publish))) => {
+                let device = device_id(&self.topic, &publish.topic).to_string();
+                self.handle_message(device, &publish.payload)
+            }
+            Ok(_) => Ok(0),
+            Err(e) => {
+                self.reconnect.disconnected();
+                let delay = self.reconnect.failed()?;
+                log::warn!("MQTT connection lost: {}, reconnecting in {:?}", e, delay);
+                thread::sleep(delay);
+                Ok(0)
+            }
+        }
+    }
+
+    /// Closes the open spans of every device and flushes its exporters.
+    pub fn finish(self) -> Result<(), Error> {
+        let mut result = Ok(());
+        for (id, stream) in self.streams {
+            let Some(stream) = stream else {
+                continue;
+            };
+            if let Err(e) = stream.finish() {
+                log::warn!("Device {} failed: {}", id, e);
+                if result.is_ok() {
+                    result = Err(Error::Device {
+                        device: id,
+                        source: Box::new(e),
+                    });
+                }
+            }
+        }
+        result
+    }
+
+    fn handle_message(&mut self, device: String, payload: &[u8]) -> Result<usize, Error> {
+        let bytes = match self.payload {
+            MqttPayload::Raw => payload.to_vec(),
+            MqttPayload::Base64 => {
+                match std::str::from_utf8(payload).ok().and_then(decode_base64) {
+                    Some(bytes) => bytes,
+                    None => {
+                        log::warn!("Device {}: message is not base64, dropped", device);
+                        return Ok(0);
+                    }
+                }
+            }
+        };
+
+        if !self.streams.contains_key(&device) {
+            let stream = match (self.decoder_for)(&device) {
+                Ok(Some(decoder)) => {
+                    log::info!("Decoding device {}", device);
+                    let mut stream = decoder.into_stream();
+                    if let Some(setup) = &self.setup {
+                        setup(&device, &mut stream);
+                    }
+                    Some(stream)
+                }
+                Ok(None) => None,
+                Err(e) => {
+                    log::warn!("Device {}: no decoder: {}", device, e);
+                    None
+                }
+            };
+            self.streams.insert(device.clone(), stream);
+        }
+        let Some(stream) = self.streams.get_mut(&device).and_then(Option::as_mut) else {
+            return Ok(0);
+        };
+        if let Err(e) = stream.process(&bytes) {
+            log::warn!("Device {} failed: {}", device, e);
+            stream.reset();
+            return Ok(0);
+        }
+        Ok(bytes.len())
+    }
+}
+
+/// The device id in `topic`: the level matched by the first `+` of `filter`, or the
+/// whole topic.
+fn device_id<'t>(filter: &str, topic: &'t str) -> &'t str {
+    filter
+        .split('/')
+        .position(|level| level == "+")
+        .and_then(|i| topic.split('/').nth(i))
+        .unwrap_or(topic)
+}
@@ -0,0 +1,431 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::{Chunk, ChunkSink, FrameSource};
+use crate::{Error, TraceStream};
+
+/// Block type of a pcapng Section Header Block, the same in both byte orders.
+const SECTION_HEADER: u32 = 0x0a0d_0d0a;
+/// Byte-order magic of a pcapng section, as written by the capturing host.
+const BYTE_ORDER_MAGIC: u32 = 0x1a2b_3c4d;
+const INTERFACE_DESCRIPTION: u32 = 1;
+const OBSOLETE_PACKET: u32 = 2;
+const SIMPLE_PACKET: u32 = 3;
+const ENHANCED_PACKET: u32 = 6;
+
+/// Magic numbers of classic pcap files, with microsecond and nanosecond timestamps.
+const PCAP_MAGIC: [u32; 2] = [0xa1b2_c3d4, 0xa1b2_3c4d];
+/// The pcapng interface option giving the timestamp resolution.
+const IF_TSRESOL: u16 = 9;
+
+/// Link types whose packets carry a USB header before the transferred data.
+const LINKTYPE_USB_LINUX: u32 = 189;
+const LINKTYPE_USB_LINUX_MMAPPED: u32 = 220;
+const LINKTYPE_USBPCAP: u32 = 249;
+
+/// Blocks larger than this are taken for corrupted lengths.
+const MAX_BLOCK_SIZE: usize = 16 << 20;
+
+/// Counters of a [`Pcap`] source.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct PcapStats {
+    /// Packets whose payload was fed into the decoder.
+    pub packets: u64,
+    /// Packets without defmt data: USB requests, control transfers, other endpoints or
+    /// interfaces.
+    pub skipped: u64,
+    /// Packets cut short by the capture's snapshot length, after which the decoder is reset.
+    pub truncated: u64,
+    /// Payload bytes fed into the decoder.
+    pub bytes: u64,
+}
+
+/// Extracts defmt bytes from a pcap or pcapng capture, e.g. a UART decode exported by
+/// sigrok or a USB capture of a CDC ACM port, taken without a decoder attached.
+///
+/// USB captures (Linux usbmon and USBPcap link types) yield the data of completed
+/// bulk and interrupt IN transfers, optionally of one device and endpoint only. The
+/// packets of other link types, such as the `USER0` to `USER15` types that logic
+/// analyzer exports use, are taken as raw defmt bytes. The packets of pcapng interface
+/// `n` go to context `n` of the stream.
+///
+/// The packet timestamps are passed on as the receive time of the bytes, so wall clock
+/// anchoring places the records at the time they were captured.
+///
+/// # Example
+/// ```rust,ignore
+/// let mut stream = TraceDecoder::new(&elf)?.new_stream();
+/// Pcap::open("soak.pcapng")?.with_usb_endpoint(0x81).run(&mut stream)?;
+/// ```
+pub struct Pcap {
+    reader: Box<dyn Read + Send>,
+    interface: Option<u32>,
+    usb_device: Option<u16>,
+    usb_endpoint: Option<u8>,
+    stats: PcapStats,
+}
+
+/// A pcapng interface of the current section.
+#[derive(Copy, Clone)]
+struct Interface {
+    linktype: u32,
+    snaplen: u32,
+    /// Timestamp units per second, `None` if unknown or too fine to represent.
+    resolution: Option<u64>,
+}
+
+/// Byte order of the capture being read.
+#[derive(Copy, Clone)]
+enum Order {
+    Little,
+    Big,
+}
+
+impl Order {
+    fn u16(self, bytes: &[u8]) -> u16 {
+        let bytes = [bytes[0], bytes[1]];
+        match self {
+            Order::Little => u16::from_le_bytes(bytes),
+            Order::Big => u16::from_be_bytes(bytes),
+        }
+    }
+
+    fn u32(self, bytes: &[u8]) -> u32 {
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        match self {
+            Order::Little => u32::from_le_bytes(bytes),
+            Order::Big => u32::from_be_bytes(bytes),
+        }
+    }
+}
+
+impl Pcap {
+    /// Opens the capture file at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self::new(BufReader::new(File::open(path)?)))
+    }
+
+    /// Reads the capture from `reader`.
+    pub fn new(reader: impl Read + Send + 'static) -> Self {
+        Self {
+            reader: Box::new(reader),
+            interface: None,
+            usb_device: None,
+            usb_endpoint: None,
+            stats: PcapStats::default(),
+        }
+    }
+
+    /// Only reads the packets of pcapng interface `id`, which go to context 0.
+    pub fn with_interface(mut self, id: u32) -> Self {
+        self.interface = Some(id);
+        self
+    }
+
+    /// Only reads the USB transfers of the device with bus address `address`.
+    pub fn with_usb_device(mut self, address: u16) -> Self {
+        self.usb_device = Some(address);
+        self
+    }
+
+    /// Only reads the USB transfers of IN endpoint `endpoint`, e.g. `0x81`.
+    pub fn with_usb_endpoint(mut self, endpoint: u8) -> Self {
+        self.usb_endpoint = Some(endpoint | 0x80);
+        self
+    }
+
+    /// Returns the counters collected so far.
+    pub fn stats(&self) -> PcapStats {
+        self.stats
+    }
+
+    /// Decodes the whole capture, then flushes the stream. Returns the number of payload
+    /// bytes fed into it.
+    ///
+    /// A capture that ends in the middle of a packet, e.g. one still being written, ends
+    /// there without error.
+    pub fn run(&mut self, stream: &mut TraceStream) -> Result<u64, Error> {
+        self.read_capture(&mut |chunk| stream.process_chunk(chunk))?;
+        stream.flush()?;
+        Ok(self.stats.bytes)
+    }
+
+    fn read_capture(&mut self, sink: &mut ChunkSink<'_>) -> Result<(), Error> {
+        let mut magic = [0; 4];
+        if !self.read_exact(&mut magic)? {
+            return Ok(());
+        }
+        if u32::from_le_bytes(magic) == SECTION_HEADER {
+            return self.read_pcapng(sink);
+        }
+        let (order, magic) = if PCAP_MAGIC.contains(&u32::from_le_bytes(magic)) {
+            (Order::Little, u32::from_le_bytes(magic))
+        } else if PCAP_MAGIC.contains(&u32::from_be_bytes(magic)) {
+            (Order::Big, u32::from_be_bytes(magic))
+        } else {
+            return Err(invalid("neither a pcap nor a pcapng capture").into());
+        };
+        let resolution = if magic == PCAP_MAGIC[0] {
+            1_000_000
+        } else {
+            1_000_000_000
+        };
+        self.read_pcap(order, resolution, sink)
+    }
+
+    fn read_pcap(
+        &mut self,
+        order: Order,
+        resolution: u64,
+        sink: &mut ChunkSink<'_>,
+    ) -> Result<(), Error> {
+        // The rest of the file header, up to the link type.
+        let mut header = [0; 20];
+        if !self.read_exact(&mut header)? {
+            return Ok(());
+        }
+        let linktype = order.u32(&header[16..]) & 0xffff;
+        let mut record = [0; 16];
+        while self.read_exact(&mut record)? {
+            let seconds = u64::from(order.u32(&record));
+            let fraction = order.u32(&record[4..]);
+            let captured = order.u32(&record[8..]) as usize;
+            let original = order.u32(&record[12..]) as usize;
+            let Some(packet) = self.read_block(captured)? else {
+                break;
+            };
+            let received_at = time_at(seconds * resolution + u64::from(fraction), resolution);
+            let truncated = captured < original;
+            self.packet(sink, 0, linktype, &packet, truncated, received_at)?;
+        }
+        Ok(())
+    }
+
+    fn read_pcapng(&mut self, sink: &mut ChunkSink<'_>) -> Result<(), Error> {
+        let mut order = Order::Little;
+        // The interfaces of the current section, by interface id.
+        let mut interfaces: Vec<Interface> = Vec::new();
+        // The type of the first block was read by `run`.
+        let mut block_type = SECTION_HEADER;
+        loop {
+            let mut length = [0; 4];
+            if !self.read_exact(&mut length)? {
+                break;
+            }
+            if block_type == SECTION_HEADER {
+                let mut magic = [0; 4];
+                if !self.read_exact(&mut magic)? {
+                    break;
+                }
+                order = match u32::from_le_bytes(magic) {
+                    BYTE_ORDER_MAGIC => Order::Little,
+                    _ if u32::from_be_bytes(magic) == BYTE_ORDER_MAGIC => Order::Big,
+                    _ => return Err(invalid("bad pcapng byte-order magic").into()),
+                };
+                interfaces.clear();
+            }
+            let length = order.u32(&length) as usize;
+            // The block type and the lengths before and after the body, and the
+            // byte-order magic read above.
+            let consumed = if block_type == SECTION_HEADER { 12 } else { 8 };
+            if length < consumed + 4 || !length.is_multiple_of(4) {
+                return Err(invalid("bad pcapng block length").into());
+            }
+            let Some(body) = self.read_block(length - consumed)? else {
+                break;
+            };
+
+            match block_type {
+                INTERFACE_DESCRIPTION if body.len() >= 8 => interfaces.push(Interface {
+                    linktype: u32::from(order.u16(&body)),
+                    snaplen: order.u32(&body[4..]),
+                    resolution: timestamp_resolution(order, &body[8..]),
+                }),
+                ENHANCED_PACKET | OBSOLETE_PACKET if body.len() >= 20 => {
+                    let interface = match block_type {
+                        ENHANCED_PACKET => order.u32(&body),
+                        _ => u32::from(order.u16(&body)),
+                    };
+                    let timestamp =
+                        u64::from(order.u32(&body[4..])) << 32 | u64::from(order.u32(&body[8..]));
+                    let captured = order.u32(&body[12..]) as usize;
+                    let original = order.u32(&body[16..]) as usize;
+                    let Some(packet) = body.get(20..20 + captured) else {
+                        return Err(invalid("pcapng packet larger than its block").into());
+                    };
+                    let description = interfaces.get(interface as usize);
+                    let linktype = description.map_or(0, |i| i.linktype);
+                    let received_at = description
+                        .and_then(|i| i.resolution)
+                        .and_then(|resolution| time_at(timestamp, resolution));
+                    let truncated = captured < original;
+                    self.packet(sink, interface, linktype, packet, truncated, received_at)?;
+                }
+                SIMPLE_PACKET if body.len() >= 4 => {
+                    let original = order.u32(&body) as usize;
+                    let (linktype, snaplen) = interfaces
+                        .first()
+                        .map_or((0, 0), |i| (i.linktype, i.snaplen));
+                    let captured = match snaplen {
+                        0 => original,
+                        snaplen => original.min(snaplen as usize),
+                    };
+                    let Some(packet) = body.get(4..4 + captured) else {
+                        return Err(invalid("pcapng packet larger than its block").into());
+                    };
+                    // Simple packets have no timestamp.
+                    self.packet(sink, 0, linktype, packet, captured < original, None)?;
+                }
+                _ => {}
+            }
+
+            let mut next = [0; 4];
+            if !self.read_exact(&mut next)? {
+                break;
+            }
+            // Only a section header has the same type in both byte orders.
+            block_type = match u32::from_le_bytes(next) {
+                SECTION_HEADER => SECTION_HEADER,
+                _ => order.u32(&next),
+            };
+        }
+        Ok(())
+    }
+
+    /// Passes the defmt bytes of `packet`, if any, to `sink`.
+    fn packet(
+        &mut self,
+        sink: &mut ChunkSink<'_>,
+        interface: u32,
+        linktype: u32,
+        packet: &[u8],
+        truncated: bool,
+        received_at: Option<SystemTime>,
+    ) -> Result<(), Error> {
+        let context = match self.interface {
+            Some(id) if id != interface => {
+                self.stats.skipped += 1;
+                return Ok(());
+            }
+            Some(_) => 0,
+            None => interface,
+        };
+        let Some(payload) = self.payload(linktype, packet) else {
+            self.stats.skipped += 1;
+            return Ok(());
+        };
+        self.stats.packets += 1;
+        self.stats.bytes += payload.len() as u64;
+        sink(Chunk::Data {
+            context,
+            bytes: payload,
+            received_at,
+        })?;
+        if truncated {
+            // The rest of the packet is missing, so is the end of the frame it was in.
+            self.stats.truncated += 1;
+            sink(Chunk::Lost { frames: None })?;
+        }
+        Ok(())
+    }
+
+    /// The defmt bytes of a packet: the data of a completed USB IN transfer, or the
+    /// whole packet for link types without a header.
+    fn payload<'p>(&self, linktype: u32, packet: &'p [u8]) -> Option<&'p [u8]> {
+        let (header_len, completed, transfer, device, endpoint) = match linktype {
+            LINKTYPE_USB_LINUX | LINKTYPE_USB_LINUX_MMAPPED => {
+                // Written in the byte order of the capturing host, little endian on
+                // anything that runs usbmon these days.
+                let header_len = if linktype == LINKTYPE_USB_LINUX {
+                    48
+                } else {
+                    64
+                };
+                let header = packet.get(..header_len)?;
+                let device = u16::from(header[11]);
+                (header_len, header[8] == b'C', header[9], device, header[10])
+            }
+            LINKTYPE_USBPCAP => {
+                let header = packet.get(..27)?;
+                let header_len = usize::from(u16::from_le_bytes([header[0], header[1]]));
+                let device = u16::from_le_bytes([header[19], header[20]]);
+                (
+                    header_len,
+                    header[16] & 1 == 1,
+                    header[22],
+                    device,
+                    header[21],
+                )
+            }
+            _ => return Some(packet),
+        };
+        // Bulk or interrupt data sent by the device.
+        let wanted = completed
+            && matches!(transfer, 1 | 3)
+            && endpoint & 0x80 != 0
+            && self.usb_device.is_none_or(|address| address == device)
+            && self.usb_endpoint.is_none_or(|wanted| wanted == endpoint);
+        wanted
+            .then(|| packet.get(header_len..))
+            .flatten()
+            .filter(|data| !data.is_empty())
+    }
+
+    /// Fills `buf`, returning `false` at the end of the capture, even in the middle of `buf`.
+    fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<bool> {
+        match self.reader.read_exact(buf) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Reads the next `len` bytes, `None` at the end of the capture.
+    fn read_block(&mut self, len: usize) -> io::Result<Option<Vec<u8>>> {
+        if len > MAX_BLOCK_SIZE {
+            return Err(invalid("pcap block too large"));
+        }
+        let mut block = vec![0; len];
+        Ok(self.read_exact(&mut block)?.then_some(block))
+    }
+}
+
+impl FrameSource for Pcap {
+    /// Reads the whole capture at once.
+    fn read_chunks(&mut self, sink: &mut ChunkSink<'_>) -> Result<bool, Error> {
+        self.read_capture(sink)?;
+        Ok(false)
+    }
+}
+
+/// Timestamp units per second of a pcapng interface with `options`: microseconds unless
+/// given by its `if_tsresol` option.
+fn timestamp_resolution(order: Order, mut options: &[u8]) -> Option<u64> {
+    while options.len() >= 4 {
+        let code = order.u16(options);
+        let len = usize::from(order.u16(&options[2..]));
+        let value = options.get(4..4 + len)?;
+        if code == IF_TSRESOL && len == 1 {
+            let exponent = u32::from(value[0] & 0x7f);
+            return match value[0] & 0x80 {
+                0 => 10u64.checked_pow(exponent),
+                _ => 1u64.checked_shl(exponent),
+            };
+        }
+        options = options.get((4 + len).next_multiple_of(4)..)?;
+    }
+    Some(1_000_000)
+}
+
+/// The time `timestamp` units of `resolution` per second after the epoch.
+fn time_at(timestamp: u64, resolution: u64) -> Option<SystemTime> {
+    let seconds = timestamp / resolution;
+    let nanos = u128::from(timestamp % resolution) * 1_000_000_000 / u128::from(resolution);
+    UNIX_EPOCH.checked_add(Duration::new(seconds, nanos as u32))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
@@ -0,0 +1,192 @@
+use std::io;
+use std::time::{Duration, Instant};
+
+use super::Chunk;
+use crate::Error;
+
+const DEFAULT_DELAY: Duration = Duration::from_secs(1);
+
+/// How a source that loses its transport gets it back: how long it waits between
+/// attempts, how many it makes, and what the trace shows of the outage.
+///
+/// Once reconnected, the stream decoder is reset, since the frame in flight is lost,
+/// and by default the trace gets a warning event saying how long the link was down,
+/// see [`TraceStream::report_reconnect`](crate::TraceStream::report_reconnect).
+///
+/// # Example
+/// ```rust,ignore
+/// // 100 ms, 200 ms, 400 ms, ... up to a minute between attempts, forever.
+/// let policy = ReconnectPolicy::exponential(Duration::from_millis(100), Duration::from_secs(60));
+/// let tcp = Tcp::connect("127.0.0.1:19021")?.with_reconnect_policy(policy);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReconnectPolicy {
+    initial_delay: Duration,
+    max_delay: Duration,
+    max_attempts: Option<u32>,
+    annotate: bool,
+}
+
+impl Default for ReconnectPolicy {
+    /// A second between attempts, forever.
+    fn default() -> Self {
+        Self::fixed(DEFAULT_DELAY)
+    }
+}
+
+impl ReconnectPolicy {
+    /// Waits `delay` between attempts.
+    pub fn fixed(delay: Duration) -> Self {
+        Self::exponential(delay, delay)
+    }
+
+    /// Waits `initial_delay` after the first failed attempt, and twice as long after
+    /// each further one, up to `max_delay`.
+    pub fn exponential(initial_delay: Duration, max_delay: Duration) -> Self {
+        Self {
+            initial_delay,
+            max_delay: max_delay.max(initial_delay),
+            max_attempts: None,
+            annotate: true,
+        }
+    }
+
+    /// Gives up after `attempts` failed attempts in a row, failing the source's `run`.
+    pub fn with_max_attempts(mut self, attempts: u32) -> Self {
+        self.max_attempts = Some(attempts);
+        self
+    }
+
+    /// Whether a reconnect is annotated in the trace, or only resets the stream
+    /// decoder. Defaults to `true`.
+    pub fn with_annotation(mut self, annotate: bool) -> Self {
+        self.annotate = annotate;
+        self
+    }
+
+    /// The delay after `failures` failed attempts in a row.
+    fn delay(&self, failures: u32) -> Duration {
+        let doublings = failures.saturating_sub(1).min(31);
+        self.initial_delay
+            .saturating_mul(1 << doublings)
+            .min(self.max_delay)
+    }
+}
+
+/// Follows a [`ReconnectPolicy`] for one transport.
+#[derive(Debug)]
+pub(crate) struct Reconnector {
+    policy: ReconnectPolicy,
+    /// Failed attempts since the last connection.
+    failures: u32,
+    /// When the transport was lost, if it was connected before.
+    lost_at: Option<Instant>,
+}
+
+impl Reconnector {
+    pub(crate) fn new(policy: ReconnectPolicy) -> Self {
+        Self {
+            policy,
+            failures: 0,
+            lost_at: None,
+        }
+    }
+
+    /// Records a failed attempt, returning how long to wait before the next one, or an
+    /// error if the policy gives up.
+    pub(crate) fn failed(&mut self) -> Result<Duration, Error> {
+        self.failures += 1;
+        match self.policy.max_attempts {
+            Some(max) if self.failures >= max => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("gave up reconnecting after {} attempts", self.failures),
+            )
+            .into()),
+            _ => Ok(self.policy.delay(self.failures)),
+        }
+    }
+
+    /// Records that the transport was lost.
+    pub(crate) fn disconnected(&mut self) {
+        self.lost_at.get_or_insert_with(Instant::now);
+    }
+
+    /// Records a connection, returning the chunk that resynchronizes the stream if it
+    /// replaces a lost one.
+    pub(crate) fn connected(&mut self) -> Option<Chunk<'static>> {
+        let attempts = std::mem::take(&mut self.failures) + 1;
+        let downtime = self.lost_at.take()?.elapsed();
+        Some(match self.policy.annotate {
+            true => Chunk::Reconnected { attempts, downtime },
+            false => Chunk::Lost { frames: None },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: Duration = Duration::from_millis(1);
+
+    #[test]
+    fn test_exponential_delay_doubles_up_to_the_maximum() {
+        let policy = ReconnectPolicy::exponential(100 * MS, 1000 * MS);
+        let delays: Vec<Duration> = (1..=6).map(|failures| policy.delay(failures)).collect();
+        assert_eq!(
+            delays,
+            [100 * MS, 200 * MS, 400 * MS, 800 * MS, 1000 * MS, 1000 * MS]
+        );
+        // No overflow, however long the device stays away.
+        assert_eq!(policy.delay(u32::MAX), 1000 * MS);
+    }
+
+    #[test]
+    fn test_fixed_delay_does_not_grow() {
+        let policy = ReconnectPolicy::fixed(250 * MS);
+        assert_eq!(policy.delay(1), 250 * MS);
+        assert_eq!(policy.delay(40), 250 * MS);
+        // A maximum below the initial delay is raised to it.
+        let policy = ReconnectPolicy::exponential(250 * MS, 10 * MS);
+        assert_eq!(policy.delay(3), 250 * MS);
+    }
+
+    #[test]
+    fn test_reconnector_gives_up_after_max_attempts() {
+        let policy = ReconnectPolicy::exponential(10 * MS, 100 * MS).with_max_attempts(3);
+        let mut reconnector = Reconnector::new(policy);
+        assert_eq!(reconnector.failed().unwrap(), 10 * MS);
+        assert_eq!(reconnector.failed().unwrap(), 20 * MS);
+        match reconnector.failed() {
+            Err(Error::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::TimedOut),
+            other => panic!("expected to give up, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_connection_resets_the_backoff() {
+        let mut reconnector = Reconnector::new(ReconnectPolicy::exponential(10 * MS, 100 * MS));
+        // The first connection replaces nothing.
+        assert!(reconnector.connected().is_none());
+
+        reconnector.disconnected();
+        reconnector.failed().unwrap();
+        reconnector.failed().unwrap();
+        assert!(matches!(
+            reconnector.connected(),
+            Some(Chunk::Reconnected { attempts: 3, .. })
+        ));
+        assert_eq!(reconnector.failed().unwrap(), 10 * MS);
+    }
+
+    #[test]
+    fn test_unannotated_reconnect_only_marks_a_loss() {
+        let policy = ReconnectPolicy::default().with_annotation(false);
+        let mut reconnector = Reconnector::new(policy);
+        reconnector.disconnected();
+        assert!(matches!(
+            reconnector.connected(),
+            Some(Chunk::Lost { frames: None })
+        ));
+    }
+}
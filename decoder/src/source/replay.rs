@@ -0,0 +1,154 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::{Chunk, ChunkSink, FrameSource};
+use crate::{Error, TraceRecord, TraceStream};
+
+const READ_BUFFER_SIZE: usize = 4096;
+
+/// The first bytes of a gzip file.
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+/// The first bytes of a zstd frame.
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// Feeds a previously captured raw defmt byte stream through the decoder.
+///
+/// By default the capture is decoded as fast as it can be read. With
+/// [`with_realtime`](Self::with_realtime) records are emitted at the pace given by their
+/// device timestamps, so live dashboards see the capture as it originally happened.
+///
+/// # Example
+/// ```rust,ignore
+/// let decoder = TraceDecoder::new(&elf)?;
+/// let mut stream = decoder.new_stream();
+/// Replay::open("capture.bin")?.run(&mut stream)?;
+/// ```
+pub struct Replay {
+    reader: Box<dyn Read + Send>,
+    realtime: bool,
+    // Device timestamp and host instant the pacing is anchored to.
+    anchor: Option<(u64, Instant)>,
+    last_timestamp: u64,
+}
+
+impl Replay {
+    /// Opens the capture file at `path`. Captures compressed with gzip or zstd, e.g. by
+    /// [`create_compressed_capture_file`](crate::capture::create_compressed_capture_file),
+    /// are decompressed on the fly with the `compression` feature.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let start = reader.fill_buf()?;
+        if start.starts_with(GZIP_MAGIC) {
+            // Captures appended to each other are gzip files of several members.
+            #[cfg(feature = "compression")]
+            return Ok(Self::new(flate2::bufread::MultiGzDecoder::new(reader)));
+            #[cfg(not(feature = "compression"))]
+            return Err(needs_compression("gzip"));
+        }
+        if start.starts_with(ZSTD_MAGIC) {
+            #[cfg(feature = "compression")]
+            return Ok(Self::new(zstd::Decoder::with_buffer(reader)?));
+            #[cfg(not(feature = "compression"))]
+            return Err(needs_compression("zstd"));
+        }
+        Ok(Self::new(reader))
+    }
+
+    /// Replays the bytes read from `reader`.
+    pub fn new(reader: impl Read + Send + 'static) -> Self {
+        Self {
+            reader: Box::new(reader),
+            realtime: false,
+            anchor: None,
+            last_timestamp: 0,
+        }
+    }
+
+    /// Paces records by their device timestamps instead of decoding at full speed.
+    ///
+    /// Records without a timestamp are emitted immediately. A timestamp going backwards
+    /// (e.g. a device reboot during the capture) restarts the pacing.
+    pub fn with_realtime(mut self, realtime: bool) -> Self {
+        self.realtime = realtime;
+        self
+    }
+
+    /// Decodes the whole capture, then flushes the stream. Returns the number of bytes read.
+    pub fn run(&mut self, stream: &mut TraceStream) -> Result<u64, Error> {
+        let mut buf = [0u8; READ_BUFFER_SIZE];
+        let mut total = 0;
+        loop {
+            let n = match self.reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+            total += n as u64;
+            if self.realtime {
+                stream.process_with(&buf[..n], |record| self.wait_for(record))?;
+            } else {
+                stream.process(&buf[..n])?;
+            }
+        }
+        stream.flush()?;
+        Ok(total)
+    }
+
+    fn wait_for(&mut self, record: &TraceRecord) {
+        let Some(timestamp) = record.timestamp else {
+            return;
+        };
+        let previous = std::mem::replace(&mut self.last_timestamp, timestamp);
+        let (base, started) = match self.anchor {
+            Some((base, started)) if timestamp >= previous => (base, started),
+            _ => {
+                self.anchor = Some((timestamp, Instant::now()));
+                return;
+            }
+        };
+        let due = started + Duration::from_micros(timestamp - base);
+        if let Some(delay) = due.checked_duration_since(Instant::now()) {
+            thread::sleep(delay);
+        }
+    }
+}
+
+impl FrameSource for Replay {
+    /// Reads the capture at full speed: the pacing of
+    /// [`with_realtime`](Replay::with_realtime) needs the decoded timestamps, so it only
+    /// applies to [`Replay::run`] and `feed`.
+    fn read_chunks(&mut self, sink: &mut ChunkSink<'_>) -> Result<bool, Error> {
+        let mut buf = [0u8; READ_BUFFER_SIZE];
+        loop {
+            match self.reader.read(&mut buf) {
+                Ok(0) => return Ok(false),
+                Ok(n) => {
+                    sink(Chunk::data(&buf[..n]))?;
+                    return Ok(true);
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// Same as [`Replay::run`].
+    fn feed(&mut self, stream: &mut TraceStream) -> Result<(), Error> {
+        Replay::run(self, stream).map(drop)
+    }
+}
+
+#[cfg(not(feature = "compression"))]
+fn needs_compression(format: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "the capture is compressed with {}, which needs the `compression` feature",
+            format
+        ),
+    )
+}
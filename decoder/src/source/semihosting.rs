@@ -0,0 +1,97 @@
+use std::io::{self, Read};
+use std::process::{Child, Command, ExitStatus, Stdio};
+
+use super::{Chunk, ChunkSink, FrameSource};
+use crate::{Error, TraceStream};
+
+const READ_BUFFER_SIZE: usize = 4096;
+
+/// Reads the defmt bytes firmware writes to the host's stdout through semihosting, e.g.
+/// with `defmt-semihosting`, from an emulator it runs or any other reader.
+///
+/// [`spawn`](Self::spawn) runs the emulator, typically QEMU with `-semihosting-config
+/// enable=on,target=native`, and reads its stdout until it exits; its stderr is left
+/// alone, so QEMU's own messages still show. Semihosting output routed elsewhere, e.g. by
+/// OpenOCD's `arm semihosting_redirect tcp`, can be read with [`new`](Self::new).
+///
+/// # Example
+/// ```rust,ignore
+/// let mut qemu = Command::new("qemu-system-arm");
+/// qemu.args(["-machine", "lm3s6965evb", "-nographic"])
+///     .args(["-semihosting-config", "enable=on,target=native", "-kernel", "firmware"]);
+/// let status = Semihosting::spawn(qemu)?.run(&mut stream)?;
+/// ```
+pub struct Semihosting {
+    reader: Box<dyn Read + Send>,
+    /// The emulator, if spawned by [`Semihosting::spawn`].
+    child: Option<Child>,
+}
+
+impl Semihosting {
+    /// Starts `command` with its stdout piped to the decoder.
+    pub fn spawn(mut command: Command) -> io::Result<Self> {
+        let mut child = command
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let stdout = child.stdout.take().expect("stdout is piped");
+        Ok(Self {
+            reader: Box::new(stdout),
+            child: Some(child),
+        })
+    }
+
+    /// Reads semihosting output from `reader`.
+    pub fn new(reader: impl Read + Send + 'static) -> Self {
+        Self {
+            reader: Box::new(reader),
+            child: None,
+        }
+    }
+
+    /// Decodes the output until it ends, then flushes the stream.
+    ///
+    /// Returns the exit status of a spawned emulator, which for QEMU is the code the
+    /// firmware passed to the semihosting exit call, so a test run can fail with it.
+    pub fn run(&mut self, stream: &mut TraceStream) -> Result<Option<ExitStatus>, Error> {
+        while self.read_chunks(&mut |chunk| stream.process_chunk(chunk))? {}
+        stream.flush()?;
+        match self.child.take() {
+            Some(mut child) => Ok(Some(child.wait()?)),
+            None => Ok(None),
+        }
+    }
+}
+
+impl FrameSource for Semihosting {
+    /// Ends with the output, without waiting for a spawned emulator to exit.
+    fn read_chunks(&mut self, sink: &mut ChunkSink<'_>) -> Result<bool, Error> {
+        let mut buf = [0u8; READ_BUFFER_SIZE];
+        loop {
+            match self.reader.read(&mut buf) {
+                Ok(0) => return Ok(false),
+                Ok(n) => {
+                    sink(Chunk::data(&buf[..n]))?;
+                    return Ok(true);
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// Same as [`Semihosting::run`], which waits for a spawned emulator to exit.
+    fn feed(&mut self, stream: &mut TraceStream) -> Result<(), Error> {
+        Semihosting::run(self, stream).map(drop)
+    }
+}
+
+impl Drop for Semihosting {
+    /// Stops an emulator that is still running, e.g. after a decoding error.
+    fn drop(&mut self) {
+        if let Some(child) = &mut self.child {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use std::io::{self, Read};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::Duration;
+
+use super::reconnect::{ReconnectPolicy, Reconnector};
+use super::{Chunk, ChunkSink, FrameSource};
+use crate::{Error, TraceStream};
+
+const READ_BUFFER_SIZE: usize = 4096;
+
+/// How the [`Tcp`] source obtains its connection.
+#[derive(Debug)]
+enum Mode {
+    /// Wait for the device (or a bridge) to connect to us.
+    Listen(TcpListener),
+    /// Connect out to a device, bridge or probe forwarder.
+    Connect(Vec<SocketAddr>),
+}
+
+/// Reads defmt bytes from a TCP connection.
+///
+/// The source either listens for an incoming connection or connects out to a remote
+/// address. When the connection drops it waits for (or re-establishes, following its
+/// [`ReconnectPolicy`]) a new one and resets the stream decoder, since any frame that was
+/// in flight is lost.
+///
+/// # Example
+/// ```rust,ignore
+/// let decoder = TraceDecoder::new(&elf)?;
+/// let mut stream = decoder.new_stream();
+/// let mut tcp = Tcp::connect("127.0.0.1:19021")?;
+/// tcp.run(&mut stream)?;
+/// ```
+#[derive(Debug)]
+pub struct Tcp {
+    mode: Mode,
+    connection: Option<TcpStream>,
+    reconnect: Reconnector,
+}
+
+impl Tcp {
+    /// Binds to `addr` and waits for a peer to connect.
+    pub fn listen<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        Ok(Self::with_mode(Mode::Listen(listener)))
+    }
+
+    /// Connects out to `addr`. The connection is established lazily on the first read.
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        let addrs: Vec<SocketAddr> = addr.to_socket_addrs()?.collect();
+        if addrs.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no socket addresses to connect to",
+            ));
+        }
+        Ok(Self::with_mode(Mode::Connect(addrs)))
+    }
+
+    fn with_mode(mode: Mode) -> Self {
+        Self {
+            mode,
+            connection: None,
+            reconnect: Reconnector::new(ReconnectPolicy::default()),
+        }
+    }
+
+    /// Sets how long to wait between failed connection attempts. Defaults to 1 second.
+    pub fn with_reconnect_delay(self, delay: Duration) -> Self {
+        self.with_reconnect_policy(ReconnectPolicy::fixed(delay))
+    }
+
+    /// Sets how to reconnect when connecting out, see [`ReconnectPolicy`].
+    pub fn with_reconnect_policy(mut self, policy: ReconnectPolicy) -> Self {
+        self.reconnect = Reconnector::new(policy);
+        self
+    }
+
+    /// Returns the local address when listening, e.g. to find the port after binding to port 0.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        match &self.mode {
+            Mode::Listen(listener) => listener.local_addr().ok(),
+            Mode::Connect(_) => None,
+        }
+    }
+
+    /// Reads forever, feeding every received chunk into `stream`.
+    ///
+    /// Only returns on errors that can't be fixed by reconnecting, such as a failing listener,
+    /// or when the reconnect policy gives up.
+    pub fn run(&mut self, stream: &mut TraceStream) -> Result<(), Error> {
+        loop {
+            self.poll(stream)?;
+        }
+    }
+
+    /// Blocks until one chunk of data has been read and processed.
+    ///
+    /// Returns the number of bytes fed into `stream`. A return value of 0 means the
+    /// connection was closed; the next call will reconnect.
+    pub fn poll(&mut self, stream: &mut TraceStream) -> Result<usize, Error> {
+        self.read(&mut |chunk| stream.process_chunk(chunk))
+    }
+
+    fn read(&mut self, sink: &mut ChunkSink<'_>) -> Result<usize, Error> {
+        let mut buf = [0u8; READ_BUFFER_SIZE];
+
+        if self.connection.is_none() {
+            self.connection = Some(self.establish()?);
+            // Bytes were lost between the two connections.
+            if let Some(chunk) = self.reconnect.connected() {
+                sink(chunk)?;
+            }
+        }
+
+        let connection = self.connection.as_mut().unwrap();
+        let read = loop {
+            match connection.read(&mut buf) {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                read => break read,
+            }
+        };
+        match read {
+            Ok(0) => {
+                self.connection = None;
+                self.reconnect.disconnected();
+                Ok(0)
+            }
+            Ok(n) => {
+                sink(Chunk::data(&buf[..n]))?;
+                Ok(n)
+            }
+            Err(e) => {
+                log::warn!("TCP connection lost: {}", e);
+                self.connection = None;
+                self.reconnect.disconnected();
+                Ok(0)
+            }
+        }
+    }
+
+    fn establish(&mut self) -> Result<TcpStream, Error> {
+        match &self.mode {
+            Mode::Listen(listener) => {
+                let (connection, peer) = listener.accept()?;
+                log::info!("Accepted TCP connection from {}", peer);
+                Ok(connection)
+            }
+            Mode::Connect(addrs) => loop {
+                match TcpStream::connect(&addrs[..]) {
+                    Ok(connection) => {
+                        log::info!("Connected to {:?}", connection.peer_addr().ok());
+                        return Ok(connection);
+                    }
+                    Err(e) => {
+                        let delay = self.reconnect.failed()?;
+                        log::warn!("TCP connect failed: {}, retrying in {:?}", e, delay);
+                        thread::sleep(delay);
+                    }
+                }
+            },
+        }
+    }
+}
+
+impl FrameSource for Tcp {
+    /// Never ends, like [`Tcp::run`].
+    fn read_chunks(&mut self, sink: &mut ChunkSink<'_>) -> Result<bool, Error> {
+        self.read(sink).map(|_| true)
+    }
+}
@@ -0,0 +1,282 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+use serde_json::Value;
+use tracing::Level;
+
+use crate::record::RecordLocation;
+use crate::{Error, StreamStats, TraceRecord, TraceStream};
+
+/// Feeds logs that were already decoded on the host, by `defmt-print` or `probe-rs`,
+/// through span reconstruction and the exporters.
+///
+/// Useful when only a log file is at hand and the ELF is gone, e.g. a CI artifact. Build
+/// the decoder with [`TraceDecoderBuilder::build_for_text`](crate::TraceDecoderBuilder::build_for_text).
+///
+/// Two formats are recognized line by line:
+/// - `defmt-print --json` / `probe-rs run --log-format json` output, one JSON object per
+///   frame, with level, location and timestamp.
+/// - The default text output, `<timestamp> <LEVEL> <message>` (the timestamp is
+///   optional, the level may be in brackets), optionally followed by a
+///   `└─ <module> @ <file>:<line>` location line. Colors are stripped.
+///
+/// Other lines are treated as messages without level.
+///
+/// # Example
+/// ```rust,ignore
+/// let decoder = TraceDecoder::builder().build_for_text()?;
+/// let mut stream = decoder.new_stream();
+/// TextLines::open("defmt.log")?.run(&mut stream)?;
+/// ```
+pub struct TextLines {
+    reader: Box<dyn BufRead>,
+    parser: LineParser,
+}
+
+impl TextLines {
+    /// Opens the log file at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self::new(BufReader::new(File::open(path)?)))
+    }
+
+    /// Reads the log from `reader`.
+    pub fn new(reader: impl BufRead + 'static) -> Self {
+        Self {
+            reader: Box::new(reader),
+            parser: LineParser::default(),
+        }
+    }
+
+    /// Processes the whole log, then flushes the stream. Returns the number of lines read.
+    pub fn run(&mut self, stream: &mut TraceStream) -> Result<u64, Error> {
+        let mut line = String::new();
+        let mut lines = 0;
+        loop {
+            line.clear();
+            match self.reader.read_line(&mut line) {
+                Ok(0) => break,
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+            lines += 1;
+            self.parser.push(stream, &line)?;
+        }
+        self.parser.flush(stream)?;
+        stream.flush()?;
+        Ok(lines)
+    }
+}
+
+/// Rebuilds the span tree from log lines handed over one at a time, e.g. read from
+/// `defmt-print` on stdin, emitting the spans and events like [`TraceStream::process`].
+///
+/// Accepts the formats of [`TextLines`]. Nesting is tracked on the stream's span stack
+/// rather than the host's call stack, so any nesting depth works.
+///
+/// # Example
+/// ```rust,ignore
+/// let decoder = TraceDecoder::builder().build_for_text()?;
+/// let mut reconstructor = LineReconstructor::new(decoder.new_stream());
+/// for line in std::io::stdin().lines() {
+///     reconstructor.push_line(&line?)?;
+/// }
+/// reconstructor.finish()?;
+/// ```
+pub struct LineReconstructor<'a> {
+    stream: TraceStream<'a>,
+    parser: LineParser,
+}
+
+impl<'a> LineReconstructor<'a> {
+    pub fn new(stream: TraceStream<'a>) -> Self {
+        Self {
+            stream,
+            parser: LineParser::default(),
+        }
+    }
+
+    /// The underlying stream, e.g. to add exporters.
+    pub fn stream_mut(&mut self) -> &mut TraceStream<'a> {
+        &mut self.stream
+    }
+
+    /// Processes one line, without its line ending. A text record is held back until the
+    /// next line shows whether it is followed by a location.
+    pub fn push_line(&mut self, line: &str) -> Result<(), Error> {
+        self.parser.push(&mut self.stream, line)
+    }
+
+    /// Emits the last line and closes the spans that are still open, see
+    /// [`TraceStream::finish`].
+    pub fn finish(mut self) -> Result<StreamStats, Error> {
+        self.parser.flush(&mut self.stream)?;
+        self.stream.finish()
+    }
+}
+
+/// Turns log lines into records, attaching location lines to the record before them.
+#[derive(Default)]
+struct LineParser {
+    /// A text record waiting for its location line.
+    pending: Option<TraceRecord>,
+}
+
+impl LineParser {
+    fn flush(&mut self, stream: &mut TraceStream) -> Result<(), Error> {
+        match self.pending.take() {
+            Some(record) => stream.process_record(record),
+            None => Ok(()),
+        }
+    }
+
+    fn push(&mut self, stream: &mut TraceStream, line: &str) -> Result<(), Error> {
+        let line = strip_ansi(line.trim_end_matches(['\r', '\n']));
+        if line.trim().is_empty() {
+            return Ok(());
+        }
+
+        if let Some(location) = parse_location_line(&line) {
+            if let Some(mut record) = self.pending.take() {
+                record.location = Some(location);
+                stream.process_record(record)?;
+            }
+            return Ok(());
+        }
+        self.flush(stream)?;
+
+        if line.starts_with('{') {
+            if let Ok(value) = serde_json::from_str::<Value>(&line) {
+                if let Some(record) = parse_json(&value, stream) {
+                    stream.process_record(record)?;
+                }
+                return Ok(());
+            }
+        }
+
+        let (rendered, level, message) = parse_text(&line);
+        let timestamp = rendered.and_then(|ts| stream.device_timestamp(0, ts));
+        self.pending = Some(TraceRecord::from_message(
+            0,
+            level,
+            message.to_string(),
+            None,
+            timestamp,
+        ));
+        Ok(())
+    }
+}
+
+/// Converts a `defmt-json` frame; `None` for the schema version line and other objects.
+fn parse_json(value: &Value, stream: &mut TraceStream) -> Option<TraceRecord> {
+    let message = value.get("data")?.as_str()?;
+    let level = value
+        .get("level")
+        .and_then(Value::as_str)
+        .and_then(parse_level);
+    let timestamp = value
+        .get("target_timestamp")
+        .and_then(Value::as_str)
+        .filter(|ts| !ts.is_empty())
+        .and_then(|ts| stream.device_timestamp(0, ts));
+    let location = value.get("location").and_then(|location| {
+        let path = location.get("module_path")?;
+        let mut module = path.get("crate_name")?.as_str()?.to_string();
+        for part in path
+            .get("modules")?
+            .as_array()?
+            .iter()
+            .filter_map(Value::as_str)
+        {
+            module.push_str("::");
+            module.push_str(part);
+        }
+        Some(RecordLocation {
+            file: location.get("file")?.as_str()?.into(),
+            line: location.get("line")?.as_u64()?,
+            module: module.into(),
+        })
+    });
+    Some(TraceRecord::from_message(
+        0,
+        level,
+        message.to_string(),
+        location,
+        timestamp,
+    ))
+}
+
+/// Splits a text line into timestamp, level and message.
+fn parse_text(line: &str) -> (Option<&str>, Option<Level>, &str) {
+    let line = line.trim_start();
+    let (first, rest) = split_word(line);
+    if let Some(level) = parse_level(first) {
+        return (None, Some(level), rest);
+    }
+    let (second, after) = split_word(rest);
+    match parse_level(second) {
+        Some(level) if first.starts_with(|c: char| c.is_ascii_digit()) => {
+            (Some(first), Some(level), after)
+        }
+        _ => (None, None, line),
+    }
+}
+
+/// Splits off the first word; a bracketed word like `[WARN ]` may contain spaces.
+fn split_word(line: &str) -> (&str, &str) {
+    if let (true, Some(end)) = (line.starts_with('['), line.find(']')) {
+        return (&line[..=end], line[end + 1..].trim_start());
+    }
+    match line.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim_start()),
+        None => (line, ""),
+    }
+}
+
+fn parse_level(word: &str) -> Option<Level> {
+    let word = word
+        .strip_prefix('[')
+        .and_then(|word| word.strip_suffix(']'))
+        .map_or(word, str::trim);
+    match word.to_ascii_uppercase().as_str() {
+        "TRACE" => Some(Level::TRACE),
+        "DEBUG" => Some(Level::DEBUG),
+        "INFO" => Some(Level::INFO),
+        "WARN" => Some(Level::WARN),
+        "ERROR" => Some(Level::ERROR),
+        _ => None,
+    }
+}
+
+/// Parses `└─ <module> @ <file>:<line>`.
+fn parse_location_line(line: &str) -> Option<RecordLocation> {
+    let rest = line.trim_start().strip_prefix("└─")?.trim();
+    let (module, path) = rest.split_once(" @ ")?;
+    let (file, line) = path.rsplit_once(':')?;
+    Some(RecordLocation {
+        file: file.into(),
+        line: line.parse().ok()?,
+        module: module.into(),
+    })
+}
+
+/// Removes ANSI escape sequences, e.g. the colors of `defmt-print`.
+fn strip_ansi(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            out.push(c);
+            continue;
+        }
+        if chars.next() == Some('[') {
+            for c in chars.by_ref() {
+                if ('@'..='~').contains(&c) {
+                    break;
+                }
+            }
+        }
+    }
+    out
+}
@@ -0,0 +1,186 @@
+use std::fmt;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+
+use super::{Chunk, ChunkSink, FrameSource};
+use crate::{Error, TraceStream};
+
+// Large enough for any UDP payload.
+const DATAGRAM_BUFFER_SIZE: usize = 65536;
+/// How far behind the expected sequence number a datagram may be to count as a duplicate
+/// or reordered one. Further back, the gateway was restarted or reset its counter.
+const REORDER_WINDOW: u64 = 64;
+
+/// Sequence-number header prepended to each datagram by the forwarding gateway.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SequenceHeader {
+    /// Datagrams carry only defmt bytes.
+    None,
+    /// A little-endian `u16` counter precedes the defmt bytes.
+    U16Le,
+    /// A little-endian `u32` counter precedes the defmt bytes.
+    U32Le,
+}
+
+impl SequenceHeader {
+    fn len(&self) -> usize {
+        match self {
+            SequenceHeader::None => 0,
+            SequenceHeader::U16Le => 2,
+            SequenceHeader::U32Le => 4,
+        }
+    }
+
+    fn modulus(&self) -> u64 {
+        match self {
+            SequenceHeader::None => 0,
+            SequenceHeader::U16Le => 1 << 16,
+            SequenceHeader::U32Le => 1 << 32,
+        }
+    }
+
+    fn parse(&self, datagram: &[u8]) -> Option<u64> {
+        match self {
+            SequenceHeader::None => None,
+            SequenceHeader::U16Le => Some(u16::from_le_bytes([datagram[0], datagram[1]]) as u64),
+            SequenceHeader::U32Le => {
+                Some(
+                    u32::from_le_bytes([datagram[0], datagram[1], datagram[2], datagram[3]]) as u64,
+                )
+            }
+        }
+    }
+}
+
+/// Loss accounting for a [`Udp`] source.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct UdpStats {
+    /// Datagrams fed into the decoder.
+    pub received: u64,
+    /// Datagrams missing according to the sequence numbers. Each held one or more frames.
+    pub lost: u64,
+    /// Datagrams that arrived after a later one and were dropped.
+    pub out_of_order: u64,
+    /// Times the sequence jumped back further than reordering explains, e.g. because the
+    /// gateway restarted. Whatever was lost around it is not counted in `lost`.
+    pub resyncs: u64,
+    /// Datagrams too short to hold the sequence header.
+    pub truncated: u64,
+}
+
+/// Reads defmt bytes from UDP datagrams, one chunk per datagram.
+///
+/// When a [`SequenceHeader`] is configured, gaps in the sequence are reported to the
+/// [`TraceStream`] via [`TraceStream::report_loss`], which resets the stream decoder and
+/// annotates the trace with the loss. The count is of lost datagrams: the frames in
+/// them are unknown, so it is the least number of frames lost.
+///
+/// A datagram up to 64 sequence numbers behind the expected one is a duplicate or was
+/// reordered, and is dropped. One further behind means the gateway's counter was reset:
+/// the source starts over from it and reports a loss of unknown size.
+pub struct Udp {
+    socket: UdpSocket,
+    header: SequenceHeader,
+    next_sequence: Option<u64>,
+    stats: UdpStats,
+    buf: Box<[u8]>,
+}
+
+impl fmt::Debug for Udp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Udp")
+            .field("socket", &self.socket)
+            .field("header", &self.header)
+            .field("next_sequence", &self.next_sequence)
+            .field("stats", &self.stats)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Udp {
+    /// Binds to `addr` and receives datagrams from any peer.
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        let socket = UdpSocket::bind(addr)?;
+        Ok(Self {
+            socket,
+            header: SequenceHeader::None,
+            next_sequence: None,
+            stats: UdpStats::default(),
+            buf: vec![0; DATAGRAM_BUFFER_SIZE].into_boxed_slice(),
+        })
+    }
+
+    /// Sets the sequence-number header the gateway prepends to each datagram.
+    pub fn with_sequence_header(mut self, header: SequenceHeader) -> Self {
+        self.header = header;
+        self
+    }
+
+    /// Returns the bound local address.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Returns the loss accounting collected so far.
+    pub fn stats(&self) -> UdpStats {
+        self.stats
+    }
+
+    /// Receives datagrams forever, feeding each one into `stream`.
+    pub fn run(&mut self, stream: &mut TraceStream) -> Result<(), Error> {
+        loop {
+            self.poll(stream)?;
+        }
+    }
+
+    /// Blocks until one datagram has been received and processed.
+    ///
+    /// Returns the number of defmt bytes fed into `stream`.
+    pub fn poll(&mut self, stream: &mut TraceStream) -> Result<usize, Error> {
+        self.read(&mut |chunk| stream.process_chunk(chunk))
+    }
+
+    fn read(&mut self, sink: &mut ChunkSink<'_>) -> Result<usize, Error> {
+        let (len, _peer) = self.socket.recv_from(&mut self.buf)?;
+        let header_len = self.header.len();
+        if len < header_len {
+            self.stats.truncated += 1;
+            return Ok(0);
+        }
+
+        if let Some(sequence) = self.header.parse(&self.buf[..len]) {
+            if let Some(expected) = self.next_sequence {
+                let modulus = self.header.modulus();
+                let gap = (sequence + modulus - expected) % modulus;
+                if gap >= modulus / 2 && modulus - gap <= REORDER_WINDOW {
+                    // A late duplicate or reordered datagram. The stream has already moved
+                    // on, so feeding it would corrupt decoding.
+                    self.stats.out_of_order += 1;
+                    return Ok(0);
+                }
+                if gap >= modulus / 2 {
+                    // The counter was reset, so there is no telling what was lost.
+                    self.stats.resyncs += 1;
+                    sink(Chunk::Lost { frames: None })?;
+                } else if gap > 0 {
+                    self.stats.lost += gap;
+                    // A lower bound on the frames lost, see the type's documentation.
+                    sink(Chunk::Lost { frames: Some(gap) })?;
+                }
+            }
+            self.next_sequence = Some((sequence + 1) % self.header.modulus());
+        }
+
+        let payload = &self.buf[header_len..len];
+        self.stats.received += 1;
+        sink(Chunk::data(payload))?;
+        Ok(payload.len())
+    }
+}
+
+impl FrameSource for Udp {
+    /// Never ends, like [`Udp::run`].
+    fn read_chunks(&mut self, sink: &mut ChunkSink<'_>) -> Result<bool, Error> {
+        self.read(sink).map(|_| true)
+    }
+}
@@ -0,0 +1,434 @@
+use std::io;
+use std::thread;
+use std::time::Duration;
+
+use rusb::{ConfigDescriptor, Context, DeviceHandle, Direction, TransferType, UsbContext as _};
+
+use super::reconnect::{ReconnectPolicy, Reconnector};
+use super::{Chunk, ChunkSink, FrameSource};
+use crate::{Error, TraceStream};
+
+const READ_BUFFER_SIZE: usize = 4096;
+/// How long a read waits for data, so [`Usb::poll`] returns now and then.
+const READ_TIMEOUT: Duration = Duration::from_secs(1);
+const CONTROL_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Interface class of the control interface of CDC-ACM serial ports.
+const CDC_CONTROL_CLASS: u8 = 0x02;
+/// The CDC request that sets DTR and RTS, which many firmwares wait for before sending.
+const SET_CONTROL_LINE_STATE: u8 = 0x22;
+
+/// Reads defmt bytes from a device's own USB interface: a vendor-specific bulk endpoint,
+/// or the data interface of a CDC-ACM serial port.
+///
+/// The device is found by vendor and product id, and optionally by serial number. Unless
+/// chosen with [`with_interface`](Self::with_interface) or
+/// [`with_endpoint`](Self::with_endpoint), the first bulk IN endpoint of the device is
+/// read. A kernel driver bound to the interface, such as Linux's `cdc_acm`, is detached
+/// for as long as the source reads it, and CDC-ACM ports get DTR raised as a terminal
+/// would.
+///
+/// Hot-plugging is handled by waiting for the device to show up, and again whenever it
+/// goes away, e.g. when it reboots; the stream decoder is reset in between.
+///
+/// # Example
+/// ```rust,ignore
+/// let decoder = TraceDecoder::new(&elf)?;
+/// let mut stream = decoder.new_stream();
+/// Usb::new(0x16c0, 0x27dd)?.with_serial_number("A1B2").run(&mut stream)?;
+/// ```
+pub struct Usb {
+    context: Context,
+    selector: Selector,
+    reconnect: Reconnector,
+    connection: Option<Connection>,
+}
+
+/// Which device the source reads, and which of its endpoints.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Selector {
+    vendor_id: u16,
+    product_id: u16,
+    serial_number: Option<String>,
+    interface: Option<u8>,
+    endpoint: Option<u8>,
+}
+
+/// An endpoint of the default setting of an interface.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct EndpointInfo {
+    interface: u8,
+    address: u8,
+    direction: Direction,
+    transfer_type: TransferType,
+}
+
+/// The open device and the endpoint read.
+struct Connection {
+    handle: DeviceHandle<Context>,
+    endpoint: u8,
+    interrupt: bool,
+}
+
+impl Usb {
+    /// Reads the device with the given vendor and product id. The device is opened
+    /// lazily on the first read.
+    pub fn new(vendor_id: u16, product_id: u16) -> io::Result<Self> {
+        Ok(Self {
+            context: Context::new().map_err(io::Error::other)?,
+            selector: Selector {
+                vendor_id,
+                product_id,
+                serial_number: None,
+                interface: None,
+                endpoint: None,
+            },
+            reconnect: Reconnector::new(ReconnectPolicy::default()),
+            connection: None,
+        })
+    }
+
+    /// Only reads the device with this serial number, when several are plugged in.
+    pub fn with_serial_number(mut self, serial_number: impl Into<String>) -> Self {
+        self.selector.serial_number = Some(serial_number.into());
+        self
+    }
+
+    /// Reads the first bulk or interrupt IN endpoint of interface `number`.
+    pub fn with_interface(mut self, number: u8) -> Self {
+        self.selector.interface = Some(number);
+        self
+    }
+
+    /// Reads the bulk or interrupt IN endpoint with address `address`, e.g. `0x81`.
+    pub fn with_endpoint(mut self, address: u8) -> Self {
+        self.selector.endpoint = Some(address);
+        self
+    }
+
+    /// Sets how long to wait between looking for the device. Defaults to 1 second.
+    pub fn with_reconnect_delay(self, delay: Duration) -> Self {
+        self.with_reconnect_policy(ReconnectPolicy::fixed(delay))
+    }
+
+    /// Sets how to look for the device, see [`ReconnectPolicy`]. Each look counts as an
+    /// attempt.
+    pub fn with_reconnect_policy(mut self, policy: ReconnectPolicy) -> Self {
+        self.reconnect = Reconnector::new(policy);
+        self
+    }
+
+    /// Reads forever, feeding every received transfer into `stream`.
+    ///
+    /// Only returns on decoding errors, or when the reconnect policy gives up; a device
+    /// that goes away is waited for.
+    pub fn run(&mut self, stream: &mut TraceStream) -> Result<(), Error> {
+        loop {
+            self.poll(stream)?;
+        }
+    }
+
+    /// Blocks until one transfer has been read and processed, or for at most a second
+    /// once the device is open.
+    ///
+    /// Returns the number of bytes fed into `stream`. A return value of 0 means nothing
+    /// arrived or the device went away; the next call will look for it again.
+    pub fn poll(&mut self, stream: &mut TraceStream) -> Result<usize, Error> {
+        self.read(&mut |chunk| stream.process_chunk(chunk))
+    }
+
+    fn read(&mut self, sink: &mut ChunkSink<'_>) -> Result<usize, Error> {
+        let mut buf = [0u8; READ_BUFFER_SIZE];
+
+        if self.connection.is_none() {
+            self.connection = Some(self.establish()?);
+            // Bytes were lost while the device was away.
+            if let Some(chunk) = self.reconnect.connected() {
+                sink(chunk)?;
+            }
+        }
+
+        let connection = self.connection.as_ref().unwrap();
+        let read = if connection.interrupt {
+            connection
+                .handle
+                .read_interrupt(connection.endpoint, &mut buf, READ_TIMEOUT)
+        } else {
+            connection
+                .handle
+                .read_bulk(connection.endpoint, &mut buf, READ_TIMEOUT)
+        };
+        match read {
+            Ok(n) => {
+                sink(Chunk::data(&buf[..n]))?;
+                Ok(n)
+            }
+            Err(rusb::Error::Timeout | rusb::Error::Interrupted) => Ok(0),
+            Err(e) => {
+                log::warn!("USB device lost: {}", e);
+                self.connection = None;
+                self.reconnect.disconnected();
+                Ok(0)
+            }
+        }
+    }
+
+    fn establish(&mut self) -> Result<Connection, Error> {
+        let mut waiting = false;
+        loop {
+            match self.open() {
+                Ok(Some(connection)) => {
+                    log::info!(
+                        "Reading USB device {:04x}:{:04x} endpoint {:#04x}",
+                        self.selector.vendor_id,
+                        self.selector.product_id,
+                        connection.endpoint
+                    );
+                    return Ok(connection);
+                }
+                Ok(None) if !waiting => {
+                    log::info!(
+                        "Waiting for USB device {:04x}:{:04x}",
+                        self.selector.vendor_id,
+                        self.selector.product_id
+                    );
+                    waiting = true;
+                }
+                Ok(None) => {}
+                Err(e) => log::warn!("Opening USB device failed: {}, retrying", e),
+            }
+            thread::sleep(self.reconnect.failed()?);
+        }
+    }
+
+    /// Opens the device and claims the interface to read, `None` if it is not plugged in.
+    fn open(&self) -> rusb::Result<Option<Connection>> {
+        for device in self.context.devices()?.iter() {
+            let descriptor = device.device_descriptor()?;
+            if !self
+                .selector
+                .matches_ids(descriptor.vendor_id(), descriptor.product_id())
+            {
+                continue;
+            }
+            let handle = device.open()?;
+            // Reading the serial number takes a request, so only when it is asked for.
+            let serial_number = match self.selector.serial_number {
+                Some(_) => handle.read_serial_number_string_ascii(&descriptor).ok(),
+                None => None,
+            };
+            if !self
+                .selector
+                .matches_serial_number(serial_number.as_deref())
+            {
+                continue;
+            }
+
+            let config = device.active_config_descriptor()?;
+            let (interface, endpoint, interrupt) = self
+                .selector
+                .find_endpoint(&endpoints(&config))
+                .ok_or(rusb::Error::NotFound)?;
+            // Not supported on all platforms, which have nothing to detach then.
+            let _ = handle.set_auto_detach_kernel_driver(true);
+            handle.claim_interface(interface)?;
+            if let Some(control) = cdc_control_interface(&config) {
+                // DTR and RTS. Devices that are not serial ports ignore it.
+                let _ = handle.write_control(
+                    rusb::request_type(
+                        Direction::Out,
+                        rusb::RequestType::Class,
+                        rusb::Recipient::Interface,
+                    ),
+                    SET_CONTROL_LINE_STATE,
+                    0x03,
+                    u16::from(control),
+                    &[],
+                    CONTROL_TIMEOUT,
+                );
+            }
+            return Ok(Some(Connection {
+                handle,
+                endpoint,
+                interrupt,
+            }));
+        }
+        Ok(None)
+    }
+}
+
+impl Selector {
+    /// Whether a device with these ids is the one read, as far as the ids tell.
+    fn matches_ids(&self, vendor_id: u16, product_id: u16) -> bool {
+        vendor_id == self.vendor_id && product_id == self.product_id
+    }
+
+    /// Whether a device with `serial_number`, `None` if it has none, is the one read.
+    fn matches_serial_number(&self, serial_number: Option<&str>) -> bool {
+        self.serial_number
+            .as_deref()
+            .is_none_or(|wanted| serial_number == Some(wanted))
+    }
+
+    /// The interface and address of the endpoint to read, and whether it is an interrupt
+    /// endpoint. Interrupt endpoints, such as the notifications of CDC-ACM ports, are only
+    /// read when chosen.
+    fn find_endpoint(&self, endpoints: &[EndpointInfo]) -> Option<(u8, u8, bool)> {
+        let chosen = self.interface.is_some() || self.endpoint.is_some();
+        endpoints
+            .iter()
+            .filter(|endpoint| endpoint.direction == Direction::In)
+            .filter(|endpoint| {
+                self.interface
+                    .is_none_or(|number| number == endpoint.interface)
+            })
+            .filter(|endpoint| {
+                self.endpoint
+                    .is_none_or(|address| address == endpoint.address)
+            })
+            .find_map(|endpoint| match endpoint.transfer_type {
+                TransferType::Bulk => Some((endpoint.interface, endpoint.address, false)),
+                TransferType::Interrupt if chosen => {
+                    Some((endpoint.interface, endpoint.address, true))
+                }
+                _ => None,
+            })
+    }
+}
+
+impl FrameSource for Usb {
+    /// Never ends, like [`Usb::run`].
+    fn read_chunks(&mut self, sink: &mut ChunkSink<'_>) -> Result<bool, Error> {
+        self.read(sink).map(|_| true)
+    }
+}
+
+/// The control interface of a CDC-ACM port, if the device has one.
+fn cdc_control_interface(config: &ConfigDescriptor) -> Option<u8> {
+    config
+        .interfaces()
+        .flat_map(|interface| interface.descriptors())
+        .find(|setting| setting.class_code() == CDC_CONTROL_CLASS)
+        .map(|setting| setting.interface_number())
+}
+
+/// The endpoints of the default settings of the interfaces of `config`, in order.
+fn endpoints(config: &ConfigDescriptor) -> Vec<EndpointInfo> {
+    config
+        .interfaces()
+        .flat_map(|interface| interface.descriptors())
+        .filter(|setting| setting.setting_number() == 0)
+        .flat_map(|setting| {
+            let interface = setting.interface_number();
+            setting
+                .endpoint_descriptors()
+                .map(move |endpoint| EndpointInfo {
+                    interface,
+                    address: endpoint.address(),
+                    direction: endpoint.direction(),
+                    transfer_type: endpoint.transfer_type(),
+                })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn selector() -> Selector {
+        Selector {
+            vendor_id: 0x16c0,
+            product_id: 0x27dd,
+            serial_number: None,
+            interface: None,
+            endpoint: None,
+        }
+    }
+
+    fn endpoint(
+        interface: u8,
+        address: u8,
+        direction: Direction,
+        transfer_type: TransferType,
+    ) -> EndpointInfo {
+        EndpointInfo {
+            interface,
+            address,
+            direction,
+            transfer_type,
+        }
+    }
+
+    /// A CDC-ACM port: notifications on the control interface, data on the other.
+    fn cdc_acm() -> Vec<EndpointInfo> {
+        vec![
+            endpoint(0, 0x83, Direction::In, TransferType::Interrupt),
+            endpoint(1, 0x02, Direction::Out, TransferType::Bulk),
+            endpoint(1, 0x81, Direction::In, TransferType::Bulk),
+        ]
+    }
+
+    #[test]
+    fn test_ids_must_both_match() {
+        let selector = selector();
+        assert!(selector.matches_ids(0x16c0, 0x27dd));
+        assert!(!selector.matches_ids(0x16c0, 0x27de));
+        assert!(!selector.matches_ids(0x1209, 0x27dd));
+    }
+
+    #[test]
+    fn test_serial_number_only_matters_when_given() {
+        let selector = selector();
+        assert!(selector.matches_serial_number(None));
+        assert!(selector.matches_serial_number(Some("A1B2")));
+
+        let selector = Selector {
+            serial_number: Some("A1B2".to_string()),
+            ..selector
+        };
+        assert!(selector.matches_serial_number(Some("A1B2")));
+        assert!(!selector.matches_serial_number(Some("A1B3")));
+        assert!(!selector.matches_serial_number(None));
+    }
+
+    #[test]
+    fn test_first_bulk_in_endpoint_is_read_by_default() {
+        assert_eq!(selector().find_endpoint(&cdc_acm()), Some((1, 0x81, false)));
+        // OUT endpoints are never read.
+        let endpoints = [endpoint(0, 0x01, Direction::Out, TransferType::Bulk)];
+        assert_eq!(selector().find_endpoint(&endpoints), None);
+    }
+
+    #[test]
+    fn test_interrupt_endpoint_is_only_read_when_chosen() {
+        let endpoints = &cdc_acm()[..1];
+        assert_eq!(selector().find_endpoint(endpoints), None);
+
+        let by_interface = Selector {
+            interface: Some(0),
+            ..selector()
+        };
+        assert_eq!(by_interface.find_endpoint(endpoints), Some((0, 0x83, true)));
+        let by_address = Selector {
+            endpoint: Some(0x83),
+            ..selector()
+        };
+        assert_eq!(by_address.find_endpoint(&cdc_acm()), Some((0, 0x83, true)));
+    }
+
+    #[test]
+    fn test_chosen_interface_and_endpoint_must_exist() {
+        let selector = Selector {
+            interface: Some(1),
+            endpoint: Some(0x83),
+            ..selector()
+        };
+        assert_eq!(selector.find_endpoint(&cdc_acm()), None);
+        let selector = Selector {
+            interface: Some(2),
+            ..selector
+        };
+        assert_eq!(selector.find_endpoint(&cdc_acm()), None);
+    }
+}
@@ -0,0 +1,176 @@
+//! Symbolication of code addresses, e.g. those of a crash frame, against the firmware ELF:
+//! the function from the symbol table, the file and line from the DWARF line tables.
+//!
+//! Inlined calls are not expanded, an address in inlined code is attributed to the
+//! function it was inlined into, at the line of the inlined code.
+
+use std::fmt;
+use std::sync::Arc;
+
+use object::{Object, ObjectSymbol, SymbolKind};
+
+use crate::dwarf::{self, LineRow};
+
+/// A function of the symbol table, with its address range.
+#[derive(Clone, Debug)]
+struct Function {
+    start: u64,
+    end: u64,
+    name: String,
+}
+
+/// The functions and line tables of a firmware ELF. Empty for decoders built without one.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Symbolizer {
+    /// Ordered by start address.
+    functions: Vec<Function>,
+    lines: Vec<LineRow>,
+}
+
+/// Where a code address is, as far as the ELF tells.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct CodeLocation<'s> {
+    pub function: Option<&'s str>,
+    pub file: Option<&'s Arc<str>>,
+    pub line: u64,
+}
+
+impl fmt::Display for CodeLocation<'_> {
+    /// `app::sensor::read at src/sensor.rs:42`, or as much of it as is known.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(function) = self.function {
+            write!(f, "{}", function)?;
+        }
+        if let Some(file) = self.file {
+            if self.function.is_some() {
+                write!(f, " at ")?;
+            }
+            write!(f, "{}:{}", file, self.line)?;
+        }
+        Ok(())
+    }
+}
+
+impl Symbolizer {
+    pub(crate) fn new(elf_data: &[u8]) -> Self {
+        let Ok(file) = object::File::parse(elf_data) else {
+            return Self::default();
+        };
+        let mut functions: Vec<Function> = file
+            .symbols()
+            .filter(|symbol| symbol.kind() == SymbolKind::Text && symbol.is_definition())
+            .filter_map(|symbol| {
+                // The Thumb bit is set in the addresses of Thumb functions.
+                let start = symbol.address() & !1;
+                let name = symbol.name().ok().filter(|name| !name.is_empty())?;
+                Some(Function {
+                    start,
+                    end: start + symbol.size(),
+                    name: demangle(name),
+                })
+            })
+            .collect();
+        functions.sort_by_key(|function| function.start);
+        functions.dedup_by_key(|function| function.start);
+        // Functions without a size end where the next starts.
+        for i in 1..functions.len() {
+            if functions[i - 1].end == functions[i - 1].start {
+                functions[i - 1].end = functions[i].start;
+            }
+        }
+
+        Self {
+            functions,
+            lines: dwarf::line_rows(elf_data),
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.functions.is_empty() && self.lines.is_empty()
+    }
+
+    /// Where the instruction at `address` is, `None` if the ELF does not know it.
+    pub(crate) fn locate(&self, address: u64) -> Option<CodeLocation<'_>> {
+        let address = address & !1;
+        let function = match self
+            .functions
+            .partition_point(|function| function.start <= address)
+        {
+            0 => None,
+            i => Some(&self.functions[i - 1]).filter(|function| address < function.end),
+        };
+        let row = match self.lines.partition_point(|row| row.address <= address) {
+            0 => None,
+            i => self.lines[i - 1].location.as_ref(),
+        };
+        if function.is_none() && row.is_none() {
+            return None;
+        }
+        Some(CodeLocation {
+            function: function.map(|function| function.name.as_str()),
+            file: row.map(|(file, _)| file),
+            line: row.map_or(0, |(_, line)| *line),
+        })
+    }
+}
+
+/// Demangles a legacy Rust symbol, e.g. `_ZN3app6sensor4read17h0123456789abcdefE` to
+/// `app::sensor::read`, without its hash. Other symbols are returned as they are.
+fn demangle(symbol: &str) -> String {
+    legacy_path(symbol).unwrap_or_else(|| symbol.to_string())
+}
+
+fn legacy_path(symbol: &str) -> Option<String> {
+    let mut rest = symbol
+        .strip_prefix("_ZN")
+        .or_else(|| symbol.strip_prefix("__ZN"))?;
+    let mut segments = Vec::new();
+    while !rest.starts_with('E') {
+        let digits = rest.find(|c: char| !c.is_ascii_digit())?;
+        let len: usize = rest[..digits].parse().ok()?;
+        let segment = rest.get(digits..digits + len)?;
+        segments.push(segment);
+        rest = &rest[digits + len..];
+    }
+    let is_hash = |segment: &&str| {
+        segment.len() == 17
+            && segment.starts_with('h')
+            && segment[1..].chars().all(|c| c.is_ascii_hexdigit())
+    };
+    if segments.last().is_some_and(is_hash) {
+        segments.pop();
+    }
+    let segments: Vec<String> = segments.into_iter().map(unescape).collect();
+    (!segments.is_empty()).then(|| segments.join("::"))
+}
+
+/// Replaces the `$..$` escapes and `..` of a legacy symbol segment.
+fn unescape(segment: &str) -> String {
+    const ESCAPES: [(&str, &str); 15] = [
+        ("$SP$", "@"),
+        ("$BP$", "*"),
+        ("$RF$", "&"),
+        ("$LT$", "<"),
+        ("$GT$", ">"),
+        ("$LP$", "("),
+        ("$RP$", ")"),
+        ("$C$", ","),
+        ("$u20$", " "),
+        ("$u27$", "'"),
+        ("$u5b$", "["),
+        ("$u5d$", "]"),
+        ("$u7b$", "{"),
+        ("$u7d$", "}"),
+        ("$u7e$", "~"),
+    ];
+    // Segments that would start with an escape get a leading `_`.
+    let segment = match segment.strip_prefix('_') {
+        Some(rest) if rest.starts_with('$') => rest,
+        _ => segment,
+    };
+    let mut out = segment.replace("..", "::");
+    for (escape, replacement) in ESCAPES {
+        out = out.replace(escape, replacement);
+    }
+    out
+}
@@ -0,0 +1,97 @@
+//! Mapping of device module paths to tracing targets.
+
+use std::str::FromStr;
+
+use crate::Error;
+
+/// Gives the records of device modules stable logical targets, e.g.
+/// `app::net=network,drivers::*::isr=interrupts`, so `EnvFilter` rules and dashboards
+/// keep working when the firmware's modules are reorganized.
+///
+/// A rule's pattern matches a module path and the modules below it; `*` matches any
+/// run of characters, `::` included. The first matching rule wins. Module paths are
+/// those of the defmt location table, so records without a location keep the default
+/// target, as do records whose firmware gave a `target:` of its own.
+///
+/// Besides being the tracing target (which tracing-opentelemetry exports as the
+/// `target` attribute of events), a mapped target becomes the `target` attribute of
+/// spans.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TargetMap {
+    rules: Vec<(String, String)>,
+}
+
+impl TargetMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Maps the modules matching `pattern` to `target`, unless an earlier rule matches.
+    pub fn with_rule(mut self, pattern: impl Into<String>, target: impl Into<String>) -> Self {
+        self.rules.push((pattern.into(), target.into()));
+        self
+    }
+
+    /// The target of the records of module `module`, if a rule matches it.
+    pub fn target(&self, module: &str) -> Option<&str> {
+        self.rules
+            .iter()
+            .find(|(pattern, _)| {
+                // The module itself and every module above it, e.g. `app::net` for
+                // `app::net::tcp`.
+                let mut ancestors = module.match_indices("::").map(|(idx, _)| &module[..idx]);
+                glob_match(pattern, module) || ancestors.any(|parent| glob_match(pattern, parent))
+            })
+            .map(|(_, target)| target.as_str())
+    }
+}
+
+/// Whether `text` matches `pattern`, in which `*` matches any run of characters.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    // Without a `*`, there is a single part that has to match all of `text`.
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts = parts.peekable();
+    while let Some(part) = parts.next() {
+        if parts.peek().is_none() {
+            return rest.ends_with(part);
+        }
+        match rest.find(part) {
+            Some(idx) => rest = &rest[idx + part.len()..],
+            None => return false,
+        }
+    }
+    rest.is_empty()
+}
+
+impl FromStr for TargetMap {
+    type Err = Error;
+
+    /// Parses comma-separated `pattern=target` rules.
+    fn from_str(spec: &str) -> Result<Self, Error> {
+        let mut map = Self::new();
+        for rule in spec
+            .split(',')
+            .map(str::trim)
+            .filter(|rule| !rule.is_empty())
+        {
+            match rule.split_once('=') {
+                Some((pattern, target))
+                    if !pattern.trim().is_empty() && !target.trim().is_empty() =>
+                {
+                    map = map.with_rule(pattern.trim(), target.trim());
+                }
+                _ => {
+                    return Err(Error::TargetRule(format!(
+                        "`{}` is not pattern=target",
+                        rule
+                    )));
+                }
+            }
+        }
+        Ok(map)
+    }
+}
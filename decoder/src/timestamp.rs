@@ -0,0 +1,203 @@
+//! Parsing of the device timestamps defined via `defmt::timestamp!`.
+
+use std::time::Duration;
+
+/// How raw device timestamps relate to real time.
+///
+/// Timestamps formatted with a time hint (`{=u64:us}`, `{=u64:tms}`, ...) are already in
+/// real units. Plain integers are tick counts of whatever timer the firmware reads
+/// (SysTick, DWT cycle counter, RTC), which this converts to microseconds.
+///
+/// ```
+/// # use std::time::Duration;
+/// # use tracing_defmt_decoder::Timebase;
+/// // DWT cycle counter of a 125 MHz core.
+/// let timebase = Timebase::new(125_000_000);
+/// assert_eq!(timebase.ticks_to_micros(250_000), 2_000);
+/// // A 32.768 kHz RTC with a prescaler of 32, counting from a known wall-clock time.
+/// let rtc = Timebase::new(32_768)
+///     .with_prescaler(32)
+///     .with_epoch(Duration::from_secs(1_700_000_000));
+/// assert_eq!(rtc.ticks_to_micros(1_024), 1_700_000_001_000_000);
+/// // 32-bit SysTick at 64 MHz, which wraps every 67 seconds.
+/// let systick = Timebase::new(64_000_000).with_counter_width(32);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Timebase {
+    frequency: u64,
+    prescaler: u64,
+    epoch: Duration,
+    counter_width: u32,
+}
+
+impl Timebase {
+    /// Ticks at `frequency` Hz. Panics if `frequency` is zero.
+    pub fn new(frequency: u64) -> Self {
+        assert!(frequency > 0, "tick frequency must not be zero");
+        Self {
+            frequency,
+            prescaler: 1,
+            epoch: Duration::ZERO,
+            counter_width: 64,
+        }
+    }
+
+    /// Divides the input frequency by `prescaler` before counting.
+    pub fn with_prescaler(mut self, prescaler: u32) -> Self {
+        self.prescaler = u64::from(prescaler.max(1));
+        self
+    }
+
+    /// Time since the Unix epoch at which the device clock reads zero, making all
+    /// device timestamps (tick counts and formatted ones) absolute.
+    pub fn with_epoch(mut self, since_unix_epoch: Duration) -> Self {
+        self.epoch = since_unix_epoch;
+        self
+    }
+
+    /// Number of bits of the device counter, after which it wraps around to zero.
+    ///
+    /// Timestamps of each context are then unwrapped into a monotonic time, taking a
+    /// step back by more than half the counter range as a rollover. This also applies to
+    /// timestamps formatted in real units, e.g. a `{=u32:us}` microsecond counter. A step
+    /// forward by more than a quarter of the range is reported as
+    /// [`StreamIssue::TimestampJump`](crate::StreamIssue::TimestampJump): it cannot be
+    /// told apart from a rollover, or from a reboot late in the counter range, so the
+    /// following durations may be off. Panics unless `bits` is between 1 and 64.
+    pub fn with_counter_width(mut self, bits: u32) -> Self {
+        assert!(
+            (1..=64).contains(&bits),
+            "counter width must be 1 to 64 bits"
+        );
+        self.counter_width = bits;
+        self
+    }
+
+    pub fn ticks_to_micros(&self, ticks: u64) -> u64 {
+        let micros =
+            u128::from(ticks) * u128::from(self.prescaler) * 1_000_000 / u128::from(self.frequency);
+        self.epoch_micros().saturating_add(micros as u64)
+    }
+
+    fn epoch_micros(&self) -> u64 {
+        self.epoch.as_micros() as u64
+    }
+}
+
+/// Converts the rendered defmt timestamps of one context into microseconds, unwrapping
+/// the rollovers of a counter narrower than 64 bits.
+pub(crate) struct Unwrapper {
+    /// Largest counter value; `u64::MAX` for a counter that does not wrap.
+    max: u64,
+    /// The previous timestamp: unwrapped counter value and microseconds.
+    last: Option<(u64, u64)>,
+}
+
+/// A converted device timestamp.
+pub(crate) struct Timestamp {
+    pub(crate) micros: u64,
+    /// The previous timestamp, when the counter jumped forward by more than a quarter of
+    /// its range since.
+    pub(crate) jumped_from: Option<u64>,
+}
+
+impl Unwrapper {
+    pub(crate) fn new(timebase: Option<&Timebase>) -> Self {
+        let width = timebase.map_or(64, |timebase| timebase.counter_width);
+        Self {
+            max: u64::MAX >> (64 - width),
+            last: None,
+        }
+    }
+
+    /// Converts a rendered timestamp, using `timebase` for tick counts. Without a
+    /// timebase, tick counts are taken as microseconds.
+    pub(crate) fn convert(
+        &mut self,
+        rendered: &str,
+        timebase: Option<&Timebase>,
+    ) -> Option<Timestamp> {
+        let rendered = parse(rendered)?;
+        let (Rendered::Ticks(value) | Rendered::Micros(value)) = rendered;
+        let (value, jump) = self.unwrap(value & self.max);
+        let micros = match (rendered, timebase) {
+            (Rendered::Ticks(_), Some(timebase)) => timebase.ticks_to_micros(value),
+            (Rendered::Micros(_), Some(timebase)) => timebase.epoch_micros().saturating_add(value),
+            (_, None) => value,
+        };
+        let jumped_from = match self.last.replace((value, micros)) {
+            Some((_, last)) if jump => Some(last),
+            _ => None,
+        };
+        Some(Timestamp {
+            micros,
+            jumped_from,
+        })
+    }
+
+    /// Places `value` next to the previous one, within half the counter range either
+    /// way. Also returns whether it is more than a quarter of the range ahead.
+    fn unwrap(&self, value: u64) -> (u64, bool) {
+        let last = match self.last {
+            Some((last, _)) if self.max != u64::MAX => last,
+            _ => return (value, false),
+        };
+        let ahead = value.wrapping_sub(last) & self.max;
+        if ahead <= self.max / 2 {
+            (last.saturating_add(ahead), ahead > self.max / 4)
+        } else {
+            let behind = self.max - ahead + 1;
+            (last.saturating_sub(behind), false)
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Rendered {
+    Ticks(u64),
+    Micros(u64),
+}
+
+/// Parses a rendered defmt timestamp.
+///
+/// Understands plain integers (tick counts), seconds with a fractional part as produced
+/// by the `:us`/`:ms` hints (`1.000250`), and the `:tus`/`:tms` clock format
+/// (`00:00:01.000250`). Returns `None` for anything else, including times beyond
+/// `u64::MAX` microseconds.
+fn parse(rendered: &str) -> Option<Rendered> {
+    let rendered = rendered.trim();
+
+    let (clock, seconds) = match rendered.rsplit_once(':') {
+        Some((clock, seconds)) => (Some(clock), seconds),
+        None => (None, rendered),
+    };
+
+    let mut micros = match seconds.split_once('.') {
+        Some((whole, fraction)) => {
+            let whole: u64 = whole.parse().ok()?;
+            // Also keeps the slice below on a character boundary.
+            if !fraction.bytes().all(|b| b.is_ascii_digit()) {
+                return None;
+            }
+            let digits = fraction.len().min(6);
+            let fraction: u64 = fraction[..digits].parse().ok()?;
+            whole
+                .checked_mul(1_000_000)?
+                .checked_add(fraction * 10u64.pow((6 - digits) as u32))?
+        }
+        // A bare integer is the raw tick count.
+        None if clock.is_none() => return rendered.parse().ok().map(Rendered::Ticks),
+        None => seconds.parse::<u64>().ok()?.checked_mul(1_000_000)?,
+    };
+
+    if let Some(clock) = clock {
+        // [days:]hours:minutes
+        let mut scale: u64 = 60 * 1_000_000;
+        for (i, part) in clock.rsplit(':').enumerate() {
+            micros = micros.checked_add(part.parse::<u64>().ok()?.checked_mul(scale)?)?;
+            scale = scale.checked_mul(if i == 0 { 60 } else { 24 })?;
+        }
+    }
+
+    Some(Rendered::Micros(micros))
+}
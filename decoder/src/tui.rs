@@ -0,0 +1,252 @@
+//! Live terminal view of decoded records, used by the `tracing-defmt-tui` binary.
+//!
+//! Renders with plain ANSI escape sequences, so it works in any VT100-compatible
+//! terminal without further dependencies.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{self, Write};
+
+use tracing::Level;
+
+use crate::{ControlCommand, RecordKind, TraceRecord};
+
+/// Number of records kept for scrolling back and searching.
+const HISTORY: usize = 10_000;
+
+/// What a key press did, see [`LiveView::handle_key`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum KeyAction {
+    /// The view changed and should be redrawn.
+    Redraw,
+    /// The key did nothing.
+    Ignored,
+    /// A control command for the device was entered, see [`LiveView::take_command`].
+    Command,
+    Quit,
+}
+
+/// A scrolling view of the decoded events with the current span stack of every context.
+///
+/// Feed it every record, e.g. from [`TraceStream::process_into`](crate::TraceStream::process_into),
+/// and [`render`](Self::render) it periodically. Keys: space pauses and resumes the
+/// display, `/` starts a search (Enter to apply, Esc to clear), `:` starts a
+/// [control command](ControlCommand) for the device, e.g. `:set-filter app::net=trace`,
+/// `q` quits.
+pub struct LiveView {
+    lines: VecDeque<TraceRecord>,
+    stacks: BTreeMap<u32, Vec<String>>,
+    paused: bool,
+    /// Records that arrived while paused.
+    held: usize,
+    search: Option<String>,
+    /// The prompt (`/` or `:`) and text being typed, if any.
+    input: Option<(char, String)>,
+    /// A control command entered and not taken yet.
+    command: Option<ControlCommand>,
+    /// Shown in the header until the next key press, e.g. why a command was rejected.
+    notice: Option<String>,
+    size: (u16, u16),
+}
+
+impl Default for LiveView {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LiveView {
+    pub fn new() -> Self {
+        Self {
+            lines: VecDeque::new(),
+            stacks: BTreeMap::new(),
+            paused: false,
+            held: 0,
+            search: None,
+            input: None,
+            command: None,
+            notice: None,
+            size: (24, 80),
+        }
+    }
+
+    /// Sets the terminal size in rows and columns. Defaults to 24x80.
+    pub fn set_size(&mut self, rows: u16, columns: u16) {
+        self.size = (rows.max(4), columns.max(20));
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// The control command entered last, to be sent to the device.
+    pub fn take_command(&mut self) -> Option<ControlCommand> {
+        self.command.take()
+    }
+
+    /// Shows `notice` in the header until the next key press, e.g. the outcome of
+    /// sending a command.
+    pub fn set_notice(&mut self, notice: impl Into<String>) {
+        self.notice = Some(notice.into());
+    }
+
+    pub fn push(&mut self, record: &TraceRecord) {
+        let stack = self.stacks.entry(record.context).or_default();
+        match record.kind {
+            RecordKind::SpanEnter => stack.push(record.message.clone()),
+            RecordKind::SpanExit => {
+                stack.pop();
+                return;
+            }
+            _ => {}
+        }
+        if self.lines.len() == HISTORY {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(record.clone());
+        if self.paused {
+            self.held = (self.held + 1).min(self.lines.len());
+        }
+    }
+
+    pub fn handle_key(&mut self, key: u8) -> KeyAction {
+        self.notice = None;
+        if let Some((prompt, input)) = &mut self.input {
+            match key {
+                b'\r' | b'\n' if *prompt == ':' => {
+                    let command = input.parse();
+                    self.input = None;
+                    match command {
+                        Ok(command) => {
+                            self.command = Some(command);
+                            return KeyAction::Command;
+                        }
+                        Err(e) => self.notice = Some(e.to_string()),
+                    }
+                }
+                b'\r' | b'\n' => {
+                    self.search = Some(std::mem::take(input)).filter(|s| !s.is_empty());
+                    self.input = None;
+                }
+                0x1b => self.input = None,
+                0x7f | 0x08 => {
+                    input.pop();
+                }
+                key if key.is_ascii_graphic() || key == b' ' => input.push(key as char),
+                _ => return KeyAction::Ignored,
+            }
+            return KeyAction::Redraw;
+        }
+        match key {
+            b'q' | 0x03 => KeyAction::Quit,
+            b' ' => {
+                self.paused = !self.paused;
+                self.held = 0;
+                KeyAction::Redraw
+            }
+            b'/' | b':' => {
+                self.input = Some((key as char, String::new()));
+                KeyAction::Redraw
+            }
+            0x1b if self.search.is_some() => {
+                self.search = None;
+                KeyAction::Redraw
+            }
+            _ => KeyAction::Ignored,
+        }
+    }
+
+    /// Draws the whole screen.
+    pub fn render(&self, out: &mut impl Write) -> io::Result<()> {
+        let (rows, columns) = (self.size.0 as usize, self.size.1 as usize);
+        write!(out, "\x1b[H\x1b[2J")?;
+
+        let status = match (&self.input, &self.notice, &self.search, self.paused) {
+            (Some((prompt, input)), _, _, _) => format!("{}{}", prompt, input),
+            (None, Some(notice), _, _) => notice.clone(),
+            (None, None, Some(search), true) => format!("PAUSED  search: {}", search),
+            (None, None, Some(search), false) => format!("search: {}", search),
+            (None, None, None, true) => "PAUSED".to_string(),
+            (None, None, None, false) => String::new(),
+        };
+        let header = format!(
+            "tracing-defmt  {}  [space] pause  [/] search  [:] command  [q] quit",
+            status
+        );
+        writeln!(
+            out,
+            "\x1b[7m{:<columns$}\x1b[0m\r",
+            truncate(&header, columns)
+        )?;
+
+        for (context, stack) in &self.stacks {
+            let line = format!("context {}: {}", context, stack.join(" > "));
+            writeln!(out, "\x1b[1m{}\x1b[0m\r", truncate(&line, columns))?;
+        }
+        writeln!(out, "{}\r", "-".repeat(columns))?;
+
+        let available = rows.saturating_sub(self.stacks.len() + 2);
+        let shown = self.lines.len() - self.held;
+        let visible: Vec<&TraceRecord> = self
+            .lines
+            .iter()
+            .take(shown)
+            .filter(|record| self.matches(record))
+            .collect();
+        for record in &visible[visible.len().saturating_sub(available)..] {
+            let line = format_record(record);
+            write!(
+                out,
+                "{}{}\x1b[0m\r\n",
+                color(record),
+                truncate(&line, columns)
+            )?;
+        }
+        out.flush()
+    }
+
+    fn matches(&self, record: &TraceRecord) -> bool {
+        match &self.search {
+            Some(search) => format_record(record).contains(search.as_str()),
+            None => true,
+        }
+    }
+}
+
+fn format_record(record: &TraceRecord) -> String {
+    let timestamp = match record.timestamp {
+        Some(micros) => format!("{:>6}.{:06} ", micros / 1_000_000, micros % 1_000_000),
+        None => String::new(),
+    };
+    let level = record.level.map(|level| level.as_str()).unwrap_or("");
+    let text = match record.kind {
+        RecordKind::SpanEnter => format!("-> {}", record.message),
+        _ => record.message.clone(),
+    };
+    let fields: String = record
+        .fields
+        .iter()
+        .map(|(key, value)| format!(" {}={}", key, value))
+        .collect();
+    format!(
+        "{}[{}] {:<5} {}{}",
+        timestamp, record.context, level, text, fields
+    )
+}
+
+fn color(record: &TraceRecord) -> &'static str {
+    match record.level {
+        Some(Level::ERROR) => "\x1b[31m",
+        Some(Level::WARN) => "\x1b[33m",
+        Some(Level::INFO) => "\x1b[32m",
+        Some(Level::DEBUG) => "\x1b[34m",
+        Some(Level::TRACE) => "\x1b[90m",
+        None => "",
+    }
+}
+
+fn truncate(line: &str, columns: usize) -> &str {
+    match line.char_indices().nth(columns) {
+        Some((end, _)) => &line[..end],
+        None => line,
+    }
+}
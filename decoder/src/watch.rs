@@ -0,0 +1,97 @@
+//! Detection of rebuilt firmware images.
+
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Watches an ELF file for changes, so the decoder can follow `cargo run` rebuilds.
+///
+/// The file's modification time and size are checked on every [`poll`](Self::poll); the
+/// contents are only read when those change, and reported only if they actually differ.
+///
+/// ```no_run
+/// # use tracing_defmt_decoder::{ElfWatcher, TraceDecoder, source::Tcp};
+/// let mut watcher = ElfWatcher::new("target/thumbv6m-none-eabi/debug/app");
+/// let mut decoder = TraceDecoder::new(&watcher.read()?)?;
+/// let mut source = Tcp::connect("192.168.1.50:4000")?;
+/// loop {
+///     let mut stream = decoder.new_stream();
+///     let elf = loop {
+///         source.poll(&mut stream)?;
+///         if let Some(elf) = watcher.poll()? {
+///             break elf;
+///         }
+///     };
+///     drop(stream);
+///     if let Err(e) = decoder.reload(&elf) {
+///         eprintln!("keeping previous firmware image: {}", e);
+///     }
+/// }
+/// # Ok::<(), tracing_defmt_decoder::Error>(())
+/// ```
+pub struct ElfWatcher {
+    path: PathBuf,
+    stamp: Option<(SystemTime, u64)>,
+    hash: Option<u64>,
+}
+
+impl ElfWatcher {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            stamp: None,
+            hash: None,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Reads the current contents of the file and remembers them as seen.
+    pub fn read(&mut self) -> io::Result<Vec<u8>> {
+        self.stamp = Some(self.stamp()?);
+        let data = fs::read(&self.path)?;
+        self.hash = Some(hash(&data));
+        Ok(data)
+    }
+
+    /// Returns the new contents of the file if it changed since the last `read`/`poll`.
+    ///
+    /// A missing file (e.g. while the linker recreates it) is not an error and reports
+    /// no change.
+    pub fn poll(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let stamp = match self.stamp() {
+            Ok(stamp) => stamp,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        if self.stamp == Some(stamp) {
+            return Ok(None);
+        }
+        self.stamp = Some(stamp);
+
+        let data = fs::read(&self.path)?;
+        let hash = hash(&data);
+        if self.hash == Some(hash) {
+            return Ok(None);
+        }
+        self.hash = Some(hash);
+        log::info!("{} changed, reloading", self.path.display());
+        Ok(Some(data))
+    }
+
+    fn stamp(&self) -> io::Result<(SystemTime, u64)> {
+        let metadata = fs::metadata(&self.path)?;
+        Ok((metadata.modified()?, metadata.len()))
+    }
+}
+
+fn hash(data: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    data.hash(&mut hasher);
+    hasher.finish()
+}
@@ -0,0 +1,147 @@
+mod common;
+
+use std::time::Duration;
+
+use common::FrameBytes;
+use tracing_defmt_decoder::budget::{BudgetCheck, LatencyBudgets};
+use tracing_defmt_decoder::diff::Statistic;
+use tracing_defmt_decoder::{Error, TraceDecoder, TraceRecord};
+
+/// Ten `spi_write` spans of 100us to 1000us, then one `flash_erase` of 30ms.
+fn records() -> Vec<TraceRecord> {
+    let table = common::table(
+        &[
+            ("Info", "span_enter: spi_write()"),
+            ("Info", "span_enter: flash_erase()"),
+            ("Info", "span_exit: {=str}"),
+        ],
+        Some("{=u64:us}"),
+    );
+    let decoder = TraceDecoder::builder()
+        .build_from_table(table, common::locations(3))
+        .unwrap();
+
+    let mut data = Vec::new();
+    let mut now = 0;
+    for i in 1..=10 {
+        data.extend(FrameBytes::new(0).u64(now).bytes());
+        now += i * 100;
+        data.extend(FrameBytes::new(2).u64(now).str("spi_write").bytes());
+    }
+    data.extend(FrameBytes::new(1).u64(now).bytes());
+    data.extend(
+        FrameBytes::new(2)
+            .u64(now + 30_000)
+            .str("flash_erase")
+            .bytes(),
+    );
+    decoder.decode_records(&data).unwrap()
+}
+
+#[test]
+fn test_budgets_parse() {
+    let budgets: LatencyBudgets = "\
+# SPI
+spi_write <= 200us p99.9
+flash_erase <= 1.5ms, idle <= 2s mean
+"
+    .parse()
+    .unwrap();
+    let parsed: Vec<_> = budgets
+        .budgets()
+        .iter()
+        .map(|budget| budget.to_string())
+        .collect();
+    assert_eq!(
+        parsed,
+        [
+            "spi_write <= 200µs p99.9",
+            "flash_erase <= 1.5ms",
+            "idle <= 2s mean"
+        ]
+    );
+
+    for invalid in [
+        "spi_write < 200us",
+        "spi_write <= 200",
+        "spi_write <= 1ms p200",
+    ] {
+        assert!(matches!(
+            invalid.parse::<LatencyBudgets>(),
+            Err(Error::Budget(_))
+        ));
+    }
+}
+
+#[test]
+fn test_budget_violations_list_offenders() {
+    let report = "spi_write <= 900us p90, spi_write <= 600us p50, flash_erase <= 40ms"
+        .parse::<LatencyBudgets>()
+        .unwrap()
+        .evaluate(&records());
+
+    let violated: Vec<_> = report
+        .results()
+        .iter()
+        .map(|result| (result.observed, result.violated()))
+        .collect();
+    assert_eq!(
+        violated,
+        [
+            (Some(Duration::from_micros(900)), false),
+            (Some(Duration::from_micros(500)), false),
+            (Some(Duration::from_millis(30)), false),
+        ]
+    );
+    assert!(report.passed());
+
+    let report = LatencyBudgets::new()
+        .with_budget("spi_write", Duration::from_micros(500), Statistic::Mean)
+        .with_budget("flash_erase", Duration::from_millis(10), Statistic::Max)
+        .evaluate(&records());
+    assert!(!report.passed());
+    assert_eq!(report.violations().count(), 2);
+    assert_eq!(
+        report.to_string(),
+        "\
+FAIL spi_write <= 500µs mean: 550µs over 10 spans
+       1ms span 10 at 4500us
+       900µs span 9 at 3600us
+       800µs span 8 at 2800us
+       700µs span 7 at 2100us
+       600µs span 6 at 1500us
+FAIL flash_erase <= 10ms: 30ms over 1 spans
+       30ms span 11 at 5500us
+"
+    );
+    assert_eq!(
+        report.to_json()["budgets"][0]["offenders"][0]["duration_us"],
+        1000
+    );
+}
+
+#[test]
+fn test_budget_check_on_a_stream() {
+    let check = BudgetCheck::new("spi_write <= 1ms".parse().unwrap());
+    let table = common::table(
+        &[
+            ("Info", "span_enter: spi_write()"),
+            ("Info", "span_exit: {=str}"),
+        ],
+        Some("{=u64:us}"),
+    );
+    let decoder = TraceDecoder::builder()
+        .build_from_table(table, common::locations(2))
+        .unwrap();
+    let mut stream = decoder.new_stream();
+    stream.add_exporter(check.clone());
+
+    let mut data = FrameBytes::new(0).u64(0).bytes();
+    data.extend(FrameBytes::new(1).u64(1_200).str("spi_write").bytes());
+    stream.process(&data).unwrap();
+    drop(stream);
+
+    let report = check.report();
+    assert_eq!(report.results()[0].count, 1);
+    assert!(!report.passed());
+}
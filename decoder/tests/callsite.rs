@@ -0,0 +1,84 @@
+//! Callsites are process-wide, so filling them up gets a test binary of its own.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use tracing::field::{Field, Visit};
+use tracing_defmt_decoder::source::LineReconstructor;
+use tracing_defmt_decoder::TraceDecoder;
+use tracing_subscriber::layer::SubscriberExt;
+
+/// An event as dispatched: its callsite, target and fields.
+type Seen = (tracing::callsite::Identifier, String, Vec<(String, String)>);
+
+/// Collects the dispatched events.
+struct Events(Arc<Mutex<Vec<Seen>>>);
+
+struct Fields(Vec<(String, String)>);
+
+impl Visit for Fields {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .push((field.name().to_string(), format!("{:?}", value)));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.push((field.name().to_string(), value.to_string()));
+    }
+}
+
+impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for Events {
+    fn on_event(&self, event: &tracing::Event, _: tracing_subscriber::layer::Context<S>) {
+        let mut fields = Fields(Vec::new());
+        event.record(&mut fields);
+        let metadata = event.metadata();
+        self.0
+            .lock()
+            .unwrap()
+            .push((metadata.callsite(), metadata.target().to_string(), fields.0));
+    }
+}
+
+#[test]
+fn test_new_field_names_stop_creating_callsites() {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let subscriber = tracing_subscriber::registry().with(Events(Arc::clone(&events)));
+    let decoder = TraceDecoder::builder()
+        .with_dispatch(tracing::Dispatch::new(subscriber))
+        .build_for_text()
+        .unwrap();
+
+    let lines = 3_000;
+    let mut reconstructor = LineReconstructor::new(decoder.new_stream());
+    for i in 0..lines {
+        let line = format!("reading, target=t{}, sensor{}={}", i, i, i);
+        reconstructor.push_line(&line).unwrap();
+    }
+    reconstructor.finish().unwrap();
+
+    let events = events.lock().unwrap();
+    assert_eq!(events.len(), lines);
+    let callsites: HashSet<_> = events.iter().map(|(callsite, _, _)| callsite).collect();
+    assert!(callsites.len() <= 1_024 + 1, "{}", callsites.len());
+
+    let field = |i: usize, name: &str| {
+        events[i]
+            .2
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.clone())
+    };
+    assert_eq!(events[0].1, "t0");
+    assert_eq!(field(0, "sensor0"), Some("0".to_string()));
+    assert_eq!(field(0, "fields"), None);
+
+    let last = lines - 1;
+    assert_eq!(events[last].1, "device_log");
+    assert_eq!(field(last, "sensor2999"), None);
+    assert_eq!(
+        field(last, "fields"),
+        Some("target=t2999, sensor2999=2999".to_string())
+    );
+    assert_eq!(field(last, "message"), Some("reading".to_string()));
+}
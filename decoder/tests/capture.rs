@@ -0,0 +1,105 @@
+mod common;
+
+use std::fs;
+
+use common::FrameBytes;
+use tracing_defmt_decoder::capture::create_capture_file;
+use tracing_defmt_decoder::source::Replay;
+use tracing_defmt_decoder::TraceDecoder;
+
+#[test]
+fn test_capture_replays_to_the_same_records() {
+    let table = common::table(&[("Info", "reading {=u32}")], None);
+    let decoder = TraceDecoder::builder()
+        .build_from_table(table, common::locations(1))
+        .unwrap();
+
+    let dir = std::env::temp_dir().join(format!("tracing-defmt-capture-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let (path, file) = create_capture_file(&dir).unwrap();
+    let name = path.file_name().unwrap().to_str().unwrap();
+    assert!(
+        name.starts_with("capture-") && name.ends_with("Z.bin"),
+        "{}",
+        name
+    );
+
+    let data = [
+        FrameBytes::new(0).u32(1).bytes(),
+        FrameBytes::new(0).u32(2).bytes(),
+    ]
+    .concat();
+    let mut live = Vec::new();
+    let mut stream = decoder.new_stream();
+    stream.set_capture(0, file);
+    // A frame split across reads is captured byte for byte.
+    for chunk in data.chunks(3) {
+        stream
+            .process_into(chunk, |record| live.push(record.message))
+            .unwrap();
+    }
+    stream.flush().unwrap();
+    drop(stream);
+
+    assert_eq!(fs::read(&path).unwrap(), data);
+    assert_eq!(live, ["reading 1", "reading 2"]);
+    Replay::open(&path)
+        .unwrap()
+        .run(&mut decoder.new_stream())
+        .unwrap();
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "compression")]
+#[test]
+fn test_compressed_captures_replay() {
+    use std::sync::{Arc, Mutex};
+
+    use tracing_defmt_decoder::capture::{create_compressed_capture_file, Compression};
+    use tracing_defmt_decoder::export::Exporter;
+    use tracing_defmt_decoder::TraceRecord;
+
+    struct Messages(Arc<Mutex<Vec<String>>>);
+
+    impl Exporter for Messages {
+        fn export(&mut self, record: &TraceRecord) -> std::io::Result<()> {
+            self.0.lock().unwrap().push(record.message.clone());
+            Ok(())
+        }
+    }
+
+    let table = common::table(&[("Info", "reading {=u32}")], None);
+    let decoder = TraceDecoder::builder()
+        .build_from_table(table, common::locations(1))
+        .unwrap();
+    let dir = std::env::temp_dir().join(format!("tracing-defmt-compressed-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let data: Vec<u8> = (0..100)
+        .flat_map(|i| FrameBytes::new(0).u32(i).bytes())
+        .collect();
+
+    for (compression, extension) in [
+        (Compression::Gzip, ".bin.gz"),
+        (Compression::Zstd, ".bin.zst"),
+    ] {
+        let (path, writer) = create_compressed_capture_file(&dir, compression).unwrap();
+        assert!(path.to_str().unwrap().ends_with(extension), "{:?}", path);
+        let mut stream = decoder.new_stream();
+        stream.set_capture(0, writer);
+        stream.process(&data).unwrap();
+        drop(stream);
+        assert!(fs::metadata(&path).unwrap().len() < data.len() as u64);
+
+        let messages = Arc::new(Mutex::new(Vec::new()));
+        let mut stream = decoder.new_stream();
+        stream.add_exporter(Messages(messages.clone()));
+        let read = Replay::open(&path).unwrap().run(&mut stream).unwrap();
+        assert_eq!(read, data.len() as u64);
+        let messages = messages.lock().unwrap();
+        assert_eq!(messages.len(), 100);
+        assert_eq!(messages[99], "reading 99");
+    }
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,34 @@
+#![cfg(feature = "cli")]
+
+use std::process::{Command, Output};
+
+fn cargo_defmt_trace(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_cargo-defmt-trace"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn test_help_prints_usage() {
+    let output = cargo_defmt_trace(&["defmt-trace", "--help"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("usage: cargo defmt-trace"), "{}", stdout);
+    assert!(stdout.contains("--export"), "{}", stdout);
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn test_unknown_option_fails_before_building() {
+    let output = cargo_defmt_trace(&["defmt-trace", "--bogus"]);
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.starts_with("error: unknown option `--bogus`"),
+        "{}",
+        stderr
+    );
+    assert!(stderr.contains("usage: cargo defmt-trace"), "{}", stderr);
+}
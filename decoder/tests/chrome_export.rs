@@ -0,0 +1,60 @@
+mod common;
+
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+use common::FrameBytes;
+use tracing_defmt_decoder::export::ChromeTraceWriter;
+use tracing_defmt_decoder::TraceDecoder;
+
+/// A writer whose contents stay readable after the exporter is dropped.
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_spans_become_begin_end_events() {
+    let table = common::table(
+        &[
+            ("Info", "span_enter: my_function(x={=u8})"),
+            ("Warn", "inside"),
+            ("Info", "span_exit: {=str}"),
+        ],
+        Some("{=u64:us}"),
+    );
+    let decoder = TraceDecoder::builder()
+        .build_from_table(table, common::locations(3))
+        .unwrap();
+
+    let buffer = SharedBuffer::default();
+    let mut stream = decoder.new_stream();
+    stream.add_exporter(ChromeTraceWriter::new(buffer.clone()).unwrap());
+
+    let mut data = FrameBytes::new(0).u64(1_000).u8(10).bytes();
+    data.extend(FrameBytes::new(1).u64(1_500).bytes());
+    data.extend(FrameBytes::new(2).u64(2_000).str("my_function").bytes());
+    stream.process(&data).unwrap();
+    drop(stream);
+
+    let trace: serde_json::Value = serde_json::from_slice(&buffer.0.lock().unwrap()).unwrap();
+    let events = trace.as_array().unwrap();
+    let phases: Vec<&str> = events.iter().map(|e| e["ph"].as_str().unwrap()).collect();
+    assert_eq!(phases, ["M", "B", "i", "E"]);
+
+    assert_eq!(events[1]["name"], "my_function");
+    assert_eq!(events[1]["ts"], 1_000);
+    assert_eq!(events[1]["args"]["x"], "10");
+    assert_eq!(events[1]["args"]["line"], 10);
+    assert_eq!(events[2]["name"], "inside");
+    assert_eq!(events[2]["args"]["level"], "WARN");
+    assert_eq!(events[3]["ts"], 2_000);
+}
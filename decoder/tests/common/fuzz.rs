@@ -0,0 +1,103 @@
+//! Drives a stream with arbitrary input, shared by `tests/robustness.rs` and the fuzz
+//! target in `fuzz/`.
+
+use std::time::Duration;
+
+use tracing_defmt_decoder::{Sampler, Timebase, TraceDecoder};
+
+use super::FrameBytes;
+
+/// Log statements that reach every kind of special frame. Most take a string, so the
+/// input decides which parser sees it.
+pub const ENTRIES: &[(&str, &str)] = &[
+    ("Info", "{=str}"),
+    ("Info", "span_enter: {=str}"),
+    ("Info", "span_exit: {=str}"),
+    ("Warn", "{=str}, port={=u8}"),
+    ("Info", "counter: {=str}={=u32}"),
+    ("Info", "gauge: level={=i32}"),
+    ("Info", "image_switch: {=u8}"),
+    ("Info", "time_sync: {=u32}"),
+    ("Info", "boot"),
+    ("Debug", "{=[u8]:x}"),
+    ("Error", "{=u64} {=f32} {=bool}"),
+];
+
+/// A well-formed capture using every entry, with a `u64` timestamp first in each frame,
+/// for mutation-based testing.
+pub fn seed() -> Vec<u8> {
+    let frames = [
+        FrameBytes::new(8).u64(1),
+        FrameBytes::new(1).u64(2).str("poll(n=3)"),
+        FrameBytes::new(3)
+            .u64(3)
+            .str("queue full, error=Busy")
+            .u8(7),
+        FrameBytes::new(4).u64(4).str("rx").u32(9),
+        FrameBytes::new(5).u64(5).u32(-2i32 as u32),
+        FrameBytes::new(7).u64(6).u32(0),
+        FrameBytes::new(0).u64(7).str("follows_from: poll"),
+        FrameBytes::new(9).u64(8).str("\u{1}\u{2}"),
+        FrameBytes::new(10).u64(9).u64(10).u32(0).u8(1),
+        FrameBytes::new(2).u64(10).str("poll"),
+        FrameBytes::new(6).u64(11).u8(0),
+    ];
+    frames.into_iter().flat_map(FrameBytes::bytes).collect()
+}
+
+/// Decodes `input`: its first byte selects the encoding and the decoder's options, the
+/// rest is fed in chunks whose lengths, contexts and interleaved stream events are taken
+/// from the input itself. Must neither panic nor hang.
+pub fn decode_arbitrary(input: &[u8]) {
+    let Some((&options, mut data)) = input.split_first() else {
+        return;
+    };
+    let bit = |n: u8| options & (1 << n) != 0;
+
+    let encoding = if bit(0) { "Rzcobs" } else { "Raw" };
+    let timestamp = bit(1).then_some("{=u64:us}");
+    let table = super::encoded_table(ENTRIES, timestamp, encoding);
+    let mut builder = TraceDecoder::builder().with_wall_clock_anchoring(bit(2));
+    if bit(3) {
+        builder = builder.with_reorder_window(Duration::from_micros(50));
+    }
+    if bit(4) {
+        builder = builder.with_min_span_duration(Duration::from_micros(3));
+    }
+    if bit(5) {
+        builder = builder.with_sampler(Sampler::new().with_ratio("poll", 0.5));
+    }
+    if bit(6) {
+        builder = builder.with_timebase(Timebase::new(32_768).with_counter_width(16));
+    }
+    builder = builder.with_field_extraction(!bit(7));
+    let decoder = builder
+        .build_from_table(table, super::locations(ENTRIES.len()))
+        .unwrap();
+
+    let mut stream = decoder.new_stream();
+    while let Some((&control, rest)) = data.split_first() {
+        let context = u32::from(control >> 6);
+        match control & 0x3f {
+            0x3f => {
+                let _ = stream.report_reboot(context);
+                data = rest;
+            }
+            0x3e => {
+                stream.report_loss(u64::from(context));
+                data = rest;
+            }
+            0x3d => {
+                let _ = stream.time_sync_request();
+                data = rest;
+            }
+            len => {
+                let (chunk, rest) = rest.split_at(rest.len().min(usize::from(len) + 1));
+                // Errors are fine, but the stream must take more data afterwards.
+                let _ = stream.process_context_into(context, chunk, |_| {});
+                data = rest;
+            }
+        }
+    }
+    let _ = stream.finish();
+}
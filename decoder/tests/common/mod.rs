@@ -0,0 +1,215 @@
+//! Builds defmt tables and raw frames, so the decoder can be tested without a firmware ELF.
+
+#![allow(dead_code)]
+
+pub mod fuzz;
+
+use std::path::PathBuf;
+
+use defmt_decoder::{Location, Locations, Table};
+use serde_json::json;
+
+/// Frame index of each entry passed to [`table`], in order.
+pub const FIRST_INDEX: u16 = 1;
+
+/// Builds a raw-encoded table from `(level, format)` pairs.
+pub fn table(entries: &[(&str, &str)], timestamp: Option<&str>) -> Table {
+    encoded_table(entries, timestamp, "Raw")
+}
+
+/// Like [`table`], declaring `encoding` (`"Raw"` or `"Rzcobs"`).
+pub fn encoded_table(entries: &[(&str, &str)], timestamp: Option<&str>, encoding: &str) -> Table {
+    let entries: serde_json::Map<String, serde_json::Value> = entries
+        .iter()
+        .enumerate()
+        .map(|(i, (tag, string))| {
+            (
+                (i + FIRST_INDEX as usize).to_string(),
+                json!({ "string": { "tag": tag, "string": string }, "raw_symbol": "<test>" }),
+            )
+        })
+        .collect();
+    let timestamp = timestamp.map(|string| {
+        json!({ "string": { "tag": "Timestamp", "string": string }, "raw_symbol": "<test>" })
+    });
+
+    serde_json::from_value(json!({
+        "timestamp": timestamp,
+        "entries": entries,
+        "bitflags": {},
+        "encoding": encoding,
+    }))
+    .unwrap()
+}
+
+/// Builds a minimal firmware ELF with a raw-encoded defmt table of `(level, format)`
+/// pairs and, if given, the wire protocol version symbol of the facade.
+pub fn elf(entries: &[(&str, &str)], protocol_version: Option<u32>) -> Vec<u8> {
+    elf_with_functions(entries, protocol_version, &[])
+}
+
+/// Like [`elf`], with a `.text` section holding the `(symbol, address, size)` functions.
+pub fn elf_with_functions(
+    entries: &[(&str, &str)],
+    protocol_version: Option<u32>,
+    functions: &[(&str, u64, u64)],
+) -> Vec<u8> {
+    use object::write::{Object, Symbol, SymbolSection};
+    use object::{
+        Architecture, BinaryFormat, Endianness, SectionKind, SymbolFlags, SymbolKind, SymbolScope,
+    };
+
+    let mut elf = Object::new(BinaryFormat::Elf, Architecture::Arm, Endianness::Little);
+    let symbol = |name: String, section, value| Symbol {
+        name: name.into_bytes(),
+        value,
+        size: 0,
+        kind: SymbolKind::Data,
+        scope: SymbolScope::Dynamic,
+        weak: false,
+        section,
+        flags: SymbolFlags::None,
+    };
+    for name in ["_defmt_version_ = 4", "_defmt_encoding_ = raw"] {
+        elf.add_symbol(symbol(name.to_string(), SymbolSection::Absolute, 0));
+    }
+
+    let defmt = elf.add_section(Vec::new(), b".defmt".to_vec(), SectionKind::ReadOnlyData);
+    elf.append_section_data(defmt, &vec![0; entries.len() + FIRST_INDEX as usize], 1);
+    for (i, (tag, string)) in entries.iter().enumerate() {
+        let name = json!({
+            "package": "app",
+            "tag": format!("defmt_{}", tag.to_lowercase()),
+            "data": string,
+            "disambiguator": i.to_string(),
+            "crate_name": "app",
+        });
+        let index = (i + FIRST_INDEX as usize) as u64;
+        elf.add_symbol(symbol(
+            name.to_string(),
+            SymbolSection::Section(defmt),
+            index,
+        ));
+    }
+
+    if let Some(version) = protocol_version {
+        let rodata = elf.add_section(Vec::new(), b".rodata".to_vec(), SectionKind::ReadOnlyData);
+        let offset = elf.append_section_data(rodata, &version.to_le_bytes(), 4);
+        elf.add_symbol(symbol(
+            "_TRACING_DEFMT_VERSION".to_string(),
+            SymbolSection::Section(rodata),
+            offset,
+        ));
+    }
+    if let Some(end) = functions
+        .iter()
+        .map(|(_, address, size)| address + size)
+        .max()
+    {
+        let text = elf.add_section(Vec::new(), b".text".to_vec(), SectionKind::Text);
+        elf.append_section_data(text, &vec![0; end as usize], 4);
+        for &(name, address, size) in functions {
+            elf.add_symbol(Symbol {
+                size,
+                kind: SymbolKind::Text,
+                ..symbol(name.to_string(), SymbolSection::Section(text), address)
+            });
+        }
+    }
+    elf.write().unwrap()
+}
+
+/// Gives every entry a location in `src/main.rs` of module `app`, at line `10 * index`.
+pub fn locations(count: usize) -> Locations {
+    (0..count as u64)
+        .map(|i| {
+            let index = i + FIRST_INDEX as u64;
+            let location = Location {
+                file: PathBuf::from("src/main.rs"),
+                line: index * 10,
+                module: "app".to_string(),
+            };
+            (index, location)
+        })
+        .collect()
+}
+
+/// Encodes one raw defmt frame.
+pub struct FrameBytes(Vec<u8>);
+
+impl FrameBytes {
+    /// Starts the frame for the entry at position `entry` of the table.
+    pub fn new(entry: usize) -> Self {
+        FrameBytes((entry as u16 + FIRST_INDEX).to_le_bytes().to_vec())
+    }
+
+    pub fn u8(mut self, value: u8) -> Self {
+        self.0.push(value);
+        self
+    }
+
+    pub fn u32(mut self, value: u32) -> Self {
+        self.0.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    pub fn u64(mut self, value: u64) -> Self {
+        self.0.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    pub fn str(mut self, value: &str) -> Self {
+        self.0
+            .extend_from_slice(&(value.len() as u32).to_le_bytes());
+        self.0.extend_from_slice(value.as_bytes());
+        self
+    }
+
+    /// An interned string: the index of the table entry at position `entry`.
+    pub fn istr(mut self, entry: usize) -> Self {
+        self.0
+            .extend_from_slice(&(entry as u16 + FIRST_INDEX).to_le_bytes());
+        self
+    }
+
+    pub fn bytes(self) -> Vec<u8> {
+        self.0
+    }
+}
+
+/// Encodes a frame with rzCOBS, followed by the frame separator, like `defmt`'s encoder.
+pub fn rzcobs(frame: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let (mut run, mut zeros) = (0u8, 0u8);
+    for &byte in frame {
+        if run < 7 {
+            if byte == 0 {
+                zeros |= 1 << run;
+            } else {
+                out.push(byte);
+            }
+            run += 1;
+            if run == 7 && zeros != 0 {
+                out.push(zeros);
+                (run, zeros) = (0, 0);
+            }
+        } else if byte == 0 {
+            out.push((run - 7) | 0x80);
+            (run, zeros) = (0, 0);
+        } else {
+            out.push(byte);
+            run += 1;
+            if run == 134 {
+                out.push(0xff);
+                (run, zeros) = (0, 0);
+            }
+        }
+    }
+    match run {
+        0 => {}
+        1..=6 => out.push((zeros | (0xff << run)) & 0x7f),
+        _ => out.push((run - 7) | 0x80),
+    }
+    out.push(0x00);
+    out
+}
@@ -0,0 +1,40 @@
+use tracing::level_filters::LevelFilter;
+use tracing_defmt_decoder::{ControlCommand, Error};
+
+#[test]
+fn test_commands_round_trip() {
+    for line in [
+        "set-filter app::net=trace",
+        "set-filter off",
+        "reset-filter",
+        "sync 7",
+    ] {
+        let command: ControlCommand = line.parse().unwrap();
+        assert_eq!(command.to_string(), line);
+    }
+    assert_eq!(
+        ControlCommand::SetFilter {
+            target: None,
+            level: LevelFilter::WARN,
+        }
+        .to_bytes(),
+        b"set-filter warn\n"
+    );
+}
+
+#[test]
+fn test_invalid_commands_are_rejected() {
+    for line in [
+        "reboot",
+        "set-filter app::net=3",
+        "set-filter =info",
+        "sync -1",
+        &format!("set-filter {}=info", "a".repeat(64)),
+    ] {
+        assert!(
+            matches!(line.parse::<ControlCommand>(), Err(Error::Command(_))),
+            "{}",
+            line
+        );
+    }
+}
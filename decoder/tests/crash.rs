@@ -0,0 +1,108 @@
+mod common;
+
+use common::FrameBytes;
+use tracing_defmt_decoder::{RecordKind, TraceDecoder, TraceRecord};
+
+const ENTRIES: [(&str, &str); 3] = [
+    ("Info", "span_enter: handle_rx()"),
+    ("Info", "span_exit: {=str}"),
+    (
+        "Error",
+        "crash: {=str}, pc={=u32:#x}, lr={=u32:#x}, sp={=u32:#x}, cfsr={=u32:#x}, hfsr={=u32:#x}, bfar={=u32:#x}, backtrace={=str}",
+    ),
+];
+
+/// A crash in `handle_rx`: a precise bus error at `0x20010000`.
+fn decode(decoder: &TraceDecoder, pc: u32, lr: u32, backtrace: &str) -> Vec<TraceRecord> {
+    let mut data = FrameBytes::new(0).bytes();
+    data.extend(
+        FrameBytes::new(2)
+            .str("HardFault")
+            .u32(pc)
+            .u32(lr)
+            .u32(0x2000_7fc0)
+            .u32(0x8200)
+            .u32(0x4000_0000)
+            .u32(0x2001_0000)
+            .str(backtrace)
+            .bytes(),
+    );
+    decoder.decode_records(&data).unwrap()
+}
+
+fn field<'r>(record: &'r TraceRecord, key: &str) -> Option<&'r str> {
+    record
+        .fields
+        .iter()
+        .find(|(k, _)| k == key)
+        .map(|(_, value)| value.as_str())
+}
+
+#[test]
+fn test_crash_frame_becomes_a_crash_span() {
+    let table = common::table(&ENTRIES, None);
+    let decoder = TraceDecoder::builder()
+        .build_from_table(table, common::locations(3))
+        .unwrap();
+    let records = decode(&decoder, 0x0800_0110, 0x0800_0221, "[0x8000221, 0x80002a1]");
+
+    let kinds: Vec<_> = records
+        .iter()
+        .map(|record| (record.kind, record.message.as_str()))
+        .collect();
+    assert_eq!(
+        kinds,
+        [
+            (RecordKind::SpanEnter, "handle_rx"),
+            (RecordKind::SpanEnter, "crash"),
+            (
+                RecordKind::Event,
+                "HardFault at 0x08000110: precise data bus error, at address 0x20010000"
+            ),
+            (RecordKind::SpanExit, "crash"),
+        ]
+    );
+    let crash = &records[1];
+    assert_eq!(crash.parent_id, records[0].span_id);
+    assert_eq!(field(crash, "fault"), Some("HardFault"));
+    assert_eq!(field(crash, "pc"), Some("0x8000110"));
+    assert_eq!(field(crash, "hfsr"), Some("0x40000000"));
+    assert_eq!(
+        field(crash, "backtrace"),
+        Some("#0 0x08000110\n#1 0x08000221\n#2 0x080002a1")
+    );
+    assert_eq!(records[2].span_id, crash.span_id);
+}
+
+#[test]
+fn test_crash_addresses_are_symbolicated() {
+    let functions = [
+        ("_ZN3app6sensor4read17h0123456789abcdefE", 0x100, 0x40),
+        ("_ZN3app4main17hfedcba9876543210E", 0x200, 0x100),
+        (
+            "_ZN46_$LT$app..Uart$u20$as$u20$core..fmt..Write$GT$9write_str17h00000000000000aaE",
+            0x400,
+            0x20,
+        ),
+    ];
+    let elf = common::elf_with_functions(&ENTRIES, None, &functions);
+    let decoder = TraceDecoder::new(&elf).unwrap();
+    let records = decode(&decoder, 0x111, 0xffff_fff9, "[0x221, 0x405, 0x801]");
+
+    assert_eq!(
+        records[2].message,
+        "HardFault in app::sensor::read: precise data bus error, at address 0x20010000"
+    );
+    let crash = &records[1];
+    assert_eq!(field(crash, "function"), Some("app::sensor::read"));
+    assert_eq!(
+        field(crash, "backtrace"),
+        Some(
+            "\
+#0 0x00000111 app::sensor::read
+#1 0x00000221 app::main
+#2 0x00000405 <app::Uart as core::fmt::Write>::write_str
+#3 0x00000801"
+        )
+    );
+}
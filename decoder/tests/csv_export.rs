@@ -0,0 +1,47 @@
+mod common;
+
+use common::FrameBytes;
+use tracing_defmt_decoder::export::{Exporter, SpanCsvWriter};
+use tracing_defmt_decoder::TraceDecoder;
+
+#[test]
+fn test_completed_spans_become_rows() {
+    let table = common::table(
+        &[
+            ("Info", "span_enter: handle(len={=u8}, tag={=str})"),
+            ("Debug", "span_enter: {=str}()"),
+            ("Info", "span_exit: {=str}"),
+        ],
+        Some("{=u64:us}"),
+    );
+    let decoder = TraceDecoder::builder()
+        .build_from_table(table, common::locations(3))
+        .unwrap();
+
+    let mut data = FrameBytes::new(0).u64(1_000).u8(12).str("a,\"b\"").bytes();
+    data.extend(FrameBytes::new(1).u64(1_100).str("parse").bytes());
+    data.extend(FrameBytes::new(2).u64(1_350).str("parse").bytes());
+    data.extend(FrameBytes::new(2).u64(2_000).str("handle").bytes());
+    // Still open at the end, so not in the table.
+    data.extend(FrameBytes::new(1).u64(2_100).str("parse").bytes());
+
+    let mut records = Vec::new();
+    let mut stream = decoder.new_stream();
+    stream
+        .process_into(&data, |record| records.push(record))
+        .unwrap();
+    let mut writer = SpanCsvWriter::new(Vec::new()).unwrap();
+    for record in &records {
+        writer.export(record).unwrap();
+    }
+    let csv = String::from_utf8(writer.finish().unwrap()).unwrap();
+
+    assert_eq!(
+        csv.lines().collect::<Vec<_>>(),
+        [
+            "name,start_us,end_us,duration_us,context,span_id,parent_id,parent,attributes",
+            "parse,1100,1350,250,0,2,1,handle,{}",
+            r#"handle,1000,2000,1000,0,1,,,"{""len"":""12"",""tag"":""a,\""b\""""}""#,
+        ]
+    );
+}
@@ -0,0 +1,102 @@
+mod common;
+
+use common::FrameBytes;
+use tracing_defmt_decoder::diff::{Change, Statistic, TraceDiff};
+use tracing_defmt_decoder::{TraceDecoder, TraceRecord};
+
+fn decoder() -> TraceDecoder {
+    let table = common::table(
+        &[
+            ("Info", "span_enter: handle_rx()"),
+            ("Info", "span_enter: crc()"),
+            ("Info", "span_enter: legacy()"),
+            ("Info", "span_enter: compress()"),
+            ("Info", "span_exit: {=str}"),
+        ],
+        Some("{=u64:us}"),
+    );
+    TraceDecoder::builder()
+        .build_from_table(table, common::locations(5))
+        .unwrap()
+}
+
+/// Runs each `(index, name, duration)` span one after the other.
+fn session(spans: &[(usize, &str, u64)]) -> Vec<TraceRecord> {
+    let mut data = Vec::new();
+    let mut now = 0;
+    for &(index, name, duration) in spans {
+        data.extend(FrameBytes::new(index).u64(now).bytes());
+        now += duration;
+        data.extend(FrameBytes::new(4).u64(now).str(name).bytes());
+    }
+    decoder().decode_records(&data).unwrap()
+}
+
+#[test]
+fn test_diff_finds_added_removed_and_regressed_spans() {
+    let before = session(&[
+        (0, "handle_rx", 100),
+        (0, "handle_rx", 100),
+        (1, "crc", 10),
+        (2, "legacy", 50),
+    ]);
+    let after = session(&[
+        (0, "handle_rx", 130),
+        (1, "crc", 11),
+        (1, "crc", 11),
+        (3, "compress", 40),
+    ]);
+    let diff = TraceDiff::new(&before, &after);
+
+    let changes: Vec<_> = diff
+        .spans()
+        .iter()
+        .map(|span| (span.name.as_str(), span.change, span.count_delta()))
+        .collect();
+    assert_eq!(
+        changes,
+        [
+            ("compress", Change::Added, 1),
+            ("crc", Change::Unchanged, 1),
+            ("handle_rx", Change::Regressed, -1),
+            ("legacy", Change::Removed, -1),
+        ]
+    );
+    assert_eq!(diff.regressions().count(), 1);
+    assert_eq!(diff.spans()[2].duration_change, Some(30.0));
+
+    assert_eq!(
+        diff.to_string(),
+        "\
+span       before   after   delta  before_us   after_us   change  status
+compress        -       1      +1          -         40        -  added
+crc             1       2      +1         10         11   +10.0%
+handle_rx       2       1      -1        100        130   +30.0%  regressed
+legacy          1       -      -1         50          -        -  removed
+"
+    );
+
+    let json = diff.to_json();
+    assert_eq!(json["regressions"], 1);
+    assert_eq!(json["spans"][2]["before_us"], 100);
+    assert_eq!(json["spans"][2]["change"], "regressed");
+}
+
+#[test]
+fn test_diff_thresholds() {
+    let before = session(&[(1, "crc", 10), (0, "handle_rx", 100)]);
+    let after = session(&[(1, "crc", 20), (0, "handle_rx", 50)]);
+
+    let diff =
+        TraceDiff::new(&before, &after).with_min_change(std::time::Duration::from_micros(20));
+    assert_eq!(diff.spans()[0].change, Change::Unchanged);
+    assert_eq!(diff.spans()[1].change, Change::Improved);
+
+    let diff = TraceDiff::new(&before, &after)
+        .with_threshold(150.0)
+        .with_statistic(Statistic::Max);
+    assert!(diff
+        .spans()
+        .iter()
+        .all(|span| span.change == Change::Unchanged));
+}
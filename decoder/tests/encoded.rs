@@ -0,0 +1,87 @@
+mod common;
+
+use std::io::Cursor;
+
+use common::FrameBytes;
+use tracing_defmt_decoder::export::Exporter;
+use tracing_defmt_decoder::source::{EncodedLines, LineEncoding};
+use tracing_defmt_decoder::{TraceDecoder, TraceRecord};
+
+/// Collects exported messages.
+struct Messages<'a>(&'a mut Vec<String>);
+
+impl Exporter for Messages<'_> {
+    fn export(&mut self, record: &TraceRecord) -> std::io::Result<()> {
+        self.0.push(record.message.clone());
+        Ok(())
+    }
+}
+
+fn hex(bytes: &[u8], separator: &str) -> String {
+    let digits: Vec<_> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    digits.join(separator)
+}
+
+#[test]
+fn test_line_encodings() {
+    let bytes = [0x00, 0x0f, 0xa4, 0xff];
+    for line in [
+        "00 0f a4 ff",
+        "000FA4FF",
+        "0x00, 0xf, 0xA4, 0xff",
+        "00:0f:a4:ff\r\n",
+    ] {
+        assert_eq!(
+            LineEncoding::Hex.decode(line),
+            Some(bytes.to_vec()),
+            "{}",
+            line
+        );
+    }
+    for line in ["AA+k/w==", "AA+k/w", " AA+k /w==\n"] {
+        assert_eq!(
+            LineEncoding::Base64.decode(line),
+            Some(bytes.to_vec()),
+            "{}",
+            line
+        );
+    }
+    assert_eq!(LineEncoding::Base64.decode("AAAA"), Some(vec![0; 3]));
+
+    for line in ["0f a", "0g", "-- log start --"] {
+        assert_eq!(LineEncoding::Hex.decode(line), None, "{}", line);
+    }
+    for line in ["A", "AA=A", "AA?A"] {
+        assert_eq!(LineEncoding::Base64.decode(line), None, "{}", line);
+    }
+}
+
+#[test]
+fn test_hex_lines_are_decoded() {
+    let table = common::table(&[("Info", "reading {=u32}")], None);
+    let decoder = TraceDecoder::builder()
+        .build_from_table(table, common::locations(1))
+        .unwrap();
+    let first = FrameBytes::new(0).u32(1).bytes();
+    let second = FrameBytes::new(0).u32(2).bytes();
+    let third = FrameBytes::new(0).u32(3).bytes();
+    // A frame across lines, one cut short by a line that does not decode.
+    let text = [
+        "",
+        &hex(&first[..2], " "),
+        &hex(&first[2..], ""),
+        &hex(&second[..3], " "),
+        "<disconnected>",
+        &hex(&third, ", "),
+    ]
+    .join("\n");
+
+    let mut messages = Vec::new();
+    let mut stream = decoder.new_stream();
+    let mut lines = EncodedLines::new(Cursor::new(text), LineEncoding::Hex);
+    stream.add_exporter(Messages(&mut messages));
+    assert_eq!(lines.run(&mut stream).unwrap(), 6);
+    assert_eq!(lines.invalid_lines(), 1);
+    drop(stream);
+    assert_eq!(messages, ["reading 1", "reading 3"]);
+}
@@ -0,0 +1,98 @@
+mod common;
+
+use common::FrameBytes;
+use tracing_defmt_decoder::{Encoding, Error, TraceDecoder};
+
+const ENTRIES: &[(&str, &str)] = &[
+    ("Info", "span_enter: poll(port={=u8})"),
+    ("Info", "span_exit: {=str}"),
+];
+
+fn frames() -> Vec<Vec<u8>> {
+    vec![
+        FrameBytes::new(0).u8(3).bytes(),
+        FrameBytes::new(1).str("poll").bytes(),
+    ]
+}
+
+#[test]
+fn test_encoding_is_exposed_and_can_be_overridden() {
+    let table = common::encoded_table(ENTRIES, None, "Raw");
+    let decoder = TraceDecoder::builder()
+        .build_from_table(table.clone(), common::locations(2))
+        .unwrap();
+    assert_eq!(decoder.encoding(), Some(Encoding::Raw));
+    assert_eq!(decoder.declared_encoding(), Some(Encoding::Raw));
+    assert_eq!(
+        TraceDecoder::builder().build_for_text().unwrap().encoding(),
+        None
+    );
+
+    let decoder = TraceDecoder::builder()
+        .with_encoding(Encoding::Rzcobs)
+        .build_from_table(table, common::locations(2))
+        .unwrap();
+    assert_eq!(decoder.encoding(), Some(Encoding::Rzcobs));
+    assert_eq!(decoder.declared_encoding(), Some(Encoding::Raw));
+
+    let data: Vec<u8> = frames().iter().flat_map(|f| common::rzcobs(f)).collect();
+    let mut records = Vec::new();
+    let mut stream = decoder.new_stream();
+    // Split mid-frame: the partial frame is kept between calls.
+    let (head, tail) = data.split_at(4);
+    stream.process_into(head, |r| records.push(r)).unwrap();
+    stream.process_into(tail, |r| records.push(r)).unwrap();
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].fields, [("port".to_string(), "3".to_string())]);
+    assert_eq!(stream.stats().malformed, 0);
+}
+
+#[test]
+fn test_raw_data_fails_rzcobs_decoder() {
+    let table = common::encoded_table(ENTRIES, None, "Rzcobs");
+    let decoder = TraceDecoder::builder()
+        .build_from_table(table, common::locations(2))
+        .unwrap();
+
+    let data: Vec<u8> = frames().concat();
+    let mut stream = decoder.new_stream();
+    let error = stream.process(&data).unwrap_err();
+    assert!(
+        matches!(
+            error,
+            Error::EncodingMismatch {
+                context: 0,
+                expected: Encoding::Rzcobs,
+                detected: Encoding::Raw,
+            }
+        ),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn test_rzcobs_data_fails_raw_decoder() {
+    let table = common::encoded_table(ENTRIES, None, "Raw");
+    let decoder = TraceDecoder::builder()
+        .build_from_table(table, common::locations(2))
+        .unwrap();
+
+    // The encoder starts with a separator, in case a previous boot left a partial frame.
+    let mut data = vec![0x00];
+    data.extend(frames().iter().flat_map(|f| common::rzcobs(f)));
+    let mut stream = decoder.new_stream();
+    let error = stream.process(&data).unwrap_err();
+    assert!(
+        matches!(
+            error,
+            Error::EncodingMismatch {
+                expected: Encoding::Raw,
+                detected: Encoding::Rzcobs,
+                ..
+            }
+        ),
+        "{}",
+        error
+    );
+}
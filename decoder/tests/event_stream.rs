@@ -0,0 +1,53 @@
+#![cfg(feature = "async")]
+
+mod common;
+
+use std::future::poll_fn;
+use std::pin::Pin;
+
+use common::FrameBytes;
+use futures_core::Stream;
+use tracing_defmt_decoder::{RecordKind, TraceDecoder};
+
+#[test]
+fn test_event_stream_yields_records_until_eof() {
+    let table = common::table(
+        &[
+            ("Info", "span_enter: poll()"),
+            ("Info", "tick {=u8}"),
+            ("Info", "span_exit: {=str}"),
+        ],
+        None,
+    );
+    let decoder = TraceDecoder::builder()
+        .build_from_table(table, common::locations(3))
+        .unwrap();
+
+    let mut data = FrameBytes::new(0).bytes();
+    data.extend(FrameBytes::new(1).u8(3).bytes());
+    data.extend(FrameBytes::new(2).str("poll").bytes());
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let records = runtime.block_on(async {
+        let mut events = decoder.new_stream().into_event_stream(&data[..]);
+        let mut records = Vec::new();
+        while let Some(record) = poll_fn(|cx| Pin::new(&mut events).poll_next(cx)).await {
+            records.push(record.unwrap());
+        }
+        records
+    });
+
+    let kinds: Vec<RecordKind> = records.iter().map(|r| r.kind).collect();
+    assert_eq!(
+        kinds,
+        [
+            RecordKind::SpanEnter,
+            RecordKind::Event,
+            RecordKind::SpanExit
+        ]
+    );
+    assert_eq!(records[1].message, "tick 3");
+    assert_eq!(records[1].span_id, Some(1));
+}
@@ -0,0 +1,79 @@
+mod common;
+
+use common::FrameBytes;
+use tracing::Level;
+use tracing_defmt_decoder::expect::{expect_event, expect_span};
+use tracing_defmt_decoder::{TraceDecoder, TraceRecord};
+
+/// Two packets, the second with a bad checksum.
+fn records() -> Vec<TraceRecord> {
+    let table = common::table(
+        &[
+            ("Info", "span_enter: handle_packet(len={=u32})"),
+            ("Warn", "checksum mismatch"),
+            ("Info", "span_exit: {=str}"),
+            ("Info", "span_enter: verify()"),
+        ],
+        Some("{=u64:us}"),
+    );
+    let decoder = TraceDecoder::builder()
+        .build_from_table(table, common::locations(4))
+        .unwrap();
+
+    let mut data = FrameBytes::new(0).u64(1_000).u32(8).bytes();
+    data.extend(FrameBytes::new(2).u64(1_100).str("handle_packet").bytes());
+    data.extend(FrameBytes::new(0).u64(2_000).u32(12).bytes());
+    data.extend(FrameBytes::new(3).u64(2_050).bytes());
+    data.extend(FrameBytes::new(1).u64(2_150).bytes());
+    data.extend(FrameBytes::new(2).u64(2_200).str("verify").bytes());
+    data.extend(FrameBytes::new(2).u64(2_250).str("handle_packet").bytes());
+    decoder.decode_records(&data).unwrap()
+}
+
+#[test]
+fn test_span_expectations_match_any_run() {
+    let records = records();
+    expect_span(&records, "handle_packet")
+        .with_field("len", 12)
+        .containing_event(Level::WARN, "checksum")
+        .completed()
+        .assert();
+    expect_span(&records, "handle_packet")
+        .with_field("len", 8)
+        .without_errors()
+        .assert();
+
+    let failed = expect_span(&records, "handle_packet")
+        .with_field("len", 8)
+        .containing_event(Level::WARN, "checksum")
+        .check()
+        .unwrap_err();
+    assert_eq!(
+        failed.to_string(),
+        "expected span `handle_packet` with len=8 containing WARN event `checksum`, found 2 \
+         runs, which has no WARN event `checksum`; has len=12"
+    );
+}
+
+#[test]
+fn test_event_expectations_count_matching_events() {
+    let records = records();
+    expect_event(&records, "checksum mismatch")
+        .at_level(Level::WARN)
+        .in_span("verify")
+        .times(1)
+        .assert();
+    expect_event(&records, "checksum")
+        .in_span("handle_packet")
+        .assert();
+    expect_event(&records, "timeout").times(0).assert();
+
+    let failed = expect_event(&records, "checksum")
+        .at_level(Level::ERROR)
+        .check()
+        .unwrap_err();
+    assert_eq!(
+        failed.to_string(),
+        "expected event `checksum` at ERROR, found none, the first similar one is at WARN"
+    );
+}
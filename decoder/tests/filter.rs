@@ -0,0 +1,50 @@
+mod common;
+
+use common::FrameBytes;
+use defmt_decoder::Location;
+use tracing_defmt_decoder::{Filter, TraceDecoder};
+
+#[test]
+fn test_filter_by_module_and_level() {
+    let table = common::table(
+        &[
+            ("Debug", "span_enter: poll_radio()"),
+            ("Trace", "rssi {=u8}"),
+            ("Debug", "span_exit: {=str}"),
+            ("Debug", "main loop"),
+            ("Info", "started"),
+        ],
+        None,
+    );
+    let mut locations = common::locations(5);
+    for index in 1..=3 {
+        let location: &mut Location = locations.get_mut(&index).unwrap();
+        location.module = "app::radio".into();
+    }
+    let filter: Filter = "info,app::radio=trace,app::radio::irq=off".parse().unwrap();
+    let decoder = TraceDecoder::builder()
+        .with_filter(filter)
+        .build_from_table(table, locations)
+        .unwrap();
+
+    let data = [
+        FrameBytes::new(0).bytes(),
+        FrameBytes::new(1).u8(42).bytes(),
+        FrameBytes::new(2).str("poll_radio").bytes(),
+        FrameBytes::new(3).bytes(),
+        FrameBytes::new(4).bytes(),
+    ]
+    .concat();
+
+    let mut messages = Vec::new();
+    let mut stream = decoder.new_stream();
+    stream
+        .process_into(&data, |record| messages.push(record.message))
+        .unwrap();
+    assert_eq!(messages, ["poll_radio", "rssi 42", "poll_radio", "started"]);
+}
+
+#[test]
+fn test_invalid_filter_level() {
+    assert!("app=loud".parse::<Filter>().is_err());
+}
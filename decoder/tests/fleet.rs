@@ -0,0 +1,123 @@
+mod common;
+
+use std::io::Cursor;
+use std::sync::{Arc, Mutex};
+
+use common::FrameBytes;
+use tracing_defmt_decoder::export::Exporter;
+use tracing_defmt_decoder::source::{Replay, Source};
+use tracing_defmt_decoder::{Error, Fleet, RecordKind, TraceDecoder, TraceRecord, TraceStream};
+
+type Records = Arc<Mutex<Vec<(String, RecordKind, String)>>>;
+
+/// Collects the records of one device, tagged with its id.
+struct Tagged {
+    device: String,
+    records: Records,
+}
+
+impl Exporter for Tagged {
+    fn export(&mut self, record: &TraceRecord) -> std::io::Result<()> {
+        self.records.lock().unwrap().push((
+            self.device.clone(),
+            record.kind,
+            record.message.clone(),
+        ));
+        Ok(())
+    }
+}
+
+fn decoder(id: &str, entries: &[(&str, &str)]) -> TraceDecoder {
+    TraceDecoder::builder()
+        .with_device_id(id)
+        .build_from_table(
+            common::table(entries, None),
+            common::locations(entries.len()),
+        )
+        .unwrap()
+}
+
+fn collect(fleet: &mut Fleet) -> Records {
+    let records = Records::default();
+    let shared = Arc::clone(&records);
+    fleet.on_stream(move |id, stream| {
+        stream.add_exporter(Tagged {
+            device: id.to_string(),
+            records: Arc::clone(&shared),
+        })
+    });
+    records
+}
+
+#[test]
+fn test_devices_are_decoded_with_their_own_firmware() {
+    let mut fleet = Fleet::new();
+    fleet.add_decoder(
+        "board-1",
+        decoder("board-1", &[("Info", "temp {=u8}")]),
+        Replay::new(Cursor::new(FrameBytes::new(0).u8(21).bytes())),
+    );
+    let mut data = FrameBytes::new(1).bytes();
+    data.extend(FrameBytes::new(0).u8(5).bytes());
+    fleet.add_decoder(
+        "board-2",
+        decoder(
+            "board-2",
+            &[("Info", "volts {=u8}"), ("Info", "span_enter: poll()")],
+        ),
+        Replay::new(Cursor::new(data)),
+    );
+    assert_eq!(
+        fleet.device_ids().collect::<Vec<_>>(),
+        ["board-1", "board-2"]
+    );
+    let records = collect(&mut fleet);
+    fleet.run().unwrap();
+
+    let mut records = records.lock().unwrap().clone();
+    records.sort_by(|a, b| a.0.cmp(&b.0));
+    assert_eq!(
+        records,
+        [
+            ("board-1".into(), RecordKind::Event, "temp 21".into()),
+            ("board-2".into(), RecordKind::SpanEnter, "poll".into()),
+            ("board-2".into(), RecordKind::Event, "volts 5".into()),
+            // Closed when the capture ends.
+            ("board-2".into(), RecordKind::SpanExit, "poll".into()),
+        ]
+    );
+}
+
+/// A transport that drops right away.
+struct Unplugged;
+
+impl Source for Unplugged {
+    fn run(&mut self, _stream: &mut TraceStream) -> Result<(), Error> {
+        Err(std::io::Error::from(std::io::ErrorKind::NotConnected).into())
+    }
+}
+
+#[test]
+fn test_failing_device_does_not_stop_the_others() {
+    let entries = [("Info", "temp {=u8}")];
+    let mut fleet = Fleet::new();
+    fleet.add_decoder("board-1", decoder("board-1", &entries), Unplugged);
+    fleet.add_decoder(
+        "board-2",
+        decoder("board-2", &entries),
+        Replay::new(Cursor::new(FrameBytes::new(0).u8(22).bytes())),
+    );
+    let records = collect(&mut fleet);
+
+    match fleet.run() {
+        Err(Error::Device { device, source }) => {
+            assert_eq!(device, "board-1");
+            assert!(matches!(*source, Error::Io(_)), "{:?}", source);
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    assert_eq!(
+        *records.lock().unwrap(),
+        [("board-2".into(), RecordKind::Event, "temp 22".into())]
+    );
+}
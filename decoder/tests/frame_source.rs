@@ -0,0 +1,128 @@
+mod common;
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use common::FrameBytes;
+use tracing_defmt_decoder::export::Exporter;
+use tracing_defmt_decoder::source::{Chunk, ChunkSink, FrameSource};
+use tracing_defmt_decoder::{Error, Fleet, TraceDecoder, TraceRecord};
+
+/// A CAN bus carrying defmt bytes in 8-byte frames, each timestamped by the adapter.
+struct CanBus {
+    frames: VecDeque<(Vec<u8>, SystemTime)>,
+}
+
+impl CanBus {
+    fn new(data: &[u8], start: SystemTime) -> Self {
+        let frames = data
+            .chunks(8)
+            .enumerate()
+            .map(|(i, frame)| (frame.to_vec(), start + Duration::from_millis(i as u64)))
+            .collect();
+        Self { frames }
+    }
+}
+
+impl FrameSource for CanBus {
+    fn read_chunks(&mut self, sink: &mut ChunkSink<'_>) -> Result<bool, Error> {
+        let Some((frame, received_at)) = self.frames.pop_front() else {
+            return Ok(false);
+        };
+        sink(Chunk::Data {
+            context: 0,
+            bytes: &frame,
+            received_at: Some(received_at),
+        })?;
+        Ok(true)
+    }
+}
+
+type Records = Arc<Mutex<Vec<(String, Option<SystemTime>)>>>;
+
+/// Collects exported messages with their wall-clock time.
+struct WallTimes(Records);
+
+impl Exporter for WallTimes {
+    fn export(&mut self, record: &TraceRecord) -> std::io::Result<()> {
+        self.0
+            .lock()
+            .unwrap()
+            .push((record.message.clone(), record.wall_time));
+        Ok(())
+    }
+}
+
+fn decoder() -> TraceDecoder {
+    let table = common::table(&[("Info", "reading {=u32}")], Some("{=u64:us}"));
+    TraceDecoder::builder()
+        .with_wall_clock_anchoring(true)
+        .build_from_table(table, common::locations(1))
+        .unwrap()
+}
+
+#[test]
+fn test_chunks_are_anchored_to_their_receive_time() {
+    let start = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    // Two frames of 13 bytes, the second completed by the fourth CAN frame.
+    let mut data = FrameBytes::new(0).u64(1_000).u32(1).bytes();
+    data.extend(FrameBytes::new(0).u64(1_500).u32(2).bytes());
+    let mut can = CanBus::new(&data, start);
+
+    let records = Records::default();
+    let decoder = decoder();
+    let mut stream = decoder.new_stream();
+    stream.add_exporter(WallTimes(records.clone()));
+    can.feed(&mut stream).unwrap();
+    assert_eq!(stream.stats().bytes_received, data.len() as u64);
+    drop(stream);
+
+    // The first frame arrived with the second CAN frame; the second frame keeps its
+    // device interval, as its CAN frame arrived later than that.
+    assert_eq!(
+        *records.lock().unwrap(),
+        [
+            (
+                "reading 1".to_string(),
+                Some(start + Duration::from_millis(1))
+            ),
+            (
+                "reading 2".to_string(),
+                Some(start + Duration::from_millis(1) + Duration::from_micros(500))
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_frame_sources_run_in_a_fleet() {
+    let records = Records::default();
+    let shared = records.clone();
+    let mut fleet = Fleet::new();
+    fleet.add_decoder(
+        "can-node-7",
+        decoder(),
+        Box::new(CanBus::new(
+            &FrameBytes::new(0).u64(0).u32(7).bytes(),
+            SystemTime::now(),
+        )),
+    );
+    // Unboxed, it is a `Source` as well.
+    fleet.add_decoder(
+        "can-node-8",
+        decoder(),
+        CanBus::new(&FrameBytes::new(0).u64(0).u32(8).bytes(), SystemTime::now()),
+    );
+    fleet.on_stream(move |_, stream| stream.add_exporter(WallTimes(shared.clone())));
+    fleet.run().unwrap();
+
+    let mut messages: Vec<String> = records
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(message, _)| message.clone())
+        .collect();
+    messages.sort();
+    assert_eq!(messages, ["reading 7", "reading 8"]);
+}
@@ -0,0 +1,168 @@
+mod common;
+
+use std::sync::Mutex;
+
+use common::FrameBytes;
+use tracing_defmt_decoder::{RebootCause, RecordKind, StreamIssue, TraceDecoder};
+
+#[test]
+fn test_malformed_data_is_reported() {
+    let table = common::table(&[("Info", "ok {=u8}")], None);
+    let decoder = TraceDecoder::builder()
+        .build_from_table(table, common::locations(1))
+        .unwrap();
+
+    let issues = Mutex::new(Vec::new());
+    let mut stream = decoder.new_stream();
+    stream.on_issue(|issue| issues.lock().unwrap().push(issue.clone()));
+
+    let mut messages = Vec::new();
+    stream
+        .process_into(&FrameBytes::new(0).u8(1).bytes(), |r| {
+            messages.push(r.message)
+        })
+        .unwrap();
+    // No entry has index 0xffff.
+    stream
+        .process_into(&[0xff, 0xff, 0x00], |r| messages.push(r.message))
+        .unwrap();
+    stream
+        .process_into(&FrameBytes::new(0).u8(2).bytes(), |r| {
+            messages.push(r.message)
+        })
+        .unwrap();
+    stream.report_loss(4);
+
+    assert_eq!(messages, ["ok 1", "ok 2"]);
+    assert_eq!(
+        *issues.lock().unwrap(),
+        [
+            // The read that completed the last good frame counts as well.
+            StreamIssue::Malformed {
+                context: 0,
+                offset: 6,
+                bytes_discarded: 6,
+            },
+            StreamIssue::FramesLost {
+                context: 0,
+                count: 4,
+            },
+        ]
+    );
+
+    let stats = stream.stats();
+    assert_eq!(stats.bytes_received, 9);
+    assert_eq!(stats.frames_decoded, 2);
+    assert_eq!(stats.malformed, 1);
+    assert_eq!(stats.bytes_discarded, 6);
+    assert_eq!(stats.frames_lost, 4);
+}
+
+#[test]
+fn test_reboot_truncates_spans_and_is_reported() {
+    let table = common::table(
+        &[("Info", "span_enter: {=str}()"), ("Info", "boot")],
+        Some("{=u64:us}"),
+    );
+    let decoder = TraceDecoder::builder()
+        .build_from_table(table, common::locations(2))
+        .unwrap();
+
+    let issues = Mutex::new(Vec::new());
+    let mut stream = decoder.new_stream();
+    stream.on_issue(|issue| issues.lock().unwrap().push(issue.clone()));
+
+    let mut data = FrameBytes::new(0).u64(5_000).str("main_loop").bytes();
+    // The device resets and starts counting from zero.
+    data.extend(FrameBytes::new(0).u64(10).str("init").bytes());
+    data.extend(FrameBytes::new(1).u64(20).bytes());
+    let mut records = Vec::new();
+    stream
+        .process_into(&data, |record| records.push(record))
+        .unwrap();
+
+    let summary: Vec<(RecordKind, &str, Option<u64>)> = records
+        .iter()
+        .map(|r| (r.kind, r.message.as_str(), r.parent_id))
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            (RecordKind::SpanEnter, "main_loop", None),
+            (RecordKind::SpanExit, "main_loop", None),
+            (RecordKind::SpanEnter, "init", None),
+            (RecordKind::SpanExit, "init", None),
+            (RecordKind::Event, "boot", None),
+        ]
+    );
+    assert_eq!(
+        records[1].fields,
+        [("truncated".to_string(), "true".to_string())]
+    );
+    assert_eq!(
+        *issues.lock().unwrap(),
+        [
+            StreamIssue::Reboot {
+                context: 0,
+                cause: RebootCause::TimestampReset,
+            },
+            StreamIssue::Reboot {
+                context: 0,
+                cause: RebootCause::BootFrame,
+            },
+        ]
+    );
+    assert_eq!(stream.stats().reboots, 2);
+    assert_eq!(stream.stats().spans_truncated, 2);
+}
+
+#[test]
+fn test_boots_are_counted_by_reset_reason() {
+    let table = common::table(
+        &[
+            ("Info", "boot: reset.reason={=str}"),
+            ("Info", "span_enter: main_loop()"),
+            ("Info", "boot"),
+            ("Info", "boot: v1.2, cold, reset.reason={=str}"),
+        ],
+        None,
+    );
+    let decoder = TraceDecoder::builder()
+        .build_from_table(table, common::locations(4))
+        .unwrap();
+
+    let mut data = FrameBytes::new(0).str("por").bytes();
+    data.extend(FrameBytes::new(1).bytes());
+    data.extend(FrameBytes::new(0).str("watchdog").bytes());
+    data.extend(FrameBytes::new(3).str("watchdog").bytes());
+    data.extend(FrameBytes::new(2).bytes());
+    data.extend(FrameBytes::new(0).str("watchdog").bytes());
+    let mut stream = decoder.new_stream();
+    let mut records = Vec::new();
+    stream
+        .process_into(&data, |record| records.push(record))
+        .unwrap();
+
+    let boots: Vec<_> = records
+        .iter()
+        .filter(|record| record.kind == RecordKind::Event)
+        .map(|record| (record.message.as_str(), record.fields.clone()))
+        .collect();
+    let reason = |reason: &str| vec![("reset.reason".to_string(), reason.to_string())];
+    assert_eq!(
+        boots,
+        [
+            ("boot", reason("por")),
+            ("boot", reason("watchdog")),
+            ("boot: v1.2, cold", reason("watchdog")),
+            ("boot", Vec::new()),
+            ("boot", reason("watchdog")),
+        ]
+    );
+    let stats = stream.stats();
+    assert_eq!(stats.reboots, 4);
+    assert_eq!(
+        stats.resets.iter().collect::<Vec<_>>(),
+        [(&"por".to_string(), &1), (&"watchdog".to_string(), &3)]
+    );
+}
@@ -0,0 +1,124 @@
+mod common;
+
+use std::collections::VecDeque;
+use std::thread;
+use std::time::Duration;
+
+use common::FrameBytes;
+use tracing_defmt_decoder::hil::{HilRun, StopReason};
+use tracing_defmt_decoder::source::{Chunk, ChunkSink, FrameSource};
+use tracing_defmt_decoder::{Error, TraceDecoder};
+
+/// A device that sends its trace in packets, then keeps quiet.
+struct Device(VecDeque<Vec<u8>>);
+
+impl FrameSource for Device {
+    fn read_chunks(&mut self, sink: &mut ChunkSink<'_>) -> Result<bool, Error> {
+        match self.0.pop_front() {
+            Some(packet) => sink(Chunk::data(&packet))?,
+            None => thread::sleep(Duration::from_millis(10)),
+        }
+        Ok(true)
+    }
+}
+
+fn decoder() -> TraceDecoder {
+    let table = common::table(
+        &[
+            ("Info", "span_enter: dfu_update()"),
+            ("Info", "span_enter: write_block()"),
+            ("Error", "flash write failed"),
+            ("Info", "span_exit: {=str}"),
+            ("Info", "dfu done"),
+            ("Info", "late"),
+        ],
+        Some("{=u64:us}"),
+    );
+    TraceDecoder::builder()
+        .build_from_table(table, common::locations(6))
+        .unwrap()
+}
+
+fn dfu(write_error: bool) -> Device {
+    let mut packets = vec![
+        FrameBytes::new(0).u64(0).bytes(),
+        FrameBytes::new(1).u64(100_000).bytes(),
+    ];
+    if write_error {
+        packets.push(FrameBytes::new(2).u64(200_000).bytes());
+    }
+    packets.push(FrameBytes::new(3).u64(300_000).str("write_block").bytes());
+    packets.push(FrameBytes::new(3).u64(1_500_000).str("dfu_update").bytes());
+    packets.push(FrameBytes::new(4).u64(1_600_000).bytes());
+    packets.push(FrameBytes::new(5).u64(1_700_000).bytes());
+    Device(packets.into())
+}
+
+#[test]
+fn test_run_stops_at_marker() {
+    let decoder = decoder();
+    let trace = HilRun::new()
+        .until_event("dfu done")
+        .run(&decoder, dfu(false))
+        .unwrap();
+
+    assert_eq!(trace.stop_reason(), StopReason::Marker);
+    assert_eq!(trace.records().last().unwrap().message, "dfu done");
+    trace
+        .expect_span("dfu_update")
+        .completed_within(Duration::from_secs(2))
+        .without_errors()
+        .assert();
+    assert!(trace
+        .expect_span("dfu_update")
+        .completed_within(Duration::from_secs(1))
+        .check()
+        .is_err());
+}
+
+#[test]
+fn test_errors_in_nested_spans_fail_the_span() {
+    let decoder = decoder();
+    let trace = HilRun::new()
+        .until_event("dfu done")
+        .run(&decoder, dfu(true))
+        .unwrap();
+
+    assert_eq!(trace.errors().count(), 1);
+    let failed = trace
+        .expect_span("dfu_update")
+        .without_errors()
+        .check()
+        .unwrap_err();
+    assert_eq!(
+        failed.to_string(),
+        "expected span `dfu_update` without errors, found its one run logged error `flash write failed`"
+    );
+    assert!(trace.expect_span("reboot").check().is_err());
+}
+
+#[test]
+fn test_run_stops_after_its_duration() {
+    let decoder = decoder();
+    let trace = HilRun::new()
+        .with_duration(Duration::from_millis(100))
+        .until_event("never")
+        .run(&decoder, dfu(false))
+        .unwrap();
+
+    assert_eq!(trace.stop_reason(), StopReason::Duration);
+    assert_eq!(trace.records().len(), 6);
+    assert!(trace.elapsed() >= Duration::from_millis(100));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_failed_flashing_fails_the_run() {
+    let mut flash = std::process::Command::new("sh");
+    flash.args(["-c", "exit 1"]);
+    let error = HilRun::new()
+        .with_flash_command(flash)
+        .run(&decoder(), dfu(false))
+        .unwrap_err();
+    assert!(error.to_string().contains("exit status: 1"), "{}", error);
+}
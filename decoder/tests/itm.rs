@@ -0,0 +1,100 @@
+mod common;
+
+use std::io::Cursor;
+
+use common::FrameBytes;
+use tracing_defmt_decoder::export::Exporter;
+use tracing_defmt_decoder::source::{Itm, ItmStats};
+use tracing_defmt_decoder::{TraceDecoder, TraceRecord};
+
+/// Collects exported messages with their context.
+struct Messages<'a>(&'a mut Vec<(u32, String)>);
+
+impl Exporter for Messages<'_> {
+    fn export(&mut self, record: &TraceRecord) -> std::io::Result<()> {
+        self.0.push((record.context, record.message.clone()));
+        Ok(())
+    }
+}
+
+fn run(itm: &mut Itm) -> Vec<(u32, String)> {
+    let table = common::table(&[("Info", "reading {=u32}")], None);
+    let decoder = TraceDecoder::builder()
+        .build_from_table(table, common::locations(1))
+        .unwrap();
+    let mut messages = Vec::new();
+    let mut stream = decoder.new_stream();
+    stream.add_exporter(Messages(&mut messages));
+    itm.run(&mut stream).unwrap();
+    drop(stream);
+    messages
+}
+
+fn reading(value: u32) -> Vec<u8> {
+    FrameBytes::new(0).u32(value).bytes()
+}
+
+/// Stimulus port packets writing `data` to `port`, as words and then single bytes.
+fn stimulus(port: u8, data: &[u8]) -> Vec<u8> {
+    let words = data.chunks_exact(4);
+    let bytes = words
+        .remainder()
+        .iter()
+        .flat_map(|byte| [port << 3 | 1, *byte]);
+    let mut packets: Vec<u8> = words
+        .flat_map(|word| [&[port << 3 | 3][..], word].concat())
+        .collect();
+    packets.extend(bytes);
+    packets
+}
+
+#[test]
+fn test_stimulus_ports_are_decoded() {
+    let first = reading(1);
+    let swo = [
+        // Synchronization.
+        &[0, 0, 0, 0, 0, 0x80][..],
+        &stimulus(0, &first[..3]),
+        // A local timestamp with two continuation bytes, and a DWT data trace packet.
+        &[0xc0, 0x85, 0x03],
+        &[0x46, 0x12, 0x34],
+        // Another port, between the bytes of a frame.
+        &stimulus(1, &reading(2)),
+        &stimulus(0, &first[3..]),
+        &stimulus(5, &reading(3)),
+    ]
+    .concat();
+
+    let mut itm = Itm::new(Cursor::new(swo.clone()));
+    assert_eq!(run(&mut itm), [(0, "reading 1".to_string())]);
+    assert_eq!(itm.stats().bytes, first.len() as u64);
+
+    let mut itm = Itm::new(Cursor::new(swo)).with_stimulus_ports([0, 1]);
+    assert_eq!(
+        run(&mut itm),
+        [(1, "reading 2".to_string()), (0, "reading 1".to_string())]
+    );
+    // The DWT packet, and a word and two bytes on port 5.
+    assert_eq!(itm.stats().ignored, 4);
+}
+
+#[test]
+fn test_overflow_resets_the_decoder() {
+    let swo = [
+        stimulus(0, &reading(1)[..4]),
+        vec![0x70],
+        stimulus(0, &reading(2)),
+    ]
+    .concat();
+
+    let mut itm = Itm::new(Cursor::new(swo));
+    assert_eq!(run(&mut itm), [(0, "reading 2".to_string())]);
+    assert_eq!(
+        itm.stats(),
+        ItmStats {
+            bytes: 4 + reading(2).len() as u64,
+            ignored: 0,
+            overflows: 1,
+        }
+    );
+}
@@ -0,0 +1,78 @@
+mod common;
+
+use common::FrameBytes;
+use serde_json::{json, Value};
+use tracing_defmt_decoder::export::{Exporter, JsonLinesWriter};
+use tracing_defmt_decoder::TraceDecoder;
+
+#[test]
+fn test_records_follow_the_documented_schema() {
+    let table = common::table(
+        &[
+            ("Info", "span_enter: {=str}(x={=u8})"),
+            ("Info", "span_exit: {=str}"),
+            ("Warn", "late, retry={=u8}"),
+        ],
+        Some("{=u64:us}"),
+    );
+    // The event's statement has no location.
+    let decoder = TraceDecoder::builder()
+        .build_from_table(table, common::locations(2))
+        .unwrap();
+    let mut data = FrameBytes::new(0).u64(1_000).str("poll").u8(10).bytes();
+    data.extend(FrameBytes::new(2).u64(1_500).u8(3).bytes());
+    data.extend(FrameBytes::new(1).u64(2_000).str("poll").bytes());
+
+    let mut writer = JsonLinesWriter::new(Vec::new());
+    decoder
+        .new_stream()
+        .process_into(&data, |record| writer.export(&record).unwrap())
+        .unwrap();
+    let output = String::from_utf8(writer.finish().unwrap()).unwrap();
+    let lines: Vec<Value> = output
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+
+    assert_eq!(
+        lines,
+        [
+            json!({
+                "kind": "span_enter",
+                "timestamp": 1_000,
+                "level": "INFO",
+                "message": "poll",
+                "fields": { "x": "10" },
+                "span_id": 1,
+                "parent_id": null,
+                "context": 0,
+                "file": "src/main.rs",
+                "line": 10,
+                "module": "app",
+            }),
+            json!({
+                "kind": "event",
+                "timestamp": 1_500,
+                "level": "WARN",
+                "message": "late",
+                "fields": { "retry": "3" },
+                "span_id": 1,
+                "parent_id": null,
+                "context": 0,
+            }),
+            json!({
+                "kind": "span_exit",
+                "timestamp": 2_000,
+                "level": "INFO",
+                "message": "poll",
+                "fields": {},
+                "span_id": 1,
+                "parent_id": null,
+                "context": 0,
+                "file": "src/main.rs",
+                "line": 20,
+                "module": "app",
+            }),
+        ]
+    );
+}
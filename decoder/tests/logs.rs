@@ -0,0 +1,109 @@
+#![cfg(feature = "logs")]
+
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use common::FrameBytes;
+use opentelemetry::logs::{AnyValue, LoggerProvider as _, Severity};
+use opentelemetry::trace::{TraceResult, TracerProvider as _};
+use opentelemetry::{Context, InstrumentationScope, Key};
+use opentelemetry_sdk::export::trace::SpanData;
+use opentelemetry_sdk::logs::{LogProcessor, LogRecord, LogResult, LoggerProvider};
+use opentelemetry_sdk::trace::{Span, SpanProcessor, TracerProvider};
+use tracing::Dispatch;
+use tracing_defmt_decoder::export::DeviceLogs;
+use tracing_defmt_decoder::TraceDecoder;
+use tracing_subscriber::layer::SubscriberExt;
+
+/// Collects the log records emitted through the OpenTelemetry SDK.
+#[derive(Clone, Debug, Default)]
+struct Logs(Arc<Mutex<Vec<LogRecord>>>);
+
+impl LogProcessor for Logs {
+    fn emit(&self, record: &mut LogRecord, _scope: &InstrumentationScope) {
+        self.0.lock().unwrap().push(record.clone());
+    }
+
+    fn force_flush(&self) -> LogResult<()> {
+        Ok(())
+    }
+
+    fn shutdown(&self) -> LogResult<()> {
+        Ok(())
+    }
+}
+
+/// Collects the spans ended by the OpenTelemetry SDK.
+#[derive(Clone, Debug, Default)]
+struct Spans(Arc<Mutex<Vec<SpanData>>>);
+
+impl SpanProcessor for Spans {
+    fn on_start(&self, _span: &mut Span, _cx: &Context) {}
+
+    fn on_end(&self, span: SpanData) {
+        self.0.lock().unwrap().push(span);
+    }
+
+    fn force_flush(&self) -> TraceResult<()> {
+        Ok(())
+    }
+
+    fn shutdown(&self) -> TraceResult<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_events_are_emitted_as_correlated_log_records() {
+    let table = common::table(
+        &[
+            ("Info", "span_enter: poll()"),
+            ("Warn", "retry {=u8}, attempt={=u8}"),
+            ("Info", "span_exit: {=str}"),
+            ("Error", "out of memory"),
+        ],
+        None,
+    );
+    let spans = Spans::default();
+    let tracer_provider = TracerProvider::builder()
+        .with_span_processor(spans.clone())
+        .build();
+    let subscriber = tracing_subscriber::registry()
+        .with(tracing_opentelemetry::layer().with_tracer(tracer_provider.tracer("test")));
+    let decoder = TraceDecoder::builder()
+        .with_dispatch(Dispatch::new(subscriber))
+        .with_field_extraction(true)
+        .build_from_table(table, common::locations(4))
+        .unwrap();
+
+    let logs = Logs::default();
+    let logger_provider = LoggerProvider::builder()
+        .with_log_processor(logs.clone())
+        .build();
+    let mut data = FrameBytes::new(0).bytes();
+    data.extend(FrameBytes::new(1).u8(1).u8(2).bytes());
+    data.extend(FrameBytes::new(2).str("poll").bytes());
+    data.extend(FrameBytes::new(3).bytes());
+    let mut stream = decoder.new_stream();
+    stream.add_exporter(DeviceLogs::new(logger_provider.logger("test")));
+    stream.process(&data).unwrap();
+
+    let span = spans.0.lock().unwrap()[0].span_context.clone();
+    let logs = logs.0.lock().unwrap();
+    assert_eq!(logs.len(), 2);
+
+    assert_eq!(logs[0].body, Some(AnyValue::from("retry 1".to_string())));
+    assert_eq!(logs[0].severity_number, Some(Severity::Warn));
+    assert_eq!(logs[0].severity_text, Some("WARN"));
+    let trace_context = logs[0].trace_context.as_ref().unwrap();
+    assert_eq!(trace_context.trace_id, span.trace_id());
+    assert_eq!(trace_context.span_id, span.span_id());
+    assert!(logs[0]
+        .attributes_iter()
+        .any(|(key, value)| *key == Key::from("attempt") && *value == AnyValue::Int(2)));
+
+    // Outside of any span.
+    assert_eq!(logs[1].severity_number, Some(Severity::Error));
+    assert!(logs[1].trace_context.is_none());
+}
@@ -0,0 +1,87 @@
+#![cfg(feature = "loki")]
+
+mod common;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::mpsc;
+use std::thread;
+
+use common::FrameBytes;
+use serde_json::{json, Value};
+use tracing_defmt_decoder::TraceDecoder;
+
+/// Accepts push requests on a local port, answering 204, and returns their bodies.
+fn fake_loki() -> (String, mpsc::Receiver<Value>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = BufReader::new(stream.unwrap());
+            loop {
+                let mut length = 0;
+                let mut line = String::new();
+                while stream.read_line(&mut line).unwrap() > 2 {
+                    if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                        length = value.trim().parse().unwrap();
+                    }
+                    line.clear();
+                }
+                if line.is_empty() {
+                    break;
+                }
+                let mut body = vec![0; length];
+                stream.read_exact(&mut body).unwrap();
+                sender.send(serde_json::from_slice(&body).unwrap()).unwrap();
+                stream
+                    .get_mut()
+                    .write_all(b"HTTP/1.1 204 No Content\r\ncontent-length: 0\r\n\r\n")
+                    .unwrap();
+            }
+        }
+    });
+    (url, receiver)
+}
+
+#[test]
+fn test_events_are_pushed_with_labels() {
+    let (url, requests) = fake_loki();
+    let table = common::table(
+        &[
+            ("Info", "span_enter: poll()"),
+            ("Warn", "timeout, retries={=u8}"),
+            ("Info", "span_exit: {=str}"),
+        ],
+        None,
+    );
+    let decoder = TraceDecoder::builder()
+        .with_loki_endpoint(url)
+        .with_device_id("board-7")
+        .build_from_table(table, common::locations(3))
+        .unwrap();
+
+    let mut data = FrameBytes::new(0).bytes();
+    data.extend(FrameBytes::new(1).u8(3).bytes());
+    data.extend(FrameBytes::new(2).str("poll").bytes());
+    let mut stream = decoder.new_stream();
+    stream.process(&data).unwrap();
+    stream.flush().unwrap();
+
+    let request = requests.recv().unwrap();
+    let streams = request["streams"].as_array().unwrap();
+    assert_eq!(streams.len(), 1);
+    assert_eq!(
+        streams[0]["stream"],
+        json!({
+            "service_name": "tracing-defmt-decoder",
+            "device_id": "board-7",
+            "level": "warn",
+            "module": "app",
+        })
+    );
+    let values = streams[0]["values"].as_array().unwrap();
+    assert_eq!(values.len(), 1);
+    assert!(values[0][0].as_str().unwrap().parse::<u128>().unwrap() > 0);
+    assert_eq!(values[0][1], "timeout retries=3");
+}
@@ -0,0 +1,219 @@
+#![cfg(feature = "metrics")]
+
+mod common;
+
+use std::sync::{Arc, Weak};
+
+use common::FrameBytes;
+use opentelemetry::metrics::MeterProvider as _;
+use opentelemetry::KeyValue;
+use opentelemetry_sdk::metrics::data::{Gauge, Histogram, ResourceMetrics, Sum};
+use opentelemetry_sdk::metrics::reader::MetricReader;
+use opentelemetry_sdk::metrics::{
+    InstrumentKind, ManualReader, MetricResult, Pipeline, SdkMeterProvider, Temporality,
+};
+use opentelemetry_sdk::Resource;
+use tracing_defmt_decoder::export::{DeviceMetrics, SpanMetrics};
+use tracing_defmt_decoder::TraceDecoder;
+
+/// Lets the test collect from a reader owned by the meter provider.
+#[derive(Clone, Debug)]
+struct SharedReader(Arc<ManualReader>);
+
+impl MetricReader for SharedReader {
+    fn register_pipeline(&self, pipeline: Weak<Pipeline>) {
+        self.0.register_pipeline(pipeline)
+    }
+
+    fn collect(&self, rm: &mut ResourceMetrics) -> MetricResult<()> {
+        self.0.collect(rm)
+    }
+
+    fn force_flush(&self) -> MetricResult<()> {
+        self.0.force_flush()
+    }
+
+    fn shutdown(&self) -> MetricResult<()> {
+        self.0.shutdown()
+    }
+
+    fn temporality(&self, kind: InstrumentKind) -> Temporality {
+        self.0.temporality(kind)
+    }
+}
+
+/// Returns a meter provider and a reader to collect what was recorded through it.
+fn test_provider() -> (SdkMeterProvider, SharedReader) {
+    let reader = SharedReader(Arc::new(ManualReader::default()));
+    let provider = SdkMeterProvider::builder()
+        .with_reader(reader.clone())
+        .build();
+    (provider, reader)
+}
+
+fn collect(reader: &SharedReader) -> ResourceMetrics {
+    let mut metrics = ResourceMetrics {
+        resource: Resource::empty(),
+        scope_metrics: Vec::new(),
+    };
+    reader.collect(&mut metrics).unwrap();
+    metrics
+}
+
+#[test]
+fn test_span_durations_are_aggregated_by_name() {
+    let table = common::table(
+        &[
+            ("Info", "span_enter: {=str}()"),
+            ("Info", "span_exit: {=str}"),
+        ],
+        Some("{=u64:us}"),
+    );
+    let decoder = TraceDecoder::builder()
+        .build_from_table(table, common::locations(2))
+        .unwrap();
+
+    let (provider, reader) = test_provider();
+    let mut stream = decoder.new_stream();
+    stream.add_exporter(SpanMetrics::new(&provider.meter("test")));
+
+    let mut data = Vec::new();
+    for (start, end) in [(1_000, 1_250), (2_000, 2_750)] {
+        data.extend(FrameBytes::new(0).u64(start).str("poll").bytes());
+        data.extend(FrameBytes::new(1).u64(end).str("poll").bytes());
+    }
+    data.extend(FrameBytes::new(0).u64(3_000).str("hang").bytes());
+    stream.process(&data).unwrap();
+
+    let metrics = collect(&reader);
+    let metrics = &metrics.scope_metrics[0].metrics;
+    let find = |name: &str| metrics.iter().find(|m| m.name == name).unwrap();
+
+    let duration = find("device.span.duration")
+        .data
+        .as_any()
+        .downcast_ref::<Histogram<f64>>()
+        .unwrap();
+    assert_eq!(duration.data_points.len(), 1);
+    let point = &duration.data_points[0];
+    assert_eq!(point.attributes, [KeyValue::new("span.name", "poll")]);
+    assert_eq!(point.count, 2);
+    assert!((point.sum - 0.001).abs() < 1e-9);
+
+    let calls = find("device.span.calls")
+        .data
+        .as_any()
+        .downcast_ref::<Sum<u64>>()
+        .unwrap();
+    let mut counts: Vec<(String, u64)> = calls
+        .data_points
+        .iter()
+        .map(|point| (point.attributes[0].value.to_string(), point.value))
+        .collect();
+    counts.sort();
+    assert_eq!(counts, [("hang".to_string(), 1), ("poll".to_string(), 2)]);
+}
+
+#[test]
+fn test_device_metric_frames_become_instruments() {
+    let table = common::table(
+        &[
+            ("Info", "counter: rx_packets={=u32}, iface={=str}"),
+            ("Info", "gauge: battery_mv={=u32}"),
+        ],
+        None,
+    );
+    let decoder = TraceDecoder::builder()
+        .build_from_table(table, common::locations(2))
+        .unwrap();
+
+    let (provider, reader) = test_provider();
+    let mut stream = decoder.new_stream();
+    stream.add_exporter(DeviceMetrics::new(&provider.meter("test")));
+
+    let mut data = FrameBytes::new(0).u32(3).str("eth0").bytes();
+    data.extend(FrameBytes::new(0).u32(4).str("eth0").bytes());
+    data.extend(FrameBytes::new(1).u32(3_300).bytes());
+    data.extend(FrameBytes::new(1).u32(3_250).bytes());
+    stream.process(&data).unwrap();
+
+    let metrics = collect(&reader);
+    let metrics = &metrics.scope_metrics[0].metrics;
+    let find = |name: &str| metrics.iter().find(|m| m.name == name).unwrap();
+
+    let rx = find("rx_packets")
+        .data
+        .as_any()
+        .downcast_ref::<Sum<f64>>()
+        .unwrap();
+    assert_eq!(
+        rx.data_points[0].attributes,
+        [KeyValue::new("iface", "eth0")]
+    );
+    assert_eq!(rx.data_points[0].value, 7.0);
+
+    let battery = find("battery_mv")
+        .data
+        .as_any()
+        .downcast_ref::<Gauge<f64>>()
+        .unwrap();
+    assert_eq!(battery.data_points[0].value, 3_250.0);
+}
+
+#[test]
+fn test_memory_gauges_carry_innermost_span() {
+    let table = common::table(
+        &[
+            ("Info", "span_enter: {=str}()"),
+            ("Info", "span_exit: {=str}"),
+            ("Info", "gauge: memory.heap.free={=u32}"),
+            ("Info", "gauge: battery_mv={=u32}"),
+        ],
+        None,
+    );
+    let decoder = TraceDecoder::builder()
+        .build_from_table(table, common::locations(4))
+        .unwrap();
+
+    let (provider, reader) = test_provider();
+    let mut stream = decoder.new_stream();
+    stream.add_exporter(DeviceMetrics::new(&provider.meter("test")));
+
+    let mut data = FrameBytes::new(0).str("main_loop").bytes();
+    data.extend(FrameBytes::new(0).str("parse").bytes());
+    data.extend(FrameBytes::new(2).u32(1_024).bytes());
+    data.extend(FrameBytes::new(3).u32(3_300).bytes());
+    data.extend(FrameBytes::new(1).str("parse").bytes());
+    data.extend(FrameBytes::new(2).u32(4_096).bytes());
+    stream.process(&data).unwrap();
+
+    let metrics = collect(&reader);
+    let metrics = &metrics.scope_metrics[0].metrics;
+    let find = |name: &str| metrics.iter().find(|m| m.name == name).unwrap();
+
+    let heap = find("memory.heap.free")
+        .data
+        .as_any()
+        .downcast_ref::<Gauge<f64>>()
+        .unwrap();
+    let mut points: Vec<(String, f64)> = heap
+        .data_points
+        .iter()
+        .map(|point| (point.attributes[0].value.to_string(), point.value))
+        .collect();
+    points.sort_by(|a, b| a.0.cmp(&b.0));
+    assert_eq!(
+        points,
+        [
+            ("main_loop".to_string(), 4_096.0),
+            ("parse".to_string(), 1_024.0)
+        ]
+    );
+
+    let battery = find("battery_mv")
+        .data
+        .as_any()
+        .downcast_ref::<Gauge<f64>>()
+        .unwrap();
+    assert!(battery.data_points[0].attributes.is_empty());
+}
@@ -0,0 +1,145 @@
+#![cfg(feature = "mqtt")]
+
+mod common;
+
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use common::FrameBytes;
+use tracing_defmt_decoder::export::Exporter;
+use tracing_defmt_decoder::source::{MqttFleet, MqttPayload};
+use tracing_defmt_decoder::{TraceDecoder, TraceRecord, TraceStream};
+
+type Messages = Arc<Mutex<Vec<(String, String)>>>;
+
+/// Collects exported messages with the id of their device.
+struct DeviceMessages(String, Messages);
+
+impl Exporter for DeviceMessages {
+    fn export(&mut self, record: &TraceRecord) -> io::Result<()> {
+        self.1
+            .lock()
+            .unwrap()
+            .push((self.0.clone(), record.message.clone()));
+        Ok(())
+    }
+}
+
+fn base64(data: &[u8]) -> Vec<u8> {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = Vec::new();
+    for chunk in data.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, byte)| {
+            bits | u32::from(*byte) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(bits >> (18 - 6 * i) & 0x3f) as usize]);
+            } else {
+                out.push(b'=');
+            }
+        }
+    }
+    out
+}
+
+/// Reads one MQTT packet, returning its type and body.
+fn read_packet(socket: &mut TcpStream) -> (u8, Vec<u8>) {
+    let mut byte = [0];
+    socket.read_exact(&mut byte).unwrap();
+    let kind = byte[0] >> 4;
+    let mut length = 0;
+    for shift in (0..28).step_by(7) {
+        socket.read_exact(&mut byte).unwrap();
+        length |= usize::from(byte[0] & 0x7f) << shift;
+        if byte[0] & 0x80 == 0 {
+            break;
+        }
+    }
+    let mut body = vec![0; length];
+    socket.read_exact(&mut body).unwrap();
+    (kind, body)
+}
+
+fn publish(topic: &str, payload: &[u8]) -> Vec<u8> {
+    let length = 2 + topic.len() + payload.len();
+    assert!(length < 128);
+    [
+        &[0x30, length as u8][..],
+        &(topic.len() as u16).to_be_bytes(),
+        topic.as_bytes(),
+        payload,
+    ]
+    .concat()
+}
+
+/// A broker that takes one client and publishes `messages` once it has subscribed.
+fn broker(messages: Vec<(&'static str, Vec<u8>)>) -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    thread::spawn(move || {
+        let (mut socket, _) = listener.accept().unwrap();
+        assert_eq!(read_packet(&mut socket).0, 1, "CONNECT");
+        socket.write_all(&[0x20, 2, 0, 0]).unwrap();
+        let (kind, body) = read_packet(&mut socket);
+        assert_eq!(kind, 8, "SUBSCRIBE");
+        assert!(body.windows(15).any(|w| w == b"devices/+/defmt"));
+        socket.write_all(&[0x90, 3, body[0], body[1], 0]).unwrap();
+        for (topic, payload) in messages {
+            socket.write_all(&publish(topic, &payload)).unwrap();
+        }
+        // Until the client goes away.
+        let _ = io::copy(&mut socket, &mut io::sink());
+    });
+    port
+}
+
+#[test]
+fn test_devices_get_a_stream_each() {
+    let table = common::table(&[("Info", "reading {=u32}")], None);
+    let reading = |value| FrameBytes::new(0).u32(value).bytes();
+    let first = reading(1);
+    let port = broker(vec![
+        ("devices/a/defmt", base64(&first[..3])),
+        ("devices/b/defmt", base64(&reading(2))),
+        ("devices/unknown/defmt", base64(&reading(3))),
+        ("devices/a/defmt", base64(&first[3..])),
+    ]);
+
+    let messages = Messages::default();
+    let exported = messages.clone();
+    let setup = move |id: &str, stream: &mut TraceStream<'static>| {
+        stream.add_exporter(DeviceMessages(id.to_string(), exported.clone()))
+    };
+    let mut fleet = MqttFleet::new("127.0.0.1", port, "devices/+/defmt", move |id| {
+        if id == "unknown" {
+            return Ok(None);
+        }
+        let builder = TraceDecoder::builder().with_device_id(id);
+        Ok(Some(
+            builder.build_from_table(table.clone(), common::locations(1))?,
+        ))
+    })
+    .with_payload(MqttPayload::Base64)
+    .on_stream(setup);
+
+    let mut fed = 0;
+    while fed < first.len() + reading(2).len() {
+        fed += fleet.poll().unwrap();
+    }
+    let mut ids: Vec<_> = fleet.device_ids().collect();
+    ids.sort();
+    assert_eq!(ids, ["a", "b"]);
+    fleet.finish().unwrap();
+
+    let messages = messages.lock().unwrap();
+    assert_eq!(
+        *messages,
+        [
+            ("b".to_string(), "reading 2".to_string()),
+            ("a".to_string(), "reading 1".to_string()),
+        ]
+    );
+}
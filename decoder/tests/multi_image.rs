@@ -0,0 +1,41 @@
+mod common;
+
+use common::FrameBytes;
+use tracing_defmt_decoder::TraceDecoder;
+
+#[test]
+fn test_image_switch_changes_table_mid_chunk() {
+    let bootloader = common::table(
+        &[("Info", "booting"), ("Info", "image_switch: {=usize}")],
+        None,
+    );
+    let application = common::table(&[("Info", "app started v{=u8}")], None);
+    let decoder = TraceDecoder::builder()
+        .build_from_tables(vec![
+            (bootloader, common::locations(2)),
+            (application, common::locations(1)),
+        ])
+        .unwrap();
+
+    // Entry 0 of both images shares the same frame index.
+    let mut data = FrameBytes::new(0).bytes();
+    data.extend(FrameBytes::new(1).u32(1).bytes());
+    data.extend(FrameBytes::new(0).u8(2).bytes());
+
+    let mut messages = Vec::new();
+    let mut stream = decoder.new_stream();
+    stream
+        .process_into(&data, |record| messages.push(record.message))
+        .unwrap();
+    assert_eq!(messages, ["booting", "image_switch: 1", "app started v2"]);
+
+    // The device rebooted into the bootloader.
+    stream.select_image(0, 0);
+    messages.clear();
+    stream
+        .process_into(&FrameBytes::new(0).bytes(), |record| {
+            messages.push(record.message)
+        })
+        .unwrap();
+    assert_eq!(messages, ["booting"]);
+}
@@ -0,0 +1,152 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use common::FrameBytes;
+use defmt_decoder::Location;
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Dispatch, Event, Metadata, Subscriber};
+use tracing_defmt_decoder::{TargetMap, TraceDecoder};
+
+/// `(kind, target, name)` of a span or event.
+type Seen = (&'static str, String, String);
+
+/// Records every span and event it sees.
+#[derive(Clone, Default)]
+struct Recorder(Arc<Mutex<Vec<Seen>>>);
+
+impl Subscriber for Recorder {
+    fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, span: &Attributes<'_>) -> Id {
+        let meta = span.metadata();
+        let mut seen = self.0.lock().unwrap();
+        seen.push(("span", meta.target().into(), meta.name().into()));
+        Id::from_u64(seen.len() as u64)
+    }
+
+    fn record(&self, _span: &Id, _values: &Record<'_>) {}
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let meta = event.metadata();
+        let mut seen = self.0.lock().unwrap();
+        seen.push(("event", meta.target().into(), meta.name().into()));
+    }
+
+    fn enter(&self, _span: &Id) {}
+
+    fn exit(&self, _span: &Id) {}
+}
+
+#[test]
+fn test_custom_target_and_span_name() {
+    let table = common::table(
+        &[
+            ("Info", "span_enter: sample()"),
+            ("Info", "sampled"),
+            ("Info", "span_exit: {=str}"),
+        ],
+        None,
+    );
+    let recorder = Recorder::default();
+    let decoder = TraceDecoder::builder()
+        .with_target("sensor_node")
+        .with_span_name("firmware_span")
+        .with_dispatch(Dispatch::new(recorder.clone()))
+        .build_from_table(table, common::locations(3))
+        .unwrap();
+
+    let mut stream = decoder.new_stream();
+    let mut data = FrameBytes::new(0).bytes();
+    data.extend(FrameBytes::new(1).bytes());
+    data.extend(FrameBytes::new(2).str("sample").bytes());
+    stream.process(&data).unwrap();
+
+    let seen = recorder.0.lock().unwrap();
+    assert_eq!(
+        seen[..2],
+        [
+            ("span", "sensor_node".into(), "firmware_span".into()),
+            ("event", "sensor_node".into(), "device_event".into()),
+        ]
+    );
+}
+
+#[test]
+fn test_module_paths_map_to_targets() {
+    let table = common::table(
+        &[
+            ("Info", "span_enter: rx_isr()"),
+            ("Info", "rx"),
+            ("Info", "span_exit: {=str}"),
+            ("Info", "link up"),
+            ("Info", "idle"),
+        ],
+        None,
+    );
+    let mut locations = common::locations(5);
+    // Location indices start at 1.
+    for (index, module) in [
+        (1, "drivers::uart::isr"),
+        (2, "drivers::uart::isr"),
+        (4, "app::net::tcp"),
+    ] {
+        let location: &mut Location = locations.get_mut(&index).unwrap();
+        location.module = module.into();
+    }
+    let map: TargetMap = "app::net=network, drivers::*::isr=interrupts, app::*=app"
+        .parse()
+        .unwrap();
+    let recorder = Recorder::default();
+    let decoder = TraceDecoder::builder()
+        .with_target_map(map)
+        .with_dispatch(Dispatch::new(recorder.clone()))
+        .build_from_table(table, locations)
+        .unwrap();
+
+    let mut data = FrameBytes::new(0).bytes();
+    data.extend(FrameBytes::new(1).bytes());
+    data.extend(FrameBytes::new(2).str("rx_isr").bytes());
+    data.extend(FrameBytes::new(3).bytes());
+    data.extend(FrameBytes::new(4).bytes());
+    decoder.new_stream().process(&data).unwrap();
+
+    let targets: Vec<_> = recorder
+        .0
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(_, target, _)| target.clone())
+        .collect();
+    // `app` itself is not below `app::*`.
+    assert_eq!(
+        targets,
+        ["interrupts", "interrupts", "network", "device_log"]
+    );
+    assert!("app::net".parse::<TargetMap>().is_err());
+}
+
+#[test]
+fn test_resource_attributes() {
+    let decoder = TraceDecoder::builder()
+        .with_service_name("sensor-node")
+        .with_device_id("A1")
+        .with_firmware_version("0.1.0")
+        .with_device_id("B2")
+        .build_from_table(common::table(&[], None), common::locations(0))
+        .unwrap();
+
+    let attributes: Vec<(&str, &str)> = decoder.resource_attributes().collect();
+    assert_eq!(
+        attributes,
+        [
+            ("service.name", "sensor-node"),
+            ("service.version", "0.1.0"),
+            ("device.id", "B2"),
+        ]
+    );
+}
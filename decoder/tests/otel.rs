@@ -0,0 +1,696 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use common::FrameBytes;
+use opentelemetry::trace::{SpanId, Status, TraceId, TraceResult, TracerProvider as _};
+use opentelemetry::{Context, KeyValue};
+use opentelemetry_sdk::export::trace::SpanData;
+use opentelemetry_sdk::trace::{Span, SpanProcessor, TracerProvider};
+use tracing::Dispatch;
+use tracing_defmt_decoder::export::Exporter;
+use tracing_defmt_decoder::{ErrorDescription, RecordKind, SpanKind, TraceDecoder, TraceRecord};
+use tracing_subscriber::layer::SubscriberExt;
+
+/// Collects the spans ended by the OpenTelemetry SDK.
+#[derive(Clone, Debug, Default)]
+struct Collector(Arc<Mutex<Vec<SpanData>>>);
+
+impl SpanProcessor for Collector {
+    fn on_start(&self, _span: &mut Span, _cx: &Context) {}
+
+    fn on_end(&self, span: SpanData) {
+        self.0.lock().unwrap().push(span);
+    }
+
+    fn force_flush(&self) -> TraceResult<()> {
+        Ok(())
+    }
+
+    fn shutdown(&self) -> TraceResult<()> {
+        Ok(())
+    }
+}
+
+/// Collects exported records.
+#[derive(Clone, Default)]
+struct Records(Arc<Mutex<Vec<TraceRecord>>>);
+
+impl Exporter for Records {
+    fn export(&mut self, record: &TraceRecord) -> std::io::Result<()> {
+        self.0.lock().unwrap().push(record.clone());
+        Ok(())
+    }
+}
+
+/// Returns a dispatch recording into OpenTelemetry, and the spans it ends.
+fn otel_dispatch() -> (Dispatch, Collector) {
+    let collector = Collector::default();
+    let provider = TracerProvider::builder()
+        .with_span_processor(collector.clone())
+        .build();
+    let subscriber = tracing_subscriber::registry()
+        .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+    (Dispatch::new(subscriber), collector)
+}
+
+#[test]
+fn test_traceparent_frame_sets_remote_parent() {
+    let table = common::table(
+        &[
+            ("Info", "traceparent: {=str}"),
+            ("Info", "span_enter: handle_request()"),
+            ("Info", "span_exit: {=str}"),
+        ],
+        None,
+    );
+    let (dispatch, collector) = otel_dispatch();
+    let decoder = TraceDecoder::builder()
+        .with_dispatch(dispatch)
+        .build_from_table(table, common::locations(3))
+        .unwrap();
+
+    let mut data = FrameBytes::new(0)
+        .str("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01")
+        .bytes();
+    data.extend(FrameBytes::new(1).bytes());
+    data.extend(FrameBytes::new(2).str("handle_request").bytes());
+    data.extend(FrameBytes::new(0).str("none").bytes());
+    data.extend(FrameBytes::new(1).bytes());
+    data.extend(FrameBytes::new(2).str("handle_request").bytes());
+    decoder.new_stream().process(&data).unwrap();
+
+    let spans = collector.0.lock().unwrap();
+    assert_eq!(spans.len(), 2);
+    let remote_trace = TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap();
+    assert_eq!(spans[0].span_context.trace_id(), remote_trace);
+    assert_eq!(
+        spans[0].parent_span_id,
+        SpanId::from_hex("00f067aa0ba902b7").unwrap()
+    );
+    assert_ne!(spans[1].span_context.trace_id(), remote_trace);
+    assert_eq!(spans[1].parent_span_id, SpanId::INVALID);
+}
+
+#[test]
+fn test_follows_from_frame_links_spans() {
+    let table = common::table(
+        &[
+            ("Info", "span_enter: {=str}()"),
+            ("Info", "span_exit: {=str}"),
+            ("Info", "follows_from: {=str}"),
+        ],
+        None,
+    );
+    let (dispatch, collector) = otel_dispatch();
+    let decoder = TraceDecoder::builder()
+        .with_dispatch(dispatch)
+        .build_from_table(table, common::locations(3))
+        .unwrap();
+
+    let mut data = FrameBytes::new(0).str("uart_isr").bytes();
+    data.extend(FrameBytes::new(1).str("uart_isr").bytes());
+    data.extend(FrameBytes::new(0).str("process_rx").bytes());
+    data.extend(FrameBytes::new(2).str("uart_isr").bytes());
+    data.extend(FrameBytes::new(1).str("process_rx").bytes());
+    decoder.new_stream().process(&data).unwrap();
+
+    let spans = collector.0.lock().unwrap();
+    assert_eq!(spans.len(), 2);
+    let links: Vec<_> = spans[1]
+        .links
+        .iter()
+        .map(|link| link.span_context.clone())
+        .collect();
+    assert_eq!(links, [spans[0].span_context.clone()]);
+    assert!(spans[0].links.is_empty());
+}
+
+#[test]
+fn test_finish_closes_open_spans() {
+    let table = common::table(&[("Info", "span_enter: {=str}()")], None);
+    let (dispatch, collector) = otel_dispatch();
+    let decoder = TraceDecoder::builder()
+        .with_dispatch(dispatch)
+        .build_from_table(table, common::locations(1))
+        .unwrap();
+
+    let mut data = FrameBytes::new(0).str("main_loop").bytes();
+    data.extend(FrameBytes::new(0).str("poll").bytes());
+    let mut stream = decoder.new_stream();
+    stream.process(&data).unwrap();
+    assert!(collector.0.lock().unwrap().is_empty());
+
+    let stats = stream.finish().unwrap();
+    assert_eq!(stats.spans_truncated, 2);
+    let spans = collector.0.lock().unwrap();
+    let names: Vec<&str> = spans.iter().map(|span| span.name.as_ref()).collect();
+    assert_eq!(names, ["poll", "main_loop"]);
+    assert!(spans
+        .iter()
+        .all(|span| span.attributes.contains(&KeyValue::new("truncated", true))));
+    drop(spans);
+    decoder.shutdown().unwrap();
+}
+
+#[test]
+fn test_missing_locations_are_left_out() {
+    let table = common::table(
+        &[
+            ("Info", "span_enter: located()"),
+            ("Info", "span_exit: {=str}"),
+            ("Info", "span_enter: unlocated()"),
+        ],
+        None,
+    );
+    let (dispatch, collector) = otel_dispatch();
+    let decoder = TraceDecoder::builder()
+        .with_dispatch(dispatch)
+        .build_from_table(table, common::locations(2))
+        .unwrap();
+    assert_eq!(decoder.missing_locations(), 1);
+
+    let mut data = FrameBytes::new(0).bytes();
+    data.extend(FrameBytes::new(1).str("located").bytes());
+    data.extend(FrameBytes::new(2).bytes());
+    data.extend(FrameBytes::new(1).str("unlocated").bytes());
+    decoder.new_stream().process(&data).unwrap();
+
+    let spans = collector.0.lock().unwrap();
+    let has_attribute = |span: &SpanData, key: &str| {
+        span.attributes
+            .iter()
+            .any(|attribute| attribute.key.as_str() == key)
+    };
+    assert!(has_attribute(&spans[0], "code.filepath"));
+    assert!(!has_attribute(&spans[1], "code.filepath"));
+    assert!(!has_attribute(&spans[1], "code.lineno"));
+    assert!(has_attribute(&spans[1], "code.namespace"));
+}
+
+#[test]
+fn test_error_events_set_span_status() {
+    let table = common::table(
+        &[
+            ("Info", "span_enter: {=str}()"),
+            ("Error", "flash write failed"),
+            ("Warn", "retrying, error={=str}"),
+            ("Info", "span_exit: {=str}"),
+        ],
+        None,
+    );
+    let (dispatch, collector) = otel_dispatch();
+    let decoder = TraceDecoder::builder()
+        .with_dispatch(dispatch)
+        .build_from_table(table, common::locations(4))
+        .unwrap();
+
+    let mut data = FrameBytes::new(0).str("write").bytes();
+    data.extend(FrameBytes::new(1).bytes());
+    data.extend(FrameBytes::new(3).str("write").bytes());
+    data.extend(FrameBytes::new(0).str("retry").bytes());
+    data.extend(FrameBytes::new(2).str("Timeout").bytes());
+    data.extend(FrameBytes::new(3).str("retry").bytes());
+    data.extend(FrameBytes::new(0).str("idle").bytes());
+    data.extend(FrameBytes::new(3).str("idle").bytes());
+    decoder.new_stream().process(&data).unwrap();
+
+    let spans = collector.0.lock().unwrap();
+    assert_eq!(spans[0].status, Status::error("flash write failed"));
+    assert!(spans[0].events.iter().any(|event| event
+        .attributes
+        .contains(&KeyValue::new("exception.message", "flash write failed"))));
+    assert!(spans[0]
+        .attributes
+        .contains(&KeyValue::new("error.count", 1i64)));
+    assert_eq!(spans[1].status, Status::error("Timeout"));
+    assert_eq!(spans[2].status, Status::Unset);
+    assert!(spans[2]
+        .attributes
+        .iter()
+        .all(|attribute| attribute.key.as_str() != "error.count"));
+}
+
+#[test]
+fn test_first_error_describes_span_status() {
+    let table = common::table(
+        &[
+            ("Info", "span_enter: {=str}()"),
+            ("Error", "{=str}"),
+            ("Info", "span_exit: {=str}"),
+        ],
+        None,
+    );
+    let (dispatch, collector) = otel_dispatch();
+    let decoder = TraceDecoder::builder()
+        .with_dispatch(dispatch)
+        .with_error_description(ErrorDescription::First)
+        .build_from_table(table, common::locations(3))
+        .unwrap();
+
+    let mut data = FrameBytes::new(0).str("flash_write").bytes();
+    for error in ["erase failed", "verify failed", "write failed"] {
+        data.extend(FrameBytes::new(1).str(error).bytes());
+    }
+    // Spans still open at the end are closed with their count as well.
+    data.extend(FrameBytes::new(0).str("flash_read").bytes());
+    data.extend(FrameBytes::new(1).str("ecc error").bytes());
+    let mut stream = decoder.new_stream();
+    stream.process(&data).unwrap();
+    stream.finish().unwrap();
+
+    let spans = collector.0.lock().unwrap();
+    assert_eq!(spans.len(), 2);
+    let flash_write = spans
+        .iter()
+        .find(|span| span.name == "flash_write")
+        .unwrap();
+    assert_eq!(flash_write.status, Status::error("erase failed"));
+    assert!(flash_write
+        .attributes
+        .contains(&KeyValue::new("error.count", 3i64)));
+    let flash_read = spans.iter().find(|span| span.name == "flash_read").unwrap();
+    assert!(flash_read
+        .attributes
+        .contains(&KeyValue::new("error.count", 1i64)));
+}
+
+#[test]
+fn test_cancelled_spans_are_marked() {
+    let table = common::table(
+        &[
+            ("Info", "span_enter: {=str}()"),
+            ("Info", "span_exit: {=str}"),
+            ("Info", "span_cancel: {=str}"),
+            ("Warn", "span_cancel: {=str}, reason={=str}"),
+        ],
+        None,
+    );
+    let (dispatch, collector) = otel_dispatch();
+    let decoder = TraceDecoder::builder()
+        .with_dispatch(dispatch)
+        .build_from_table(table, common::locations(4))
+        .unwrap();
+
+    let data = [
+        FrameBytes::new(0).str("connect").bytes(),
+        FrameBytes::new(0).str("handshake").bytes(),
+        FrameBytes::new(3).str("handshake").str("timeout").bytes(),
+        FrameBytes::new(2).str("connect").bytes(),
+        FrameBytes::new(0).str("send").bytes(),
+        FrameBytes::new(1).str("send").bytes(),
+    ]
+    .concat();
+    let records = Records::default();
+    let mut stream = decoder.new_stream();
+    stream.add_exporter(records.clone());
+    stream.process(&data).unwrap();
+    drop(stream);
+
+    let records = records.0.lock().unwrap();
+    assert_eq!(records[2].kind, RecordKind::SpanExit);
+    assert_eq!(records[2].message, "handshake");
+    assert_eq!(
+        records[2].fields[1],
+        ("reason".to_string(), "timeout".to_string())
+    );
+    let spans = collector.0.lock().unwrap();
+    let span = |name| spans.iter().find(|span| span.name == name).unwrap();
+    let cancelled = KeyValue::new("cancelled", true);
+    assert_eq!(
+        span("handshake").status,
+        Status::error("cancelled: timeout")
+    );
+    assert!(span("handshake").attributes.contains(&cancelled));
+    assert_eq!(span("connect").status, Status::error("cancelled"));
+    assert!(span("connect").attributes.contains(&cancelled));
+    assert_eq!(span("send").status, Status::Unset);
+    assert!(!span("send").attributes.contains(&cancelled));
+}
+
+#[test]
+fn test_event_fields_become_typed_attributes() {
+    let table = common::table(
+        &[
+            ("Info", "span_enter: {=str}()"),
+            (
+                "Info",
+                "link changed, port={=u8}, up={=str}, rtt={=str}, peer={=str}",
+            ),
+            ("Info", "span_exit: {=str}"),
+        ],
+        None,
+    );
+    let (dispatch, collector) = otel_dispatch();
+    let decoder = TraceDecoder::builder()
+        .with_dispatch(dispatch)
+        .build_from_table(table, common::locations(3))
+        .unwrap();
+
+    let mut data = FrameBytes::new(0).str("net").bytes();
+    data.extend(
+        FrameBytes::new(1)
+            .u8(3)
+            .str("true")
+            .str("1.5")
+            .str("gw")
+            .bytes(),
+    );
+    data.extend(FrameBytes::new(2).str("net").bytes());
+    decoder.new_stream().process(&data).unwrap();
+
+    let spans = collector.0.lock().unwrap();
+    let event = &spans[0].events[0];
+    assert_eq!(event.name, "link changed");
+    for attribute in [
+        KeyValue::new("port", 3),
+        KeyValue::new("up", true),
+        KeyValue::new("rtt", 1.5),
+        KeyValue::new("peer", "gw"),
+    ] {
+        assert!(event.attributes.contains(&attribute), "{:?}", attribute);
+    }
+}
+
+#[test]
+fn test_spans_carry_boot_session() {
+    let table = common::table(&[("Info", "boot"), ("Info", "span_enter: {=str}()")], None);
+    let (dispatch, collector) = otel_dispatch();
+    let decoder = TraceDecoder::builder()
+        .with_dispatch(dispatch)
+        .build_from_table(table, common::locations(2))
+        .unwrap();
+
+    let mut data = FrameBytes::new(0).bytes();
+    data.extend(FrameBytes::new(1).str("main_loop").bytes());
+    data.extend(FrameBytes::new(0).bytes());
+    data.extend(FrameBytes::new(1).str("main_loop").bytes());
+    let mut stream = decoder.new_stream();
+    stream.process(&data).unwrap();
+    stream.finish().unwrap();
+
+    let spans = collector.0.lock().unwrap();
+    let attribute = |span: &SpanData, key: &str| {
+        span.attributes
+            .iter()
+            .find(|attribute| attribute.key.as_str() == key)
+            .map(|attribute| attribute.value.clone())
+    };
+    assert_eq!(attribute(&spans[0], "boot.id"), Some(0.into()));
+    assert_eq!(attribute(&spans[1], "boot.id"), Some(1.into()));
+    assert_ne!(
+        attribute(&spans[0], "session.id"),
+        attribute(&spans[1], "session.id")
+    );
+    assert_ne!(
+        spans[0].span_context.trace_id(),
+        spans[1].span_context.trace_id()
+    );
+}
+
+#[test]
+fn test_boot_reset_reason() {
+    let table = common::table(
+        &[
+            ("Info", "boot: reset.reason={=str}"),
+            ("Info", "span_enter: {=str}()"),
+            ("Info", "boot"),
+        ],
+        None,
+    );
+    let (dispatch, collector) = otel_dispatch();
+    let decoder = TraceDecoder::builder()
+        .with_dispatch(dispatch)
+        .build_from_table(table, common::locations(3))
+        .unwrap();
+
+    // A reboot for a known reason, then one that gives none.
+    let mut data = FrameBytes::new(0).str("watchdog").bytes();
+    data.extend(FrameBytes::new(1).str("main_loop").bytes());
+    data.extend(FrameBytes::new(2).bytes());
+    data.extend(FrameBytes::new(1).str("main_loop").bytes());
+    let mut stream = decoder.new_stream();
+    stream.process(&data).unwrap();
+    stream.finish().unwrap();
+
+    let spans = collector.0.lock().unwrap();
+    let attribute = |span: &SpanData, key: &str| {
+        span.attributes
+            .iter()
+            .find(|attribute| attribute.key.as_str() == key)
+            .map(|attribute| attribute.value.clone())
+    };
+    assert_eq!(attribute(&spans[0], "boot.id"), Some(0.into()));
+    assert_eq!(
+        attribute(&spans[0], "reset.reason"),
+        Some("watchdog".into())
+    );
+    assert_eq!(attribute(&spans[1], "boot.id"), Some(1.into()));
+    assert_eq!(attribute(&spans[1], "reset.reason"), None);
+}
+
+#[test]
+fn test_spans_carry_memory_peaks() {
+    let table = common::table(
+        &[
+            ("Info", "span_enter: {=str}()"),
+            ("Info", "span_exit: {=str}"),
+            ("Info", "gauge: memory.heap.free={=u32}"),
+            ("Info", "gauge: memory.stack.high_water={=u32}"),
+        ],
+        None,
+    );
+    let (dispatch, collector) = otel_dispatch();
+    let decoder = TraceDecoder::builder()
+        .with_dispatch(dispatch)
+        .build_from_table(table, common::locations(4))
+        .unwrap();
+
+    let mut data = FrameBytes::new(0).str("main_loop").bytes();
+    data.extend(FrameBytes::new(2).u32(4_096).bytes());
+    data.extend(FrameBytes::new(0).str("parse").bytes());
+    data.extend(FrameBytes::new(2).u32(1_024).bytes());
+    data.extend(FrameBytes::new(3).u32(512).bytes());
+    data.extend(FrameBytes::new(2).u32(2_048).bytes());
+    data.extend(FrameBytes::new(1).str("parse").bytes());
+    data.extend(FrameBytes::new(3).u32(256).bytes());
+    data.extend(FrameBytes::new(1).str("main_loop").bytes());
+    let mut stream = decoder.new_stream();
+    stream.process(&data).unwrap();
+    stream.finish().unwrap();
+
+    let spans = collector.0.lock().unwrap();
+    let attribute = |name: &str, key: &str| {
+        let span = spans.iter().find(|span| span.name == name).unwrap();
+        span.attributes
+            .iter()
+            .find(|attribute| attribute.key.as_str() == key)
+            .map(|attribute| attribute.value.clone())
+    };
+    assert_eq!(
+        attribute("parse", "memory.heap.free.min"),
+        Some(1_024.into())
+    );
+    assert_eq!(
+        attribute("parse", "memory.stack.high_water"),
+        Some(512.into())
+    );
+    assert_eq!(
+        attribute("main_loop", "memory.heap.free.min"),
+        Some(1_024.into())
+    );
+    assert_eq!(
+        attribute("main_loop", "memory.stack.high_water"),
+        Some(512.into())
+    );
+    assert_eq!(attribute("main_loop", "memory.heap.used.max"), None);
+}
+
+#[test]
+fn test_span_record_frames_set_span_attributes() {
+    let table = common::table(
+        &[
+            ("Info", "span_enter: {=str}()"),
+            ("Info", "span_exit: {=str}"),
+            ("Info", "span_record: {=str}, {=str}={=u32}"),
+            ("Info", "span_record: {=str}, {=str}={=bool}"),
+            ("Info", "span_record: {=str}, {=str}={=str}"),
+        ],
+        None,
+    );
+    let (dispatch, collector) = otel_dispatch();
+    let decoder = TraceDecoder::builder()
+        .with_dispatch(dispatch)
+        .build_from_table(table, common::locations(5))
+        .unwrap();
+
+    let mut data = FrameBytes::new(0).str("send").bytes();
+    data.extend(FrameBytes::new(0).str("crc").bytes());
+    // Recorded on the enclosing span by name, not on the innermost one.
+    data.extend(FrameBytes::new(2).str("send").str("len").u32(42).bytes());
+    data.extend(FrameBytes::new(3).str("crc").str("ok").u8(1).bytes());
+    data.extend(FrameBytes::new(1).str("crc").bytes());
+    data.extend(
+        FrameBytes::new(4)
+            .str("send")
+            .str("state")
+            .str("idle")
+            .bytes(),
+    );
+    // No span of that name is open: the field is dropped.
+    data.extend(FrameBytes::new(2).str("crc").str("len").u32(1).bytes());
+    data.extend(FrameBytes::new(1).str("send").bytes());
+    decoder.new_stream().process(&data).unwrap();
+
+    let spans = collector.0.lock().unwrap();
+    let span = |name: &str| spans.iter().find(|span| span.name == name).unwrap();
+    let send = span("send");
+    assert!(send.attributes.contains(&KeyValue::new("len", 42i64)));
+    assert!(send.attributes.contains(&KeyValue::new("state", "idle")));
+    let crc = span("crc");
+    assert!(crc.attributes.contains(&KeyValue::new("ok", true)));
+    assert!(!crc.attributes.iter().any(|kv| kv.key.as_str() == "len"));
+    // The frames are fields, not events.
+    assert!(send.events.is_empty() && crc.events.is_empty());
+}
+
+#[test]
+fn test_exported_records_carry_trace_id() {
+    let table = common::table(
+        &[
+            ("Info", "span_enter: poll()"),
+            ("Warn", "timeout"),
+            ("Info", "span_exit: {=str}"),
+        ],
+        None,
+    );
+    let (dispatch, collector) = otel_dispatch();
+    let decoder = TraceDecoder::builder()
+        .with_dispatch(dispatch)
+        .build_from_table(table, common::locations(3))
+        .unwrap();
+
+    let mut data = FrameBytes::new(0).bytes();
+    data.extend(FrameBytes::new(1).bytes());
+    data.extend(FrameBytes::new(2).str("poll").bytes());
+    data.extend(FrameBytes::new(1).bytes());
+    let records = Records::default();
+    let mut stream = decoder.new_stream();
+    stream.add_exporter(records.clone());
+    stream.process(&data).unwrap();
+
+    let trace_id = collector.0.lock().unwrap()[0]
+        .span_context
+        .trace_id()
+        .to_string();
+    let trace_ids: Vec<Option<String>> = records
+        .0
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|record| record.trace_id.clone())
+        .collect();
+    let inside = Some(trace_id);
+    assert_eq!(trace_ids, [inside.clone(), inside.clone(), inside, None]);
+}
+
+#[test]
+fn test_span_kind_from_firmware_or_rule() {
+    let table = common::table(
+        &[
+            ("Info", "span_enter: radio_tx(otel.kind=client)"),
+            ("Info", "span_enter: {=str}()"),
+            ("Info", "span_exit: {=str}"),
+        ],
+        None,
+    );
+    let (dispatch, collector) = otel_dispatch();
+    let decoder = TraceDecoder::builder()
+        .with_dispatch(dispatch)
+        .with_span_kind("rx_isr", SpanKind::Consumer)
+        .build_from_table(table, common::locations(3))
+        .unwrap();
+
+    let mut data = FrameBytes::new(0).bytes();
+    data.extend(FrameBytes::new(2).str("radio_tx").bytes());
+    data.extend(FrameBytes::new(1).str("rx_isr").bytes());
+    data.extend(FrameBytes::new(2).str("rx_isr").bytes());
+    data.extend(FrameBytes::new(1).str("poll").bytes());
+    data.extend(FrameBytes::new(2).str("poll").bytes());
+    decoder.new_stream().process(&data).unwrap();
+
+    let spans = collector.0.lock().unwrap();
+    let kinds: Vec<_> = spans
+        .iter()
+        .map(|span| (span.name.as_ref(), span.span_kind.clone()))
+        .collect();
+    assert_eq!(
+        kinds,
+        [
+            ("radio_tx", SpanKind::Client),
+            ("rx_isr", SpanKind::Consumer),
+            ("poll", SpanKind::Internal),
+        ]
+    );
+    assert!(spans[0]
+        .attributes
+        .iter()
+        .all(|attribute| attribute.key.as_str() != "otel.kind"));
+}
+
+#[test]
+fn test_span_events_are_capped_per_level() {
+    let table = common::table(
+        &[
+            ("Info", "span_enter: {=str}()"),
+            ("Debug", "sample, n={=u32}"),
+            ("Error", "overrun"),
+            ("Info", "span_exit: {=str}"),
+        ],
+        None,
+    );
+    let (dispatch, collector) = otel_dispatch();
+    let decoder = TraceDecoder::builder()
+        .with_dispatch(dispatch)
+        .with_max_span_events(10)
+        .with_max_span_events_at(tracing::Level::DEBUG, 2)
+        .build_from_table(table, common::locations(4))
+        .unwrap();
+
+    let mut data = FrameBytes::new(0).str("adc_loop").bytes();
+    for n in 0..5u32 {
+        data.extend(FrameBytes::new(1).u32(n).bytes());
+        data.extend(FrameBytes::new(2).bytes());
+    }
+    data.extend(FrameBytes::new(3).str("adc_loop").bytes());
+    let mut stream = decoder.new_stream();
+    stream.process(&data).unwrap();
+    assert_eq!(stream.stats().events_dropped, 3);
+
+    let spans = collector.0.lock().unwrap();
+    let events: Vec<_> = spans[0]
+        .events
+        .iter()
+        .map(|event| event.name.as_ref())
+        .collect();
+    assert_eq!(
+        events,
+        [
+            "sample",
+            "overrun",
+            "sample",
+            "overrun",
+            "overrun",
+            "overrun",
+            "overrun",
+            "dropped 3 further events"
+        ]
+    );
+    assert!(spans[0]
+        .events
+        .events
+        .last()
+        .unwrap()
+        .attributes
+        .contains(&KeyValue::new("dropped_events", 3i64)));
+}
@@ -0,0 +1,217 @@
+#![cfg(feature = "otlp")]
+
+mod common;
+
+use std::net::SocketAddr;
+use std::sync::mpsc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use common::FrameBytes;
+use tracing_defmt_decoder::{Error, TraceDecoder, TraceDecoderBuilder};
+
+/// An export call received by the collector: its path, headers and gRPC body.
+type Request = (String, http::HeaderMap, Vec<u8>);
+
+/// Starts a gRPC server on loopback that answers every request with an empty
+/// `Export*ServiceResponse`, and returns its address and the requests it got.
+fn collector() -> (SocketAddr, mpsc::Receiver<Request>) {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.set_nonblocking(true).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_io()
+            .build()
+            .unwrap();
+        runtime.block_on(async move {
+            let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+            let (socket, _) = listener.accept().await.unwrap();
+            let mut connection = h2::server::handshake(socket).await.unwrap();
+            while let Some(Ok((request, mut respond))) = connection.accept().await {
+                let tx = tx.clone();
+                // The body only arrives while the connection is polled, so it is read on a
+                // task of its own.
+                tokio::spawn(async move {
+                    let (parts, mut body) = request.into_parts();
+                    let mut data = Vec::new();
+                    while let Some(Ok(chunk)) = body.data().await {
+                        let _ = body.flow_control().release_capacity(chunk.len());
+                        data.extend_from_slice(&chunk);
+                    }
+                    let _ = tx.send((parts.uri.path().to_string(), parts.headers, data));
+                    // The exporter may hang up as soon as it has the response, so errors
+                    // from here on are of no interest.
+                    let response = http::Response::builder()
+                        .header("content-type", "application/grpc")
+                        .body(())
+                        .unwrap();
+                    let Ok(mut stream) = respond.send_response(response, false) else {
+                        return;
+                    };
+                    // An empty message: not compressed, zero bytes long.
+                    let _ = stream.send_data(Bytes::from_static(&[0; 5]), false);
+                    let mut trailers = http::HeaderMap::new();
+                    trailers.insert("grpc-status", http::HeaderValue::from_static("0"));
+                    let _ = stream.send_trailers(trailers);
+                });
+            }
+        });
+    });
+    (addr, rx)
+}
+
+/// Whether `needle` occurs in `haystack`; protobuf keeps strings as they are.
+fn contains(haystack: &[u8], needle: &str) -> bool {
+    haystack
+        .windows(needle.len())
+        .any(|window| window == needle.as_bytes())
+}
+
+#[test]
+fn test_spans_reach_the_grpc_endpoint_with_headers_and_resource() {
+    let (addr, requests) = collector();
+    let table = common::table(
+        &[
+            ("Info", "span_enter: poll()"),
+            ("Info", "span_exit: {=str}"),
+        ],
+        None,
+    );
+    let decoder = TraceDecoder::builder()
+        .with_service_name("bench-rig")
+        .with_resource_attribute("deployment.environment", "lab")
+        .with_otlp_endpoint(format!("http://{}", addr))
+        .with_otlp_header("x-api-key", "secret")
+        .build_from_table(table, common::locations(2))
+        .unwrap();
+
+    let mut data = FrameBytes::new(0).bytes();
+    data.extend(FrameBytes::new(1).str("poll").bytes());
+    decoder.new_stream().process(&data).unwrap();
+    // Dropping the decoder flushes the batch.
+    drop(decoder);
+
+    let (path, headers, body) = requests.recv_timeout(Duration::from_secs(10)).unwrap();
+    assert_eq!(
+        path,
+        "/opentelemetry.proto.collector.trace.v1.TraceService/Export"
+    );
+    assert_eq!(headers["x-api-key"], "secret");
+    assert_eq!(headers["content-type"], "application/grpc");
+    for expected in [
+        "service.name",
+        "bench-rig",
+        "deployment.environment",
+        "lab",
+        "poll",
+    ] {
+        assert!(contains(&body, expected), "{} not exported", expected);
+    }
+}
+
+#[test]
+fn test_invalid_endpoint_is_an_error() {
+    let result = TraceDecoder::builder()
+        .with_otlp_endpoint("not a uri")
+        .build_for_text();
+    assert!(matches!(result, Err(Error::Exporter(_))));
+}
+
+#[test]
+fn test_invalid_header_is_an_error() {
+    let result = TraceDecoder::builder()
+        .with_otlp_endpoint("http://127.0.0.1:4317")
+        .with_otlp_header("x api key", "secret")
+        .build_for_text();
+    assert!(matches!(result, Err(Error::Exporter(_))));
+
+    let result = TraceDecoder::builder()
+        .with_otlp_endpoint("http://127.0.0.1:4317")
+        .with_otlp_header("x-api-key", "line\nbreak")
+        .build_for_text();
+    assert!(matches!(result, Err(Error::Exporter(_))));
+}
+
+/// Builds a decoder of spans named by their enter frame, see [`span`].
+fn span_decoder(builder: TraceDecoderBuilder) -> TraceDecoder {
+    let table = common::table(
+        &[
+            ("Info", "span_enter: {=str}()"),
+            ("Info", "span_exit: {=str}"),
+        ],
+        None,
+    );
+    builder
+        .build_from_table(table, common::locations(2))
+        .unwrap()
+}
+
+/// The frames of a span that starts and ends right away.
+fn span(name: &str) -> Vec<u8> {
+    let mut data = FrameBytes::new(0).str(name).bytes();
+    data.extend(FrameBytes::new(1).str(name).bytes());
+    data
+}
+
+/// How long to wait for an export that should not wait for the default scheduled delay
+/// of 5 s.
+const PROMPTLY: Duration = Duration::from_secs(2);
+
+#[test]
+fn test_max_export_batch_size_reaches_the_batch_processor() {
+    let (addr, requests) = collector();
+    let decoder = span_decoder(
+        TraceDecoder::builder()
+            .with_otlp_endpoint(format!("http://{}", addr))
+            .with_otlp_max_export_batch_size(1),
+    );
+
+    // A full batch is exported at once, so every span is a request of its own.
+    let mut stream = decoder.new_stream();
+    for name in ["first", "second", "third"] {
+        stream.process(&span(name)).unwrap();
+        let (_, _, body) = requests.recv_timeout(PROMPTLY).unwrap();
+        assert!(contains(&body, name), "{} not exported", name);
+    }
+}
+
+#[test]
+fn test_max_queue_size_reaches_the_batch_processor() {
+    let (addr, requests) = collector();
+    // The batch cannot be larger than the queue, so this also makes batches of one.
+    let decoder = span_decoder(
+        TraceDecoder::builder()
+            .with_otlp_endpoint(format!("http://{}", addr))
+            .with_otlp_max_queue_size(1),
+    );
+    // The provider hands the resource to the processor through the same queue, so the
+    // first span only fits once the processor has taken it.
+    std::thread::sleep(Duration::from_millis(100));
+
+    let mut stream = decoder.new_stream();
+    for name in ["first", "second", "third"] {
+        stream.process(&span(name)).unwrap();
+        let (_, _, body) = requests.recv_timeout(PROMPTLY).unwrap();
+        assert!(contains(&body, name), "{} not exported", name);
+    }
+}
+
+#[test]
+fn test_export_interval_reaches_the_batch_processor() {
+    let (addr, requests) = collector();
+    let decoder = span_decoder(
+        TraceDecoder::builder()
+            .with_otlp_endpoint(format!("http://{}", addr))
+            .with_otlp_export_interval(Duration::from_millis(50)),
+    );
+
+    // Both spans wait for the next scheduled export, which comes long before the
+    // decoder is dropped.
+    let mut stream = decoder.new_stream();
+    stream.process(&span("first")).unwrap();
+    stream.process(&span("second")).unwrap();
+    let (_, _, body) = requests.recv_timeout(PROMPTLY).unwrap();
+    assert!(contains(&body, "first") && contains(&body, "second"));
+}
@@ -0,0 +1,138 @@
+#![cfg(feature = "otlp-http")]
+
+mod common;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::sync::mpsc;
+use std::time::Duration;
+
+use common::FrameBytes;
+use tracing_defmt_decoder::{OtlpProtocol, TraceDecoder};
+
+/// An export call received by the collector: its request line, headers (names in lower
+/// case) and body.
+struct Request {
+    line: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Starts an HTTP/1.1 server on loopback that answers every request with `200 OK`, and
+/// returns its address and the requests it got.
+fn collector() -> (SocketAddr, mpsc::Receiver<Request>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut reader = BufReader::new(stream.unwrap());
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                    break;
+                }
+                let mut headers = Vec::new();
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    let Some((key, value)) = header.trim_end().split_once(':') else {
+                        break;
+                    };
+                    headers.push((key.to_ascii_lowercase(), value.trim().to_string()));
+                }
+                let length = headers
+                    .iter()
+                    .find(|(key, _)| key == "content-length")
+                    .map_or(0, |(_, value)| value.parse().unwrap());
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                reader
+                    .get_mut()
+                    .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                    .unwrap();
+                let request = Request {
+                    line: line.trim_end().to_string(),
+                    headers,
+                    body,
+                };
+                if tx.send(request).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+    (addr, rx)
+}
+
+/// Exports one span named `poll` with `protocol` and returns the request the collector got.
+fn export(protocol: OtlpProtocol) -> Request {
+    let (addr, requests) = collector();
+    let table = common::table(
+        &[
+            ("Info", "span_enter: poll()"),
+            ("Info", "span_exit: {=str}"),
+        ],
+        None,
+    );
+    let decoder = TraceDecoder::builder()
+        .with_service_name("bench-rig")
+        .with_otlp_protocol(protocol)
+        .with_otlp_endpoint(format!("http://{}/v1/traces", addr))
+        .with_otlp_header("x-api-key", "secret")
+        .with_otlp_header("x-tenant", "lab")
+        .build_from_table(table, common::locations(2))
+        .unwrap();
+
+    let mut data = FrameBytes::new(0).bytes();
+    data.extend(FrameBytes::new(1).str("poll").bytes());
+    decoder.new_stream().process(&data).unwrap();
+    // Dropping the decoder flushes the batch.
+    drop(decoder);
+
+    requests.recv_timeout(Duration::from_secs(10)).unwrap()
+}
+
+#[test]
+fn test_http_protobuf_posts_with_headers() {
+    let request = export(OtlpProtocol::HttpProtobuf);
+    assert_eq!(request.line, "POST /v1/traces HTTP/1.1");
+    assert_eq!(
+        request.header("content-type"),
+        Some("application/x-protobuf")
+    );
+    assert_eq!(request.header("x-api-key"), Some("secret"));
+    assert_eq!(request.header("x-tenant"), Some("lab"));
+    let body = String::from_utf8_lossy(&request.body);
+    assert!(body.contains("bench-rig"), "{:?}", body);
+    assert!(body.contains("poll"), "{:?}", body);
+}
+
+#[test]
+fn test_http_json_posts_with_headers() {
+    let request = export(OtlpProtocol::HttpJson);
+    assert_eq!(request.line, "POST /v1/traces HTTP/1.1");
+    assert_eq!(request.header("content-type"), Some("application/json"));
+    assert_eq!(request.header("x-api-key"), Some("secret"));
+    assert_eq!(request.header("x-tenant"), Some("lab"));
+    let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+    let resource = &body["resourceSpans"][0]["resource"]["attributes"];
+    assert!(
+        resource.as_array().unwrap().iter().any(|attribute| {
+            attribute["key"] == "service.name" && attribute["value"]["stringValue"] == "bench-rig"
+        }),
+        "{}",
+        resource
+    );
+    let span = &body["resourceSpans"][0]["scopeSpans"][0]["spans"][0];
+    assert_eq!(span["name"], "poll");
+}
@@ -0,0 +1,76 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use common::FrameBytes;
+use tracing_defmt_decoder::{TraceDecoder, TraceRecord, TraceStream};
+
+fn decoder() -> TraceDecoder {
+    let table = common::table(
+        &[
+            ("Info", "span_enter: poll(port={=u8})"),
+            ("Info", "span_exit: {=str}"),
+        ],
+        None,
+    );
+    TraceDecoder::builder()
+        .build_from_table(table, common::locations(2))
+        .unwrap()
+}
+
+#[test]
+fn test_owned_stream_is_send_and_sync() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<TraceDecoder>();
+    assert_send_sync::<TraceStream<'static>>();
+}
+
+#[test]
+fn test_shared_streams_decode_in_threads() {
+    let decoder = Arc::new(decoder());
+    let records: Arc<Mutex<Vec<TraceRecord>>> = Default::default();
+
+    let threads: Vec<_> = (0..4u8)
+        .map(|port| {
+            let mut stream = decoder.new_shared_stream();
+            let records = Arc::clone(&records);
+            thread::spawn(move || {
+                let mut data = FrameBytes::new(0).u8(port).bytes();
+                data.extend(FrameBytes::new(1).str("poll").bytes());
+                // Split mid-frame: the partial frame is kept between calls.
+                let (head, tail) = data.split_at(3);
+                let mut sink = |record| records.lock().unwrap().push(record);
+                stream.process_into(head, &mut sink).unwrap();
+                stream.process_into(tail, &mut sink).unwrap();
+            })
+        })
+        .collect();
+    drop(decoder);
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    let mut ports: Vec<String> = records
+        .lock()
+        .unwrap()
+        .iter()
+        .flat_map(|record| record.fields.iter())
+        .map(|(_, value)| value.clone())
+        .collect();
+    ports.sort();
+    assert_eq!(ports, ["0", "1", "2", "3"]);
+}
+
+#[test]
+fn test_into_stream_outlives_builder_scope() {
+    let mut stream = decoder().into_stream();
+    let handle = thread::spawn(move || {
+        let mut data = FrameBytes::new(0).u8(1).bytes();
+        data.extend(FrameBytes::new(1).str("poll").bytes());
+        stream.process(&data).unwrap();
+        stream.finish().unwrap()
+    });
+    let stats = handle.join().unwrap();
+    assert_eq!(stats.frames_decoded, 2);
+}
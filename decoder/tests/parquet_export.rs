@@ -0,0 +1,54 @@
+#![cfg(feature = "parquet")]
+
+mod common;
+
+use common::FrameBytes;
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use parquet::record::RowAccessor;
+use tracing_defmt_decoder::export::SpanParquetWriter;
+use tracing_defmt_decoder::TraceDecoder;
+
+#[test]
+fn test_completed_spans_become_rows() {
+    let table = common::table(
+        &[
+            ("Info", "span_enter: handle(len={=u8})"),
+            ("Debug", "span_enter: {=str}()"),
+            ("Info", "span_exit: {=str}"),
+        ],
+        None,
+    );
+    let decoder = TraceDecoder::builder()
+        .build_from_table(table, common::locations(3))
+        .unwrap();
+
+    let path = std::env::temp_dir().join(format!("spans-{}.parquet", std::process::id()));
+    let mut stream = decoder.new_stream();
+    stream.add_exporter(SpanParquetWriter::create(&path).unwrap());
+    let mut data = FrameBytes::new(0).u8(12).bytes();
+    data.extend(FrameBytes::new(1).str("parse").bytes());
+    data.extend(FrameBytes::new(2).str("parse").bytes());
+    data.extend(FrameBytes::new(2).str("handle").bytes());
+    stream.process(&data).unwrap();
+    // Writes the footer.
+    drop(stream);
+
+    let reader = SerializedFileReader::new(std::fs::File::open(&path).unwrap()).unwrap();
+    let rows: Vec<_> = reader
+        .get_row_iter(None)
+        .unwrap()
+        .map(|row| row.unwrap())
+        .collect();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[0].get_string(0).unwrap(), "parse");
+    // Without device timestamps there is no timing.
+    assert!(rows[0].get_long(3).is_err());
+    assert_eq!(rows[0].get_long(5).unwrap(), 2);
+    assert_eq!(rows[0].get_long(6).unwrap(), 1);
+    assert_eq!(rows[0].get_string(7).unwrap(), "handle");
+    assert_eq!(rows[1].get_string(0).unwrap(), "handle");
+    assert!(rows[1].get_long(6).is_err());
+    assert_eq!(rows[1].get_string(8).unwrap(), r#"{"len":"12"}"#);
+}
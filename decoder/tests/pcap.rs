@@ -0,0 +1,203 @@
+mod common;
+
+use std::io::Cursor;
+use std::time::{Duration, UNIX_EPOCH};
+
+use common::FrameBytes;
+use tracing_defmt_decoder::export::Exporter;
+use tracing_defmt_decoder::source::{Chunk, FrameSource, Pcap, PcapStats};
+use tracing_defmt_decoder::{TraceDecoder, TraceRecord};
+
+/// Collects exported messages.
+struct Messages<'a>(&'a mut Vec<String>);
+
+impl Exporter for Messages<'_> {
+    fn export(&mut self, record: &TraceRecord) -> std::io::Result<()> {
+        self.0.push(record.message.clone());
+        Ok(())
+    }
+}
+
+fn decoder() -> TraceDecoder {
+    let table = common::table(&[("Info", "reading {=u32}")], None);
+    TraceDecoder::builder()
+        .build_from_table(table, common::locations(1))
+        .unwrap()
+}
+
+fn reading(value: u32) -> Vec<u8> {
+    FrameBytes::new(0).u32(value).bytes()
+}
+
+fn run(pcap: &mut Pcap) -> Vec<String> {
+    let mut messages = Vec::new();
+    let decoder = decoder();
+    let mut stream = decoder.new_stream();
+    stream.add_exporter(Messages(&mut messages));
+    pcap.run(&mut stream).unwrap();
+    drop(stream);
+    messages
+}
+
+/// A big-endian classic pcap file of `USER0` packets, as `(captured, original length)`.
+fn pcap(packets: &[(&[u8], usize)]) -> Vec<u8> {
+    let mut file = Vec::new();
+    for word in [0xa1b2_c3d4u32, 0x0002_0004, 0, 0, 0xffff, 147] {
+        file.extend(word.to_be_bytes());
+    }
+    for (data, original) in packets {
+        for word in [0, 0, data.len() as u32, *original as u32] {
+            file.extend(word.to_be_bytes());
+        }
+        file.extend(*data);
+    }
+    file
+}
+
+fn block(block_type: u32, body: &[u8]) -> Vec<u8> {
+    let mut body = body.to_vec();
+    body.resize(body.len().next_multiple_of(4), 0);
+    let length = (body.len() + 12) as u32;
+    [
+        &block_type.to_le_bytes()[..],
+        &length.to_le_bytes(),
+        &body,
+        &length.to_le_bytes(),
+    ]
+    .concat()
+}
+
+/// A usbmon (mmapped) packet of `device` and `endpoint` with `data`.
+fn usbmon(event: u8, device: u8, endpoint: u8, data: &[u8]) -> Vec<u8> {
+    let mut packet = vec![0; 64];
+    packet[8] = event;
+    packet[9] = 3; // bulk
+    packet[10] = endpoint;
+    packet[11] = device;
+    packet.extend(data);
+    packet
+}
+
+fn enhanced_packet(interface: u32, packet: &[u8]) -> Vec<u8> {
+    let mut body = Vec::new();
+    for word in [interface, 0, 0, packet.len() as u32, packet.len() as u32] {
+        body.extend(word.to_le_bytes());
+    }
+    body.extend(packet);
+    block(6, &body)
+}
+
+#[test]
+fn test_pcap_packets_are_decoded() {
+    let data = [reading(1), reading(2)].concat();
+    let third = reading(3);
+    // The second frame is split across packets, the third cut by the snapshot length.
+    let file = pcap(&[
+        (&data[..5], 5),
+        (&data[5..], data.len() - 5),
+        (&third[..2], third.len()),
+        (&reading(4), third.len()),
+    ]);
+
+    let mut source = Pcap::new(Cursor::new(file));
+    assert_eq!(run(&mut source), ["reading 1", "reading 2", "reading 4"]);
+    assert_eq!(
+        source.stats(),
+        PcapStats {
+            packets: 4,
+            skipped: 0,
+            truncated: 1,
+            bytes: (data.len() + 2 + third.len()) as u64,
+        }
+    );
+}
+
+#[test]
+fn test_pcapng_usb_transfers_are_decoded() {
+    let mut file = block(
+        0x0a0d_0d0a,
+        &[
+            0x4d, 0x3c, 0x2b, 0x1a, 1, 0, 0, 0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        ],
+    );
+    // usbmon, no snapshot length.
+    file.extend(block(1, &[220, 0, 0, 0, 0, 0, 0, 0]));
+    for packet in [
+        // The request for the data, then its completion.
+        usbmon(b'S', 4, 0x81, &[]),
+        usbmon(b'C', 4, 0x81, &reading(1)),
+        // Another endpoint and another device.
+        usbmon(b'C', 4, 0x82, &reading(2)),
+        usbmon(b'C', 5, 0x81, &reading(3)),
+        // OUT data.
+        usbmon(b'C', 4, 0x01, &reading(4)),
+    ] {
+        file.extend(enhanced_packet(0, &packet));
+    }
+    // A block type the source knows nothing about.
+    file.extend(block(0x8000_0001, b"note"));
+    file.extend(enhanced_packet(0, &usbmon(b'C', 4, 0x81, &reading(5))));
+
+    let mut source = Pcap::new(Cursor::new(file.clone()));
+    assert_eq!(
+        run(&mut source),
+        ["reading 1", "reading 2", "reading 3", "reading 5"]
+    );
+
+    let mut source = Pcap::new(Cursor::new(file))
+        .with_usb_device(4)
+        .with_usb_endpoint(1);
+    assert_eq!(run(&mut source), ["reading 1", "reading 5"]);
+    assert_eq!(source.stats().skipped, 4);
+}
+
+#[test]
+fn test_packet_timestamps_are_passed_on() {
+    let mut file = block(
+        0x0a0d_0d0a,
+        &[
+            0x4d, 0x3c, 0x2b, 0x1a, 1, 0, 0, 0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        ],
+    );
+    // USER0 in nanoseconds: an if_tsresol option of 10^-9, then the end of options.
+    file.extend(block(
+        1,
+        &[147, 0, 0, 0, 0, 0, 0, 0, 9, 0, 1, 0, 9, 0, 0, 0, 0, 0, 0, 0],
+    ));
+    let nanos = 1_700_000_000_250_000_123u64;
+    let packet = reading(1);
+    let mut body = Vec::new();
+    for word in [
+        0,
+        (nanos >> 32) as u32,
+        nanos as u32,
+        packet.len() as u32,
+        packet.len() as u32,
+    ] {
+        body.extend(word.to_le_bytes());
+    }
+    body.extend(&packet);
+    file.extend(block(6, &body));
+
+    let mut received = Vec::new();
+    let more = Pcap::new(Cursor::new(file))
+        .read_chunks(&mut |chunk| {
+            if let Chunk::Data { received_at, .. } = chunk {
+                received.push(received_at);
+            }
+            Ok(())
+        })
+        .unwrap();
+    assert!(!more);
+    assert_eq!(received, [Some(UNIX_EPOCH + Duration::from_nanos(nanos))]);
+}
+
+#[test]
+fn test_other_files_are_rejected() {
+    let decoder = decoder();
+    let mut stream = decoder.new_stream();
+    let error = Pcap::new(Cursor::new(b"not a capture".to_vec()))
+        .run(&mut stream)
+        .unwrap_err();
+    assert!(error.to_string().contains("pcap"), "{}", error);
+}
@@ -0,0 +1,202 @@
+#![cfg(feature = "perfetto")]
+
+mod common;
+
+use common::FrameBytes;
+use tracing_defmt_decoder::export::{Exporter, PerfettoTraceWriter};
+use tracing_defmt_decoder::TraceDecoder;
+
+/// A protobuf field value, as far as the wire format tells.
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    Varint(u64),
+    Bytes(Vec<u8>),
+}
+
+/// The fields of a protobuf message, in order.
+#[derive(Debug)]
+struct Message(Vec<(u32, Value)>);
+
+fn varint(data: &mut &[u8]) -> u64 {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = data[0];
+        *data = &data[1..];
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+    }
+    panic!("varint longer than 10 bytes");
+}
+
+impl Message {
+    /// Decodes `data`, which must hold whole fields of varint or length-delimited types.
+    fn parse(mut data: &[u8]) -> Self {
+        let mut fields = Vec::new();
+        while !data.is_empty() {
+            let key = varint(&mut data);
+            let value = match key & 7 {
+                0 => Value::Varint(varint(&mut data)),
+                2 => {
+                    let len = varint(&mut data) as usize;
+                    let (bytes, rest) = data.split_at(len);
+                    data = rest;
+                    Value::Bytes(bytes.to_vec())
+                }
+                wire_type => panic!("unexpected wire type {}", wire_type),
+            };
+            fields.push(((key >> 3) as u32, value));
+        }
+        Message(fields)
+    }
+
+    fn all(&self, field: u32) -> impl Iterator<Item = &Value> {
+        self.0
+            .iter()
+            .filter(move |(number, _)| *number == field)
+            .map(|(_, value)| value)
+    }
+
+    fn varint(&self, field: u32) -> Option<u64> {
+        self.all(field).find_map(|value| match value {
+            Value::Varint(value) => Some(*value),
+            Value::Bytes(_) => None,
+        })
+    }
+
+    fn string(&self, field: u32) -> Option<String> {
+        self.all(field).find_map(|value| match value {
+            Value::Bytes(bytes) => Some(String::from_utf8(bytes.clone()).unwrap()),
+            Value::Varint(_) => None,
+        })
+    }
+
+    fn message(&self, field: u32) -> Option<Message> {
+        self.messages(field).next()
+    }
+
+    fn messages(&self, field: u32) -> impl Iterator<Item = Message> + '_ {
+        self.all(field).filter_map(|value| match value {
+            Value::Bytes(bytes) => Some(Message::parse(bytes)),
+            Value::Varint(_) => None,
+        })
+    }
+}
+
+#[test]
+fn test_nested_spans_and_events_become_track_events() {
+    let table = common::table(
+        &[
+            ("Info", "span_enter: {=str}()"),
+            ("Warn", "inside, retry={=u8}"),
+            ("Info", "span_exit: {=str}"),
+        ],
+        Some("{=u64:us}"),
+    );
+    let decoder = TraceDecoder::builder()
+        .build_from_table(table, common::locations(3))
+        .unwrap();
+    let mut data = FrameBytes::new(0).u64(10).str("poll").bytes();
+    data.extend(FrameBytes::new(0).u64(20).str("read").bytes());
+    data.extend(FrameBytes::new(1).u64(30).u8(2).bytes());
+    data.extend(FrameBytes::new(2).u64(40).str("read").bytes());
+    data.extend(FrameBytes::new(0).u64(50).str("read").bytes());
+    data.extend(FrameBytes::new(2).u64(60).str("read").bytes());
+    data.extend(FrameBytes::new(2).u64(70).str("poll").bytes());
+
+    let mut writer = PerfettoTraceWriter::new(Vec::new()).unwrap();
+    decoder
+        .new_stream()
+        .process_into(&data, |record| writer.export(&record).unwrap())
+        .unwrap();
+    let trace = Message::parse(&writer.finish().unwrap());
+    // Field numbers below are those of Perfetto's `trace_packet.proto` and the messages
+    // it includes, not of the writer, so a wrong tag fails here.
+
+    // Every entry of the trace is a `TracePacket`.
+    assert!(trace.0.iter().all(|(field, _)| *field == 1));
+    let packets: Vec<Message> = trace.messages(1).collect();
+    assert_eq!(packets.len(), 2 + 7);
+
+    // The process and the thread of context 0.
+    let process = packets[0].message(60).unwrap();
+    assert_eq!(packets[0].varint(13), Some(1));
+    assert_eq!(process.varint(1), Some(1));
+    let descriptor = process.message(3).unwrap();
+    assert_eq!(descriptor.string(6).as_deref(), Some("device"));
+    let thread = packets[1].message(60).unwrap();
+    assert_eq!(thread.varint(1), Some(0x1000));
+    assert_eq!(thread.varint(5), Some(1));
+    let descriptor = thread.message(4).unwrap();
+    assert_eq!(
+        (descriptor.varint(1), descriptor.varint(2)),
+        (Some(1), Some(0))
+    );
+    assert_eq!(descriptor.string(5).as_deref(), Some("context 0"));
+
+    let events: Vec<(u64, Message)> = packets[2..]
+        .iter()
+        .map(|packet| {
+            assert_eq!(packet.varint(10), Some(1));
+            assert_eq!(packet.varint(13), Some(2));
+            (packet.varint(8).unwrap(), packet.message(11).unwrap())
+        })
+        .collect();
+    let timestamps: Vec<u64> = events.iter().map(|(timestamp, _)| *timestamp).collect();
+    assert_eq!(
+        timestamps,
+        [10_000, 20_000, 30_000, 40_000, 50_000, 60_000, 70_000]
+    );
+    assert!(events
+        .iter()
+        .all(|(_, event)| event.varint(11) == Some(0x1000)));
+    let types: Vec<u64> = events
+        .iter()
+        .map(|(_, event)| event.varint(9).unwrap())
+        .collect();
+    assert_eq!(types, [1, 1, 3, 2, 1, 2, 2]);
+
+    // Names and the category are interned once, on first use.
+    let interned = |packet: &Message, table: u32| -> Vec<(u64, String)> {
+        packet
+            .message(12)
+            .map(|interned| {
+                interned
+                    .messages(table)
+                    .map(|entry| (entry.varint(1).unwrap(), entry.string(2).unwrap()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    assert_eq!(interned(&packets[2], 1), [(1, "app".to_string())]);
+    assert_eq!(interned(&packets[2], 2), [(1, "poll".to_string())]);
+    assert_eq!(interned(&packets[3], 1), []);
+    assert_eq!(interned(&packets[3], 2), [(2, "read".to_string())]);
+    assert!(packets[6].message(12).is_none());
+    let name_iids: Vec<Option<u64>> = [0, 1, 4].iter().map(|&i| events[i].1.varint(10)).collect();
+    assert_eq!(name_iids, [Some(1), Some(2), Some(2)]);
+    assert_eq!(events[0].1.varint(3), Some(1));
+
+    // The event is an instant named by its message, with its fields as annotations.
+    let event = &events[2].1;
+    assert_eq!(event.string(23).as_deref(), Some("inside"));
+    let annotations: Vec<(String, String)> = event
+        .messages(4)
+        .map(|annotation| {
+            (
+                annotation.string(10).unwrap(),
+                annotation.string(6).unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        annotations,
+        [
+            ("retry".to_string(), "2".to_string()),
+            ("level".to_string(), "WARN".to_string()),
+            ("file".to_string(), "src/main.rs".to_string()),
+            ("line".to_string(), "20".to_string()),
+        ]
+    );
+}
@@ -0,0 +1,62 @@
+#![cfg(feature = "prometheus")]
+
+mod common;
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+
+use common::FrameBytes;
+use tracing_defmt_decoder::{HealthEndpoint, TraceDecoder};
+
+fn scrape(endpoint: &HealthEndpoint, path: &str) -> String {
+    let mut connection = TcpStream::connect(endpoint.local_addr().unwrap()).unwrap();
+    write!(
+        connection,
+        "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        path
+    )
+    .unwrap();
+    let mut response = String::new();
+    connection.read_to_string(&mut response).unwrap();
+    response
+}
+
+#[test]
+fn test_health_is_served() {
+    let table = common::table(
+        &[("Info", "span_enter: poll()"), ("Info", "ok {=u8}")],
+        None,
+    );
+    let decoder = TraceDecoder::builder()
+        .build_from_table(table, common::locations(2))
+        .unwrap();
+    let endpoint = HealthEndpoint::serve("127.0.0.1:0").unwrap();
+
+    let mut stream = decoder.new_stream();
+    stream.report_health(&endpoint, "board \"7\"");
+    let mut data = FrameBytes::new(0).bytes();
+    data.extend(FrameBytes::new(1).u8(1).bytes());
+    stream.process_context(3, &data).unwrap();
+    // No entry has index 0xffff.
+    stream.process_context(3, &[0xff, 0xff, 0x00]).unwrap();
+
+    let response = scrape(&endpoint, "/metrics");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    for line in [
+        "# TYPE tracing_defmt_frames_decoded_total counter",
+        r#"tracing_defmt_bytes_received_total{stream="board \"7\""} 8"#,
+        r#"tracing_defmt_frames_decoded_total{stream="board \"7\""} 2"#,
+        r#"tracing_defmt_malformed_total{stream="board \"7\""} 1"#,
+        r#"tracing_defmt_open_spans{stream="board \"7\"",context="3"} 1"#,
+        r#"tracing_defmt_exporter_queue{stream="board \"7\""} 0"#,
+    ] {
+        assert!(
+            response.lines().any(|l| l == line),
+            "{}\n{}",
+            line,
+            response
+        );
+    }
+
+    assert!(scrape(&endpoint, "/").starts_with("HTTP/1.1 404"));
+}
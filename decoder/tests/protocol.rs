@@ -0,0 +1,79 @@
+mod common;
+
+use common::FrameBytes;
+use tracing_defmt_decoder::{Error, TraceDecoder};
+
+#[test]
+fn test_protocol_version_is_read_from_the_elf() {
+    let entries = [("Info", "sent {=u32}")];
+    let decoder = TraceDecoder::new(&common::elf(&entries, Some(1))).unwrap();
+    assert_eq!(decoder.protocol_version(), Some(1));
+
+    let mut messages = Vec::new();
+    decoder
+        .new_stream()
+        .process_into(&FrameBytes::new(0).u32(3).bytes(), |record| {
+            messages.push(record.message)
+        })
+        .unwrap();
+    assert_eq!(messages, ["sent 3"]);
+
+    // Firmware from before the symbol existed.
+    let decoder = TraceDecoder::new(&common::elf(&entries, None)).unwrap();
+    assert_eq!(decoder.protocol_version(), None);
+}
+
+#[test]
+fn test_newer_protocol_is_refused() {
+    let elf = common::elf(&[("Info", "sent {=u32}")], Some(7));
+    assert!(matches!(
+        TraceDecoder::new(&elf),
+        Err(Error::UnsupportedProtocol {
+            found: 7,
+            supported: 1
+        })
+    ));
+}
+
+#[test]
+fn test_hashed_span_names_are_looked_up() {
+    let entries = [
+        ("Info", "span_enter: uart_task(n={=u8})"),
+        ("Info", "span_exit: #{=u32:x}"),
+        ("Info", "poll"),
+    ];
+    let decoder = TraceDecoder::new(&common::elf(&entries, Some(1))).unwrap();
+    let hash = tracing_defmt_wire::name_hash("uart_task");
+
+    let mut data = FrameBytes::new(1).u32(hash).bytes();
+    data.extend(FrameBytes::new(0).u8(2).bytes());
+    data.extend(FrameBytes::new(2).bytes());
+    data.extend(FrameBytes::new(1).u32(hash).bytes());
+    let mut names = Vec::new();
+    decoder
+        .new_stream()
+        .process_into(&data, |record| names.push(record.message))
+        .unwrap();
+    // Also named when the span was entered before the capture started.
+    assert_eq!(names, ["uart_task", "uart_task", "poll", "uart_task"]);
+
+    // Without the ELF's symbols, the open span names its exit.
+    let table = common::table(&entries, None);
+    let decoder = TraceDecoder::builder()
+        .build_from_table(table, common::locations(entries.len()))
+        .unwrap();
+    let mut names = Vec::new();
+    decoder
+        .new_stream()
+        .process_into(&data, |record| names.push(record.message))
+        .unwrap();
+    assert_eq!(
+        names,
+        [
+            format!("#{:x}", hash).as_str(),
+            "uart_task",
+            "poll",
+            "uart_task"
+        ]
+    );
+}
@@ -0,0 +1,78 @@
+mod common;
+
+use std::io::Write;
+use std::net::TcpListener;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use common::FrameBytes;
+use tracing_defmt_decoder::source::{ReconnectPolicy, Tcp};
+use tracing_defmt_decoder::{Error, StreamIssue, TraceDecoder};
+
+#[test]
+fn test_dropped_connection_is_annotated() {
+    let table = common::table(&[("Info", "reading {=u32}")], None);
+    let decoder = TraceDecoder::builder()
+        .build_from_table(table, common::locations(1))
+        .unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        // Half a frame, then the link drops.
+        let (mut socket, _) = listener.accept().unwrap();
+        socket
+            .write_all(&FrameBytes::new(0).u32(1).bytes()[..3])
+            .unwrap();
+        drop(socket);
+        let (mut socket, _) = listener.accept().unwrap();
+        socket
+            .write_all(&FrameBytes::new(0).u32(2).bytes())
+            .unwrap();
+    });
+
+    let issues = Mutex::new(Vec::new());
+    let mut stream = decoder.new_stream();
+    stream.on_issue(|issue| issues.lock().unwrap().push(issue.clone()));
+    let mut tcp = Tcp::connect(addr)
+        .unwrap()
+        .with_reconnect_policy(ReconnectPolicy::fixed(Duration::from_millis(10)));
+
+    let mut closed = 0;
+    while closed < 2 {
+        if tcp.poll(&mut stream).unwrap() == 0 {
+            closed += 1;
+        }
+    }
+    server.join().unwrap();
+    stream.flush().unwrap();
+
+    assert_eq!(stream.stats().reconnects, 1);
+    assert_eq!(stream.stats().frames_decoded, 1);
+    assert!(matches!(
+        issues.lock().unwrap()[..],
+        [StreamIssue::Reconnected { attempts: 1, .. }]
+    ));
+}
+
+#[test]
+fn test_policy_gives_up() {
+    let table = common::table(&[("Info", "reading {=u32}")], None);
+    let decoder = TraceDecoder::builder()
+        .build_from_table(table, common::locations(1))
+        .unwrap();
+    // Nothing listens on the port once the listener is gone.
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let policy = ReconnectPolicy::exponential(Duration::from_millis(1), Duration::from_millis(4))
+        .with_max_attempts(3);
+    let mut tcp = Tcp::connect(addr).unwrap().with_reconnect_policy(policy);
+
+    let mut stream = decoder.new_stream();
+    match tcp.poll(&mut stream) {
+        Err(Error::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::TimedOut),
+        other => panic!("expected to give up, got {:?}", other),
+    }
+}
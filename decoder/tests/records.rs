@@ -0,0 +1,756 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use common::FrameBytes;
+use defmt_decoder::Location;
+use tracing_defmt_decoder::{ControlCommand, RecordKind, StreamIssue, Timebase, TraceDecoder};
+
+#[test]
+fn test_process_into_yields_span_tree() {
+    let table = common::table(
+        &[
+            ("Info", "span_enter: outer(n={=u32})"),
+            ("Debug", "span_enter: inner()"),
+            ("Warn", "low battery"),
+            ("Debug", "span_exit: {=str}"),
+            ("Info", "span_exit: {=str}"),
+        ],
+        None,
+    );
+    let decoder = TraceDecoder::builder()
+        .build_from_table(table, common::locations(5))
+        .unwrap();
+
+    let mut data = FrameBytes::new(0).u32(7).bytes();
+    data.extend(FrameBytes::new(1).bytes());
+    data.extend(FrameBytes::new(2).bytes());
+    data.extend(FrameBytes::new(3).str("inner").bytes());
+    data.extend(FrameBytes::new(4).str("outer").bytes());
+
+    let mut records = Vec::new();
+    let mut stream = decoder.new_stream();
+    stream
+        .process_into(&data, |record| records.push(record))
+        .unwrap();
+
+    let kinds: Vec<RecordKind> = records.iter().map(|r| r.kind).collect();
+    assert_eq!(
+        kinds,
+        [
+            RecordKind::SpanEnter,
+            RecordKind::SpanEnter,
+            RecordKind::Event,
+            RecordKind::SpanExit,
+            RecordKind::SpanExit,
+        ]
+    );
+
+    let outer = &records[0];
+    assert_eq!(outer.message, "outer");
+    assert_eq!(outer.fields, [("n".to_string(), "7".to_string())]);
+    assert_eq!((outer.span_id, outer.parent_id), (Some(1), None));
+
+    let inner = &records[1];
+    assert_eq!((inner.span_id, inner.parent_id), (Some(2), Some(1)));
+
+    let event = &records[2];
+    assert_eq!(event.message, "low battery");
+    assert_eq!(event.level, Some(tracing::Level::WARN));
+    assert_eq!(event.span_id, Some(2));
+    assert_eq!(event.location.as_ref().unwrap().line, 30);
+
+    assert_eq!(
+        (records[3].span_id, records[3].parent_id),
+        (Some(2), Some(1))
+    );
+    assert_eq!((records[4].span_id, records[4].parent_id), (Some(1), None));
+}
+
+#[test]
+fn test_instance_field_suffixes_span_name() {
+    let table = common::table(
+        &[
+            ("Info", "span_enter: uart_task(instance={=u8})"),
+            ("Info", "span_enter: poll(otel_name_suffix={=str})"),
+            ("Info", "span_exit: {=str}"),
+        ],
+        None,
+    );
+    let decoder = TraceDecoder::builder()
+        .with_sampler(tracing_defmt_decoder::Sampler::new().with_ratio("uart_task", 1.0))
+        .build_from_table(table, common::locations(3))
+        .unwrap();
+
+    let mut data = FrameBytes::new(0).u8(2).bytes();
+    data.extend(FrameBytes::new(1).str("ep1").bytes());
+    data.extend(FrameBytes::new(2).str("poll").bytes());
+    data.extend(FrameBytes::new(2).str("uart_task").bytes());
+
+    let mut records = Vec::new();
+    decoder
+        .new_stream()
+        .process_into(&data, |record| records.push(record))
+        .unwrap();
+
+    let names: Vec<&str> = records.iter().map(|r| r.message.as_str()).collect();
+    assert_eq!(
+        names,
+        ["uart_task[2]", "poll[ep1]", "poll[ep1]", "uart_task[2]"]
+    );
+    assert_eq!(
+        records[0].fields[0],
+        ("instance".to_string(), "2".to_string())
+    );
+    // The sampler's rules name spans without the suffix.
+    assert!(records[0]
+        .fields
+        .contains(&("sampling.rate".to_string(), "1".to_string())));
+}
+
+/// Collects the targets of the tracing events.
+struct EventTargets(Arc<Mutex<Vec<String>>>);
+
+impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for EventTargets {
+    fn on_event(&self, event: &tracing::Event, _: tracing_subscriber::layer::Context<S>) {
+        let target = event.metadata().target().to_string();
+        self.0.lock().unwrap().push(target);
+    }
+}
+
+#[test]
+fn test_explicit_parent_and_target_place_records() {
+    use tracing_subscriber::layer::SubscriberExt;
+
+    let table = common::table(
+        &[
+            ("Info", "span_enter: {=str}"),
+            ("Info", "span_enter: isr(parent=-)"),
+            ("Info", "late, target=radio, parent={=str}"),
+            ("Info", "boot, parent=-"),
+            ("Info", "span_exit: {=str}"),
+        ],
+        None,
+    );
+    let targets = Arc::new(Mutex::new(Vec::new()));
+    let subscriber = tracing_subscriber::registry().with(EventTargets(Arc::clone(&targets)));
+    let decoder = TraceDecoder::builder()
+        .with_dispatch(tracing::Dispatch::new(subscriber))
+        .build_from_table(table, common::locations(5))
+        .unwrap();
+
+    let mut data = FrameBytes::new(0).str("poll").bytes();
+    data.extend(FrameBytes::new(0).str("inner").bytes());
+    data.extend(FrameBytes::new(2).str("poll").bytes());
+    data.extend(FrameBytes::new(3).bytes());
+    data.extend(FrameBytes::new(1).bytes());
+    data.extend(FrameBytes::new(2).str("").bytes());
+    data.extend(FrameBytes::new(4).str("isr").bytes());
+
+    let mut records = Vec::new();
+    decoder
+        .new_stream()
+        .process_into(&data, |record| records.push(record))
+        .unwrap();
+    decoder.new_stream().process(&data).unwrap();
+
+    let placed: Vec<(&str, Option<u64>, Option<u64>)> = records
+        .iter()
+        .map(|r| (r.message.as_str(), r.span_id, r.parent_id))
+        .collect();
+    assert_eq!(
+        placed,
+        [
+            ("poll", Some(1), None),
+            ("inner", Some(2), Some(1)),
+            // Named parent, instead of the innermost span.
+            ("late", Some(1), None),
+            ("boot", None, None),
+            ("isr", Some(3), None),
+            // A parent without a name leaves the record where it is.
+            ("late", Some(3), None),
+            ("isr", Some(3), Some(2)),
+        ]
+    );
+    assert_eq!(*targets.lock().unwrap(), ["radio", "device_log", "radio"]);
+}
+
+#[test]
+fn test_contexts_have_independent_span_stacks() {
+    let table = common::table(
+        &[
+            ("Info", "span_enter: {=str}()"),
+            ("Info", "span_exit: {=str}"),
+            ("Info", "work"),
+        ],
+        None,
+    );
+    let decoder = TraceDecoder::builder()
+        .build_from_table(table, common::locations(3))
+        .unwrap();
+
+    let mut records = Vec::new();
+    let mut stream = decoder.new_stream();
+    let mut feed = |context: u32, data: Vec<u8>| {
+        stream
+            .process_context_into(context, &data, |record| records.push(record))
+            .unwrap();
+    };
+    feed(0, FrameBytes::new(0).str("core0_task").bytes());
+    feed(1, FrameBytes::new(0).str("core1_task").bytes());
+    // Core 0 finishes a partial frame only after core 1 logged.
+    let work = FrameBytes::new(2).bytes();
+    feed(0, work[..1].to_vec());
+    feed(1, FrameBytes::new(2).bytes());
+    feed(0, work[1..].to_vec());
+    feed(1, FrameBytes::new(1).str("core1_task").bytes());
+    feed(0, FrameBytes::new(1).str("core0_task").bytes());
+
+    let summary: Vec<(u32, RecordKind, Option<u64>, Option<u64>)> = records
+        .iter()
+        .map(|r| (r.context, r.kind, r.span_id, r.parent_id))
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            (0, RecordKind::SpanEnter, Some(1), None),
+            (1, RecordKind::SpanEnter, Some(2), None),
+            (1, RecordKind::Event, Some(2), None),
+            (0, RecordKind::Event, Some(1), None),
+            (1, RecordKind::SpanExit, Some(2), None),
+            (0, RecordKind::SpanExit, Some(1), None),
+        ]
+    );
+}
+
+#[test]
+fn test_reorder_window_sorts_by_device_timestamp() {
+    let table = common::table(
+        &[
+            ("Info", "span_enter: task()"),
+            ("Info", "before task"),
+            ("Info", "span_exit: {=str}"),
+        ],
+        Some("{=u64:us}"),
+    );
+    let decoder = TraceDecoder::builder()
+        .with_reorder_window(Duration::from_millis(1))
+        .build_from_table(table, common::locations(3))
+        .unwrap();
+
+    // The event was logged before the span was entered, but arrives after it.
+    let mut data = FrameBytes::new(0).u64(1_000).bytes();
+    data.extend(FrameBytes::new(1).u64(900).bytes());
+    data.extend(FrameBytes::new(2).u64(1_500).str("task").bytes());
+
+    let mut records = Vec::new();
+    let mut stream = decoder.new_stream();
+    stream
+        .process_into(&data, |record| records.push(record))
+        .unwrap();
+    assert!(records.is_empty());
+    stream.flush_into(|record| records.push(record));
+
+    let summary: Vec<(RecordKind, Option<u64>, Option<u64>)> = records
+        .iter()
+        .map(|r| (r.kind, r.timestamp, r.span_id))
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            (RecordKind::Event, Some(900), None),
+            (RecordKind::SpanEnter, Some(1_000), Some(1)),
+            (RecordKind::SpanExit, Some(1_500), Some(1)),
+        ]
+    );
+}
+
+#[test]
+fn test_timebase_converts_ticks() {
+    let table = common::table(&[("Info", "tick")], Some("{=u32}"));
+    let decoder = TraceDecoder::builder()
+        .with_timebase(Timebase::new(32_768))
+        .build_from_table(table, common::locations(1))
+        .unwrap();
+
+    let mut timestamps = Vec::new();
+    let mut stream = decoder.new_stream();
+    stream
+        .process_into(&FrameBytes::new(0).u32(49_152).bytes(), |record| {
+            timestamps.push(record.timestamp)
+        })
+        .unwrap();
+    assert_eq!(timestamps, [Some(1_500_000)]);
+}
+
+#[test]
+fn test_counter_rollover_is_unwrapped() {
+    let table = common::table(&[("Info", "tick")], Some("{=u32}"));
+    let decoder = TraceDecoder::builder()
+        .with_timebase(Timebase::new(1_000).with_counter_width(16))
+        .build_from_table(table, common::locations(1))
+        .unwrap();
+
+    let issues = Mutex::new(Vec::new());
+    let mut timestamps = Vec::new();
+    let mut stream = decoder.new_stream();
+    stream.on_issue(|issue| issues.lock().unwrap().push(issue.clone()));
+    for ticks in [60_000, 65_000, 500, 1_000, 21_000] {
+        stream
+            .process_into(&FrameBytes::new(0).u32(ticks).bytes(), |record| {
+                timestamps.push(record.timestamp.unwrap() / 1_000)
+            })
+            .unwrap();
+    }
+    drop(stream);
+
+    assert_eq!(timestamps, [60_000, 65_000, 66_036, 66_536, 86_536]);
+    assert_eq!(
+        *issues.lock().unwrap(),
+        [StreamIssue::TimestampJump {
+            context: 0,
+            from: 66_536_000,
+            to: 86_536_000,
+        }]
+    );
+}
+
+#[test]
+fn test_wall_clock_anchoring_keeps_device_intervals() {
+    let table = common::table(&[("Info", "tick")], Some("{=u64:us}"));
+    let decoder = TraceDecoder::builder()
+        .with_wall_clock_anchoring(true)
+        .build_from_table(table, common::locations(1))
+        .unwrap();
+
+    let mut wall_times = Vec::new();
+    let mut stream = decoder.new_stream();
+    let before = SystemTime::now();
+    stream
+        .process_into(&FrameBytes::new(0).u64(5_000_000).bytes(), |record| {
+            wall_times.push(record.wall_time.unwrap())
+        })
+        .unwrap();
+    let after = SystemTime::now();
+
+    // The second frame arrives later than its timestamp suggests, so the anchor holds.
+    std::thread::sleep(Duration::from_millis(5));
+    stream
+        .process_into(&FrameBytes::new(0).u64(5_000_100).bytes(), |record| {
+            wall_times.push(record.wall_time.unwrap())
+        })
+        .unwrap();
+
+    assert!(wall_times[0] >= before && wall_times[0] <= after);
+    assert_eq!(
+        wall_times[1].duration_since(wall_times[0]).unwrap(),
+        Duration::from_micros(100)
+    );
+}
+
+#[test]
+fn test_time_sync_round_trips_correct_drift() {
+    let table = common::table(
+        &[("Info", "time_sync: {=u32}"), ("Info", "tick")],
+        Some("{=u64:us}"),
+    );
+    let decoder = TraceDecoder::builder()
+        .with_wall_clock_anchoring(true)
+        .build_from_table(table, common::locations(2))
+        .unwrap();
+    let mut stream = decoder.new_stream();
+    let mut wall_times = Vec::new();
+
+    // The device clock runs four times as fast as the host's.
+    let before = SystemTime::now();
+    assert_eq!(
+        stream.time_sync_request(),
+        ControlCommand::Sync { nonce: 0 }
+    );
+    stream
+        .process_into(
+            &FrameBytes::new(0).u64(1_000_000).u32(0).bytes(),
+            |record| wall_times.push(record.wall_time.unwrap()),
+        )
+        .unwrap();
+    let after = SystemTime::now();
+    assert_eq!(stream.clock_drift_ppm(), None);
+
+    std::thread::sleep(Duration::from_millis(250));
+    let request = stream.time_sync_request();
+    let mut data = FrameBytes::new(0).u64(2_000_000).u32(1).bytes();
+    data.extend(FrameBytes::new(1).u64(5_000_000).bytes());
+    stream
+        .process_into(&data, |record| wall_times.push(record.wall_time.unwrap()))
+        .unwrap();
+
+    assert_eq!(request.to_bytes(), b"sync 1\n");
+    assert!(wall_times[0] >= before && wall_times[0] <= after);
+    let drift = stream.clock_drift_ppm().unwrap();
+    assert!((2_600_000.0..3_010_000.0).contains(&drift), "{}", drift);
+    // The tick is 4 s of device time after the first sync, about 1 s of host time at
+    // the measured rate; how long the sleep really took decides the exact value.
+    let elapsed = wall_times[2].duration_since(wall_times[0]).unwrap();
+    let expected = 4.0 / (1.0 + drift / 1e6);
+    assert!(
+        (elapsed.as_secs_f64() - expected).abs() < 0.005,
+        "{:?}, expected {}s",
+        elapsed,
+        expected
+    );
+}
+
+#[test]
+fn test_metric_frames_are_classified() {
+    let table = common::table(
+        &[
+            ("Info", "counter: rx_packets={=u32}, iface={=str}"),
+            ("Info", "gauge: battery_mv={=u32}"),
+        ],
+        None,
+    );
+    let decoder = TraceDecoder::builder()
+        .build_from_table(table, common::locations(2))
+        .unwrap();
+
+    let mut data = FrameBytes::new(0).u32(3).str("eth0").bytes();
+    data.extend(FrameBytes::new(1).u32(3_300).bytes());
+
+    let mut records = Vec::new();
+    let mut stream = decoder.new_stream();
+    stream
+        .process_into(&data, |record| records.push(record))
+        .unwrap();
+
+    let field = |key: &str, value: &str| (key.to_string(), value.to_string());
+    assert_eq!(records[0].kind, RecordKind::Counter);
+    assert_eq!(records[0].message, "rx_packets");
+    assert_eq!(
+        records[0].fields,
+        [field("value", "3"), field("iface", "eth0")]
+    );
+    assert_eq!(records[1].kind, RecordKind::Gauge);
+    assert_eq!(records[1].message, "battery_mv");
+    assert_eq!(records[1].fields, [field("value", "3300")]);
+}
+
+#[test]
+fn test_event_fields_are_extracted() {
+    let table = common::table(
+        &[
+            ("Info", "link changed, port={=u8}, up={=str}"),
+            ("Info", "peer={=str}, rtt={=str}"),
+            ("Info", "ratio a=b, done"),
+        ],
+        None,
+    );
+    let decoder = TraceDecoder::builder()
+        .build_from_table(table, common::locations(3))
+        .unwrap();
+
+    let mut data = FrameBytes::new(0).u8(3).str("true").bytes();
+    data.extend(FrameBytes::new(1).str("gw").str("1.5").bytes());
+    data.extend(FrameBytes::new(2).bytes());
+
+    let mut records = Vec::new();
+    let mut stream = decoder.new_stream();
+    stream
+        .process_into(&data, |record| records.push(record))
+        .unwrap();
+
+    let field = |key: &str, value: &str| (key.to_string(), value.to_string());
+    assert_eq!(records[0].message, "link changed");
+    assert_eq!(records[0].fields, [field("port", "3"), field("up", "true")]);
+    assert_eq!(records[1].message, "peer=gw, rtt=1.5");
+    assert_eq!(
+        records[1].fields,
+        [field("peer", "gw"), field("rtt", "1.5")]
+    );
+    assert_eq!(records[2].message, "ratio a=b, done");
+    assert!(records[2].fields.is_empty());
+}
+
+#[test]
+fn test_interned_fields_are_marked() {
+    let table = common::table(
+        &[
+            ("Info", "state changed, state={=istr}, code={=u8}"),
+            ("Info", "span_enter: poll(mode={=istr})"),
+            ("Info", "retry {=istr}"),
+            ("Str", "1"),
+            ("Str", "idle, backoff"),
+        ],
+        None,
+    );
+    let decoder = TraceDecoder::builder()
+        .build_from_table(table, common::locations(5))
+        .unwrap();
+
+    let mut data = FrameBytes::new(0).istr(4).u8(2).bytes();
+    data.extend(FrameBytes::new(1).istr(3).bytes());
+    data.extend(FrameBytes::new(2).istr(3).bytes());
+
+    let mut records = Vec::new();
+    let mut stream = decoder.new_stream();
+    stream
+        .process_into(&data, |record| records.push(record))
+        .unwrap();
+
+    let field = |key: &str, value: &str| (key.to_string(), value.to_string());
+    assert_eq!(
+        records[0].fields,
+        [field("state", "idle, backoff"), field("code", "2")]
+    );
+    assert_eq!(records[0].interned_fields, ["state"]);
+    assert_eq!(records[1].kind, RecordKind::SpanEnter);
+    assert_eq!(records[1].fields, [field("mode", "1")]);
+    assert_eq!(records[1].interned_fields, ["mode"]);
+    // Not a field.
+    assert!(records[2].interned_fields.is_empty());
+}
+
+#[test]
+fn test_repeated_events_are_coalesced() {
+    let table = common::table(
+        &[
+            ("Info", "span_enter: {=str}()"),
+            ("Info", "span_exit: {=str}"),
+            ("Warn", "retry, attempt={=u8}"),
+        ],
+        None,
+    );
+    let decoder = TraceDecoder::builder()
+        .with_event_coalescing(true)
+        .build_from_table(table, common::locations(3))
+        .unwrap();
+
+    let retry = |attempt| FrameBytes::new(2).u8(attempt).bytes();
+    let data = [
+        FrameBytes::new(0).str("poll").bytes(),
+        retry(1),
+        retry(1),
+        retry(1),
+        retry(2),
+        FrameBytes::new(1).str("poll").bytes(),
+        retry(2),
+    ]
+    .concat();
+
+    let mut records = Vec::new();
+    let mut stream = decoder.new_stream();
+    stream
+        .process_into(&data, |record| records.push(record))
+        .unwrap();
+    // The last run is still open.
+    assert_eq!(records.len(), 4);
+    stream.flush_into(|record| records.push(record));
+
+    let field = |key: &str, value: &str| (key.to_string(), value.to_string());
+    let summary: Vec<_> = records
+        .iter()
+        .map(|r| (r.kind, r.fields.clone(), r.span_id))
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            (RecordKind::SpanEnter, vec![], Some(1)),
+            (
+                RecordKind::Event,
+                vec![field("attempt", "1"), field("count", "3")],
+                Some(1)
+            ),
+            (RecordKind::Event, vec![field("attempt", "2")], Some(1)),
+            (RecordKind::SpanExit, vec![], Some(1)),
+            // Not merged with the one inside the span.
+            (RecordKind::Event, vec![field("attempt", "2")], None),
+        ]
+    );
+}
+
+#[test]
+fn test_field_display_hints_are_rendered() {
+    // `field::hex` and friends write their value with a nested, hinted format string.
+    let table = common::table(
+        &[
+            (
+                "Info",
+                "registers, reg={=?}, flags={=?}, uptime={=?}, timeout={=?}",
+            ),
+            ("Write", "{=u32:x}"),
+            ("Write", "{=u8:b}"),
+            ("Write", "{=u64:us}"),
+            ("Write", "{=u32:ms}"),
+        ],
+        None,
+    );
+    let decoder = TraceDecoder::builder()
+        .build_from_table(table, common::locations(5))
+        .unwrap();
+
+    let nested = |entry: usize| FrameBytes::new(entry).bytes();
+    let mut data = FrameBytes::new(0).bytes();
+    data.extend(nested(1));
+    data.extend(0x1fu32.to_le_bytes());
+    data.extend(nested(2));
+    data.push(0b101);
+    data.extend(nested(3));
+    data.extend(1_500_000u64.to_le_bytes());
+    data.extend(nested(4));
+    data.extend(250u32.to_le_bytes());
+
+    let mut records = Vec::new();
+    decoder
+        .new_stream()
+        .process_into(&data, |record| records.push(record))
+        .unwrap();
+
+    let field = |key: &str, value: &str| (key.to_string(), value.to_string());
+    assert_eq!(
+        records[0].fields,
+        [
+            field("reg", "1f"),
+            field("flags", "101"),
+            field("uptime", "1.500000"),
+            field("timeout", "0.250"),
+        ]
+    );
+}
+
+#[test]
+fn test_field_limits_truncate_records() {
+    let table = common::table(
+        &[
+            ("Info", "rx, buf={=str}, len=3, crc=ok, target=radio"),
+            ("Info", "span_enter: {=str}(dump={=str})"),
+        ],
+        None,
+    );
+    let decoder = TraceDecoder::builder()
+        .with_max_fields(2)
+        .with_max_field_len(4)
+        .build_from_table(table, common::locations(2))
+        .unwrap();
+
+    let mut data = FrameBytes::new(0).str("0a1b2c3d").bytes();
+    data.extend(FrameBytes::new(1).str("dump").str("é€").bytes());
+    let mut records = Vec::new();
+    decoder
+        .new_stream()
+        .process_into(&data, |record| records.push(record))
+        .unwrap();
+
+    let field = |key: &str, value: &str| (key.to_string(), value.to_string());
+    assert_eq!(
+        records[0].fields,
+        [
+            field("buf", "0a1b"),
+            field("len", "3"),
+            field("target", "radio"),
+            field("truncated", "true"),
+        ]
+    );
+    // Cut at a character boundary.
+    assert_eq!(
+        records[1].fields,
+        [field("dump", "é"), field("truncated", "true")]
+    );
+}
+
+#[test]
+fn test_source_paths_are_remapped() {
+    let table = common::table(&[("Info", "a"), ("Info", "b"), ("Info", "c")], None);
+    let mut locations = common::locations(3);
+    for (index, file) in [
+        (1, "/home/ci/fw/src/main.rs"),
+        (2, "/home/ci/.cargo/registry/src/index.crates.io-1949cf8c6b5b557f/heapless-0.8.0/src/vec.rs"),
+        (3, "/home/ci/fw2/src/main.rs"),
+    ] {
+        let location: &mut Location = locations.get_mut(&index).unwrap();
+        location.file = file.into();
+    }
+    let decoder = TraceDecoder::builder()
+        .with_workspace_root("/home/ci/fw/")
+        .with_path_prefix(
+            "/home/ci/.cargo/registry/src/index.crates.io-1949cf8c6b5b557f",
+            "crates",
+        )
+        .build_from_table(table, locations)
+        .unwrap();
+
+    let data = [0, 1, 2]
+        .map(|entry| FrameBytes::new(entry).bytes())
+        .concat();
+    let mut files = Vec::new();
+    decoder
+        .new_stream()
+        .process_into(&data, |record| {
+            files.push(record.location.unwrap().file.to_string())
+        })
+        .unwrap();
+    assert_eq!(
+        files,
+        [
+            "src/main.rs",
+            "crates/heapless-0.8.0/src/vec.rs",
+            "/home/ci/fw2/src/main.rs",
+        ]
+    );
+}
+
+#[test]
+fn test_on_frame_sees_raw_bytes_and_location() {
+    let table = common::table(&[("Info", "a {=u8}"), ("Info", "b {=str}")], None);
+    let decoder = TraceDecoder::builder()
+        .build_from_table(table, common::locations(2))
+        .unwrap();
+
+    let first = FrameBytes::new(0).u8(7).bytes();
+    let second = FrameBytes::new(1).str("xyz").bytes();
+    let frames = std::sync::Mutex::new(Vec::new());
+    let mut stream = decoder.new_stream();
+    stream.on_frame(|raw| {
+        let line = raw.location.map(|location| location.line);
+        frames.lock().unwrap().push((
+            raw.bytes.to_vec(),
+            raw.frame.display_message().to_string(),
+            line,
+        ));
+    });
+    stream
+        .process_into(&[first.clone(), second.clone()].concat(), |_| {})
+        .unwrap();
+    drop(stream);
+
+    assert_eq!(
+        frames.into_inner().unwrap(),
+        [
+            (first, "a 7".to_string(), Some(10)),
+            (second, "b xyz".to_string(), Some(20)),
+        ]
+    );
+}
+
+#[test]
+fn test_locations_are_shared_between_records() {
+    let table = common::table(&[("Info", "a {=u8}"), ("Info", "b {=u8}")], None);
+    let decoder = TraceDecoder::builder()
+        .build_from_table(table, common::locations(2))
+        .unwrap();
+
+    let mut data = FrameBytes::new(0).u8(1).bytes();
+    data.extend(FrameBytes::new(0).u8(2).bytes());
+    data.extend(FrameBytes::new(1).u8(3).bytes());
+    let mut locations = Vec::new();
+    let mut stream = decoder.new_stream();
+    stream
+        .process_into(&data, |record| locations.push(record.location.unwrap()))
+        .unwrap();
+
+    assert_eq!(locations[0], locations[1]);
+    assert_eq!(locations[2].line, locations[0].line + 10);
+    // Resolved once per firmware, not per frame; both statements are in `src/main.rs`.
+    assert!(locations
+        .windows(2)
+        .all(|pair| Arc::ptr_eq(&pair[0].file, &pair[1].file)));
+}
@@ -0,0 +1,44 @@
+mod common;
+
+use std::io::Cursor;
+use std::time::{Duration, Instant};
+
+use common::FrameBytes;
+use tracing_defmt_decoder::export::Exporter;
+use tracing_defmt_decoder::source::Replay;
+use tracing_defmt_decoder::{TraceDecoder, TraceRecord};
+
+/// Collects exported record messages.
+struct Messages<'a>(&'a mut Vec<String>);
+
+impl Exporter for Messages<'_> {
+    fn export(&mut self, record: &TraceRecord) -> std::io::Result<()> {
+        self.0.push(record.message.clone());
+        Ok(())
+    }
+}
+
+#[test]
+fn test_realtime_replay_follows_device_timestamps() {
+    let table = common::table(&[("Info", "sample {=u8}")], Some("{=u64:us}"));
+    let decoder = TraceDecoder::builder()
+        .build_from_table(table, common::locations(1))
+        .unwrap();
+
+    let mut capture = FrameBytes::new(0).u64(1_000_000).u8(1).bytes();
+    capture.extend(FrameBytes::new(0).u64(1_050_000).u8(2).bytes());
+
+    let mut messages = Vec::new();
+    let mut stream = decoder.new_stream();
+    stream.add_exporter(Messages(&mut messages));
+    let started = Instant::now();
+    let read = Replay::new(Cursor::new(capture.clone()))
+        .with_realtime(true)
+        .run(&mut stream)
+        .unwrap();
+    assert!(started.elapsed() >= Duration::from_millis(50));
+    assert_eq!(read, capture.len() as u64);
+    drop(stream);
+
+    assert_eq!(messages, ["sample 1", "sample 2"]);
+}
@@ -0,0 +1,107 @@
+mod common;
+
+use common::FrameBytes;
+use tracing_defmt_decoder::report::{Outcome, TestReport};
+use tracing_defmt_decoder::TraceDecoder;
+
+/// A self-test run: `test_crc` passes, `test_flash` logs an error, and `test_radio` is
+/// still running when the trace ends.
+fn report() -> TestReport {
+    let table = common::table(
+        &[
+            ("Info", "span_enter: selftest()"),
+            ("Info", "span_enter: test_crc(len={=u32})"),
+            ("Info", "span_enter: test_flash()"),
+            ("Error", "erase failed: <bank 1>"),
+            ("Info", "span_exit: {=str}"),
+            ("Info", "span_enter: test_radio()"),
+        ],
+        Some("{=u64:us}"),
+    );
+    let decoder = TraceDecoder::builder()
+        .build_from_table(table, common::locations(6))
+        .unwrap();
+
+    let mut data = FrameBytes::new(0).u64(1_000).bytes();
+    data.extend(FrameBytes::new(1).u64(1_100).u32(12).bytes());
+    data.extend(FrameBytes::new(4).u64(1_350).str("test_crc").bytes());
+    data.extend(FrameBytes::new(2).u64(1_400).bytes());
+    data.extend(FrameBytes::new(3).u64(1_500).bytes());
+    data.extend(FrameBytes::new(4).u64(3_400).str("test_flash").bytes());
+    data.extend(FrameBytes::new(5).u64(3_500).bytes());
+    TestReport::decode(&decoder, &data).unwrap()
+}
+
+#[test]
+fn test_top_level_spans_are_cases() {
+    let report = report();
+    let cases = report.cases();
+    assert_eq!(cases.len(), 1);
+    assert_eq!(cases[0].name, "selftest");
+    assert_eq!(
+        cases[0].outcome,
+        Outcome::Failed {
+            errors: vec!["erase failed: <bank 1>".to_string()]
+        }
+    );
+    assert!(!report.passed());
+}
+
+#[test]
+fn test_prefixed_spans_are_cases() {
+    let report = report().with_case_prefix("test_");
+    let outcomes: Vec<_> = report
+        .cases()
+        .iter()
+        .map(|case| (case.name.as_str(), case.outcome.clone(), case.duration))
+        .collect();
+    assert_eq!(
+        outcomes,
+        [
+            (
+                "test_crc",
+                Outcome::Passed,
+                Some(std::time::Duration::from_micros(250))
+            ),
+            (
+                "test_flash",
+                Outcome::Failed {
+                    errors: vec!["erase failed: <bank 1>".to_string()]
+                },
+                Some(std::time::Duration::from_micros(2_000))
+            ),
+            ("test_radio", Outcome::Incomplete, None),
+        ]
+    );
+
+    let json = report.to_json();
+    assert_eq!(json["tests"], 3);
+    assert_eq!(json["failed"], 1);
+    assert_eq!(json["cases"][0]["fields"]["len"], "12");
+    assert_eq!(json["cases"][1]["duration_us"], 2_000);
+}
+
+#[test]
+fn test_junit_xml() {
+    let xml = report()
+        .with_suite_name("selftest")
+        .with_case_prefix("test_")
+        .to_junit_xml();
+    assert_eq!(
+        xml,
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<testsuites>
+  <testsuite name="selftest" tests="3" failures="1" errors="1" skipped="0" time="0.002250">
+    <testcase name="test_crc(len=12)" classname="selftest" time="0.000250"/>
+    <testcase name="test_flash" classname="selftest" time="0.002000">
+      <failure message="erase failed: &lt;bank 1&gt;" type="ERROR">erase failed: &lt;bank 1&gt;</failure>
+      <system-out>ERROR erase failed: &lt;bank 1&gt;</system-out>
+    </testcase>
+    <testcase name="test_radio" classname="selftest" time="0.000000">
+      <error message="span did not exit" type="incomplete"/>
+    </testcase>
+  </testsuite>
+</testsuites>
+"#
+    );
+}
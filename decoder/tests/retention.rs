@@ -0,0 +1,94 @@
+mod common;
+
+use std::time::Duration;
+
+use common::FrameBytes;
+use tracing_defmt_decoder::export::Exporter;
+use tracing_defmt_decoder::{RecordKind, Retention, TraceDecoder, TraceRecord};
+
+/// Collects exported records.
+struct Records<'a>(&'a mut Vec<TraceRecord>);
+
+impl Exporter for Records<'_> {
+    fn export(&mut self, record: &TraceRecord) -> std::io::Result<()> {
+        self.0.push(record.clone());
+        Ok(())
+    }
+}
+
+fn decoder(retention: Retention) -> TraceDecoder {
+    let table = common::table(
+        &[
+            ("Info", "span_enter: {=str}()"),
+            ("Info", "span_exit: {=str}"),
+            ("Info", "sample {=u8}"),
+            ("Error", "sensor fault"),
+        ],
+        None,
+    );
+    TraceDecoder::builder()
+        .with_retention(retention)
+        .build_from_table(table, common::locations(4))
+        .unwrap()
+}
+
+fn sample(value: u8) -> Vec<u8> {
+    FrameBytes::new(2).u8(value).bytes()
+}
+
+#[test]
+fn test_error_exports_retained_records() {
+    let decoder = decoder(Retention::new(Duration::from_secs(60), 1 << 20));
+    let mut records = Vec::new();
+    let mut stream = decoder.new_stream();
+
+    let data = [FrameBytes::new(0).str("poll").bytes(), sample(1), sample(2)].concat();
+    stream.process_into(&data, |r| records.push(r)).unwrap();
+    assert!(records.is_empty());
+
+    stream
+        .process_into(&FrameBytes::new(3).bytes(), |r| records.push(r))
+        .unwrap();
+    let summary: Vec<_> = records
+        .iter()
+        .map(|r| (r.kind, r.message.as_str(), r.span_id))
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            (RecordKind::SpanEnter, "poll", Some(1)),
+            (RecordKind::Event, "sample 1", Some(1)),
+            (RecordKind::Event, "sample 2", Some(1)),
+            (RecordKind::Event, "sensor fault", Some(1)),
+        ]
+    );
+
+    // Kept again until the next trigger, which never comes.
+    records.clear();
+    stream
+        .process_into(&FrameBytes::new(1).str("poll").bytes(), |r| records.push(r))
+        .unwrap();
+    assert!(records.is_empty());
+    assert_eq!(stream.finish().unwrap().records_discarded, 1);
+}
+
+#[test]
+fn test_oldest_records_are_discarded_past_the_size_limit() {
+    // Room for two records only.
+    let size = std::mem::size_of::<TraceRecord>();
+    let retention = Retention::new(Duration::from_secs(60), 2 * size + 32).with_trigger_level(None);
+    let decoder = decoder(retention);
+    let mut records = Vec::new();
+    let mut stream = decoder.new_stream();
+    stream.add_exporter(Records(&mut records));
+
+    // Without a trigger level, the error is kept like the others.
+    let data = [sample(1), sample(2), sample(3), FrameBytes::new(3).bytes()].concat();
+    stream.process(&data).unwrap();
+    assert_eq!(stream.stats().records_discarded, 2);
+    stream.export_retained().unwrap();
+    drop(stream);
+
+    let messages: Vec<_> = records.iter().map(|r| r.message.as_str()).collect();
+    assert_eq!(messages, ["sample 3", "sensor fault"]);
+}
@@ -0,0 +1,87 @@
+mod common;
+
+use common::fuzz;
+
+/// Inputs tried per test, enough to reach the special frame parsers with every option.
+const RUNS: usize = 3000;
+
+/// xorshift64, so failures reproduce.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+#[test]
+fn test_random_bytes_do_not_panic() {
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+    for _ in 0..RUNS {
+        let input: Vec<u8> = (0..rng.below(512)).map(|_| rng.next() as u8).collect();
+        fuzz::decode_arbitrary(&input);
+    }
+}
+
+#[test]
+fn test_corrupted_captures_do_not_panic() {
+    let seed = common::fuzz::seed();
+    let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+    for _ in 0..RUNS {
+        // Raw frames with timestamps, fed in one chunk per context, then corrupted.
+        let mut input = vec![(rng.next() as u8 & !1) | 2];
+        for _ in 0..1 + rng.below(3) {
+            input.push(0x3c | (rng.next() as u8 & 0xc0));
+            input.extend(&seed);
+        }
+        for _ in 0..1 + rng.below(8) {
+            if input.len() < 2 {
+                break;
+            }
+            let at = rng.below(input.len() - 1) + 1;
+            match rng.below(3) {
+                0 => input[at] = rng.next() as u8,
+                1 => input.truncate(at),
+                _ => input.insert(at, rng.next() as u8),
+            }
+        }
+        fuzz::decode_arbitrary(&input);
+    }
+}
+
+#[test]
+fn test_corrupt_length_does_not_buffer_the_stream() {
+    let table = common::table(&[("Info", "{=str}"), ("Info", "tick")], None);
+    let decoder = tracing_defmt_decoder::TraceDecoder::builder()
+        .build_from_table(table, common::locations(2))
+        .unwrap();
+    let mut stream = decoder.new_stream();
+    let mut records = Vec::new();
+
+    // A string claiming to be 4 GiB long swallows everything after it, up to the chunk
+    // that takes the pending bytes past 64 KiB...
+    let mut data = common::FrameBytes::new(0).u32(u32::MAX).bytes();
+    data.resize(65 * 1024, 0x55);
+    for chunk in data.chunks(1024) {
+        stream
+            .process_into(chunk, |record| records.push(record))
+            .unwrap();
+    }
+    // ...until it is given up on, and decoding starts over.
+    stream
+        .process_into(&common::FrameBytes::new(1).bytes(), |record| {
+            records.push(record)
+        })
+        .unwrap();
+
+    assert_eq!(stream.stats().malformed, 1);
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].message, "tick");
+}
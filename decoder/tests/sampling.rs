@@ -0,0 +1,96 @@
+mod common;
+
+use common::FrameBytes;
+use tracing_defmt_decoder::{RecordKind, Sampler, TraceDecoder, TraceRecord};
+
+fn table() -> defmt_decoder::Table {
+    common::table(
+        &[
+            ("Info", "span_enter: {=str}()"),
+            ("Info", "span_exit: {=str}"),
+            ("Info", "inside"),
+        ],
+        Some("{=u64:us}"),
+    )
+}
+
+/// Enters and exits span `name` at `timestamp`, logging an event inside.
+fn span(timestamp: u64, name: &str) -> Vec<u8> {
+    let mut data = FrameBytes::new(0).u64(timestamp).str(name).bytes();
+    data.extend(FrameBytes::new(2).u64(timestamp).bytes());
+    data.extend(FrameBytes::new(1).u64(timestamp).str(name).bytes());
+    data
+}
+
+fn decode(sampler: Sampler, data: &[u8]) -> Vec<TraceRecord> {
+    let decoder = TraceDecoder::builder()
+        .with_sampler(sampler)
+        .build_from_table(table(), common::locations(3))
+        .unwrap();
+    let mut records = Vec::new();
+    decoder
+        .new_stream()
+        .process_into(data, |record| records.push(record))
+        .unwrap();
+    records
+}
+
+fn sampling_rate(record: &TraceRecord) -> Option<String> {
+    record
+        .fields
+        .iter()
+        .find(|(key, _)| key == "sampling.rate")
+        .map(|(_, value)| value.clone())
+}
+
+/// `(name, sampling.rate)` of every entered span.
+fn sampled_spans(records: &[TraceRecord]) -> Vec<(String, Option<String>)> {
+    records
+        .iter()
+        .filter(|record| record.kind == RecordKind::SpanEnter)
+        .map(|record| (record.message.clone(), sampling_rate(record)))
+        .collect()
+}
+
+#[test]
+fn test_ratio_drops_whole_spans() {
+    let mut data = Vec::new();
+    for i in 0..4 {
+        data.extend(span(i * 1_000, "poll"));
+    }
+    data.extend(span(5_000, "idle"));
+
+    let records = decode(Sampler::new().with_ratio("poll", 0.5), &data);
+    assert_eq!(
+        sampled_spans(&records),
+        [
+            ("poll".to_string(), Some("2".to_string())),
+            ("poll".to_string(), Some("2".to_string())),
+            ("idle".to_string(), None),
+        ]
+    );
+    // Events inside dropped spans go with them.
+    assert_eq!(records.len(), 9);
+}
+
+#[test]
+fn test_rate_limit_uses_device_time() {
+    let mut data = Vec::new();
+    for timestamp in [0, 100_000, 500_000, 1_200_000] {
+        data.extend(span(timestamp, "radio_rx"));
+    }
+
+    let records = decode(Sampler::new().with_rate_limit("radio_rx", 1.0), &data);
+    let kept: Vec<(Option<u64>, Option<String>)> = records
+        .iter()
+        .filter(|record| record.kind == RecordKind::SpanEnter)
+        .map(|record| (record.timestamp, sampling_rate(record)))
+        .collect();
+    assert_eq!(
+        kept,
+        [
+            (Some(0), Some("1".to_string())),
+            (Some(1_200_000), Some("3".to_string())),
+        ]
+    );
+}
@@ -0,0 +1,56 @@
+mod common;
+
+use std::fs;
+use std::process::Command;
+
+use common::FrameBytes;
+use tracing_defmt_decoder::export::Exporter;
+use tracing_defmt_decoder::source::Semihosting;
+use tracing_defmt_decoder::{TraceDecoder, TraceRecord};
+
+/// Collects exported messages.
+struct Messages<'a>(&'a mut Vec<String>);
+
+impl Exporter for Messages<'_> {
+    fn export(&mut self, record: &TraceRecord) -> std::io::Result<()> {
+        self.0.push(record.message.clone());
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_emulator_output_and_exit_status() {
+    let table = common::table(&[("Info", "reading {=u32}")], None);
+    let decoder = TraceDecoder::builder()
+        .build_from_table(table, common::locations(1))
+        .unwrap();
+    let dir =
+        std::env::temp_dir().join(format!("tracing-defmt-semihosting-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let output = dir.join("stdout.bin");
+    let data = [
+        FrameBytes::new(0).u32(1).bytes(),
+        FrameBytes::new(0).u32(2).bytes(),
+    ]
+    .concat();
+    fs::write(&output, data).unwrap();
+
+    // Stands in for an emulator whose firmware exits with code 3.
+    let mut emulator = Command::new("sh");
+    emulator
+        .arg("-c")
+        .arg(format!("cat '{}'; exit 3", output.display()));
+    let mut messages = Vec::new();
+    let mut stream = decoder.new_stream();
+    stream.add_exporter(Messages(&mut messages));
+    let status = Semihosting::spawn(emulator)
+        .unwrap()
+        .run(&mut stream)
+        .unwrap();
+    drop(stream);
+
+    assert_eq!(status.and_then(|status| status.code()), Some(3));
+    assert_eq!(messages, ["reading 1", "reading 2"]);
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,98 @@
+mod common;
+
+use std::time::Duration;
+
+use common::FrameBytes;
+use tracing_defmt_decoder::{RecordKind, TraceDecoder, TraceRecord};
+
+fn decoder() -> TraceDecoder {
+    let table = common::table(
+        &[
+            ("Info", "span_enter: {=str}()"),
+            ("Info", "span_exit: {=str}"),
+            ("Warn", "retry {=u8}"),
+        ],
+        Some("{=u64:us}"),
+    );
+    TraceDecoder::builder()
+        .with_min_span_duration(Duration::from_micros(50))
+        .build_from_table(table, common::locations(3))
+        .unwrap()
+}
+
+fn enter(time: u64, name: &str) -> Vec<u8> {
+    FrameBytes::new(0).u64(time).str(name).bytes()
+}
+
+fn exit(time: u64, name: &str) -> Vec<u8> {
+    FrameBytes::new(1).u64(time).str(name).bytes()
+}
+
+fn summary(records: &[TraceRecord]) -> Vec<(RecordKind, &str, Option<u64>, Option<u64>)> {
+    records
+        .iter()
+        .map(|r| (r.kind, r.message.as_str(), r.span_id, r.parent_id))
+        .collect()
+}
+
+#[test]
+fn test_short_spans_are_dropped_and_their_events_kept() {
+    let data = [
+        enter(0, "poll"),
+        enter(10, "crc"),
+        FrameBytes::new(2).u64(12).u8(1).bytes(),
+        exit(15, "crc"),
+        enter(20, "flash_write"),
+        exit(200, "flash_write"),
+        exit(300, "poll"),
+    ]
+    .concat();
+
+    let mut records = Vec::new();
+    let mut stream = decoder().into_stream();
+    stream.process_into(&data, |r| records.push(r)).unwrap();
+
+    assert_eq!(
+        summary(&records),
+        [
+            (RecordKind::SpanEnter, "poll", Some(1), None),
+            // Moved up from `crc`.
+            (RecordKind::Event, "retry 1", Some(1), None),
+            (RecordKind::SpanEnter, "flash_write", Some(2), Some(1)),
+            (RecordKind::SpanExit, "flash_write", Some(2), Some(1)),
+            (RecordKind::SpanExit, "poll", Some(1), None),
+        ]
+    );
+}
+
+#[test]
+fn test_long_spans_are_released_before_they_exit() {
+    let mut records = Vec::new();
+    let mut stream = decoder().into_stream();
+    stream
+        .process_into(&[enter(0, "poll"), enter(10, "crc")].concat(), |r| {
+            records.push(r)
+        })
+        .unwrap();
+    assert!(records.is_empty());
+
+    stream
+        .process_into(&FrameBytes::new(2).u64(55).u8(2).bytes(), |r| {
+            records.push(r)
+        })
+        .unwrap();
+    // `poll` has lasted long enough, `crc` may still turn out short.
+    assert_eq!(
+        summary(&records),
+        [(RecordKind::SpanEnter, "poll", Some(1), None)]
+    );
+
+    stream.flush_into(|r| records.push(r));
+    assert_eq!(
+        summary(&records)[1..],
+        [
+            (RecordKind::SpanEnter, "crc", Some(2), Some(1)),
+            (RecordKind::Event, "retry 2", Some(2), None),
+        ]
+    );
+}
@@ -0,0 +1,109 @@
+mod common;
+
+use common::FrameBytes;
+use serde_json::json;
+use tracing_defmt_decoder::snapshot::{Normalizer, Snapshot, Timestamps};
+use tracing_defmt_decoder::TraceDecoder;
+
+fn capture() -> (TraceDecoder, Vec<u8>) {
+    let table = common::table(
+        &[
+            ("Info", "span_enter: handle_packet(len={=u32})"),
+            ("Warn", "checksum mismatch"),
+            ("Info", "span_exit: {=str}"),
+            ("Info", "counter: rx_packets={=u32}"),
+            ("Info", "span_enter: idle()"),
+        ],
+        Some("{=u64:us}"),
+    );
+    let decoder = TraceDecoder::builder()
+        .build_from_table(table, common::locations(5))
+        .unwrap();
+
+    let mut data = FrameBytes::new(0).u64(1_000).u32(12).bytes();
+    data.extend(FrameBytes::new(1).u64(1_150).bytes());
+    data.extend(FrameBytes::new(2).u64(1_250).str("handle_packet").bytes());
+    data.extend(FrameBytes::new(3).u64(1_300).u32(1).bytes());
+    data.extend(FrameBytes::new(4).u64(1_400).bytes());
+    (decoder, data)
+}
+
+#[test]
+fn test_text_snapshot_is_relative_and_indented() {
+    let (decoder, data) = capture();
+    let snapshot = Snapshot::decode(&decoder, &data).unwrap();
+    assert_eq!(
+        snapshot.to_string(),
+        "\
+handle_packet len=12 +0us 250us
+  WARN checksum mismatch +150us
+counter rx_packets value=1 +300us
+idle +400us (not exited)
+"
+    );
+
+    let snapshot = snapshot
+        .with_timestamps(Timestamps::Omitted)
+        .with_locations(true);
+    assert!(snapshot
+        .to_string()
+        .starts_with("handle_packet len=12 @ src/main.rs:10\n"));
+}
+
+#[test]
+fn test_json_snapshot_nests_children() {
+    let (decoder, data) = capture();
+    let snapshot = Snapshot::decode(&decoder, &data)
+        .unwrap()
+        .with_timestamps(Timestamps::Omitted);
+    assert_eq!(
+        snapshot.to_json()[0]["trace"][0],
+        json!({
+            "span": "handle_packet",
+            "fields": { "len": "12" },
+            "children": [{ "event": "checksum mismatch", "level": "WARN" }],
+        })
+    );
+}
+
+#[test]
+fn test_normalizer_makes_runs_comparable() {
+    let (decoder, data) = capture();
+    let mut first = decoder.decode_records(&data).unwrap();
+    let mut second = first.clone();
+    for (i, record) in second.iter_mut().enumerate() {
+        record.timestamp = record.timestamp.map(|t| t + 50_000 + (i as u64 % 2) * 20);
+        record.span_id = record.span_id.map(|id| id + 7);
+        record.parent_id = record.parent_id.map(|id| id + 7);
+    }
+    for record in first.iter_mut().chain(&mut second) {
+        if let Some(location) = &mut record.location {
+            location.file = "/home/ci/work/firmware/src/main.rs".into();
+        }
+    }
+    second[0].fields[0].1 = "13".to_string();
+
+    let normalizer = Normalizer::new()
+        .with_timestamps(Timestamps::Rounded(100))
+        .with_scrubbed_field("len");
+    let first = normalizer.normalize(&first);
+    assert_eq!(first, normalizer.normalize(&second));
+    assert_eq!(first[0].span_id, Some(1));
+    assert_eq!(
+        first[0].fields,
+        [("len".to_string(), "<scrubbed>".to_string())]
+    );
+    assert_eq!(first[1].timestamp, Some(200));
+    assert_eq!(
+        &*first[0].location.as_ref().unwrap().file,
+        "firmware/src/main.rs"
+    );
+
+    let stripped = Normalizer::new()
+        .with_path_prefix("/home/ci/work")
+        .normalize(&first[..1]);
+    assert_eq!(
+        &*stripped[0].location.as_ref().unwrap().file,
+        "firmware/src/main.rs"
+    );
+}
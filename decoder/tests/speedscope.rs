@@ -0,0 +1,77 @@
+mod common;
+
+use common::FrameBytes;
+use serde_json::{json, Value};
+use tracing_defmt_decoder::export::{Exporter, SpeedscopeWriter};
+use tracing_defmt_decoder::{TraceDecoder, TraceRecord};
+
+#[test]
+fn test_spans_become_evented_profiles_per_context() {
+    let table = common::table(
+        &[
+            ("Info", "span_enter: poll()"),
+            ("Debug", "span_enter: {=str}()"),
+            ("Warn", "inside"),
+            ("Info", "span_exit: {=str}"),
+        ],
+        Some("{=u64:us}"),
+    );
+    let decoder = TraceDecoder::builder()
+        .build_from_table(table, common::locations(4))
+        .unwrap();
+
+    let mut records: Vec<TraceRecord> = Vec::new();
+    let mut stream = decoder.new_stream();
+    let mut core0 = FrameBytes::new(0).u64(1_000).bytes();
+    core0.extend(FrameBytes::new(1).u64(1_200).str("read").bytes());
+    core0.extend(FrameBytes::new(2).u64(1_300).bytes());
+    core0.extend(FrameBytes::new(3).u64(1_500).str("read").bytes());
+    // `poll` stays open.
+    stream
+        .process_context_into(0, &core0, |record| records.push(record))
+        .unwrap();
+    let mut core1 = FrameBytes::new(0).u64(1_100).bytes();
+    core1.extend(FrameBytes::new(3).u64(1_400).str("poll").bytes());
+    stream
+        .process_context_into(1, &core1, |record| records.push(record))
+        .unwrap();
+
+    let mut writer = SpeedscopeWriter::new(Vec::new());
+    for record in &records {
+        writer.export(record).unwrap();
+    }
+    let profile: Value = serde_json::from_slice(&writer.finish().unwrap()).unwrap();
+
+    assert_eq!(
+        profile["shared"]["frames"],
+        json!([
+            { "name": "poll", "file": "src/main.rs", "line": 10 },
+            { "name": "read", "file": "src/main.rs", "line": 20 },
+        ])
+    );
+    let profiles = profile["profiles"].as_array().unwrap();
+    assert_eq!(profiles.len(), 2);
+    assert_eq!(profiles[0]["name"], "context 0");
+    assert_eq!(profiles[0]["unit"], "microseconds");
+    assert_eq!(
+        profiles[0]["events"],
+        json!([
+            { "type": "O", "frame": 0, "at": 1_000 },
+            { "type": "O", "frame": 1, "at": 1_200 },
+            { "type": "C", "frame": 1, "at": 1_500 },
+            // Closed at the end of the profile.
+            { "type": "C", "frame": 0, "at": 1_500 },
+        ])
+    );
+    assert_eq!(
+        (&profiles[0]["startValue"], &profiles[0]["endValue"]),
+        (&json!(1_000), &json!(1_500))
+    );
+    assert_eq!(
+        profiles[1]["events"],
+        json!([
+            { "type": "O", "frame": 0, "at": 1_100 },
+            { "type": "C", "frame": 0, "at": 1_400 },
+        ])
+    );
+}
@@ -0,0 +1,107 @@
+mod common;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use common::FrameBytes;
+use tracing::span;
+use tracing::Subscriber;
+use tracing_defmt_decoder::export::{Exporter, SpanSummary};
+use tracing_defmt_decoder::TraceDecoder;
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::Layer;
+
+fn table() -> defmt_decoder::Table {
+    common::table(
+        &[
+            ("Info", "span_enter: {=str}()"),
+            ("Info", "span_exit: {=str}"),
+            ("Warn", "write failed, error={=str}"),
+        ],
+        Some("{=u64:us}"),
+    )
+}
+
+/// Calls of `name` lasting `durations`, the second one failing.
+fn calls(name: &str, durations: &[u64]) -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut time = 0;
+    for (i, duration) in durations.iter().enumerate() {
+        data.extend(FrameBytes::new(0).u64(time).str(name).bytes());
+        if i == 1 {
+            data.extend(FrameBytes::new(2).u64(time).str("Timeout").bytes());
+        }
+        time += duration;
+        data.extend(FrameBytes::new(1).u64(time).str(name).bytes());
+    }
+    data
+}
+
+#[test]
+fn test_spans_are_aggregated_by_name() {
+    let decoder = TraceDecoder::builder()
+        .build_from_table(table(), common::locations(3))
+        .unwrap();
+    let mut durations: Vec<u64> = (1..=100).collect();
+    durations[99] = 5_000;
+
+    let mut summary = SpanSummary::new(Vec::new());
+    let mut stream = decoder.new_stream();
+    stream
+        .process_into(&calls("handle_rx", &durations), |record| {
+            summary.export(&record).unwrap()
+        })
+        .unwrap();
+
+    let stats = &summary.spans()["handle_rx"];
+    assert_eq!((stats.count, stats.errors), (100, 1));
+    assert_eq!((stats.min_us, stats.max_us), (1, 5_000));
+    assert_eq!(stats.mean_us(), (4_950 + 5_000) / 100);
+    assert_eq!(stats.percentile_us(0.5), 50);
+    assert_eq!(stats.percentile_us(0.99), 99);
+    assert_eq!(stats.percentile_us(1.0), 5_000);
+
+    let table = String::from_utf8(summary.finish().unwrap()).unwrap();
+    assert_eq!(
+        table.lines().collect::<Vec<_>>(),
+        [
+            "span          count  errors    min_us   mean_us    p99_us    max_us",
+            "handle_rx       100       1         1        99        99      5000",
+        ]
+    );
+}
+
+/// Counts the spans created in tracing.
+struct SpanCount(Arc<AtomicUsize>);
+
+impl<S: Subscriber> Layer<S> for SpanCount {
+    fn on_new_span(&self, _: &span::Attributes, _: &span::Id, _: Context<S>) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[test]
+fn test_summary_mode_emits_no_spans() {
+    let spans = Arc::new(AtomicUsize::new(0));
+    let subscriber = tracing_subscriber::registry().with(SpanCount(Arc::clone(&spans)));
+    let decoder = TraceDecoder::builder()
+        .with_dispatch(tracing::Dispatch::new(subscriber))
+        .with_summary(Duration::from_secs(60))
+        .build_from_table(table(), common::locations(3))
+        .unwrap();
+
+    let mut parents = Vec::new();
+    let mut stream = decoder.new_stream();
+    stream.process(&calls("poll", &[10, 20])).unwrap();
+    stream
+        .process_into(&calls("poll", &[10, 20]), |record| {
+            parents.push(record.span_id)
+        })
+        .unwrap();
+    stream.finish().unwrap();
+
+    assert_eq!(spans.load(Ordering::Relaxed), 0);
+    // Records are still placed in the span tree.
+    assert_eq!(parents, [Some(3), Some(3), Some(4), Some(4), Some(4)]);
+}
@@ -0,0 +1,82 @@
+#![cfg(feature = "syslog")]
+
+mod common;
+
+use std::net::UdpSocket;
+
+use common::FrameBytes;
+use tracing_defmt_decoder::export::SyslogWriter;
+use tracing_defmt_decoder::TraceDecoder;
+
+fn decoder() -> TraceDecoder {
+    let table = common::table(
+        &[
+            ("Info", "span_enter: poll()"),
+            ("Warn", "queue full, port={=u8}"),
+        ],
+        None,
+    );
+    TraceDecoder::builder()
+        .build_from_table(table, common::locations(2))
+        .unwrap()
+}
+
+fn frames() -> Vec<u8> {
+    let mut data = FrameBytes::new(0).bytes();
+    data.extend(FrameBytes::new(1).u8(3).bytes());
+    data
+}
+
+#[test]
+fn test_events_are_sent_as_rfc5424_messages() {
+    let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let decoder = decoder();
+    let mut stream = decoder.new_stream();
+    stream.add_exporter(
+        SyslogWriter::udp(server.local_addr().unwrap())
+            .unwrap()
+            .with_hostname("board 7")
+            .with_app_name("gateway"),
+    );
+    stream.process_context(1, &frames()).unwrap();
+
+    let mut buf = [0; 1024];
+    let len = server.recv(&mut buf).unwrap();
+    let message = std::str::from_utf8(&buf[..len]).unwrap();
+    // user.warning
+    assert!(message.starts_with("<12>1 20"), "{}", message);
+    assert!(
+        message.ends_with(
+            " board7 gateway - - [defmt@32473 file=\"src/main.rs\" line=\"20\" module=\"app\" \
+             context=\"1\" port=\"3\"] queue full"
+        ),
+        "{}",
+        message
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_events_are_written_to_the_journal() {
+    use std::os::unix::net::UnixDatagram;
+    use tracing_defmt_decoder::export::JournaldWriter;
+
+    let path = std::env::temp_dir().join(format!("tracing-defmt-journal-{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let journal = UnixDatagram::bind(&path).unwrap();
+    let decoder = decoder();
+    let mut stream = decoder.new_stream();
+    stream.add_exporter(JournaldWriter::connect(&path).unwrap());
+    stream.process_context(1, &frames()).unwrap();
+
+    let mut buf = [0; 1024];
+    let len = journal.recv(&mut buf).unwrap();
+    let entry = std::str::from_utf8(&buf[..len]).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(
+        entry,
+        "MESSAGE=queue full\nPRIORITY=4\nSYSLOG_IDENTIFIER=tracing-defmt\n\
+         CODE_FILE=src/main.rs\nCODE_LINE=20\nDEFMT_MODULE=app\nDEFMT_CONTEXT=1\n\
+         DEFMT_PORT=3\n"
+    );
+}
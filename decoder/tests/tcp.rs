@@ -0,0 +1,56 @@
+mod common;
+
+use std::io::{ErrorKind, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+
+use common::FrameBytes;
+use tracing_defmt_decoder::source::Tcp;
+use tracing_defmt_decoder::TraceDecoder;
+
+#[test]
+fn test_listener_decodes_each_connection() {
+    let table = common::table(&[("Info", "reading {=u32}")], None);
+    let decoder = TraceDecoder::builder()
+        .build_from_table(table, common::locations(1))
+        .unwrap();
+    let mut tcp = Tcp::listen("127.0.0.1:0").unwrap();
+    let addr = tcp.local_addr().unwrap();
+    assert_ne!(addr.port(), 0);
+    let device = thread::spawn(move || {
+        let mut data = FrameBytes::new(0).u32(1).bytes();
+        data.extend(FrameBytes::new(0).u32(2).bytes());
+        TcpStream::connect(addr).unwrap().write_all(&data).unwrap();
+        // The device comes back after a reset.
+        TcpStream::connect(addr)
+            .unwrap()
+            .write_all(&FrameBytes::new(0).u32(3).bytes())
+            .unwrap();
+    });
+
+    let mut stream = decoder.new_stream();
+    let mut closed = 0;
+    while closed < 2 {
+        if tcp.poll(&mut stream).unwrap() == 0 {
+            closed += 1;
+        }
+    }
+    device.join().unwrap();
+    stream.flush().unwrap();
+
+    assert_eq!(stream.stats().frames_decoded, 3);
+    assert_eq!(stream.stats().reconnects, 1);
+}
+
+#[test]
+fn test_connect_without_addresses_is_an_error() {
+    let addrs: &[SocketAddr] = &[];
+    let e = Tcp::connect(addrs).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidInput);
+}
+
+#[test]
+fn test_local_addr_is_only_known_when_listening() {
+    let tcp = Tcp::connect("127.0.0.1:19021").unwrap();
+    assert_eq!(tcp.local_addr(), None);
+}
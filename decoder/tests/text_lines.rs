@@ -0,0 +1,190 @@
+mod common;
+
+use std::io::Cursor;
+use std::sync::Arc;
+
+use common::FrameBytes;
+use tracing_defmt_decoder::export::Exporter;
+use tracing_defmt_decoder::source::TextLines;
+use tracing_defmt_decoder::{RecordKind, TraceDecoder, TraceRecord};
+
+/// Collects exported records.
+struct Records<'a>(&'a mut Vec<TraceRecord>);
+
+impl Exporter for Records<'_> {
+    fn export(&mut self, record: &TraceRecord) -> std::io::Result<()> {
+        self.0.push(record.clone());
+        Ok(())
+    }
+}
+
+fn run(log: &str) -> Vec<TraceRecord> {
+    let decoder = TraceDecoder::builder().build_for_text().unwrap();
+    let mut records = Vec::new();
+    let mut stream = decoder.new_stream();
+    stream.add_exporter(Records(&mut records));
+    TextLines::new(Cursor::new(log.to_string()))
+        .run(&mut stream)
+        .unwrap();
+    drop(stream);
+    records
+}
+
+#[test]
+fn test_defmt_print_text_rebuilds_spans() {
+    let log = "\
+0.000100 INFO  span_enter: handle_packet(len=12)
+\x1b[2m└─ app::net @ src/net.rs:40\x1b[0m
+0.000150 [WARN ] checksum mismatch
+└─ app::net @ src/net.rs:52
+0.000250 INFO  span_exit: handle_packet
+unstructured output
+";
+    let records = run(log);
+
+    let kinds: Vec<RecordKind> = records.iter().map(|r| r.kind).collect();
+    assert_eq!(
+        kinds,
+        [
+            RecordKind::SpanEnter,
+            RecordKind::Event,
+            RecordKind::SpanExit,
+            RecordKind::Event
+        ]
+    );
+    assert_eq!(records[0].message, "handle_packet");
+    assert_eq!(records[0].fields, [("len".to_string(), "12".to_string())]);
+    assert_eq!(records[0].timestamp, Some(100));
+    assert_eq!(records[1].level, Some(tracing::Level::WARN));
+    assert_eq!(records[1].message, "checksum mismatch");
+    assert_eq!(records[1].span_id, records[0].span_id);
+    let location = records[1].location.as_ref().unwrap();
+    assert_eq!((&*location.file, location.line), ("src/net.rs", 52));
+    assert_eq!(&*location.module, "app::net");
+    assert_eq!(records[3].level, None);
+    assert_eq!(records[3].message, "unstructured output");
+}
+
+#[test]
+fn test_defmt_json_lines() {
+    let log = r#"{"schema_version":1}
+{"data":"span_enter: boot","host_timestamp":0,"level":"INFO","location":{"file":"src/main.rs","line":7,"module_path":{"crate_name":"app","modules":["init"],"function":"boot"}},"target_timestamp":"5"}
+{"data":"counter: resets=1","host_timestamp":0,"level":null,"location":null,"target_timestamp":""}
+{"data":"span_exit: boot","host_timestamp":0,"level":"INFO","location":null,"target_timestamp":"9"}
+"#;
+    let records = run(log);
+
+    assert_eq!(records.len(), 3);
+    assert_eq!(records[0].kind, RecordKind::SpanEnter);
+    assert_eq!(records[0].timestamp, Some(5));
+    assert_eq!(&*records[0].location.as_ref().unwrap().module, "app::init");
+    assert_eq!(records[1].kind, RecordKind::Counter);
+    assert_eq!(records[1].span_id, records[0].span_id);
+    assert_eq!(records[2].kind, RecordKind::SpanExit);
+}
+
+#[test]
+fn test_malformed_timestamps_are_left_out() {
+    let log: String = [
+        "00:00:01.000250",
+        "99999999999999.0",
+        "1.12345\u{e9}",
+        "1.5x",
+        "1:1:1:1:1:1:1:1:1:1:1:1:1:1:1:1.0",
+        "213503983:00:00:00.000000",
+    ]
+    .iter()
+    .map(|timestamp| {
+        format!(
+            "{{\"data\":\"tick\",\"host_timestamp\":0,\"level\":\"INFO\",\"location\":null,\"target_timestamp\":\"{}\"}}\n",
+            timestamp
+        )
+    })
+    .collect();
+    let records = run(&log);
+
+    let timestamps: Vec<Option<u64>> = records.iter().map(|r| r.timestamp).collect();
+    assert_eq!(timestamps, [Some(1_000_250), None, None, None, None, None]);
+}
+
+#[test]
+fn test_text_decoder_rejects_raw_data() {
+    let decoder = TraceDecoder::builder().build_for_text().unwrap();
+    assert!(decoder.new_stream().process(&[1, 2, 3]).is_err());
+}
+
+#[test]
+fn test_frames_decoded_by_a_runner_rebuild_spans() {
+    let table = common::table(
+        &[
+            ("Info", "span_enter: send(len={=u32})"),
+            ("Warn", "retry"),
+            ("Info", "span_exit: {=str}"),
+        ],
+        Some("{=u64:us}"),
+    );
+    let locations = common::locations(3);
+    let decoder = TraceDecoder::builder().build_for_text().unwrap();
+    let mut records = Vec::new();
+    let mut stream = decoder.new_stream();
+    stream.add_exporter(Records(&mut records));
+
+    // The runner decodes the frames with its own table and hands them on.
+    let mut frames = table.new_stream_decoder();
+    frames.received(&FrameBytes::new(0).u64(10).u32(4).bytes());
+    frames.received(&FrameBytes::new(1).u64(20).bytes());
+    frames.received(&FrameBytes::new(2).u64(30).str("send").bytes());
+    while let Ok(frame) = frames.decode() {
+        stream
+            .process_frame(0, &frame, locations.get(&frame.index()))
+            .unwrap();
+    }
+    assert_eq!(stream.stats().frames_decoded, 3);
+    drop(stream);
+
+    let records: Vec<_> = records
+        .iter()
+        .map(|record| (record.kind, record.message.as_str(), record.timestamp))
+        .collect();
+    assert_eq!(
+        records,
+        [
+            (RecordKind::SpanEnter, "send", Some(10)),
+            (RecordKind::Event, "retry", Some(20)),
+            (RecordKind::SpanExit, "send", Some(30)),
+        ]
+    );
+}
+
+#[test]
+fn test_runner_locations_are_resolved_once_per_statement() {
+    let table = common::table(&[("Info", "tick {=u8}")], None);
+    let mut locations = common::locations(1);
+    let decoder = TraceDecoder::builder().build_for_text().unwrap();
+    let mut records = Vec::new();
+    let mut stream = decoder.new_stream();
+    stream.add_exporter(Records(&mut records));
+
+    let mut frames = table.new_stream_decoder();
+    for value in 0..3 {
+        frames.received(&FrameBytes::new(0).u8(value).bytes());
+        let frame = frames.decode().unwrap();
+        if value == 2 {
+            // Another firmware has another statement at the same index.
+            locations
+                .values_mut()
+                .for_each(|location| location.line = 99);
+        }
+        stream
+            .process_frame(0, &frame, locations.get(&frame.index()))
+            .unwrap();
+    }
+    drop(stream);
+
+    let locations: Vec<_> = records
+        .iter()
+        .map(|record| record.location.clone().unwrap())
+        .collect();
+    assert!(Arc::ptr_eq(&locations[0].file, &locations[1].file));
+    assert_eq!((locations[1].line, locations[2].line), (10, 99));
+}
@@ -0,0 +1,86 @@
+#![cfg(feature = "tui")]
+
+mod common;
+
+use common::FrameBytes;
+use tracing::level_filters::LevelFilter;
+use tracing_defmt_decoder::tui::{KeyAction, LiveView};
+use tracing_defmt_decoder::{ControlCommand, TraceDecoder};
+
+fn screen(view: &LiveView) -> String {
+    let mut out = Vec::new();
+    view.render(&mut out).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn test_live_view_shows_stack_search_and_pause() {
+    let table = common::table(
+        &[
+            ("Info", "span_enter: {=str}()"),
+            ("Warn", "queue full {=u32}"),
+            ("Info", "sent {=u32}"),
+        ],
+        None,
+    );
+    let decoder = TraceDecoder::builder()
+        .build_from_table(table, common::locations(3))
+        .unwrap();
+    let mut stream = decoder.new_stream();
+    let mut view = LiveView::new();
+
+    let mut data = FrameBytes::new(0).str("radio_task").bytes();
+    data.extend(FrameBytes::new(1).u32(7).bytes());
+    data.extend(FrameBytes::new(2).u32(1).bytes());
+    stream
+        .process_into(&data, |record| view.push(&record))
+        .unwrap();
+
+    let all = screen(&view);
+    assert!(all.contains("context 0: radio_task"));
+    assert!(all.contains("\x1b[33m[0] WARN  queue full 7"));
+    assert!(all.contains("sent 1"));
+
+    for key in b"/queue\r" {
+        assert_eq!(view.handle_key(*key), KeyAction::Redraw);
+    }
+    let filtered = screen(&view);
+    assert!(filtered.contains("search: queue"));
+    assert!(filtered.contains("queue full 7"));
+    assert!(!filtered.contains("sent 1"));
+
+    assert_eq!(view.handle_key(0x1b), KeyAction::Redraw);
+    assert_eq!(view.handle_key(b' '), KeyAction::Redraw);
+    stream
+        .process_into(&FrameBytes::new(2).u32(2).bytes(), |record| {
+            view.push(&record)
+        })
+        .unwrap();
+    assert!(!screen(&view).contains("sent 2"));
+    view.handle_key(b' ');
+    assert!(screen(&view).contains("sent 2"));
+    assert_eq!(view.handle_key(b'q'), KeyAction::Quit);
+}
+
+#[test]
+fn test_commands_are_entered_after_colon() {
+    let mut view = LiveView::new();
+    for key in b":set-filter app::net=debug" {
+        assert_eq!(view.handle_key(*key), KeyAction::Redraw);
+    }
+    assert!(screen(&view).contains(":set-filter app::net=debug"));
+    assert_eq!(view.handle_key(b'\r'), KeyAction::Command);
+    assert_eq!(
+        view.take_command(),
+        Some(ControlCommand::SetFilter {
+            target: Some("app::net".into()),
+            level: LevelFilter::DEBUG,
+        })
+    );
+
+    for key in b":set-filter loud\r" {
+        view.handle_key(*key);
+    }
+    assert_eq!(view.take_command(), None);
+    assert!(screen(&view).contains("invalid level"));
+}
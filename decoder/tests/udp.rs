@@ -0,0 +1,168 @@
+use std::net::UdpSocket;
+
+use tracing_defmt_decoder::source::{Chunk, FrameSource, SequenceHeader, Udp, UdpStats};
+
+/// What a datagram turned into: the bytes passed on, or the datagrams lost before it.
+#[derive(Debug, PartialEq)]
+enum Seen {
+    Data(Vec<u8>),
+    Lost(Option<u64>),
+}
+
+/// Sends `datagrams` to a [`Udp`] source with `header` over the loopback interface and
+/// returns what it passed on, and its statistics.
+fn receive(header: SequenceHeader, datagrams: &[Vec<u8>]) -> (Vec<Seen>, UdpStats) {
+    let mut udp = Udp::bind("127.0.0.1:0")
+        .unwrap()
+        .with_sequence_header(header);
+    let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+    for datagram in datagrams {
+        sender.send_to(datagram, udp.local_addr().unwrap()).unwrap();
+    }
+
+    let mut seen = Vec::new();
+    for _ in datagrams {
+        udp.read_chunks(&mut |chunk| {
+            seen.push(match chunk {
+                Chunk::Data { bytes, .. } => Seen::Data(bytes.to_vec()),
+                Chunk::Lost { frames } => Seen::Lost(frames),
+                chunk => panic!("unexpected {:?}", chunk),
+            });
+            Ok(())
+        })
+        .unwrap();
+    }
+    (seen, udp.stats())
+}
+
+/// A datagram with a `u32` sequence number and `payload`.
+fn datagram(sequence: u32, payload: &[u8]) -> Vec<u8> {
+    let mut datagram = sequence.to_le_bytes().to_vec();
+    datagram.extend_from_slice(payload);
+    datagram
+}
+
+#[test]
+fn test_sequence_gap_is_reported_as_lost_datagrams() {
+    let (seen, stats) = receive(
+        SequenceHeader::U32Le,
+        &[datagram(7, b"a"), datagram(8, b"b"), datagram(11, b"c")],
+    );
+    assert_eq!(
+        seen,
+        [
+            Seen::Data(b"a".to_vec()),
+            Seen::Data(b"b".to_vec()),
+            Seen::Lost(Some(2)),
+            Seen::Data(b"c".to_vec()),
+        ]
+    );
+    assert_eq!(
+        stats,
+        UdpStats {
+            received: 3,
+            lost: 2,
+            ..UdpStats::default()
+        }
+    );
+}
+
+#[test]
+fn test_sequence_wraps_around() {
+    let (seen, stats) = receive(
+        SequenceHeader::U32Le,
+        &[
+            datagram(u32::MAX - 1, b"a"),
+            datagram(u32::MAX, b"b"),
+            datagram(0, b"c"),
+            datagram(2, b"d"),
+        ],
+    );
+    assert_eq!(seen.len(), 5);
+    assert_eq!(seen[3], Seen::Lost(Some(1)));
+    assert_eq!((stats.received, stats.lost), (4, 1));
+
+    let u16_datagram = |sequence: u16| sequence.to_le_bytes().to_vec();
+    let (_, stats) = receive(
+        SequenceHeader::U16Le,
+        &[u16_datagram(u16::MAX), u16_datagram(0)],
+    );
+    assert_eq!((stats.received, stats.lost), (2, 0));
+}
+
+#[test]
+fn test_duplicate_and_late_datagrams_are_dropped() {
+    let (seen, stats) = receive(
+        SequenceHeader::U32Le,
+        &[
+            datagram(5, b"a"),
+            datagram(5, b"a"),
+            datagram(6, b"b"),
+            datagram(3, b"late"),
+        ],
+    );
+    assert_eq!(seen, [Seen::Data(b"a".to_vec()), Seen::Data(b"b".to_vec())]);
+    assert_eq!(
+        stats,
+        UdpStats {
+            received: 2,
+            out_of_order: 2,
+            ..UdpStats::default()
+        }
+    );
+}
+
+#[test]
+fn test_counter_reset_resynchronizes() {
+    let (seen, stats) = receive(
+        SequenceHeader::U32Le,
+        &[
+            datagram(1000, b"a"),
+            datagram(1001, b"b"),
+            // The gateway restarts.
+            datagram(0, b"c"),
+            datagram(1, b"d"),
+            datagram(1, b"d"),
+        ],
+    );
+    assert_eq!(
+        seen,
+        [
+            Seen::Data(b"a".to_vec()),
+            Seen::Data(b"b".to_vec()),
+            Seen::Lost(None),
+            Seen::Data(b"c".to_vec()),
+            Seen::Data(b"d".to_vec()),
+        ]
+    );
+    assert_eq!(
+        stats,
+        UdpStats {
+            received: 4,
+            out_of_order: 1,
+            resyncs: 1,
+            ..UdpStats::default()
+        }
+    );
+
+    // Just outside the reorder window, the same holds for 16-bit counters.
+    let u16_datagram = |sequence: u16| sequence.to_le_bytes().to_vec();
+    let (_, stats) = receive(
+        SequenceHeader::U16Le,
+        &[u16_datagram(100), u16_datagram(36), u16_datagram(35)],
+    );
+    assert_eq!(
+        (stats.received, stats.out_of_order, stats.resyncs),
+        (2, 1, 1)
+    );
+}
+
+#[test]
+fn test_truncated_header_is_counted() {
+    let (seen, stats) = receive(
+        SequenceHeader::U32Le,
+        &[vec![1, 0], datagram(0, b"a"), datagram(1, b"")],
+    );
+    assert_eq!(seen, [Seen::Data(b"a".to_vec()), Seen::Data(Vec::new())]);
+    assert_eq!((stats.truncated, stats.received, stats.lost), (1, 2, 0));
+}
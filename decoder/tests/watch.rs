@@ -0,0 +1,23 @@
+use std::fs;
+
+use tracing_defmt_decoder::ElfWatcher;
+
+#[test]
+fn test_watcher_reports_changed_contents_once() {
+    let path = std::env::temp_dir().join(format!("tracing-defmt-watch-{}.elf", std::process::id()));
+    fs::write(&path, b"first build").unwrap();
+
+    let mut watcher = ElfWatcher::new(&path);
+    assert_eq!(watcher.read().unwrap(), b"first build");
+    assert_eq!(watcher.poll().unwrap(), None);
+
+    fs::write(&path, b"second, longer build").unwrap();
+    assert_eq!(
+        watcher.poll().unwrap().as_deref(),
+        Some(&b"second, longer build"[..])
+    );
+    assert_eq!(watcher.poll().unwrap(), None);
+
+    fs::remove_file(&path).unwrap();
+    assert_eq!(watcher.poll().unwrap(), None);
+}
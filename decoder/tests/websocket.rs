@@ -0,0 +1,85 @@
+#![cfg(feature = "websocket")]
+
+mod common;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+
+use common::FrameBytes;
+use serde_json::Value;
+use tracing_defmt_decoder::export::WebSocketServer;
+use tracing_defmt_decoder::TraceDecoder;
+
+fn request(server: &WebSocketServer, request: &str) -> BufReader<TcpStream> {
+    let mut connection = TcpStream::connect(server.local_addr()).unwrap();
+    connection.write_all(request.as_bytes()).unwrap();
+    BufReader::new(connection)
+}
+
+#[test]
+fn test_records_are_streamed_to_websocket_clients() {
+    let table = common::table(&[("Warn", "queue full {=u32}")], None);
+    let decoder = TraceDecoder::builder()
+        .build_from_table(table, common::locations(1))
+        .unwrap();
+    let server = WebSocketServer::serve("127.0.0.1:0").unwrap();
+    let mut stream = decoder.new_stream();
+    stream.add_exporter(server.clone());
+
+    // The key and accept value of the example in RFC 6455.
+    let mut client = request(
+        &server,
+        "GET /ws HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\n\
+         Connection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+         Sec-WebSocket-Version: 13\r\n\r\n",
+    );
+    let mut response = String::new();
+    while !response.ends_with("\r\n\r\n") {
+        client.read_line(&mut response).unwrap();
+    }
+    assert!(response.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+    assert!(response.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+    while server.clients() == 0 {
+        thread::sleep(Duration::from_millis(1));
+    }
+
+    stream
+        .process_context(2, &FrameBytes::new(0).u32(7).bytes())
+        .unwrap();
+
+    let mut header = [0; 2];
+    client.read_exact(&mut header).unwrap();
+    assert_eq!(header[0], 0x81);
+    let mut len = header[1] as usize;
+    if len == 126 {
+        let mut extended = [0; 2];
+        client.read_exact(&mut extended).unwrap();
+        len = u16::from_be_bytes(extended) as usize;
+    }
+    let mut message = vec![0; len];
+    client.read_exact(&mut message).unwrap();
+    let record: Value = serde_json::from_slice(&message).unwrap();
+    assert_eq!(record["kind"], "event");
+    assert_eq!(record["level"], "WARN");
+    assert_eq!(record["message"], "queue full 7");
+    assert_eq!(record["context"], 2);
+}
+
+#[test]
+fn test_viewer_page_is_served() {
+    let server = WebSocketServer::serve("127.0.0.1:0").unwrap();
+    let mut response = String::new();
+    request(&server, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .read_to_string(&mut response)
+        .unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.contains("new WebSocket("));
+
+    let mut response = String::new();
+    request(&server, "GET /nope HTTP/1.1\r\n\r\n")
+        .read_to_string(&mut response)
+        .unwrap();
+    assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+}
@@ -0,0 +1,153 @@
+//! Reports how much flash `#[instrument]` costs per function, so changes to the macros
+//! can be weighed by their size as well as their speed.
+//!
+//! Every function below comes twice, as is and instrumented, with and without arguments
+//! recorded as fields. The report reads the symbol sizes from its own executable and
+//! prints the difference of each pair, plus the span name the exit frame carries in
+//! `.rodata` unless the names are hashed. Run it once per mode and compare:
+//!
+//! ```text
+//! DEFMT_LOG=info cargo run --release --example size_report
+//! DEFMT_LOG=info cargo run --release --example size_report --features hashed-names
+//! DEFMT_LOG=info cargo run --release --example size_report --features control
+//! ```
+//!
+//! Without `DEFMT_LOG`, defmt leaves out the frames of the `info` spans and the
+//! instrumented functions cost next to nothing.
+//!
+//! The sizes are those of host code, so only the differences between the modes carry
+//! over to a Cortex-M build. Format strings go to the `.defmt` section of the ELF, which
+//! is not flashed, and are not counted.
+
+use std::error::Error;
+use std::hint::black_box;
+
+use object::{Object, ObjectSymbol, SymbolKind};
+use tracing_defmt::instrument;
+
+// The bodies differ between pairs, so that the linker does not fold identical functions.
+#[inline(never)]
+fn plain_no_args() -> u32 {
+    black_box(7)
+}
+
+#[inline(never)]
+#[instrument]
+fn traced_no_args() -> u32 {
+    black_box(7)
+}
+
+#[inline(never)]
+fn plain_with_args(count: u32, port: u8) -> u32 {
+    black_box(count.wrapping_mul(u32::from(port)))
+}
+
+#[inline(never)]
+#[instrument]
+fn traced_with_args(count: u32, port: u8) -> u32 {
+    black_box(count.wrapping_mul(u32::from(port)))
+}
+
+#[inline(never)]
+fn plain_skipped_args(count: u32, port: u8) -> u32 {
+    black_box(count.wrapping_add(u32::from(port)))
+}
+
+#[inline(never)]
+#[instrument(skip(count, port))]
+fn traced_skipped_args(count: u32, port: u8) -> u32 {
+    black_box(count.wrapping_add(u32::from(port)))
+}
+
+/// The functions compared, as is and instrumented, and what they show.
+const PAIRS: [(&str, &str, &str); 3] = [
+    ("plain_no_args", "traced_no_args", "no arguments"),
+    ("plain_with_args", "traced_with_args", "two arguments"),
+    (
+        "plain_skipped_args",
+        "traced_skipped_args",
+        "two arguments, skipped",
+    ),
+];
+
+/// The bytes of code of function `name`, including those of the functions defined in it,
+/// such as the drop of its span guard.
+fn code_size(file: &object::File, name: &str) -> u64 {
+    // Legacy mangling prefixes each path segment with its length.
+    let segment = format!("{}{}", name.len(), name);
+    let nested = format!("..{}..", name);
+    file.symbols()
+        .filter(|symbol| symbol.kind() == SymbolKind::Text)
+        .filter(|symbol| {
+            symbol
+                .name()
+                .is_ok_and(|symbol| symbol.contains(&segment) || symbol.contains(&nested))
+        })
+        .map(|symbol| symbol.size())
+        .sum()
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    // Calls keep the functions from being left out of the executable.
+    black_box(plain_no_args() + traced_no_args());
+    black_box(plain_with_args(2, 3) + traced_with_args(2, 3));
+    black_box(plain_skipped_args(2, 3) + traced_skipped_args(2, 3));
+
+    if option_env!("DEFMT_LOG").is_none() {
+        eprintln!("DEFMT_LOG was not set for the build, so the spans log nothing");
+    }
+    let data = std::fs::read(std::env::current_exe()?)?;
+    let file = object::File::parse(&*data)?;
+
+    let names = if cfg!(feature = "hashed-names") {
+        "hashed"
+    } else {
+        "strings"
+    };
+    println!(
+        "span names: {}, runtime filter: {}",
+        names,
+        if cfg!(feature = "control") {
+            "on"
+        } else {
+            "off"
+        }
+    );
+    println!(
+        "{:<24} {:>8} {:>8} {:>8} {:>8}",
+        "function", "plain", "traced", "code", "name"
+    );
+    for (plain, traced, description) in PAIRS {
+        let (plain_size, traced_size) = (code_size(&file, plain), code_size(&file, traced));
+        if plain_size == 0 || traced_size == 0 {
+            return Err(format!("no symbols for {}, was the example stripped?", plain).into());
+        }
+        let name = if cfg!(feature = "hashed-names") {
+            0
+        } else {
+            traced.len()
+        };
+        println!(
+            "{:<24} {:>8} {:>8} {:>+8} {:>8}",
+            description,
+            plain_size,
+            traced_size,
+            traced_size as i64 - plain_size as i64,
+            name
+        );
+    }
+    Ok(())
+}
+
+// Stubs to satisfy the linker when running examples on host
+#[unsafe(no_mangle)]
+fn _defmt_acquire() {}
+
+#[unsafe(no_mangle)]
+fn _defmt_release() {}
+
+#[unsafe(no_mangle)]
+fn _defmt_write(_bytes: &[u8]) {}
+
+#[unsafe(no_mangle)]
+fn _defmt_timestamp(_fmt: tracing_defmt::defmt::Formatter<'_>) {}
@@ -4,8 +4,9 @@ use syn::{
     parse::{Parse, ParseStream},
     parse_macro_input,
     punctuated::Punctuated,
-    Expr, ExprLit, FnArg, Ident, ItemFn, Lit, LitStr, Meta, Pat, Token,
+    Block, Expr, ExprLit, FnArg, Ident, ItemFn, Lit, LitStr, Meta, Pat, Token,
 };
+use tracing_defmt_wire as wire;
 
 // =============================================================================
 // #[instrument]
@@ -16,14 +17,33 @@ use syn::{
 /// This is a facade for `tracing::instrument`.
 ///
 /// # Arguments
-/// * `level` - The log level to use. Defaults to info.
+/// * `level` - The log level to use. Defaults to info. `"off"` leaves the function as it
+///   is, without a span.
 /// * `name` - Sets the name of the span. Defaults to the function name.
 /// * `skip` - A list of arguments to skip logging.
+/// * `instance` - An expression that tells apart spans of the same name, e.g. a channel
+///   index. The decoder appends it to the span name: `uart_task[2]`. An argument called
+///   `instance` does the same.
+/// * `fields` - Extra fields of the span, `key = value`. Keys may be dotted, and string
+///   literals go into the format string. `otel.kind = "client"` sets the OpenTelemetry
+///   span kind, which the decoder defaults to internal.
 ///
 /// # Example
-/// ```rust
+/// ```rust,no_run
+/// # use tracing_defmt::instrument;
+/// # struct Uart;
+/// # impl Uart { fn index(&self) -> u8 { 2 } }
+/// # struct Config { retries: u8 }
 /// #[instrument(level = "debug", skip(y))]
-/// fn my_fn(x: u32, y: u32) { ... }
+/// fn my_fn(x: u32, y: u32) -> u32 {
+///     x + y
+/// }
+///
+/// #[instrument(instance = uart.index())]
+/// fn uart_task(uart: &Uart) {}
+///
+/// #[instrument(fields(otel.kind = "client", retries = cfg.retries))]
+/// fn radio_tx(cfg: &Config) {}
 /// ```
 #[proc_macro_attribute]
 pub fn instrument(args: TokenStream, item: TokenStream) -> TokenStream {
@@ -36,6 +56,8 @@ pub fn instrument(args: TokenStream, item: TokenStream) -> TokenStream {
     let mut level = "info".to_string();
     let mut name = fn_name_str.clone();
     let mut skip = Vec::new();
+    let mut instance = None;
+    let mut extra_fields = Vec::new();
 
     // Parse attributes
     for meta in args_parsed {
@@ -55,80 +77,271 @@ pub fn instrument(args: TokenStream, item: TokenStream) -> TokenStream {
                     {
                         name = lit.value();
                     }
+                } else if nv.path.is_ident(wire::INSTANCE_FIELD) {
+                    instance = Some(nv.value);
                 }
             }
-            Meta::List(list) => {
-                if list.path.is_ident("skip") {
-                    let nested_ids = list
-                        .parse_args_with(Punctuated::<Ident, Token![,]>::parse_terminated)
-                        .unwrap_or_default();
-                    for id in nested_ids {
-                        skip.push(id.to_string());
-                    }
+            Meta::List(list) if list.path.is_ident("fields") => {
+                match list.parse_args_with(Punctuated::<FieldArg, Token![,]>::parse_terminated) {
+                    Ok(fields) => extra_fields.extend(fields),
+                    Err(err) => return err.to_compile_error().into(),
+                }
+            }
+            Meta::List(list) if list.path.is_ident("skip") => {
+                let nested_ids = list
+                    .parse_args_with(Punctuated::<Ident, Token![,]>::parse_terminated)
+                    .unwrap_or_default();
+                for id in nested_ids {
+                    skip.push(id.to_string());
                 }
             }
             _ => {}
         }
     }
 
-    let macro_path = level_to_macro_path(&level);
-
-    // Build format string and arguments
-    // We prefix with "span_enter: " to make it easily parsable for host tools
-    let mut fmt_str = String::from("span_enter: ");
-    fmt_str.push_str(&name);
-
-    let mut log_args = Vec::new();
-    let mut first = true;
-    let mut has_args = false;
+    if level == "off" {
+        return TokenStream::from(quote!(#item_fn));
+    }
 
+    let mut fields = Vec::new();
     for input in &item_fn.sig.inputs {
         if let FnArg::Typed(pat_type) = input {
             if let Pat::Ident(pat_ident) = &*pat_type.pat {
                 let arg_name = pat_ident.ident.to_string();
                 if !skip.contains(&arg_name) {
-                    if first {
-                        fmt_str.push_str("(");
-                        first = false;
-                    } else {
-                        fmt_str.push_str(", ");
-                    }
-                    fmt_str.push_str(&arg_name);
-                    fmt_str.push_str("={}");
                     let ident = &pat_ident.ident;
-                    log_args.push(quote!(#ident));
-                    has_args = true;
+                    fields.push((arg_name, FieldValue::Arg(quote!(#ident))));
                 }
             }
         }
     }
-
-    if has_args {
-        fmt_str.push(')');
+    fields.extend(
+        extra_fields
+            .into_iter()
+            .map(|field| (field.key, field.value)),
+    );
+    if let Some(instance) = instance {
+        fields.push((
+            wire::INSTANCE_FIELD.to_string(),
+            FieldValue::Arg(quote!(#instance)),
+        ));
     }
 
     let block = &item_fn.block;
     let attrs = &item_fn.attrs;
     let vis = &item_fn.vis;
     let sig = &item_fn.sig;
+    let is_async = item_fn.sig.asyncness.is_some();
+    let body = span_scope(&level, &name, &fields, quote!(#block), is_async);
 
     let expanded = quote! {
         #(#attrs)*
         #vis #sig {
+            #body
+        }
+    };
+
+    TokenStream::from(expanded)
+}
+
+/// The statements that enter the span `name` with `fields`, exit it when the scope ends,
+/// and then evaluate `body`.
+///
+/// The `body` of an async function runs in a future of its own, so the span is cancelled
+/// instead of exited when the future is dropped before it completes.
+fn span_scope(
+    level: &str,
+    name: &str,
+    fields: &[(String, FieldValue)],
+    body: proc_macro2::TokenStream,
+    is_async: bool,
+) -> proc_macro2::TokenStream {
+    let macro_path = level_to_macro_path(level);
+
+    // Build format string and arguments
+    // We prefix with "span_enter: " to make it easily parsable for host tools
+    let mut fmt_str = String::from(wire::SPAN_ENTER);
+    fmt_str.push_str(name);
+    let mut log_args = Vec::new();
+    for (i, (key, value)) in fields.iter().enumerate() {
+        if i == 0 {
+            fmt_str.push(wire::FIELDS_START);
+        } else {
+            fmt_str.push_str(wire::FIELD_SEPARATOR);
+        }
+        push_field(&mut fmt_str, &mut log_args, key, value);
+    }
+    if !fields.is_empty() {
+        fmt_str.push(wire::FIELDS_END);
+    }
+
+    // With hashed names, the name is only in the format string of the enter frame, which
+    // stays in the ELF.
+    let end_frame = |prefix: &str| {
+        if cfg!(feature = "hashed-names") {
+            let fmt_str = format!("{}{}{{=u32:x}}", prefix, wire::NAME_HASH);
+            let hash = wire::name_hash(name);
+            quote!(#macro_path!(#fmt_str, #hash);)
+        } else {
+            let fmt_str = format!("{}{{}}", prefix);
+            quote!(#macro_path!(#fmt_str, #name);)
+        }
+    };
+    // We emit "span_exit: name" to allow matching exit events
+    let exit = end_frame(wire::SPAN_EXIT);
+
+    let (done_field, done_init, end, body) = if is_async {
+        let cancel = end_frame(wire::SPAN_CANCEL);
+        (
+            quote!(done: bool,),
+            quote!(done: false,),
+            quote!(if self.done { #exit } else { #cancel }),
+            quote! {
+                let __defmt_value = async move #body.await;
+                _guard.done = true;
+                __defmt_value
+            },
+        )
+    } else {
+        (quote!(), quote!(), exit, body)
+    };
+    let guard = if is_async {
+        quote!(mut _guard)
+    } else {
+        quote!(_guard)
+    };
+
+    match enabled_check(level) {
+        // The filter is checked once, so a span that was entered is also exited.
+        Some(enabled) => quote! {
+            let __defmt_span_enabled = #enabled;
+            if __defmt_span_enabled {
+                #macro_path!(#fmt_str, #(#log_args),*);
+            }
+            struct DefmtInstrumentGuard {
+                enabled: bool,
+                #done_field
+            }
+            impl Drop for DefmtInstrumentGuard {
+                fn drop(&mut self) {
+                    if self.enabled {
+                        #end
+                    }
+                }
+            }
+            let #guard = DefmtInstrumentGuard {
+                enabled: __defmt_span_enabled,
+                #done_init
+            };
+            #body
+        },
+        None => quote! {
             #macro_path!(#fmt_str, #(#log_args),*);
-            struct DefmtInstrumentGuard;
+            struct DefmtInstrumentGuard {
+                #done_field
+            }
             impl Drop for DefmtInstrumentGuard {
                 fn drop(&mut self) {
-                    // We emit "span_exit: name" to allow matching exit events
-                    #macro_path!("span_exit: {}", #name);
+                    #end
                 }
             }
-            let _guard = DefmtInstrumentGuard;
-            #block
+            let #guard = DefmtInstrumentGuard { #done_init };
+            #body
+        },
+    }
+}
+
+// =============================================================================
+// with_span!
+// =============================================================================
+
+struct WithSpanArgs {
+    placement: Placement,
+    level_expr: Expr,
+    level: String,
+    name: LitStr,
+    fields: Vec<(String, Expr)>,
+    body: Block,
+}
+
+impl Parse for WithSpanArgs {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut placement = Placement::default();
+        while placement.parse_one(input)? {}
+        let level_expr: Expr = input.parse()?;
+        let level = match &level_expr {
+            Expr::Path(path) => path.path.segments.last().map(|s| s.ident.to_string()),
+            _ => None,
         }
-    };
+        .map(|level| level.to_lowercase())
+        .filter(|level| ["trace", "debug", "info", "warn", "error"].contains(&level.as_str()))
+        .ok_or_else(|| {
+            syn::Error::new_spanned(&level_expr, "expected a level, e.g. `Level::INFO`")
+        })?;
+        input.parse::<Token![,]>()?;
+        let name = input.parse()?;
+        input.parse::<Token![,]>()?;
 
-    TokenStream::from(expanded)
+        let mut fields = Vec::new();
+        while !input.peek(syn::token::Brace) {
+            let key: Ident = input.parse()?;
+            input.parse::<Token![=]>()?;
+            fields.push((key.to_string(), input.parse()?));
+            input.parse::<Token![,]>()?;
+        }
+        let body = input.parse()?;
+        if input.peek(Token![,]) {
+            input.parse::<Token![,]>()?;
+        }
+        Ok(WithSpanArgs {
+            placement,
+            level_expr,
+            level,
+            name,
+            fields,
+            body,
+        })
+    }
+}
+
+/// Enters a span around a block, and evaluates to the value of the block.
+///
+/// For instrumenting a region of a long function without moving it into an
+/// `#[instrument]`ed function of its own. Takes the level, the span name and its fields,
+/// optionally preceded by `target:` and `parent:` like `span!`.
+///
+/// # Example
+/// ```rust,ignore
+/// let sent = with_span!(Level::INFO, "dma_xfer", chan = 1, {
+///     dma.start(&buf);
+///     dma.wait()
+/// });
+/// with_span!(target: "radio", parent: None, Level::DEBUG, "rx_isr", { ... });
+/// ```
+#[proc_macro]
+pub fn with_span(input: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(input as WithSpanArgs);
+    let mut fields: Vec<_> = args
+        .fields
+        .iter()
+        .map(|(key, value)| (key.clone(), FieldValue::of(value)))
+        .collect();
+    fields.extend(args.placement.fields());
+    let body = &args.body;
+    let scope = span_scope(
+        &args.level,
+        &args.name.value(),
+        &fields,
+        quote!(#body),
+        false,
+    );
+    // The level is read from the tokens, but still type checked.
+    let level = &args.level_expr;
+    quote!({
+        let _: ::tracing_defmt::Level = #level;
+        #scope
+    })
+    .into()
 }
 
 // =============================================================================
@@ -136,6 +349,7 @@ pub fn instrument(args: TokenStream, item: TokenStream) -> TokenStream {
 // =============================================================================
 
 struct LogArgs {
+    placement: Placement,
     fields: Vec<(String, Expr)>,
     fmt_str: Option<LitStr>,
     fmt_args: Vec<Expr>,
@@ -143,6 +357,7 @@ struct LogArgs {
 
 impl Parse for LogArgs {
     fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut placement = Placement::default();
         let mut fields = Vec::new();
         let mut fmt_str = None;
         let mut fmt_args = Vec::new();
@@ -162,8 +377,11 @@ impl Parse for LogArgs {
                 continue;
             }
 
-            // 2. Check for target: target: value (ignore)
-            if input.peek(Ident) && input.peek2(Token![:]) {
+            // 2. Check for target: value and parent: value, other keys are ignored
+            if placement.parse_one(input)? {
+                continue;
+            }
+            if input.peek(Ident) && input.peek2(Token![:]) && !input.peek2(Token![::]) {
                 let _key: Ident = input.parse()?;
                 let _colon: Token![:] = input.parse()?;
                 let _val: Expr = input.parse()?;
@@ -206,6 +424,7 @@ impl Parse for LogArgs {
         }
 
         Ok(LogArgs {
+            placement,
             fields,
             fmt_str,
             fmt_args,
@@ -223,30 +442,35 @@ fn impl_log_macro(level: &str, args: TokenStream) -> TokenStream {
         String::new()
     };
 
-    let mut final_args = args.fmt_args;
+    let final_args = args.fmt_args;
 
     // Append fields to format string
     // defmt doesn't support structured fields disjoint from the message.
     // We append them: "msg, key={}, key2={}"
+    let fields = args
+        .fields
+        .into_iter()
+        .map(|(key, val)| (key, FieldValue::of(&val)))
+        .chain(args.placement.fields());
+    let mut final_args: Vec<_> = final_args.iter().map(|arg| quote!(#arg)).collect();
     let mut first = true;
-    for (key, val) in args.fields {
+    for (key, val) in fields {
         if first {
             if !final_fmt_str.is_empty() {
-                final_fmt_str.push_str(", ");
+                final_fmt_str.push_str(wire::FIELD_SEPARATOR);
             }
             first = false;
         } else {
-            final_fmt_str.push_str(", ");
+            final_fmt_str.push_str(wire::FIELD_SEPARATOR);
         }
-        final_fmt_str.push_str(&key);
-        final_fmt_str.push_str("={}");
-        final_args.push(val);
+        push_field(&mut final_fmt_str, &mut final_args, &key, &val);
     }
 
-    quote! {
-        #macro_path!(#final_fmt_str, #(#final_args),*)
+    let log = quote!(#macro_path!(#final_fmt_str, #(#final_args),*));
+    match enabled_check(level) {
+        Some(enabled) => quote!(if #enabled { #log }).into(),
+        None => log.into(),
     }
-    .into()
 }
 
 #[proc_macro]
@@ -278,6 +502,168 @@ pub fn error(input: TokenStream) -> TokenStream {
 // Helpers
 // =============================================================================
 
+/// The value of a field in a format string.
+enum FieldValue {
+    /// An argument, formatted with `{}`.
+    Arg(proc_macro2::TokenStream),
+    /// Text known when the macro expands, which goes into the format string as is.
+    Text(String),
+    /// A `defmt::intern!(..)` string, formatted with `{=istr}` so the decoder knows the
+    /// field is a string.
+    Interned(proc_macro2::TokenStream),
+}
+
+impl FieldValue {
+    fn of(expr: &Expr) -> Self {
+        match unwrap_group(expr) {
+            Expr::Macro(mac)
+                if mac
+                    .mac
+                    .path
+                    .segments
+                    .last()
+                    .is_some_and(|s| s.ident == "intern") =>
+            {
+                FieldValue::Interned(quote!(#expr))
+            }
+            _ => FieldValue::Arg(quote!(#expr)),
+        }
+    }
+}
+
+/// One `key = value` of `#[instrument(fields(...))]`, whose key may be dotted.
+struct FieldArg {
+    key: String,
+    value: FieldValue,
+}
+
+impl Parse for FieldArg {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut key = input.parse::<Ident>()?.to_string();
+        while input.peek(Token![.]) {
+            input.parse::<Token![.]>()?;
+            key.push('.');
+            key.push_str(&input.parse::<Ident>()?.to_string());
+        }
+        input.parse::<Token![=]>()?;
+        let value = match input.parse()? {
+            Expr::Lit(ExprLit {
+                lit: Lit::Str(lit), ..
+            }) => FieldValue::Text(lit.value()),
+            value => FieldValue::of(&value),
+        };
+        Ok(FieldArg { key, value })
+    }
+}
+
+/// Appends `key=value` to a format string and its arguments.
+fn push_field(
+    fmt_str: &mut String,
+    args: &mut Vec<proc_macro2::TokenStream>,
+    key: &str,
+    value: &FieldValue,
+) {
+    fmt_str.push_str(key);
+    fmt_str.push(wire::KEY_VALUE_SEPARATOR);
+    match value {
+        FieldValue::Arg(arg) => {
+            fmt_str.push_str("{}");
+            args.push(arg.clone());
+        }
+        FieldValue::Interned(arg) => {
+            fmt_str.push_str("{=istr}");
+            args.push(arg.clone());
+        }
+        FieldValue::Text(text) => fmt_str.push_str(&text.replace('{', "{{").replace('}', "}}")),
+    }
+}
+
+/// The `target:` and `parent:` of an event or span, which are sent as fields.
+#[derive(Default)]
+struct Placement {
+    target: Option<Expr>,
+    parent: Option<Expr>,
+}
+
+impl Placement {
+    /// Parses `target: <expr>` or `parent: <expr>` and the comma after it, if `input`
+    /// starts with one.
+    fn parse_one(&mut self, input: ParseStream) -> syn::Result<bool> {
+        let fork = input.fork();
+        let key = match fork.parse::<Ident>() {
+            Ok(key) if fork.peek(Token![:]) && !fork.peek(Token![::]) => key,
+            _ => return Ok(false),
+        };
+        let slot = if key == "target" {
+            &mut self.target
+        } else if key == "parent" {
+            &mut self.parent
+        } else {
+            return Ok(false);
+        };
+        input.parse::<Ident>()?;
+        input.parse::<Token![:]>()?;
+        *slot = Some(input.parse()?);
+        if input.peek(Token![,]) {
+            input.parse::<Token![,]>()?;
+        }
+        Ok(true)
+    }
+
+    /// Literal targets and `parent: None` go into the format string, so they cost
+    /// nothing on the device.
+    fn fields(&self) -> Vec<(String, FieldValue)> {
+        let mut fields = Vec::new();
+        if let Some(target) = &self.target {
+            let value = match unwrap_group(target) {
+                Expr::Lit(ExprLit {
+                    lit: Lit::Str(lit), ..
+                }) => FieldValue::Text(lit.value()),
+                _ => FieldValue::Arg(quote!(#target)),
+            };
+            fields.push((wire::TARGET_FIELD.to_string(), value));
+        }
+        if let Some(parent) = &self.parent {
+            let value = match unwrap_group(parent) {
+                Expr::Path(path) if path.path.is_ident("None") => {
+                    FieldValue::Text(wire::NO_PARENT.to_string())
+                }
+                _ => FieldValue::Arg(quote!(::tracing_defmt::AsParent::parent_name(&(#parent)))),
+            };
+            fields.push((wire::PARENT_FIELD.to_string(), value));
+        }
+        fields
+    }
+}
+
+/// `expr` without the invisible group around an expression passed on by `macro_rules!`.
+fn unwrap_group(expr: &Expr) -> &Expr {
+    match expr {
+        Expr::Group(group) => unwrap_group(&group.expr),
+        Expr::Paren(paren) => unwrap_group(&paren.expr),
+        _ => expr,
+    }
+}
+
+/// Whether the runtime filter lets `level` through at the call site; `None` without
+/// the `control` feature.
+fn enabled_check(level: &str) -> Option<proc_macro2::TokenStream> {
+    if !cfg!(feature = "control") {
+        return None;
+    }
+    let level = match level {
+        "trace" => quote!(Trace),
+        "debug" => quote!(Debug),
+        "warn" => quote!(Warn),
+        "error" => quote!(Error),
+        _ => quote!(Info),
+    };
+    Some(quote!(::tracing_defmt::control::enabled(
+        ::tracing_defmt::Level::#level,
+        module_path!()
+    )))
+}
+
 fn level_to_macro_path(level: &str) -> proc_macro2::TokenStream {
     match level {
         "trace" => quote!(defmt::trace),
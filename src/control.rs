@@ -0,0 +1,285 @@
+//! Runtime log filtering, adjusted by the host over a down channel.
+//!
+//! With the `control` feature, every event and `#[instrument]` span first checks
+//! [`enabled`] against the filter set here, so trace-level logging can be turned on for
+//! one module of a deployed device without reflashing it. The filter can only narrow
+//! what was compiled in: build with `DEFMT_LOG=trace` and start with a lower
+//! [`set_default_level`] to have trace logs at hand, or start with
+//! [`LevelFilter::OFF`] to keep the device quiet until the host asks for logs.
+//!
+//! # Protocol
+//!
+//! The host sends ASCII command lines, each terminated by `\n` (a `\r` before it is
+//! ignored), over an RTT down channel or a serial port:
+//!
+//! - `set-filter <level>` sets the level of all targets without a filter of their own.
+//! - `set-filter <target>=<level>` sets the level of a module path and the modules
+//!   below it, e.g. `set-filter app::net=trace`. The most specific target wins.
+//! - `reset-filter` removes the target filters and restores the default level.
+//! - `sync <nonce>` is answered with a `time_sync: <nonce>` frame, whose timestamp lets
+//!   the host estimate the offset and drift of the device clock from the round trip.
+//!
+//! `<level>` is one of `off`, `error`, `warn`, `info`, `debug` and `trace`. Lines are
+//! at most [`MAX_LINE`] bytes long, and at most [`MAX_TARGETS`] targets have a filter.
+//! Every other command is answered with a `control: ...` log frame, which says whether
+//! it was applied.
+//!
+//! # Example
+//! ```rust,ignore
+//! tracing_defmt::control::set_default_level(Some(Level::Info));
+//! loop {
+//!     tracing_defmt::control::poll(|buf| down_channel.read(buf));
+//!     // ...
+//! }
+//! ```
+
+use core::sync::atomic::{AtomicBool, AtomicU8, AtomicU32, Ordering};
+
+use crate::{Level, LevelFilter};
+
+/// Most targets with a filter of their own.
+pub const MAX_TARGETS: usize = 8;
+/// Longest command line, without its line ending. Longer lines are rejected.
+pub const MAX_LINE: usize = 64;
+
+/// Level value of a filter that lets nothing through; levels count up from 1.
+const OFF: u8 = 0;
+/// Level value of an unused target slot.
+const UNUSED: u8 = u8::MAX;
+
+/// Level of the targets without a filter of their own.
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Trace as u8);
+/// What `reset-filter` returns [`LEVEL`] to.
+static DEFAULT_LEVEL: AtomicU8 = AtomicU8::new(Level::Trace as u8);
+static HAS_TARGETS: AtomicBool = AtomicBool::new(false);
+static TARGETS: [Target; MAX_TARGETS] = [const { Target::new() }; MAX_TARGETS];
+
+/// The filter of one target, identified by the hash of its module path. Only plain
+/// loads and stores are used, so this works on cores without compare-and-swap.
+struct Target {
+    hash: AtomicU32,
+    level: AtomicU8,
+}
+
+impl Target {
+    const fn new() -> Self {
+        Self {
+            hash: AtomicU32::new(0),
+            level: AtomicU8::new(UNUSED),
+        }
+    }
+}
+
+/// Sets the level the device starts with, which `reset-filter` returns to. Takes a
+/// [`Level`], an `Option<Level>` or a [`LevelFilter`]; `None` and [`LevelFilter::OFF`]
+/// turn logging off.
+pub fn set_default_level(level: impl Into<LevelFilter>) {
+    let level = level.into().into_level().map_or(OFF, |level| level as u8);
+    DEFAULT_LEVEL.store(level, Ordering::Relaxed);
+    LEVEL.store(level, Ordering::Relaxed);
+}
+
+/// Whether an event or span of `level` in module `module_path` passes the filter.
+pub fn enabled(level: Level, module_path: &str) -> bool {
+    let mut max = LEVEL.load(Ordering::Relaxed);
+    if HAS_TARGETS.load(Ordering::Relaxed) {
+        // Checks every `::` boundary of the module path; the longest match comes last.
+        let bytes = module_path.as_bytes();
+        let mut hash = FNV_OFFSET;
+        for (i, &byte) in bytes.iter().enumerate() {
+            if byte == b':' && bytes.get(i + 1) == Some(&b':') {
+                max = target_level(hash).unwrap_or(max);
+            }
+            hash = fnv1a(hash, byte);
+        }
+        max = target_level(hash).unwrap_or(max);
+    }
+    level as u8 <= max
+}
+
+fn target_level(hash: u32) -> Option<u8> {
+    TARGETS.iter().find_map(|target| {
+        let level = target.level.load(Ordering::Relaxed);
+        (level != UNUSED && target.hash.load(Ordering::Relaxed) == hash).then_some(level)
+    })
+}
+
+const FNV_OFFSET: u32 = 0x811c_9dc5;
+
+fn fnv1a(hash: u32, byte: u8) -> u32 {
+    (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193)
+}
+
+/// Why a command line was not applied.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, defmt::Format)]
+pub enum CommandError {
+    /// The line is not a command of the protocol.
+    Unknown,
+    /// The level is not one of `off`, `error`, `warn`, `info`, `debug` and `trace`.
+    InvalidLevel,
+    /// All [`MAX_TARGETS`] target filters are in use.
+    TooManyTargets,
+    /// The line is longer than [`MAX_LINE`].
+    TooLong,
+}
+
+/// Applies one filter command line, without its line ending. `sync` is only answered
+/// by [`Control`].
+pub fn execute(line: &str) -> Result<(), CommandError> {
+    let line = line.trim();
+    if line == "reset-filter" {
+        HAS_TARGETS.store(false, Ordering::Relaxed);
+        for target in &TARGETS {
+            target.level.store(UNUSED, Ordering::Relaxed);
+        }
+        LEVEL.store(DEFAULT_LEVEL.load(Ordering::Relaxed), Ordering::Relaxed);
+        return Ok(());
+    }
+    let filter = line
+        .strip_prefix("set-filter ")
+        .ok_or(CommandError::Unknown)?
+        .trim();
+    match filter.split_once('=') {
+        Some((target, level)) => set_target(target.trim(), parse_level(level.trim())?),
+        None => {
+            LEVEL.store(parse_level(filter)?, Ordering::Relaxed);
+            Ok(())
+        }
+    }
+}
+
+fn set_target(target: &str, level: u8) -> Result<(), CommandError> {
+    if target.is_empty() {
+        return Err(CommandError::Unknown);
+    }
+    let hash = target.bytes().fold(FNV_OFFSET, fnv1a);
+    let slot = TARGETS
+        .iter()
+        .find(|slot| {
+            slot.level.load(Ordering::Relaxed) != UNUSED
+                && slot.hash.load(Ordering::Relaxed) == hash
+        })
+        .or_else(|| {
+            TARGETS
+                .iter()
+                .find(|slot| slot.level.load(Ordering::Relaxed) == UNUSED)
+        })
+        .ok_or(CommandError::TooManyTargets)?;
+    slot.hash.store(hash, Ordering::Relaxed);
+    slot.level.store(level, Ordering::Relaxed);
+    HAS_TARGETS.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+fn parse_level(level: &str) -> Result<u8, CommandError> {
+    let level = match level {
+        "off" => return Ok(OFF),
+        "error" => Level::Error,
+        "warn" => Level::Warn,
+        "info" => Level::Info,
+        "debug" => Level::Debug,
+        "trace" => Level::Trace,
+        _ => return Err(CommandError::InvalidLevel),
+    };
+    Ok(level as u8)
+}
+
+/// Assembles command lines from the bytes of a down channel and applies them.
+///
+/// Use this directly when [`poll`] is not available (on cores without compare-and-swap)
+/// or when commands arrive from more than one channel.
+pub struct Control {
+    line: [u8; MAX_LINE],
+    len: usize,
+    /// Set while the rest of a line that did not fit is skipped.
+    overflow: bool,
+}
+
+impl Default for Control {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Control {
+    pub const fn new() -> Self {
+        Self {
+            line: [0; MAX_LINE],
+            len: 0,
+            overflow: false,
+        }
+    }
+
+    /// Reads from the down channel until `read` returns 0, which it should do when no
+    /// more bytes are waiting, and applies every complete command line.
+    pub fn poll(&mut self, mut read: impl FnMut(&mut [u8]) -> usize) {
+        let mut buf = [0u8; 16];
+        loop {
+            let n = read(&mut buf);
+            if n == 0 {
+                break;
+            }
+            for &byte in &buf[..n.min(buf.len())] {
+                self.push(byte);
+            }
+        }
+    }
+
+    /// Adds one byte, applying the line it completes.
+    pub fn push(&mut self, byte: u8) {
+        if byte != b'\n' {
+            if self.len < MAX_LINE {
+                self.line[self.len] = byte;
+                self.len += 1;
+            } else {
+                self.overflow = true;
+            }
+            return;
+        }
+        let len = core::mem::take(&mut self.len);
+        let result = if core::mem::take(&mut self.overflow) {
+            Err(CommandError::TooLong)
+        } else {
+            core::str::from_utf8(&self.line[..len])
+                .map_err(|_| CommandError::Unknown)
+                .and_then(apply)
+        };
+        if let Err(e) = result {
+            defmt::warn!("control: command rejected: {}", e);
+        }
+    }
+}
+
+/// Applies one command line and answers it, with the `time_sync: ` and `control: `
+/// frames of `tracing-defmt-wire` (defmt format strings have to be literals).
+fn apply(line: &str) -> Result<(), CommandError> {
+    let line = line.trim();
+    if let Some(nonce) = line.strip_prefix("sync ") {
+        // Logged right away, so the frame's timestamp is as close to the request as
+        // possible.
+        let nonce: u32 = nonce.trim().parse().map_err(|_| CommandError::Unknown)?;
+        defmt::info!("time_sync: {=u32}", nonce);
+        return Ok(());
+    }
+    execute(line)?;
+    defmt::info!("control: {=str}", line);
+    Ok(())
+}
+
+/// Applies the commands waiting on the down channel, see [`Control::poll`]. Call it
+/// regularly, e.g. from the main loop or a low-priority task.
+///
+/// A call made while another one is running, e.g. from an interrupt, returns right away.
+#[cfg(target_has_atomic = "8")]
+pub fn poll(read: impl FnMut(&mut [u8]) -> usize) {
+    static BUSY: AtomicBool = AtomicBool::new(false);
+    static mut CONTROL: Control = Control::new();
+
+    if BUSY.swap(true, Ordering::Acquire) {
+        return;
+    }
+    // SAFETY: `BUSY` gives this call exclusive access until it is released below.
+    let control = unsafe { &mut *core::ptr::addr_of_mut!(CONTROL) };
+    control.poll(read);
+    BUSY.store(false, Ordering::Release);
+}
@@ -0,0 +1,177 @@
+//! Crash reports for Cortex-M faults.
+//!
+//! A fault handler logs the registers the core stacked, the fault status registers and
+//! the return addresses found on the stack as one `crash: ` frame. The decoder
+//! symbolicates the addresses against the ELF and adds a `crash` span to the trace, with
+//! the faulting function, a `reason` decoded from the fault status and a `backtrace`
+//! field, under the span that was running when the fault hit.
+//!
+//! With `cortex-m-rt`, the `crash` feature provides [`log_hard_fault`], which does all
+//! of it. Other fault handlers can log the frame with [`log_crash`].
+//!
+//! # Example
+//! ```rust,ignore
+//! #[cortex_m_rt::exception]
+//! unsafe fn HardFault(frame: &cortex_m_rt::ExceptionFrame) -> ! {
+//!     tracing_defmt::crash::log_hard_fault(frame as *const _ as *const _);
+//!     loop {
+//!         cortex_m::asm::bkpt();
+//!     }
+//! }
+//! ```
+//!
+//! # Backtraces
+//!
+//! Firmware is rarely built with frame pointers or unwind tables, so the return
+//! addresses are guessed: every word of the stack above the exception frame that is a
+//! Thumb address (bit 0 set) inside the code counts. Stale return addresses of calls that
+//! already returned show up too, and the list is cut at [`MAX_BACKTRACE`] entries. The
+//! program counter and link register of the faulting code are exact.
+
+use core::ops::Range;
+
+/// Most return addresses a crash frame carries.
+pub const MAX_BACKTRACE: usize = 16;
+
+/// The registers the core pushes on the stack on exception entry, in the order it does,
+/// like `cortex_m_rt::ExceptionFrame`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ExceptionFrame {
+    pub r0: u32,
+    pub r1: u32,
+    pub r2: u32,
+    pub r3: u32,
+    pub r12: u32,
+    pub lr: u32,
+    pub pc: u32,
+    pub xpsr: u32,
+}
+
+impl ExceptionFrame {
+    /// The stack pointer of the faulting code: the address above this frame, including
+    /// the alignment word the core inserted if `xpsr` says so. The floating-point
+    /// context of an extended frame is not accounted for.
+    pub fn stack_pointer(&self) -> u32 {
+        let frame = self as *const Self as usize as u32;
+        let aligned = self.xpsr & (1 << 9) != 0;
+        frame + core::mem::size_of::<Self>() as u32 + if aligned { 4 } else { 0 }
+    }
+}
+
+/// The fault status and address registers of the System Control Block. Cores without
+/// them (Armv6-M, Armv8-M Baseline) leave them zero.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct FaultStatus {
+    /// Configurable Fault Status Register: MemManage, BusFault and UsageFault causes.
+    pub cfsr: u32,
+    /// HardFault Status Register.
+    pub hfsr: u32,
+    /// MemManage Fault Address Register, valid if `cfsr` says so.
+    pub mmfar: u32,
+    /// BusFault Address Register, valid if `cfsr` says so.
+    pub bfar: u32,
+}
+
+impl FaultStatus {
+    /// Reads the registers.
+    ///
+    /// # Safety
+    /// Only on Armv7-M and Armv8-M Mainline cores, which have them.
+    #[cfg(all(target_arch = "arm", target_os = "none"))]
+    pub unsafe fn read() -> Self {
+        let read = |address: usize| unsafe { core::ptr::read_volatile(address as *const u32) };
+        Self {
+            cfsr: read(0xE000_ED28),
+            hfsr: read(0xE000_ED2C),
+            mmfar: read(0xE000_ED34),
+            bfar: read(0xE000_ED38),
+        }
+    }
+}
+
+/// Collects the words of `stack` that look like return addresses, i.e. Thumb addresses
+/// inside `code`, into `out`, innermost first. Returns how many were found.
+pub fn return_addresses(stack: &[u32], code: Range<u32>, out: &mut [u32]) -> usize {
+    let mut count = 0;
+    for &word in stack {
+        if count == out.len() {
+            break;
+        }
+        if word & 1 == 1 && code.contains(&word) {
+            out[count] = word;
+            count += 1;
+        }
+    }
+    count
+}
+
+/// Logs the `crash: ` frame of a fault: its name (e.g. `HardFault`), the registers the
+/// core stacked, the stack pointer of the faulting code, the fault status and the return
+/// addresses found on the stack, of which the first [`MAX_BACKTRACE`] are sent.
+///
+/// Crash frames are logged at error level, whatever the runtime filter of the `control`
+/// feature says.
+pub fn log_crash(
+    fault: &str,
+    frame: &ExceptionFrame,
+    sp: u32,
+    status: &FaultStatus,
+    backtrace: &[u32],
+) {
+    defmt::error!(
+        "crash: {=str}, pc={=u32:#x}, lr={=u32:#x}, sp={=u32:#x}, xpsr={=u32:#x}, r0={=u32:#x}, r1={=u32:#x}, r2={=u32:#x}, r3={=u32:#x}, r12={=u32:#x}, cfsr={=u32:#x}, hfsr={=u32:#x}, mmfar={=u32:#x}, bfar={=u32:#x}, backtrace={=[?]:#x}",
+        fault,
+        frame.pc,
+        frame.lr,
+        sp,
+        frame.xpsr,
+        frame.r0,
+        frame.r1,
+        frame.r2,
+        frame.r3,
+        frame.r12,
+        status.cfsr,
+        status.hfsr,
+        status.mmfar,
+        status.bfar,
+        &backtrace[..backtrace.len().min(MAX_BACKTRACE)],
+    );
+}
+
+/// Logs the crash frame of a HardFault from the exception frame `cortex-m-rt` passes to
+/// the handler, reading the fault status registers and scanning the stack between the
+/// faulting code's stack pointer and `_stack_start` for return addresses into
+/// `__stext..__etext`.
+///
+/// # Safety
+/// `frame` must point to the exception frame of the fault, on an Armv7-M or Armv8-M
+/// Mainline core, in firmware linked with the `cortex-m-rt` linker script.
+#[cfg(all(feature = "crash", target_arch = "arm", target_os = "none"))]
+pub unsafe fn log_hard_fault(frame: *const ExceptionFrame) {
+    unsafe extern "C" {
+        static _stack_start: u32;
+        static __stext: u32;
+        static __etext: u32;
+    }
+
+    let frame = unsafe { &*frame };
+    let sp = frame.stack_pointer();
+    let (stack_start, code) = unsafe {
+        (
+            core::ptr::addr_of!(_stack_start) as u32,
+            core::ptr::addr_of!(__stext) as u32..core::ptr::addr_of!(__etext) as u32,
+        )
+    };
+    // A stack overflow may leave the stack pointer outside the stack.
+    let stack: &[u32] = match stack_start.checked_sub(sp) {
+        Some(len) if sp % 4 == 0 => unsafe {
+            core::slice::from_raw_parts(sp as *const u32, len as usize / 4)
+        },
+        _ => &[],
+    };
+    let mut backtrace = [0; MAX_BACKTRACE];
+    let count = return_addresses(stack, code, &mut backtrace);
+    let status = unsafe { FaultStatus::read() };
+    log_crash("HardFault", frame, sp, &status, &backtrace[..count]);
+}
@@ -6,9 +6,39 @@
 //! This allows using `defmt`'s efficient logging with code written for `tracing` (mostly).
 
 pub use defmt;
-pub use tracing_defmt_macros::{debug, error, info, instrument, trace, warn};
+pub use tracing_defmt_macros::{debug, error, info, instrument, trace, warn, with_span};
 
-/// Wrapper types to support `tracing::field::debug` and `tracing::field::display`.
+pub mod control;
+pub mod crash;
+pub mod memory;
+pub mod reset;
+
+/// The macros, types and traits most code needs, so ported code can replace
+/// `use tracing::...` with `use tracing_defmt::prelude::*;`.
+pub mod prelude {
+    pub use crate::field;
+    pub use crate::{Instrument, Level, Span};
+    pub use crate::{debug, error, event, info, instrument, trace, warn, with_span};
+    pub use crate::{debug_span, error_span, info_span, span, trace_span, warn_span};
+}
+
+/// The wire protocol version of the frames logged through this crate. The decoder reads
+/// it from the ELF and refuses firmware that speaks a newer protocol than it knows.
+///
+/// Linking with `--gc-sections` may drop the unreferenced symbol; keep it with
+/// `-C link-arg=--undefined=_TRACING_DEFMT_VERSION`. Without it, the decoder assumes the
+/// first version.
+#[used]
+#[unsafe(no_mangle)]
+static _TRACING_DEFMT_VERSION: u32 = tracing_defmt_wire::VERSION;
+
+/// Wrapper types to support `tracing::field::debug` and `tracing::field::display`, and
+/// to request a defmt display hint per field (`hex`, `bin`, `us`, `ms`), which the host
+/// decoder renders.
+///
+/// With the `heapless` feature, `heapless::String` and `heapless::Vec` can be logged as
+/// fields directly, `hex` and `bin` take a `heapless::Vec<u8, N>` (sent as bytes rather
+/// than a list), and `len` logs the length of `spsc` queues.
 pub mod field {
     /// A wrapper that implements `defmt::Format` using `core::fmt::Debug`.
     pub struct DebugValue<T>(pub T);
@@ -39,6 +69,271 @@ pub mod field {
     pub fn display<T>(t: T) -> DisplayValue<T> {
         DisplayValue(t)
     }
+
+    /// Integers and byte slices that can be formatted in hex or binary.
+    pub trait Radix {
+        fn format_hex(&self, fmt: defmt::Formatter);
+        fn format_bin(&self, fmt: defmt::Formatter);
+    }
+
+    /// Integer counts of microseconds or milliseconds, and with the `fugit` feature, `fugit`
+    /// durations and instants (since their epoch), converted from their tick rate.
+    pub trait Ticks {
+        fn format_us(&self, fmt: defmt::Formatter);
+        fn format_ms(&self, fmt: defmt::Formatter);
+    }
+
+    macro_rules! impl_radix {
+        ($($ty:ty => $hex:literal, $bin:literal;)*) => {$(
+            impl Radix for $ty {
+                fn format_hex(&self, fmt: defmt::Formatter) {
+                    defmt::write!(fmt, $hex, self)
+                }
+
+                fn format_bin(&self, fmt: defmt::Formatter) {
+                    defmt::write!(fmt, $bin, self)
+                }
+            }
+        )*};
+    }
+
+    impl_radix! {
+        u8 => "{=u8:x}", "{=u8:b}";
+        u16 => "{=u16:x}", "{=u16:b}";
+        u32 => "{=u32:x}", "{=u32:b}";
+        u64 => "{=u64:x}", "{=u64:b}";
+        u128 => "{=u128:x}", "{=u128:b}";
+        usize => "{=usize:x}", "{=usize:b}";
+        i8 => "{=i8:x}", "{=i8:b}";
+        i16 => "{=i16:x}", "{=i16:b}";
+        i32 => "{=i32:x}", "{=i32:b}";
+        i64 => "{=i64:x}", "{=i64:b}";
+        i128 => "{=i128:x}", "{=i128:b}";
+        isize => "{=isize:x}", "{=isize:b}";
+        [u8] => "{=[u8]:x}", "{=[u8]:b}";
+    }
+
+    impl<const N: usize> Radix for [u8; N] {
+        fn format_hex(&self, fmt: defmt::Formatter) {
+            self[..].format_hex(fmt)
+        }
+
+        fn format_bin(&self, fmt: defmt::Formatter) {
+            self[..].format_bin(fmt)
+        }
+    }
+
+    impl<T: Radix + ?Sized> Radix for &T {
+        fn format_hex(&self, fmt: defmt::Formatter) {
+            (**self).format_hex(fmt)
+        }
+
+        fn format_bin(&self, fmt: defmt::Formatter) {
+            (**self).format_bin(fmt)
+        }
+    }
+
+    macro_rules! impl_ticks {
+        ($($ty:ty => $us:literal, $ms:literal;)*) => {$(
+            impl Ticks for $ty {
+                fn format_us(&self, fmt: defmt::Formatter) {
+                    defmt::write!(fmt, $us, self)
+                }
+
+                fn format_ms(&self, fmt: defmt::Formatter) {
+                    defmt::write!(fmt, $ms, self)
+                }
+            }
+        )*};
+    }
+
+    impl_ticks! {
+        u8 => "{=u8:us}", "{=u8:ms}";
+        u16 => "{=u16:us}", "{=u16:ms}";
+        u32 => "{=u32:us}", "{=u32:ms}";
+        u64 => "{=u64:us}", "{=u64:ms}";
+        usize => "{=usize:us}", "{=usize:ms}";
+    }
+
+    impl<T: Ticks + ?Sized> Ticks for &T {
+        fn format_us(&self, fmt: defmt::Formatter) {
+            (**self).format_us(fmt)
+        }
+
+        fn format_ms(&self, fmt: defmt::Formatter) {
+            (**self).format_ms(fmt)
+        }
+    }
+
+    #[cfg(feature = "fugit")]
+    macro_rules! impl_fugit_ticks {
+        ($($ty:ty),*) => {$(
+            impl<const NOM: u32, const DENOM: u32> Ticks for fugit::Duration<$ty, NOM, DENOM> {
+                fn format_us(&self, fmt: defmt::Formatter) {
+                    // Widened first, so the conversion cannot overflow the tick type.
+                    let wide = fugit::Duration::<u64, NOM, DENOM>::from_ticks(self.ticks().into());
+                    wide.to_micros().format_us(fmt)
+                }
+
+                fn format_ms(&self, fmt: defmt::Formatter) {
+                    let wide = fugit::Duration::<u64, NOM, DENOM>::from_ticks(self.ticks().into());
+                    wide.to_millis().format_ms(fmt)
+                }
+            }
+
+            impl<const NOM: u32, const DENOM: u32> Ticks for fugit::Instant<$ty, NOM, DENOM> {
+                fn format_us(&self, fmt: defmt::Formatter) {
+                    self.duration_since_epoch().format_us(fmt)
+                }
+
+                fn format_ms(&self, fmt: defmt::Formatter) {
+                    self.duration_since_epoch().format_ms(fmt)
+                }
+            }
+        )*};
+    }
+
+    #[cfg(feature = "fugit")]
+    impl_fugit_ticks!(u32, u64);
+
+    /// A wrapper that implements `defmt::Format` with the `:x` display hint.
+    pub struct HexValue<T>(pub T);
+
+    impl<T: Radix> defmt::Format for HexValue<T> {
+        fn format(&self, fmt: defmt::Formatter) {
+            self.0.format_hex(fmt)
+        }
+    }
+
+    /// Wraps an integer or bytes to be shown in hex, e.g. `info!(reg = field::hex(v))`.
+    pub fn hex<T: Radix>(t: T) -> HexValue<T> {
+        HexValue(t)
+    }
+
+    /// A wrapper that implements `defmt::Format` with the `:b` display hint.
+    pub struct BinValue<T>(pub T);
+
+    impl<T: Radix> defmt::Format for BinValue<T> {
+        fn format(&self, fmt: defmt::Formatter) {
+            self.0.format_bin(fmt)
+        }
+    }
+
+    /// Wraps an integer or bytes to be shown in binary.
+    pub fn bin<T: Radix>(t: T) -> BinValue<T> {
+        BinValue(t)
+    }
+
+    /// A wrapper that implements `defmt::Format` with the `:us` display hint.
+    pub struct MicrosValue<T>(pub T);
+
+    impl<T: Ticks> defmt::Format for MicrosValue<T> {
+        fn format(&self, fmt: defmt::Formatter) {
+            self.0.format_us(fmt)
+        }
+    }
+
+    /// Wraps a count of microseconds, or a `fugit` duration or instant, to be shown as
+    /// seconds, e.g. `1.500000`.
+    pub fn us<T: Ticks>(t: T) -> MicrosValue<T> {
+        MicrosValue(t)
+    }
+
+    /// A wrapper that implements `defmt::Format` with the `:ms` display hint.
+    pub struct MillisValue<T>(pub T);
+
+    impl<T: Ticks> defmt::Format for MillisValue<T> {
+        fn format(&self, fmt: defmt::Formatter) {
+            self.0.format_ms(fmt)
+        }
+    }
+
+    /// Wraps a count of milliseconds, or a `fugit` duration or instant, to be shown as
+    /// seconds, e.g. `1.500`.
+    pub fn ms<T: Ticks>(t: T) -> MillisValue<T> {
+        MillisValue(t)
+    }
+
+    #[cfg(feature = "heapless")]
+    impl<const N: usize> Radix for heapless::Vec<u8, N> {
+        fn format_hex(&self, fmt: defmt::Formatter) {
+            self.as_slice().format_hex(fmt)
+        }
+
+        fn format_bin(&self, fmt: defmt::Formatter) {
+            self.as_slice().format_bin(fmt)
+        }
+    }
+
+    /// Collections whose number of elements can be logged with [`len`].
+    #[cfg(feature = "heapless")]
+    pub trait Len {
+        fn len(&self) -> usize;
+
+        fn is_empty(&self) -> bool {
+            self.len() == 0
+        }
+    }
+
+    #[cfg(feature = "heapless")]
+    impl<T, const N: usize> Len for heapless::spsc::Queue<T, N> {
+        fn len(&self) -> usize {
+            heapless::spsc::Queue::len(self)
+        }
+    }
+
+    #[cfg(feature = "heapless")]
+    impl<T, const N: usize> Len for heapless::spsc::Producer<'_, T, N> {
+        fn len(&self) -> usize {
+            heapless::spsc::Producer::len(self)
+        }
+    }
+
+    #[cfg(feature = "heapless")]
+    impl<T, const N: usize> Len for heapless::spsc::Consumer<'_, T, N> {
+        fn len(&self) -> usize {
+            heapless::spsc::Consumer::len(self)
+        }
+    }
+
+    #[cfg(feature = "heapless")]
+    impl<T, const N: usize> Len for heapless::Vec<T, N> {
+        fn len(&self) -> usize {
+            self.as_slice().len()
+        }
+    }
+
+    #[cfg(feature = "heapless")]
+    impl<const N: usize> Len for heapless::String<N> {
+        fn len(&self) -> usize {
+            self.as_str().len()
+        }
+    }
+
+    #[cfg(feature = "heapless")]
+    impl<T: Len + ?Sized> Len for &T {
+        fn len(&self) -> usize {
+            (**self).len()
+        }
+    }
+
+    /// A wrapper that implements `defmt::Format` with the length of a collection.
+    #[cfg(feature = "heapless")]
+    pub struct LenValue<T>(pub T);
+
+    #[cfg(feature = "heapless")]
+    impl<T: Len> defmt::Format for LenValue<T> {
+        fn format(&self, fmt: defmt::Formatter) {
+            defmt::write!(fmt, "{=usize}", self.0.len())
+        }
+    }
+
+    /// Wraps a `heapless` collection, e.g. an `spsc` queue or one of its ends, to log
+    /// how many elements it holds: `debug!(rx_pending = field::len(&consumer))`.
+    #[cfg(feature = "heapless")]
+    pub fn len<T: Len>(t: T) -> LenValue<T> {
+        LenValue(t)
+    }
 }
 
 /// Describes the level of verbosity of a span or event.
@@ -64,12 +359,54 @@ impl Level {
     pub const ERROR: Self = Self::Error;
 }
 
+/// The most verbose [`Level`] let through, or none at all with [`LevelFilter::OFF`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct LevelFilter(Option<Level>);
+
+impl LevelFilter {
+    /// Lets nothing through.
+    pub const OFF: Self = Self(None);
+    pub const ERROR: Self = Self(Some(Level::Error));
+    pub const WARN: Self = Self(Some(Level::Warn));
+    pub const INFO: Self = Self(Some(Level::Info));
+    pub const DEBUG: Self = Self(Some(Level::Debug));
+    pub const TRACE: Self = Self(Some(Level::Trace));
+
+    /// The most verbose level let through; `None` for [`LevelFilter::OFF`].
+    pub const fn into_level(self) -> Option<Level> {
+        self.0
+    }
+}
+
+impl From<Level> for LevelFilter {
+    fn from(level: Level) -> Self {
+        Self(Some(level))
+    }
+}
+
+impl From<Option<Level>> for LevelFilter {
+    fn from(level: Option<Level>) -> Self {
+        Self(level)
+    }
+}
+
+/// Where `tracing` keeps [`LevelFilter`].
+pub mod level_filters {
+    pub use crate::LevelFilter;
+}
+
 // Initial placeholder for `event!` which tracing uses extensively.
+// `target:` and `parent:` are passed on to the level macros, which send them as fields.
 #[macro_export]
 macro_rules! event {
+    (target: $target:expr, parent: $parent:expr, $lvl:expr, $($args:tt)*) => {
+        $crate::event!($lvl, target: $target, parent: $parent, $($args)*)
+    };
     (target: $target:expr, $lvl:expr, $($args:tt)*) => {
-        // We currently ignore target
-        $crate::event!($lvl, $($args)*)
+        $crate::event!($lvl, target: $target, $($args)*)
+    };
+    (parent: $parent:expr, $lvl:expr, $($args:tt)*) => {
+        $crate::event!($lvl, parent: $parent, $($args)*)
     };
     ($lvl:expr, $($args:tt)*) => {
         match $lvl {
@@ -82,60 +419,326 @@ macro_rules! event {
     };
 }
 
-/// A dummy Span to satisfy the tracing API.
+/// Logs a defmt frame at a [`Level`] known only at runtime.
+macro_rules! log_at {
+    ($level:expr, $($args:tt)*) => {
+        match $level {
+            Level::Error => defmt::error!($($args)*),
+            Level::Warn => defmt::warn!($($args)*),
+            Level::Info => defmt::info!($($args)*),
+            Level::Debug => defmt::debug!($($args)*),
+            Level::Trace => defmt::trace!($($args)*),
+        }
+    };
+}
+
+/// A span made by `span!`, logged as a `span_enter` frame when it is entered and a
+/// `span_exit` frame when the guard is dropped, like the spans of `#[instrument]`.
 ///
-/// `defmt` does not natively support nested spans with attached key-value pairs
-/// in the same way `tracing` does. This struct allows code that creates spans
-/// to compile, but span context is currently not propagated or logged.
-#[derive(Clone, Debug, Default)]
-pub struct Span;
+/// The frames carry the `target:` and `parent:` given to `span!`. Its other fields are
+/// not logged, and `Span::current` is not tracked: it returns a span that logs nothing.
+/// Values given to the typed `record_*` methods are logged as `span_record` frames,
+/// which the decoder sets as fields of the span.
+#[derive(Clone, Debug)]
+pub struct Span {
+    name: Option<&'static str>,
+    level: Level,
+    /// The module of the `span!`, for the runtime filter.
+    #[cfg_attr(not(feature = "control"), allow(dead_code))]
+    module: &'static str,
+    target: Option<&'static str>,
+    parent: Option<&'static str>,
+}
+
+impl Default for Span {
+    fn default() -> Self {
+        Span::none()
+    }
+}
 
 impl Span {
     pub const fn none() -> Self {
-        Span
+        Span {
+            name: None,
+            level: Level::Info,
+            module: "",
+            target: None,
+            parent: None,
+        }
     }
 
     pub fn current() -> Self {
-        Span
+        Span::none()
     }
 
-    pub fn enter(&self) -> Entered {
-        Entered
+    /// The span `span!` makes; `parent` is the [`AsParent::parent_name`] of its parent.
+    #[doc(hidden)]
+    pub const fn new(
+        level: Level,
+        name: &'static str,
+        module: &'static str,
+        target: Option<&'static str>,
+        parent: Option<&'static str>,
+    ) -> Self {
+        Span {
+            name: Some(name),
+            level,
+            module,
+            target,
+            parent,
+        }
     }
 
+    /// Logs the `span_enter` frame; the returned guard logs the `span_exit` frame when
+    /// dropped.
+    pub fn enter(&self) -> Entered<'_> {
+        let name = self.name.filter(|_| self.is_enabled());
+        if let Some(name) = name {
+            // The layout of `tracing_defmt_wire::SPAN_ENTER`, with the `TARGET_FIELD` and
+            // `PARENT_FIELD` fields.
+            match (self.target, self.parent) {
+                (None, None) => log_at!(self.level, "span_enter: {=str}", name),
+                (Some(target), None) => {
+                    log_at!(
+                        self.level,
+                        "span_enter: {=str}(target={=str})",
+                        name,
+                        target
+                    )
+                }
+                (None, Some(parent)) => {
+                    log_at!(
+                        self.level,
+                        "span_enter: {=str}(parent={=str})",
+                        name,
+                        parent
+                    )
+                }
+                (Some(target), Some(parent)) => log_at!(
+                    self.level,
+                    "span_enter: {=str}(target={=str}, parent={=str})",
+                    name,
+                    target,
+                    parent
+                ),
+            }
+        }
+        Entered { span: self, name }
+    }
+
+    /// Whether the runtime filter lets the span be logged, see [`control`].
+    fn is_enabled(&self) -> bool {
+        #[cfg(feature = "control")]
+        return control::enabled(self.level, self.module);
+        #[cfg(not(feature = "control"))]
+        return true;
+    }
+
+    /// Accepted for compatibility with `tracing`; the value is discarded, as defmt cannot
+    /// format `Debug` values. The typed `record_*` methods log theirs.
     pub fn record(&self, _field: &str, _value: &dyn core::fmt::Debug) -> &Self {
         self
     }
 
+    /// Whether the span logs nothing, because it is [`none`](Span::none) or the runtime
+    /// filter disables it.
     pub fn is_disabled(&self) -> bool {
-        false
+        self.name.is_none() || !self.is_enabled()
     }
 
+    /// Whether the span is [`none`](Span::none), e.g. the one `Span::current` returns.
     pub fn is_none(&self) -> bool {
-        false
+        self.name.is_none()
     }
 
     pub fn in_scope<F, T>(&self, f: F) -> T
     where
         F: FnOnce() -> T,
     {
+        let _enter = self.enter();
         f()
     }
 }
 
-pub struct Entered;
+macro_rules! span_record {
+    ($($(#[$doc:meta])* $method:ident: $ty:ty => $format:tt),* $(,)?) => {
+        impl Span {
+            $(
+                $(#[$doc])*
+                pub fn $method(&self, field: &str, value: $ty) -> &Self {
+                    // The layout of `tracing_defmt_wire::SPAN_RECORD`.
+                    if let Some(name) = self.name.filter(|_| self.is_enabled()) {
+                        log_at!(self.level, $format, name, field, value);
+                    }
+                    self
+                }
+            )*
+        }
+    };
+}
+
+span_record!(
+    /// Logs `field` of the span as a `span_record` frame, which the decoder sets on the
+    /// innermost open span of its name. Spans that log nothing record nothing either.
+    record_str: &str => "span_record: {=str}, {=str}={=str}",
+    /// Like [`record_str`](Span::record_str) for a boolean.
+    record_bool: bool => "span_record: {=str}, {=str}={=bool}",
+    /// Like [`record_str`](Span::record_str) for an integer.
+    record_u8: u8 => "span_record: {=str}, {=str}={=u8}",
+    /// Like [`record_str`](Span::record_str) for an integer.
+    record_u16: u16 => "span_record: {=str}, {=str}={=u16}",
+    /// Like [`record_str`](Span::record_str) for an integer.
+    record_u32: u32 => "span_record: {=str}, {=str}={=u32}",
+    /// Like [`record_str`](Span::record_str) for an integer.
+    record_u64: u64 => "span_record: {=str}, {=str}={=u64}",
+    /// Like [`record_str`](Span::record_str) for an integer.
+    record_i8: i8 => "span_record: {=str}, {=str}={=i8}",
+    /// Like [`record_str`](Span::record_str) for an integer.
+    record_i16: i16 => "span_record: {=str}, {=str}={=i16}",
+    /// Like [`record_str`](Span::record_str) for an integer.
+    record_i32: i32 => "span_record: {=str}, {=str}={=i32}",
+    /// Like [`record_str`](Span::record_str) for an integer.
+    record_i64: i64 => "span_record: {=str}, {=str}={=i64}",
+);
+
+/// What `parent:` of an event or span accepts: a span, a reference to one, or an
+/// `Option` of them, `None` meaning no parent.
+#[doc(hidden)]
+pub trait AsParent {
+    /// The value of the `parent` field on the wire, see `tracing_defmt_wire::PARENT_FIELD`.
+    fn parent_name(&self) -> &'static str;
+}
+
+impl AsParent for Span {
+    fn parent_name(&self) -> &'static str {
+        self.name.unwrap_or("")
+    }
+}
+
+impl<T: AsParent + ?Sized> AsParent for &T {
+    fn parent_name(&self) -> &'static str {
+        (**self).parent_name()
+    }
+}
 
-impl Drop for Entered {
-    fn drop(&mut self) {}
+impl<T: AsParent> AsParent for Option<T> {
+    fn parent_name(&self) -> &'static str {
+        match self {
+            Some(parent) => parent.parent_name(),
+            None => tracing_defmt_wire::NO_PARENT,
+        }
+    }
 }
 
+/// The guard of an entered [`Span`], which logs its exit when dropped.
+pub struct Entered<'a> {
+    span: &'a Span,
+    /// The name of the span if its entry was logged.
+    name: Option<&'static str>,
+}
+
+impl Drop for Entered<'_> {
+    fn drop(&mut self) {
+        if let Some(name) = self.name {
+            log_at!(self.span.level, "span_exit: {=str}", name);
+        }
+    }
+}
+
+/// Attaches a span to a future, like `tracing::Instrument`.
+///
+/// Unlike with `tracing`, the span is entered and exited around every poll, so the
+/// decoder shows one span per poll, covering the time the future ran, rather than one
+/// for its whole life: the frames of a context nest as a single stack, and the span
+/// cannot stay open while other tasks run between polls.
+pub trait Instrument: Sized {
+    fn instrument(self, span: Span) -> Instrumented<Self> {
+        Instrumented { inner: self, span }
+    }
+
+    fn in_current_span(self) -> Instrumented<Self> {
+        self.instrument(Span::current())
+    }
+}
+
+impl<T: Sized> Instrument for T {}
+
+pin_project_lite::pin_project! {
+    /// A future with a span attached, logged as a span of its own on every poll, see
+    /// [`Instrument`].
+    #[derive(Clone, Debug)]
+    pub struct Instrumented<T> {
+        #[pin]
+        inner: T,
+        span: Span,
+    }
+}
+
+impl<T> Instrumented<T> {
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: core::future::Future> core::future::Future for Instrumented<T> {
+    type Output = T::Output;
+
+    fn poll(
+        self: core::pin::Pin<&mut Self>,
+        cx: &mut core::task::Context<'_>,
+    ) -> core::task::Poll<Self::Output> {
+        let this = self.project();
+        let _enter = this.span.enter();
+        this.inner.poll(cx)
+    }
+}
+
+// `target:` and `parent:` are logged with the span's entry; its other fields are not.
+// The name has to be a string literal.
 #[macro_export]
 macro_rules! span {
+    (@placed $lvl:expr, $target:expr, $parent:expr, $name:literal $(, $($fields:tt)*)?) => {
+        $crate::Span::new($lvl, $name, module_path!(), $target, $parent)
+    };
+    (@placed $lvl:expr, $target:expr, $parent:expr, $($args:tt)*) => {
+        compile_error!("the name of a span must be a string literal, as defmt logs it")
+    };
+    // A bare `None` has no type to find its `AsParent` impl by.
+    (target: $target:expr, parent: None, $lvl:expr, $($args:tt)*) => {
+        $crate::span!(target: $target, parent: None::<$crate::Span>, $lvl, $($args)*)
+    };
+    (parent: None, $lvl:expr, $($args:tt)*) => {
+        $crate::span!(parent: None::<$crate::Span>, $lvl, $($args)*)
+    };
+    (target: $target:expr, parent: $parent:expr, $lvl:expr, $($args:tt)*) => {
+        $crate::span!(
+            @placed $lvl,
+            Some($target),
+            Some($crate::AsParent::parent_name(&($parent))),
+            $($args)*
+        )
+    };
     (target: $target:expr, $lvl:expr, $($args:tt)*) => {
-        $crate::Span
+        $crate::span!(@placed $lvl, Some($target), None, $($args)*)
+    };
+    (parent: $parent:expr, $lvl:expr, $($args:tt)*) => {
+        $crate::span!(
+            @placed $lvl,
+            None,
+            Some($crate::AsParent::parent_name(&($parent))),
+            $($args)*
+        )
     };
     ($lvl:expr, $($args:tt)*) => {
-        $crate::Span
+        $crate::span!(@placed $lvl, None, None, $($args)*)
     };
 }
 
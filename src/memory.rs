@@ -0,0 +1,153 @@
+//! Heap and stack usage, logged as gauges.
+//!
+//! [`log_memory`] logs the `memory.heap.free`, `memory.heap.used` and
+//! `memory.stack.high_water` gauges the decoder knows: it exports them as OpenTelemetry
+//! gauges with the name of the span they were measured in, and gives the spans open at
+//! the time the lowest free heap and the highest stack use seen while they ran. That
+//! ties memory pressure to the operations that cause it.
+//!
+//! The heap figures come from the allocator, e.g. `embedded-alloc`'s `Heap::used` and
+//! `Heap::free`. The stack high-water mark comes from stack painting: [`paint_stack`]
+//! fills the unused stack with a pattern early on, and [`stack_high_water`] finds how
+//! much of it was overwritten since. With the `memory` feature and `cortex-m-rt`,
+//! `paint_unused_stack` and `stack_usage` find the stack themselves.
+//!
+//! # Example
+//! ```rust,ignore
+//! unsafe { tracing_defmt::memory::paint_unused_stack() };
+//! let mut reporter = MemoryReporter::new(1_000);
+//! loop {
+//!     reporter.poll(now_ms(), || MemoryUsage {
+//!         heap_used: Some(HEAP.used() as u32),
+//!         heap_free: Some(HEAP.free() as u32),
+//!         stack_high_water: Some(tracing_defmt::memory::stack_usage()),
+//!     });
+//!     // ...
+//! }
+//! ```
+
+/// The word unused stack is painted with.
+pub const STACK_PAINT: u32 = 0xCCCC_CCCC;
+
+/// Memory figures in bytes; those left `None` are not logged.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    pub heap_used: Option<u32>,
+    pub heap_free: Option<u32>,
+    /// The most stack used since boot.
+    pub stack_high_water: Option<u32>,
+}
+
+/// Logs one gauge frame per figure of `usage`.
+pub fn log_memory(usage: &MemoryUsage) {
+    if let Some(free) = usage.heap_free {
+        defmt::info!("gauge: memory.heap.free={=u32}", free);
+    }
+    if let Some(used) = usage.heap_used {
+        defmt::info!("gauge: memory.heap.used={=u32}", used);
+    }
+    if let Some(high_water) = usage.stack_high_water {
+        defmt::info!("gauge: memory.stack.high_water={=u32}", high_water);
+    }
+}
+
+/// Logs the memory gauges at most once per period, for calling from a main loop or a
+/// timer with the current time, in any unit that wraps around at `u32::MAX`.
+#[derive(Clone, Debug)]
+pub struct MemoryReporter {
+    period: u32,
+    last: Option<u32>,
+}
+
+impl MemoryReporter {
+    pub const fn new(period: u32) -> Self {
+        Self { period, last: None }
+    }
+
+    /// Logs the figures returned by `usage` if a period passed since the last report, or
+    /// if there was none. `usage` is only called then. Returns whether it reported.
+    pub fn poll(&mut self, now: u32, usage: impl FnOnce() -> MemoryUsage) -> bool {
+        if self
+            .last
+            .is_some_and(|last| now.wrapping_sub(last) < self.period)
+        {
+            return false;
+        }
+        self.last = Some(now);
+        log_memory(&usage());
+        true
+    }
+}
+
+/// Fills `unused`, stack memory below the stack pointer, with [`STACK_PAINT`].
+pub fn paint_stack(unused: &mut [u32]) {
+    unused.fill(STACK_PAINT);
+}
+
+/// The bytes of `stack`, lowest address first, overwritten since it was painted: all
+/// but the painted words at its bottom. The stack grows down, so they were never
+/// reached.
+pub fn stack_high_water(stack: &[u32]) -> u32 {
+    let untouched = stack
+        .iter()
+        .take_while(|&&word| word == STACK_PAINT)
+        .count();
+    ((stack.len() - untouched) * 4) as u32
+}
+
+#[cfg(all(feature = "memory", target_arch = "arm", target_os = "none"))]
+mod cortex_m {
+    use super::STACK_PAINT;
+
+    unsafe extern "C" {
+        /// The top of the stack.
+        static _stack_start: u32;
+        /// The end of the static data, below which the stack must not grow.
+        static __euninit: u32;
+    }
+
+    /// Space left unpainted below the stack pointer, for the frame of the painting itself.
+    const MARGIN: usize = 64;
+
+    fn bounds() -> (usize, usize) {
+        unsafe {
+            (
+                core::ptr::addr_of!(__euninit) as usize,
+                core::ptr::addr_of!(_stack_start) as usize,
+            )
+        }
+    }
+
+    /// Paints the stack between the end of the static data and the stack pointer, for
+    /// [`stack_usage`]. Call it once, early after boot.
+    ///
+    /// # Safety
+    /// The memory between the end of the static data and the stack pointer must be
+    /// unused, e.g. not hold a heap, as with the `cortex-m-rt` linker script.
+    pub unsafe fn paint_unused_stack() {
+        let sp: usize;
+        unsafe { core::arch::asm!("mov {}, sp", out(reg) sp) };
+        let (bottom, _) = bounds();
+        let mut word = bottom as *mut u32;
+        while (word as usize) + MARGIN < sp {
+            unsafe {
+                word.write_volatile(STACK_PAINT);
+                word = word.add(1);
+            }
+        }
+    }
+
+    /// The most stack used since [`paint_unused_stack`], in bytes.
+    pub fn stack_usage() -> u32 {
+        let (bottom, top) = bounds();
+        // One word at a time, as the stack above is in use.
+        let mut word = bottom as *const u32;
+        while (word as usize) < top && unsafe { word.read_volatile() } == STACK_PAINT {
+            word = unsafe { word.add(1) };
+        }
+        (top - word as usize) as u32
+    }
+}
+
+#[cfg(all(feature = "memory", target_arch = "arm", target_os = "none"))]
+pub use cortex_m::{paint_unused_stack, stack_usage};
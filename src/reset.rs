@@ -0,0 +1,116 @@
+//! The cause of the last reset, logged at boot.
+//!
+//! [`log_boot`] logs the `boot` frame the decoder starts a new session with, carrying a
+//! `reset.reason`. The decoder adds the reason to the spans of the session and counts the
+//! boots by reason, so watchdog resets and brown-outs across a fleet stand out from
+//! power cycles. The reason comes from the chip's reset cause register, which the
+//! functions of [`ResetReason`] interpret for common chips.
+//!
+//! # Example
+//! ```rust,ignore
+//! let csr = dp.RCC.csr.read().bits();
+//! tracing_defmt::reset::log_boot(ResetReason::from_stm32f4_csr(csr));
+//! // The flags stick until cleared, which would blame the next reset on this one.
+//! dp.RCC.csr.modify(|_, w| w.rmvf().set_bit());
+//! ```
+
+/// Why the chip was reset.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, defmt::Format)]
+pub enum ResetReason {
+    /// The supply was switched on.
+    PowerOn,
+    /// The supply dropped below the brown-out threshold.
+    Brownout,
+    Watchdog,
+    /// The firmware requested it, e.g. with `SCB::sys_reset`.
+    Software,
+    /// The reset pin was pulled.
+    Pin,
+    /// The core locked up, e.g. on a fault within the HardFault handler.
+    Lockup,
+    /// The chip woke up from a low-power mode that resets it.
+    Wakeup,
+    /// The chip entered a low-power mode it was not allowed to.
+    LowPower,
+    Unknown,
+}
+
+impl ResetReason {
+    /// The `reset.reason` of the boot frame, e.g. `watchdog`.
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            ResetReason::PowerOn => "por",
+            ResetReason::Brownout => "bod",
+            ResetReason::Watchdog => "watchdog",
+            ResetReason::Software => "software",
+            ResetReason::Pin => "pin",
+            ResetReason::Lockup => "lockup",
+            ResetReason::Wakeup => "wakeup",
+            ResetReason::LowPower => "low_power",
+            ResetReason::Unknown => "unknown",
+        }
+    }
+
+    /// Interprets the `RCC_CSR` register of STM32F2, F4 and F7 chips. Every reset pulls
+    /// the reset pin, so its flag only counts when no other is set.
+    pub fn from_stm32f4_csr(csr: u32) -> Self {
+        const REASONS: [(u32, ResetReason); 7] = [
+            (29, ResetReason::Watchdog),
+            (30, ResetReason::Watchdog),
+            (31, ResetReason::LowPower),
+            (28, ResetReason::Software),
+            // A power-on reset sets the brown-out flag as well.
+            (27, ResetReason::PowerOn),
+            (25, ResetReason::Brownout),
+            (26, ResetReason::Pin),
+        ];
+        first_set(csr, &REASONS)
+    }
+
+    /// Interprets the `RESETREAS` register of nRF52 chips. Power-on and brown-out resets
+    /// clear it, so an empty register counts as power-on.
+    pub fn from_nrf52_resetreas(resetreas: u32) -> Self {
+        const REASONS: [(u32, ResetReason); 9] = [
+            (1, ResetReason::Watchdog),
+            (3, ResetReason::Lockup),
+            (2, ResetReason::Software),
+            (0, ResetReason::Pin),
+            (16, ResetReason::Wakeup),
+            (17, ResetReason::Wakeup),
+            (18, ResetReason::Wakeup),
+            (19, ResetReason::Wakeup),
+            (20, ResetReason::Wakeup),
+        ];
+        match resetreas {
+            0 => ResetReason::PowerOn,
+            _ => first_set(resetreas, &REASONS),
+        }
+    }
+
+    /// Interprets the `CHIP_RESET` register of the RP2040 and the `REASON` register of
+    /// its watchdog, which also tells resets forced through the watchdog (as
+    /// `reset_to_usb_boot` and most software resets do).
+    pub fn from_rp2040(chip_reset: u32, watchdog_reason: u32) -> Self {
+        match (chip_reset, watchdog_reason) {
+            (_, reason) if reason & 1 != 0 => ResetReason::Watchdog,
+            (_, reason) if reason & 2 != 0 => ResetReason::Software,
+            (chip, _) if chip & (1 << 8) != 0 => ResetReason::PowerOn,
+            (chip, _) if chip & (1 << 16) != 0 => ResetReason::Pin,
+            _ => ResetReason::Unknown,
+        }
+    }
+}
+
+/// The reason of the first flag of `reasons` set in `register`, in their order.
+fn first_set(register: u32, reasons: &[(u32, ResetReason)]) -> ResetReason {
+    reasons
+        .iter()
+        .find(|(bit, _)| register & (1 << bit) != 0)
+        .map_or(ResetReason::Unknown, |(_, reason)| *reason)
+}
+
+/// Logs the `boot` frame with the reason of the reset, first thing after it. The decoder
+/// then starts a new session for the following spans.
+pub fn log_boot(reason: ResetReason) {
+    defmt::info!("boot: reset.reason={=str}", reason.as_str());
+}
@@ -17,6 +17,8 @@ fn test_args() {
     tracing::info!("value", x = x);
     // Key-value pairs (mixed/leading - supported by our macro parser)
     tracing::info!(y = x, "value");
+    // Interned strings
+    tracing::info!(state = tracing::defmt::intern!("idle"), "changed");
 }
 
 #[tracing::instrument]
@@ -24,17 +26,96 @@ fn instrumented_fn(x: u32) {
     tracing::info!("inside instrumented function");
 }
 
+#[tracing::instrument(instance = channel + 1, skip(channel))]
+fn instanced_fn(channel: u8) {
+    let span = tracing::info_span!("rx", instance = channel);
+    let _enter = span.enter();
+}
+
+#[tracing::instrument(fields(otel.kind = "client", retries = x + 1))]
+fn client_fn(x: u32) {}
+
+// Left as it is, or it could not stay `const`.
+#[tracing::instrument(level = "off")]
+const fn quiet_fn(x: u32) -> u32 {
+    x + 1
+}
+
+#[tracing::instrument]
+async fn fetch(x: u32) -> Result<u32, ()> {
+    if x == 0 {
+        return Err(());
+    }
+    let y = async { x + 1 }.await;
+    Ok(y)
+}
+
+#[tracing::instrument]
+async fn wait() {
+    std::future::pending::<()>().await
+}
+
+#[test]
+fn test_instrument_async() {
+    use std::task::{Context, Poll, Waker};
+
+    let mut cx = Context::from_waker(Waker::noop());
+    let mut fetched = std::pin::pin!(fetch(1));
+    assert_eq!(fetched.as_mut().poll(&mut cx), Poll::Ready(Ok(2)));
+    let mut failed = std::pin::pin!(fetch(0));
+    assert_eq!(failed.as_mut().poll(&mut cx), Poll::Ready(Err(())));
+    // Dropped while it waits, which cancels the span.
+    let mut waiting = Box::pin(wait());
+    assert_eq!(waiting.as_mut().poll(&mut cx), Poll::Pending);
+    drop(waiting);
+}
+
 #[test]
 fn test_instrument() {
     instrumented_fn(123);
+    instanced_fn(2);
+    client_fn(3);
+    const QUIET: u32 = quiet_fn(1);
+    assert_eq!(QUIET, 2);
+}
+
+#[test]
+fn test_parent_and_target() {
+    use tracing::Level;
+
+    const TARGET: &str = "radio";
+    let span = tracing::info_span!("poll");
+    tracing::event!(target: "radio", parent: &span, Level::DEBUG, "rx", len = 3u8);
+    tracing::event!(parent: None, Level::INFO, "boot");
+    tracing::event!(target: TARGET, Level::WARN, "late");
+    tracing::info!(target: "radio", parent: Some(&span), "tx");
+    tracing::error!(parent: tracing::Span::current(), "fault");
+    let _child = tracing::span!(target: "radio", parent: &span, Level::INFO, "rx", len = 1);
+    tracing::with_span!(target: "radio", parent: None, Level::DEBUG, "rx_isr", {});
+}
+
+#[test]
+fn test_with_span() {
+    use tracing::Level;
+
+    let chan = 1u8;
+    let sent = tracing::with_span!(Level::INFO, "dma_xfer", chan = chan, len = 64u32, {
+        tracing::debug!("started");
+        64u32
+    });
+    assert_eq!(sent, 64);
+    tracing::with_span!(tracing::Level::Trace, "idle", {});
 }
 
 #[test]
 fn test_spans() {
-    // Spans are currently dummy implementations, but should compile
     let span = tracing::info_span!("my_span");
     let _enter = span.enter();
     tracing::info!("in span");
+    span.record_str("peer", "gw")
+        .record_u32("len", 64)
+        .record_i64("offset", -1)
+        .record_bool("retry", false);
 }
 
 #[test]
@@ -60,6 +141,84 @@ fn test_fields_wrappers() {
     tracing::info!(val = tracing::field::debug(&n), "testing debug wrapper");
 }
 
+#[test]
+fn test_display_hint_wrappers() {
+    use tracing::field;
+
+    let reg = 0x1fu32;
+    let buf = [0xde, 0xad];
+    let elapsed: u64 = 1_500;
+    tracing::info!(
+        reg = field::hex(reg),
+        flags = field::bin(0b101u8),
+        "registers"
+    );
+    tracing::info!(frame = field::hex(&buf[..]), mac = field::hex(buf));
+    tracing::info!(uptime = field::us(elapsed), timeout = field::ms(&250u32));
+    tracing::debug!(offset = field::hex(-4i32));
+}
+
+#[cfg(feature = "fugit")]
+#[test]
+fn test_fugit_fields() {
+    use fugit::{ExtU32, MillisDurationU32, TimerInstantU64};
+    use tracing::field;
+
+    let timeout: MillisDurationU32 = 250.millis();
+    let now = TimerInstantU64::<32_768>::from_ticks(49_152);
+    let elapsed = fugit::Duration::<u32, 1, 32_768>::from_ticks(u32::MAX);
+    tracing::info!(timeout = field::ms(timeout), at = field::us(now), "armed");
+    tracing::debug!(elapsed = field::us(&elapsed));
+}
+
+#[cfg(feature = "heapless")]
+#[test]
+fn test_heapless_fields() {
+    use heapless::spsc::Queue;
+    use tracing::field;
+
+    let mut name = heapless::String::<16>::new();
+    name.push_str("uart0").unwrap();
+    let frame = heapless::Vec::<u8, 8>::from_slice(&[0xde, 0xad]).unwrap();
+    tracing::info!(name, frame, "opened");
+    tracing::info!(raw = field::hex(&frame), len = field::len(&frame));
+
+    let mut queue = Queue::<u8, 4>::new();
+    let (mut producer, consumer) = queue.split();
+    producer.enqueue(1).unwrap();
+    tracing::debug!(
+        queued = field::len(&producer),
+        pending = field::len(&consumer),
+        "rx"
+    );
+}
+
+mod ported {
+    use tracing_defmt::prelude::*;
+
+    #[instrument(level = "debug")]
+    pub fn poll(channel: u8) {
+        info!(channel, raw = field::hex(channel), "polled");
+        let span = info_span!("rx");
+        let _enter = span.enter();
+        event!(Level::WARN, "late");
+    }
+
+    pub async fn recv() -> u8 {
+        async { 7 }.instrument(Span::current()).await
+    }
+}
+
+#[test]
+fn test_prelude() {
+    use std::task::{Context, Poll, Waker};
+
+    ported::poll(2);
+    let mut recv = std::pin::pin!(ported::recv());
+    let poll = recv.as_mut().poll(&mut Context::from_waker(Waker::noop()));
+    assert_eq!(poll, Poll::Ready(7));
+}
+
 // Stubs to satisfy the linker when running tests on host
 #[unsafe(no_mangle)]
 fn _defmt_acquire() {}
@@ -0,0 +1,72 @@
+use tracing_defmt::control::{self, CommandError, Control};
+use tracing_defmt::{Level, LevelFilter};
+
+fn feed(control: &mut Control, commands: &str) {
+    let mut bytes = commands.as_bytes();
+    // Arrives in pieces, like from a serial port.
+    control.poll(|buf| {
+        let n = bytes.len().min(3).min(buf.len());
+        buf[..n].copy_from_slice(&bytes[..n]);
+        bytes = &bytes[n..];
+        n
+    });
+}
+
+// The filter is global, so everything is checked in one test.
+#[test]
+fn test_filters_follow_host_commands() {
+    assert!(control::enabled(Level::Trace, "app::net"));
+
+    control::set_default_level(Some(Level::Info));
+    assert!(control::enabled(Level::Info, "app::net"));
+    assert!(!control::enabled(Level::Debug, "app::net"));
+
+    let mut control = Control::new();
+    feed(
+        &mut control,
+        "set-filter app::net=trace\r\nset-filter app::net::tcp=off\nset-filter warn\n",
+    );
+    assert!(control::enabled(Level::Trace, "app::net"));
+    assert!(control::enabled(Level::Trace, "app::net::udp"));
+    assert!(!control::enabled(Level::Error, "app::net::tcp"));
+    assert!(!control::enabled(Level::Trace, "app::network"));
+    assert!(!control::enabled(Level::Info, "app::storage"));
+    assert!(control::enabled(Level::Warn, "app::storage"));
+
+    assert_eq!(
+        control::execute("set-filter app=loud"),
+        Err(CommandError::InvalidLevel)
+    );
+    assert_eq!(control::execute("reboot"), Err(CommandError::Unknown));
+    feed(
+        &mut control,
+        &format!("set-filter {}=trace\n", "x".repeat(64)),
+    );
+    assert!(!control::enabled(Level::Trace, &"x".repeat(64)));
+
+    feed(&mut control, "reset-filter\n");
+    assert!(!control::enabled(Level::Trace, "app::net"));
+    assert!(control::enabled(Level::Info, "app::net::tcp"));
+
+    control::set_default_level(LevelFilter::OFF);
+    assert!(!control::enabled(Level::Error, "app::net"));
+    feed(&mut control, "set-filter app::net=debug\n");
+    assert!(control::enabled(Level::Debug, "app::net"));
+    feed(&mut control, "reset-filter\n");
+    assert!(!control::enabled(Level::Error, "app::net"));
+    control::set_default_level(Level::Trace);
+    assert!(control::enabled(Level::Trace, "app::net"));
+}
+
+// Stubs to satisfy the linker when running tests on host
+#[unsafe(no_mangle)]
+fn _defmt_acquire() {}
+
+#[unsafe(no_mangle)]
+fn _defmt_release() {}
+
+#[unsafe(no_mangle)]
+fn _defmt_write(_bytes: &[u8]) {}
+
+#[unsafe(no_mangle)]
+fn _defmt_timestamp(_fmt: tracing_defmt::defmt::Formatter<'_>) {}
@@ -0,0 +1,55 @@
+use tracing_defmt::crash::{self, ExceptionFrame, FaultStatus, MAX_BACKTRACE};
+
+#[test]
+fn test_return_addresses_are_thumb_code_addresses() {
+    let stack = [
+        0x2000_0100,
+        0x0800_0101,
+        0x0800_0200,
+        0x0000_0003,
+        0x0800_4001,
+        0x0801_0001,
+    ];
+    let mut out = [0; 4];
+    let count = crash::return_addresses(&stack, 0x0800_0000..0x0801_0000, &mut out);
+    assert_eq!(out[..count], [0x0800_0101, 0x0800_4001]);
+
+    let stack = [0x0800_0101; 8];
+    let mut out = [0; 2];
+    assert_eq!(
+        crash::return_addresses(&stack, 0x0800_0000..0x0801_0000, &mut out),
+        2
+    );
+}
+
+#[test]
+fn test_crash_frame_is_logged() {
+    let frame = ExceptionFrame {
+        pc: 0x0800_0100,
+        lr: 0x0800_0201,
+        xpsr: 0x0100_0000,
+        ..Default::default()
+    };
+    let sp = frame.stack_pointer();
+    assert_eq!(sp, &frame as *const _ as usize as u32 + 32);
+    crash::log_crash(
+        "HardFault",
+        &frame,
+        sp,
+        &FaultStatus::default(),
+        &[0x0800_0301; MAX_BACKTRACE + 1],
+    );
+}
+
+// Stubs to satisfy the linker when running tests on host
+#[unsafe(no_mangle)]
+fn _defmt_acquire() {}
+
+#[unsafe(no_mangle)]
+fn _defmt_release() {}
+
+#[unsafe(no_mangle)]
+fn _defmt_write(_bytes: &[u8]) {}
+
+#[unsafe(no_mangle)]
+fn _defmt_timestamp(_fmt: tracing_defmt::defmt::Formatter<'_>) {}
@@ -0,0 +1,43 @@
+use tracing_defmt::memory::{self, MemoryReporter, MemoryUsage, STACK_PAINT};
+
+#[test]
+fn test_stack_high_water_counts_overwritten_words() {
+    let mut stack = [0x2000_0000; 16];
+    memory::paint_stack(&mut stack[..12]);
+    assert_eq!(memory::stack_high_water(&stack), 16);
+    // The deepest call reached down to the fifth word.
+    stack[4] = 0;
+    assert_eq!(memory::stack_high_water(&stack), 48);
+    assert_eq!(memory::stack_high_water(&[STACK_PAINT; 4]), 0);
+}
+
+#[test]
+fn test_reporter_logs_once_per_period() {
+    let mut reporter = MemoryReporter::new(1_000);
+    let mut reports = 0;
+    let mut usage = || {
+        reports += 1;
+        MemoryUsage {
+            heap_free: Some(512),
+            ..Default::default()
+        }
+    };
+    assert!(reporter.poll(u32::MAX - 100, &mut usage));
+    assert!(!reporter.poll(u32::MAX, &mut usage));
+    assert!(!reporter.poll(800, &mut usage));
+    assert!(reporter.poll(900, &mut usage));
+    assert_eq!(reports, 2);
+}
+
+// Stubs to satisfy the linker when running tests on host
+#[unsafe(no_mangle)]
+fn _defmt_acquire() {}
+
+#[unsafe(no_mangle)]
+fn _defmt_release() {}
+
+#[unsafe(no_mangle)]
+fn _defmt_write(_bytes: &[u8]) {}
+
+#[unsafe(no_mangle)]
+fn _defmt_timestamp(_fmt: tracing_defmt::defmt::Formatter<'_>) {}
@@ -1,88 +1,111 @@
-use tracing::Level;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use tracing_defmt_decoder::export::Exporter;
+use tracing_defmt_decoder::source::LineReconstructor;
+use tracing_defmt_decoder::{RecordKind, TraceDecoder, TraceRecord};
 use tracing_fluent_assertions::{AssertionRegistry, AssertionsLayer};
 use tracing_subscriber::{Registry, layer::SubscriberExt};
 
-// Re-implementation of the logic from examples/host_trace_reconstructor.rs
-// adjusted to read from a slice of strings.
-fn process_logs(logs: &[String]) {
-    let mut iter = logs.iter();
-    process_scope(&mut iter);
+/// Keeps the records it is given, for checking them after the stream is done.
+#[derive(Clone, Default)]
+struct Collect(Arc<Mutex<Vec<TraceRecord>>>);
+
+impl Exporter for Collect {
+    fn export(&mut self, record: &TraceRecord) -> io::Result<()> {
+        self.0.lock().unwrap().push(record.clone());
+        Ok(())
+    }
 }
 
-fn process_scope<'a, I>(lines: &mut I)
-where
-    I: Iterator<Item = &'a String>,
-{
-    while let Some(line) = lines.next() {
-        let line = line.trim();
-        if line.is_empty() {
-            continue;
-        }
-
-        if let Some(msg_start) = line.find("span_enter: ") {
-            let content = &line[msg_start + "span_enter: ".len()..];
-            let (name, args) = if let Some(idx) = content.find('(') {
-                let end = content.len().saturating_sub(1);
-                (&content[..idx], &content[idx + 1..end])
-            } else {
-                (content, "")
-            };
-
-            // Use match to provide static names for expected spans
-            let span = match name {
-                "my_function" => tracing::span!(Level::INFO, "my_function", args = args),
-                "nested_call" => tracing::span!(Level::INFO, "nested_call", args = args),
-                _ => tracing::span!(Level::INFO, "unknown", function = name, args = args),
-            };
-
-            let _guard = span.enter();
-            process_scope(lines);
-        } else if let Some(msg_start) = line.find("span_exit: ") {
-            let _name = &line[msg_start + "span_exit: ".len()..];
-            return;
-        } else {
-            tracing::info!(target: "device_log", "{}", line);
-        }
+/// Reconstructs the spans of `logs` and returns the records they were built from.
+fn process_logs(logs: &[&str]) -> Vec<TraceRecord> {
+    let decoder = TraceDecoder::builder().build_for_text().unwrap();
+    let mut reconstructor = LineReconstructor::new(decoder.new_stream());
+    let records = Collect::default();
+    reconstructor.stream_mut().add_exporter(records.clone());
+    for line in logs {
+        reconstructor.push_line(line).unwrap();
     }
+    reconstructor.finish().unwrap();
+    std::mem::take(&mut *records.0.lock().unwrap())
 }
 
 #[test]
 fn test_nested_span_reconstruction() {
+    // A span is tracked by only one of the assertions it matches, so the overlapping
+    // assertions get a registry each.
     let assertion_registry = AssertionRegistry::default();
-    let layer = AssertionsLayer::new(&assertion_registry);
-    let subscriber = Registry::default().with(layer);
+    let nested_registry = AssertionRegistry::default();
+    let subscriber = Registry::default()
+        .with(AssertionsLayer::new(&assertion_registry))
+        .with(AssertionsLayer::new(&nested_registry));
 
     // Build assertions BEFORE execution
-    let my_fn_assertion = assertion_registry
+    let spans_assertion = assertion_registry
         .build()
-        .with_name("my_function")
-        .was_entered()
-        .was_closed()
+        .with_name("device_span")
+        .was_created_exactly(2)
+        .was_closed_exactly(2)
         .finalize();
 
-    let nested_call_assertion = assertion_registry
+    let nested_call_assertion = nested_registry
         .build()
-        .with_name("nested_call")
-        .was_entered()
-        .was_closed()
+        .with_name("device_span")
+        .with_parent_name("device_span")
+        .was_created_exactly(1)
+        .was_closed_exactly(1)
         .finalize();
 
-    let logs = vec![
-        "span_enter: my_function(x=10, y=20)".to_string(),
-        "Entered my_function with x=10, y=20".to_string(),
-        "span_enter: nested_call(value=30)".to_string(),
-        "Inside nested_call with value=30".to_string(),
-        "Very verbose info from nested call".to_string(),
-        "span_exit: nested_call".to_string(),
-        "This is a warning inside the function".to_string(),
-        "span_exit: my_function".to_string(),
+    let logs = [
+        "span_enter: my_function(x=10, y=20)",
+        "Entered my_function with x=10, y=20",
+        "span_enter: nested_call(value=30)",
+        "Inside nested_call with value=30",
+        "Very verbose info from nested call",
+        "span_exit: nested_call",
+        "This is a warning inside the function",
+        "span_exit: my_function",
     ];
 
+    let records = tracing::subscriber::with_default(subscriber, || process_logs(&logs));
+
+    // Assertions
+    spans_assertion.assert();
+    nested_call_assertion.assert();
+
+    let entered: Vec<&TraceRecord> = records
+        .iter()
+        .filter(|record| record.kind == RecordKind::SpanEnter)
+        .collect();
+    assert_eq!(entered.len(), 2);
+    assert_eq!(entered[0].message, "my_function");
+    assert_eq!(entered[0].parent_id, None);
+    assert_eq!(entered[1].message, "nested_call");
+    assert_eq!(entered[1].parent_id, entered[0].span_id);
+    assert!(entered[0].span_id.is_some());
+}
+
+#[test]
+fn test_deep_nesting_does_not_overflow() {
+    let assertion_registry = AssertionRegistry::default();
+    let layer = AssertionsLayer::new(&assertion_registry);
+    let subscriber = Registry::default().with(layer);
+
+    let depth = 100_000;
+    let spans_assertion = assertion_registry
+        .build()
+        .with_name("device_span")
+        .was_created_exactly(depth)
+        .was_closed_exactly(depth)
+        .finalize();
+
+    let mut logs = vec!["span_enter: recurse"; depth];
+    logs.extend(vec!["span_exit: recurse"; depth]);
+
     tracing::subscriber::with_default(subscriber, || {
         process_logs(&logs);
     });
 
-    // Assertions
-    my_fn_assertion.assert();
-    nested_call_assertion.assert();
+    spans_assertion.assert();
 }
@@ -0,0 +1,49 @@
+use tracing_defmt::reset::{self, ResetReason};
+
+#[test]
+fn test_reset_registers_are_interpreted() {
+    // Power-on sets the power-on, brown-out and pin flags.
+    assert_eq!(
+        ResetReason::from_stm32f4_csr(0x0e00_0000),
+        ResetReason::PowerOn
+    );
+    assert_eq!(
+        ResetReason::from_stm32f4_csr(0x2400_0000),
+        ResetReason::Watchdog
+    );
+    assert_eq!(
+        ResetReason::from_stm32f4_csr(0x0600_0000),
+        ResetReason::Brownout
+    );
+    assert_eq!(ResetReason::from_stm32f4_csr(0x0400_0000), ResetReason::Pin);
+    assert_eq!(ResetReason::from_stm32f4_csr(0), ResetReason::Unknown);
+
+    assert_eq!(ResetReason::from_nrf52_resetreas(0), ResetReason::PowerOn);
+    assert_eq!(
+        ResetReason::from_nrf52_resetreas(0b110),
+        ResetReason::Watchdog
+    );
+    assert_eq!(
+        ResetReason::from_nrf52_resetreas(1 << 16),
+        ResetReason::Wakeup
+    );
+
+    assert_eq!(ResetReason::from_rp2040(1 << 8, 0), ResetReason::PowerOn);
+    assert_eq!(ResetReason::from_rp2040(1 << 16, 1), ResetReason::Watchdog);
+    assert_eq!(ResetReason::Brownout.as_str(), "bod");
+
+    reset::log_boot(ResetReason::Watchdog);
+}
+
+// Stubs to satisfy the linker when running tests on host
+#[unsafe(no_mangle)]
+fn _defmt_acquire() {}
+
+#[unsafe(no_mangle)]
+fn _defmt_release() {}
+
+#[unsafe(no_mangle)]
+fn _defmt_write(_bytes: &[u8]) {}
+
+#[unsafe(no_mangle)]
+fn _defmt_timestamp(_fmt: tracing_defmt::defmt::Formatter<'_>) {}
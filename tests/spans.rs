@@ -0,0 +1,178 @@
+use std::cell::RefCell;
+use std::future::Future;
+use std::pin::{Pin, pin};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use tracing_defmt as tracing;
+use tracing_defmt::{Instrument, Level};
+use tracing_defmt_decoder::export::Exporter;
+use tracing_defmt_decoder::source::LineReconstructor;
+use tracing_defmt_decoder::{RecordKind, TraceDecoder, TraceRecord};
+
+thread_local! {
+    /// The bytes logged by the test running on this thread, as the logger gets them.
+    static LOG: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Takes the bytes logged so far by this test.
+fn logged() -> Vec<u8> {
+    LOG.with(|log| log.take())
+}
+
+/// Splits `data` into frames of `{=str}` arguments, given their number per frame:
+/// each frame is its format string index, then each string with its length first.
+fn frames(mut data: &[u8], arguments: &[usize]) -> Vec<(u16, Vec<String>)> {
+    let mut frames = Vec::new();
+    for &count in arguments {
+        let index = u16::from_le_bytes([data[0], data[1]]);
+        data = &data[2..];
+        let mut strings = Vec::new();
+        for _ in 0..count {
+            let len = u32::from_le_bytes(data[..4].try_into().unwrap()) as usize;
+            strings.push(String::from_utf8(data[4..4 + len].to_vec()).unwrap());
+            data = &data[4 + len..];
+        }
+        frames.push((index, strings));
+    }
+    assert!(data.is_empty(), "{} bytes left", data.len());
+    frames
+}
+
+#[test]
+fn test_span_logs_target_and_parent() {
+    // Error spans are logged whatever `DEFMT_LOG` says.
+    let poll = tracing::error_span!("poll");
+    {
+        let _poll = poll.enter();
+        let rx = tracing::span!(target: "radio", parent: &poll, Level::ERROR, "rx", len = 1);
+        rx.in_scope(|| {});
+        tracing::span!(parent: None, Level::ERROR, "isr").in_scope(|| {});
+    }
+
+    let frames = frames(&logged(), &[1, 3, 1, 2, 1, 1]);
+    let arguments: Vec<&[String]> = frames.iter().map(|(_, args)| &args[..]).collect();
+    assert_eq!(
+        arguments,
+        [
+            &["poll"][..],
+            &["rx", "radio", "poll"],
+            &["rx"],
+            &["isr", "-"],
+            &["isr"],
+            &["poll"],
+        ]
+    );
+    // The exits share a format string, distinct from the entries'.
+    let (exit, enter) = (frames[2].0, frames[0].0);
+    assert!(frames[4].0 == exit && frames[5].0 == exit);
+    assert!(enter != exit && frames[1].0 != enter && frames[3].0 != frames[1].0);
+}
+
+#[test]
+fn test_record_logs_typed_fields() {
+    let send = tracing::error_span!("send");
+    let _send = send.enter();
+    send.record_str("state", "idle");
+    tracing::Span::none()
+        .record_str("state", "idle")
+        .record_u32("len", 1);
+
+    let frames = frames(&logged(), &[1, 3]);
+    assert_eq!(frames[1].1, ["send", "state", "idle"]);
+}
+
+#[test]
+fn test_only_spans_without_name_are_none() {
+    assert!(tracing::Span::none().is_none());
+    assert!(tracing::Span::none().is_disabled());
+    assert!(tracing::Span::current().is_none());
+    let span = tracing::error_span!("send");
+    assert!(!span.is_none());
+    assert!(!span.is_disabled());
+}
+
+/// A future that is pending once, then ready.
+struct YieldOnce(bool);
+
+impl Future for YieldOnce {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<()> {
+        if self.0 {
+            Poll::Ready(())
+        } else {
+            self.0 = true;
+            Poll::Pending
+        }
+    }
+}
+
+/// Keeps the records it is given, for checking them after the stream is done.
+#[derive(Clone, Default)]
+struct Collect(Arc<Mutex<Vec<TraceRecord>>>);
+
+impl Exporter for Collect {
+    fn export(&mut self, record: &TraceRecord) -> std::io::Result<()> {
+        self.0.lock().unwrap().push(record.clone());
+        Ok(())
+    }
+}
+
+#[test]
+fn test_instrumented_future_is_a_span_per_poll() {
+    let mut cx = Context::from_waker(Waker::noop());
+    let mut fetch = pin!(YieldOnce(false).instrument(tracing::error_span!("fetch")));
+    assert!(fetch.as_mut().poll(&mut cx).is_pending());
+    tracing::error!("between polls");
+    assert!(fetch.as_mut().poll(&mut cx).is_ready());
+
+    // Decoded as `defmt-print` would print the frames.
+    let frames = frames(&logged(), &[1, 1, 0, 1, 1]);
+    let (enter, exit) = (frames[0].0, frames[1].0);
+    let decoder = TraceDecoder::builder().build_for_text().unwrap();
+    let mut reconstructor = LineReconstructor::new(decoder.new_stream());
+    let records = Collect::default();
+    reconstructor.stream_mut().add_exporter(records.clone());
+    for (index, arguments) in &frames {
+        let line = match *index {
+            index if index == enter => format!("span_enter: {}", arguments[0]),
+            index if index == exit => format!("span_exit: {}", arguments[0]),
+            _ => "between polls".to_string(),
+        };
+        reconstructor.push_line(&line).unwrap();
+    }
+    reconstructor.finish().unwrap();
+
+    let records = records.0.lock().unwrap();
+    let shape: Vec<_> = records
+        .iter()
+        .map(|record| (record.kind, record.message.as_str(), record.span_id))
+        .collect();
+    assert_eq!(
+        shape,
+        [
+            (RecordKind::SpanEnter, "fetch", Some(1)),
+            (RecordKind::SpanExit, "fetch", Some(1)),
+            (RecordKind::Event, "between polls", None),
+            (RecordKind::SpanEnter, "fetch", Some(2)),
+            (RecordKind::SpanExit, "fetch", Some(2)),
+        ]
+    );
+    assert!(records.iter().all(|record| record.parent_id.is_none()));
+}
+
+// Stubs to satisfy the linker when running tests on host
+#[unsafe(no_mangle)]
+fn _defmt_acquire() {}
+
+#[unsafe(no_mangle)]
+fn _defmt_release() {}
+
+#[unsafe(no_mangle)]
+fn _defmt_write(bytes: &[u8]) {
+    LOG.with(|log| log.borrow_mut().extend_from_slice(bytes));
+}
+
+#[unsafe(no_mangle)]
+fn _defmt_timestamp(_fmt: tracing_defmt::defmt::Formatter<'_>) {}
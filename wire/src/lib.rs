@@ -0,0 +1,131 @@
+//! The conventions of the defmt frames written by `tracing-defmt` and read by
+//! `tracing-defmt-decoder`.
+//!
+//! defmt frames carry a formatted message only, so spans, metrics and the other records
+//! the decoder reconstructs are marked by a prefix of the message, e.g. `span_enter: `.
+//! The proc macros and the decoder both take them from here, so the two sides cannot
+//! drift apart. Firmware that logs such frames itself (defmt format strings have to be
+//! literals) should follow the layouts documented below.
+//!
+//! # Example
+//! A function `send(len: u32)` instrumented at info level logs
+//! `span_enter: send(len={})` on entry and `span_exit: send` when it returns.
+
+#![no_std]
+
+/// Version of the conventions below, raised when a change breaks older decoders.
+pub const VERSION: u32 = 1;
+/// The `u32` symbol through which the facade records [`VERSION`] in the firmware ELF.
+pub const VERSION_SYMBOL: &str = "_TRACING_DEFMT_VERSION";
+
+/// Starts the frame logged on entry of an instrumented function:
+/// `span_enter: <name>[(<field>=<value>, ...)]`.
+pub const SPAN_ENTER: &str = "span_enter: ";
+/// Starts the frame logged when an instrumented function returns: `span_exit: <name>`.
+pub const SPAN_EXIT: &str = "span_exit: ";
+/// Starts the frame logged instead of `span_exit` when the operation of a span is
+/// abandoned, e.g. on a timeout or when the future of an instrumented async function is
+/// dropped: `span_cancel: <name>[, <field>=<value>...]`, e.g. with a [`REASON_FIELD`].
+pub const SPAN_CANCEL: &str = "span_cancel: ";
+/// The field of a `span_cancel` frame that says why the operation was abandoned, e.g.
+/// `timeout`.
+pub const REASON_FIELD: &str = "reason";
+/// Starts the frame logged when a field is recorded on an entered span, e.g. by
+/// `Span::record_u32`: `span_record: <name>, <field>=<value>`. The decoder sets the field
+/// on the innermost open span of that name.
+pub const SPAN_RECORD: &str = "span_record: ";
+/// Starts the hash that replaces the name in a `span_exit` or `span_cancel` frame of
+/// firmware built with hashed span names: `span_exit: #<hex hash>`, see [`name_hash`].
+/// The name then only lives in the ELF, in the format string of the `span_enter` frame.
+pub const NAME_HASH: char = '#';
+
+/// The 32-bit FNV-1a hash of a span name, sent instead of the name with hashed span names.
+pub const fn name_hash(name: &str) -> u32 {
+    let bytes = name.as_bytes();
+    let mut hash = 0x811c_9dc5u32;
+    let mut i = 0;
+    while i < bytes.len() {
+        hash ^= bytes[i] as u32;
+        hash = hash.wrapping_mul(0x0100_0193);
+        i += 1;
+    }
+    hash
+}
+
+/// Opens the fields of a `span_enter` frame.
+pub const FIELDS_START: char = '(';
+/// Closes the fields of a `span_enter` frame.
+pub const FIELDS_END: char = ')';
+
+/// Separates the fields of a frame, and an event message from its fields:
+/// `<message>, <field>=<value>, <field>=<value>`.
+pub const FIELD_SEPARATOR: &str = ", ";
+/// Separates the key of a field from its value.
+pub const KEY_VALUE_SEPARATOR: char = '=';
+/// The field that describes the error of an event.
+pub const ERROR_FIELD: &str = "error";
+/// The field of a `span_enter` frame that tells apart spans of the same name, e.g. the
+/// channel of a task run once per peripheral. The decoder appends its value to the span
+/// name: `uart_task(instance=2)` becomes the span `uart_task[2]`.
+pub const INSTANCE_FIELD: &str = "instance";
+/// Same as [`INSTANCE_FIELD`], under the name OpenTelemetry-minded code may look for.
+pub const NAME_SUFFIX_FIELD: &str = "otel_name_suffix";
+/// The field of an event or `span_enter` frame given by `target:`, the tracing target
+/// the decoder gives it instead of its default.
+pub const TARGET_FIELD: &str = "target";
+/// The field of an event or `span_enter` frame given by `parent:`: the name of the span
+/// it belongs to, the innermost open one of that name, instead of the innermost open
+/// span. [`NO_PARENT`] for none, and empty when the parent has no name.
+pub const PARENT_FIELD: &str = "parent";
+/// The [`PARENT_FIELD`] of a frame that belongs to no span (`parent: None`).
+pub const NO_PARENT: &str = "-";
+
+/// The field of a `span_enter` frame with the OpenTelemetry kind of the span: `client`,
+/// `server`, `producer`, `consumer` or `internal`, the default.
+pub const KIND_FIELD: &str = "otel.kind";
+
+/// Starts a frame that adds to a counter: `counter: <name>=<value>[, <attribute>=<value>...]`.
+pub const COUNTER: &str = "counter: ";
+/// Starts a frame that sets a gauge: `gauge: <name>=<value>[, <attribute>=<value>...]`.
+pub const GAUGE: &str = "gauge: ";
+/// The gauge of the bytes of heap free, e.g. `gauge: memory.heap.free=1024`.
+pub const HEAP_FREE_GAUGE: &str = "memory.heap.free";
+/// The gauge of the bytes of heap in use.
+pub const HEAP_USED_GAUGE: &str = "memory.heap.used";
+/// The gauge of the most bytes of stack used since boot.
+pub const STACK_HIGH_WATER_GAUGE: &str = "memory.stack.high_water";
+
+/// The frame logged first thing after reset, on its own or followed by `: ` and details,
+/// which may end in fields: `boot: reset.reason=watchdog`.
+pub const BOOT: &str = "boot";
+/// The field of a `boot` frame with the cause of the reset: `por` (power-on), `bod`
+/// (brown-out), `watchdog`, `software`, `pin`, `lockup`, `wakeup`, `low_power` or
+/// `unknown`.
+pub const RESET_REASON_FIELD: &str = "reset.reason";
+/// Starts a frame that announces the firmware image of the following frames:
+/// `image_switch: <index>`.
+pub const IMAGE_SWITCH: &str = "image_switch: ";
+/// Starts a frame that sets the remote parent of the following root spans:
+/// `traceparent: <W3C traceparent>`.
+pub const TRACEPARENT: &str = "traceparent: ";
+/// Starts a frame that links the enclosing span to the most recent span of a name:
+/// `follows_from: <name>`.
+pub const FOLLOWS_FROM: &str = "follows_from: ";
+
+/// Starts the answer to a command of the control channel: `control: <command>`.
+pub const CONTROL: &str = "control: ";
+/// Starts the answer to a time sync request of the control channel:
+/// `time_sync: <nonce>`.
+pub const TIME_SYNC: &str = "time_sync: ";
+
+/// Starts the frame logged by a fault handler:
+/// `crash: <fault>, pc=<address>, lr=<address>, <register>=<value>, ...,
+/// backtrace=[<address>, ...]`, with the registers the core stacked, the fault status
+/// registers (`cfsr`, `hfsr`, `mmfar`, `bfar`) and the return addresses found on the
+/// stack, in hex. The decoder symbolicates the addresses against the ELF and makes a
+/// [`CRASH_SPAN`] of the frame.
+pub const CRASH: &str = "crash: ";
+/// The name of the span the decoder makes of a `crash` frame.
+pub const CRASH_SPAN: &str = "crash";
+/// The field of the `crash` span with the symbolicated return addresses, one per line.
+pub const BACKTRACE_FIELD: &str = "backtrace";